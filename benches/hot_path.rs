@@ -0,0 +1,115 @@
+//! What one send costs before the request reaches reqwest: building the
+//! order's URL, headers and body on every attempt, as before the send loop
+//! prepared them, against taking a copy stocked ahead of the batch.
+//!
+//! Run with `cargo bench --bench hot_path`.
+
+use sarkhati::console::Timing;
+use sarkhati::latency;
+use sarkhati::runner::Broker;
+use sarkhati::standard_broker::StandardBrokerConfig;
+use serde_json::json;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+const ATTEMPTS: usize = 10_000;
+
+fn broker() -> StandardBrokerConfig {
+    serde_json::from_value(json!({
+        "name": "bmi",
+        "cookie": "ASP.NET_SessionId=0123456789abcdef; .ASPXAUTH=0123456789abcdef0123456789abcdef",
+        "order_url": "https://api.bmibourse.ir/Web/V1/Order/Post",
+        "origin": "https://online.bmibourse.ir",
+        "referer": "https://online.bmibourse.ir/",
+        "orders": [{
+            "IsSymbolCautionAgreement": false,
+            "CautionAgreementSelected": false,
+            "IsSymbolSepahAgreement": false,
+            "SepahAgreementSelected": false,
+            "orderCount": 100,
+            "orderPrice": 50340,
+            "FinancialProviderId": 1,
+            "minimumQuantity": 0,
+            "maxShow": 0,
+            "orderId": 0,
+            "isin": "IRO1RVND0001",
+            "orderSide": 65,
+            "orderValidity": 74,
+            "orderValiditydate": null,
+            "shortSellIsEnabled": false,
+            "shortSellIncentivePercent": 0
+        }]
+    }))
+    .expect("bench config should deserialize")
+}
+
+/// Run `attempt` `ATTEMPTS` times, printing allocations and time per attempt.
+fn measure(label: &str, mut attempt: impl FnMut()) {
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ATTEMPTS {
+        attempt();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    println!(
+        "{:<10} {:>6.1} allocations {:>9.0}ns per attempt",
+        label,
+        allocations as f64 / ATTEMPTS as f64,
+        elapsed.as_nanos() as f64 / ATTEMPTS as f64
+    );
+}
+
+fn main() {
+    let broker = broker();
+    let order = &broker.orders()[0];
+
+    measure("rebuilt", || {
+        let request = broker
+            .prepare(&order.data, broker.order_json(order).unwrap())
+            .unwrap();
+        let mut timing = Timing::start();
+        timing.mark("rate limit");
+        black_box((request.parts(), request.body.clone(), timing));
+    });
+
+    let request = broker
+        .prepare(&order.data, broker.order_json(order).unwrap())
+        .unwrap();
+    request.stock(ATTEMPTS);
+    measure("prepared", || {
+        let mut timing = Timing::start();
+        timing.mark("rate limit");
+        black_box((request.parts(), request.body.clone(), timing));
+    });
+
+    // The phase timing wrapped around every send.
+    measure("timed", || {
+        black_box(futures::executor::block_on(latency::timed(async { 1 })));
+    });
+}
@@ -0,0 +1,148 @@
+use crate::runner::Broker;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Separates the broker and account in the name of an account, as in
+/// `"bmi/wife"`.
+pub const SEPARATOR: char = '/';
+
+/// How each order is spread over a broker's accounts.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AccountStrategy {
+    /// Every account sends every order.
+    #[default]
+    BroadcastAll,
+    /// Each order goes to the next account in turn.
+    RoundRobin,
+    /// Every account sends the order at once and the first acceptance
+    /// cancels the sends still pending; later attempts of that order only go
+    /// through the account that got it accepted.
+    FirstSuccessStopsOthers,
+}
+
+/// Parse a multi-broker config file, expanding `accounts` first when any
+/// broker lists them. Files without accounts are parsed straight from the
+/// text so errors keep their line numbers.
+pub fn parse_config<T: DeserializeOwned>(path: &str, config_str: &str) -> Result<T> {
+    let mut value: Value =
+        serde_json::from_str(config_str).with_context(|| format!("Failed to parse {}", path))?;
+    let parsed = if expand(&mut value)? {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_str(config_str)
+    };
+    parsed.with_context(|| format!("Failed to parse {}", path))
+}
+
+/// Turn every `accounts` entry into a complete broker object: the broker's
+/// own fields with the account's fields on top, named `<broker>/<account>`.
+/// Works on a `brokers` array or a single broker object. Returns whether
+/// anything was expanded.
+pub fn expand(config: &mut Value) -> Result<bool> {
+    let mut expanded = false;
+    match config.get_mut("brokers").and_then(Value::as_array_mut) {
+        Some(brokers) => {
+            for broker in brokers {
+                expanded |= expand_broker(broker)?;
+            }
+        }
+        None => expanded = expand_broker(config)?,
+    }
+    Ok(expanded)
+}
+
+fn expand_broker(broker: &mut Value) -> Result<bool> {
+    let Some(object) = broker.as_object_mut() else {
+        return Ok(false);
+    };
+    let Some(accounts) = object.remove("accounts") else {
+        return Ok(false);
+    };
+    let broker_name = object
+        .get("name")
+        .and_then(Value::as_str)
+        .context("A broker with 'accounts' needs a 'name'")?
+        .to_string();
+    let Value::Array(accounts) = accounts else {
+        anyhow::bail!("'accounts' of {} must be an array", broker_name);
+    };
+
+    let mut expanded = Vec::with_capacity(accounts.len());
+    for account in accounts {
+        let Value::Object(fields) = account else {
+            anyhow::bail!(
+                "Every entry in 'accounts' of {} must be an object",
+                broker_name
+            );
+        };
+        let account_name = fields
+            .get("name")
+            .and_then(Value::as_str)
+            .filter(|name| !name.is_empty() && !name.contains(SEPARATOR))
+            .with_context(|| {
+                format!(
+                    "Every account of {} needs a 'name' without '{}'",
+                    broker_name, SEPARATOR
+                )
+            })?
+            .to_string();
+        if fields.contains_key("accounts") {
+            anyhow::bail!(
+                "Account {} of {} cannot list accounts",
+                account_name,
+                broker_name
+            );
+        }
+
+        let mut merged = object.clone();
+        merged.extend(fields);
+        merged.insert(
+            "name".to_string(),
+            Value::String(format!("{}{}{}", broker_name, SEPARATOR, account_name)),
+        );
+        expanded.push(Value::Object(merged));
+    }
+    if expanded.is_empty() {
+        anyhow::bail!("'accounts' of {} is empty", broker_name);
+    }
+    object.insert("accounts".to_string(), Value::Array(expanded));
+    Ok(true)
+}
+
+/// The broker and account parts of a broker or account name.
+pub fn split_name(name: &str) -> (&str, Option<&str>) {
+    match name.split_once(SEPARATOR) {
+        Some((broker, account)) => (broker, Some(account)),
+        None => (name, None),
+    }
+}
+
+/// The broker or account called `name` among `brokers`.
+pub fn find<'a, B: Broker>(brokers: &'a [B], name: &str) -> Option<&'a B> {
+    brokers
+        .iter()
+        .flat_map(|broker| std::iter::once(broker).chain(broker.accounts()))
+        .find(|broker| broker.name().eq_ignore_ascii_case(name))
+}
+
+/// The configs orders are sent with: each account, or the broker itself when
+/// it has none.
+pub fn senders<B: Broker>(broker: &B) -> Vec<&B> {
+    if broker.accounts().is_empty() {
+        vec![broker]
+    } else {
+        broker.accounts().iter().collect()
+    }
+}
+
+/// The account part of an account's name, for per-account reports.
+pub fn account_label<'a, B: Broker>(broker: &B, account: &'a B) -> &'a str {
+    account
+        .name()
+        .strip_prefix(broker.name())
+        .and_then(|rest| rest.strip_prefix(SEPARATOR))
+        .unwrap_or(account.name())
+}
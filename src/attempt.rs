@@ -0,0 +1,111 @@
+//! Correlation IDs: each order attempt gets an ID of its own, appended to
+//! every line logged while it runs, stored in its batch result and, with
+//! `correlation_header`, sent to the broker, so a response body in the log
+//! can be matched to the exact attempt that produced it. An order sent
+//! through several accounts is one attempt.
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+/// The fields a variation rewrote and the values it gave them.
+type VariedFields = Vec<(String, Value)>;
+
+tokio::task_local! {
+    static CURRENT: Attempt;
+}
+
+#[derive(Debug, Clone)]
+pub struct Attempt {
+    pub id: String,
+    /// Broker the attempt sends for, prefixed to the lines it logs.
+    broker: String,
+    /// Header the ID is sent in, if the broker tolerates one.
+    header: Option<HeaderName>,
+    /// Values `vary` gave the payloads of this attempt, by variation
+    /// attempt number; logged once the attempt is over.
+    varied: Arc<Mutex<Vec<(u64, VariedFields)>>>,
+}
+
+impl Attempt {
+    /// A new attempt for `broker` with a random 12-digit hex ID, sent in
+    /// `header` when given.
+    pub fn new(broker: &str, header: Option<&HeaderName>) -> Self {
+        Self {
+            id: format!("{:012x}", rand::random::<u64>() >> 16),
+            broker: broker.to_string(),
+            header: header.cloned(),
+            varied: Arc::default(),
+        }
+    }
+
+    /// Run `future` as this attempt, then log the values `vary` sent.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        let varied = self.varied.clone();
+        let broker = self.broker.clone();
+        CURRENT
+            .scope(self, async {
+                let output = future.await;
+                let varied = std::mem::take(&mut *varied.lock().unwrap_or_else(|e| e.into_inner()));
+                for (number, fields) in varied {
+                    log_variation(Some(&broker), number, &fields);
+                }
+                output
+            })
+            .await
+    }
+}
+
+/// Keep the values `vary` gave a payload to log after the send, or log them
+/// now outside of an attempt.
+pub fn note_variation(number: u64, fields: VariedFields) {
+    let mut fields = Some(fields);
+    let _ = CURRENT.try_with(|attempt| {
+        if let Some(fields) = fields.take() {
+            attempt
+                .varied
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push((number, fields));
+        }
+    });
+    if let Some(fields) = fields {
+        log_variation(None, number, &fields);
+    }
+}
+
+fn log_variation(broker: Option<&str>, number: u64, fields: &[(String, Value)]) {
+    if fields.is_empty() {
+        return;
+    }
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(field, value)| format!("{}={}", field, value))
+        .collect();
+    match broker {
+        Some(broker) => println!(
+            "[{}] [Vary] Attempt {}: {}",
+            broker,
+            number,
+            fields.join(", ")
+        ),
+        None => println!("[Vary] Attempt {}: {}", number, fields.join(", ")),
+    }
+}
+
+/// ID of the attempt the calling task runs, if any.
+pub fn current_id() -> Option<String> {
+    CURRENT.try_with(|attempt| attempt.id.clone()).ok()
+}
+
+/// Add the current attempt's ID to `headers` when it has a header to go in.
+pub fn add_header(headers: &mut HeaderMap) {
+    let _ = CURRENT.try_with(|attempt| {
+        if let Some(header) = &attempt.header
+            && let Ok(value) = HeaderValue::from_str(&attempt.id)
+        {
+            headers.insert(header.clone(), value);
+        }
+    });
+}
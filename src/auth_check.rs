@@ -0,0 +1,206 @@
+use crate::accounts;
+use crate::login::jwt_expiry;
+use crate::registry::{self, AnyBroker};
+use crate::runner::Broker;
+use crate::with_broker;
+use anyhow::Result;
+use reqwest::StatusCode;
+use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HeaderMap};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    Valid,
+    Expired,
+    /// The check could not tell, e.g. the request failed.
+    Unknown,
+}
+
+/// Result of checking one broker's session.
+#[derive(Debug, Clone)]
+pub struct AuthReport {
+    pub name: String,
+    pub state: SessionState,
+    pub status: Option<StatusCode>,
+    /// `exp` of a JWT found in the Authorization or Cookie header.
+    pub expires_at: Option<u64>,
+    pub detail: String,
+}
+
+fn epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// The earliest JWT expiry among the credentials in `headers`.
+fn credential_expiry(headers: &HeaderMap) -> Option<u64> {
+    let authorization = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(jwt_expiry);
+    let cookie = headers
+        .get(COOKIE)
+        .and_then(|value| value.to_str().ok())
+        .into_iter()
+        .flat_map(|cookie| cookie.split(';'))
+        .filter_map(|pair| {
+            pair.split_once('=')
+                .and_then(|(_, value)| jwt_expiry(value.trim()))
+        })
+        .min();
+    authorization.into_iter().chain(cookie).min()
+}
+
+/// Send the broker's credentials to `auth_check_url` (the order URL by
+/// default, fetched with GET so nothing is placed) and classify the answer:
+/// 401/403 means the session is gone, any other answer means it was accepted.
+pub async fn check_broker<B: Broker>(broker: &B, client: &reqwest::Client) -> AuthReport {
+    let mut report = AuthReport {
+        name: broker.name().to_string(),
+        state: SessionState::Unknown,
+        status: None,
+        expires_at: None,
+        detail: String::new(),
+    };
+
+    let mut headers = match broker.order_headers("{}") {
+        Ok(headers) => headers,
+        Err(e) => {
+            report.detail = format!("invalid credentials in config: {}", e);
+            return report;
+        }
+    };
+    headers.remove(CONTENT_TYPE);
+    headers.remove(CONTENT_LENGTH);
+    if headers.values().any(|value| {
+        value
+            .to_str()
+            .is_ok_and(|value| value.contains("PASTE_YOUR_"))
+    }) {
+        report.state = SessionState::Expired;
+        report.detail = "credentials not set in config".to_string();
+        return report;
+    }
+    report.expires_at = credential_expiry(&headers);
+
+    let url = broker
+        .settings()
+        .auth_check_url
+        .as_deref()
+        .unwrap_or(broker.order_url());
+    match client.get(url).headers(headers).send().await {
+        Ok(response) => {
+            let status = response.status();
+            report.status = Some(status);
+            report.state = match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => SessionState::Expired,
+                _ => SessionState::Valid,
+            };
+        }
+        Err(e) => report.detail = format!("request failed: {}", e),
+    }
+
+    if report
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= epoch_secs())
+    {
+        report.state = SessionState::Expired;
+    }
+    report
+}
+
+pub(crate) fn format_expiry(expires_at: u64) -> String {
+    let now = epoch_secs();
+    if expires_at <= now {
+        let ago = now - expires_at;
+        format!("expired {}h {}m ago", ago / 3600, ago % 3600 / 60)
+    } else {
+        let left = expires_at - now;
+        format!("expires in {}h {}m", left / 3600, left % 3600 / 60)
+    }
+}
+
+fn print_report(report: &AuthReport) {
+    let state = match report.state {
+        SessionState::Valid => "OK",
+        SessionState::Expired => "EXPIRED",
+        SessionState::Unknown => "UNKNOWN",
+    };
+    let mut details = Vec::new();
+    if let Some(status) = report.status {
+        details.push(status.to_string());
+    }
+    if let Some(expires_at) = report.expires_at {
+        details.push(format_expiry(expires_at));
+    }
+    if !report.detail.is_empty() {
+        details.push(report.detail.clone());
+    }
+    println!(
+        "[Auth] {:<14} {:<8} {}",
+        report.name,
+        state,
+        details.join(", ")
+    );
+}
+
+/// Run `auth-check`: check `names`, or every configured broker when empty,
+/// and fail if any session needs a new login.
+pub async fn run_auth_check(names: &[String]) -> Result<()> {
+    let brokers: Vec<AnyBroker> = if names.is_empty() {
+        registry::all_brokers()?
+    } else {
+        names
+            .iter()
+            .map(|name| registry::find_broker(name))
+            .collect::<Result<_>>()?
+    };
+    if brokers.is_empty() {
+        anyhow::bail!("No broker configs found in the current directory");
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    // Brokers with accounts are checked once per account.
+    let reports: Vec<AuthReport> = futures::future::join_all(brokers.iter().map(|broker| async {
+        with_broker!(broker, b => {
+            futures::future::join_all(
+                accounts::senders(b)
+                    .into_iter()
+                    .map(|sender| check_broker(sender, &client)),
+            )
+            .await
+        })
+    }))
+    .await
+    .into_iter()
+    .flatten()
+    .collect();
+
+    for report in &reports {
+        print_report(report);
+    }
+
+    let need_login: Vec<&str> = reports
+        .iter()
+        .filter(|report| report.state == SessionState::Expired)
+        .map(|report| report.name.as_str())
+        .collect();
+    if !need_login.is_empty() {
+        anyhow::bail!("Re-login needed for: {}", need_login.join(", "));
+    }
+    if reports
+        .iter()
+        .any(|report| report.state == SessionState::Unknown)
+    {
+        println!("[Auth] Some sessions could not be checked; see above.");
+    } else {
+        println!("[Auth] All sessions valid.");
+    }
+    Ok(())
+}
@@ -0,0 +1,312 @@
+//! Structured results of each batch: the outcome and timing of every order,
+//! handed to the sinks in the broker's `batch_sinks` once the last response
+//! of the batch is in. A failing sink is only reported.
+
+use crate::attempt;
+use crate::errors::{OrderErrorKind, order_error_kind};
+use crate::session_summary::SessionSummary;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OrderStatus {
+    Accepted,
+    Failed,
+    /// Not sent: already accepted, disabled, held back by the depth check
+    /// or the circuit breaker.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OrderOutcome {
+    /// 1-based, as in `--orders`.
+    pub order: usize,
+    /// Correlation ID of the attempt; none for skipped orders.
+    pub attempt_id: Option<String>,
+    pub tags: Vec<String>,
+    pub status: OrderStatus,
+    pub error: Option<String>,
+    pub error_kind: Option<OrderErrorKind>,
+    pub sent_at_epoch_ms: i64,
+    /// From the start of the dispatch to the last account's response.
+    pub elapsed_us: u64,
+}
+
+impl OrderOutcome {
+    /// Outcome of order `index`, `Err` holding why it failed, with the ID of
+    /// the attempt it is created in.
+    pub fn new(
+        index: usize,
+        tags: &[String],
+        result: Result<OrderStatus, &anyhow::Error>,
+        sent_at_epoch_ms: i64,
+        elapsed: Duration,
+    ) -> Self {
+        let (status, error, error_kind) = match result {
+            Ok(status) => (status, None, None),
+            Err(e) => (
+                OrderStatus::Failed,
+                Some(format!("{:#}", e)),
+                order_error_kind(e),
+            ),
+        };
+        Self {
+            order: index + 1,
+            attempt_id: (status != OrderStatus::Skipped)
+                .then(attempt::current_id)
+                .flatten(),
+            tags: tags.to_vec(),
+            status,
+            error,
+            error_kind,
+            sent_at_epoch_ms,
+            elapsed_us: elapsed.as_micros() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub broker: String,
+    /// Batch number of a continuous run, or round of a scheduled one.
+    pub batch: u64,
+    pub started_epoch_ms: i64,
+    pub elapsed_ms: u64,
+    /// In the order the responses came in.
+    pub orders: Vec<OrderOutcome>,
+}
+
+impl BatchResult {
+    /// An empty result for a batch starting now.
+    pub fn new(broker: &str, batch: u64) -> Self {
+        Self {
+            broker: broker.to_string(),
+            batch,
+            started_epoch_ms: chrono::Utc::now().timestamp_millis(),
+            elapsed_ms: 0,
+            orders: Vec::new(),
+        }
+    }
+
+    pub fn count(&self, status: OrderStatus) -> usize {
+        self.orders
+            .iter()
+            .filter(|outcome| outcome.status == status)
+            .count()
+    }
+}
+
+/// Gathers the outcomes of one batch whose orders run as separate tasks;
+/// the task adding the last one gets the finished result.
+pub struct BatchCollector {
+    result: Mutex<BatchResult>,
+    started: Instant,
+    pending: AtomicUsize,
+}
+
+impl BatchCollector {
+    pub fn new(broker: &str, batch: u64, orders: usize) -> Self {
+        Self {
+            result: Mutex::new(BatchResult::new(broker, batch)),
+            started: Instant::now(),
+            pending: AtomicUsize::new(orders),
+        }
+    }
+
+    /// Add one order's outcome; the batch result once it was the last.
+    pub fn add(&self, outcome: OrderOutcome) -> Option<BatchResult> {
+        let mut result = self.result.lock().unwrap_or_else(|e| e.into_inner());
+        result.orders.push(outcome);
+        if self.pending.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return None;
+        }
+        result.elapsed_ms = self.started.elapsed().as_millis() as u64;
+        Some(result.clone())
+    }
+}
+
+/// One entry of `batch_sinks`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BatchSinkConfig {
+    /// One summary line per batch.
+    Console,
+    /// Append each result as a line of JSON.
+    File { path: String },
+    /// POST each result as JSON.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+    },
+    /// Insert a row per order into the `batch_orders` table.
+    Sqlite { path: String },
+}
+
+impl BatchSinkConfig {
+    /// Names the sink in warnings without its headers, which may hold keys.
+    fn label(&self) -> &str {
+        match self {
+            BatchSinkConfig::Console => "console",
+            BatchSinkConfig::File { path } | BatchSinkConfig::Sqlite { path } => path,
+            BatchSinkConfig::Webhook { url, .. } => url,
+        }
+    }
+}
+
+pub fn default_batch_sinks() -> Vec<BatchSinkConfig> {
+    vec![BatchSinkConfig::Console]
+}
+
+const SQLITE_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS batch_orders (
+    broker TEXT NOT NULL,
+    batch INTEGER NOT NULL,
+    started_epoch_ms INTEGER NOT NULL,
+    order_number INTEGER NOT NULL,
+    tags TEXT NOT NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    error_kind TEXT,
+    sent_at_epoch_ms INTEGER NOT NULL,
+    elapsed_us INTEGER NOT NULL,
+    attempt_id TEXT
+)";
+
+/// The sinks of one broker, plus the channel of [`RunOptions::batch_results`]
+/// when an embedder listens and the summary of the session.
+///
+/// [`RunOptions::batch_results`]: crate::runner::RunOptions::batch_results
+pub struct BatchSinks {
+    sinks: Vec<BatchSinkConfig>,
+    listener: Option<mpsc::UnboundedSender<BatchResult>>,
+    session: Option<Arc<SessionSummary>>,
+    client: reqwest::Client,
+}
+
+impl BatchSinks {
+    pub fn new(
+        sinks: &[BatchSinkConfig],
+        listener: Option<mpsc::UnboundedSender<BatchResult>>,
+        session: Option<Arc<SessionSummary>>,
+    ) -> Self {
+        Self {
+            sinks: sinks.to_vec(),
+            listener,
+            session,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Hand `result` to every sink, reporting the ones that fail.
+    pub async fn write(&self, result: &BatchResult) {
+        if let Some(session) = &self.session {
+            session.add(result);
+        }
+        for sink in &self.sinks {
+            if let Err(e) = self.write_to(sink, result).await {
+                eprintln!(
+                    "[{}] Warning: could not write batch #{} to the {} sink: {:#}",
+                    result.broker,
+                    result.batch,
+                    sink.label(),
+                    e
+                );
+            }
+        }
+        if let Some(listener) = &self.listener {
+            // A listener that went away is not an error.
+            let _ = listener.send(result.clone());
+        }
+    }
+
+    async fn write_to(&self, sink: &BatchSinkConfig, result: &BatchResult) -> Result<()> {
+        match sink {
+            BatchSinkConfig::Console => {
+                println!(
+                    "[{}] Batch #{} done in {}ms: {} accepted, {} failed, {} skipped",
+                    result.broker,
+                    result.batch,
+                    result.elapsed_ms,
+                    result.count(OrderStatus::Accepted),
+                    result.count(OrderStatus::Failed),
+                    result.count(OrderStatus::Skipped)
+                );
+                Ok(())
+            }
+            BatchSinkConfig::File { path } => {
+                let mut line = serde_json::to_vec(result)?;
+                line.push(b'\n');
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || -> Result<()> {
+                    std::fs::OpenOptions::new()
+                        .create(true)
+                        .append(true)
+                        .open(&path)
+                        .and_then(|mut file| file.write_all(&line))
+                        .with_context(|| format!("Failed to append to {}", path))
+                })
+                .await?
+            }
+            BatchSinkConfig::Webhook { url, headers } => {
+                let mut request = self.client.post(url).json(result);
+                for (name, value) in headers {
+                    request = request.header(name, value);
+                }
+                request
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status())
+                    .with_context(|| format!("Failed to post to {}", url))?;
+                Ok(())
+            }
+            BatchSinkConfig::Sqlite { path } => {
+                let path = path.clone();
+                let result = result.clone();
+                tokio::task::spawn_blocking(move || insert_rows(&path, &result)).await?
+            }
+        }
+    }
+}
+
+fn insert_rows(path: &str, result: &BatchResult) -> Result<()> {
+    let mut connection =
+        rusqlite::Connection::open(path).with_context(|| format!("Failed to open {}", path))?;
+    connection.execute(SQLITE_SCHEMA, [])?;
+    // Tables created before correlation IDs lack the column.
+    if connection
+        .prepare("SELECT attempt_id FROM batch_orders LIMIT 0")
+        .is_err()
+    {
+        connection.execute("ALTER TABLE batch_orders ADD COLUMN attempt_id TEXT", [])?;
+    }
+    let transaction = connection.transaction()?;
+    for outcome in &result.orders {
+        transaction.execute(
+            "INSERT INTO batch_orders (broker, batch, started_epoch_ms, order_number, tags, status,
+                error, error_kind, sent_at_epoch_ms, elapsed_us, attempt_id)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            rusqlite::params![
+                result.broker,
+                result.batch as i64,
+                result.started_epoch_ms,
+                outcome.order as i64,
+                outcome.tags.join(","),
+                serde_json::to_value(outcome.status)?.as_str(),
+                outcome.error,
+                outcome.error_kind.map(|kind| kind.to_string()),
+                outcome.sent_at_epoch_ms,
+                outcome.elapsed_us as i64,
+                outcome.attempt_id,
+            ],
+        )?;
+    }
+    transaction.commit()?;
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use crate::calibration::percentile;
+use crate::runner::{Broker, current_epoch_millis, wait_until_epoch_ms};
+use anyhow::Result;
+use std::time::Instant;
+
+pub fn default_iterations() -> usize {
+    10_000
+}
+
+/// Each wake-up sample waits for the next millisecond boundary, so cap them
+/// to keep the run around a second.
+const MAX_SCHEDULING_SAMPLES: usize = 1_000;
+
+/// Measure the local per-order overhead of the send path (no network):
+/// serializing the body, building headers, spawning the send task and waking
+/// up at a scheduled time.
+pub async fn run_bench<B: Broker>(broker: B, iterations: usize) -> Result<()> {
+    if broker.orders().is_empty() {
+        anyhow::bail!(
+            "No orders configured for {} in {}.",
+            broker.name(),
+            broker.config_file()
+        );
+    }
+    if iterations == 0 {
+        anyhow::bail!("--iterations must be >= 1");
+    }
+
+    println!(
+        "[Bench] {}: {} iteration(s) over {} order(s), network excluded",
+        broker.name(),
+        iterations,
+        broker.orders().len()
+    );
+
+    let mut serialize_ns = Vec::with_capacity(iterations);
+    let mut headers_ns = Vec::with_capacity(iterations);
+    let mut total_ns = Vec::with_capacity(iterations);
+    for index in 0..iterations {
+        let order = &broker.orders()[index % broker.orders().len()];
+
+        let t0 = Instant::now();
+        let order_json = broker.order_json(order)?;
+        let t1 = Instant::now();
+        let headers = broker.order_headers(&order_json)?;
+        let t2 = Instant::now();
+        std::hint::black_box(headers);
+
+        serialize_ns.push((t1 - t0).as_nanos() as u64);
+        headers_ns.push((t2 - t1).as_nanos() as u64);
+        total_ns.push((t2 - t0).as_nanos() as u64);
+    }
+
+    let mut spawn_ns = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let spawned_at = Instant::now();
+        let started_at = tokio::spawn(async { Instant::now() }).await?;
+        spawn_ns.push((started_at - spawned_at).as_nanos() as u64);
+    }
+
+    let scheduling_samples = iterations.min(MAX_SCHEDULING_SAMPLES);
+    let mut wake_ns = Vec::with_capacity(scheduling_samples);
+    let mut last_wall_epoch_ms = current_epoch_millis()?;
+    for _ in 0..scheduling_samples {
+        let target_epoch_ms = current_epoch_millis()? + 1;
+        wait_until_epoch_ms(target_epoch_ms, &mut last_wall_epoch_ms).await?;
+        let late = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_nanos()
+            .saturating_sub(target_epoch_ms as u128 * 1_000_000);
+        wake_ns.push(late as u64);
+    }
+
+    report("serialize", &mut serialize_ns);
+    report("headers", &mut headers_ns);
+    report("serialize+headers", &mut total_ns);
+    report("task spawn", &mut spawn_ns);
+    report("scheduled wake", &mut wake_ns);
+    Ok(())
+}
+
+fn report(stage: &str, samples_ns: &mut [u64]) {
+    samples_ns.sort_unstable();
+    let micros = |ns: u64| ns as f64 / 1_000.0;
+    let mean_ns = samples_ns.iter().sum::<u64>() / samples_ns.len().max(1) as u64;
+    println!(
+        "[Bench] {:<18} n={:<6} p50={:.2}µs p90={:.2}µs p99={:.2}µs max={:.2}µs mean={:.2}µs",
+        stage,
+        samples_ns.len(),
+        micros(percentile(samples_ns, 50.0)),
+        micros(percentile(samples_ns, 90.0)),
+        micros(percentile(samples_ns, 99.0)),
+        micros(samples_ns.last().copied().unwrap_or_default()),
+        micros(mean_ns)
+    );
+}
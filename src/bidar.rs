@@ -1,5 +1,15 @@
-use crate::calibration::{self, CalibrationConfig};
-use crate::rate_limiter::RateLimiter;
+use crate::bidar_token::{self, BidarRefreshConfig, BidarSession};
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::BidarOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
 use anyhow::{Context, Result};
 use reqwest::StatusCode;
 use reqwest::header::{
@@ -7,28 +17,42 @@ use reqwest::header::{
     ORIGIN, REFERER, USER_AGENT,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::future::Future;
 use std::time::Instant;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct BidarConfig {
+    /// May be left empty when `refresh_token` is set.
+    #[serde(default)]
     pub authorization: String,
+    /// Renews `authorization` in the background before it expires.
+    #[serde(default)]
+    pub refresh_token: Option<String>,
+    #[serde(default)]
+    pub token_refresh: BidarRefreshConfig,
     #[serde(default = "default_user_agent")]
     pub user_agent: String,
+    /// Buy endpoint; sell orders go to the same URL ending in `/sell`.
     #[serde(default = "default_order_url")]
     pub order_url: String,
     #[serde(default)]
     pub x_user_trace: String,
-    pub orders: Vec<BidarOrderData>,
-    #[serde(default = "default_batch_delay")]
-    pub batch_delay_ms: u64,
-    #[serde(default = "default_batch_repeat")]
-    pub batch_repeat: usize,
-    #[serde(default)]
-    pub target_time: Option<String>,
-    #[serde(default)]
-    pub calibration: Option<CalibrationConfig>,
-    #[serde(default)]
-    pub delay_model: BidarDelayModel,
+    pub orders: Vec<OrderEntry<BidarOrderData>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
+    #[serde(skip)]
+    pub session: BidarSession,
+}
+
+impl BidarConfig {
+    /// The latest refreshed access token, or the configured one.
+    pub fn current_token(&self) -> String {
+        self.session
+            .get()
+            .unwrap_or_else(|| self.authorization.clone())
+    }
 }
 
 fn default_user_agent() -> String {
@@ -39,57 +63,298 @@ fn default_order_url() -> String {
     "https://api.bidartrader.ir/trader/v1/order/buy".to_string()
 }
 
-fn default_batch_delay() -> u64 {
-    100
-}
-
-fn default_batch_repeat() -> usize {
-    1
-}
-
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct BidarOrderData {
     #[serde(rename = "type")]
     pub order_type: String,
     pub quantity: String,
+    #[serde(default)]
     pub isin: String,
     pub validity: String,
     pub price: String,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
+    /// `buy` or `sell`, picking the endpoint; never sent. Without it a
+    /// `type` of `BUY`/`SELL` decides, and otherwise the order buys.
+    #[serde(default, skip_serializing)]
+    pub side: Option<String>,
+    /// Endpoint for this order instead of the one derived from its side;
+    /// never sent.
+    #[serde(default, skip_serializing)]
+    pub order_url: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
-#[serde(rename_all = "snake_case")]
-pub enum BidarDelayModel {
-    Rtt,
-    HalfRtt,
+impl BidarOrderData {
+    fn explicit_side(&self) -> Option<OrderSide> {
+        self.side
+            .as_deref()
+            .or(Some(&self.order_type))
+            .and_then(|side| OrderSide::from_value(&Value::from(side)))
+    }
+}
+
+pub fn load_config(path: &str) -> Result<BidarConfig> {
+    let config_str = encryption::read_config(path)?;
+    let mut config: BidarConfig =
+        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+    for (index, order) in config.orders.iter().enumerate() {
+        if let Some(side) = &order.data.side
+            && OrderSide::from_value(&Value::from(side.as_str())).is_none()
+        {
+            anyhow::bail!(
+                "Order {} in {} has side '{}'; use 'buy' or 'sell'",
+                index + 1,
+                path,
+                side
+            );
+        }
+        if order_url_for(&config, &order.data).is_none() {
+            anyhow::bail!(
+                "Order {} in {} sells, but order_url does not end in /buy to derive the sell endpoint from; set order_url on the order",
+                index + 1,
+                path
+            );
+        }
+    }
+    secrets::resolve(&mut config.authorization)?;
+    if let Some(refresh_token) = &mut config.refresh_token {
+        secrets::resolve(refresh_token)?;
+    }
+    Ok(config)
 }
+impl OrderFields for BidarOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.isin)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.isin = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
 
-impl Default for BidarDelayModel {
-    fn default() -> Self {
-        Self::Rtt
+    fn price(&self) -> Option<f64> {
+        self.price.trim().parse().ok()
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.price = (price.round() as i64).to_string();
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        self.quantity.trim().parse().ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.quantity = quantity.to_string();
+    }
+
+    /// From `side`, or a `type` of `BUY`/`SELL`, buying otherwise.
+    fn side(&self) -> OrderSide {
+        self.explicit_side().unwrap_or(OrderSide::Buy)
+    }
+}
+
+/// Endpoint `order` is posted to: its own `order_url`, or the configured
+/// one with a trailing `/buy` turned into `/sell` for sell orders. `None`
+/// for a sell order when `order_url` does not end in `/buy`.
+pub fn order_url_for<'a>(config: &'a BidarConfig, order: &BidarOrderData) -> Option<Cow<'a, str>> {
+    if let Some(url) = &order.order_url {
+        return Some(Cow::Owned(url.clone()));
+    }
+    match order.side() {
+        OrderSide::Buy => Some(Cow::Borrowed(&config.order_url)),
+        OrderSide::Sell => {
+            let base = config
+                .order_url
+                .trim_end_matches('/')
+                .strip_suffix("/buy")?;
+            Some(Cow::Owned(format!("{}/sell", base)))
+        }
     }
 }
 
+impl Broker for BidarConfig {
+    type Order = BidarOrderData;
+
+    fn name(&self) -> &str {
+        "Bidar"
+    }
+
+    fn config_file(&self) -> &str {
+        "config_bidar.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn order_url_for(&self, order: &BidarOrderData) -> Cow<'_, str> {
+        order_url_for(self, order).unwrap_or(Cow::Borrowed(&self.order_url))
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<BidarOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<BidarOrderData>> {
+        &mut self.orders
+    }
+
+    fn ensure_session(&self) -> impl Future<Output = Result<()>> + Send {
+        bidar_token::start_refresher(self)
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        let token = self.current_token();
+        if token.is_empty() {
+            anyhow::bail!(
+                "Authorization token is required for Bidar. Please set 'authorization' or 'refresh_token' in config_bidar.json"
+            );
+        }
+
+        println!("[Bidar] Using Bearer token authentication");
+        println!(
+            "[Bidar] Token preview: {}...",
+            &token[..token.len().min(50)]
+        );
+        if self
+            .refresh_token
+            .as_ref()
+            .is_some_and(|token| !token.is_empty())
+        {
+            println!("[Bidar] Token refresh: enabled");
+        }
+        Ok(())
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &BidarOrderData,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(client, self)
+    }
+}
+
+fn authorization_value(config: &BidarConfig) -> String {
+    let token = config.current_token();
+    if token.starts_with("Bearer ") {
+        token
+    } else {
+        format!("Bearer {}", token)
+    }
+}
+
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(config: &BidarConfig, order_json: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
+    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("en-US,en;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(REFERER, HeaderValue::from_static("https://bidartrader.ir/"));
+    headers.insert(ORIGIN, HeaderValue::from_static("https://bidartrader.ir"));
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    headers.insert("Priority", HeaderValue::from_static("u=0"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    headers.insert("TE", HeaderValue::from_static("trailers"));
+
+    headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&authorization_value(config))?,
+    );
+
+    // x-user-trace header (optional)
+    if !config.x_user_trace.is_empty() {
+        headers.insert("x-user-trace", HeaderValue::from_str(&config.x_user_trace)?);
+    }
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// Post `order_json` to `order_url`, normally [`order_url_for`] the order.
 pub async fn send_order(
     config: &BidarConfig,
-    order: &BidarOrderData,
+    order_url: &str,
+    order_json: &str,
     test_mode: bool,
     curl_only: bool,
     rate_limiter: Option<&RateLimiter>,
 ) -> Result<()> {
-    let client = reqwest::Client::new();
+    let request = PreparedRequest::new(
+        order_url,
+        config.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(config, &request, test_mode, curl_only, rate_limiter).await
+}
 
-    let order_json = serde_json::to_string(order)?;
+/// Post a request built by [`Broker::prepare`].
+pub async fn send_request(
+    config: &BidarConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = config.settings.client();
 
     // Authorization header
-    let auth_value = if config.authorization.starts_with("Bearer ") {
-        config.authorization.clone()
-    } else {
-        format!("Bearer {}", config.authorization)
-    };
+    let auth_value = authorization_value(config);
 
-    // Print curl command in test mode
-    if test_mode {
+    // Print curl command in test or curl-only mode
+    if test_mode || curl_only {
         let x_user_trace_header = if !config.x_user_trace.is_empty() {
             format!("-H 'x-user-trace: {}' \\\n  ", config.x_user_trace)
         } else {
@@ -117,7 +382,7 @@ pub async fn send_order(
   -H 'Cache-Control: no-cache' \
   -H 'TE: trailers' \
   --data-raw '{}'"#,
-            config.order_url, config.user_agent, auth_value, x_user_trace_header, order_json
+            request.url, config.user_agent, auth_value, x_user_trace_header, order_json
         );
         println!();
 
@@ -127,58 +392,39 @@ pub async fn send_order(
         }
     }
 
-    if let Some(limiter) = rate_limiter {
-        limiter.wait().await;
-    }
-
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
-    headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
-    headers.insert(
-        "Accept-Language",
-        HeaderValue::from_static("en-US,en;q=0.5"),
-    );
-    headers.insert(
-        ACCEPT_ENCODING,
-        HeaderValue::from_static("gzip, deflate, br, zstd"),
-    );
-    headers.insert(REFERER, HeaderValue::from_static("https://bidartrader.ir/"));
-    headers.insert(ORIGIN, HeaderValue::from_static("https://bidartrader.ir"));
-    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
-    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
-    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
-    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
-    headers.insert("Priority", HeaderValue::from_static("u=0"));
-    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
-    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
-    headers.insert("TE", HeaderValue::from_static("trailers"));
-
+    // The token may have been refreshed since the request was built.
+    let (url, mut headers) = request.parts();
     headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_value)?);
 
-    // x-user-trace header (optional)
-    if !config.x_user_trace.is_empty() {
-        headers.insert("x-user-trace", HeaderValue::from_str(&config.x_user_trace)?);
+    let mut timing = console::Timing::start();
+    if let Some(limiter) = rate_limiter {
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
     }
-
-    let body_bytes = order_json.as_bytes();
-
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    headers.insert(
-        CONTENT_LENGTH,
-        HeaderValue::from_str(&body_bytes.len().to_string())?,
-    );
-
-    println!("[Bidar] Sending order JSON: {}", order_json);
-
-    let response = client
-        .post(&config.order_url)
-        .headers(headers)
-        .body(order_json)
-        .send()
-        .await?;
+    timing.mark("rate limit");
+
+    console::debug_headers("Bidar", "Request", &headers);
+    let mut capture = Capture::start("Bidar", &url, &headers, &request.body);
+
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[Bidar] Sent order JSON: {}", order_json);
+    let response = capture.sent(response)?;
 
     let status = response.status();
+    console::debug_headers("Bidar", "Response", response.headers());
+    capture.response_head(status, response.headers());
     let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
 
     let decoded_text = if response_text.contains("\\u") {
         crate::decode_unicode_escapes(&response_text)
@@ -188,12 +434,16 @@ pub async fn send_order(
 
     println!("[Bidar] Order response status: {}", status);
     println!("[Bidar] Order response body: {}", decoded_text);
+    timing.report("Bidar");
 
-    if !status.is_success() {
-        anyhow::bail!("Order failed with status {}: {}", status, decoded_text);
+    if let Some(file) = &config.settings.response_schema_file {
+        schema_drift::check("Bidar", file, status, &decoded_text);
     }
-
-    Ok(())
+    success::check_response_as::<BidarOrderResponse>(
+        &config.settings.success_rules,
+        status,
+        decoded_text,
+    )
 }
 
 pub async fn run_calibration(
@@ -202,6 +452,7 @@ pub async fn run_calibration(
     rate_limiter: &RateLimiter,
 ) -> Result<calibration::CalibrationSummary> {
     let calibration = config
+        .settings
         .calibration
         .as_ref()
         .context("Calibration config missing")?;
@@ -222,13 +473,11 @@ async fn send_probe(
     headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
     headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
 
-    if !config.authorization.is_empty() {
-        let auth_value = if config.authorization.starts_with("Bearer ") {
-            config.authorization.clone()
-        } else {
-            format!("Bearer {}", config.authorization)
-        };
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_value)?);
+    if !config.current_token().is_empty() {
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&authorization_value(config))?,
+        );
     }
 
     let base_url = calibration::probe_url(&config.order_url)?;
@@ -0,0 +1,246 @@
+use crate::bidar::BidarConfig;
+use crate::login::{self, jwt_expiry};
+use crate::runner::Broker;
+use anyhow::{Context, Result};
+use reqwest::header::{ACCEPT, HeaderValue, ORIGIN, REFERER, USER_AGENT};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn default_refresh_url() -> String {
+    "https://api.bidartrader.ir/identity/v1/auth/refresh".to_string()
+}
+
+fn default_refresh_before_secs() -> u64 {
+    120
+}
+
+fn default_access_token_pointer() -> String {
+    "/accessToken".to_string()
+}
+
+fn default_refresh_token_pointer() -> String {
+    "/refreshToken".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Refresh after this long when neither the token nor the response says when
+/// it expires.
+const UNKNOWN_EXPIRY_REFRESH: Duration = Duration::from_secs(600);
+const RETRY_AFTER_FAILURE: Duration = Duration::from_secs(15);
+/// Floor between refreshes, for tokens that live shorter than
+/// `refresh_before_secs`.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+
+/// `token_refresh` section of `config_bidar.json`, used when `refresh_token`
+/// is set.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BidarRefreshConfig {
+    #[serde(default = "default_refresh_url")]
+    pub url: String,
+    /// Renew the access token this many seconds before it expires.
+    #[serde(default = "default_refresh_before_secs")]
+    pub refresh_before_secs: u64,
+    /// JSON pointers into the refresh response.
+    #[serde(default = "default_access_token_pointer")]
+    pub access_token_pointer: String,
+    #[serde(default = "default_refresh_token_pointer")]
+    pub refresh_token_pointer: String,
+    /// Write renewed tokens back to `config_bidar.json`.
+    #[serde(default = "default_true")]
+    pub save_tokens: bool,
+}
+
+impl Default for BidarRefreshConfig {
+    fn default() -> Self {
+        Self {
+            url: default_refresh_url(),
+            refresh_before_secs: default_refresh_before_secs(),
+            access_token_pointer: default_access_token_pointer(),
+            refresh_token_pointer: default_refresh_token_pointer(),
+            save_tokens: true,
+        }
+    }
+}
+
+/// The access token currently in use, shared by every clone of the config so
+/// a refresh reaches orders that are already scheduled.
+#[derive(Debug, Clone, Default)]
+pub struct BidarSession(Arc<RwLock<Option<String>>>);
+
+impl BidarSession {
+    pub fn get(&self) -> Option<String> {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set(&self, access_token: String) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(access_token);
+    }
+}
+
+/// A successful refresh-token exchange.
+#[derive(Debug, Clone)]
+pub struct RefreshedToken {
+    pub access_token: String,
+    /// Set when the server rotates the refresh token.
+    pub refresh_token: Option<String>,
+    pub expires_in_secs: Option<u64>,
+}
+
+fn epoch_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs())
+        .unwrap_or_default()
+}
+
+/// Exchange `refresh_token` for a new access token.
+pub async fn refresh(config: &BidarConfig, refresh_token: &str) -> Result<RefreshedToken> {
+    let refresh_config = &config.token_refresh;
+    let response = reqwest::Client::new()
+        .post(&refresh_config.url)
+        .header(USER_AGENT, HeaderValue::from_str(&config.user_agent)?)
+        .header(ACCEPT, HeaderValue::from_static("application/json"))
+        .header(REFERER, HeaderValue::from_static("https://bidartrader.ir/"))
+        .header(ORIGIN, HeaderValue::from_static("https://bidartrader.ir"))
+        .json(&json!({ "refreshToken": refresh_token }))
+        .send()
+        .await
+        .context("Bidar token refresh request failed")?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "Bidar token refresh failed with status {}: {}",
+            status,
+            crate::decode_unicode_escapes(&body)
+        );
+    }
+
+    let value: Value = serde_json::from_str(&body)
+        .with_context(|| format!("Token refresh returned invalid JSON: {}", body))?;
+    let access_token = value
+        .pointer(&refresh_config.access_token_pointer)
+        .and_then(Value::as_str)
+        .with_context(|| {
+            format!(
+                "Token refresh response has no access token at {}: {}",
+                refresh_config.access_token_pointer, body
+            )
+        })?;
+    Ok(RefreshedToken {
+        access_token: access_token.to_string(),
+        refresh_token: value
+            .pointer(&refresh_config.refresh_token_pointer)
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        expires_in_secs: value.get("expires_in").and_then(Value::as_u64),
+    })
+}
+
+/// Refresh now if the current token is missing or about to expire, then keep
+/// renewing it in the background for the rest of the process.
+pub async fn start_refresher(config: &BidarConfig) -> Result<()> {
+    let Some(refresh_token) = config
+        .refresh_token
+        .clone()
+        .filter(|token| !token.is_empty())
+    else {
+        return Ok(());
+    };
+    let mut refresher = Refresher {
+        config: config.clone(),
+        refresh_token,
+        expires_at: jwt_expiry(&config.current_token()),
+    };
+
+    if refresher.due_in() == Duration::ZERO {
+        refresher
+            .refresh()
+            .await
+            .context("Bidar access token is expired and could not be refreshed")?;
+    }
+    tokio::spawn(async move { refresher.run().await });
+    Ok(())
+}
+
+struct Refresher {
+    config: BidarConfig,
+    refresh_token: String,
+    expires_at: Option<u64>,
+}
+
+impl Refresher {
+    fn due_in(&self) -> Duration {
+        if self.config.current_token().is_empty() {
+            return Duration::ZERO;
+        }
+        match self.expires_at {
+            Some(expires_at) => Duration::from_secs(
+                expires_at
+                    .saturating_sub(self.config.token_refresh.refresh_before_secs)
+                    .saturating_sub(epoch_secs()),
+            ),
+            None => UNKNOWN_EXPIRY_REFRESH,
+        }
+    }
+
+    async fn run(&mut self) {
+        loop {
+            let due_in = self.due_in().max(MIN_REFRESH_INTERVAL);
+            println!(
+                "[Bidar] Next token refresh in {}m {}s",
+                due_in.as_secs() / 60,
+                due_in.as_secs() % 60
+            );
+            tokio::time::sleep(due_in).await;
+            while let Err(e) = self.refresh().await {
+                eprintln!(
+                    "[Bidar] Token refresh failed, retrying in {}s: {:#}",
+                    RETRY_AFTER_FAILURE.as_secs(),
+                    e
+                );
+                tokio::time::sleep(RETRY_AFTER_FAILURE).await;
+            }
+        }
+    }
+
+    async fn refresh(&mut self) -> Result<()> {
+        let token = refresh(&self.config, &self.refresh_token).await?;
+        self.expires_at = jwt_expiry(&token.access_token).or_else(|| {
+            token
+                .expires_in_secs
+                .map(|expires_in| epoch_secs() + expires_in)
+        });
+        if let Some(refresh_token) = &token.refresh_token {
+            self.refresh_token = refresh_token.clone();
+        }
+        self.config.session.set(token.access_token.clone());
+        println!("[Bidar] Access token refreshed");
+
+        if self.config.token_refresh.save_tokens {
+            let path = self.config.config_file();
+            if let Err(e) = login::update_config_fields(
+                path,
+                None,
+                &[
+                    ("authorization", Value::String(token.access_token)),
+                    ("refresh_token", Value::String(self.refresh_token.clone())),
+                ],
+            ) {
+                summary!("[Bidar] Warning: could not save refreshed token: {:#}", e);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use crate::market_data::{MarketData, OrderBook};
+use crate::orders::{OrderEntry, OrderFields};
+use crate::session_summary::SessionSummary;
+use anyhow::{Context, Result};
+use chrono_tz::Asia::Tehran;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+fn default_before_ms() -> u64 {
+    500
+}
+
+fn default_after_ms() -> u64 {
+    2000
+}
+
+/// `book_snapshots` setting: record the order book of every instrument
+/// ordered a moment before and after the send, to see later which queue
+/// the orders joined.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BookSnapshotSettings {
+    /// How long before the send the first snapshot is taken, in scheduled
+    /// mode; continuous runs take it as the first batch goes out.
+    #[serde(default = "default_before_ms")]
+    pub before_ms: u64,
+    /// How long after the send the second snapshot is taken.
+    #[serde(default = "default_after_ms")]
+    pub after_ms: u64,
+}
+
+/// Which side of the send a snapshot was taken on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnapshotMoment {
+    Before,
+    After,
+}
+
+impl std::fmt::Display for SnapshotMoment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Before => write!(f, "before"),
+            Self::After => write!(f, "after"),
+        }
+    }
+}
+
+/// The order book of one instrument at one moment around a send.
+#[derive(Debug, Clone, Serialize)]
+pub struct BookSnapshot {
+    pub isin: String,
+    pub moment: SnapshotMoment,
+    /// When the orders went out, or were scheduled to.
+    pub send_epoch_ms: i64,
+    /// When the book was fetched.
+    pub epoch_ms: i64,
+    pub book: OrderBook,
+}
+
+/// Takes [`BookSnapshot`]s for a broker's instruments and keeps them in the
+/// session summary, next to the broker's send results.
+pub struct BookRecorder {
+    name: String,
+    settings: BookSnapshotSettings,
+    market_data: MarketData,
+    /// ISIN and TSETMC code of each instrument ordered.
+    instruments: Vec<(String, String)>,
+    session: Option<Arc<SessionSummary>>,
+    pending: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl BookRecorder {
+    /// Look up the instruments of `orders` once up front.
+    pub async fn new<T: OrderFields>(
+        name: &str,
+        settings: &BookSnapshotSettings,
+        orders: &[OrderEntry<T>],
+        base_url: &str,
+        session: Option<Arc<SessionSummary>>,
+    ) -> Result<Self> {
+        let market_data = MarketData::new(base_url)?;
+        let mut instruments: Vec<(String, String)> = Vec::new();
+        for (index, order) in orders.iter().enumerate() {
+            let isin =
+                order.data.isin().map(str::trim).with_context(|| {
+                    format!("book_snapshots needs an ISIN on order #{}", index + 1)
+                })?;
+            if instruments.iter().any(|(known, _)| known == isin) {
+                continue;
+            }
+            let instrument = market_data.find_instrument(isin).await?;
+            instruments.push((isin.to_string(), instrument.ins_code));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            settings: settings.clone(),
+            market_data,
+            instruments,
+            session,
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Snapshot every instrument `before_ms` ahead of `send_epoch_ms`, right
+    /// away when that has passed, and again `after_ms` after it, without
+    /// holding up the send.
+    pub fn record_around(self: &Arc<Self>, send_epoch_ms: i64) {
+        let recorder = self.clone();
+        let task = tokio::spawn(async move {
+            let before = send_epoch_ms - recorder.settings.before_ms as i64;
+            recorder
+                .take_at(before, SnapshotMoment::Before, send_epoch_ms)
+                .await;
+            let after = send_epoch_ms + recorder.settings.after_ms as i64;
+            recorder
+                .take_at(after, SnapshotMoment::After, send_epoch_ms)
+                .await;
+        });
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        pending.retain(|task| !task.is_finished());
+        pending.push(task);
+    }
+
+    /// Wait for the snapshots still to be taken.
+    pub async fn finish(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap_or_else(|e| e.into_inner()));
+        for task in pending {
+            let _ = task.await;
+        }
+    }
+
+    async fn take_at(&self, epoch_ms: i64, moment: SnapshotMoment, send_epoch_ms: i64) {
+        let wait = epoch_ms - chrono::Utc::now().timestamp_millis();
+        if wait > 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(wait as u64)).await;
+        }
+        for (isin, ins_code) in &self.instruments {
+            let book = match self.market_data.order_book(ins_code).await {
+                Ok(book) => book,
+                Err(e) => {
+                    eprintln!(
+                        "[{}] Warning: no order book {} the send for {}: {:#}",
+                        self.name, moment, isin, e
+                    );
+                    continue;
+                }
+            };
+            let snapshot = BookSnapshot {
+                isin: isin.clone(),
+                moment,
+                send_epoch_ms,
+                epoch_ms: chrono::Utc::now().timestamp_millis(),
+                book,
+            };
+            println!(
+                "[{}] Book of {} {} the send, at {}: {}",
+                self.name,
+                isin,
+                moment,
+                time_of_day(snapshot.epoch_ms),
+                snapshot.book
+            );
+            if let Some(session) = &self.session {
+                session.add_book_snapshot(&self.name, snapshot);
+            }
+        }
+    }
+}
+
+fn time_of_day(epoch_ms: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(epoch_ms)
+        .map(|time| {
+            time.with_timezone(&Tehran)
+                .format("%H:%M:%S%.3f")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
@@ -1,3 +1,4 @@
+use crate::latency;
 use crate::rate_limiter::RateLimiter;
 use anyhow::{Context, Result};
 use reqwest::StatusCode;
@@ -30,9 +31,20 @@ fn default_max_acceptable_rtt_ms() -> u64 {
     500
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+fn default_max_extra_probes() -> usize {
+    10
+}
+
+/// Samples needed before their spread is judged.
+const MIN_STDDEV_SAMPLES: usize = 3;
+
+/// Width of the probe progress bar.
+const PROGRESS_WIDTH: usize = 20;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum CalibrationEstimator {
+    #[default]
     P50,
     P75,
     P90,
@@ -40,12 +52,82 @@ pub enum CalibrationEstimator {
     Ewma,
 }
 
-impl Default for CalibrationEstimator {
+/// How a calibrated round trip maps to the time an order takes to reach the
+/// broker, which is how early it is put on the wire.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DelayModel {
+    /// Send a full round trip early.
+    #[default]
+    Rtt,
+    /// Send half a round trip early: the order is matched when it arrives,
+    /// not when the response comes back.
+    HalfRtt,
+}
+
+impl DelayModel {
+    /// The send-ahead delay for a round trip of `rtt_ms`.
+    pub fn apply(self, rtt_ms: u64) -> u64 {
+        match self {
+            DelayModel::Rtt => rtt_ms,
+            DelayModel::HalfRtt => rtt_ms.div_ceil(2),
+        }
+    }
+}
+
+fn default_warmup_window_ms() -> u64 {
+    2_500
+}
+
+fn default_warmup_interval_ms() -> u64 {
+    500
+}
+
+/// Probes sent in the last moments before a scheduled send, over the
+/// connections the order goes out on, so they are open and hot for it.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ConnectionWarmup {
+    /// How long before the send the first warm-up probe goes out.
+    #[serde(default = "default_warmup_window_ms")]
+    pub window_ms: u64,
+    /// Gap between warm-up probes; the last one leaves this long before
+    /// the send.
+    #[serde(default = "default_warmup_interval_ms")]
+    pub interval_ms: u64,
+}
+
+impl Default for ConnectionWarmup {
     fn default() -> Self {
-        CalibrationEstimator::P50
+        Self {
+            window_ms: default_warmup_window_ms(),
+            interval_ms: default_warmup_interval_ms(),
+        }
     }
 }
 
+impl ConnectionWarmup {
+    /// Epoch milliseconds of each probe for a send at `send_epoch_ms`.
+    pub fn schedule(&self, send_epoch_ms: i64) -> Vec<i64> {
+        let interval_ms = self.interval_ms.max(1) as i64;
+        (0..)
+            .map(|probe| send_epoch_ms - self.window_ms as i64 + probe * interval_ms)
+            .take_while(|epoch_ms| *epoch_ms <= send_epoch_ms - interval_ms)
+            .collect()
+    }
+}
+
+/// What to do when the probe RTTs spread more than `max_rtt_stddev_ms`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OnUnstable {
+    /// Stop calibrating as soon as the spread is too wide.
+    #[default]
+    Abort,
+    /// Keep probing, judging the latest `probe_count - warmup_probes`
+    /// samples, for up to `max_extra_probes` more probes.
+    Extend,
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct CalibrationConfig {
     #[serde(default = "default_calibration_enabled")]
@@ -62,6 +144,41 @@ pub struct CalibrationConfig {
     pub estimator: CalibrationEstimator,
     #[serde(default = "default_max_acceptable_rtt_ms")]
     pub max_acceptable_rtt_ms: u64,
+    /// Largest standard deviation of the RTTs after warm-up that still gives
+    /// a usable estimate; unchecked if unset.
+    #[serde(default)]
+    pub max_rtt_stddev_ms: Option<f64>,
+    #[serde(default)]
+    pub on_unstable: OnUnstable,
+    #[serde(default = "default_max_extra_probes")]
+    pub max_extra_probes: usize,
+}
+
+impl CalibrationConfig {
+    /// The most probes one calibration may send.
+    pub fn max_probes(&self) -> usize {
+        match (self.max_rtt_stddev_ms, self.on_unstable) {
+            (Some(_), OnUnstable::Extend) => self.probe_count + self.max_extra_probes,
+            _ => self.probe_count,
+        }
+    }
+}
+
+impl Default for CalibrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_calibration_enabled(),
+            probe_count: default_probe_count(),
+            probe_interval_ms: default_probe_interval_ms(),
+            warmup_probes: default_warmup_probes(),
+            safety_margin_ms: default_safety_margin_ms(),
+            estimator: CalibrationEstimator::default(),
+            max_acceptable_rtt_ms: default_max_acceptable_rtt_ms(),
+            max_rtt_stddev_ms: None,
+            on_unstable: OnUnstable::default(),
+            max_extra_probes: default_max_extra_probes(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -92,9 +209,11 @@ where
         anyhow::bail!("warmup_probes must be less than probe_count");
     }
 
-    let mut rtts_ms = Vec::with_capacity(calibration.probe_count);
-    let mut last_probe_wall = SystemTime::now();
+    let window = calibration.probe_count - calibration.warmup_probes;
+    let mut rtts_ms = Vec::with_capacity(calibration.max_probes());
+    let mut last_probe_wall;
     let mut last_wall_time = SystemTime::now();
+    let mut previous_probe_start: Option<Instant> = None;
 
     println!(
         "{} Calibration enabled: {} probes every {}ms (warmup: {})",
@@ -104,8 +223,15 @@ where
         calibration.warmup_probes
     );
 
-    for probe_index in 0..calibration.probe_count {
-        let probe_start = Instant::now();
+    loop {
+        if let Some(previous) = previous_probe_start {
+            let elapsed = previous.elapsed();
+            let target = Duration::from_millis(calibration.probe_interval_ms);
+            if elapsed < target {
+                sleep(target - elapsed).await;
+            }
+        }
+        previous_probe_start = Some(Instant::now());
 
         rate_limiter.wait().await;
         let current_wall = SystemTime::now();
@@ -113,17 +239,25 @@ where
             anyhow::bail!("System clock moved backwards during calibration; aborting");
         }
         last_wall_time = current_wall;
-        let (rtt_ms, rtt_micros, status) = send_probe().await?;
+        let (probe, phases) = latency::timed(send_probe()).await;
+        let (rtt_ms, rtt_micros, status) = probe?;
         last_probe_wall = SystemTime::now();
+        rtts_ms.push(rtt_ms);
+        let probes = rtts_ms.len();
+        let samples = latest_samples(&rtts_ms, calibration.warmup_probes, window);
+        let stddev_ms = std_dev(samples);
 
         println!(
-            "{} Probe #{}/{} status={} rtt={}ms ({}µs)",
+            "{} Probe #{}/{} {} status={} rtt={}ms ({}µs) stddev={:.1}ms{}",
             broker_label,
-            probe_index + 1,
+            probes,
             calibration.probe_count,
+            progress_bar(probes, calibration.probe_count),
             status,
             rtt_ms,
-            rtt_micros
+            rtt_micros,
+            stddev_ms,
+            phases.describe()
         );
 
         if rtt_ms > calibration.max_acceptable_rtt_ms {
@@ -134,22 +268,40 @@ where
             );
         }
 
-        rtts_ms.push(rtt_ms);
-
-        if probe_index + 1 < calibration.probe_count {
-            let elapsed = probe_start.elapsed();
-            let target = Duration::from_millis(calibration.probe_interval_ms);
-            if elapsed < target {
-                sleep(target - elapsed).await;
+        let unstable = calibration
+            .max_rtt_stddev_ms
+            .filter(|max| samples.len() >= MIN_STDDEV_SAMPLES && stddev_ms > *max);
+        if let Some(max_stddev_ms) = unstable {
+            match calibration.on_unstable {
+                OnUnstable::Abort => anyhow::bail!(
+                    "Probe RTT standard deviation {:.1}ms exceeded max_rtt_stddev_ms {} after {} probes; the connection is too unstable to calibrate",
+                    stddev_ms,
+                    max_stddev_ms,
+                    probes
+                ),
+                OnUnstable::Extend if probes >= calibration.max_probes() => anyhow::bail!(
+                    "Probe RTT standard deviation {:.1}ms still exceeds max_rtt_stddev_ms {} after {} extra probes",
+                    stddev_ms,
+                    max_stddev_ms,
+                    calibration.max_extra_probes
+                ),
+                OnUnstable::Extend if probes >= calibration.probe_count => {
+                    println!(
+                        "{} RTT standard deviation {:.1}ms exceeds max_rtt_stddev_ms {}; probing again",
+                        broker_label, stddev_ms, max_stddev_ms
+                    );
+                    continue;
+                }
+                OnUnstable::Extend => {}
             }
         }
+
+        if probes >= calibration.probe_count {
+            break;
+        }
     }
 
-    let samples_ms = rtts_ms
-        .iter()
-        .skip(calibration.warmup_probes)
-        .copied()
-        .collect::<Vec<_>>();
+    let samples_ms = latest_samples(&rtts_ms, calibration.warmup_probes, window).to_vec();
 
     if samples_ms.is_empty() {
         anyhow::bail!("No calibration samples available after warmup.");
@@ -192,6 +344,38 @@ where
     })
 }
 
+/// The last `window` RTTs after the warm-up probes.
+fn latest_samples(rtts_ms: &[u64], warmup_probes: usize, window: usize) -> &[u64] {
+    let samples = rtts_ms.get(warmup_probes..).unwrap_or_default();
+    &samples[samples.len().saturating_sub(window)..]
+}
+
+/// Population standard deviation, 0 for fewer than two samples.
+pub fn std_dev(samples: &[u64]) -> f64 {
+    if samples.len() < 2 {
+        return 0.0;
+    }
+    let mean = samples.iter().sum::<u64>() as f64 / samples.len() as f64;
+    let variance = samples
+        .iter()
+        .map(|&sample| (sample as f64 - mean).powi(2))
+        .sum::<f64>()
+        / samples.len() as f64;
+    variance.sqrt()
+}
+
+/// `[#######-------------]` for `done` of `total` probes, full once extended.
+fn progress_bar(done: usize, total: usize) -> String {
+    let filled = (done.min(total) * PROGRESS_WIDTH)
+        .checked_div(total)
+        .unwrap_or(PROGRESS_WIDTH);
+    format!(
+        "[{}{}]",
+        "#".repeat(filled),
+        "-".repeat(PROGRESS_WIDTH - filled)
+    )
+}
+
 pub fn probe_url(order_url: &str) -> Result<String> {
     let parsed = reqwest::Url::parse(order_url)
         .with_context(|| format!("Invalid order_url {}", order_url))?;
@@ -203,7 +387,7 @@ pub fn probe_url(order_url: &str) -> Result<String> {
     Ok(base)
 }
 
-fn percentile(sorted: &[u64], percentile: f64) -> u64 {
+pub(crate) fn percentile(sorted: &[u64], percentile: f64) -> u64 {
     if sorted.is_empty() {
         return 0;
     }
@@ -0,0 +1,408 @@
+//! `cancel_replace` strategy: follow each order once the broker registered
+//! it, poll its status, and when it is still unfilled after a window cancel
+//! it and send it again one price step further, up to a number of times.
+//! Registration is read from the response that accepted the order, so the
+//! order ID has to be in it.
+
+use crate::orders::OrderFields;
+use crate::runner::Broker;
+use anyhow::{Context, Result};
+use reqwest::Method;
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::Instant;
+
+fn default_poll_interval_ms() -> u64 {
+    500
+}
+
+fn default_cancel_method() -> String {
+    "DELETE".to_string()
+}
+
+/// `cancel_replace` setting.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CancelReplaceSettings {
+    /// JSON pointer to the order ID in the response that accepted the order.
+    pub order_id: String,
+    pub status: StatusCheck,
+    pub cancel: CancelRequest,
+    /// How long a registered order may stay unfilled before it is replaced.
+    pub window_ms: u64,
+    /// Added to the price of each replacement; negative to step down.
+    pub price_step: f64,
+    /// Replacements of one order at most; it is left open after the last.
+    pub max_replacements: usize,
+}
+
+/// The broker's endpoint reporting one order.
+#[derive(Debug, Deserialize, Clone)]
+pub struct StatusCheck {
+    /// `{order_id}` stands for the order's ID.
+    pub url: String,
+    /// JSON pointer to the order's state in the response.
+    pub state: String,
+    /// States meaning the order was filled.
+    pub filled: Vec<Value>,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+/// The broker's endpoint cancelling one order.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CancelRequest {
+    /// `{order_id}` stands for the order's ID.
+    pub url: String,
+    #[serde(default = "default_cancel_method")]
+    pub method: String,
+    /// Sent as JSON with `{order_id}` replaced in its strings; no body when
+    /// unset.
+    #[serde(default)]
+    pub body: Option<Value>,
+}
+
+/// Check the broker's `cancel_replace` setting, if it has one, and stop
+/// sending each order once it was accepted, so the only copies sent after
+/// that are the replacements. Accounts, `batch_repeat` and an order's
+/// `repeat` would leave several copies of one order open, so they are
+/// refused, as are orders without a price to step from.
+pub fn apply<B: Broker>(broker: &mut B) -> Result<()> {
+    let Some(settings) = broker.settings().cancel_replace.clone() else {
+        return Ok(());
+    };
+    let name = broker.name().to_string();
+    if !broker.accounts().is_empty() {
+        anyhow::bail!("{} has accounts and cannot use cancel_replace", name);
+    }
+    if settings.window_ms == 0 || settings.status.poll_interval_ms == 0 {
+        anyhow::bail!(
+            "cancel_replace of {} needs a window_ms and status.poll_interval_ms of at least 1",
+            name
+        );
+    }
+    if settings.price_step == 0.0 {
+        anyhow::bail!("cancel_replace of {} needs a price_step other than 0", name);
+    }
+    Method::from_bytes(settings.cancel.method.as_bytes()).with_context(|| {
+        format!(
+            "Invalid cancel_replace.cancel.method '{}' for {}",
+            settings.cancel.method, name
+        )
+    })?;
+    if broker.settings().batch_repeat > 1 {
+        anyhow::bail!(
+            "{} has batch_repeat {} and cannot use cancel_replace, which keeps one copy of each order open",
+            name,
+            broker.settings().batch_repeat
+        );
+    }
+    for (index, order) in broker.orders().iter().enumerate() {
+        if order.repeat > 1 {
+            anyhow::bail!(
+                "Order #{} of {} has repeat {} and cannot use cancel_replace, which keeps one copy of each order open",
+                index + 1,
+                name,
+                order.repeat
+            );
+        }
+        if order.price_limit.is_none() && order.data.price().is_none() {
+            anyhow::bail!(
+                "Order #{} of {} needs a price for cancel_replace to step from",
+                index + 1,
+                name
+            );
+        }
+    }
+    broker.settings_mut().stop_on_accept = true;
+    println!(
+        "[{}] Cancel and replace: orders unfilled after {}ms are sent again {} apart in price, up to {} time(s)",
+        name, settings.window_ms, settings.price_step, settings.max_replacements
+    );
+    Ok(())
+}
+
+/// An order the broker accepted.
+#[derive(Debug)]
+pub struct Registered {
+    pub index: usize,
+    /// Price it was sent at.
+    pub price: f64,
+    /// Body of the response that accepted it.
+    pub body: String,
+}
+
+/// What the follower needs from the broker whose orders it follows.
+pub trait Resender: Send + Sync + 'static {
+    /// Headers carrying the broker's current session.
+    fn session_headers(&self) -> Result<HeaderMap>;
+
+    /// Send order `index` again at `price`, returning the body of the
+    /// response that accepted it.
+    fn resend(&self, index: usize, price: f64) -> impl Future<Output = Result<String>> + Send;
+}
+
+/// Follows every order sent to it over the channel [`Follower::start`]
+/// returns until it is filled, replaced `max_replacements` times, or the
+/// follower is dropped.
+pub struct Follower {
+    task: JoinHandle<()>,
+    finish: Arc<Notify>,
+}
+
+impl Follower {
+    pub fn start<R: Resender>(
+        name: &str,
+        settings: &CancelReplaceSettings,
+        resender: R,
+    ) -> (Self, UnboundedSender<Registered>) {
+        let (sender, registered) = mpsc::unbounded_channel();
+        let finish = Arc::new(Notify::new());
+        let replacer = Arc::new(Replacer {
+            name: name.to_string(),
+            settings: settings.clone(),
+            client: reqwest::Client::new(),
+            resender,
+        });
+        let task = tokio::spawn(follow(replacer, registered, finish.clone()));
+        (Self { task, finish }, sender)
+    }
+
+    /// Wait for the orders still followed, taking no new ones.
+    pub async fn finish(mut self) {
+        self.finish.notify_one();
+        let _ = (&mut self.task).await;
+    }
+}
+
+impl Drop for Follower {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn follow<R: Resender>(
+    replacer: Arc<Replacer<R>>,
+    mut registered: UnboundedReceiver<Registered>,
+    finish: Arc<Notify>,
+) {
+    let mut orders = JoinSet::new();
+    loop {
+        tokio::select! {
+            Some(order) = registered.recv() => {
+                orders.spawn(replacer.clone().replace_until_filled(order));
+            }
+            Some(_) = orders.join_next(), if !orders.is_empty() => {}
+            _ = finish.notified() => break,
+        }
+    }
+    while let Ok(order) = registered.try_recv() {
+        orders.spawn(replacer.clone().replace_until_filled(order));
+    }
+    while orders.join_next().await.is_some() {}
+}
+
+struct Replacer<R> {
+    name: String,
+    settings: CancelReplaceSettings,
+    client: reqwest::Client,
+    resender: R,
+}
+
+impl<R: Resender> Replacer<R> {
+    async fn replace_until_filled(self: Arc<Self>, order: Registered) {
+        let Registered {
+            index,
+            mut price,
+            mut body,
+        } = order;
+        let name = &self.name;
+        let settings = &self.settings;
+        for replacement in 1.. {
+            let Some(order_id) = order_id(&body, &settings.order_id) else {
+                eprintln!(
+                    "[{}] Warning: no order ID at {} in the response accepting order #{}; not following it: {}",
+                    name,
+                    settings.order_id,
+                    index + 1,
+                    body
+                );
+                return;
+            };
+            match self.wait_for_fill(&order_id).await {
+                Ok(true) => {
+                    summary!("[{}] Order #{} ({}) was filled", name, index + 1, order_id);
+                    return;
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    eprintln!(
+                        "[{}] Warning: failed to check order #{} ({}); leaving it open: {:#}",
+                        name,
+                        index + 1,
+                        order_id,
+                        e
+                    );
+                    return;
+                }
+            }
+            if replacement > settings.max_replacements {
+                summary!(
+                    "[{}] Order #{} ({}) is still unfilled after {} replacement(s); leaving it open",
+                    name,
+                    index + 1,
+                    order_id,
+                    settings.max_replacements
+                );
+                return;
+            }
+            let next_price = price + settings.price_step;
+            if next_price <= 0.0 {
+                summary!(
+                    "[{}] Order #{} ({}) cannot step below a price of {}; leaving it open",
+                    name,
+                    index + 1,
+                    order_id,
+                    price
+                );
+                return;
+            }
+            if let Err(e) = self.cancel(&order_id).await {
+                eprintln!(
+                    "[{}] Warning: failed to cancel order #{} ({}); leaving it open: {:#}",
+                    name,
+                    index + 1,
+                    order_id,
+                    e
+                );
+                return;
+            }
+            price = next_price;
+            summary!(
+                "[{}] Order #{} ({}) was unfilled after {}ms; cancelled it and sending it at {} ({}/{})",
+                name,
+                index + 1,
+                order_id,
+                settings.window_ms,
+                price,
+                replacement,
+                settings.max_replacements
+            );
+            body = match self.resender.resend(index, price).await {
+                Ok(body) => body,
+                Err(e) => {
+                    eprintln!(
+                        "[{}] Replacement of order #{} at {} failed: {:#}",
+                        name,
+                        index + 1,
+                        price,
+                        e
+                    );
+                    return;
+                }
+            };
+        }
+    }
+
+    /// Poll the order's status until it is filled or the window passed;
+    /// `true` when it was filled.
+    async fn wait_for_fill(&self, order_id: &str) -> Result<bool> {
+        let status = &self.settings.status;
+        let deadline = Instant::now() + Duration::from_millis(self.settings.window_ms);
+        let poll_interval = Duration::from_millis(status.poll_interval_ms);
+        loop {
+            if self.is_filled(order_id).await? {
+                return Ok(true);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            tokio::time::sleep(poll_interval.min(deadline - now)).await;
+        }
+    }
+
+    async fn is_filled(&self, order_id: &str) -> Result<bool> {
+        let status = &self.settings.status;
+        let url = status.url.replace("{order_id}", order_id);
+        let body = self
+            .request(self.client.get(&url))
+            .await
+            .context("Order status request failed")?;
+        let value: Value = serde_json::from_str(&body)
+            .with_context(|| format!("Invalid order status response: {}", body))?;
+        let state = value.pointer(&status.state).with_context(|| {
+            format!(
+                "No order state at {} in the status response: {}",
+                status.state, body
+            )
+        })?;
+        Ok(status.filled.contains(state))
+    }
+
+    async fn cancel(&self, order_id: &str) -> Result<()> {
+        let cancel = &self.settings.cancel;
+        let method = Method::from_bytes(cancel.method.as_bytes())?;
+        let mut request = self
+            .client
+            .request(method, cancel.url.replace("{order_id}", order_id));
+        if let Some(body) = &cancel.body {
+            request = request.json(&with_order_id(body, order_id));
+        }
+        self.request(request)
+            .await
+            .context("Cancel request failed")?;
+        Ok(())
+    }
+
+    /// Send `request` with the broker's session, returning the body of a 2xx
+    /// response.
+    async fn request(&self, request: reqwest::RequestBuilder) -> Result<String> {
+        let response = request
+            .headers(self.resender.session_headers()?)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!(
+                "status {}: {}",
+                status,
+                crate::decode_unicode_escapes(&body)
+            );
+        }
+        Ok(body)
+    }
+}
+
+/// The order ID at `pointer` in `body`, given as a string or a number.
+pub fn order_id(body: &str, pointer: &str) -> Option<String> {
+    let value: Value = serde_json::from_str(body).ok()?;
+    match value.pointer(pointer)? {
+        Value::String(id) => Some(id.clone()),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    }
+}
+
+/// `body` with `{order_id}` replaced in every string.
+fn with_order_id(body: &Value, order_id: &str) -> Value {
+    match body {
+        Value::String(text) => Value::String(text.replace("{order_id}", order_id)),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| with_order_id(item, order_id))
+            .collect(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(key, value)| (key.clone(), with_order_id(value, order_id)))
+            .collect(),
+        other => other.clone(),
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::future::Future;
+use std::time::Duration;
+
+/// A captcha image returned by a broker's login endpoint.
+#[derive(Debug, Clone)]
+pub struct Captcha {
+    pub image: Vec<u8>,
+    /// Extension used when the image is written to disk, e.g. `png`.
+    pub extension: String,
+}
+
+/// Turns a captcha image into the text the login form expects.
+pub trait CaptchaSolver: Send + Sync {
+    fn solve(&self, captcha: &Captcha) -> impl Future<Output = Result<String>> + Send;
+}
+
+fn default_image_path() -> String {
+    "captcha".to_string()
+}
+
+fn default_answer_pointer() -> String {
+    "/text".to_string()
+}
+
+fn default_timeout_ms() -> u64 {
+    30_000
+}
+
+/// `captcha` section of a broker config that logs in automatically.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CaptchaConfig {
+    /// Save the image and read the answer from the console.
+    Manual {
+        /// Path without extension; the image's extension is appended.
+        #[serde(default = "default_image_path")]
+        image_path: String,
+    },
+    /// POST the base64 image to a solver service and read the answer from
+    /// its JSON response.
+    Http {
+        url: String,
+        #[serde(default)]
+        api_key: Option<String>,
+        #[serde(default = "default_answer_pointer")]
+        answer_pointer: String,
+        #[serde(default = "default_timeout_ms")]
+        timeout_ms: u64,
+    },
+}
+
+impl Default for CaptchaConfig {
+    fn default() -> Self {
+        CaptchaConfig::Manual {
+            image_path: default_image_path(),
+        }
+    }
+}
+
+/// The solver selected by a [`CaptchaConfig`].
+pub enum ConfiguredSolver {
+    Manual(ManualSolver),
+    Http(HttpSolver),
+}
+
+impl ConfiguredSolver {
+    pub fn from_config(config: &CaptchaConfig) -> Result<Self> {
+        Ok(match config {
+            CaptchaConfig::Manual { image_path } => ConfiguredSolver::Manual(ManualSolver {
+                image_path: image_path.clone(),
+            }),
+            CaptchaConfig::Http {
+                url,
+                api_key,
+                answer_pointer,
+                timeout_ms,
+            } => ConfiguredSolver::Http(HttpSolver::new(
+                url.clone(),
+                api_key.clone(),
+                answer_pointer.clone(),
+                Duration::from_millis(*timeout_ms),
+            )?),
+        })
+    }
+}
+
+impl CaptchaSolver for ConfiguredSolver {
+    async fn solve(&self, captcha: &Captcha) -> Result<String> {
+        match self {
+            ConfiguredSolver::Manual(solver) => solver.solve(captcha).await,
+            ConfiguredSolver::Http(solver) => solver.solve(captcha).await,
+        }
+    }
+}
+
+/// Writes the captcha next to the binary and asks the user to type it.
+pub struct ManualSolver {
+    pub image_path: String,
+}
+
+impl CaptchaSolver for ManualSolver {
+    async fn solve(&self, captcha: &Captcha) -> Result<String> {
+        let path = format!("{}.{}", self.image_path, captcha.extension);
+        std::fs::write(&path, &captcha.image)
+            .with_context(|| format!("Failed to write captcha image to {}", path))?;
+        println!("[Captcha] Image saved to {}", path);
+
+        crate::login::prompt_line("[Captcha] Enter the captcha text: ")
+            .await
+            .context("No captcha answer entered")
+    }
+}
+
+/// Sends `{"image": "<base64>", "api_key": ...}` to a solver service.
+pub struct HttpSolver {
+    client: reqwest::Client,
+    url: String,
+    api_key: Option<String>,
+    answer_pointer: String,
+}
+
+impl HttpSolver {
+    pub fn new(
+        url: String,
+        api_key: Option<String>,
+        answer_pointer: String,
+        timeout: Duration,
+    ) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .context("Failed to build captcha solver client")?;
+        Ok(Self {
+            client,
+            url,
+            api_key,
+            answer_pointer,
+        })
+    }
+}
+
+impl CaptchaSolver for HttpSolver {
+    async fn solve(&self, captcha: &Captcha) -> Result<String> {
+        let mut request = json!({ "image": STANDARD.encode(&captcha.image) });
+        if let Some(api_key) = &self.api_key {
+            request["api_key"] = Value::String(api_key.clone());
+        }
+
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .context("Captcha solver request failed")?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!("Captcha solver returned status {}: {}", status, body);
+        }
+
+        let value: Value = serde_json::from_str(&body)
+            .with_context(|| format!("Captcha solver returned invalid JSON: {}", body))?;
+        let answer = match value.pointer(&self.answer_pointer) {
+            Some(Value::String(text)) => text.trim().to_string(),
+            Some(Value::Number(number)) => number.to_string(),
+            _ => anyhow::bail!(
+                "Captcha solver response has no answer at {}: {}",
+                self.answer_pointer,
+                body
+            ),
+        };
+        println!("[Captcha] Solver answered {}", answer);
+        Ok(answer)
+    }
+}
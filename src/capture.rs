@@ -0,0 +1,184 @@
+//! `--capture-dir`: keep every order request and the broker's raw response
+//! in a file of its own, named after the time it was sent, as evidence when
+//! a broker claims an order never arrived. Cookies and authorization headers
+//! are left out. A capture that cannot be written is only reported. The same
+//! exchange feeds `--record-fixtures`; see [`crate::fixtures`].
+
+use crate::fixtures::{self, Fixture};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use chrono::DateTime;
+use chrono_tz::{Asia::Tehran, Tz};
+use reqwest::header::{AUTHORIZATION, COOKIE, HeaderMap, HeaderName, SET_COOKIE};
+use reqwest::{StatusCode, Url};
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+static DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Capture every order sent from now on into `dir`, created if missing.
+pub fn set_dir(dir: &str) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create capture directory {}", dir))?;
+    if DIR.set(PathBuf::from(dir)).is_err() {
+        anyhow::bail!("The capture directory is already set");
+    }
+    Ok(())
+}
+
+/// Whether `name` holds credentials that never go to disk.
+pub fn is_credential_header(name: &HeaderName) -> bool {
+    [AUTHORIZATION, COOKIE, SET_COOKIE].contains(name)
+}
+
+/// One order request being captured; does nothing without `--capture-dir`
+/// or `--record-fixtures`.
+pub struct Capture(Option<Box<Pending>>);
+
+struct Pending {
+    name: String,
+    sent_at: DateTime<Tz>,
+    url: Url,
+    headers: HeaderMap,
+    body: Bytes,
+    response: Option<(DateTime<Tz>, StatusCode, HeaderMap)>,
+}
+
+impl Capture {
+    /// Start capturing the request `name` is about to send. Only copies it;
+    /// nothing is formatted before the response is in.
+    pub fn start(name: &str, url: &Url, headers: &HeaderMap, body: &Bytes) -> Self {
+        if DIR.get().is_none() && !fixtures::recording() {
+            return Self(None);
+        }
+        Self(Some(Box::new(Pending {
+            name: name.to_string(),
+            sent_at: chrono::Utc::now().with_timezone(&Tehran),
+            url: url.clone(),
+            headers: headers.clone(),
+            body: body.clone(),
+            response: None,
+        })))
+    }
+
+    /// Pass the outcome of sending through, writing the capture out when the
+    /// request failed without a response.
+    pub fn sent(
+        &mut self,
+        response: reqwest::Result<reqwest::Response>,
+    ) -> reqwest::Result<reqwest::Response> {
+        if let Err(e) = &response
+            && let Some(pending) = self.0.take()
+        {
+            let mut ending = format!("No response: {}", e);
+            let mut source = std::error::Error::source(e);
+            while let Some(cause) = source {
+                let _ = write!(ending, ": {}", cause);
+                source = cause.source();
+            }
+            ending.push('\n');
+            pending.write(&ending);
+        }
+        response
+    }
+
+    /// Record the status line and headers of the response.
+    pub fn response_head(&mut self, status: StatusCode, headers: &HeaderMap) {
+        if let Some(pending) = &mut self.0 {
+            let received_at = chrono::Utc::now().with_timezone(&Tehran);
+            pending.response = Some((received_at, status, headers.clone()));
+        }
+    }
+
+    /// Record the response body and write the capture out.
+    pub fn finish(mut self, body: &str) {
+        let Some(pending) = self.0.take() else {
+            return;
+        };
+        if let Some((_, status, headers)) = &pending.response
+            && fixtures::recording()
+        {
+            let fixture = Fixture::new(
+                &pending.name,
+                &pending.url,
+                &pending.headers,
+                &pending.body,
+                *status,
+                headers,
+                body,
+            );
+            fixtures::record(&fixture, &pending.sent_at);
+        }
+        let mut ending = String::new();
+        if let Some((received_at, status, headers)) = &pending.response {
+            let _ = writeln!(
+                ending,
+                "Received at {}\nHTTP {}",
+                received_at.format("%Y-%m-%d %H:%M:%S%.6f"),
+                status
+            );
+            write_headers(&mut ending, headers);
+            ending.push('\n');
+        }
+        ending.push_str(body);
+        ending.push('\n');
+        pending.write(&ending);
+    }
+}
+
+impl Pending {
+    /// Write the request followed by `ending`, what came back.
+    fn write(&self, ending: &str) {
+        let Some(dir) = DIR.get() else {
+            return;
+        };
+        let mut text = format!(
+            "Sent at {}\nPOST {}\n",
+            self.sent_at.format("%Y-%m-%d %H:%M:%S%.6f"),
+            self.url
+        );
+        write_headers(&mut text, &self.headers);
+        text.push('\n');
+        text.push_str(&String::from_utf8_lossy(&self.body));
+        text.push_str("\n\n");
+        text.push_str(ending);
+
+        let path = dir.join(format!("{}.txt", file_stem(&self.name, &self.sent_at)));
+        if let Err(e) = std::fs::write(&path, text) {
+            eprintln!(
+                "[{}] Warning: could not write capture {}: {}",
+                self.name,
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// File name, without extension, of what `name` sent at `sent_at`: the
+/// time, the broker and the attempt's correlation ID.
+pub fn file_stem(name: &str, sent_at: &DateTime<Tz>) -> String {
+    let mut stem = format!(
+        "{}_{}",
+        sent_at.format("%Y%m%d-%H%M%S%.6f"),
+        name.replace(['/', '\\', ' '], "-")
+    );
+    if let Some(id) = crate::attempt::current_id() {
+        stem.push('_');
+        stem.push_str(&id);
+    }
+    stem
+}
+
+/// One `Name: value` line per header, credentials redacted.
+fn write_headers(text: &mut String, headers: &HeaderMap) {
+    for (name, value) in headers {
+        let value = if is_credential_header(name) {
+            "<redacted>"
+        } else {
+            value.to_str().unwrap_or("<binary>")
+        };
+        let _ = writeln!(text, "{}: {}", name, value);
+    }
+}
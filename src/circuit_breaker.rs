@@ -0,0 +1,160 @@
+//! `circuit_breaker`: stop sending to a broker whose endpoint is down. After
+//! `failure_threshold` hard failures in a row, connection errors, timeouts or
+//! 5xx responses, orders are skipped for `cool_down_ms`; then a single order
+//! goes out as a probe, and full-rate sending resumes only once it gets an
+//! answer from the broker.
+
+use crate::errors::OrderError;
+use serde::Deserialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_cool_down_ms() -> u64 {
+    30_000
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CircuitBreakerSettings {
+    /// Consecutive hard failures that open the circuit.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long an open circuit skips orders before probing.
+    #[serde(default = "default_cool_down_ms")]
+    pub cool_down_ms: u64,
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            cool_down_ms: default_cool_down_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// One order is out as the probe; every other one is skipped.
+    HalfOpen,
+}
+
+pub struct CircuitBreaker {
+    name: String,
+    failure_threshold: u32,
+    cool_down: Duration,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, settings: &CircuitBreakerSettings) -> Self {
+        Self {
+            name: name.to_string(),
+            failure_threshold: settings.failure_threshold.max(1),
+            cool_down: Duration::from_millis(settings.cool_down_ms),
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether an order may go out now. Once the cool-down is over the first
+    /// caller is let through as the probe.
+    pub fn admit(&self) -> bool {
+        let mut state = self.state();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if Instant::now() >= until => {
+                *state = State::HalfOpen;
+                println!(
+                    "[{}] Circuit breaker half-open; sending one order as a probe",
+                    self.name
+                );
+                true
+            }
+            State::Open { .. } | State::HalfOpen => false,
+        }
+    }
+
+    /// Hand back an admission that sent nothing, so the next order can be
+    /// the probe instead.
+    pub fn release(&self) {
+        let mut state = self.state();
+        if *state == State::HalfOpen {
+            *state = State::Open {
+                until: Instant::now(),
+            };
+        }
+    }
+
+    /// Count the outcome of one send. Any answer from the broker, even a
+    /// rejection, shows the endpoint is up and closes the circuit.
+    pub fn record(&self, result: &anyhow::Result<()>) {
+        let hard = result.as_ref().err().is_some_and(is_hard_failure);
+        let mut state = self.state();
+        *state = match (*state, hard) {
+            (State::HalfOpen, false) => {
+                summary!(
+                    "[{}] Circuit breaker closed; the probe got through, resuming",
+                    self.name
+                );
+                State::Closed { failures: 0 }
+            }
+            (State::HalfOpen, true) => {
+                summary!(
+                    "[{}] Circuit breaker probe failed; skipping orders for another {}ms",
+                    self.name,
+                    self.cool_down.as_millis()
+                );
+                State::Open {
+                    until: Instant::now() + self.cool_down,
+                }
+            }
+            (State::Closed { failures }, true) if failures + 1 >= self.failure_threshold => {
+                summary!(
+                    "[{}] Circuit breaker open after {} hard failures in a row; skipping orders for {}ms",
+                    self.name,
+                    failures + 1,
+                    self.cool_down.as_millis()
+                );
+                State::Open {
+                    until: Instant::now() + self.cool_down,
+                }
+            }
+            (State::Closed { failures }, true) => State::Closed {
+                failures: failures + 1,
+            },
+            (State::Closed { .. }, false) => State::Closed { failures: 0 },
+            // Sends admitted before the circuit opened.
+            (open @ State::Open { .. }, _) => open,
+        };
+    }
+
+    pub fn is_open(&self) -> bool {
+        !matches!(*self.state(), State::Closed { .. })
+    }
+}
+
+/// Whether `error` means the broker could not be reached or failed itself:
+/// a connection error, a timeout or a 5xx response.
+pub fn is_hard_failure(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            return e.is_connect() || e.is_timeout();
+        }
+        cause
+            .downcast_ref::<OrderError>()
+            .is_some_and(|e| e.status.is_server_error())
+    })
+}
@@ -0,0 +1,239 @@
+//! `compare`: probe every configured broker back to back and rank them by
+//! latency, to decide which account to lean on for an IPO.
+
+use crate::calibration::{self, percentile, std_dev};
+use crate::latency;
+use crate::registry::{self, AnyBroker};
+use crate::runner::Broker;
+use crate::with_broker;
+use anyhow::Result;
+use reqwest::Version;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use std::cmp::Ordering;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+pub fn default_probes() -> usize {
+    10
+}
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Probe results of one broker.
+#[derive(Debug, Clone)]
+pub struct Comparison {
+    pub name: String,
+    /// RTTs of the probes after the first, in microseconds.
+    pub rtts_us: Vec<u64>,
+    /// TCP and TLS handshakes of the first probe.
+    pub connect: Option<Duration>,
+    pub version: Option<Version>,
+    pub remote_addr: Option<SocketAddr>,
+    /// Why probing stopped early.
+    pub error: Option<String>,
+}
+
+impl Comparison {
+    fn sorted(&self) -> Vec<u64> {
+        let mut sorted = self.rtts_us.clone();
+        sorted.sort_unstable();
+        sorted
+    }
+
+    pub fn p50_us(&self) -> Option<u64> {
+        (!self.rtts_us.is_empty()).then(|| percentile(&self.sorted(), 50.0))
+    }
+
+    pub fn p90_us(&self) -> Option<u64> {
+        (!self.rtts_us.is_empty()).then(|| percentile(&self.sorted(), 90.0))
+    }
+
+    /// Standard deviation of the RTTs, in microseconds.
+    pub fn jitter_us(&self) -> f64 {
+        std_dev(&self.rtts_us)
+    }
+}
+
+/// Sort fastest first by p50, then p90; brokers without a measurement last.
+pub fn rank(comparisons: &mut [Comparison]) {
+    comparisons.sort_by(|a, b| match (a.p50_us(), b.p50_us()) {
+        (Some(a_p50), Some(b_p50)) => a_p50.cmp(&b_p50).then(a.p90_us().cmp(&b.p90_us())),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    });
+}
+
+/// Send `probes` calibration probes to `broker` over a fresh connection,
+/// spaced by its probe interval and never faster than `batch_delay_ms`.
+/// The first one opens the connection and only counts for `connect`.
+pub async fn probe_broker<B: Broker>(broker: &B, probes: usize) -> Comparison {
+    let mut comparison = Comparison {
+        name: broker.name().to_string(),
+        rtts_us: Vec::new(),
+        connect: None,
+        version: None,
+        remote_addr: None,
+        error: None,
+    };
+    let settings = broker.settings();
+    let interval = Duration::from_millis(
+        settings
+            .calibration
+            .as_ref()
+            .map_or(300, |calibration| calibration.probe_interval_ms)
+            .max(settings.batch_delay_ms),
+    );
+    let url = match calibration::probe_url(broker.order_url()) {
+        Ok(url) => url,
+        Err(e) => {
+            comparison.error = Some(format!("{:#}", e));
+            return comparison;
+        }
+    };
+    let mut headers = match broker.order_headers("{}") {
+        Ok(headers) => headers,
+        Err(e) => {
+            comparison.error = Some(format!("invalid credentials in config: {}", e));
+            return comparison;
+        }
+    };
+    headers.remove(CONTENT_TYPE);
+    headers.remove(CONTENT_LENGTH);
+
+    let client = match latency::builder(&settings.socket)
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            comparison.error = Some(format!("failed to build the HTTP client: {}", e));
+            return comparison;
+        }
+    };
+    for probe in 0..probes {
+        if probe > 0 {
+            tokio::time::sleep(interval).await;
+        }
+        let start = Instant::now();
+        let (response, phases) =
+            latency::timed(client.head(&url).headers(headers.clone()).send()).await;
+        let rtt = start.elapsed();
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                comparison.error = Some(format!("probe #{} failed: {}", probe + 1, e));
+                break;
+            }
+        };
+        if probe == 0 {
+            comparison.connect = phases.handshake();
+            comparison.version = Some(response.version());
+            comparison.remote_addr = response.remote_addr();
+        } else {
+            comparison.rtts_us.push(rtt.as_micros() as u64);
+        }
+        crate::verbose!(
+            "[Compare] {} probe #{}/{} status={} rtt={:.1}ms{}",
+            comparison.name,
+            probe + 1,
+            probes,
+            response.status(),
+            latency::ms(rtt),
+            phases.describe()
+        );
+    }
+    comparison
+}
+
+fn format_us(us: Option<u64>) -> String {
+    us.map(|us| format!("{:.1}ms", us as f64 / 1000.0))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn print_table(comparisons: &[Comparison]) {
+    println!(
+        "[Compare] {:<4} {:<14} {:>9} {:>9} {:>8} {:>9} {:<8} {}",
+        "Rank", "Broker", "p50", "p90", "jitter", "connect", "Protocol", "IP"
+    );
+    for (index, comparison) in comparisons.iter().enumerate() {
+        let rank = if comparison.rtts_us.is_empty() {
+            "-".to_string()
+        } else {
+            (index + 1).to_string()
+        };
+        println!(
+            "[Compare] {:<4} {:<14} {:>9} {:>9} {:>8} {:>9} {:<8} {}{}",
+            rank,
+            comparison.name,
+            format_us(comparison.p50_us()),
+            format_us(comparison.p90_us()),
+            if comparison.rtts_us.is_empty() {
+                "-".to_string()
+            } else {
+                format!("{:.1}ms", comparison.jitter_us() / 1000.0)
+            },
+            comparison
+                .connect
+                .map(|connect| format!("{:.1}ms", latency::ms(connect)))
+                .unwrap_or_else(|| "-".to_string()),
+            comparison
+                .version
+                .map(|version| format!("{:?}", version))
+                .unwrap_or_else(|| "-".to_string()),
+            comparison
+                .remote_addr
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            comparison
+                .error
+                .as_ref()
+                .map(|error| format!("  ({})", error))
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Run `compare`: probe `names`, or every configured broker when empty, one
+/// after the other, and print them ranked by latency.
+pub async fn run_compare(names: &[String], probes: usize) -> Result<()> {
+    if probes < 2 {
+        anyhow::bail!("--probes must be >= 2: the first only opens the connection");
+    }
+    let brokers: Vec<AnyBroker> = if names.is_empty() {
+        registry::all_brokers()?
+    } else {
+        names
+            .iter()
+            .map(|name| registry::find_broker(name))
+            .collect::<Result<_>>()?
+    };
+    if brokers.is_empty() {
+        anyhow::bail!("No broker configs found in the current directory");
+    }
+
+    println!(
+        "[Compare] Probing {} broker(s), {} probes each",
+        brokers.len(),
+        probes
+    );
+    let mut comparisons = Vec::with_capacity(brokers.len());
+    for broker in &brokers {
+        // Probe with working credentials, the first account's when the
+        // broker's own are not used.
+        let comparison = with_broker!(broker, b => {
+            let prober = b.accounts().first().unwrap_or(b);
+            probe_broker(prober, probes).await
+        });
+        comparisons.push(comparison);
+    }
+    rank(&mut comparisons);
+    print_table(&comparisons);
+    if comparisons
+        .iter()
+        .all(|comparison| comparison.rtts_us.is_empty())
+    {
+        anyhow::bail!("No broker could be probed");
+    }
+    Ok(())
+}
@@ -0,0 +1,368 @@
+use crate::market_data::{MarketData, Quote};
+use crate::market_feed::{self, MarketFeedConfig};
+use crate::orders::{OrderEntry, is_isin};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+pub fn default_quote_poll_interval_ms() -> u64 {
+    1000
+}
+
+/// Quote price a condition compares.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteField {
+    LastPrice,
+    BestBid,
+    BestAsk,
+}
+
+impl QuoteField {
+    fn parse(field: &str) -> Option<Self> {
+        match field {
+            "last_price" => Some(Self::LastPrice),
+            "best_bid" => Some(Self::BestBid),
+            "best_ask" => Some(Self::BestAsk),
+            _ => None,
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::LastPrice => "last_price",
+            Self::BestBid => "best_bid",
+            Self::BestAsk => "best_ask",
+        }
+    }
+
+    fn read(&self, quote: &Quote) -> Option<f64> {
+        match self {
+            Self::LastPrice => quote.last_price,
+            Self::BestBid => quote.best_bid,
+            Self::BestAsk => quote.best_ask,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    AtMost,
+    Below,
+    AtLeast,
+    Above,
+}
+
+impl Comparison {
+    const ALL: [Self; 4] = [Self::AtMost, Self::AtLeast, Self::Below, Self::Above];
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::AtMost => "<=",
+            Self::Below => "<",
+            Self::AtLeast => ">=",
+            Self::Above => ">",
+        }
+    }
+}
+
+/// A price condition such as `best_ask <= 2480`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct PriceCondition {
+    pub field: QuoteField,
+    pub comparison: Comparison,
+    pub price: f64,
+}
+
+impl PriceCondition {
+    /// Whether `quote` satisfies the condition; never when the price is
+    /// missing from the quote.
+    pub fn is_met(&self, quote: &Quote) -> bool {
+        let Some(price) = self.field.read(quote) else {
+            return false;
+        };
+        match self.comparison {
+            Comparison::AtMost => price <= self.price,
+            Comparison::Below => price < self.price,
+            Comparison::AtLeast => price >= self.price,
+            Comparison::Above => price > self.price,
+        }
+    }
+}
+
+impl TryFrom<String> for PriceCondition {
+    type Error = anyhow::Error;
+
+    fn try_from(condition: String) -> Result<Self> {
+        let invalid = || {
+            format!(
+                "Invalid send_when '{}'; expected e.g. 'best_ask <= 2480' with last_price, best_bid or best_ask and <=, <, >= or >",
+                condition
+            )
+        };
+        let split = condition.find(['<', '>']).with_context(invalid)?;
+        let field = QuoteField::parse(condition[..split].trim()).with_context(invalid)?;
+        let rest = &condition[split..];
+        let comparison = Comparison::ALL
+            .into_iter()
+            .find(|comparison| rest.starts_with(comparison.as_str()))
+            .with_context(invalid)?;
+        let price = rest[comparison.as_str().len()..]
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|price| price.is_finite())
+            .with_context(invalid)?;
+        Ok(Self {
+            field,
+            comparison,
+            price,
+        })
+    }
+}
+
+impl std::fmt::Display for PriceCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.field.as_str(),
+            self.comparison.as_str(),
+            self.price
+        )
+    }
+}
+
+/// An order's `send_when`: either just the condition, checked against the
+/// order's own ISIN, or `{ "condition": ..., "isin": ... }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "SendWhenConfig")]
+pub struct SendWhen {
+    pub condition: PriceCondition,
+    pub isin: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SendWhenConfig {
+    Condition(PriceCondition),
+    Full {
+        condition: PriceCondition,
+        #[serde(default)]
+        isin: Option<String>,
+    },
+}
+
+impl From<SendWhenConfig> for SendWhen {
+    fn from(config: SendWhenConfig) -> Self {
+        match config {
+            SendWhenConfig::Condition(condition) => Self {
+                condition,
+                isin: None,
+            },
+            SendWhenConfig::Full { condition, isin } => Self { condition, isin },
+        }
+    }
+}
+
+/// The ISIN a condition watches: the configured one, or the first field of
+/// the order that looks like an ISIN.
+fn watched_isin<T: Serialize>(send_when: &SendWhen, order: &OrderEntry<T>) -> Result<String> {
+    if let Some(isin) = &send_when.isin {
+        return Ok(isin.trim().to_string());
+    }
+    let value = serde_json::to_value(&order.data)?;
+    value
+        .as_object()
+        .into_iter()
+        .flat_map(|fields| fields.values())
+        .filter_map(Value::as_str)
+        .map(str::trim)
+        .find(|field| is_isin(field))
+        .map(str::to_string)
+        .context("send_when needs an 'isin' for an order without an ISIN field")
+}
+
+/// Orders watching one instrument: (order index, condition).
+type Watches = Vec<(usize, PriceCondition)>;
+
+/// Polls quotes for the orders that carry `send_when`, or follows a market
+/// feed, and arms each order once its condition holds. Armed orders stay
+/// armed for the rest of the run. Watching stops when the watcher is
+/// dropped.
+pub struct ConditionWatcher {
+    armed: Arc<Vec<AtomicBool>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl ConditionWatcher {
+    /// Start watching the conditions of `orders`; `None` when no order has
+    /// one. Quotes are checked once before returning, so orders whose
+    /// condition already holds are armed from the first send. With `feed`,
+    /// later quotes come from its pushed updates instead of polls.
+    pub async fn start<T: Serialize>(
+        name: &str,
+        orders: &[OrderEntry<T>],
+        base_url: &str,
+        poll_interval_ms: u64,
+        feed: Option<&MarketFeedConfig>,
+    ) -> Result<Option<Self>> {
+        let mut by_isin: BTreeMap<String, Watches> = BTreeMap::new();
+        for (index, order) in orders.iter().enumerate() {
+            if let Some(send_when) = &order.send_when {
+                let isin = watched_isin(send_when, order)
+                    .with_context(|| format!("Order #{} of {}", index + 1, name))?;
+                by_isin
+                    .entry(isin)
+                    .or_default()
+                    .push((index, send_when.condition.clone()));
+            }
+        }
+        if by_isin.is_empty() {
+            return Ok(None);
+        }
+
+        // Subscribed before the first check so no update in between is lost.
+        let subscription = match feed {
+            Some(feed) => {
+                let isins: Vec<String> = by_isin.keys().cloned().collect();
+                Some(market_feed::subscribe(name, feed, &isins).await?)
+            }
+            None => None,
+        };
+        let market_data = MarketData::new(base_url)?;
+        let armed: Arc<Vec<AtomicBool>> =
+            Arc::new(orders.iter().map(|_| AtomicBool::new(false)).collect());
+        let interval = Duration::from_millis(poll_interval_ms.max(1));
+        let mut tasks = Vec::with_capacity(by_isin.len());
+        let mut pushed: HashMap<String, (Quote, Watches)> = HashMap::new();
+        for (isin, watches) in by_isin {
+            let instrument = market_data.find_instrument(&isin).await?;
+            for (index, condition) in &watches {
+                println!(
+                    "[{}] Order #{} held until {} on {}",
+                    name,
+                    index + 1,
+                    condition,
+                    instrument.symbol
+                );
+            }
+            let quote = fetch_quote(name, &market_data, &instrument.ins_code).await;
+            if quote
+                .as_ref()
+                .is_some_and(|quote| arm_met(name, &watches, quote, &armed))
+            {
+                continue;
+            }
+            if subscription.is_some() {
+                pushed.insert(isin, (quote.unwrap_or_default(), watches));
+                continue;
+            }
+
+            let name = name.to_string();
+            let market_data = market_data.clone();
+            let armed = armed.clone();
+            tasks.push(tokio::spawn(async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    if poll_once(&name, &market_data, &instrument.ins_code, &watches, &armed).await
+                    {
+                        break;
+                    }
+                }
+            }));
+        }
+        if let Some(mut subscription) = subscription.filter(|_| !pushed.is_empty()) {
+            let name = name.to_string();
+            let armed = armed.clone();
+            tasks.push(tokio::spawn(async move {
+                while let Some(update) = subscription.updates.recv().await {
+                    let Some((quote, watches)) = pushed.get_mut(&update.isin) else {
+                        continue;
+                    };
+                    update.apply(quote);
+                    if arm_met(&name, watches, quote, &armed) {
+                        pushed.remove(&update.isin);
+                        if pushed.is_empty() {
+                            break;
+                        }
+                    }
+                }
+            }));
+        }
+        Ok(Some(Self { armed, tasks }))
+    }
+
+    pub fn is_armed(&self, index: usize) -> bool {
+        self.armed
+            .get(index)
+            .is_none_or(|armed| armed.load(Ordering::Relaxed))
+    }
+}
+
+impl Drop for ConditionWatcher {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Fetch one quote and arm the orders whose condition it meets. Returns
+/// whether every order in `watches` is armed.
+async fn poll_once(
+    name: &str,
+    market_data: &MarketData,
+    ins_code: &str,
+    watches: &[(usize, PriceCondition)],
+    armed: &[AtomicBool],
+) -> bool {
+    match fetch_quote(name, market_data, ins_code).await {
+        Some(quote) => arm_met(name, watches, &quote, armed),
+        None => false,
+    }
+}
+
+async fn fetch_quote(name: &str, market_data: &MarketData, ins_code: &str) -> Option<Quote> {
+    match market_data.quote(ins_code).await {
+        Ok(quote) => Some(quote),
+        Err(e) => {
+            eprintln!("[{}] Warning: quote poll failed: {:#}", name, e);
+            None
+        }
+    }
+}
+
+/// Arm the orders whose condition `quote` meets. Returns whether every
+/// order in `watches` is armed.
+fn arm_met(
+    name: &str,
+    watches: &[(usize, PriceCondition)],
+    quote: &Quote,
+    armed: &[AtomicBool],
+) -> bool {
+    let mut all_armed = true;
+    for (index, condition) in watches {
+        if armed[*index].load(Ordering::Relaxed) {
+            continue;
+        }
+        if condition.is_met(quote) {
+            armed[*index].store(true, Ordering::Relaxed);
+            println!(
+                "[{}] Order #{} armed: {} (now {})",
+                name,
+                index + 1,
+                condition,
+                condition.field.read(quote).unwrap_or_default()
+            );
+        } else {
+            all_armed = false;
+        }
+    }
+    all_armed
+}
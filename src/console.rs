@@ -0,0 +1,372 @@
+//! Console output. Every line the crate prints goes through `println!` and
+//! `eprintln!` and their siblings defined here, which drop it below the
+//! verbosity level, copy it to the log file and color it when writing to a
+//! terminal: the `[name]` prefix in a color of its own per broker, successes
+//! in green and failures in red. Lines logged during an order attempt end
+//! with its correlation ID.
+
+use crate::attempt;
+use crate::latency::{self, Phases};
+use anyhow::{Context, Result};
+use chrono_tz::Asia::Tehran;
+use reqwest::header::HeaderMap;
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::{LazyLock, Mutex, OnceLock};
+use std::time::Instant;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const BOLD_RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[33m";
+
+/// Prefix colors, leaving out green and red so a broker never looks like a
+/// success or a failure.
+const PREFIX_COLORS: [&str; 8] = [
+    "\x1b[36m", "\x1b[35m", "\x1b[34m", "\x1b[33m", "\x1b[96m", "\x1b[95m", "\x1b[94m", "\x1b[93m",
+];
+
+/// Text that marks a line as an authentication failure.
+const AUTH_FAILURES: [&str; 5] = [
+    "AuthExpired",
+    "Unauthorized",
+    "Forbidden",
+    "EXPIRED",
+    "session expired",
+];
+
+/// How much is printed: `-q`, the default, `-v` and `-vv`. Each level
+/// prints the lines of the levels before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    /// Errors, warnings, successes and batch summaries.
+    Quiet,
+    /// Plus every request and response body.
+    Normal,
+    /// Plus how long each order took.
+    Verbose,
+    /// Plus request and response headers and the timing breakdown.
+    Debug,
+}
+
+/// Where `-q` writes the lines it hides, unless `--log-file` is given.
+pub const QUIET_LOG_FILE: &str = "sarkhati.log";
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Normal as u8);
+
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+static COLOR: LazyLock<AtomicBool> = LazyLock::new(|| {
+    AtomicBool::new(std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none())
+});
+
+/// Whether output is colored: by default when stdout is a terminal and
+/// `NO_COLOR` is unset.
+pub fn color_enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Turn colors off, for `--no-color` and for output that is captured.
+pub fn disable_color() {
+    COLOR.store(false, Ordering::Relaxed);
+}
+
+/// Prepare the Windows console: UTF-8 output so Persian text is not
+/// garbled, and ANSI escapes for the colors, which are turned off when the
+/// console cannot show them. Elsewhere this does nothing.
+#[cfg(windows)]
+pub fn init_terminal() {
+    use windows_sys::Win32::Globalization::CP_UTF8;
+    use windows_sys::Win32::System::Console::{
+        ENABLE_VIRTUAL_TERMINAL_PROCESSING, GetConsoleMode, GetStdHandle, STD_ERROR_HANDLE,
+        STD_OUTPUT_HANDLE, SetConsoleMode, SetConsoleOutputCP,
+    };
+
+    // SAFETY: console calls on this process's standard handles; failures
+    // are reported through the return values.
+    unsafe {
+        SetConsoleOutputCP(CP_UTF8);
+        for handle in [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE] {
+            let handle = GetStdHandle(handle);
+            let mut mode = 0;
+            if GetConsoleMode(handle, &mut mode) != 0
+                && SetConsoleMode(handle, mode | ENABLE_VIRTUAL_TERMINAL_PROCESSING) == 0
+            {
+                disable_color();
+            }
+        }
+    }
+}
+
+#[cfg(not(windows))]
+pub fn init_terminal() {}
+
+pub fn level() -> Level {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Quiet,
+        1 => Level::Normal,
+        2 => Level::Verbose,
+        _ => Level::Debug,
+    }
+}
+
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Append every line to `path` from now on, whatever the level, without
+/// colors and with a timestamp.
+pub fn open_log_file(path: &str) -> Result<()> {
+    let file = File::options()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {}", path))?;
+    if LOG_FILE.set(Mutex::new(file)).is_err() {
+        anyhow::bail!("The log file is already open");
+    }
+    Ok(())
+}
+
+#[doc(hidden)]
+#[derive(Clone, Copy)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[doc(hidden)]
+pub fn emit(stream: Stream, level: Level, args: fmt::Arguments) {
+    let shown = level <= self::level();
+    if !shown && LOG_FILE.get().is_none() {
+        return;
+    }
+    let mut line = args.to_string();
+    if let Some(id) = attempt::current_id() {
+        line.push_str(&format!(" (attempt {})", id));
+    }
+    if let Some(file) = LOG_FILE.get() {
+        let now = chrono::Utc::now().with_timezone(&Tehran);
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = writeln!(file, "{} {}", now.format("%Y-%m-%d %H:%M:%S%.6f"), line);
+    }
+    if !shown {
+        return;
+    }
+    let line = if color_enabled() { paint(&line) } else { line };
+    match stream {
+        Stream::Stdout => std::println!("{}", line),
+        Stream::Stderr => std::eprintln!("{}", line),
+    }
+}
+
+/// `text` with ANSI colors: a bold `[name]` prefix in the broker's color and
+/// the rest green for a success, red for a failure (bold for a rejected
+/// session) and yellow for a warning. Multi-line text is painted per line.
+pub fn paint(text: &str) -> String {
+    text.split('\n')
+        .map(paint_line)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn paint_line(line: &str) -> String {
+    let (prefix, rest) = match line
+        .strip_prefix('[')
+        .and_then(|line| line.split_once("] "))
+    {
+        Some((name, rest)) => (
+            format!("{}{}[{}]{} ", BOLD, prefix_color(name), name, RESET),
+            rest,
+        ),
+        None => (String::new(), line),
+    };
+    let body = match body_color(rest) {
+        Some(color) if !rest.is_empty() => format!("{}{}{}", color, rest, RESET),
+        _ => rest.to_string(),
+    };
+    format!("{}{}", prefix, body)
+}
+
+/// The color of a `[name]` prefix. Accounts (`name/account`) share their
+/// broker's color.
+pub fn prefix_color(name: &str) -> &'static str {
+    let broker = name.split('/').next().unwrap_or(name).to_lowercase();
+    // FNV-1a, so the color stays the same from run to run.
+    let hash = broker.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    PREFIX_COLORS[(hash % PREFIX_COLORS.len() as u64) as usize]
+}
+
+fn body_color(text: &str) -> Option<&'static str> {
+    if AUTH_FAILURES.iter().any(|marker| text.contains(marker)) {
+        Some(BOLD_RED)
+    } else if text.contains('✓')
+        || text.contains("Sent successfully")
+        || text.starts_with("Order response status: 2")
+    {
+        Some(GREEN)
+    } else if text.contains('✗') || text.contains("Error") || text.contains("Failed") {
+        Some(RED)
+    } else if text.starts_with("Warning") {
+        Some(YELLOW)
+    } else {
+        None
+    }
+}
+
+/// Print `headers` one per line at `-vv`.
+pub fn debug_headers(name: &str, label: &str, headers: &HeaderMap) {
+    if level() < Level::Debug && LOG_FILE.get().is_none() {
+        return;
+    }
+    for (header, value) in headers {
+        crate::debug!(
+            "[{}] {} header {}: {}",
+            name,
+            label,
+            header,
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+}
+
+/// Time spent in each phase of one request: the round trip, time to first
+/// byte and connection setup at `-v`, the full breakdown at `-vv`.
+pub struct Timing {
+    start: Instant,
+    last: Instant,
+    /// Inline, so timing a send allocates nothing; phases past the last
+    /// slot are dropped.
+    phases: [(&'static str, f64); MAX_PHASES],
+    phase_count: usize,
+    /// Time to first byte and connection setup of the request, once sent.
+    response: Option<(f64, Phases)>,
+}
+
+/// Rate limit, DNS, connect, time to first byte and body, with room to spare.
+const MAX_PHASES: usize = 8;
+
+impl Timing {
+    pub fn start() -> Self {
+        let now = Instant::now();
+        Self {
+            start: now,
+            last: now,
+            phases: [("", 0.0); MAX_PHASES],
+            phase_count: 0,
+            response: None,
+        }
+    }
+
+    fn push(&mut self, phase: &'static str, ms: f64) {
+        if let Some(slot) = self.phases.get_mut(self.phase_count) {
+            *slot = (phase, ms);
+            self.phase_count += 1;
+        }
+    }
+
+    /// Await `send`, splitting the time until the response headers arrive
+    /// into resolving, connecting and waiting for the first byte.
+    pub async fn response<F: Future>(&mut self, send: F) -> F::Output {
+        let (output, phases) = latency::timed(send).await;
+        let now = Instant::now();
+        if let Some(dns) = phases.dns {
+            self.push("dns", latency::ms(dns));
+        }
+        if let Some(handshake) = phases.handshake() {
+            self.push("connect", latency::ms(handshake));
+        }
+        let ttfb =
+            latency::ms((now - self.last).saturating_sub(phases.connect.unwrap_or_default()));
+        self.push("ttfb", ttfb);
+        self.response = Some((ttfb, phases));
+        self.last = now;
+        output
+    }
+
+    /// End the phase `phase`, which started at the previous mark.
+    pub fn mark(&mut self, phase: &'static str) {
+        let now = Instant::now();
+        self.push(phase, (now - self.last).as_secs_f64() * 1000.0);
+        self.last = now;
+    }
+
+    pub fn report(&self, name: &str) {
+        let total = (self.last - self.start).as_secs_f64() * 1000.0;
+        let phases: Vec<String> = self.phases[..self.phase_count]
+            .iter()
+            .map(|(phase, ms)| format!("{} {:.1}ms", phase, ms))
+            .collect();
+        if level() >= Level::Debug {
+            crate::debug!(
+                "[{}] Timing: {}, total {:.1}ms",
+                name,
+                phases.join(", "),
+                total
+            );
+        } else if let Some((ttfb, phases)) = &self.response {
+            crate::verbose!(
+                "[{}] Order took {:.1}ms: ttfb={:.1}ms{}",
+                name,
+                total,
+                ttfb,
+                phases.describe()
+            );
+        } else {
+            crate::verbose!("[{}] Order took {:.1}ms", name, total);
+        }
+    }
+}
+
+/// `println!` that colors the line on a terminal; hidden by `-q`.
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::console::emit($crate::console::Stream::Stdout, $crate::console::Level::Normal, format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::console::emit($crate::console::Stream::Stdout, $crate::console::Level::Normal, format_args!($($arg)*))
+    };
+}
+
+/// `eprintln!` that colors the line on a terminal; always printed.
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::console::emit($crate::console::Stream::Stderr, $crate::console::Level::Quiet, format_args!(""))
+    };
+    ($($arg:tt)*) => {
+        $crate::console::emit($crate::console::Stream::Stderr, $crate::console::Level::Quiet, format_args!($($arg)*))
+    };
+}
+
+/// A line printed even with `-q`: a success or a summary.
+#[macro_export]
+macro_rules! summary {
+    ($($arg:tt)*) => {
+        $crate::console::emit($crate::console::Stream::Stdout, $crate::console::Level::Quiet, format_args!($($arg)*))
+    };
+}
+
+/// A line printed with `-v` or `-vv`.
+#[macro_export]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        $crate::console::emit($crate::console::Stream::Stdout, $crate::console::Level::Verbose, format_args!($($arg)*))
+    };
+}
+
+/// A line printed with `-vv`.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::console::emit($crate::console::Stream::Stdout, $crate::console::Level::Debug, format_args!($($arg)*))
+    };
+}
@@ -0,0 +1,327 @@
+//! `--control-port`: a local HTTP API to watch and steer a running session
+//! without restarting it. `--grpc-port` serves the same operations over
+//! gRPC, see [`crate::grpc`].
+
+use anyhow::{Context, Result};
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{Notify, broadcast, watch};
+
+/// Order events kept for a watcher that falls behind.
+const EVENT_BUFFER: usize = 1024;
+
+/// Every broker running in this process, as the control API sees them.
+pub struct Control {
+    brokers: Mutex<Vec<Arc<BrokerControl>>>,
+    events: broadcast::Sender<OrderEvent>,
+}
+
+/// One send that was accepted or failed.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct OrderEvent {
+    pub broker: String,
+    /// 1-based position of the order.
+    pub order: usize,
+    pub accepted: bool,
+    pub error: Option<String>,
+    pub epoch_ms: i64,
+}
+
+impl Control {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            brokers: Mutex::new(Vec::new()),
+            events: broadcast::Sender::new(EVENT_BUFFER),
+        })
+    }
+
+    /// Order events from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<OrderEvent> {
+        self.events.subscribe()
+    }
+
+    /// Add a broker with `orders` orders, or return the one of that name.
+    pub fn register(&self, name: &str, orders: usize) -> Arc<BrokerControl> {
+        let mut brokers = self.brokers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(broker) = brokers.iter().find(|broker| broker.name == name) {
+            return broker.clone();
+        }
+        let broker = Arc::new(BrokerControl::new(name, orders, self.events.clone()));
+        brokers.push(broker.clone());
+        broker
+    }
+
+    pub fn broker(&self, name: &str) -> Option<Arc<BrokerControl>> {
+        self.brokers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .find(|broker| broker.name.eq_ignore_ascii_case(name))
+            .cloned()
+    }
+
+    pub fn status(&self) -> Vec<BrokerStatus> {
+        self.brokers
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .map(|broker| broker.status())
+            .collect()
+    }
+}
+
+/// Switches and counters of one running broker.
+pub struct BrokerControl {
+    name: String,
+    orders: usize,
+    paused: watch::Sender<bool>,
+    /// Zero-based indexes of the orders not to send.
+    disabled: Mutex<BTreeSet<usize>>,
+    send_now: Notify,
+    batches: AtomicU64,
+    accepted: AtomicU64,
+    failed: AtomicU64,
+    last_error: Mutex<Option<String>>,
+    events: broadcast::Sender<OrderEvent>,
+}
+
+/// What `GET /status` reports per broker.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct BrokerStatus {
+    pub name: String,
+    pub paused: bool,
+    pub orders: usize,
+    /// 1-based positions, as in `--orders`.
+    pub disabled_orders: Vec<usize>,
+    pub batches: u64,
+    pub accepted: u64,
+    pub failed: u64,
+    pub last_error: Option<String>,
+}
+
+impl BrokerControl {
+    fn new(name: &str, orders: usize, events: broadcast::Sender<OrderEvent>) -> Self {
+        Self {
+            name: name.to_string(),
+            orders,
+            paused: watch::Sender::new(false),
+            disabled: Mutex::new(BTreeSet::new()),
+            send_now: Notify::new(),
+            batches: AtomicU64::new(0),
+            accepted: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            last_error: Mutex::new(None),
+            events,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.send_replace(paused);
+        let state = if paused { "Paused" } else { "Resumed" };
+        println!("[{}] {} from the control API", self.name, state);
+    }
+
+    /// Return once the broker is not paused.
+    pub async fn wait_while_paused(&self) {
+        let mut paused = self.paused.subscribe();
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+
+    pub fn is_disabled(&self, index: usize) -> bool {
+        self.disabled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains(&index)
+    }
+
+    /// Disable or re-enable the order at 1-based `position`.
+    pub fn set_disabled(&self, position: usize, disabled: bool) -> Result<()> {
+        if position == 0 || position > self.orders {
+            anyhow::bail!(
+                "{} has orders 1 to {}, not {}",
+                self.name,
+                self.orders,
+                position
+            );
+        }
+        let mut set = self.disabled.lock().unwrap_or_else(|e| e.into_inner());
+        if disabled {
+            set.insert(position - 1);
+        } else {
+            set.remove(&(position - 1));
+        }
+        let state = if disabled { "disabled" } else { "enabled" };
+        println!(
+            "[{}] Order #{} {} from the control API",
+            self.name, position, state
+        );
+        Ok(())
+    }
+
+    /// Ask for the next batch, or the next scheduled order, to go out now.
+    pub fn request_send_now(&self) {
+        self.send_now.notify_one();
+        println!(
+            "[{}] Immediate send requested from the control API",
+            self.name
+        );
+    }
+
+    /// Return when an immediate send is requested, at once if one was
+    /// requested while nothing waited.
+    pub async fn send_now_requested(&self) {
+        self.send_now.notified().await;
+    }
+
+    pub fn record_batch(&self) {
+        self.batches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a send of the order at `index` and tell the watchers.
+    pub fn record_send<T>(&self, index: usize, result: &Result<T>) {
+        let error = result.as_ref().err().map(|e| format!("{:#}", e));
+        match &error {
+            None => {
+                self.accepted.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(error) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                *self.last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(error.clone());
+            }
+        }
+        // No watcher is not an error.
+        let _ = self.events.send(OrderEvent {
+            broker: self.name.clone(),
+            order: index + 1,
+            accepted: error.is_none(),
+            error,
+            epoch_ms: chrono::Utc::now().timestamp_millis(),
+        });
+    }
+
+    pub fn status(&self) -> BrokerStatus {
+        BrokerStatus {
+            name: self.name.clone(),
+            paused: self.is_paused(),
+            orders: self.orders,
+            disabled_orders: self
+                .disabled
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .iter()
+                .map(|index| index + 1)
+                .collect(),
+            batches: self.batches.load(Ordering::Relaxed),
+            accepted: self.accepted.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            last_error: self
+                .last_error
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
+    }
+}
+
+/// Serve the control API on `127.0.0.1:port` in the background, returning
+/// the address it listens on.
+pub async fn start(control: Arc<Control>, port: u16) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .with_context(|| format!("Failed to listen on control port {}", port))?;
+    let address = listener.local_addr()?;
+    let app = Router::new()
+        .route("/status", get(status))
+        .route("/brokers/{name}/pause", post(pause))
+        .route("/brokers/{name}/resume", post(resume))
+        .route("/brokers/{name}/send", post(send_now))
+        .route("/brokers/{name}/orders/{position}/disable", post(disable))
+        .route("/brokers/{name}/orders/{position}/enable", post(enable))
+        .with_state(control);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            eprintln!("[Control] Error: {}", e);
+        }
+    });
+    println!("[Control] Listening on http://{}", address);
+    Ok(address)
+}
+
+fn error(status: StatusCode, message: String) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+/// Apply `action` to the broker called `name` and answer with its status.
+fn with_broker(
+    control: &Control,
+    name: &str,
+    action: impl FnOnce(&BrokerControl) -> Result<()>,
+) -> Response {
+    let Some(broker) = control.broker(name) else {
+        return error(StatusCode::NOT_FOUND, format!("No broker named {}", name));
+    };
+    match action(&broker) {
+        Ok(()) => Json(broker.status()).into_response(),
+        Err(e) => error(StatusCode::BAD_REQUEST, e.to_string()),
+    }
+}
+
+async fn status(State(control): State<Arc<Control>>) -> Json<Vec<BrokerStatus>> {
+    Json(control.status())
+}
+
+async fn pause(State(control): State<Arc<Control>>, Path(name): Path<String>) -> Response {
+    with_broker(&control, &name, |broker| {
+        broker.set_paused(true);
+        Ok(())
+    })
+}
+
+async fn resume(State(control): State<Arc<Control>>, Path(name): Path<String>) -> Response {
+    with_broker(&control, &name, |broker| {
+        broker.set_paused(false);
+        Ok(())
+    })
+}
+
+async fn send_now(State(control): State<Arc<Control>>, Path(name): Path<String>) -> Response {
+    with_broker(&control, &name, |broker| {
+        broker.request_send_now();
+        Ok(())
+    })
+}
+
+async fn disable(
+    State(control): State<Arc<Control>>,
+    Path((name, position)): Path<(String, usize)>,
+) -> Response {
+    with_broker(&control, &name, |broker| {
+        broker.set_disabled(position, true)
+    })
+}
+
+async fn enable(
+    State(control): State<Arc<Control>>,
+    Path((name, position)): Path<(String, usize)>,
+) -> Response {
+    with_broker(&control, &name, |broker| {
+        broker.set_disabled(position, false)
+    })
+}
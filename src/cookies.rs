@@ -0,0 +1,228 @@
+use crate::login;
+use crate::registry;
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// One row of a browser cookie store.
+#[derive(Debug, Clone)]
+pub struct StoredCookie {
+    pub host: String,
+    pub path: String,
+    pub name: String,
+    pub value: String,
+}
+
+/// Run `cookies import`: read the cookies for `domain` from the browser and
+/// store them as the `cookie` field of the matching broker.
+pub fn run_import(
+    browser: &str,
+    domain: &str,
+    broker_name: Option<&str>,
+    profile: Option<&str>,
+) -> Result<()> {
+    if !browser.eq_ignore_ascii_case("firefox") {
+        anyhow::bail!(
+            "Unsupported browser '{}'; only firefox is supported (Chrome-based browsers encrypt their cookie store)",
+            browser
+        );
+    }
+
+    let profile = match profile {
+        Some(profile) => PathBuf::from(profile),
+        None => firefox_default_profile()?,
+    };
+    let database = profile.join("cookies.sqlite");
+    println!("[Cookies] Reading {}", database.display());
+    let cookies = read_firefox_cookies(&database, domain)?;
+    if cookies.is_empty() {
+        anyhow::bail!(
+            "No cookies for {} in {}; log in with Firefox first",
+            domain,
+            database.display()
+        );
+    }
+    let header = cookie_header(&cookies);
+
+    let broker = match broker_name {
+        Some(name) => registry::find_broker(name)?,
+        None => registry::find_broker_by_domain(domain)?,
+    };
+    if !broker.has_cookie_field() {
+        anyhow::bail!("{} does not use cookie authentication", broker.name());
+    }
+    let (config_path, entry) = broker.config_location();
+    login::update_config_fields(config_path, entry, &[("cookie", Value::String(header))])?;
+    println!(
+        "[Cookies] Wrote {} cookie(s) for {} to {} ({})",
+        cookies.len(),
+        domain,
+        config_path,
+        broker.name()
+    );
+    Ok(())
+}
+
+/// Cookies stored for `domain` or any of its subdomains, most specific path
+/// first as browsers send them.
+pub fn read_firefox_cookies(database: &Path, domain: &str) -> Result<Vec<StoredCookie>> {
+    // Firefox keeps the database locked while running, so read a copy.
+    let copy_dir = std::env::temp_dir().join(format!("sarkhati-cookies-{}", std::process::id()));
+    std::fs::create_dir_all(&copy_dir)?;
+    let copy = copy_dir.join("cookies.sqlite");
+    std::fs::copy(database, &copy)
+        .with_context(|| format!("Failed to read {}", database.display()))?;
+    let wal = database.with_extension("sqlite-wal");
+    if wal.exists() {
+        std::fs::copy(&wal, copy.with_extension("sqlite-wal"))?;
+    }
+
+    let result = query_cookies(&copy, domain);
+    let _ = std::fs::remove_dir_all(&copy_dir);
+    result
+}
+
+fn query_cookies(database: &Path, domain: &str) -> Result<Vec<StoredCookie>> {
+    let connection = Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .with_context(|| format!("Failed to open {}", database.display()))?;
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    let mut statement = connection.prepare(
+        "SELECT host, path, name, value, expiry FROM moz_cookies
+         WHERE host = ?1 OR host = ?2 OR host LIKE ?3",
+    )?;
+    let rows = statement.query_map(
+        [
+            domain.clone(),
+            format!(".{}", domain),
+            format!("%.{}", domain),
+        ],
+        |row| {
+            Ok((
+                StoredCookie {
+                    host: row.get(0)?,
+                    path: row.get(1)?,
+                    name: row.get(2)?,
+                    value: row.get(3)?,
+                },
+                row.get::<_, i64>(4)?,
+            ))
+        },
+    )?;
+
+    let now_secs = chrono::Utc::now().timestamp();
+    let mut cookies = Vec::new();
+    for row in rows {
+        let (cookie, expiry) = row?;
+        // Newer Firefox versions store milliseconds.
+        let expiry_secs = if expiry > 100_000_000_000 {
+            expiry / 1000
+        } else {
+            expiry
+        };
+        if expiry_secs > now_secs {
+            cookies.push(cookie);
+        }
+    }
+    cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+    Ok(cookies)
+}
+
+/// Join cookies into a `Cookie` header value, keeping the first of any
+/// duplicate names.
+pub fn cookie_header(cookies: &[StoredCookie]) -> String {
+    let mut seen = Vec::new();
+    let mut pairs = Vec::new();
+    for cookie in cookies {
+        if seen.contains(&&cookie.name) {
+            continue;
+        }
+        seen.push(&cookie.name);
+        pairs.push(format!("{}={}", cookie.name, cookie.value));
+    }
+    pairs.join("; ")
+}
+
+fn firefox_root() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let candidates: Vec<PathBuf> = if cfg!(windows) {
+        std::env::var_os("APPDATA")
+            .map(|appdata| PathBuf::from(appdata).join("Mozilla").join("Firefox"))
+            .into_iter()
+            .collect()
+    } else if cfg!(target_os = "macos") {
+        home.map(|home| home.join("Library/Application Support/Firefox"))
+            .into_iter()
+            .collect()
+    } else {
+        home.map(|home| {
+            vec![
+                home.join(".mozilla/firefox"),
+                home.join("snap/firefox/common/.mozilla/firefox"),
+                home.join(".var/app/org.mozilla.firefox/.mozilla/firefox"),
+            ]
+        })
+        .unwrap_or_default()
+    };
+    candidates
+        .into_iter()
+        .find(|root| root.join("profiles.ini").exists())
+        .context("Firefox profiles.ini not found; pass --profile <DIR>")
+}
+
+fn firefox_default_profile() -> Result<PathBuf> {
+    let root = firefox_root()?;
+    let ini = std::fs::read_to_string(root.join("profiles.ini"))
+        .context("Failed to read Firefox profiles.ini")?;
+    default_profile(&ini, &root).context("No default profile in profiles.ini; pass --profile <DIR>")
+}
+
+/// The profile directory Firefox opens by default, from `profiles.ini`.
+pub fn default_profile(ini: &str, root: &Path) -> Option<PathBuf> {
+    let mut install_default = None;
+    let mut marked_default = None;
+    let mut first = None;
+
+    let mut section = String::new();
+    let mut path = None;
+    let mut is_relative = true;
+    let mut is_default = false;
+    let mut finish = |section: &str, path: Option<String>, is_relative: bool, is_default: bool| {
+        let Some(path) = path else {
+            return;
+        };
+        let path = if is_relative {
+            root.join(path)
+        } else {
+            PathBuf::from(path)
+        };
+        if section.starts_with("Install") {
+            install_default.get_or_insert(path);
+        } else if section.starts_with("Profile") {
+            if is_default {
+                marked_default.get_or_insert(path.clone());
+            }
+            first.get_or_insert(path);
+        }
+    };
+
+    for line in ini.lines().map(str::trim) {
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            finish(&section, path.take(), is_relative, is_default);
+            section = name.to_string();
+            is_relative = true;
+            is_default = false;
+        } else if let Some((key, value)) = line.split_once('=') {
+            match key {
+                "Path" if section.starts_with("Profile") => path = Some(value.to_string()),
+                "Default" if section.starts_with("Install") => path = Some(value.to_string()),
+                "Default" => is_default = value == "1",
+                "IsRelative" => is_relative = value == "1",
+                _ => {}
+            }
+        }
+    }
+    finish(&section, path.take(), is_relative, is_default);
+
+    install_default.or(marked_default).or(first)
+}
@@ -0,0 +1,169 @@
+use crate::orders::{OrderFields, OrderSide};
+use crate::persian;
+use anyhow::{Context, Result};
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+fn default_every_batches() -> u64 {
+    1
+}
+
+/// `credit_check` setting: the broker's purchasing-power endpoint, asked
+/// between batches so buys stop once the account cannot pay for them.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CreditCheck {
+    /// Endpoint reporting the account's remaining purchasing power.
+    pub url: String,
+    /// JSON pointer to the remaining amount in the response, in rials.
+    pub remaining: String,
+    /// Posted as JSON; the endpoint is fetched with GET when unset.
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// Ask again after this many batches.
+    #[serde(default = "default_every_batches")]
+    pub every_batches: u64,
+}
+
+/// What a buy of `order` costs at `price`, or its own price when `None`;
+/// `None` for sells and orders without a price or quantity.
+pub fn cost<T: OrderFields>(order: &T, price: Option<f64>) -> Option<f64> {
+    if order.side() != OrderSide::Buy {
+        return None;
+    }
+    let price = price.or_else(|| order.price())?;
+    Some(price * order.quantity()? as f64)
+}
+
+/// The amount at `pointer` in `body`, as a number or a number in text such
+/// as `"۱۲٬۵۰۰٬۰۰۰"`.
+pub fn parse_remaining(body: &Value, pointer: &str) -> Option<f64> {
+    match body.pointer(pointer)? {
+        Value::String(text) => persian::plain_number(text)?.parse().ok(),
+        value => value.as_f64(),
+    }
+}
+
+/// The remaining credit of the broker, or of each of its accounts, as last
+/// reported by [`CreditCheck::url`].
+pub struct RemainingCredit {
+    check: CreditCheck,
+    client: reqwest::Client,
+    /// Name and session headers of each sender: the broker itself, or its
+    /// accounts in order.
+    senders: Vec<(String, HeaderMap)>,
+    remaining: Mutex<Vec<Option<f64>>>,
+    /// Sender and order index of the buys already reported as unaffordable
+    /// since the last check.
+    refused: Mutex<HashSet<(usize, usize)>>,
+    batches: AtomicU64,
+}
+
+impl RemainingCredit {
+    pub fn new(check: &CreditCheck, senders: Vec<(String, HeaderMap)>) -> Result<Self> {
+        if check.every_batches == 0 {
+            anyhow::bail!("credit_check.every_batches must be at least 1");
+        }
+        Ok(Self {
+            check: check.clone(),
+            client: reqwest::Client::new(),
+            remaining: Mutex::new(vec![None; senders.len()]),
+            senders,
+            refused: Mutex::new(HashSet::new()),
+            batches: AtomicU64::new(0),
+        })
+    }
+
+    /// Ask for every sender's remaining credit, logging each amount.
+    pub async fn refresh(&self) -> Result<()> {
+        let mut remaining = Vec::with_capacity(self.senders.len());
+        for (name, headers) in &self.senders {
+            let amount = self
+                .fetch(headers)
+                .await
+                .with_context(|| format!("Failed to read the remaining credit of {}", name))?;
+            println!("[{}] Remaining credit: {:.0} rials", name, amount);
+            remaining.push(Some(amount));
+        }
+        *self.remaining.lock().unwrap_or_else(|e| e.into_inner()) = remaining;
+        self.refused
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clear();
+        Ok(())
+    }
+
+    /// Count a batch about to start and refresh before every
+    /// `every_batches`-th after the first, which the check before the run
+    /// covers; the last amounts are kept with a warning if that fails.
+    pub async fn refresh_between_batches(&self, name: &str) {
+        let batches = self.batches.fetch_add(1, Ordering::Relaxed);
+        if batches == 0 || !batches.is_multiple_of(self.check.every_batches) {
+            return;
+        }
+        if let Err(e) = self.refresh().await {
+            eprintln!("[{}] Warning: {:#}", name, e);
+        }
+    }
+
+    /// Whether `sender` can still pay `cost` for order `index`. The first
+    /// refusal of each order after a check is logged.
+    pub fn covers(&self, sender: usize, index: usize, cost: f64) -> bool {
+        let Some(remaining) = self
+            .remaining
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(sender)
+            .copied()
+            .flatten()
+        else {
+            return true;
+        };
+        if cost <= remaining {
+            return true;
+        }
+        let first = self
+            .refused
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert((sender, index));
+        if first {
+            summary!(
+                "[{}] Order #{} needs {:.0} rials but {:.0} remain; holding it until credit frees up",
+                self.senders[sender].0,
+                index + 1,
+                cost,
+                remaining
+            );
+        }
+        false
+    }
+
+    async fn fetch(&self, headers: &HeaderMap) -> Result<f64> {
+        let request = match &self.check.body {
+            Some(body) => self.client.post(&self.check.url).json(body),
+            None => self.client.get(&self.check.url),
+        };
+        let response = request.headers(headers.clone()).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!(
+                "status {}: {}",
+                status,
+                crate::decode_unicode_escapes(&body)
+            );
+        }
+        let value: Value = serde_json::from_str(&body)
+            .with_context(|| format!("Invalid credit response: {}", body))?;
+        parse_remaining(&value, &self.check.remaining).with_context(|| {
+            format!(
+                "No amount at {} in the credit response: {}",
+                self.check.remaining, body
+            )
+        })
+    }
+}
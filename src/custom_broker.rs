@@ -0,0 +1,453 @@
+use crate::accounts;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use reqwest::header::{
+    ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderName, HeaderValue, USER_AGENT,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::Instant;
+
+fn default_user_agent() -> String {
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36".to_string()
+}
+
+fn default_content_type() -> String {
+    "application/json".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CustomBrokersConfig {
+    pub brokers: Vec<CustomBrokerConfig>,
+}
+
+/// A broker described entirely by config: endpoint, headers and a JSON body
+/// template whose `{{field}}` placeholders are filled from each order.
+#[derive(Debug, Deserialize, Clone)]
+pub struct CustomBrokerConfig {
+    pub name: String,
+    pub order_url: String,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default = "default_content_type")]
+    pub content_type: String,
+    pub body_template: Value,
+    pub orders: Vec<OrderEntry<Map<String, Value>>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
+    /// One copy of this broker per entry of `accounts`, each with the
+    /// account's own credentials.
+    #[serde(default)]
+    pub accounts: Vec<CustomBrokerConfig>,
+}
+
+pub fn load_config(path: &str) -> Result<CustomBrokersConfig> {
+    let config_str = encryption::read_config(path)?;
+    let mut config: CustomBrokersConfig = accounts::parse_config(path, &config_str)?;
+    for broker in &mut config.brokers {
+        for headers in std::iter::once(&mut broker.headers).chain(
+            broker
+                .accounts
+                .iter_mut()
+                .map(|account| &mut account.headers),
+        ) {
+            for value in headers.values_mut() {
+                secrets::resolve(value)?;
+            }
+        }
+    }
+    Ok(config)
+}
+
+pub fn find_broker<'a>(
+    config: &'a CustomBrokersConfig,
+    name: &str,
+) -> Option<&'a CustomBrokerConfig> {
+    accounts::find(&config.brokers, name)
+}
+
+impl OrderFields for Map<String, Value> {
+    fn isin(&self) -> Option<&str> {
+        self.get("isin").and_then(Value::as_str)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.insert("isin".to_string(), Value::String(isin.to_string()));
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.get("symbol").and_then(Value::as_str)
+    }
+
+    fn price(&self) -> Option<f64> {
+        match self.get("price")? {
+            Value::String(price) => price.trim().parse().ok(),
+            price => price.as_f64(),
+        }
+    }
+
+    /// Keeps a string price a string.
+    fn set_price(&mut self, price: f64) {
+        let price = price.round() as i64;
+        let value = match self.get("price") {
+            Some(Value::String(_)) => Value::String(price.to_string()),
+            _ => Value::from(price),
+        };
+        self.insert("price".to_string(), value);
+    }
+
+    /// Keeps a string quantity a string.
+    fn quantity(&self) -> Option<u64> {
+        match self.get("quantity")? {
+            Value::String(quantity) => quantity.trim().parse().ok(),
+            quantity => quantity.as_u64(),
+        }
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        let value = match self.get("quantity") {
+            Some(Value::String(_)) => Value::String(quantity.to_string()),
+            _ => Value::from(quantity),
+        };
+        self.insert("quantity".to_string(), value);
+    }
+
+    /// From a `side` field, buying when there is none.
+    fn side(&self) -> OrderSide {
+        self.get("side")
+            .and_then(OrderSide::from_value)
+            .unwrap_or(OrderSide::Buy)
+    }
+}
+
+impl Broker for CustomBrokerConfig {
+    type Order = Map<String, Value>;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn config_file(&self) -> &str {
+        "config_custom.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<Map<String, Value>>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<Map<String, Value>>> {
+        &mut self.orders
+    }
+
+    fn accounts(&self) -> &[Self] {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self] {
+        &mut self.accounts
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        println!(
+            "[{}] Using {} configured header(s): {}",
+            self.name,
+            self.headers.len(),
+            self.headers
+                .keys()
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Ok(())
+    }
+
+    fn order_json(&self, order: &OrderEntry<Map<String, Value>>) -> Result<String> {
+        render_body(self, order)
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(self, client)
+    }
+}
+
+/// Render the body template for one order and serialize it.
+///
+/// A string that is exactly `{{field}}` is replaced by the order value with its
+/// JSON type preserved; placeholders embedded in longer strings are substituted
+/// textually.
+pub fn render_body(
+    broker: &CustomBrokerConfig,
+    order: &OrderEntry<Map<String, Value>>,
+) -> Result<String> {
+    let fields = order.render()?;
+    let fields = fields
+        .as_object()
+        .context("Custom order must be a JSON object")?;
+    let body = render_value(&broker.body_template, fields)
+        .with_context(|| format!("Failed to render body_template for {}", broker.name))?;
+    Ok(serde_json::to_string(&body)?)
+}
+
+fn render_value(template: &Value, order: &Map<String, Value>) -> Result<Value> {
+    match template {
+        Value::String(text) => render_string(text, order),
+        Value::Array(items) => items
+            .iter()
+            .map(|item| render_value(item, order))
+            .collect::<Result<Vec<_>>>()
+            .map(Value::Array),
+        Value::Object(fields) => {
+            let mut rendered = Map::with_capacity(fields.len());
+            for (key, value) in fields {
+                rendered.insert(key.clone(), render_value(value, order)?);
+            }
+            Ok(Value::Object(rendered))
+        }
+        other => Ok(other.clone()),
+    }
+}
+
+fn render_string(text: &str, order: &Map<String, Value>) -> Result<Value> {
+    if let Some(key) = text
+        .strip_prefix("{{")
+        .and_then(|rest| rest.strip_suffix("}}"))
+        .filter(|key| !key.contains("{{") && !key.contains("}}"))
+    {
+        return lookup(order, key.trim()).cloned();
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .with_context(|| format!("Unterminated placeholder in '{}'", text))?;
+        result.push_str(&rest[..start]);
+        let key = rest[start + 2..start + end].trim();
+        match lookup(order, key)? {
+            Value::String(value) => result.push_str(value),
+            Value::Null => {}
+            value => result.push_str(&value.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    result.push_str(rest);
+    Ok(Value::String(result))
+}
+
+fn lookup<'a>(order: &'a Map<String, Value>, key: &str) -> Result<&'a Value> {
+    order
+        .get(key)
+        .with_context(|| format!("Order is missing field '{}' used in body_template", key))
+}
+
+fn build_headers(broker: &CustomBrokerConfig) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/plain, */*"),
+    );
+    for (name, value) in &broker.headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .with_context(|| format!("Invalid header name '{}' for {}", name, broker.name))?;
+        let header_value = HeaderValue::from_str(value)
+            .with_context(|| format!("Invalid value for header '{}' for {}", name, broker.name))?;
+        headers.insert(header_name, header_value);
+    }
+    Ok(headers)
+}
+
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(broker: &CustomBrokerConfig, order_json: &str) -> Result<HeaderMap> {
+    let mut headers = build_headers(broker)?;
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_str(&broker.content_type)?);
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// Build the request for `order_json` and send it.
+pub async fn send_order(
+    broker: &CustomBrokerConfig,
+    order_json: &str,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let request = PreparedRequest::new(
+        &broker.order_url,
+        broker.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(broker, &request, test_mode, curl_only, rate_limiter).await
+}
+
+/// Send a request built by [`Broker::prepare`].
+pub async fn send_request(
+    broker: &CustomBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = broker.settings.client();
+
+    if test_mode || curl_only {
+        println!("[{}] Equivalent curl command:", broker.name);
+        let mut header_lines = format!("  -H 'User-Agent: {}' \\\n", broker.user_agent);
+        for (name, value) in &broker.headers {
+            header_lines.push_str(&format!("  -H '{}: {}' \\\n", name, value));
+        }
+        println!(
+            "curl '{}' \\\n  --compressed \\\n  -X POST \\\n{}  -H 'Content-Type: {}' \\\n  --data-raw '{}'",
+            broker.order_url, header_lines, broker.content_type, order_json
+        );
+        println!();
+
+        if curl_only {
+            return Ok(());
+        }
+    }
+
+    let (url, headers) = request.parts();
+
+    let mut timing = console::Timing::start();
+    if let Some(limiter) = rate_limiter {
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
+    }
+    timing.mark("rate limit");
+
+    console::debug_headers(&broker.name, "Request", &headers);
+    let mut capture = Capture::start(&broker.name, &url, &headers, &request.body);
+
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[{}] Sent order JSON: {}", broker.name, order_json);
+    let response = capture.sent(response)?;
+
+    let status = response.status();
+    console::debug_headers(&broker.name, "Response", response.headers());
+    capture.response_head(status, response.headers());
+    let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
+
+    let decoded_text = if response_text.contains("\\u") {
+        crate::decode_unicode_escapes(&response_text)
+    } else {
+        response_text.clone()
+    };
+
+    println!("[{}] Order response status: {}", broker.name, status);
+    println!("[{}] Order response body: {}", broker.name, decoded_text);
+    timing.report(&broker.name);
+
+    if let Some(file) = &broker.settings.response_schema_file {
+        schema_drift::check(&broker.name, file, status, &decoded_text);
+    }
+    success::check_response(&broker.settings.success_rules, status, decoded_text)
+}
+
+pub async fn run_calibration(
+    broker: &CustomBrokerConfig,
+    client: &reqwest::Client,
+    rate_limiter: &RateLimiter,
+) -> Result<calibration::CalibrationSummary> {
+    let calibration = broker
+        .settings
+        .calibration
+        .as_ref()
+        .context("Calibration config missing")?;
+
+    let prefix = format!("[{}]", broker.name);
+    calibration::run_calibration(&prefix, calibration, rate_limiter, || {
+        send_probe(broker, client)
+    })
+    .await
+}
+
+async fn send_probe(
+    broker: &CustomBrokerConfig,
+    client: &reqwest::Client,
+) -> Result<(u64, u128, StatusCode)> {
+    let t0 = Instant::now();
+
+    let headers = build_headers(broker)?;
+
+    let base_url = calibration::probe_url(&broker.order_url)?;
+    let response = client.head(base_url).headers(headers).send().await?;
+    let status = response.status();
+
+    let rtt = t0.elapsed();
+    let rtt_micros = rtt.as_micros();
+    let rtt_ms = rtt.as_millis() as u64;
+
+    Ok((rtt_ms, rtt_micros, status))
+}
@@ -0,0 +1,163 @@
+//! `--daemon`: detach from the terminal and run in the background, with the
+//! process ID in a PID file so `sarkhati stop` can end it later. Meant for
+//! headless VPSes where nothing keeps a terminal open.
+
+use anyhow::Result;
+use std::sync::OnceLock;
+
+/// Where the daemon's process ID is written, unless `--pid-file` is given.
+pub const PID_FILE: &str = "sarkhati.pid";
+
+/// How long `stop` waits for the daemon to exit.
+#[cfg(unix)]
+const STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// The PID file of this process, once it has detached.
+static PID_PATH: OnceLock<String> = OnceLock::new();
+
+/// Fork into the background and write the PID file. The calling process
+/// prints the daemon's process ID and exits; only the daemon returns. Call
+/// this before any thread is started, as a fork keeps only the calling one.
+#[cfg(unix)]
+pub fn detach(pid_file: &str) -> Result<()> {
+    use anyhow::Context;
+    use std::io::Write;
+
+    if let Some(pid) = running_pid(pid_file) {
+        anyhow::bail!(
+            "Sarkhati is already running as process {} (see {})",
+            pid,
+            pid_file
+        );
+    }
+    std::io::stdout().flush()?;
+    std::io::stderr().flush()?;
+    // SAFETY: no other thread exists yet, so the forked children start in a
+    // consistent state; the descriptor calls are checked before use.
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(std::io::Error::last_os_error()).context("Failed to fork"),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+        // A new session without a controlling terminal, then a second fork so
+        // the daemon can never acquire one again.
+        if libc::setsid() < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to start a session");
+        }
+        match libc::fork() {
+            -1 => return Err(std::io::Error::last_os_error()).context("Failed to fork"),
+            0 => {}
+            pid => {
+                crate::summary!("[Daemon] Started in the background as process {}", pid);
+                libc::_exit(0)
+            }
+        }
+        let null = libc::open(c"/dev/null".as_ptr(), libc::O_RDWR);
+        if null < 0
+            || libc::dup2(null, libc::STDIN_FILENO) < 0
+            || libc::dup2(null, libc::STDOUT_FILENO) < 0
+            || libc::dup2(null, libc::STDERR_FILENO) < 0
+        {
+            return Err(std::io::Error::last_os_error())
+                .context("Failed to detach from the terminal");
+        }
+        libc::close(null);
+    }
+    std::fs::write(pid_file, format!("{}\n", std::process::id()))
+        .with_context(|| format!("Failed to write PID file {}", pid_file))?;
+    let _ = PID_PATH.set(pid_file.to_string());
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn detach(_pid_file: &str) -> Result<()> {
+    anyhow::bail!("--daemon is only supported on Unix")
+}
+
+/// Resolve once the process is told to stop, by Ctrl-C or `sarkhati stop`.
+/// Never resolves if the handlers cannot be installed.
+#[cfg(unix)]
+pub async fn stop_requested() {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let (Ok(mut terminate), Ok(mut interrupt)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::interrupt()),
+    ) else {
+        return std::future::pending().await;
+    };
+    tokio::select! {
+        _ = terminate.recv() => {}
+        _ = interrupt.recv() => {}
+    }
+}
+
+#[cfg(not(unix))]
+pub async fn stop_requested() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Remove this daemon's PID file, if it wrote one.
+pub fn remove_pid_file() {
+    if let Some(path) = PID_PATH.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// `sarkhati stop`: signal the daemon named in `pid_file` to stop and wait
+/// for it to exit. A PID file left by a daemon that died is removed.
+#[cfg(unix)]
+pub fn stop(pid_file: &str) -> Result<()> {
+    let Some(pid) = running_pid(pid_file) else {
+        if std::path::Path::new(pid_file).exists() {
+            let _ = std::fs::remove_file(pid_file);
+            anyhow::bail!("Sarkhati is not running; removed stale {}", pid_file);
+        }
+        anyhow::bail!("Sarkhati is not running ({} not found)", pid_file);
+    };
+    // SAFETY: sending a signal has no memory effects.
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(anyhow::Error::new(std::io::Error::last_os_error())
+            .context(format!("Failed to signal process {}", pid)));
+    }
+    let start = std::time::Instant::now();
+    while is_running(pid) {
+        if start.elapsed() > STOP_TIMEOUT {
+            anyhow::bail!(
+                "Process {} did not stop within {}s",
+                pid,
+                STOP_TIMEOUT.as_secs()
+            );
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    let _ = std::fs::remove_file(pid_file);
+    println!("[Daemon] Stopped process {}", pid);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn stop(_pid_file: &str) -> Result<()> {
+    anyhow::bail!("sarkhati stop is only supported on Unix")
+}
+
+/// The process ID in `pid_file`, if that process is still alive.
+#[cfg(unix)]
+fn running_pid(pid_file: &str) -> Option<libc::pid_t> {
+    let pid = std::fs::read_to_string(pid_file)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    (pid > 0 && is_running(pid)).then_some(pid)
+}
+
+#[cfg(unix)]
+fn is_running(pid: libc::pid_t) -> bool {
+    // SAFETY: signal 0 only checks that the process exists.
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
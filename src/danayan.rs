@@ -1,30 +1,55 @@
-use crate::calibration::{self, CalibrationConfig};
-use crate::rate_limiter::RateLimiter;
+use crate::accounts;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::DanayanOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
 use anyhow::{Context, Result};
+use reqwest::StatusCode;
 use reqwest::header::{
-    HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, ORIGIN,
+    ACCEPT, ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HeaderMap, HeaderValue, ORIGIN,
     USER_AGENT,
 };
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::time::Instant;
 
+/// Brokers running the TseOms `RegisterOrder` API used by Danayan.
 #[derive(Debug, Deserialize, Clone)]
-pub struct DanayanConfig {
+pub struct DanayanBrokersConfig {
+    pub brokers: Vec<DanayanBrokerConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DanayanBrokerConfig {
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Unused when `accounts` are listed; each account sets its own.
+    #[serde(default)]
     pub cookie: String,
     #[serde(default = "default_user_agent")]
     pub user_agent: String,
     #[serde(default = "default_order_url")]
     pub order_url: String,
-    pub orders: Vec<DanayanOrderData>,
-    #[serde(default = "default_batch_delay")]
-    pub batch_delay_ms: u64,
-    #[serde(default = "default_batch_repeat")]
-    pub batch_repeat: usize,
-    #[serde(default)]
-    pub target_time: Option<String>,
+    #[serde(default = "default_origin")]
+    pub origin: String,
+    pub orders: Vec<OrderEntry<DanayanOrderData>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
+    /// One copy of this broker per entry of `accounts`, each with the
+    /// account's own credentials.
     #[serde(default)]
-    pub calibration: Option<CalibrationConfig>,
+    pub accounts: Vec<DanayanBrokerConfig>,
+}
+
+fn default_name() -> String {
+    "Danayan".to_string()
 }
 
 fn default_user_agent() -> String {
@@ -35,12 +60,8 @@ fn default_order_url() -> String {
     "https://otapi.danayan.broker/api/v1/TseOms/RegisterOrder".to_string()
 }
 
-fn default_batch_delay() -> u64 {
-    100
-}
-
-fn default_batch_repeat() -> usize {
-    1
+fn default_origin() -> String {
+    "https://trader.danayan.broker".to_string()
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -53,26 +74,238 @@ pub struct DanayanOrderData {
     pub quantity: i64,
     #[serde(rename = "disclosedQuantity")]
     pub disclosed_quantity: Option<i64>,
+    #[serde(default)]
     pub isin: String,
     #[serde(rename = "orderSide")]
     pub order_side: i32,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
+}
+
+/// Load `config_danayan.json`, accepting either a `brokers` array or the
+/// original single-broker object.
+pub fn load_config(path: &str) -> Result<DanayanBrokersConfig> {
+    let config_str = encryption::read_config(path)?;
+    let mut value: serde_json::Value =
+        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+    accounts::expand(&mut value)?;
+    let mut config: DanayanBrokersConfig = if value.get("brokers").is_some() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(|broker| DanayanBrokersConfig {
+            brokers: vec![broker],
+        })
+    }
+    .with_context(|| format!("Failed to parse {}", path))?;
+    for broker in &mut config.brokers {
+        secrets::resolve(&mut broker.cookie)?;
+        for account in &mut broker.accounts {
+            secrets::resolve(&mut account.cookie)?;
+        }
+    }
+    Ok(config)
+}
+
+pub fn find_broker<'a>(
+    config: &'a DanayanBrokersConfig,
+    name: &str,
+) -> Option<&'a DanayanBrokerConfig> {
+    accounts::find(&config.brokers, name)
+}
+
+impl OrderFields for DanayanOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.isin)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.isin = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn price(&self) -> Option<f64> {
+        Some(self.price as f64)
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.price = price.round() as i64;
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        u64::try_from(self.quantity).ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.quantity = quantity as i64;
+    }
+
+    fn side(&self) -> OrderSide {
+        if self.order_side == 2 {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }
+    }
+}
+
+impl Broker for DanayanBrokerConfig {
+    type Order = DanayanOrderData;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn config_file(&self) -> &str {
+        "config_danayan.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<DanayanOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<DanayanOrderData>> {
+        &mut self.orders
+    }
+
+    fn accounts(&self) -> &[Self] {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self] {
+        &mut self.accounts
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        if self.cookie.is_empty() {
+            anyhow::bail!(
+                "Cookie is required for {}. Please set 'cookie' in config_danayan.json",
+                self.name
+            );
+        }
+
+        println!("[{}] Using Cookie authentication", self.name);
+        println!(
+            "[{}] Cookie preview: {}...",
+            self.name,
+            &self.cookie[..self.cookie.len().min(50)]
+        );
+        Ok(())
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(self, client)
+    }
+}
+
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(broker: &DanayanBrokerConfig, order_json: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/plain, */*"),
+    );
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("en-US,en;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
+    headers.insert("Priority", HeaderValue::from_static("u=0"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
 }
 
+/// Build the request for `order_json` and send it.
 pub async fn send_order(
-    config: &DanayanConfig,
-    order: &DanayanOrderData,
+    broker: &DanayanBrokerConfig,
+    order_json: &str,
     test_mode: bool,
     curl_only: bool,
     rate_limiter: Option<&RateLimiter>,
 ) -> Result<()> {
-    let client = reqwest::Client::new();
+    let request = PreparedRequest::new(
+        &broker.order_url,
+        broker.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(broker, &request, test_mode, curl_only, rate_limiter).await
+}
 
-    let order_json = serde_json::to_string(order)?;
+/// Send a request built by [`Broker::prepare`].
+pub async fn send_request(
+    broker: &DanayanBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = broker.settings.client();
 
-    // Print curl command in test mode
-    if test_mode {
-        println!("[Danayan] Equivalent curl command:");
-        println!(r#"curl '{}' \
+    // Print curl command in test or curl-only mode
+    if test_mode || curl_only {
+        println!("[{}] Equivalent curl command:", broker.name);
+        println!(
+            r#"curl '{}' \
   --compressed \
   -X POST \
   -H 'User-Agent: {}' \
@@ -80,7 +313,7 @@ pub async fn send_order(
   -H 'Accept-Language: en-US,en;q=0.5' \
   -H 'Accept-Encoding: gzip, deflate, br, zstd' \
   -H 'Content-Type: application/json' \
-  -H 'Origin: https://trader.danayan.broker' \
+  -H 'Origin: {}' \
   -H 'Connection: keep-alive' \
   -H 'Cookie: {}' \
   -H 'Sec-Fetch-Dest: empty' \
@@ -90,7 +323,8 @@ pub async fn send_order(
   -H 'Pragma: no-cache' \
   -H 'Cache-Control: no-cache' \
   --data-raw '{}'"#,
-            config.order_url, config.user_agent, config.cookie, order_json);
+            broker.order_url, broker.user_agent, broker.origin, broker.cookie, order_json
+        );
         println!();
 
         // If curl_only, don't send the request
@@ -99,40 +333,37 @@ pub async fn send_order(
         }
     }
 
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
-    headers.insert(ACCEPT, HeaderValue::from_static("application/json, text/plain, */*"));
-    headers.insert("Accept-Language", HeaderValue::from_static("en-US,en;q=0.5"));
-    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate, br, zstd"));
-    headers.insert(ORIGIN, HeaderValue::from_static("https://trader.danayan.broker"));
-    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
-    headers.insert(COOKIE, HeaderValue::from_str(&config.cookie)?);
-    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
-    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
-    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
-    headers.insert("Priority", HeaderValue::from_static("u=0"));
-    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
-    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    let (url, headers) = request.parts();
 
+    let mut timing = console::Timing::start();
     if let Some(limiter) = rate_limiter {
-        limiter.wait().await;
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
     }
+    timing.mark("rate limit");
 
-    let body_bytes = order_json.as_bytes();
-
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    headers.insert(CONTENT_LENGTH, HeaderValue::from_str(&body_bytes.len().to_string())?);
-
-    println!("[Danayan] Sending order JSON: {}", order_json);
+    console::debug_headers(&broker.name, "Request", &headers);
+    let mut capture = Capture::start(&broker.name, &url, &headers, &request.body);
 
-    let response = client.post(&config.order_url)
-        .headers(headers)
-        .body(order_json)
-        .send()
-        .await?;
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[{}] Sent order JSON: {}", broker.name, order_json);
+    let response = capture.sent(response)?;
 
     let status = response.status();
+    console::debug_headers(&broker.name, "Response", response.headers());
+    capture.response_head(status, response.headers());
     let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
 
     let decoded_text = if response_text.contains("\\u") {
         crate::decode_unicode_escapes(&response_text)
@@ -140,44 +371,50 @@ pub async fn send_order(
         response_text.clone()
     };
 
-    println!("[Danayan] Order response status: {}", status);
-    println!("[Danayan] Order response body: {}", decoded_text);
+    println!("[{}] Order response status: {}", broker.name, status);
+    println!("[{}] Order response body: {}", broker.name, decoded_text);
+    timing.report(&broker.name);
 
-    if !status.is_success() {
-        anyhow::bail!("Order failed with status {}: {}", status, decoded_text);
+    if let Some(file) = &broker.settings.response_schema_file {
+        schema_drift::check(&broker.name, file, status, &decoded_text);
     }
-
-    Ok(())
+    success::check_response_as::<DanayanOrderResponse>(
+        &broker.settings.success_rules,
+        status,
+        decoded_text,
+    )
 }
 
 pub async fn run_calibration(
-    config: &DanayanConfig,
+    broker: &DanayanBrokerConfig,
     client: &reqwest::Client,
     rate_limiter: &RateLimiter,
 ) -> Result<calibration::CalibrationSummary> {
-    let calibration = config
+    let calibration = broker
+        .settings
         .calibration
         .as_ref()
         .context("Calibration config missing")?;
 
-    calibration::run_calibration("[Danayan]", calibration, rate_limiter, || {
-        send_probe(client, config)
+    let prefix = format!("[{}]", broker.name);
+    calibration::run_calibration(&prefix, calibration, rate_limiter, || {
+        send_probe(broker, client)
     })
     .await
 }
 
 async fn send_probe(
+    broker: &DanayanBrokerConfig,
     client: &reqwest::Client,
-    config: &DanayanConfig,
 ) -> Result<(u64, u128, StatusCode)> {
     let t0 = Instant::now();
 
     let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
     headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
-    headers.insert(COOKIE, HeaderValue::from_str(&config.cookie)?);
+    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
 
-    let base_url = calibration::probe_url(&config.order_url)?;
+    let base_url = calibration::probe_url(&broker.order_url)?;
     let response = client.head(base_url).headers(headers).send().await?;
     let status = response.status();
 
@@ -0,0 +1,141 @@
+use crate::market_data::MarketData;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// `depth_check` setting: compare each order with the order book right
+/// before it is sent.
+#[derive(Debug, Deserialize, Clone)]
+pub struct DepthCheck {
+    /// How far, in percent of the best price on the order's side, an order
+    /// may trail the top of the queue: a buy priced below the best bid, or a
+    /// sell priced above the best ask.
+    pub max_behind_percent: f64,
+    #[serde(default)]
+    pub action: DepthAction,
+}
+
+/// What happens to an order that is too far behind the queue.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DepthAction {
+    /// Do not send it this time.
+    #[default]
+    Skip,
+    /// Send it at the best price on its side instead.
+    Adjust,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthDecision {
+    Send,
+    Skip,
+    /// Send at this price instead of the configured one.
+    Reprice(f64),
+}
+
+/// Runs a [`DepthCheck`] against fresh order books, with the TSETMC code of
+/// every order's instrument looked up once up front.
+pub struct DepthChecker {
+    rule: DepthCheck,
+    market_data: MarketData,
+    ins_codes: HashMap<String, String>,
+}
+
+impl DepthChecker {
+    pub async fn new<T: OrderFields>(
+        rule: &DepthCheck,
+        orders: &[OrderEntry<T>],
+        base_url: &str,
+    ) -> Result<Self> {
+        if !rule.max_behind_percent.is_finite() || rule.max_behind_percent < 0.0 {
+            anyhow::bail!("depth_check.max_behind_percent must be >= 0");
+        }
+        let market_data = MarketData::new(base_url)?;
+        let mut ins_codes = HashMap::new();
+        for (index, order) in orders.iter().enumerate() {
+            let isin = order
+                .data
+                .isin()
+                .with_context(|| format!("depth_check needs an ISIN on order #{}", index + 1))?;
+            if !ins_codes.contains_key(isin) {
+                let instrument = market_data.find_instrument(isin).await?;
+                ins_codes.insert(isin.to_string(), instrument.ins_code);
+            }
+        }
+        Ok(Self {
+            rule: rule.clone(),
+            market_data,
+            ins_codes,
+        })
+    }
+
+    /// Fetch the order book of `order`'s instrument and decide whether to
+    /// send it at `price`, or its own price when `None`, logging the book the
+    /// decision was based on. Orders are sent unchanged when the book cannot
+    /// be fetched.
+    pub async fn check<T: OrderFields>(
+        &self,
+        name: &str,
+        position: usize,
+        order: &T,
+        price: Option<f64>,
+    ) -> DepthDecision {
+        let (Some(isin), Some(price)) = (order.isin(), price.or_else(|| order.price())) else {
+            return DepthDecision::Send;
+        };
+        let Some(ins_code) = self.ins_codes.get(isin) else {
+            return DepthDecision::Send;
+        };
+        let book = match self.market_data.order_book(ins_code).await {
+            Ok(book) => book,
+            Err(e) => {
+                eprintln!(
+                    "[{}] Warning: order book fetch failed, sending order #{} unchecked: {:#}",
+                    name, position, e
+                );
+                return DepthDecision::Send;
+            }
+        };
+
+        let side = order.side();
+        let (best, behind) = match side {
+            OrderSide::Buy => (book.best_bid(), book.best_bid().map(|best| best - price)),
+            OrderSide::Sell => (book.best_ask(), book.best_ask().map(|best| price - best)),
+        };
+        let (Some(best), Some(behind)) = (best, behind) else {
+            println!(
+                "[{}] Depth order #{} ({} {}): {}; no queue on its side",
+                name, position, side, price, book
+            );
+            return DepthDecision::Send;
+        };
+        let behind_percent = behind / best * 100.0;
+        let decision = if behind_percent <= self.rule.max_behind_percent {
+            DepthDecision::Send
+        } else {
+            match self.rule.action {
+                DepthAction::Skip => DepthDecision::Skip,
+                DepthAction::Adjust => DepthDecision::Reprice(best),
+            }
+        };
+        let outcome = match decision {
+            DepthDecision::Send => "sending".to_string(),
+            DepthDecision::Skip => "skipped".to_string(),
+            DepthDecision::Reprice(price) => format!("repriced to {}", price),
+        };
+        println!(
+            "[{}] Depth order #{} ({} {}): {}; {:.2}% behind best {} -> {}",
+            name,
+            position,
+            side,
+            price,
+            book,
+            behind_percent.max(0.0),
+            best,
+            outcome
+        );
+        decision
+    }
+}
@@ -0,0 +1,232 @@
+//! `dispatch_thread`: run a broker's sends on a thread of their own, with a
+//! current-thread runtime, instead of on the shared runtime. Quote polling,
+//! the control API and other brokers' logging then never hold up an order,
+//! and the thread can run at a raised priority, under a real-time
+//! scheduling policy or pinned to one CPU.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::future::Future;
+use tokio::sync::oneshot;
+
+/// Scheduling priority of the dispatch thread.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadPriority {
+    #[default]
+    Normal,
+    /// Nice -10 on Linux, which needs root or `CAP_SYS_NICE`; the highest
+    /// thread priority on Windows.
+    High,
+}
+
+/// Linux real-time scheduling policy of the dispatch thread.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RealtimePolicy {
+    /// `SCHED_FIFO`: runs until it blocks or yields.
+    Fifo,
+    /// `SCHED_RR`: as `fifo`, time-sliced with threads of equal priority.
+    Rr,
+}
+
+fn default_realtime_priority() -> i32 {
+    50
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DispatchThread {
+    #[serde(default)]
+    pub priority: ThreadPriority,
+    /// Real-time policy, Linux only; needs root or `CAP_SYS_NICE`.
+    #[serde(default)]
+    pub realtime: Option<RealtimePolicy>,
+    /// Priority under `realtime`, from 1 to 99.
+    #[serde(default = "default_realtime_priority")]
+    pub realtime_priority: i32,
+    /// CPU to pin the thread to, Linux only; ideally one isolated with
+    /// `isolcpus`.
+    #[serde(default)]
+    pub cpu: Option<usize>,
+}
+
+impl Default for DispatchThread {
+    fn default() -> Self {
+        Self {
+            priority: ThreadPriority::default(),
+            realtime: None,
+            realtime_priority: default_realtime_priority(),
+            cpu: None,
+        }
+    }
+}
+
+/// Nice value of a high-priority dispatch thread on Linux.
+#[cfg(target_os = "linux")]
+const HIGH_PRIORITY_NICE: libc::c_int = -10;
+
+/// Run `task` on a new thread with its own current-thread runtime and wait
+/// for it. Connections opened by `task` are driven by that runtime too.
+/// Dropping the returned future stops `task` as well.
+pub async fn run_dedicated<F>(name: &str, thread: &DispatchThread, task: F) -> Result<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if thread.realtime.is_some() && !(1..=99).contains(&thread.realtime_priority) {
+        anyhow::bail!(
+            "dispatch_thread.realtime_priority must be between 1 and 99, got {}",
+            thread.realtime_priority
+        );
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .context("Failed to build the dispatch runtime")?;
+    let (mut done, finished) = oneshot::channel();
+    let label = name.to_string();
+    let thread = thread.clone();
+    std::thread::Builder::new()
+        .name(format!("dispatch-{}", name))
+        .spawn(move || {
+            configure(&label, &thread);
+            let output = runtime.block_on(async {
+                tokio::select! {
+                    output = task => Some(output),
+                    _ = done.closed() => None,
+                }
+            });
+            if let Some(output) = output {
+                let _ = done.send(output);
+            }
+        })
+        .context("Failed to start the dispatch thread")?;
+    finished
+        .await
+        .with_context(|| format!("The dispatch thread of {} stopped unexpectedly", name))
+}
+
+/// Apply `thread`'s settings to the calling thread. Each one that fails,
+/// usually for lack of permission, is reported and left out.
+fn configure(name: &str, thread: &DispatchThread) {
+    if thread.priority == ThreadPriority::High {
+        match raise_priority() {
+            Ok(()) => println!("[{}] Dispatch thread running at high priority", name),
+            Err(e) => eprintln!(
+                "[{}] Warning: could not raise the dispatch thread's priority: {}",
+                name, e
+            ),
+        }
+    }
+    if let Some(policy) = thread.realtime {
+        match set_realtime(policy, thread.realtime_priority) {
+            Ok(()) => println!(
+                "[{}] Dispatch thread scheduled {:?} at real-time priority {}",
+                name, policy, thread.realtime_priority
+            ),
+            Err(e) => eprintln!(
+                "[{}] Warning: could not switch the dispatch thread to {:?} scheduling: {}",
+                name, policy, e
+            ),
+        }
+    }
+    if let Some(cpu) = thread.cpu {
+        match pin_to_cpu(cpu) {
+            Ok(()) => println!("[{}] Dispatch thread pinned to CPU {}", name, cpu),
+            Err(e) => eprintln!(
+                "[{}] Warning: could not pin the dispatch thread to CPU {}: {}",
+                name, cpu, e
+            ),
+        }
+    }
+}
+
+/// On Linux the nice value set through a thread ID applies to that thread
+/// alone.
+#[cfg(target_os = "linux")]
+fn raise_priority() -> std::io::Result<()> {
+    // SAFETY: plain system calls on the calling thread.
+    let result = unsafe {
+        let tid = libc::gettid();
+        libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, HIGH_PRIORITY_NICE)
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// On Linux, process ID 0 stands for the calling thread.
+#[cfg(target_os = "linux")]
+fn set_realtime(policy: RealtimePolicy, priority: i32) -> std::io::Result<()> {
+    let policy = match policy {
+        RealtimePolicy::Fifo => libc::SCHED_FIFO,
+        RealtimePolicy::Rr => libc::SCHED_RR,
+    };
+    let param = libc::sched_param {
+        sched_priority: priority,
+    };
+    // SAFETY: `param` outlives the call, which only reads it.
+    if unsafe { libc::sched_setscheduler(0, policy, &param) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn pin_to_cpu(cpu: usize) -> std::io::Result<()> {
+    if cpu >= libc::CPU_SETSIZE as usize {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "no such CPU",
+        ));
+    }
+    // SAFETY: an all-zero cpu_set_t is an empty set, and the call only
+    // reads `set`.
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_realtime(_policy: RealtimePolicy, _priority: i32) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "real-time scheduling is only supported on Linux",
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_cpu(_cpu: usize) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "CPU pinning is only supported on Linux",
+    ))
+}
+
+#[cfg(windows)]
+fn raise_priority() -> std::io::Result<()> {
+    use windows_sys::Win32::System::Threading::{
+        GetCurrentThread, SetThreadPriority, THREAD_PRIORITY_HIGHEST,
+    };
+
+    // SAFETY: GetCurrentThread returns a pseudo handle that needs no closing.
+    if unsafe { SetThreadPriority(GetCurrentThread(), THREAD_PRIORITY_HIGHEST) } == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+fn raise_priority() -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "thread priorities are only supported on Linux and Windows",
+    ))
+}
@@ -0,0 +1,385 @@
+//! `sarkhati doctor`: check the machine before the morning it matters. For
+//! each configured broker host, that its name resolves and a TLS connection
+//! goes through; then the clock against an NTP server, the permissions of
+//! the config files and the open-file limit. Each check passes, warns or
+//! fails, with a hint on how to fix what did not pass.
+
+use crate::registry::{self, AnyBroker};
+use crate::runner::Broker;
+use crate::with_broker;
+use anyhow::{Context, Result};
+use reqwest::Url;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Asked for the time when `--ntp-server` is not given.
+pub const DEFAULT_NTP_SERVER: &str = "pool.ntp.org";
+
+/// Clock offsets above these warn and fail: orders are timed to the
+/// millisecond, and the exchange's clock is the one that counts.
+const CLOCK_WARN_MS: f64 = 50.0;
+const CLOCK_FAIL_MS: f64 = 500.0;
+
+/// Open-file limits below this warn: every broker and account holds
+/// connections, and a continuous run opens more as they time out.
+const MIN_OPEN_FILES: u64 = 4096;
+
+/// Seconds from the NTP epoch (1900) to the Unix epoch.
+const NTP_UNIX_OFFSET: f64 = 2_208_988_800.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckState {
+    Pass,
+    Warn,
+    Fail,
+}
+
+/// The outcome of one check.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub state: CheckState,
+    pub detail: String,
+    /// What to do about a check that did not pass.
+    pub hint: Option<String>,
+}
+
+impl Check {
+    pub(crate) fn pass(name: &str, detail: String) -> Self {
+        Self {
+            name: name.to_string(),
+            state: CheckState::Pass,
+            detail,
+            hint: None,
+        }
+    }
+
+    pub(crate) fn problem(name: &str, state: CheckState, detail: String, hint: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            state,
+            detail,
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+/// Resolve the host of `url`, then connect to its origin, through TLS for
+/// `https`. Any HTTP answer means the connection works.
+pub async fn check_host(url: &str, client: &reqwest::Client) -> Vec<Check> {
+    let parsed = match Url::parse(url) {
+        Ok(parsed) if parsed.host_str().is_some() => parsed,
+        _ => {
+            return vec![Check::problem(
+                url,
+                CheckState::Fail,
+                "not a valid URL".to_string(),
+                "fix the URL in the config",
+            )];
+        }
+    };
+    let host = parsed.host_str().unwrap_or_default();
+    let port = parsed.port_or_known_default().unwrap_or(443);
+
+    let started = Instant::now();
+    let dns_name = format!("DNS {}", host);
+    let dns =
+        match tokio::time::timeout(REQUEST_TIMEOUT, tokio::net::lookup_host((host, port))).await {
+            Ok(Ok(mut addresses)) => match addresses.next() {
+                Some(address) => Check::pass(
+                    &dns_name,
+                    format!("{} in {} ms", address.ip(), started.elapsed().as_millis()),
+                ),
+                None => Check::problem(
+                    &dns_name,
+                    CheckState::Fail,
+                    "no addresses".to_string(),
+                    "check the host name in the config",
+                ),
+            },
+            Ok(Err(e)) => Check::problem(
+                &dns_name,
+                CheckState::Fail,
+                e.to_string(),
+                "check the host name in the config and the resolver in /etc/resolv.conf",
+            ),
+            Err(_) => Check::problem(
+                &dns_name,
+                CheckState::Fail,
+                "timed out".to_string(),
+                "the resolver is slow or unreachable; set a faster one in /etc/resolv.conf",
+            ),
+        };
+    if dns.state == CheckState::Fail {
+        return vec![dns];
+    }
+
+    let tls = parsed.scheme() == "https";
+    let connect_name = format!("{} {}:{}", if tls { "TLS" } else { "Connect" }, host, port);
+    let origin = format!("{}://{}:{}/", parsed.scheme(), host, port);
+    let started = Instant::now();
+    let connect = match client.get(&origin).timeout(REQUEST_TIMEOUT).send().await {
+        Ok(response) => Check::pass(
+            &connect_name,
+            format!(
+                "HTTP {} in {} ms",
+                response.status().as_u16(),
+                started.elapsed().as_millis()
+            ),
+        ),
+        Err(e) => {
+            let detail = error_chain(&e);
+            let hint = if detail.contains("certificate") {
+                "the certificate was refused; check the system clock and CA certificates"
+            } else {
+                "the host is unreachable from here; check the firewall, VPN or proxy (some brokers refuse foreign IPs)"
+            };
+            Check::problem(&connect_name, CheckState::Fail, detail, hint)
+        }
+    };
+    vec![dns, connect]
+}
+
+fn error_chain(error: &reqwest::Error) -> String {
+    let mut text = error.to_string();
+    let mut source = std::error::Error::source(error);
+    while let Some(cause) = source {
+        text.push_str(": ");
+        text.push_str(&cause.to_string());
+        source = cause.source();
+    }
+    text
+}
+
+fn unix_now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs_f64())
+        .unwrap_or_default()
+}
+
+/// Unix time in seconds from an NTP timestamp.
+fn from_ntp(bytes: &[u8]) -> f64 {
+    let seconds = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    let fraction = u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    seconds as f64 - NTP_UNIX_OFFSET + fraction as f64 / 4_294_967_296.0
+}
+
+/// The NTP timestamp of Unix time `time` in seconds.
+pub fn to_ntp(time: f64) -> [u8; 8] {
+    let time = time + NTP_UNIX_OFFSET;
+    let seconds = time.trunc() as u32;
+    let fraction = (time.fract() * 4_294_967_296.0) as u32;
+    let mut bytes = [0; 8];
+    bytes[..4].copy_from_slice(&seconds.to_be_bytes());
+    bytes[4..].copy_from_slice(&fraction.to_be_bytes());
+    bytes
+}
+
+/// How far the local clock is behind `server` (`host` or `host:port`) in
+/// milliseconds, negative when it is ahead, by one SNTP exchange.
+pub async fn clock_offset_ms(server: &str) -> Result<f64> {
+    let server = if server.contains(':') {
+        server.to_string()
+    } else {
+        format!("{}:123", server)
+    };
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket
+        .connect(&server)
+        .await
+        .with_context(|| format!("Failed to reach NTP server {}", server))?;
+
+    // Version 4, client mode, with the send time as transmit timestamp.
+    let mut request = [0u8; 48];
+    request[0] = 0x23;
+    let sent = unix_now();
+    request[40..].copy_from_slice(&to_ntp(sent));
+    socket.send(&request).await?;
+    let mut response = [0u8; 48];
+    let length = tokio::time::timeout(REQUEST_TIMEOUT, socket.recv(&mut response))
+        .await
+        .with_context(|| format!("No answer from NTP server {}", server))??;
+    let received = unix_now();
+    if length < 48 || response[0] & 0x07 != 4 || response[1] == 0 {
+        anyhow::bail!("Invalid answer from NTP server {}", server);
+    }
+
+    let server_received = from_ntp(&response[32..40]);
+    let server_sent = from_ntp(&response[40..48]);
+    Ok(((server_received - sent) + (server_sent - received)) / 2.0 * 1000.0)
+}
+
+pub async fn check_clock(server: &str) -> Check {
+    let name = format!("Clock vs {}", server);
+    let hint = "turn on time sync (`timedatectl set-ntp true`, or chrony) and check again";
+    match clock_offset_ms(server).await {
+        Ok(offset) => {
+            let detail = format!(
+                "{:.0} ms {}",
+                offset.abs(),
+                if offset >= 0.0 { "behind" } else { "ahead" }
+            );
+            if offset.abs() > CLOCK_FAIL_MS {
+                Check::problem(&name, CheckState::Fail, detail, hint)
+            } else if offset.abs() > CLOCK_WARN_MS {
+                Check::problem(&name, CheckState::Warn, detail, hint)
+            } else {
+                Check::pass(&name, detail)
+            }
+        }
+        Err(e) => Check::problem(
+            &name,
+            CheckState::Warn,
+            format!("{:#}", e),
+            "UDP port 123 may be blocked; try another server with --ntp-server",
+        ),
+    }
+}
+
+/// Whether `path` is kept from other users: it holds sessions and tokens.
+pub fn check_config_permissions(path: &str) -> Check {
+    let name = format!("Permissions {}", path);
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            return Check::problem(
+                &name,
+                CheckState::Fail,
+                e.to_string(),
+                "check that the file exists and is readable",
+            );
+        }
+    };
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Check::problem(
+                &name,
+                CheckState::Warn,
+                format!("mode {:o}, readable by other users", mode),
+                &format!("chmod 600 {}", path),
+            );
+        }
+        Check::pass(&name, format!("mode {:o}", mode))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        Check::pass(&name, "readable".to_string())
+    }
+}
+
+/// The soft limit on open files, where the OS has one.
+pub fn check_open_files() -> Option<Check> {
+    #[cfg(unix)]
+    {
+        let name = "Open file limit";
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: getrlimit only writes the struct it is given.
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+            return Some(Check::problem(
+                name,
+                CheckState::Warn,
+                std::io::Error::last_os_error().to_string(),
+                "check `ulimit -n`",
+            ));
+        }
+        // rlim_t is not u64 everywhere.
+        #[allow(clippy::unnecessary_cast)]
+        let (soft, hard) = (limit.rlim_cur as u64, limit.rlim_max as u64);
+        let detail = format!("{} (hard {})", soft, hard);
+        Some(if soft < MIN_OPEN_FILES {
+            Check::problem(
+                name,
+                CheckState::Warn,
+                detail,
+                "raise it with `ulimit -n 65536`, or LimitNOFILE=65536 in the systemd unit",
+            )
+        } else {
+            Check::pass(name, detail)
+        })
+    }
+    #[cfg(not(unix))]
+    None
+}
+
+fn print_check(check: &Check) {
+    let state = match check.state {
+        CheckState::Pass => "PASS",
+        CheckState::Warn => "WARN",
+        CheckState::Fail => "FAIL",
+    };
+    println!("[Doctor] {:<4} {:<40} {}", state, check.name, check.detail);
+    if let Some(hint) = &check.hint {
+        println!("[Doctor]      -> {}", hint);
+    }
+}
+
+/// Run `doctor` for `names`, or every configured broker when empty, and
+/// fail if any check failed.
+pub async fn run_doctor(names: &[String], ntp_server: &str) -> Result<()> {
+    let brokers: Vec<AnyBroker> = if names.is_empty() {
+        registry::all_brokers()?
+    } else {
+        names
+            .iter()
+            .map(|name| registry::find_broker(name))
+            .collect::<Result<_>>()?
+    };
+
+    let client = reqwest::Client::new();
+    let mut checks = Vec::new();
+    let mut urls = BTreeSet::new();
+    for broker in &brokers {
+        with_broker!(broker, b => {
+            urls.insert(b.order_url().to_string());
+            if let Some(url) = &b.settings().auth_check_url {
+                urls.insert(url.clone());
+            }
+        });
+    }
+    // One check per origin, however many brokers or paths share it.
+    let origins: BTreeSet<String> = urls
+        .iter()
+        .map(|url| {
+            Url::parse(url)
+                .map(|parsed| parsed.origin().ascii_serialization())
+                .unwrap_or_else(|_| url.clone())
+        })
+        .collect();
+    for origin in &origins {
+        checks.extend(check_host(origin, &client).await);
+    }
+    checks.push(check_clock(ntp_server).await);
+    let files: BTreeSet<&str> = brokers
+        .iter()
+        .map(|broker| broker.config_location().0)
+        .collect();
+    checks.extend(files.into_iter().map(check_config_permissions));
+    checks.extend(check_open_files());
+
+    for check in &checks {
+        print_check(check);
+    }
+    let count = |state| checks.iter().filter(|check| check.state == state).count();
+    let failed = count(CheckState::Fail);
+    println!(
+        "[Doctor] {} passed, {} warning(s), {} failed",
+        count(CheckState::Pass),
+        count(CheckState::Warn),
+        failed
+    );
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed; see the hints above", failed);
+    }
+    Ok(())
+}
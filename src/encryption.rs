@@ -0,0 +1,178 @@
+use crate::remote_config;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::{Context, Result};
+use rand::RngCore;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// First bytes of every encrypted config, followed by salt, nonce and the
+/// AES-256-GCM ciphertext.
+const MAGIC: &[u8] = b"SARKHATI-ENC1\n";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Extension of the encrypted copy of a config file.
+pub const ENCRYPTED_EXTENSION: &str = "enc";
+
+/// Passphrase entered once per process and reused for every config.
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+
+fn encrypted_path(path: &str) -> String {
+    format!("{}.{}", path, ENCRYPTED_EXTENSION)
+}
+
+/// Whether `path` or its encrypted copy `<path>.enc` exists, or the
+/// `--config` document has it.
+pub fn config_exists(path: &str) -> bool {
+    remote_config::read(path).is_some()
+        || Path::new(path).exists()
+        || Path::new(&encrypted_path(path)).exists()
+}
+
+/// Read a config file from the `--config` document, or else from disk,
+/// decrypting `<path>.enc` when the plain file is absent.
+pub fn read_config(path: &str) -> Result<String> {
+    if let Some(contents) = remote_config::read(path) {
+        return Ok(contents);
+    }
+    if Path::new(path).exists() || !Path::new(&encrypted_path(path)).exists() {
+        return std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path));
+    }
+
+    let encrypted_path = encrypted_path(path);
+    let data = std::fs::read(&encrypted_path)
+        .with_context(|| format!("Failed to read {}", encrypted_path))?;
+    decrypt(&data, &passphrase(false)?)
+        .with_context(|| format!("Failed to decrypt {}", encrypted_path))
+}
+
+/// Write a config file back where [`read_config`] found it, re-encrypting it
+/// if it came from `<path>.enc`.
+pub fn write_config(path: &str, contents: &str) -> Result<()> {
+    if remote_config::update(path, contents) {
+        return Ok(());
+    }
+    if Path::new(path).exists() || !Path::new(&encrypted_path(path)).exists() {
+        return std::fs::write(path, contents).with_context(|| format!("Failed to write {}", path));
+    }
+
+    let encrypted_path = encrypted_path(path);
+    let data = encrypt(contents, &passphrase(false)?)?;
+    std::fs::write(&encrypted_path, data)
+        .with_context(|| format!("Failed to write {}", encrypted_path))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+pub fn encrypt(plaintext: &str, passphrase: &str) -> Result<Vec<u8>> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut salt);
+    rand::rng().fill_bytes(&mut nonce);
+
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, &salt)?.into());
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .map_err(|_| anyhow::anyhow!("Encryption failed"))?;
+
+    let mut data = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+    data.extend_from_slice(MAGIC);
+    data.extend_from_slice(&salt);
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<String> {
+    let rest = data
+        .strip_prefix(MAGIC)
+        .context("Not an encrypted Sarkhati config")?;
+    if rest.len() < SALT_LEN + NONCE_LEN {
+        anyhow::bail!("Encrypted config is truncated");
+    }
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    let cipher = Aes256Gcm::new(&derive_key(passphrase, salt)?.into());
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Wrong passphrase or corrupted file"))?;
+    String::from_utf8(plaintext).context("Decrypted config is not UTF-8")
+}
+
+/// The config passphrase from `SARKHATI_CONFIG_KEY_FILE`,
+/// `SARKHATI_CONFIG_PASSPHRASE` or the console, in that order.
+fn passphrase(confirm: bool) -> Result<String> {
+    let mut cached = PASSPHRASE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(passphrase) = cached.as_ref() {
+        return Ok(passphrase.clone());
+    }
+
+    let passphrase = if let Some(key_file) = std::env::var_os("SARKHATI_CONFIG_KEY_FILE") {
+        let key = std::fs::read_to_string(&key_file)
+            .with_context(|| format!("Failed to read key file {}", key_file.to_string_lossy()))?;
+        key.trim().to_string()
+    } else if let Ok(passphrase) = std::env::var("SARKHATI_CONFIG_PASSPHRASE") {
+        passphrase
+    } else {
+        let passphrase = rpassword::prompt_password("[Config] Passphrase: ")
+            .context("Failed to read passphrase")?;
+        if confirm {
+            let again = rpassword::prompt_password("[Config] Repeat passphrase: ")
+                .context("Failed to read passphrase")?;
+            if again != passphrase {
+                anyhow::bail!("Passphrases do not match");
+            }
+        }
+        passphrase
+    };
+    if passphrase.is_empty() {
+        anyhow::bail!("Config passphrase is empty");
+    }
+
+    *cached = Some(passphrase.clone());
+    Ok(passphrase)
+}
+
+/// Run `encrypt-config <file>`: write `<file>.enc` next to the plain file.
+pub fn run_encrypt(path: &str) -> Result<()> {
+    let plaintext =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+    serde_json::from_str::<serde_json::Value>(&plaintext)
+        .with_context(|| format!("{} is not valid JSON", path))?;
+
+    let data = encrypt(&plaintext, &passphrase(true)?)?;
+    let encrypted_path = encrypted_path(path);
+    std::fs::write(&encrypted_path, data)
+        .with_context(|| format!("Failed to write {}", encrypted_path))?;
+    println!("[Config] Wrote {}", encrypted_path);
+    println!(
+        "[Config] Delete {} to have Sarkhati load the encrypted copy",
+        path
+    );
+    Ok(())
+}
+
+/// Run `decrypt-config <file>`: restore the plain file from `<file>.enc`.
+pub fn run_decrypt(path: &str) -> Result<()> {
+    let path = path
+        .strip_suffix(&format!(".{}", ENCRYPTED_EXTENSION))
+        .unwrap_or(path);
+    if Path::new(path).exists() {
+        anyhow::bail!("{} already exists; remove it first", path);
+    }
+    let encrypted_path = encrypted_path(path);
+    let data = std::fs::read(&encrypted_path)
+        .with_context(|| format!("Failed to read {}", encrypted_path))?;
+    let plaintext = decrypt(&data, &passphrase(false)?)?;
+    std::fs::write(path, plaintext).with_context(|| format!("Failed to write {}", path))?;
+    println!("[Config] Wrote {}", path);
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use crate::persian;
+use crate::responses::OrderResponse;
+use reqwest::StatusCode;
+use serde::Serialize;
+use std::fmt;
+
+/// What went wrong with a rejected order, derived from the HTTP status and
+/// the broker's (often Persian) error message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum OrderErrorKind {
+    AuthExpired,
+    RateLimited,
+    MarketClosed,
+    PriceOutOfRange,
+    InsufficientFunds,
+    DuplicateOrder,
+    Unknown,
+}
+
+/// Body phrases for each kind, checked in order. Matching is done on the
+/// lowercased body with Arabic yeh/kaf, Persian digits, thousands separators
+/// and zero-width non-joiners normalized.
+const BODY_RULES: &[(OrderErrorKind, &[&str])] = &[
+    (
+        OrderErrorKind::DuplicateOrder,
+        &["duplicate", "تکراری", "قبلا ثبت شده"],
+    ),
+    (
+        OrderErrorKind::InsufficientFunds,
+        &[
+            "insufficient",
+            "not enough credit",
+            "موجودی کافی",
+            "اعتبار کافی",
+            "قدرت خرید",
+            "عدم کفایت",
+            "کسری",
+        ],
+    ),
+    (
+        OrderErrorKind::PriceOutOfRange,
+        &[
+            "price out of range",
+            "price range",
+            "price limit",
+            "آستانه",
+            "محدوده قیمت",
+            "دامنه مجاز",
+            "خارج از محدوده",
+        ],
+    ),
+    (
+        OrderErrorKind::MarketClosed,
+        &[
+            "market closed",
+            "market is closed",
+            "outside trading hours",
+            "symbol is closed",
+            "بازار بسته",
+            "بازار هنوز باز نشده",
+            "نماد بسته",
+            "خارج از ساعت",
+            "خارج از زمان",
+            "ساعت معاملاتی",
+            "نماد ممنوع",
+        ],
+    ),
+    (
+        OrderErrorKind::RateLimited,
+        &[
+            "too many requests",
+            "rate limit",
+            "throttl",
+            "تعداد درخواست",
+            "درخواست های زیاد",
+        ],
+    ),
+    (
+        OrderErrorKind::AuthExpired,
+        &[
+            "unauthorized",
+            "token expired",
+            "session expired",
+            "invalid token",
+            "احراز هویت",
+            "نشست شما",
+            "مجددا وارد",
+            "دوباره وارد",
+        ],
+    ),
+];
+
+impl OrderErrorKind {
+    pub fn classify(status: StatusCode, body: &str) -> Self {
+        let body = normalize(body);
+        for (kind, phrases) in BODY_RULES {
+            if phrases.iter().any(|phrase| body.contains(phrase)) {
+                return *kind;
+            }
+        }
+
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => OrderErrorKind::AuthExpired,
+            StatusCode::TOO_MANY_REQUESTS => OrderErrorKind::RateLimited,
+            StatusCode::CONFLICT => OrderErrorKind::DuplicateOrder,
+            _ => OrderErrorKind::Unknown,
+        }
+    }
+}
+
+fn normalize(body: &str) -> String {
+    persian::strip_thousands_separators(body)
+        .replace('\u{200c}', " ")
+        .to_lowercase()
+}
+
+impl fmt::Display for OrderErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// A broker rejected an order. Returned by every `send_order` so callers can
+/// downcast with [`order_error_kind`] and react per kind.
+#[derive(Debug)]
+pub struct OrderError {
+    pub kind: OrderErrorKind,
+    pub status: StatusCode,
+    pub body: String,
+    /// The broker's typed answer, when its body was understood.
+    pub response: Option<OrderResponse>,
+}
+
+impl OrderError {
+    pub fn new(status: StatusCode, body: String) -> Self {
+        Self {
+            kind: OrderErrorKind::classify(status, &body),
+            status,
+            body,
+            response: None,
+        }
+    }
+
+    /// A rejection the broker's typed `response` explains: an expired
+    /// session is [`OrderErrorKind::AuthExpired`], a validation error is
+    /// classified by its message alone.
+    pub fn from_response(status: StatusCode, body: String, response: OrderResponse) -> Self {
+        let kind = match &response {
+            OrderResponse::AuthError { .. } => OrderErrorKind::AuthExpired,
+            OrderResponse::ValidationError { message } if !message.is_empty() => {
+                OrderErrorKind::classify(status, message)
+            }
+            _ => OrderErrorKind::classify(status, &body),
+        };
+        Self {
+            kind,
+            status,
+            body,
+            response: Some(response),
+        }
+    }
+}
+
+impl OrderError {
+    /// Whether the body contains one of `phrases`, matched like the built-in
+    /// ones.
+    pub fn mentions(&self, phrases: &[String]) -> bool {
+        let body = normalize(&self.body);
+        phrases
+            .iter()
+            .any(|phrase| body.contains(&normalize(phrase)))
+    }
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Order failed with status {} ({}): {}",
+            self.status, self.kind, self.body
+        )
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+/// The kind of the first [`OrderError`] in `error`'s chain, if any.
+pub fn order_error_kind(error: &anyhow::Error) -> Option<OrderErrorKind> {
+    error
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<OrderError>())
+        .map(|order_error| order_error.kind)
+}
@@ -1,5 +1,18 @@
-use crate::calibration::{self, CalibrationConfig};
-use crate::rate_limiter::RateLimiter;
+use crate::accounts;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::errors::{OrderErrorKind, order_error_kind};
+use crate::exir_login::{self, ExirCredentials, ExirLoginConfig, ExirSession};
+use crate::exir_presets;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::ExirOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
 use anyhow::{Context, Result};
 use chrono::{Timelike, Utc};
 use reqwest::StatusCode;
@@ -8,20 +21,13 @@ use reqwest::header::{
     REFERER, USER_AGENT,
 };
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::time::Instant;
 
 fn default_user_agent() -> String {
     "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36".to_string()
 }
 
-fn default_batch_delay() -> u64 {
-    100
-}
-
-fn default_batch_repeat() -> usize {
-    1
-}
-
 #[derive(Debug, Deserialize, Clone)]
 pub struct ExirBrokersConfig {
     pub brokers: Vec<ExirBrokerConfig>,
@@ -30,27 +36,85 @@ pub struct ExirBrokersConfig {
 #[derive(Debug, Deserialize, Clone)]
 pub struct ExirBrokerConfig {
     pub name: String,
+    /// May be left empty when `login` is configured.
+    #[serde(default)]
     pub cookie: String,
+    #[serde(default)]
     pub nt: String,
     #[serde(default = "default_user_agent")]
     pub user_agent: String,
+    /// A known exirbroker.com subdomain, as in `"arzeshafarin"`, filling in
+    /// whichever of `order_url`, `origin` and `referer` are left out.
+    #[serde(default)]
+    pub preset: Option<String>,
+    #[serde(default)]
     pub order_url: String,
+    #[serde(default)]
     pub origin: String,
+    #[serde(default)]
     pub referer: String,
-    pub orders: Vec<ExirOrderData>,
-    #[serde(default = "default_batch_delay")]
-    pub batch_delay_ms: u64,
-    #[serde(default = "default_batch_repeat")]
-    pub batch_repeat: usize,
+    pub orders: Vec<OrderEntry<ExirOrderData>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
     #[serde(default)]
-    pub target_time: Option<String>,
+    pub login: Option<ExirLoginConfig>,
+    #[serde(skip)]
+    pub session: ExirSession,
+    /// One copy of this broker per entry of `accounts`, each with the
+    /// account's own credentials.
     #[serde(default)]
-    pub calibration: Option<CalibrationConfig>,
+    pub accounts: Vec<ExirBrokerConfig>,
+}
+
+impl ExirBrokerConfig {
+    /// Fill the URLs left out from `preset`; fails when one is still
+    /// missing.
+    pub fn apply_preset(&mut self) -> Result<()> {
+        if let Some(name) = &self.preset {
+            let preset = exir_presets::find(name)
+                .with_context(|| format!("Invalid 'preset' for {}", self.name))?;
+            if self.order_url.is_empty() {
+                self.order_url = preset.order_url();
+            }
+            if self.origin.is_empty() {
+                self.origin = preset.origin();
+            }
+            if self.referer.is_empty() {
+                self.referer = preset.referer();
+            }
+        }
+        if self.order_url.is_empty() || self.origin.is_empty() || self.referer.is_empty() {
+            anyhow::bail!(
+                "{} needs 'order_url', 'origin' and 'referer', or a 'preset'",
+                self.name
+            );
+        }
+        for account in &mut self.accounts {
+            account.apply_preset()?;
+        }
+        Ok(())
+    }
+
+    /// The session from the latest login, or the configured cookie and `nt`.
+    pub fn credentials(&self) -> ExirCredentials {
+        self.session.get().unwrap_or_else(|| ExirCredentials {
+            cookie: self.cookie.clone(),
+            nt: self.nt.clone(),
+        })
+    }
+
+    fn needs_login(&self) -> bool {
+        let credentials = self.credentials();
+        credentials.cookie.is_empty()
+            || credentials.cookie == "PASTE_YOUR_COOKIE_HERE"
+            || credentials.nt.is_empty()
+            || credentials.nt == "PASTE_YOUR_NT_TOKEN_HERE"
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct ExirOrderData {
-    #[serde(rename = "insMaxLcode")]
+    #[serde(default, rename = "insMaxLcode")]
     pub ins_max_lcode: String,
     #[serde(rename = "bankAccountId")]
     pub bank_account_id: i64,
@@ -69,28 +133,205 @@ pub struct ExirOrderData {
     pub has_under_caution_agreement: bool,
     #[serde(rename = "dividedOrder")]
     pub divided_order: bool,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
 }
 
 pub fn load_config(path: &str) -> Result<ExirBrokersConfig> {
-    let config_str =
-        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
-    let config: ExirBrokersConfig =
-        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+    let config_str = encryption::read_config(path)?;
+    let mut config: ExirBrokersConfig = accounts::parse_config(path, &config_str)?;
+    for broker in &mut config.brokers {
+        broker
+            .apply_preset()
+            .with_context(|| format!("Failed to load {}", path))?;
+        resolve_secrets(broker)?;
+        for account in &mut broker.accounts {
+            resolve_secrets(account)?;
+        }
+    }
     Ok(config)
 }
 
+fn resolve_secrets(broker: &mut ExirBrokerConfig) -> Result<()> {
+    secrets::resolve(&mut broker.cookie)?;
+    secrets::resolve(&mut broker.nt)?;
+    if let Some(login) = &mut broker.login {
+        secrets::resolve(&mut login.password)?;
+        if let Some(totp) = &mut login.totp {
+            secrets::resolve(&mut totp.secret)?;
+        }
+    }
+    Ok(())
+}
+
 pub fn find_broker<'a>(config: &'a ExirBrokersConfig, name: &str) -> Option<&'a ExirBrokerConfig> {
-    config
-        .brokers
-        .iter()
-        .find(|broker| broker.name.eq_ignore_ascii_case(name))
+    accounts::find(&config.brokers, name)
 }
 
+impl OrderFields for ExirOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.ins_max_lcode)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.ins_max_lcode = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn price(&self) -> Option<f64> {
+        Some(self.price as f64)
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.price = price.round() as i64;
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        u64::try_from(self.quantity).ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.quantity = quantity as i64;
+    }
+
+    fn side(&self) -> OrderSide {
+        if self.side == "SIDE_SELL" {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }
+    }
+}
+
+impl Broker for ExirBrokerConfig {
+    type Order = ExirOrderData;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn config_file(&self) -> &str {
+        "config_exir.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<ExirOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<ExirOrderData>> {
+        &mut self.orders
+    }
+
+    fn accounts(&self) -> &[Self] {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self] {
+        &mut self.accounts
+    }
+
+    async fn ensure_session(&self) -> Result<()> {
+        if self.login.is_some() && self.needs_login() {
+            exir_login::relogin(self, self.session.generation()).await?;
+        }
+        Ok(())
+    }
+
+    async fn relogin(&self) -> Result<bool> {
+        if !self.login.as_ref().is_some_and(|login| login.relogin) {
+            return Ok(false);
+        }
+        exir_login::relogin(self, self.session.generation()).await?;
+        Ok(true)
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        let credentials = self.credentials();
+        if credentials.cookie.is_empty() {
+            anyhow::bail!(
+                "Cookie is required for {}. Please set 'cookie' or 'login' in config_exir.json",
+                self.name
+            );
+        }
+
+        println!("[{}] Using Cookie authentication", self.name);
+        println!(
+            "[{}] Cookie preview: {}...",
+            self.name,
+            &credentials.cookie[..credentials.cookie.len().min(50)]
+        );
+        Ok(())
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        let credentials = self.credentials();
+        build_order_headers(
+            self,
+            &credentials.cookie,
+            &calculate_x_app_n(&credentials.nt, &self.order_url),
+            order_json,
+        )
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(self, client)
+    }
+}
+
+/// X-App-N for `url`: UTC seconds of two seconds ago, over the full path
+/// and query.
 pub fn calculate_x_app_n(nt: &str, url: &str) -> String {
-    let now = Utc::now() - chrono::Duration::seconds(2);
-    let utc_seconds: i64 = (3600 * now.hour() + 60 * now.minute() + now.second()) as i64;
+    x_app_n_at(
+        nt,
+        url_path(url),
+        utc_seconds(Utc::now() - chrono::Duration::seconds(2)),
+    )
+}
 
-    let url_path = if let Some(pos) = url.find("://") {
+fn utc_seconds(now: chrono::DateTime<Utc>) -> i64 {
+    (3600 * now.hour() + 60 * now.minute() + now.second()) as i64
+}
+
+fn url_path(url: &str) -> &str {
+    if let Some(pos) = url.find("://") {
         if let Some(path_start) = url[pos + 3..].find('/') {
             &url[pos + 3 + path_start..]
         } else {
@@ -98,8 +339,12 @@ pub fn calculate_x_app_n(nt: &str, url: &str) -> String {
         }
     } else {
         url
-    };
+    }
+}
 
+/// `<first>.<second>`: `second` is `utc_seconds` times the sum of the path's
+/// characters, `first` scales it by five digits of `nt` picked by the clock.
+pub fn x_app_n_at(nt: &str, url_path: &str, utc_seconds: i64) -> String {
     let url_char_sum: i64 = url_path.chars().map(|c| c as i64).sum();
 
     let l = if nt.len() > 2 { &nt[2..] } else { nt };
@@ -109,7 +354,7 @@ pub fn calculate_x_app_n(nt: &str, url: &str) -> String {
 
     let l_len = l.len() as i64;
     let pos = if l_len > 5 {
-        (utc_seconds % (l_len - 5) - offset).abs() as usize
+        (utc_seconds % (l_len - 5) - offset).unsigned_abs() as usize
     } else {
         0
     };
@@ -125,6 +370,49 @@ pub fn calculate_x_app_n(nt: &str, url: &str) -> String {
     format!("{}.{}", first_part, second_part)
 }
 
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(
+    broker: &ExirBrokerConfig,
+    cookie: &str,
+    x_app_n: &str,
+    order_json: &str,
+) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/plain, */*"),
+    );
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("en-US,en;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(REFERER, HeaderValue::from_str(&broker.referer)?);
+    headers.insert("X-App-N", HeaderValue::from_str(x_app_n)?);
+    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    headers.insert(COOKIE, HeaderValue::from_str(cookie)?);
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
+    headers.insert("Priority", HeaderValue::from_static("u=0"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// Build the request for `order_json` and send it.
 pub async fn send_order(
     broker: &ExirBrokerConfig,
     order_json: &str,
@@ -132,12 +420,55 @@ pub async fn send_order(
     curl_only: bool,
     rate_limiter: Option<&RateLimiter>,
 ) -> Result<()> {
-    let client = reqwest::Client::new();
+    let request = PreparedRequest::new(
+        &broker.order_url,
+        broker.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(broker, &request, test_mode, curl_only, rate_limiter).await
+}
 
-    let x_app_n = calculate_x_app_n(&broker.nt, &broker.order_url);
-    println!("[{}] Generated X-App-N: {}", broker.name, x_app_n);
+/// Send a request built by [`Broker::prepare`]. With `login.relogin`, an
+/// expired session triggers a fresh login and a single retry.
+pub async fn send_request(
+    broker: &ExirBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let generation = broker.session.generation();
+    let result = send_request_once(broker, request, test_mode, curl_only, rate_limiter).await;
+    let relogin = broker.login.as_ref().is_some_and(|login| login.relogin);
+    match result {
+        Err(e) if relogin && order_error_kind(&e) == Some(OrderErrorKind::AuthExpired) => {
+            println!(
+                "[{}] Session expired; logging in again and retrying",
+                broker.name
+            );
+            exir_login::relogin(broker, generation)
+                .await
+                .with_context(|| format!("Re-login failed after: {}", e))?;
+            send_request_once(broker, request, test_mode, curl_only, rate_limiter).await
+        }
+        result => result,
+    }
+}
 
-    if test_mode {
+async fn send_request_once(
+    broker: &ExirBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = broker.settings.client();
+
+    let credentials = broker.credentials();
+    let x_app_n = calculate_x_app_n(&credentials.nt, &broker.order_url);
+
+    if test_mode || curl_only {
         println!("[{}] Equivalent curl command:", broker.name);
         println!(
             r#"curl '{}' \
@@ -165,7 +496,7 @@ pub async fn send_order(
             broker.referer,
             x_app_n,
             broker.origin,
-            broker.cookie,
+            credentials.cookie,
             order_json
         );
         println!();
@@ -175,55 +506,41 @@ pub async fn send_order(
         }
     }
 
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
-    headers.insert(
-        ACCEPT,
-        HeaderValue::from_static("application/json, text/plain, */*"),
-    );
-    headers.insert(
-        "Accept-Language",
-        HeaderValue::from_static("en-US,en;q=0.5"),
-    );
-    headers.insert(
-        ACCEPT_ENCODING,
-        HeaderValue::from_static("gzip, deflate, br, zstd"),
-    );
-    headers.insert(REFERER, HeaderValue::from_str(&broker.referer)?);
+    // X-App-N depends on the clock and the cookie changes on a re-login.
+    let (url, mut headers) = request.parts();
     headers.insert("X-App-N", HeaderValue::from_str(&x_app_n)?);
-    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
-    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
-    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
-    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
-    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
-    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
-    headers.insert("Priority", HeaderValue::from_static("u=0"));
-    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
-    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    headers.insert(COOKIE, HeaderValue::from_str(&credentials.cookie)?);
 
+    let mut timing = console::Timing::start();
     if let Some(limiter) = rate_limiter {
-        limiter.wait().await;
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
     }
-
-    let body_bytes = order_json.as_bytes();
-
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    headers.insert(
-        CONTENT_LENGTH,
-        HeaderValue::from_str(&body_bytes.len().to_string())?,
-    );
-
-    println!("[{}] Sending order JSON: {}", broker.name, order_json);
-
-    let response = client
-        .post(&broker.order_url)
-        .headers(headers)
-        .body(order_json.to_string())
-        .send()
-        .await?;
+    timing.mark("rate limit");
+
+    console::debug_headers(&broker.name, "Request", &headers);
+    let mut capture = Capture::start(&broker.name, &url, &headers, &request.body);
+
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[{}] Generated X-App-N: {}", broker.name, x_app_n);
+    println!("[{}] Sent order JSON: {}", broker.name, order_json);
+    let response = capture.sent(response)?;
 
     let status = response.status();
+    console::debug_headers(&broker.name, "Response", response.headers());
+    capture.response_head(status, response.headers());
     let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
 
     let decoded_text = if response_text.contains("\\u") {
         crate::decode_unicode_escapes(&response_text)
@@ -233,12 +550,16 @@ pub async fn send_order(
 
     println!("[{}] Order response status: {}", broker.name, status);
     println!("[{}] Order response body: {}", broker.name, decoded_text);
+    timing.report(&broker.name);
 
-    if !status.is_success() {
-        anyhow::bail!("Order failed with status {}: {}", status, decoded_text);
+    if let Some(file) = &broker.settings.response_schema_file {
+        schema_drift::check(&broker.name, file, status, &decoded_text);
     }
-
-    Ok(())
+    success::check_response_as::<ExirOrderResponse>(
+        &broker.settings.success_rules,
+        status,
+        decoded_text,
+    )
 }
 
 pub async fn run_calibration(
@@ -247,6 +568,7 @@ pub async fn run_calibration(
     rate_limiter: &RateLimiter,
 ) -> Result<calibration::CalibrationSummary> {
     let calibration = broker
+        .settings
         .calibration
         .as_ref()
         .context("Calibration config missing")?;
@@ -264,11 +586,12 @@ async fn send_probe(
 ) -> Result<(u64, u128, StatusCode)> {
     let t0 = Instant::now();
 
+    let credentials = broker.credentials();
     let mut headers = HeaderMap::new();
     headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
     headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
-    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
-    headers.insert("nt", HeaderValue::from_str(&broker.nt)?);
+    headers.insert(COOKIE, HeaderValue::from_str(&credentials.cookie)?);
+    headers.insert("nt", HeaderValue::from_str(&credentials.nt)?);
 
     let base_url = calibration::probe_url(&broker.order_url)?;
     let response = client.head(base_url).headers(headers).send().await?;
@@ -280,3 +603,94 @@ async fn send_probe(
 
     Ok((rtt_ms, rtt_micros, status))
 }
+
+/// Whether the broker accepted a request signed with the X-App-N header,
+/// and its status; `None` when the request itself failed.
+pub struct XAppNCheck {
+    pub status: Option<StatusCode>,
+}
+
+impl XAppNCheck {
+    /// Only a 2xx answer shows the header passed; a 404 or 405 from a
+    /// wrong endpoint proves nothing.
+    pub fn accepted(&self) -> bool {
+        self.status.is_some_and(|status| status.is_success())
+    }
+}
+
+/// `verify-xappn`: GET `auth_check_url` with the session and a fresh
+/// X-App-N. The order URL only takes POSTs, so the endpoint must be set
+/// explicitly.
+pub async fn verify_x_app_n(
+    broker: &ExirBrokerConfig,
+    client: &reqwest::Client,
+) -> Result<XAppNCheck> {
+    let credentials = broker.credentials();
+    let url = broker.settings.auth_check_url.as_deref().with_context(|| {
+        format!(
+            "verify-xappn needs an auth_check_url for {}: an endpoint that answers a GET with the session, such as the portfolio",
+            broker.name
+        )
+    })?;
+    let x_app_n = calculate_x_app_n(&credentials.nt, url);
+    let mut headers = build_order_headers(broker, &credentials.cookie, &x_app_n, "")?;
+    headers.remove(CONTENT_TYPE);
+    headers.remove(CONTENT_LENGTH);
+    let status = match client.get(url).headers(headers).send().await {
+        Ok(response) => Some(response.status()),
+        Err(e) => {
+            eprintln!("[{}] X-App-N request failed: {}", broker.name, e);
+            None
+        }
+    };
+    Ok(XAppNCheck { status })
+}
+
+/// Run `verify-xappn`: check `names`, or every broker in `config_exir.json`,
+/// and fail unless each accepts the X-App-N header.
+pub async fn run_verify_x_app_n(names: &[String]) -> Result<()> {
+    let config = load_config("config_exir.json")?;
+    let brokers: Vec<&ExirBrokerConfig> = if names.is_empty() {
+        config.brokers.iter().collect()
+    } else {
+        names
+            .iter()
+            .map(|name| {
+                find_broker(&config, name)
+                    .with_context(|| format!("No broker named '{}' in config_exir.json", name))
+            })
+            .collect::<Result<_>>()?
+    };
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()?;
+
+    let mut failed = Vec::new();
+    for broker in brokers.into_iter().flat_map(accounts::senders) {
+        broker.ensure_session().await?;
+        let check = verify_x_app_n(broker, &client).await?;
+        println!(
+            "[{}] X-App-N: {} ({})",
+            broker.name,
+            if check.accepted() {
+                "accepted"
+            } else {
+                "rejected"
+            },
+            check
+                .status
+                .map_or("no response".to_string(), |status| status.to_string())
+        );
+        if !check.accepted() {
+            failed.push(broker.name.as_str());
+        }
+    }
+    if !failed.is_empty() {
+        anyhow::bail!(
+            "X-App-N rejected for: {}; check the session with auth-check, or the front-end may have changed how it computes the header",
+            failed.join(", ")
+        );
+    }
+    println!("[Exir] X-App-N accepted.");
+    Ok(())
+}
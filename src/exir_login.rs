@@ -0,0 +1,309 @@
+use crate::captcha::{CaptchaConfig, CaptchaSolver, ConfiguredSolver};
+use crate::exir_broker::{self, ExirBrokerConfig};
+use crate::login;
+use crate::runner::Broker;
+use crate::totp::TotpConfig;
+use anyhow::{Context, Result};
+use reqwest::header::{ACCEPT, COOKIE, HeaderValue, ORIGIN, REFERER, USER_AGENT};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+fn default_login_path() -> String {
+    "/api/v1/login".to_string()
+}
+
+fn default_user_info_path() -> String {
+    "/api/v1/user/info".to_string()
+}
+
+fn default_nt_pointer() -> String {
+    "/nt".to_string()
+}
+
+fn default_captcha_field() -> String {
+    "captcha".to_string()
+}
+
+fn default_otp_field() -> String {
+    "otp".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `login` section of an entry in `config_exir.json`. Paths are relative to
+/// the broker's `origin`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ExirLoginConfig {
+    pub username: String,
+    /// Prompted for on the console when empty.
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "default_login_path")]
+    pub login_path: String,
+    #[serde(default = "default_user_info_path")]
+    pub user_info_path: String,
+    /// JSON pointer to `nt` in the userInfo response.
+    #[serde(default = "default_nt_pointer")]
+    pub nt_pointer: String,
+    #[serde(default)]
+    pub captcha_path: Option<String>,
+    #[serde(default = "default_captcha_field")]
+    pub captcha_field: String,
+    #[serde(default)]
+    pub captcha: CaptchaConfig,
+    /// Sends a TOTP code in `otp_field` with every login, for accounts with
+    /// two-factor login.
+    #[serde(default)]
+    pub totp: Option<TotpConfig>,
+    #[serde(default = "default_otp_field")]
+    pub otp_field: String,
+    /// Log in again and retry once when an order fails with an expired
+    /// session.
+    #[serde(default = "default_true")]
+    pub relogin: bool,
+    /// Write each new cookie and `nt` back to `config_exir.json`.
+    #[serde(default = "default_true")]
+    pub save_session: bool,
+}
+
+/// The two values an Exir order needs: the session cookie and `nt`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExirCredentials {
+    pub cookie: String,
+    pub nt: String,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    current: RwLock<Option<ExirCredentials>>,
+    generation: AtomicU64,
+    login_lock: tokio::sync::Mutex<()>,
+}
+
+/// Credentials obtained at runtime, shared by every clone of a broker config
+/// so all in-flight orders switch to a new session together.
+#[derive(Debug, Clone, Default)]
+pub struct ExirSession(Arc<SessionState>);
+
+impl ExirSession {
+    pub fn get(&self) -> Option<ExirCredentials> {
+        self.0
+            .current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Incremented on every login; lets concurrent failures detect that
+    /// another task already refreshed the session.
+    pub fn generation(&self) -> u64 {
+        self.0.generation.load(Ordering::Acquire)
+    }
+
+    fn set(&self, credentials: ExirCredentials) {
+        *self
+            .0
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(credentials);
+        self.0.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Run `login <name>` for an Exir broker: authenticate and store the new
+/// cookie and `nt` in the config file.
+pub async fn run_login(config_path: &str, name: &str) -> Result<()> {
+    let config = exir_broker::load_config(config_path)?;
+    let broker = exir_broker::find_broker(&config, name)
+        .with_context(|| format!("Broker '{}' not found in {}", name, config_path))?;
+    let login_config = broker.login.as_ref().with_context(|| {
+        format!(
+            "No 'login' section for {} in {}; add username (and optionally password)",
+            broker.name, config_path
+        )
+    })?;
+
+    let credentials = login_with_prompt(broker, login_config).await?;
+    save_credentials(config_path, &broker.name, &credentials)?;
+    println!(
+        "[{}] Saved new cookie and nt to {}",
+        broker.name, config_path
+    );
+    Ok(())
+}
+
+/// Log in again unless another task already did since `seen_generation`,
+/// and make the new session the one every later order uses.
+pub async fn relogin(broker: &ExirBrokerConfig, seen_generation: u64) -> Result<()> {
+    let login_config = broker
+        .login
+        .as_ref()
+        .with_context(|| format!("No 'login' section for {}", broker.name))?;
+
+    let _guard = broker.session.0.login_lock.lock().await;
+    if broker.session.generation() != seen_generation {
+        return Ok(());
+    }
+
+    let credentials = login_with_prompt(broker, login_config).await?;
+    broker.session.set(credentials.clone());
+    if login_config.save_session {
+        match save_credentials(broker.config_file(), &broker.name, &credentials) {
+            Ok(()) => println!(
+                "[{}] Saved new session to {}",
+                broker.name,
+                broker.config_file()
+            ),
+            Err(e) => summary!(
+                "[{}] Warning: could not save new session: {:#}",
+                broker.name,
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+async fn login_with_prompt(
+    broker: &ExirBrokerConfig,
+    login_config: &ExirLoginConfig,
+) -> Result<ExirCredentials> {
+    let mut login_config = login_config.clone();
+    if login_config.password.is_empty() {
+        login_config.password =
+            login::prompt_line(&format!("[{}] Password: ", broker.name)).await?;
+    }
+
+    println!(
+        "[{}] Logging in as {} via {}{}",
+        broker.name, login_config.username, broker.origin, login_config.login_path
+    );
+    let solver = ConfiguredSolver::from_config(&login_config.captcha)?;
+    let credentials = login(
+        &login_config,
+        &broker.origin,
+        &broker.referer,
+        &broker.user_agent,
+        &solver,
+    )
+    .await?;
+    println!(
+        "[{}] Logged in; nt={}...",
+        broker.name,
+        &credentials.nt[..credentials.nt.len().min(8)]
+    );
+    Ok(credentials)
+}
+
+/// Log in to an exirbroker.com site and read `nt` from userInfo.
+pub async fn login(
+    config: &ExirLoginConfig,
+    origin: &str,
+    referer: &str,
+    user_agent: &str,
+    solver: &impl CaptchaSolver,
+) -> Result<ExirCredentials> {
+    let client = reqwest::Client::new();
+    let origin = origin.trim_end_matches('/');
+
+    let mut body = Map::new();
+    body.insert(
+        "username".to_string(),
+        Value::String(config.username.clone()),
+    );
+    body.insert(
+        "password".to_string(),
+        Value::String(config.password.clone()),
+    );
+
+    let mut cookie = String::new();
+    if let Some(captcha_path) = &config.captcha_path {
+        let captcha_url = format!("{}{}", origin, captcha_path);
+        let (captcha, captcha_cookie) =
+            login::fetch_captcha(&client, &captcha_url, user_agent).await?;
+        cookie = captcha_cookie;
+        let answer = solver.solve(&captcha).await?;
+        body.insert(config.captcha_field.clone(), Value::String(answer));
+    }
+    if let Some(totp) = &config.totp {
+        body.insert(
+            config.otp_field.clone(),
+            Value::String(totp.fresh_code().await?),
+        );
+    }
+
+    let mut request = client
+        .post(format!("{}{}", origin, config.login_path))
+        .header(USER_AGENT, HeaderValue::from_str(user_agent)?)
+        .header(ORIGIN, HeaderValue::from_str(origin)?)
+        .header(REFERER, HeaderValue::from_str(referer)?)
+        .json(&body);
+    if !cookie.is_empty() {
+        request = request.header(COOKIE, HeaderValue::from_str(&cookie)?);
+    }
+    let response = request.send().await.context("Exir login request failed")?;
+    let status = response.status();
+    let session_cookie = login::cookies_from_response(&response);
+    let response_text = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "Exir login failed with status {}: {}",
+            status,
+            crate::decode_unicode_escapes(&response_text)
+        );
+    }
+    if session_cookie.is_empty() {
+        anyhow::bail!(
+            "Exir login succeeded but set no cookie: {}",
+            crate::decode_unicode_escapes(&response_text)
+        );
+    }
+    let cookie = login::merge_cookies(&cookie, &session_cookie);
+
+    let response = client
+        .get(format!("{}{}", origin, config.user_info_path))
+        .header(USER_AGENT, HeaderValue::from_str(user_agent)?)
+        .header(ACCEPT, HeaderValue::from_static("application/json"))
+        .header(REFERER, HeaderValue::from_str(referer)?)
+        .header(COOKIE, HeaderValue::from_str(&cookie)?)
+        .send()
+        .await
+        .context("Exir userInfo request failed")?;
+    let status = response.status();
+    let response_text = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "Exir userInfo failed with status {}: {}",
+            status,
+            response_text
+        );
+    }
+    let user_info: Value = serde_json::from_str(&response_text)
+        .with_context(|| format!("userInfo returned invalid JSON: {}", response_text))?;
+    let nt = match user_info.pointer(&config.nt_pointer) {
+        Some(Value::String(nt)) => nt.clone(),
+        Some(Value::Number(nt)) => nt.to_string(),
+        _ => anyhow::bail!(
+            "userInfo has no nt at {}: {}",
+            config.nt_pointer,
+            response_text
+        ),
+    };
+
+    Ok(ExirCredentials { cookie, nt })
+}
+
+fn save_credentials(config_path: &str, name: &str, credentials: &ExirCredentials) -> Result<()> {
+    login::update_config_fields(
+        config_path,
+        Some(name),
+        &[
+            ("cookie", Value::String(credentials.cookie.clone())),
+            ("nt", Value::String(credentials.nt.clone())),
+        ],
+    )
+}
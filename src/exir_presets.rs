@@ -0,0 +1,63 @@
+use anyhow::Result;
+
+/// A broker on exirbroker.com, known by its subdomain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExirPreset {
+    pub name: &'static str,
+    /// The broker's own name, for listings.
+    pub broker: &'static str,
+}
+
+/// Brokers whose Exir front-end has been checked to live at
+/// `https://<name>.exirbroker.com`.
+pub const PRESETS: &[ExirPreset] = &[
+    ExirPreset {
+        name: "arzeshafarin",
+        broker: "Arzesh Afarin (Alvand)",
+    },
+    ExirPreset {
+        name: "artan",
+        broker: "Artan Sarmaye",
+    },
+    ExirPreset {
+        name: "charisma",
+        broker: "Charisma",
+    },
+    ExirPreset {
+        name: "nibbourse",
+        broker: "Novin Eghtesad",
+    },
+];
+
+impl ExirPreset {
+    pub fn origin(&self) -> String {
+        format!("https://{}.exirbroker.com", self.name)
+    }
+
+    pub fn order_url(&self) -> String {
+        format!("{}/api/v1/order", self.origin())
+    }
+
+    pub fn referer(&self) -> String {
+        format!("{}/exir/mainNew", self.origin())
+    }
+}
+
+/// The preset called `name`, ignoring case.
+pub fn find(name: &str) -> Result<&'static ExirPreset> {
+    match PRESETS
+        .iter()
+        .find(|preset| preset.name.eq_ignore_ascii_case(name))
+    {
+        Some(preset) => Ok(preset),
+        None => anyhow::bail!(
+            "Unknown Exir preset '{}'; known presets: {}",
+            name,
+            PRESETS
+                .iter()
+                .map(|preset| preset.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
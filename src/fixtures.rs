@@ -0,0 +1,285 @@
+//! Record and replay broker exchanges, to work on a broker module without a
+//! valid session. `--record-fixtures DIR` stores every order request and
+//! its response as a JSON fixture, with credential headers and secret-looking
+//! body fields stripped; `sarkhati replay DIR` serves them back on a local
+//! port, so a config whose `order_url` points there gets the recorded
+//! responses.
+
+use crate::capture::{self, is_credential_header};
+use anyhow::{Context, Result};
+use axum::Router;
+use axum::extract::State;
+use axum::http::{HeaderValue, Method, Uri};
+use axum::response::{IntoResponse, Response};
+use chrono::DateTime;
+use chrono_tz::Tz;
+use reqwest::header::{CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, HeaderMap, TRANSFER_ENCODING};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Stands in for every value left out of a fixture.
+const REDACTED: &str = "<redacted>";
+
+/// Body fields whose values are left out, matched within the field name.
+const SECRET_FIELDS: [&str; 7] = [
+    "password", "token", "secret", "otp", "captcha", "session", "cookie",
+];
+
+/// Port `sarkhati replay` listens on without `--port`.
+pub const DEFAULT_REPLAY_PORT: u16 = 8089;
+
+static RECORD_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// One recorded request and the response it got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub broker: String,
+    pub method: String,
+    /// Path and query, without the host.
+    pub path: String,
+    pub request_headers: BTreeMap<String, String>,
+    pub request_body: String,
+    pub status: u16,
+    pub response_headers: BTreeMap<String, String>,
+    pub response_body: String,
+}
+
+impl Fixture {
+    /// The sanitized fixture of an order `name` posted to `url`.
+    pub fn new(
+        name: &str,
+        url: &Url,
+        headers: &HeaderMap,
+        body: &[u8],
+        status: StatusCode,
+        response_headers: &HeaderMap,
+        response_body: &str,
+    ) -> Self {
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_string(),
+        };
+        Self {
+            broker: name.to_string(),
+            method: "POST".to_string(),
+            path,
+            request_headers: sanitize_headers(headers),
+            request_body: sanitize_body(&String::from_utf8_lossy(body)),
+            status: status.as_u16(),
+            response_headers: sanitize_headers(response_headers),
+            response_body: sanitize_body(response_body),
+        }
+    }
+}
+
+/// Record every order exchange from now on into `dir`, created if missing.
+pub fn set_record_dir(dir: &str) -> Result<()> {
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create fixture directory {}", dir))?;
+    if RECORD_DIR.set(PathBuf::from(dir)).is_err() {
+        anyhow::bail!("The fixture directory is already set");
+    }
+    Ok(())
+}
+
+pub fn recording() -> bool {
+    RECORD_DIR.get().is_some()
+}
+
+/// Store `fixture` of a request sent at `sent_at`; a failure is only
+/// reported.
+pub fn record(fixture: &Fixture, sent_at: &DateTime<Tz>) {
+    let Some(dir) = RECORD_DIR.get() else {
+        return;
+    };
+    let path = dir.join(format!(
+        "{}.json",
+        capture::file_stem(&fixture.broker, sent_at)
+    ));
+    let written = serde_json::to_string_pretty(fixture)
+        .map_err(anyhow::Error::from)
+        .and_then(|json| std::fs::write(&path, json).map_err(Into::into));
+    if let Err(e) = written {
+        eprintln!(
+            "[{}] Warning: could not write fixture {}: {}",
+            fixture.broker,
+            path.display(),
+            e
+        );
+    }
+}
+
+fn sanitize_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if is_credential_header(name) {
+                REDACTED
+            } else {
+                value.to_str().unwrap_or(REDACTED)
+            };
+            (name.to_string(), value.to_string())
+        })
+        .collect()
+}
+
+/// `body` with the values of secret-looking JSON fields replaced; bodies
+/// that are not JSON are kept as they are.
+pub fn sanitize_body(body: &str) -> String {
+    let Ok(mut value) = serde_json::from_str::<Value>(body) else {
+        return body.to_string();
+    };
+    redact_secrets(&mut value);
+    value.to_string()
+}
+
+fn redact_secrets(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                let key = key.to_lowercase();
+                if SECRET_FIELDS.iter().any(|secret| key.contains(secret)) {
+                    *field = Value::String(REDACTED.to_string());
+                } else {
+                    redact_secrets(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_secrets),
+        _ => {}
+    }
+}
+
+/// Every fixture in `dir`, in the order they were recorded.
+pub fn load(dir: &str) -> Result<Vec<Fixture>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read fixture directory {}", dir))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    // File names start with the time of the request.
+    paths.sort();
+    paths.iter().map(|path| load_fixture(path)).collect()
+}
+
+fn load_fixture(path: &Path) -> Result<Fixture> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Invalid fixture {}", path.display()))
+}
+
+struct Replay {
+    fixtures: Vec<Fixture>,
+    /// How many times each method and path was answered, to go through its
+    /// fixtures in turn.
+    served: Mutex<HashMap<(String, String), usize>>,
+}
+
+/// Serve `fixtures` on `port` of localhost, 0 for any free one: each
+/// request gets the next recorded response for its method and path (the
+/// query included when one was recorded with it), starting over after the
+/// last.
+pub async fn serve(fixtures: Vec<Fixture>, port: u16) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .with_context(|| format!("Failed to listen on replay port {}", port))?;
+    let address = listener.local_addr()?;
+    let replay = Arc::new(Replay {
+        fixtures,
+        served: Mutex::new(HashMap::new()),
+    });
+    let app = Router::new().fallback(answer).with_state(replay);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            eprintln!("[Replay] Error: {}", e);
+        }
+    });
+    Ok(address)
+}
+
+async fn answer(State(replay): State<Arc<Replay>>, method: Method, uri: Uri) -> Response {
+    let full_path = uri
+        .path_and_query()
+        .map_or(uri.path(), |path| path.as_str());
+    let matching: Vec<&Fixture> = [full_path, uri.path()]
+        .iter()
+        .map(|path| {
+            replay
+                .fixtures
+                .iter()
+                .filter(|fixture| fixture.method == method.as_str() && fixture.path == *path)
+                .collect::<Vec<_>>()
+        })
+        .find(|matching| !matching.is_empty())
+        .unwrap_or_default();
+    if matching.is_empty() {
+        println!("[Replay] No fixture for {} {}", method, full_path);
+        return (
+            StatusCode::NOT_FOUND,
+            format!("No fixture for {} {}", method, full_path),
+        )
+            .into_response();
+    }
+    let turn = {
+        let mut served = replay.served.lock().unwrap_or_else(|e| e.into_inner());
+        let count = served
+            .entry((method.to_string(), full_path.to_string()))
+            .or_default();
+        *count += 1;
+        *count - 1
+    };
+    let fixture = matching[turn % matching.len()];
+    println!(
+        "[Replay] {} {} -> {} (recorded from {})",
+        method, full_path, fixture.status, fixture.broker
+    );
+
+    let mut response = fixture.response_body.clone().into_response();
+    *response.status_mut() = StatusCode::from_u16(fixture.status).unwrap_or(StatusCode::OK);
+    let headers = response.headers_mut();
+    for (name, value) in &fixture.response_headers {
+        let Ok(name) = name.parse::<reqwest::header::HeaderName>() else {
+            continue;
+        };
+        // The body was stored decoded and is sent whole.
+        if [
+            CONTENT_LENGTH,
+            CONTENT_ENCODING,
+            TRANSFER_ENCODING,
+            CONNECTION,
+        ]
+        .contains(&name)
+        {
+            continue;
+        }
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.insert(name, value);
+        }
+    }
+    response
+}
+
+/// `sarkhati replay DIR`: serve the fixtures in `dir` until stopped.
+pub async fn run_replay(dir: &str, port: u16, stop: impl Future<Output = ()>) -> Result<()> {
+    let fixtures = load(dir)?;
+    if fixtures.is_empty() {
+        anyhow::bail!("No fixtures in {}", dir);
+    }
+    let count = fixtures.len();
+    let address = serve(fixtures, port).await?;
+    println!(
+        "[Replay] Serving {} fixture(s) from {} on http://{}",
+        count, dir, address
+    );
+    println!("[Replay] Point order_url at this address, keeping the recorded path.");
+    stop.await;
+    Ok(())
+}
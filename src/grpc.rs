@@ -0,0 +1,168 @@
+//! `--grpc-port`: the control API over gRPC, plus a stream of order results
+//! for supervisors that watch and intervene in real time. The service is
+//! defined in `proto/control.proto`.
+
+use crate::control::{BrokerControl, BrokerStatus, Control, OrderEvent};
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::wrappers::{BroadcastStream, TcpListenerStream};
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("sarkhati.control");
+}
+
+use proto::control_server::{Control as ControlService, ControlServer};
+
+struct Service {
+    control: Arc<Control>,
+}
+
+impl Service {
+    fn broker(&self, name: &str) -> Result<Arc<BrokerControl>, Status> {
+        self.control
+            .broker(name)
+            .ok_or_else(|| Status::not_found(format!("No broker named {}", name)))
+    }
+
+    fn set_disabled(
+        &self,
+        request: Request<proto::OrderRequest>,
+        disabled: bool,
+    ) -> Result<Response<proto::BrokerStatus>, Status> {
+        let request = request.into_inner();
+        let broker = self.broker(&request.broker)?;
+        broker
+            .set_disabled(request.order as usize, disabled)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Response::new(broker.status().into()))
+    }
+}
+
+impl From<BrokerStatus> for proto::BrokerStatus {
+    fn from(status: BrokerStatus) -> Self {
+        Self {
+            name: status.name,
+            paused: status.paused,
+            orders: status.orders as u32,
+            disabled_orders: status
+                .disabled_orders
+                .into_iter()
+                .map(|order| order as u32)
+                .collect(),
+            batches: status.batches,
+            accepted: status.accepted,
+            failed: status.failed,
+            last_error: status.last_error,
+        }
+    }
+}
+
+impl From<OrderEvent> for proto::OrderEvent {
+    fn from(event: OrderEvent) -> Self {
+        Self {
+            broker: event.broker,
+            order: event.order as u32,
+            accepted: event.accepted,
+            error: event.error,
+            epoch_ms: event.epoch_ms,
+        }
+    }
+}
+
+type OrderEventStream = Pin<Box<dyn Stream<Item = Result<proto::OrderEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl ControlService for Service {
+    async fn status(
+        &self,
+        _request: Request<proto::StatusRequest>,
+    ) -> Result<Response<proto::StatusReply>, Status> {
+        Ok(Response::new(proto::StatusReply {
+            brokers: self.control.status().into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    async fn pause(
+        &self,
+        request: Request<proto::BrokerRequest>,
+    ) -> Result<Response<proto::BrokerStatus>, Status> {
+        let broker = self.broker(&request.get_ref().broker)?;
+        broker.set_paused(true);
+        Ok(Response::new(broker.status().into()))
+    }
+
+    async fn resume(
+        &self,
+        request: Request<proto::BrokerRequest>,
+    ) -> Result<Response<proto::BrokerStatus>, Status> {
+        let broker = self.broker(&request.get_ref().broker)?;
+        broker.set_paused(false);
+        Ok(Response::new(broker.status().into()))
+    }
+
+    async fn send_now(
+        &self,
+        request: Request<proto::BrokerRequest>,
+    ) -> Result<Response<proto::BrokerStatus>, Status> {
+        let broker = self.broker(&request.get_ref().broker)?;
+        broker.request_send_now();
+        Ok(Response::new(broker.status().into()))
+    }
+
+    async fn disable_order(
+        &self,
+        request: Request<proto::OrderRequest>,
+    ) -> Result<Response<proto::BrokerStatus>, Status> {
+        self.set_disabled(request, true)
+    }
+
+    async fn enable_order(
+        &self,
+        request: Request<proto::OrderRequest>,
+    ) -> Result<Response<proto::BrokerStatus>, Status> {
+        self.set_disabled(request, false)
+    }
+
+    type WatchOrdersStream = OrderEventStream;
+
+    async fn watch_orders(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchOrdersStream>, Status> {
+        let broker = request.into_inner().broker;
+        if !broker.is_empty() {
+            self.broker(&broker)?;
+        }
+        // A watcher too slow to keep up misses events rather than holding
+        // up the sends.
+        let events = BroadcastStream::new(self.control.subscribe()).filter_map(move |event| {
+            let event = event.ok()?;
+            (broker.is_empty() || event.broker.eq_ignore_ascii_case(&broker))
+                .then(|| Ok(event.into()))
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+/// Serve the gRPC control service on `127.0.0.1:port` in the background,
+/// returning the address it listens on.
+pub async fn start(control: Arc<Control>, port: u16) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .await
+        .with_context(|| format!("Failed to listen on gRPC port {}", port))?;
+    let address = listener.local_addr()?;
+    let server = tonic::transport::Server::builder()
+        .add_service(ControlServer::new(Service { control }))
+        .serve_with_incoming(TcpListenerStream::new(listener));
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("[gRPC] Error: {}", e);
+        }
+    });
+    println!("[gRPC] Listening on {}", address);
+    Ok(address)
+}
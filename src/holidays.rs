@@ -0,0 +1,220 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveTime, TimeZone, Weekday};
+use chrono_tz::Tz;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Holidays shipped with Sarkhati, used unless `holidays.calendar` is set.
+const BUNDLED_CALENDAR: &str = include_str!("../holidays.json");
+
+/// `holidays` setting: what scheduled runs do when `target_time` falls on a
+/// day the market is closed.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct HolidaySettings {
+    #[serde(default)]
+    pub on_holiday: OnHoliday,
+    /// Path or http(s) URL of a holiday table in the format of the bundled
+    /// `holidays.json`; the bundled table if unset.
+    #[serde(default)]
+    pub calendar: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnHoliday {
+    /// Move the send to the same time on the next trading day.
+    #[default]
+    Roll,
+    /// Stop with an error instead of arming.
+    Refuse,
+    /// Send anyway, as if the market were open.
+    Ignore,
+}
+
+fn default_session_open() -> String {
+    "08:45:00".to_string()
+}
+
+/// `market_closed` setting: what continuous runs do once the broker reports
+/// the market or the symbol closed.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MarketClosedSettings {
+    #[serde(default)]
+    pub action: OnMarketClosed,
+    /// Broker messages meaning the market is closed, on top of the built-in
+    /// ones.
+    #[serde(default)]
+    pub phrases: Vec<String>,
+    /// Tehran time `wait` resumes at on the next trading day, `HH:MM:SS`.
+    #[serde(default = "default_session_open")]
+    pub session_open: String,
+}
+
+impl Default for MarketClosedSettings {
+    fn default() -> Self {
+        Self {
+            action: OnMarketClosed::default(),
+            phrases: Vec::new(),
+            session_open: default_session_open(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnMarketClosed {
+    /// End the broker's run.
+    #[default]
+    Stop,
+    /// Sleep until `session_open` on the next trading day, then carry on.
+    Wait,
+}
+
+#[derive(Deserialize)]
+struct CalendarFile {
+    holidays: Vec<Holiday>,
+}
+
+#[derive(Deserialize)]
+struct Holiday {
+    /// `YYYY-MM-DD`, Gregorian.
+    date: String,
+    name: String,
+}
+
+/// Days the Tehran exchanges are closed: Thursdays, Fridays and the listed
+/// holidays.
+#[derive(Debug, Clone)]
+pub struct HolidayCalendar {
+    holidays: BTreeMap<NaiveDate, String>,
+}
+
+impl HolidayCalendar {
+    pub fn bundled() -> Self {
+        Self::parse(BUNDLED_CALENDAR).expect("bundled holidays.json is valid")
+    }
+
+    pub fn parse(json: &str) -> Result<Self> {
+        let file: CalendarFile = serde_json::from_str(json)?;
+        let mut holidays = BTreeMap::new();
+        for holiday in file.holidays {
+            let date = NaiveDate::parse_from_str(&holiday.date, "%Y-%m-%d")
+                .with_context(|| format!("Holiday date '{}' is not YYYY-MM-DD", holiday.date))?;
+            holidays.insert(date, holiday.name);
+        }
+        Ok(Self { holidays })
+    }
+
+    /// Read the table at `source`, a path or an http(s) URL, or the bundled
+    /// one when `None`.
+    pub async fn load(source: Option<&str>) -> Result<Self> {
+        let Some(source) = source else {
+            return Ok(Self::bundled());
+        };
+        let json = if source.starts_with("http://") || source.starts_with("https://") {
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()?;
+            client
+                .get(source)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("Failed to download holidays from {}", source))?
+                .text()
+                .await
+                .with_context(|| format!("Failed to download holidays from {}", source))?
+        } else {
+            std::fs::read_to_string(source)
+                .with_context(|| format!("Failed to read holidays from {}", source))?
+        };
+        Self::parse(&json).with_context(|| format!("Invalid holiday table in {}", source))
+    }
+
+    /// Why the market is closed on `date`, or `None` on a trading day.
+    pub fn closed_because(&self, date: NaiveDate) -> Option<&str> {
+        if let Some(name) = self.holidays.get(&date) {
+            return Some(name);
+        }
+        match date.weekday() {
+            Weekday::Thu | Weekday::Fri => Some("the weekend"),
+            _ => None,
+        }
+    }
+
+    /// Apply `on_holiday` to a send planned for `target`: keep it on a
+    /// trading day, roll it to the same time on the next one, or refuse.
+    pub fn schedule(
+        &self,
+        name: &str,
+        target: DateTime<Tz>,
+        on_holiday: OnHoliday,
+    ) -> Result<DateTime<Tz>> {
+        let date = target.date_naive();
+        if self
+            .holidays
+            .last_key_value()
+            .is_some_and(|(last, _)| date > *last)
+        {
+            summary!(
+                "[{}] Warning: the holiday table ends before {}; only weekends are skipped",
+                name,
+                date
+            );
+        }
+        let Some(reason) = self.closed_because(date) else {
+            return Ok(target);
+        };
+        match on_holiday {
+            OnHoliday::Ignore => {
+                summary!(
+                    "[{}] Warning: the market is closed on {} ({}); scheduling anyway",
+                    name,
+                    date,
+                    reason
+                );
+                Ok(target)
+            }
+            OnHoliday::Refuse => anyhow::bail!(
+                "target_time falls on {} ({}), when the market is closed; set holidays.on_holiday to \"roll\" to send on the next trading day",
+                date,
+                reason
+            ),
+            OnHoliday::Roll => {
+                let next = self.next_trading_day(date);
+                println!(
+                    "[{}] The market is closed on {} ({}); rolling to {}",
+                    name, date, reason, next
+                );
+                target
+                    .timezone()
+                    .from_local_datetime(&next.and_time(target.time()))
+                    .single()
+                    .context("Failed to resolve target_time in Asia/Tehran timezone")
+            }
+        }
+    }
+
+    /// When the next session opens at `open` after `now`: later today on a
+    /// trading day, otherwise on the next trading day.
+    pub fn next_session(&self, now: DateTime<Tz>, open: NaiveTime) -> Result<DateTime<Tz>> {
+        let today = now.date_naive();
+        let date = if now.time() < open && self.closed_because(today).is_none() {
+            today
+        } else {
+            self.next_trading_day(today + chrono::Days::new(1))
+        };
+        now.timezone()
+            .from_local_datetime(&date.and_time(open))
+            .single()
+            .context("Failed to resolve the session open in Asia/Tehran timezone")
+    }
+
+    /// The first trading day on or after `date`.
+    pub fn next_trading_day(&self, date: NaiveDate) -> NaiveDate {
+        date.iter_days()
+            .find(|day| self.closed_because(*day).is_none())
+            .expect("a trading day within the calendar")
+    }
+}
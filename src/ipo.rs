@@ -0,0 +1,138 @@
+use crate::calibration::CalibrationConfig;
+use crate::market_data::TradingTrigger;
+use crate::orders::{OrderEntry, OrderFields, PriceLimit};
+use crate::runner::{Broker, BrokerSettings};
+use anyhow::Result;
+use serde::Deserialize;
+
+/// `ipo` setting: the defaults for buying into an initial public offering
+/// (عرضه اولیه) of one symbol.
+#[derive(Debug, Deserialize, Clone)]
+pub struct IpoProfile {
+    /// Symbol or ISIN of the offering. Orders whose `symbol` or `isin` is
+    /// this are the IPO orders.
+    pub symbol: String,
+    /// Shares to ask for in total, split evenly over the broker's accounts.
+    /// Each order keeps its own quantity when unset.
+    #[serde(default)]
+    pub total_quantity: Option<u64>,
+}
+
+/// Apply the broker's `ipo` profile, if it has one: price its IPO orders at
+/// the upper limit, split `total_quantity` over the accounts, start when the
+/// symbol opens (or calibrate for `target_time`) and stop each account once
+/// it got an order accepted. Settings already configured are kept.
+pub fn apply<B: Broker>(broker: &mut B) -> Result<()> {
+    let Some(profile) = broker.settings().ipo.clone() else {
+        return Ok(());
+    };
+    let name = broker.name().to_string();
+    let symbol = profile.symbol.trim();
+
+    let ipo_orders = mark_upper_limit(broker.orders_mut(), symbol);
+    if ipo_orders.is_empty() {
+        anyhow::bail!("No order of {} is for its IPO symbol {}", name, symbol);
+    }
+    for account in broker.accounts_mut() {
+        mark_upper_limit(account.orders_mut(), symbol);
+    }
+
+    if let Some(total) = profile.total_quantity {
+        let shares = split(total, broker.accounts().len().max(1)).ok_or_else(|| {
+            anyhow::anyhow!(
+                "ipo.total_quantity of {} is smaller than its {} accounts",
+                name,
+                broker.accounts().len()
+            )
+        })?;
+        if broker.accounts().is_empty() {
+            set_quantity(broker.orders_mut(), symbol, shares[0]);
+        }
+        for (account, share) in broker.accounts_mut().iter_mut().zip(&shares) {
+            set_quantity(account.orders_mut(), symbol, *share);
+        }
+        let shares: Vec<String> = shares.iter().map(u64::to_string).collect();
+        println!(
+            "[{}] IPO {}: {} shares split as {}",
+            name,
+            symbol,
+            total,
+            shares.join(" + ")
+        );
+    }
+
+    // Orders configured by symbol have their ISIN filled in by now, except in
+    // dry runs, which never poll anyway.
+    let isin = broker.orders()[ipo_orders[0]]
+        .data
+        .isin()
+        .filter(|isin| !isin.is_empty())
+        .unwrap_or(symbol)
+        .to_string();
+    configure(broker.settings_mut(), &isin);
+    for account in broker.accounts_mut() {
+        configure(account.settings_mut(), &isin);
+    }
+    let start = if broker.settings().target_time.is_some() {
+        "calibrated for target_time"
+    } else {
+        "starting when it opens for trading"
+    };
+    println!(
+        "[{}] IPO {}: {} order(s) at the upper limit, {}, each account stops once accepted",
+        name,
+        symbol,
+        ipo_orders.len(),
+        start
+    );
+    Ok(())
+}
+
+/// Price the orders for `symbol` at the upper limit, returning their indexes.
+fn mark_upper_limit<T: OrderFields>(orders: &mut [OrderEntry<T>], symbol: &str) -> Vec<usize> {
+    let mut marked = Vec::new();
+    for (index, order) in orders.iter_mut().enumerate() {
+        if is_for(&order.data, symbol) {
+            order.price_limit = Some(PriceLimit::Upper);
+            marked.push(index);
+        }
+    }
+    marked
+}
+
+fn set_quantity<T: OrderFields>(orders: &mut [OrderEntry<T>], symbol: &str, quantity: u64) {
+    for order in orders {
+        if is_for(&order.data, symbol) {
+            order.data.set_quantity(quantity);
+        }
+    }
+}
+
+fn is_for<T: OrderFields>(order: &T, symbol: &str) -> bool {
+    order.symbol().map(str::trim) == Some(symbol) || order.isin().map(str::trim) == Some(symbol)
+}
+
+/// `total` split into `parts` shares differing by at most one, larger
+/// shares first; `None` if a share would be zero.
+pub fn split(total: u64, parts: usize) -> Option<Vec<u64>> {
+    let parts = parts as u64;
+    if total < parts {
+        return None;
+    }
+    Some(
+        (0..parts)
+            .map(|part| total / parts + u64::from(part < total % parts))
+            .collect(),
+    )
+}
+
+fn configure(settings: &mut BrokerSettings, isin: &str) {
+    if settings.target_time.is_some() {
+        settings
+            .calibration
+            .get_or_insert_with(CalibrationConfig::default);
+    } else if settings.wait_for_trading.is_none() {
+        settings.wait_for_trading = Some(TradingTrigger::new(isin));
+    }
+    settings.stop_on_accept = true;
+}
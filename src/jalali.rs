@@ -0,0 +1,35 @@
+//! The Jalali (Solar Hijri) calendar Iranian OMSes use for order dates.
+
+use chrono::{Datelike, NaiveDate};
+
+/// The Jalali year, month and day of a Gregorian date.
+pub fn from_gregorian(date: NaiveDate) -> (i32, u32, u32) {
+    const DAYS_BEFORE_MONTH: [i32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+    let (gy, gm, gd) = (date.year(), date.month() as usize, date.day() as i32);
+    let gy2 = if gm > 2 { gy + 1 } else { gy };
+    let mut days = 355666 + 365 * gy + (gy2 + 3) / 4 - (gy2 + 99) / 100
+        + (gy2 + 399) / 400
+        + gd
+        + DAYS_BEFORE_MONTH[gm - 1];
+    let mut year = -1595 + 33 * (days / 12053);
+    days %= 12053;
+    year += 4 * (days / 1461);
+    days %= 1461;
+    if days > 365 {
+        year += (days - 1) / 365;
+        days = (days - 1) % 365;
+    }
+    let (month, day) = if days < 186 {
+        (1 + days / 31, 1 + days % 31)
+    } else {
+        (7 + (days - 186) / 30, 1 + (days - 186) % 30)
+    };
+    (year, month as u32, day as u32)
+}
+
+/// `date` as `YYYY/MM/DD` in the Jalali calendar, e.g. `1404/07/24`.
+pub fn format(date: NaiveDate) -> String {
+    let (year, month, day) = from_gregorian(date);
+    format!("{:04}/{:02}/{:02}", year, month, day)
+}
@@ -0,0 +1,210 @@
+//! Where the time of a request goes: resolving the broker's host, opening
+//! the connection (TCP and TLS handshakes) and waiting for the first byte of
+//! the response. Tells a slow handshake apart from a slow network or a slow
+//! broker.
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use serde::Deserialize;
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// Connection setup of one request; both unset when it reused a pooled
+/// connection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Phases {
+    /// Resolving the host; unset for IP addresses.
+    pub dns: Option<Duration>,
+    /// Opening the connection, DNS included: TCP and, for HTTPS, TLS.
+    pub connect: Option<Duration>,
+}
+
+impl Phases {
+    /// The TCP and TLS handshakes alone.
+    pub fn handshake(&self) -> Option<Duration> {
+        self.connect
+            .map(|connect| connect.saturating_sub(self.dns.unwrap_or_default()))
+    }
+
+    /// Log suffix such as ` dns=1.2ms connect=20.3ms`, or ` reused` when no
+    /// connection was opened.
+    pub fn describe(&self) -> String {
+        let Some(handshake) = self.handshake() else {
+            return " reused".to_string();
+        };
+        let dns = self
+            .dns
+            .map(|dns| format!(" dns={:.1}ms", ms(dns)))
+            .unwrap_or_default();
+        format!("{} connect={:.1}ms", dns, ms(handshake))
+    }
+}
+
+pub fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+tokio::task_local! {
+    static PHASES: Cell<Phases>;
+}
+
+/// Run `request`, recording the connection setup it does. Setup done for
+/// it by another task, such as a connection that finished opening after
+/// the request took a pooled one, is not counted. Allocates nothing.
+pub async fn timed<F: Future>(request: F) -> (F::Output, Phases) {
+    let mut scoped = std::pin::pin!(PHASES.scope(Cell::new(Phases::default()), request));
+    let output = scoped.as_mut().await;
+    let phases = scoped
+        .as_mut()
+        .take_value()
+        .map(Cell::into_inner)
+        .unwrap_or_default();
+    (output, phases)
+}
+
+fn record(update: impl FnOnce(&mut Phases)) {
+    let _ = PHASES.try_with(|phases| {
+        let mut recorded = phases.get();
+        update(&mut recorded);
+        phases.set(recorded);
+    });
+}
+
+fn default_tcp_nodelay() -> bool {
+    true
+}
+
+/// Socket options of a broker's connections.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SocketOptions {
+    /// Send small writes at once instead of batching them (Nagle's
+    /// algorithm off).
+    #[serde(default = "default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+    /// Idle time before TCP keepalive probes start; off when unset.
+    #[serde(default)]
+    pub tcp_keepalive_ms: Option<u64>,
+    /// Gap between TCP keepalive probes.
+    #[serde(default)]
+    pub tcp_keepalive_interval_ms: Option<u64>,
+    /// Unanswered keepalive probes before the connection is dropped.
+    #[serde(default)]
+    pub tcp_keepalive_retries: Option<u32>,
+    /// How long sent data may go unacknowledged before the connection is
+    /// dropped. Linux only.
+    #[serde(default)]
+    pub tcp_user_timeout_ms: Option<u64>,
+    /// How long an idle connection stays in the pool; 90s when unset.
+    #[serde(default)]
+    pub pool_idle_timeout_ms: Option<u64>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> Self {
+        Self {
+            tcp_nodelay: default_tcp_nodelay(),
+            tcp_keepalive_ms: None,
+            tcp_keepalive_interval_ms: None,
+            tcp_keepalive_retries: None,
+            tcp_user_timeout_ms: None,
+            pool_idle_timeout_ms: None,
+        }
+    }
+}
+
+/// The client brokers send with: reqwest's defaults plus `socket` and
+/// phase timing.
+pub fn client(socket: &SocketOptions) -> reqwest::Client {
+    builder(socket)
+        .build()
+        .expect("Failed to initialize the HTTP client")
+}
+
+/// Builder of [`client`], for callers adding their own settings.
+pub fn builder(socket: &SocketOptions) -> reqwest::ClientBuilder {
+    let mut builder = reqwest::Client::builder()
+        .dns_resolver(Arc::new(TimedResolver))
+        .connector_layer(TimedConnectLayer)
+        .tcp_nodelay(socket.tcp_nodelay);
+    if let Some(keepalive_ms) = socket.tcp_keepalive_ms {
+        builder = builder.tcp_keepalive(Duration::from_millis(keepalive_ms));
+    }
+    if let Some(interval_ms) = socket.tcp_keepalive_interval_ms {
+        builder = builder.tcp_keepalive_interval(Duration::from_millis(interval_ms));
+    }
+    if let Some(retries) = socket.tcp_keepalive_retries {
+        builder = builder.tcp_keepalive_retries(retries);
+    }
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    if let Some(timeout_ms) = socket.tcp_user_timeout_ms {
+        builder = builder.tcp_user_timeout(Duration::from_millis(timeout_ms));
+    }
+    if let Some(idle_ms) = socket.pool_idle_timeout_ms {
+        builder = builder.pool_idle_timeout(Duration::from_millis(idle_ms));
+    }
+    builder
+}
+
+/// The system resolver, timed.
+struct TimedResolver;
+
+impl Resolve for TimedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let start = Instant::now();
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            let elapsed = start.elapsed();
+            record(|phases| phases.dns = Some(elapsed));
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Times reqwest's connector, which resolves, connects and does the TLS
+/// handshake.
+#[derive(Clone)]
+struct TimedConnectLayer;
+
+impl<S> Layer<S> for TimedConnectLayer {
+    type Service = TimedConnect<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        TimedConnect { inner }
+    }
+}
+
+#[derive(Clone)]
+struct TimedConnect<S> {
+    inner: S,
+}
+
+impl<S, R> Service<R> for TimedConnect<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        let connecting = self.inner.call(request);
+        Box::pin(async move {
+            let start = Instant::now();
+            let connection = connecting.await?;
+            let elapsed = start.elapsed();
+            record(|phases| phases.connect = Some(elapsed));
+            Ok(connection)
+        })
+    }
+}
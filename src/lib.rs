@@ -0,0 +1,129 @@
+#[macro_use]
+pub mod console;
+
+pub mod accounts;
+pub mod attempt;
+pub mod auth_check;
+pub mod batch_results;
+pub mod bench;
+pub mod bidar;
+pub mod bidar_token;
+pub mod book_snapshots;
+pub mod calibration;
+pub mod cancel_replace;
+pub mod captcha;
+pub mod capture;
+pub mod circuit_breaker;
+pub mod compare;
+pub mod conditions;
+pub mod control;
+pub mod cookies;
+pub mod credit;
+pub mod custom_broker;
+pub mod daemon;
+pub mod danayan;
+pub mod depth;
+pub mod dispatch;
+pub mod doctor;
+pub mod encryption;
+pub mod errors;
+pub mod exir_broker;
+pub mod exir_login;
+pub mod exir_presets;
+pub mod fixtures;
+pub mod grpc;
+pub mod holidays;
+pub mod ipo;
+pub mod jalali;
+pub mod latency;
+pub mod limit_prices;
+pub mod login;
+pub mod market_data;
+pub mod market_feed;
+pub mod mobin;
+pub mod mofid;
+pub mod mofid_login;
+pub mod onlineplus;
+pub mod order_events;
+pub mod orders;
+pub mod pasargad;
+pub mod persian;
+pub mod preflight;
+pub mod price_check;
+pub mod rate_limiter;
+pub mod rayan_broker;
+pub mod rayan_login;
+pub mod registry;
+pub mod remote_config;
+pub mod responses;
+pub mod runner;
+pub mod schema_drift;
+pub mod secrets;
+pub mod service;
+pub mod session_summary;
+pub mod signalr;
+pub mod standard_broker;
+pub mod success;
+pub mod systemd;
+pub mod tadbir_broker;
+pub mod tadbir_login;
+pub mod totp;
+pub mod tui;
+pub mod twap;
+pub mod watchlist;
+
+/// Decode Unicode escape sequences (e.g., \u0645) to actual characters. A
+/// surrogate pair (\ud83d\ude80) becomes the one character it encodes; a
+/// lone surrogate, an escaped backslash (\\u0645) and anything malformed
+/// are kept as they are.
+pub fn decode_unicode_escapes(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(position) = rest.find('\\') {
+        result.push_str(&rest[..position]);
+        rest = &rest[position..];
+        if rest.starts_with("\\\\") {
+            result.push_str("\\\\");
+            rest = &rest[2..];
+            continue;
+        }
+        let Some(unit) = escaped_unit(rest) else {
+            result.push('\\');
+            rest = &rest[1..];
+            continue;
+        };
+        let decoded = match unit {
+            0xD800..=0xDBFF => escaped_unit(&rest[6..])
+                .filter(|low| (0xDC00..=0xDFFF).contains(low))
+                .and_then(|low| {
+                    let code_point = 0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00);
+                    char::from_u32(code_point).map(|ch| (ch, 12))
+                }),
+            _ => char::from_u32(unit).map(|ch| (ch, 6)),
+        };
+        match decoded {
+            Some((ch, length)) => {
+                result.push(ch);
+                rest = &rest[length..];
+            }
+            // A lone surrogate
+            None => {
+                result.push_str(&rest[..6]);
+                rest = &rest[6..];
+            }
+        }
+    }
+    result.push_str(rest);
+
+    result
+}
+
+/// The UTF-16 code unit of the `\uXXXX` that `s` starts with.
+fn escaped_unit(s: &str) -> Option<u32> {
+    let hex = s.strip_prefix("\\u")?.get(..4)?;
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok()
+}
@@ -0,0 +1,122 @@
+use crate::market_data::{MarketData, PriceBand};
+use crate::orders::{OrderEntry, OrderFields, PriceLimit};
+use anyhow::{Context, Result};
+use chrono_tz::Asia::Tehran;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// How long before a scheduled send a new day's limits are fetched.
+pub const REFRESH_LEAD_MS: i64 = 5_000;
+
+/// Prices of the orders priced at `upper_limit` or `lower_limit`, taken from
+/// the instrument's daily band and refreshed once per Tehran day.
+pub struct LimitPrices {
+    market_data: MarketData,
+    /// Order index, TSETMC code of its instrument and the limit it wants.
+    orders: Vec<(usize, String, PriceLimit)>,
+    prices: Mutex<HashMap<usize, f64>>,
+    refreshed_on: Mutex<Option<chrono::NaiveDate>>,
+}
+
+impl LimitPrices {
+    /// Look up the instruments of the limit-priced `orders`; `None` when
+    /// there are none.
+    pub async fn new<T: OrderFields>(
+        name: &str,
+        orders: &[OrderEntry<T>],
+        base_url: &str,
+    ) -> Result<Option<Self>> {
+        let market_data = MarketData::new(base_url)?;
+        let mut ins_codes: HashMap<String, String> = HashMap::new();
+        let mut limit_orders = Vec::new();
+        for (index, order) in orders.iter().enumerate() {
+            let Some(limit) = order.price_limit else {
+                continue;
+            };
+            let isin = order.data.isin().with_context(|| {
+                format!(
+                    "Order #{} of {} is priced at {} but has no ISIN",
+                    index + 1,
+                    name,
+                    limit
+                )
+            })?;
+            let ins_code = match ins_codes.get(isin) {
+                Some(ins_code) => ins_code.clone(),
+                None => {
+                    let ins_code = market_data.find_instrument(isin).await?.ins_code;
+                    ins_codes.insert(isin.to_string(), ins_code.clone());
+                    ins_code
+                }
+            };
+            limit_orders.push((index, ins_code, limit));
+        }
+        if limit_orders.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            market_data,
+            orders: limit_orders,
+            prices: Mutex::new(HashMap::new()),
+            refreshed_on: Mutex::new(None),
+        }))
+    }
+
+    /// Fetch today's bands and reprice every limit order, logging each price.
+    pub async fn refresh(&self, name: &str) -> Result<()> {
+        let mut bands: HashMap<&str, PriceBand> = HashMap::new();
+        let mut prices = HashMap::new();
+        for (index, ins_code, limit) in &self.orders {
+            let band = match bands.get(ins_code.as_str()) {
+                Some(band) => *band,
+                None => {
+                    let band = self.market_data.price_band(ins_code).await?;
+                    bands.insert(ins_code, band);
+                    band
+                }
+            };
+            let price = limit.price(&band);
+            println!("[{}] Order #{} {} is {}", name, index + 1, limit, price);
+            prices.insert(*index, price);
+        }
+        *self.prices.lock().unwrap_or_else(|e| e.into_inner()) = prices;
+        *self.refreshed_on.lock().unwrap_or_else(|e| e.into_inner()) = Some(today());
+        Ok(())
+    }
+
+    /// Whether the prices were fetched before today.
+    pub fn is_stale(&self) -> bool {
+        *self.refreshed_on.lock().unwrap_or_else(|e| e.into_inner()) != Some(today())
+    }
+
+    /// Refresh when the prices are from an earlier day, keeping the old
+    /// prices with a warning if that fails.
+    pub async fn refresh_if_stale(&self, name: &str) {
+        if !self.is_stale() {
+            return;
+        }
+        if let Err(e) = self.refresh(name).await {
+            eprintln!(
+                "[{}] Warning: failed to refresh upper_limit/lower_limit prices: {:#}",
+                name, e
+            );
+        }
+    }
+
+    /// The resolved price of order `index`, if it is a limit order.
+    pub fn price(&self, index: usize) -> Option<f64> {
+        self.prices
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&index)
+            .copied()
+    }
+
+    pub fn is_limit_order(&self, index: usize) -> bool {
+        self.orders.iter().any(|(own, _, _)| *own == index)
+    }
+}
+
+fn today() -> chrono::NaiveDate {
+    chrono::Utc::now().with_timezone(&Tehran).date_naive()
+}
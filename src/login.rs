@@ -0,0 +1,166 @@
+use crate::accounts;
+use crate::captcha::Captcha;
+use crate::encryption;
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use reqwest::header::{CONTENT_TYPE, HeaderValue, USER_AGENT};
+use serde_json::Value;
+use std::io::Write;
+
+/// Print `question` and read one trimmed line from the console.
+pub async fn prompt_line(question: &str) -> Result<String> {
+    let question = question.to_string();
+    tokio::task::spawn_blocking(move || -> Result<String> {
+        print!("{}", question);
+        std::io::stdout().flush()?;
+        let mut answer = String::new();
+        std::io::stdin()
+            .read_line(&mut answer)
+            .context("Failed to read from console")?;
+        let answer = answer.trim().to_string();
+        if answer.is_empty() {
+            anyhow::bail!("No answer entered");
+        }
+        Ok(answer)
+    })
+    .await?
+}
+
+/// Overwrite fields of a config file in place, keeping every other key and
+/// its order. Fields holding a `"keyring:<name>"` reference are updated in
+/// the keyring instead. For multi-broker files, `broker_name` selects the entry in the
+/// `brokers` array, or with `<broker>/<account>` the entry in its `accounts`.
+pub fn update_config_fields(
+    path: &str,
+    broker_name: Option<&str>,
+    fields: &[(&str, Value)],
+) -> Result<()> {
+    let config_str = encryption::read_config(path)?;
+    let mut config: Value =
+        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+
+    let target = match broker_name {
+        Some(name) => {
+            let (broker, account) = accounts::split_name(name);
+            let entry = config
+                .get_mut("brokers")
+                .and_then(|brokers| find_named(brokers, broker))
+                .with_context(|| format!("Broker '{}' not found in {}", broker, path))?;
+            match account {
+                Some(account) => entry
+                    .get_mut("accounts")
+                    .and_then(|accounts| find_named(accounts, account))
+                    .with_context(|| {
+                        format!("Account '{}' not found in {} of {}", account, broker, path)
+                    })?,
+                None => entry,
+            }
+        }
+        None => &mut config,
+    };
+    let object = target
+        .as_object_mut()
+        .with_context(|| format!("Expected a JSON object in {}", path))?;
+    for (field, value) in fields {
+        // A field that refers to the keyring keeps its reference; the new
+        // value goes to the keyring instead.
+        let reference = object
+            .get(*field)
+            .and_then(Value::as_str)
+            .and_then(crate::secrets::reference_name)
+            .map(str::to_string);
+        match (reference, value) {
+            (Some(name), Value::String(secret)) => crate::secrets::store(&name, secret)?,
+            _ => {
+                object.insert(field.to_string(), value.clone());
+            }
+        }
+    }
+
+    let mut output = serde_json::to_string_pretty(&config)?;
+    output.push('\n');
+    encryption::write_config(path, &output)
+}
+
+/// The object called `name` in a JSON array of named objects.
+fn find_named<'a>(entries: &'a mut Value, name: &str) -> Option<&'a mut Value> {
+    entries.as_array_mut()?.iter_mut().find(|entry| {
+        entry
+            .get("name")
+            .and_then(Value::as_str)
+            .is_some_and(|entry| entry.eq_ignore_ascii_case(name))
+    })
+}
+
+/// `name=value` pairs from a response's `Set-Cookie` headers, joined into a
+/// `Cookie` header value.
+pub fn cookies_from_response(response: &reqwest::Response) -> String {
+    response
+        .headers()
+        .get_all(reqwest::header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| value.split(';').next())
+        .map(str::trim)
+        .filter(|pair| pair.contains('='))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// The `exp` claim of a JWT access token, in seconds since the epoch.
+pub fn jwt_expiry(token: &str) -> Option<u64> {
+    let token = token.strip_prefix("Bearer ").unwrap_or(token);
+    let payload = token.split('.').nth(1)?;
+    let payload = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: Value = serde_json::from_slice(&payload).ok()?;
+    claims.get("exp").and_then(Value::as_u64)
+}
+
+/// Merge two `Cookie` header values; pairs in `update` replace pairs with the
+/// same name in `base`.
+pub fn merge_cookies(base: &str, update: &str) -> String {
+    let mut pairs: Vec<&str> = Vec::new();
+    for pair in base.split(';').chain(update.split(';')).map(str::trim) {
+        let Some((name, _)) = pair.split_once('=') else {
+            continue;
+        };
+        pairs.retain(|existing| !existing.starts_with(&format!("{}=", name)));
+        pairs.push(pair);
+    }
+    pairs.join("; ")
+}
+
+/// Download a login captcha, returning the image and the cookies that tie
+/// the answer to this session.
+pub async fn fetch_captcha(
+    client: &reqwest::Client,
+    captcha_url: &str,
+    user_agent: &str,
+) -> Result<(Captcha, String)> {
+    let response = client
+        .get(captcha_url)
+        .header(USER_AGENT, HeaderValue::from_str(user_agent)?)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch captcha from {}", captcha_url))?;
+    if !response.status().is_success() {
+        anyhow::bail!("Captcha request failed with status {}", response.status());
+    }
+    let cookie = cookies_from_response(&response);
+    let extension = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|content_type| content_type.strip_prefix("image/"))
+        .map(|subtype| {
+            subtype
+                .split(['+', ';'])
+                .next()
+                .unwrap_or(subtype)
+                .to_string()
+        })
+        .unwrap_or_else(|| "png".to_string());
+    let image = response.bytes().await?.to_vec();
+    Ok((Captcha { image, extension }, cookie))
+}
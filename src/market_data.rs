@@ -0,0 +1,617 @@
+use crate::market_feed::{self, MarketFeedConfig};
+use crate::orders::{OrderEntry, OrderFields, is_isin};
+use crate::persian;
+use anyhow::{Context, Result};
+use chrono::TimeZone;
+use chrono_tz::Asia::Tehran;
+use reqwest::header::{ACCEPT, HeaderMap, HeaderValue, USER_AGENT};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// TSETMC's public JSON API.
+pub const DEFAULT_MARKET_DATA_URL: &str = "https://cdn.tsetmc.com/api";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn default_market_data_url() -> String {
+    DEFAULT_MARKET_DATA_URL.to_string()
+}
+
+pub fn default_poll_interval_ms() -> u64 {
+    500
+}
+
+/// `wait_for_trading` setting: hold the batch loop until the instrument is
+/// allowed for trading.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TradingTrigger {
+    pub isin: String,
+    /// TSETMC's internal code for the instrument; looked up from `isin` if
+    /// unset.
+    #[serde(default)]
+    pub ins_code: Option<String>,
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+    /// Tehran time (`HH:MM:SS`) to start polling, so TSETMC is not polled
+    /// all night; polling starts right away if unset or already past.
+    #[serde(default)]
+    pub start_at: Option<String>,
+}
+
+impl TradingTrigger {
+    /// Poll `isin` with the default interval, starting right away.
+    pub fn new(isin: &str) -> Self {
+        Self {
+            isin: isin.to_string(),
+            ins_code: None,
+            poll_interval_ms: default_poll_interval_ms(),
+            start_at: None,
+        }
+    }
+}
+
+/// An instrument as TSETMC identifies it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instrument {
+    pub ins_code: String,
+    pub isin: String,
+    pub symbol: String,
+    pub name: String,
+    /// TSETMC's `flow`: which exchange or market the instrument trades on.
+    pub flow: Option<u64>,
+    /// TSETMC's `cgrValCot`, the board group such as `N1`.
+    pub board: String,
+}
+
+impl Instrument {
+    pub fn market(&self) -> String {
+        match self.flow {
+            Some(1) => "TSE".to_string(),
+            Some(2) => "IFB".to_string(),
+            Some(3) => "derivatives".to_string(),
+            Some(4) => "IFB base market".to_string(),
+            Some(6) => "energy exchange".to_string(),
+            Some(7) => "mercantile exchange".to_string(),
+            Some(flow) => format!("flow {}", flow),
+            None => "unknown market".to_string(),
+        }
+    }
+}
+
+/// Trading state of an instrument, TSETMC's `cEtaval` code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstrumentState(pub String);
+
+impl InstrumentState {
+    /// Only plain `A` (مجاز) accepts orders; `AR`, `AS` and `AG` are
+    /// allowed but reserved, suspended or frozen.
+    pub fn is_tradable(&self) -> bool {
+        self.0 == "A"
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self.0.as_str() {
+            "A" => "allowed",
+            "AR" => "allowed, reserved",
+            "AS" => "allowed, suspended",
+            "AG" => "allowed, frozen",
+            "I" => "forbidden",
+            "IR" => "forbidden, reserved",
+            "IS" => "forbidden, suspended",
+            "IG" => "forbidden, frozen",
+            _ => "unknown",
+        }
+    }
+}
+
+impl std::fmt::Display for InstrumentState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.0, self.description())
+    }
+}
+
+/// Current prices and trading state of an instrument. Prices are `None` when
+/// there is no trade yet or that side of the order book is empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Quote {
+    pub last_price: Option<f64>,
+    pub closing_price: Option<f64>,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub state: Option<InstrumentState>,
+}
+
+/// One row of the order book: the n-th best bid and ask.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BookLevel {
+    pub bid_price: Option<f64>,
+    pub bid_volume: u64,
+    pub bid_orders: u64,
+    pub ask_price: Option<f64>,
+    pub ask_volume: u64,
+    pub ask_orders: u64,
+}
+
+/// The visible order book, best prices first.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OrderBook {
+    pub levels: Vec<BookLevel>,
+}
+
+impl OrderBook {
+    pub fn best_bid(&self) -> Option<f64> {
+        self.levels.first().and_then(|level| level.bid_price)
+    }
+
+    pub fn best_ask(&self) -> Option<f64> {
+        self.levels.first().and_then(|level| level.ask_price)
+    }
+}
+
+/// `bids 2480x1200(3), ... | asks 2500x300(1), ...`: price x volume (orders).
+impl std::fmt::Display for OrderBook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let side = |pick: fn(&BookLevel) -> (Option<f64>, u64, u64)| {
+            let levels: Vec<String> = self
+                .levels
+                .iter()
+                .filter_map(|level| {
+                    let (price, volume, orders) = pick(level);
+                    price.map(|price| format!("{}x{}({})", price, volume, orders))
+                })
+                .collect();
+            if levels.is_empty() {
+                "-".to_string()
+            } else {
+                levels.join(", ")
+            }
+        };
+        write!(
+            f,
+            "bids {} | asks {}",
+            side(|level| (level.bid_price, level.bid_volume, level.bid_orders)),
+            side(|level| (level.ask_price, level.ask_volume, level.ask_orders))
+        )
+    }
+}
+
+/// The daily price limits (دامنه نوسان) outside which orders are rejected.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriceBand {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+impl PriceBand {
+    pub fn contains(&self, price: f64) -> bool {
+        (self.lower..=self.upper).contains(&price)
+    }
+}
+
+/// Everything `quote` reports about one instrument.
+#[derive(Debug, Clone)]
+pub struct InstrumentQuote {
+    pub instrument: Instrument,
+    pub quote: Quote,
+    pub band: PriceBand,
+}
+
+/// Client for the TSETMC market data API.
+#[derive(Debug, Clone)]
+pub struct MarketData {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl MarketData {
+    pub fn new(base_url: &str) -> Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            USER_AGENT,
+            HeaderValue::from_static(
+                "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36",
+            ),
+        );
+        headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .timeout(REQUEST_TIMEOUT)
+            .build()?;
+        Ok(Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    async fn get(&self, path: &str) -> Result<Value> {
+        let url = format!("{}/{}", self.base_url, path);
+        let response = self
+            .client
+            .get(&url)
+            .send()
+            .await
+            .with_context(|| format!("Failed to fetch {}", url))?;
+        let status = response.status();
+        if !status.is_success() {
+            anyhow::bail!("{} returned status {}", url, status);
+        }
+        response
+            .json()
+            .await
+            .with_context(|| format!("Invalid JSON from {}", url))
+    }
+
+    /// Look up the instrument with `isin`.
+    pub async fn find_instrument(&self, isin: &str) -> Result<Instrument> {
+        self.search(isin)
+            .await?
+            .into_iter()
+            .find(|instrument| instrument.isin.eq_ignore_ascii_case(isin))
+            .with_context(|| format!("No instrument with ISIN {} on TSETMC", isin))
+    }
+
+    /// Instruments whose symbol, name or ISIN matches `query`, as TSETMC's
+    /// search box finds them.
+    pub async fn search(&self, query: &str) -> Result<Vec<Instrument>> {
+        let value = self
+            .get(&format!("Instrument/GetInstrumentSearch/{}", query.trim()))
+            .await?;
+        let results = value
+            .get("instrumentSearch")
+            .and_then(Value::as_array)
+            .context("Unexpected instrument search response")?;
+        Ok(results.iter().filter_map(parse_instrument).collect())
+    }
+
+    /// The instrument whose symbol is exactly `symbol`, such as `فولاد`.
+    /// Arabic and Persian forms of ی and ک are treated as the same letter.
+    pub async fn find_symbol(&self, symbol: &str) -> Result<Instrument> {
+        let wanted = normalize_symbol(symbol);
+        let mut matches: Vec<Instrument> = self
+            .search(symbol)
+            .await?
+            .into_iter()
+            .filter(|instrument| normalize_symbol(&instrument.symbol) == wanted)
+            .collect();
+        matches.sort_by(|a, b| a.isin.cmp(&b.isin));
+        matches.dedup_by(|a, b| a.isin == b.isin);
+        match matches.len() {
+            0 => anyhow::bail!("No instrument with symbol {} on TSETMC", symbol),
+            1 => Ok(matches.remove(0)),
+            _ => {
+                let isins: Vec<String> = matches
+                    .iter()
+                    .map(|instrument| format!("{} ({})", instrument.isin, instrument.market()))
+                    .collect();
+                anyhow::bail!(
+                    "Symbol {} matches several instruments: {}; set the ISIN instead",
+                    symbol,
+                    isins.join(", ")
+                )
+            }
+        }
+    }
+
+    /// The current trading state of the instrument with `ins_code`.
+    pub async fn instrument_state(&self, ins_code: &str) -> Result<InstrumentState> {
+        let value = self
+            .get(&format!("ClosingPrice/GetClosingPriceInfo/{}", ins_code))
+            .await?;
+        let code = value
+            .pointer("/closingPriceInfo/instrumentState/cEtaval")
+            .and_then(Value::as_str)
+            .context("Unexpected closing price response: no instrument state")?;
+        Ok(InstrumentState(code.trim().to_string()))
+    }
+
+    /// Last and closing prices, trading state and the best bid and ask of
+    /// `ins_code`.
+    pub async fn quote(&self, ins_code: &str) -> Result<Quote> {
+        let closing_path = format!("ClosingPrice/GetClosingPriceInfo/{}", ins_code);
+        let (closing, book) =
+            futures::try_join!(self.get(&closing_path), self.order_book(ins_code))?;
+        Ok(Quote {
+            last_price: price(closing.pointer("/closingPriceInfo/pDrCotVal")),
+            closing_price: price(closing.pointer("/closingPriceInfo/pClosing")),
+            best_bid: book.best_bid(),
+            best_ask: book.best_ask(),
+            state: closing
+                .pointer("/closingPriceInfo/instrumentState/cEtaval")
+                .and_then(Value::as_str)
+                .map(|code| InstrumentState(code.trim().to_string())),
+        })
+    }
+
+    /// The visible order book of `ins_code`.
+    pub async fn order_book(&self, ins_code: &str) -> Result<OrderBook> {
+        let value = self.get(&format!("BestLimits/{}", ins_code)).await?;
+        let mut rows: Vec<&Value> = value
+            .get("bestLimits")
+            .and_then(Value::as_array)
+            .context("Unexpected best limits response")?
+            .iter()
+            .collect();
+        rows.sort_by_key(|row| row.get("number").and_then(Value::as_u64));
+        let count = |row: &Value, field: &str| row.get(field).and_then(Value::as_u64).unwrap_or(0);
+        Ok(OrderBook {
+            levels: rows
+                .into_iter()
+                .map(|row| BookLevel {
+                    bid_price: price(row.get("pMeDem")),
+                    bid_volume: count(row, "qTitMeDem"),
+                    bid_orders: count(row, "zOrdMeDem"),
+                    ask_price: price(row.get("pMeOf")),
+                    ask_volume: count(row, "qTitMeOf"),
+                    ask_orders: count(row, "zOrdMeOf"),
+                })
+                .collect(),
+        })
+    }
+
+    /// Today's price band of `ins_code`.
+    pub async fn price_band(&self, ins_code: &str) -> Result<PriceBand> {
+        let value = self
+            .get(&format!("Instrument/GetStaticThreshold/{}", ins_code))
+            .await?;
+        // The newest threshold is listed last.
+        let threshold = value
+            .get("staticThreshold")
+            .and_then(Value::as_array)
+            .and_then(|thresholds| thresholds.last())
+            .context("Unexpected static threshold response")?;
+        let (Some(lower), Some(upper)) = (
+            price(threshold.get("psGelStaMin")),
+            price(threshold.get("psGelStaMax")),
+        ) else {
+            anyhow::bail!("No price band for insCode {} on TSETMC", ins_code);
+        };
+        Ok(PriceBand { lower, upper })
+    }
+
+    /// Look up `isin` and fetch its quote and price band.
+    pub async fn quote_isin(&self, isin: &str) -> Result<InstrumentQuote> {
+        let instrument = self.find_instrument(isin).await?;
+        let (quote, band) = futures::try_join!(
+            self.quote(&instrument.ins_code),
+            self.price_band(&instrument.ins_code)
+        )?;
+        Ok(InstrumentQuote {
+            instrument,
+            quote,
+            band,
+        })
+    }
+}
+
+/// A price field; TSETMC reports missing prices as zero.
+fn price(value: Option<&Value>) -> Option<f64> {
+    value.and_then(Value::as_f64).filter(|price| *price > 0.0)
+}
+
+fn parse_instrument(value: &Value) -> Option<Instrument> {
+    let text = |field: &str| value.get(field).and_then(Value::as_str).map(str::trim);
+    Some(Instrument {
+        ins_code: match value.get("insCode")? {
+            Value::String(code) => code.clone(),
+            Value::Number(code) => code.to_string(),
+            _ => return None,
+        },
+        isin: text("instrumentID")?.to_string(),
+        symbol: text("lVal18AFC").unwrap_or_default().to_string(),
+        name: text("lVal30").unwrap_or_default().to_string(),
+        flow: value.get("flow").and_then(Value::as_u64),
+        board: text("cgrValCot").unwrap_or_default().to_string(),
+    })
+}
+
+/// Fold the Arabic ي and ك TSETMC uses into their Persian forms.
+fn normalize_symbol(symbol: &str) -> String {
+    persian::normalize(symbol.trim())
+}
+
+/// Give orders that set `symbol` instead of an ISIN the ISIN of that symbol,
+/// failing on orders that set neither. Orders that already have an ISIN are
+/// left alone.
+pub async fn resolve_symbols<T: OrderFields>(
+    name: &str,
+    orders: &mut [OrderEntry<T>],
+    base_url: &str,
+) -> Result<()> {
+    let market_data = MarketData::new(base_url)?;
+    let mut resolved: HashMap<String, String> = HashMap::new();
+    for (index, order) in orders.iter_mut().enumerate() {
+        let Some(symbol) = order.data.symbol().map(str::to_string) else {
+            if order.data.isin().is_some_and(|isin| isin.trim().is_empty()) {
+                anyhow::bail!("Order #{} of {} needs an ISIN or a symbol", index + 1, name);
+            }
+            continue;
+        };
+        if order
+            .data
+            .isin()
+            .is_some_and(|isin| !isin.trim().is_empty())
+        {
+            continue;
+        }
+        let isin = match resolved.get(&symbol) {
+            Some(isin) => isin.clone(),
+            None if is_isin(&symbol) => symbol.clone(),
+            None => {
+                let instrument = market_data
+                    .find_symbol(&symbol)
+                    .await
+                    .with_context(|| format!("Order #{} of {}", index + 1, name))?;
+                println!(
+                    "[{}] Symbol {} is {} ({}, board {})",
+                    name,
+                    symbol,
+                    instrument.isin,
+                    instrument.market(),
+                    instrument.board
+                );
+                resolved.insert(symbol.clone(), instrument.isin.clone());
+                instrument.isin
+            }
+        };
+        order.data.set_isin(&isin);
+    }
+    Ok(())
+}
+
+/// Poll the instrument's state until it is allowed for trading, logging
+/// every change. Failed polls are logged and retried. With `feed`, the
+/// state is checked once and then followed from the feed's updates.
+pub async fn wait_for_trading(
+    name: &str,
+    trigger: &TradingTrigger,
+    base_url: &str,
+    feed: Option<&MarketFeedConfig>,
+) -> Result<()> {
+    if let Some(start_at) = &trigger.start_at {
+        let start_at = chrono::NaiveTime::parse_from_str(start_at, "%H:%M:%S")
+            .context("wait_for_trading.start_at must be in HH:MM:SS format")?;
+        println!(
+            "[{}] Waiting until {} to start polling {}",
+            name, start_at, trigger.isin
+        );
+        let now = chrono::Utc::now().with_timezone(&Tehran);
+        let start = Tehran
+            .from_local_datetime(&now.date_naive().and_time(start_at))
+            .single()
+            .context("Failed to resolve start_at in Asia/Tehran timezone")?;
+        if let Ok(wait) = (start - now).to_std() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    let market_data = MarketData::new(base_url)?;
+    let ins_code = match &trigger.ins_code {
+        Some(ins_code) => ins_code.clone(),
+        None => {
+            let instrument = market_data.find_instrument(&trigger.isin).await?;
+            println!(
+                "[{}] Watching {} ({}), insCode {}",
+                name, instrument.symbol, instrument.isin, instrument.ins_code
+            );
+            instrument.ins_code
+        }
+    };
+
+    // Subscribed before the first check so no update in between is lost.
+    let mut subscription = match feed {
+        Some(feed) => {
+            Some(market_feed::subscribe(name, feed, std::slice::from_ref(&trigger.isin)).await?)
+        }
+        None => None,
+    };
+    let interval = Duration::from_millis(trigger.poll_interval_ms.max(1));
+    let mut last_state: Option<InstrumentState> = None;
+    loop {
+        match market_data.instrument_state(&ins_code).await {
+            Ok(state) => {
+                if report_state(name, trigger, state, &mut last_state) {
+                    return Ok(());
+                }
+            }
+            Err(e) => eprintln!("[{}] Warning: trading state poll failed: {:#}", name, e),
+        }
+        if let Some(subscription) = &mut subscription {
+            while let Some(update) = subscription.updates.recv().await {
+                if !update.isin.eq_ignore_ascii_case(&trigger.isin) {
+                    continue;
+                }
+                if let Some(state) = update.state
+                    && report_state(name, trigger, state, &mut last_state)
+                {
+                    return Ok(());
+                }
+            }
+            anyhow::bail!(
+                "Market feed stopped before {} became tradable",
+                trigger.isin
+            );
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Log `state` if it changed; returns whether it releases the batch loop.
+fn report_state(
+    name: &str,
+    trigger: &TradingTrigger,
+    state: InstrumentState,
+    last_state: &mut Option<InstrumentState>,
+) -> bool {
+    if state.is_tradable() {
+        println!(
+            "[{}] {} is now {}; releasing the batch loop",
+            name, trigger.isin, state
+        );
+        return true;
+    }
+    if last_state.as_ref() != Some(&state) {
+        println!("[{}] {} state: {}", name, trigger.isin, state);
+    }
+    *last_state = Some(state);
+    false
+}
+
+fn format_price(price: Option<f64>) -> String {
+    price.map_or("-".to_string(), |price| price.to_string())
+}
+
+/// Run `isin`: print the instruments whose symbol is `symbol`, or the
+/// closest matches when none is exact.
+pub async fn run_isin(symbol: &str, base_url: &str) -> Result<()> {
+    let market_data = MarketData::new(base_url)?;
+    let instruments = match market_data.find_symbol(symbol).await {
+        Ok(instrument) => vec![instrument],
+        Err(e) => {
+            let matches = market_data.search(symbol).await?;
+            if matches.is_empty() {
+                return Err(e);
+            }
+            eprintln!("[ISIN] {:#}; closest matches:", e);
+            matches
+        }
+    };
+    for instrument in instruments.iter().take(10) {
+        println!(
+            "[ISIN] {}  {}  {}, board {}  {}",
+            instrument.symbol,
+            instrument.isin,
+            instrument.market(),
+            instrument.board,
+            instrument.name
+        );
+    }
+    Ok(())
+}
+
+/// Run `quote`: print the quote, price band and state of `isin`.
+pub async fn run_quote(isin: &str, base_url: &str) -> Result<()> {
+    let InstrumentQuote {
+        instrument,
+        quote,
+        band,
+    } = MarketData::new(base_url)?.quote_isin(isin).await?;
+    println!(
+        "[Quote] {} ({}) {}, insCode {}",
+        instrument.symbol, instrument.isin, instrument.name, instrument.ins_code
+    );
+    println!(
+        "[Quote] State:       {}",
+        quote
+            .state
+            .as_ref()
+            .map_or("-".to_string(), |state| state.to_string())
+    );
+    println!("[Quote] Last price:  {}", format_price(quote.last_price));
+    println!("[Quote] Closing:     {}", format_price(quote.closing_price));
+    println!("[Quote] Daily band:  {} - {}", band.lower, band.upper);
+    println!("[Quote] Best bid:    {}", format_price(quote.best_bid));
+    println!("[Quote] Best ask:    {}", format_price(quote.best_ask));
+    Ok(())
+}
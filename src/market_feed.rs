@@ -0,0 +1,287 @@
+use crate::market_data::{InstrumentState, Quote};
+use anyhow::{Context, Result};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::{HeaderName, HeaderValue};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+fn default_reconnect_delay_ms() -> u64 {
+    1000
+}
+
+fn default_isin_pointer() -> String {
+    "/isin".to_string()
+}
+
+fn default_last_price_pointer() -> Option<String> {
+    Some("/lastPrice".to_string())
+}
+
+fn default_best_bid_pointer() -> Option<String> {
+    Some("/bestBid".to_string())
+}
+
+fn default_best_ask_pointer() -> Option<String> {
+    Some("/bestAsk".to_string())
+}
+
+fn default_state_pointer() -> Option<String> {
+    Some("/state".to_string())
+}
+
+/// `market_feed` setting: a WebSocket pushing instrument updates, used by
+/// `send_when` and `wait_for_trading` instead of polling TSETMC.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MarketFeedConfig {
+    /// `ws://` or `wss://` address of the feed.
+    pub url: String,
+    /// Sent with the handshake, such as the broker's `Cookie` or
+    /// `Authorization`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Message sent once per watched instrument after connecting, with
+    /// `{{isin}}` replaced in every string; nothing is sent when unset.
+    #[serde(default)]
+    pub subscribe: Option<Value>,
+    /// Where each field sits in a pushed message.
+    #[serde(default)]
+    pub fields: FeedFields,
+    /// Wait before connecting again after the feed drops.
+    #[serde(default = "default_reconnect_delay_ms")]
+    pub reconnect_delay_ms: u64,
+}
+
+/// JSON pointers into a pushed message. A message may also be an array of
+/// such objects. Prices left out of a message keep their last value; a
+/// field set to `null` is never read.
+#[derive(Debug, Deserialize, Clone)]
+pub struct FeedFields {
+    #[serde(default = "default_isin_pointer")]
+    pub isin: String,
+    #[serde(default = "default_last_price_pointer")]
+    pub last_price: Option<String>,
+    #[serde(default = "default_best_bid_pointer")]
+    pub best_bid: Option<String>,
+    #[serde(default = "default_best_ask_pointer")]
+    pub best_ask: Option<String>,
+    /// TSETMC's state code, such as `A` for allowed.
+    #[serde(default = "default_state_pointer")]
+    pub state: Option<String>,
+}
+
+impl Default for FeedFields {
+    fn default() -> Self {
+        Self {
+            isin: default_isin_pointer(),
+            last_price: default_last_price_pointer(),
+            best_bid: default_best_bid_pointer(),
+            best_ask: default_best_ask_pointer(),
+            state: default_state_pointer(),
+        }
+    }
+}
+
+/// What one message says about one instrument.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FeedUpdate {
+    pub isin: String,
+    pub last_price: Option<f64>,
+    pub best_bid: Option<f64>,
+    pub best_ask: Option<f64>,
+    pub state: Option<InstrumentState>,
+}
+
+impl FeedUpdate {
+    /// Fold the update into the last known `quote` of its instrument.
+    pub fn apply(&self, quote: &mut Quote) {
+        if self.last_price.is_some() {
+            quote.last_price = self.last_price;
+        }
+        if self.best_bid.is_some() {
+            quote.best_bid = self.best_bid;
+        }
+        if self.best_ask.is_some() {
+            quote.best_ask = self.best_ask;
+        }
+        if self.state.is_some() {
+            quote.state = self.state.clone();
+        }
+    }
+}
+
+/// The instrument updates in one pushed message; messages that are not
+/// JSON or carry no ISIN, such as heartbeats, yield none.
+pub fn parse_message(fields: &FeedFields, text: &str) -> Vec<FeedUpdate> {
+    let Ok(value) = serde_json::from_str::<Value>(text) else {
+        return Vec::new();
+    };
+    let items = match value {
+        Value::Array(items) => items,
+        item => vec![item],
+    };
+    items
+        .iter()
+        .filter_map(|item| parse_item(fields, item))
+        .collect()
+}
+
+fn parse_item(fields: &FeedFields, item: &Value) -> Option<FeedUpdate> {
+    let read = |pointer: &Option<String>| pointer.as_deref().and_then(|p| item.pointer(p));
+    let price = |pointer: &Option<String>| {
+        read(pointer)
+            .and_then(|value| match value {
+                Value::String(text) => text.trim().parse().ok(),
+                value => value.as_f64(),
+            })
+            .filter(|price: &f64| *price > 0.0)
+    };
+    let isin = item.pointer(&fields.isin)?.as_str()?.trim();
+    if isin.is_empty() {
+        return None;
+    }
+    Some(FeedUpdate {
+        isin: isin.to_string(),
+        last_price: price(&fields.last_price),
+        best_bid: price(&fields.best_bid),
+        best_ask: price(&fields.best_ask),
+        state: read(&fields.state)
+            .and_then(Value::as_str)
+            .map(|code| InstrumentState(code.trim().to_string())),
+    })
+}
+
+/// A running connection to the feed. Updates for the subscribed
+/// instruments arrive on `updates`; the connection closes when this is
+/// dropped.
+pub struct FeedSubscription {
+    pub updates: mpsc::UnboundedReceiver<FeedUpdate>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for FeedSubscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Connect to the feed and subscribe to `isins`. The first connection is
+/// made before returning so a wrong address fails the run; later drops are
+/// logged and reconnected.
+pub async fn subscribe(
+    name: &str,
+    config: &MarketFeedConfig,
+    isins: &[String],
+) -> Result<FeedSubscription> {
+    let socket = connect(config, isins)
+        .await
+        .with_context(|| format!("Failed to connect to market feed {}", config.url))?;
+    println!(
+        "[{}] Market feed connected: {} ({} instrument(s))",
+        name,
+        config.url,
+        isins.len()
+    );
+
+    let (sender, updates) = mpsc::unbounded_channel();
+    let name = name.to_string();
+    let config = config.clone();
+    let isins = isins.to_vec();
+    let task = tokio::spawn(async move {
+        let mut socket = Some(socket);
+        loop {
+            let current = match socket.take() {
+                Some(current) => current,
+                None => {
+                    tokio::time::sleep(Duration::from_millis(config.reconnect_delay_ms)).await;
+                    match connect(&config, &isins).await {
+                        Ok(current) => {
+                            println!("[{}] Market feed reconnected", name);
+                            current
+                        }
+                        Err(e) => {
+                            eprintln!("[{}] Warning: market feed reconnect failed: {:#}", name, e);
+                            continue;
+                        }
+                    }
+                }
+            };
+            match forward(current, &config.fields, &sender).await {
+                Ok(()) => eprintln!("[{}] Warning: market feed closed", name),
+                Err(e) => eprintln!("[{}] Warning: market feed dropped: {:#}", name, e),
+            }
+            if sender.is_closed() {
+                return;
+            }
+        }
+    });
+    Ok(FeedSubscription { updates, task })
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn connect(config: &MarketFeedConfig, isins: &[String]) -> Result<Socket> {
+    let mut request = config.url.as_str().into_client_request()?;
+    for (name, value) in &config.headers {
+        request.headers_mut().insert(
+            HeaderName::from_bytes(name.as_bytes())?,
+            HeaderValue::from_str(value)?,
+        );
+    }
+    let (mut socket, _) = tokio_tungstenite::connect_async(request).await?;
+    if let Some(subscribe) = &config.subscribe {
+        for isin in isins {
+            let message = fill_isin(subscribe, isin);
+            socket
+                .send(Message::text(serde_json::to_string(&message)?))
+                .await?;
+        }
+    }
+    Ok(socket)
+}
+
+/// `template` with `{{isin}}` replaced in every string.
+fn fill_isin(template: &Value, isin: &str) -> Value {
+    match template {
+        Value::String(text) => Value::String(text.replace("{{isin}}", isin)),
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|item| fill_isin(item, isin)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(key, value)| (key.clone(), fill_isin(value, isin)))
+                .collect(),
+        ),
+        value => value.clone(),
+    }
+}
+
+/// Pass every update on until the feed closes or nobody listens anymore.
+async fn forward(
+    mut socket: Socket,
+    fields: &FeedFields,
+    sender: &mpsc::UnboundedSender<FeedUpdate>,
+) -> Result<()> {
+    while let Some(message) = socket.next().await {
+        let text = match message? {
+            Message::Text(text) => text.to_string(),
+            Message::Binary(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Message::Close(_) => return Ok(()),
+            _ => continue,
+        };
+        for update in parse_message(fields, &text) {
+            if sender.send(update).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,463 @@
+use crate::accounts;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::MobinOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use reqwest::header::{
+    ACCEPT, ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HeaderMap, HeaderValue, ORIGIN,
+    REFERER, USER_AGENT,
+};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Instant;
+
+/// Mobin Sarmaye's online trading accounts.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MobinBrokersConfig {
+    pub brokers: Vec<MobinBrokerConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MobinBrokerConfig {
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Unused when `accounts` are listed; each account sets its own.
+    #[serde(default)]
+    pub cookie: String,
+    /// The `XSRF-TOKEN` of the session, echoed in the `X-XSRF-TOKEN`
+    /// header; left out when empty.
+    #[serde(default)]
+    pub xsrf_token: String,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    pub order_url: String,
+    /// The trading front-end, sent as `Origin` and `Referer`.
+    pub origin: String,
+    pub orders: Vec<OrderEntry<MobinOrderData>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
+    /// One copy of this broker per entry of `accounts`, each with the
+    /// account's own credentials.
+    #[serde(default)]
+    pub accounts: Vec<MobinBrokerConfig>,
+}
+
+fn default_name() -> String {
+    "Mobin".to_string()
+}
+
+fn default_user_agent() -> String {
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36".to_string()
+}
+
+fn default_validity_type() -> String {
+    "Day".to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct MobinOrderData {
+    #[serde(rename = "instrumentIsin", alias = "isin", default)]
+    pub isin: String,
+    /// `Buy` or `Sell`.
+    #[serde(rename = "orderSide")]
+    pub order_side: String,
+    pub price: i64,
+    pub quantity: i64,
+    /// `Day`, `GoodTillDate`, `GoodTillCancelled` or `FillAndKill`.
+    #[serde(rename = "validityType", default = "default_validity_type")]
+    pub validity_type: String,
+    #[serde(rename = "validityDate", default)]
+    pub validity_date: Option<String>,
+    #[serde(rename = "disclosedQuantity", default)]
+    pub disclosed_quantity: Option<i64>,
+    /// Sell shares the account does not hold yet, on accounts allowed to.
+    #[serde(rename = "isShortSell", default)]
+    pub is_short_sell: bool,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
+}
+
+/// Load `config_mobin.json`, accepting either a `brokers` array or a single
+/// broker object.
+pub fn load_config(path: &str) -> Result<MobinBrokersConfig> {
+    let config_str = encryption::read_config(path)?;
+    let mut value: serde_json::Value =
+        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+    accounts::expand(&mut value)?;
+    let mut config: MobinBrokersConfig = if value.get("brokers").is_some() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(|broker| MobinBrokersConfig {
+            brokers: vec![broker],
+        })
+    }
+    .with_context(|| format!("Failed to parse {}", path))?;
+    for broker in &mut config.brokers {
+        secrets::resolve(&mut broker.cookie)?;
+        secrets::resolve(&mut broker.xsrf_token)?;
+        for account in &mut broker.accounts {
+            secrets::resolve(&mut account.cookie)?;
+            secrets::resolve(&mut account.xsrf_token)?;
+        }
+    }
+    Ok(config)
+}
+
+pub fn find_broker<'a>(
+    config: &'a MobinBrokersConfig,
+    name: &str,
+) -> Option<&'a MobinBrokerConfig> {
+    accounts::find(&config.brokers, name)
+}
+
+impl OrderFields for MobinOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.isin)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.isin = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn price(&self) -> Option<f64> {
+        Some(self.price as f64)
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.price = price.round() as i64;
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        u64::try_from(self.quantity).ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.quantity = quantity as i64;
+    }
+
+    fn side(&self) -> OrderSide {
+        if self.order_side.eq_ignore_ascii_case("sell") {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }
+    }
+}
+
+impl Broker for MobinBrokerConfig {
+    type Order = MobinOrderData;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn config_file(&self) -> &str {
+        "config_mobin.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<MobinOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<MobinOrderData>> {
+        &mut self.orders
+    }
+
+    fn accounts(&self) -> &[Self] {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self] {
+        &mut self.accounts
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        if self.cookie.is_empty() {
+            anyhow::bail!(
+                "Cookie is required for {}. Please set 'cookie' in config_mobin.json",
+                self.name
+            );
+        }
+
+        println!("[{}] Using Cookie authentication", self.name);
+        println!(
+            "[{}] Cookie preview: {}...",
+            self.name,
+            &self.cookie[..self.cookie.len().min(50)]
+        );
+        if self.xsrf_token.is_empty() {
+            println!(
+                "[{}] No xsrf_token set; orders may be refused with 400 or 403",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(self, client)
+    }
+}
+
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(broker: &MobinBrokerConfig, order_json: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/plain, */*"),
+    );
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("fa-IR,fa;q=0.9,en-US;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
+    headers.insert(
+        REFERER,
+        HeaderValue::from_str(&format!("{}/", broker.origin.trim_end_matches('/')))?,
+    );
+    headers.insert(
+        "X-Requested-With",
+        HeaderValue::from_static("XMLHttpRequest"),
+    );
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
+    if !broker.xsrf_token.is_empty() {
+        headers.insert("X-XSRF-TOKEN", HeaderValue::from_str(&broker.xsrf_token)?);
+    }
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/json;charset=UTF-8"),
+    );
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// Build the request for `order_json` and send it.
+pub async fn send_order(
+    broker: &MobinBrokerConfig,
+    order_json: &str,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let request = PreparedRequest::new(
+        &broker.order_url,
+        broker.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(broker, &request, test_mode, curl_only, rate_limiter).await
+}
+
+/// Send a request built by [`Broker::prepare`].
+pub async fn send_request(
+    broker: &MobinBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = broker.settings.client();
+
+    // Print curl command in test or curl-only mode
+    if test_mode || curl_only {
+        let xsrf_line = if broker.xsrf_token.is_empty() {
+            String::new()
+        } else {
+            format!("  -H 'X-XSRF-TOKEN: {}' \\\n", broker.xsrf_token)
+        };
+        println!("[{}] Equivalent curl command:", broker.name);
+        println!(
+            r#"curl '{}' \
+  --compressed \
+  -X POST \
+  -H 'User-Agent: {}' \
+  -H 'Accept: application/json, text/plain, */*' \
+  -H 'Accept-Language: fa-IR,fa;q=0.9,en-US;q=0.5' \
+  -H 'Accept-Encoding: gzip, deflate, br, zstd' \
+  -H 'Content-Type: application/json;charset=UTF-8' \
+  -H 'Origin: {}' \
+  -H 'Referer: {}/' \
+  -H 'X-Requested-With: XMLHttpRequest' \
+  -H 'Connection: keep-alive' \
+  -H 'Cookie: {}' \
+{}  -H 'Sec-Fetch-Dest: empty' \
+  -H 'Sec-Fetch-Mode: cors' \
+  -H 'Sec-Fetch-Site: same-origin' \
+  -H 'Pragma: no-cache' \
+  -H 'Cache-Control: no-cache' \
+  --data-raw '{}'"#,
+            broker.order_url,
+            broker.user_agent,
+            broker.origin,
+            broker.origin.trim_end_matches('/'),
+            broker.cookie,
+            xsrf_line,
+            order_json
+        );
+        println!();
+
+        // If curl_only, don't send the request
+        if curl_only {
+            return Ok(());
+        }
+    }
+
+    let (url, headers) = request.parts();
+
+    let mut timing = console::Timing::start();
+    if let Some(limiter) = rate_limiter {
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
+    }
+    timing.mark("rate limit");
+
+    console::debug_headers(&broker.name, "Request", &headers);
+    let mut capture = Capture::start(&broker.name, &url, &headers, &request.body);
+
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[{}] Sent order JSON: {}", broker.name, order_json);
+    let response = capture.sent(response)?;
+
+    let status = response.status();
+    console::debug_headers(&broker.name, "Response", response.headers());
+    capture.response_head(status, response.headers());
+    let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
+
+    let decoded_text = if response_text.contains("\\u") {
+        crate::decode_unicode_escapes(&response_text)
+    } else {
+        response_text.clone()
+    };
+
+    println!("[{}] Order response status: {}", broker.name, status);
+    println!("[{}] Order response body: {}", broker.name, decoded_text);
+    timing.report(&broker.name);
+
+    if let Some(file) = &broker.settings.response_schema_file {
+        schema_drift::check(&broker.name, file, status, &decoded_text);
+    }
+    success::check_response_as::<MobinOrderResponse>(
+        &broker.settings.success_rules,
+        status,
+        decoded_text,
+    )
+}
+
+pub async fn run_calibration(
+    broker: &MobinBrokerConfig,
+    client: &reqwest::Client,
+    rate_limiter: &RateLimiter,
+) -> Result<calibration::CalibrationSummary> {
+    let calibration = broker
+        .settings
+        .calibration
+        .as_ref()
+        .context("Calibration config missing")?;
+
+    let prefix = format!("[{}]", broker.name);
+    calibration::run_calibration(&prefix, calibration, rate_limiter, || {
+        send_probe(broker, client)
+    })
+    .await
+}
+
+async fn send_probe(
+    broker: &MobinBrokerConfig,
+    client: &reqwest::Client,
+) -> Result<(u64, u128, StatusCode)> {
+    let t0 = Instant::now();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
+
+    let base_url = calibration::probe_url(&broker.order_url)?;
+    let response = client.head(base_url).headers(headers).send().await?;
+    let status = response.status();
+
+    let rtt = t0.elapsed();
+    let rtt_micros = rtt.as_micros();
+    let rtt_ms = rtt.as_millis() as u64;
+
+    Ok((rtt_ms, rtt_micros, status))
+}
@@ -1,12 +1,23 @@
-use crate::calibration::{self, CalibrationConfig};
-use crate::rate_limiter::RateLimiter;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::mofid_login::{self, MofidLoginConfig, MofidSession};
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::MofidOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
 use anyhow::{Context, Result};
+use reqwest::StatusCode;
 use reqwest::header::{
-    HeaderMap, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE,
-    COOKIE, ORIGIN, REFERER, USER_AGENT,
+    ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HeaderMap,
+    HeaderValue, ORIGIN, REFERER, USER_AGENT,
 };
-use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::future::Future;
 use std::time::Instant;
 
 #[derive(Debug, Deserialize, Clone)]
@@ -19,18 +30,25 @@ pub struct MofidConfig {
     pub user_agent: String,
     #[serde(default = "default_order_url")]
     pub order_url: String,
-    pub orders: Vec<MofidOrderData>,
-    #[serde(default = "default_batch_delay")]
-    pub batch_delay_ms: u64,
-    #[serde(default = "default_batch_repeat")]
-    pub batch_repeat: usize,
+    pub orders: Vec<OrderEntry<MofidOrderData>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
     #[serde(default)]
-    pub target_time: Option<String>,
-    #[serde(default)]
-    pub calibration: Option<CalibrationConfig>,
+    pub login: Option<MofidLoginConfig>,
+    #[serde(skip)]
+    pub session: MofidSession,
+}
+
+impl MofidConfig {
+    /// The token of the last login during the run, or the configured one.
+    pub fn current_authorization(&self) -> String {
+        self.session
+            .get()
+            .unwrap_or_else(|| self.authorization.clone())
+    }
 }
 
-fn default_user_agent() -> String {
+pub fn default_user_agent() -> String {
     "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36".to_string()
 }
 
@@ -38,21 +56,13 @@ fn default_order_url() -> String {
     "https://mofidonline.com/apigateway/api/v1/Order/send".to_string()
 }
 
-fn default_batch_delay() -> u64 {
-    100
-}
-
-fn default_batch_repeat() -> usize {
-    1
-}
-
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct MofidOrderData {
     #[serde(rename = "orderSide")]
     pub order_side: String,
     pub price: i64,
     pub quantity: i64,
-    #[serde(rename = "symbolIsin")]
+    #[serde(default, rename = "symbolIsin")]
     pub symbol_isin: String,
     #[serde(rename = "validityType")]
     pub validity_type: i32,
@@ -60,35 +70,262 @@ pub struct MofidOrderData {
     pub validity_date: Option<String>,
     #[serde(rename = "orderFrom")]
     pub order_from: String,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
 }
 
+pub fn load_config(path: &str) -> Result<MofidConfig> {
+    let config_str = encryption::read_config(path)?;
+    let mut config: MofidConfig =
+        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+    secrets::resolve(&mut config.cookie)?;
+    secrets::resolve(&mut config.authorization)?;
+    if let Some(login) = &mut config.login {
+        secrets::resolve(&mut login.password)?;
+        if let Some(totp) = &mut login.totp {
+            secrets::resolve(&mut totp.secret)?;
+        }
+    }
+    Ok(config)
+}
+impl OrderFields for MofidOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.symbol_isin)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.symbol_isin = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn price(&self) -> Option<f64> {
+        Some(self.price as f64)
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.price = price.round() as i64;
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        u64::try_from(self.quantity).ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.quantity = quantity as i64;
+    }
+
+    fn side(&self) -> OrderSide {
+        if self.order_side.eq_ignore_ascii_case("sell") {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }
+    }
+}
+
+impl Broker for MofidConfig {
+    type Order = MofidOrderData;
+
+    fn name(&self) -> &str {
+        "Mofid"
+    }
+
+    fn config_file(&self) -> &str {
+        "config_mofid.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<MofidOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<MofidOrderData>> {
+        &mut self.orders
+    }
+
+    async fn relogin(&self) -> Result<bool> {
+        // A configured cookie takes precedence over any token.
+        if !self.login.as_ref().is_some_and(|login| login.relogin) || uses_cookie(self) {
+            return Ok(false);
+        }
+        mofid_login::relogin(self).await?;
+        Ok(true)
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        let use_cookie = !self.cookie.is_empty() && self.cookie != "PASTE_YOUR_COOKIE_HERE";
+        let use_auth = !self.authorization.is_empty();
+
+        if use_cookie {
+            println!("[Mofid] Using Cookie authentication");
+            println!(
+                "[Mofid] Cookie preview: {}...",
+                &self.cookie[..self.cookie.len().min(50)]
+            );
+        } else if use_auth {
+            println!("[Mofid] Using Authorization header");
+            println!(
+                "[Mofid] Authorization preview: Bearer {}...",
+                &self.authorization[..self.authorization.len().min(30)]
+            );
+        } else {
+            anyhow::bail!(
+                "No authentication method configured. Please set either 'cookie' or 'authorization' in config_mofid.json"
+            );
+        }
+        Ok(())
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(client, self)
+    }
+}
+
+fn uses_cookie(config: &MofidConfig) -> bool {
+    !config.cookie.is_empty() && config.cookie != "PASTE_YOUR_COOKIE_HERE"
+}
+
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(config: &MofidConfig, order_json: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/plain, */*"),
+    );
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("en-US,en;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(
+        REFERER,
+        HeaderValue::from_static("https://tg.mofidonline.com/"),
+    );
+
+    if uses_cookie(config) {
+        headers.insert(COOKIE, HeaderValue::from_str(&config.cookie)?);
+    } else if !config.current_authorization().is_empty() {
+        let authorization = config.current_authorization();
+        let token = authorization
+            .strip_prefix("Bearer ")
+            .unwrap_or(&authorization);
+
+        let auth_value = format!("Bearer {}", token);
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_value)?);
+    }
+
+    headers.insert("x-appname", HeaderValue::from_static("titan"));
+    headers.insert(
+        ORIGIN,
+        HeaderValue::from_static("https://tg.mofidonline.com"),
+    );
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
+    headers.insert("Priority", HeaderValue::from_static("u=0"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// Build the request for `order_json` and send it.
 pub async fn send_order(
     config: &MofidConfig,
-    order: &MofidOrderData,
+    order_json: &str,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let request = PreparedRequest::new(
+        &config.order_url,
+        config.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(config, &request, test_mode, curl_only, rate_limiter).await
+}
+
+/// Send a request built by [`Broker::prepare`].
+pub async fn send_request(
+    config: &MofidConfig,
+    request: &PreparedRequest,
     test_mode: bool,
     curl_only: bool,
     rate_limiter: Option<&RateLimiter>,
 ) -> Result<()> {
-    let client = reqwest::Client::new();
+    let order_json = request.body_text();
+    let client = config.settings.client();
 
-    let use_cookie = !config.cookie.is_empty() && config.cookie != "PASTE_YOUR_COOKIE_HERE";
-    let order_json = serde_json::to_string(order)?;
+    let use_cookie = uses_cookie(config);
 
-    // Print curl command in test mode
-    if test_mode {
+    // Print curl command in test or curl-only mode
+    if test_mode || curl_only {
         let auth_header = if use_cookie {
             format!("-H 'Cookie: {}'", config.cookie)
         } else {
-            let token = config
-                .authorization
+            let authorization = config.current_authorization();
+            let token = authorization
                 .strip_prefix("Bearer ")
-                .unwrap_or(&config.authorization);
+                .unwrap_or(&authorization);
 
             let auth_value = format!("Bearer {}", token);
             format!("-H 'Authorization: Bearer {}'", auth_value)
         };
         println!("[Mofid] Equivalent curl command:");
-        println!(r#"curl '{}' \
+        println!(
+            r#"curl '{}' \
   --compressed \
   -X POST \
   -H 'User-Agent: {}' \
@@ -108,7 +345,8 @@ pub async fn send_order(
   -H 'Pragma: no-cache' \
   -H 'Cache-Control: no-cache' \
   --data-raw '{}'"#,
-            config.order_url, config.user_agent, auth_header, order_json);
+            config.order_url, config.user_agent, auth_header, order_json
+        );
         println!();
 
         // If curl_only, don't send the request
@@ -117,54 +355,45 @@ pub async fn send_order(
         }
     }
 
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
-    headers.insert(ACCEPT, HeaderValue::from_static("application/json, text/plain, */*"));
-    headers.insert("Accept-Language", HeaderValue::from_static("en-US,en;q=0.5"));
-    headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip, deflate, br, zstd"));
-    headers.insert(REFERER, HeaderValue::from_static("https://tg.mofidonline.com/"));
-
-    if use_cookie {
-        headers.insert(COOKIE, HeaderValue::from_str(&config.cookie)?);
-    } else if !config.authorization.is_empty() {
-        let token = config
-        .authorization
-        .strip_prefix("Bearer ")
-        .unwrap_or(&config.authorization);
-
-        let auth_value = format!("Bearer {}", token);
-        headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_value)?);
+    let (url, mut headers) = request.parts();
+    // Prepared before a login during the run, with the old token.
+    if let Some(token) = config.session.get().filter(|_| !use_cookie) {
+        let token = token.strip_prefix("Bearer ").unwrap_or(&token);
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token))?,
+        );
     }
 
+    let mut timing = console::Timing::start();
     if let Some(limiter) = rate_limiter {
-        limiter.wait().await;
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
     }
-
-    headers.insert("x-appname", HeaderValue::from_static("titan"));
-    headers.insert(ORIGIN, HeaderValue::from_static("https://tg.mofidonline.com"));
-    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
-    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
-    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
-    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
-    headers.insert("Priority", HeaderValue::from_static("u=0"));
-    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
-    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
-
-    let body_bytes = order_json.as_bytes();
-
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    headers.insert(CONTENT_LENGTH, HeaderValue::from_str(&body_bytes.len().to_string())?);
-
-    println!("[Mofid] Sending order JSON: {}", order_json);
-
-    let response = client.post(&config.order_url)
-        .headers(headers)
-        .body(order_json)
-        .send()
-        .await?;
+    timing.mark("rate limit");
+
+    console::debug_headers("Mofid", "Request", &headers);
+    let mut capture = Capture::start("Mofid", &url, &headers, &request.body);
+
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[Mofid] Sent order JSON: {}", order_json);
+    let response = capture.sent(response)?;
 
     let status = response.status();
+    console::debug_headers("Mofid", "Response", response.headers());
+    capture.response_head(status, response.headers());
     let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
 
     let decoded_text = if response_text.contains("\\u") {
         crate::decode_unicode_escapes(&response_text)
@@ -174,12 +403,16 @@ pub async fn send_order(
 
     println!("[Mofid] Order response status: {}", status);
     println!("[Mofid] Order response body: {}", decoded_text);
+    timing.report("Mofid");
 
-    if !status.is_success() {
-        anyhow::bail!("Order failed with status {}: {}", status, decoded_text);
+    if let Some(file) = &config.settings.response_schema_file {
+        schema_drift::check("Mofid", file, status, &decoded_text);
     }
-
-    Ok(())
+    success::check_response_as::<MofidOrderResponse>(
+        &config.settings.success_rules,
+        status,
+        decoded_text,
+    )
 }
 
 pub async fn run_calibration(
@@ -188,6 +421,7 @@ pub async fn run_calibration(
     rate_limiter: &RateLimiter,
 ) -> Result<calibration::CalibrationSummary> {
     let calibration = config
+        .settings
         .calibration
         .as_ref()
         .context("Calibration config missing")?;
@@ -208,14 +442,14 @@ async fn send_probe(
     headers.insert(USER_AGENT, HeaderValue::from_str(&config.user_agent)?);
     headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
 
-    let use_cookie = !config.cookie.is_empty() && config.cookie != "PASTE_YOUR_COOKIE_HERE";
+    let use_cookie = uses_cookie(config);
     if use_cookie {
         headers.insert(COOKIE, HeaderValue::from_str(&config.cookie)?);
-    } else if !config.authorization.is_empty() {
-        let token = config
-            .authorization
+    } else if !config.current_authorization().is_empty() {
+        let authorization = config.current_authorization();
+        let token = authorization
             .strip_prefix("Bearer ")
-            .unwrap_or(&config.authorization);
+            .unwrap_or(&authorization);
         let auth_value = format!("Bearer {}", token);
         headers.insert(AUTHORIZATION, HeaderValue::from_str(&auth_value)?);
     }
@@ -0,0 +1,265 @@
+use crate::captcha::{CaptchaConfig, CaptchaSolver, ConfiguredSolver};
+use crate::login;
+use crate::mofid::MofidConfig;
+use crate::runner::Broker;
+use crate::totp::TotpConfig;
+use anyhow::{Context, Result};
+use reqwest::header::{COOKIE, HeaderValue, USER_AGENT};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::{Arc, RwLock};
+
+fn default_token_url() -> String {
+    "https://account.emofid.com/connect/token".to_string()
+}
+
+fn default_client_id() -> String {
+    "titan".to_string()
+}
+
+fn default_scope() -> String {
+    "openid profile offline_access".to_string()
+}
+
+fn default_otp_field() -> String {
+    "otp".to_string()
+}
+
+fn default_captcha_field() -> String {
+    "captcha".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `login` section of `config_mofid.json`: credentials and the OAuth token
+/// endpoint used by `login mofid`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MofidLoginConfig {
+    pub username: String,
+    /// Prompted for on the console when empty.
+    #[serde(default)]
+    pub password: String,
+    #[serde(default = "default_token_url")]
+    pub token_url: String,
+    #[serde(default = "default_client_id")]
+    pub client_id: String,
+    #[serde(default = "default_scope")]
+    pub scope: String,
+    /// Form field carrying the one-time code when the account has 2FA.
+    #[serde(default = "default_otp_field")]
+    pub otp_field: String,
+    /// Image endpoint to solve before requesting the token, if Mofid asks
+    /// for a captcha.
+    #[serde(default)]
+    pub captcha_url: Option<String>,
+    #[serde(default = "default_captcha_field")]
+    pub captcha_field: String,
+    #[serde(default)]
+    pub captcha: CaptchaConfig,
+    /// Answers the one-time code prompt automatically.
+    #[serde(default)]
+    pub totp: Option<TotpConfig>,
+    /// Log in again when orders fail with an expired session mid-run.
+    #[serde(default = "default_true")]
+    pub relogin: bool,
+    /// Write the token of such a login back to `config_mofid.json`.
+    #[serde(default = "default_true")]
+    pub save_token: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct MofidToken {
+    pub access_token: String,
+    pub expires_in_secs: Option<u64>,
+}
+
+/// Token obtained by logging in during a run, shared by every clone of the
+/// config so orders already prepared pick it up.
+#[derive(Debug, Clone, Default)]
+pub struct MofidSession(Arc<RwLock<Option<String>>>);
+
+impl MofidSession {
+    pub fn get(&self) -> Option<String> {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set(&self, access_token: String) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(access_token);
+    }
+}
+
+/// Run `login mofid`: authenticate and store the token as `authorization` in
+/// the config file.
+pub async fn run_login(config_path: &str, user_agent: &str) -> Result<()> {
+    let config = crate::mofid::load_config(config_path)?;
+    let login_config = config.login.as_ref().with_context(|| {
+        format!(
+            "No 'login' section in {}; add username (and optionally password)",
+            config_path
+        )
+    })?;
+    let token = login_with_prompt(login_config, user_agent).await?;
+
+    login::update_config_fields(
+        config_path,
+        None,
+        &[("authorization", Value::String(token.access_token))],
+    )?;
+    println!("[Mofid] Saved new token to {}", config_path);
+    if let Some(expires_in) = token.expires_in_secs {
+        println!(
+            "[Mofid] Token expires in {}h {}m",
+            expires_in / 3600,
+            expires_in % 3600 / 60
+        );
+    }
+    if !config.cookie.is_empty() && config.cookie != "PASTE_YOUR_COOKIE_HERE" {
+        println!("[Mofid] Note: 'cookie' is set and takes precedence; clear it to use the token.");
+    }
+    Ok(())
+}
+
+/// Log in again after the session expired mid-run and make the new token
+/// the one every later order uses.
+pub async fn relogin(config: &MofidConfig) -> Result<()> {
+    let login_config = config
+        .login
+        .as_ref()
+        .context("No 'login' section in config_mofid.json")?;
+    let token = login_with_prompt(login_config, &config.user_agent).await?;
+    if login_config.save_token {
+        match login::update_config_fields(
+            config.config_file(),
+            None,
+            &[("authorization", Value::String(token.access_token.clone()))],
+        ) {
+            Ok(()) => println!("[Mofid] Saved new token to {}", config.config_file()),
+            Err(e) => summary!("[Mofid] Warning: could not save new token: {:#}", e),
+        }
+    }
+    config.session.set(token.access_token);
+    Ok(())
+}
+
+/// Log in with `login_config`, asking on the console for the password when
+/// it is not configured and for a one-time code when there is no `totp`.
+async fn login_with_prompt(
+    login_config: &MofidLoginConfig,
+    user_agent: &str,
+) -> Result<MofidToken> {
+    let mut login_config = login_config.clone();
+    if login_config.password.is_empty() {
+        login_config.password = login::prompt_line("[Mofid] Password: ").await?;
+    }
+
+    println!(
+        "[Mofid] Logging in as {} via {}",
+        login_config.username, login_config.token_url
+    );
+    let solver = ConfiguredSolver::from_config(&login_config.captcha)?;
+    let totp = login_config.totp.clone();
+    login(&login_config, user_agent, &solver, move || {
+        one_time_code(totp.clone())
+    })
+    .await
+}
+
+/// A code from `totp`, or typed in on the console without one. Owns its
+/// arguments so the login future stays `Send` inside a broker's run.
+async fn one_time_code(totp: Option<TotpConfig>) -> Result<String> {
+    match totp {
+        Some(totp) => {
+            println!("[Mofid] Using TOTP code");
+            totp.fresh_code().await
+        }
+        None => login::prompt_line("[Mofid] Enter the one-time code: ").await,
+    }
+}
+
+/// Request a token with the password grant, prompting through `otp` when the
+/// server asks for a one-time code.
+pub async fn login(
+    config: &MofidLoginConfig,
+    user_agent: &str,
+    solver: &impl CaptchaSolver,
+    mut otp: impl AsyncFnMut() -> Result<String>,
+) -> Result<MofidToken> {
+    let client = reqwest::Client::new();
+    let mut form = vec![
+        ("grant_type".to_string(), "password".to_string()),
+        ("client_id".to_string(), config.client_id.clone()),
+        ("scope".to_string(), config.scope.clone()),
+        ("username".to_string(), config.username.clone()),
+        ("password".to_string(), config.password.clone()),
+    ];
+
+    let mut cookie = String::new();
+    if let Some(captcha_url) = &config.captcha_url {
+        let (captcha, captcha_cookie) =
+            login::fetch_captcha(&client, captcha_url, user_agent).await?;
+        cookie = captcha_cookie;
+        let answer = solver.solve(&captcha).await?;
+        form.push((config.captcha_field.clone(), answer));
+    }
+
+    let mut otp_sent = false;
+    loop {
+        let mut request = client
+            .post(&config.token_url)
+            .header(USER_AGENT, HeaderValue::from_str(user_agent)?)
+            .form(&form);
+        if !cookie.is_empty() {
+            request = request.header(COOKIE, HeaderValue::from_str(&cookie)?);
+        }
+        let response = request.send().await.context("Mofid token request failed")?;
+        let status = response.status();
+        let body = response.text().await?;
+        let value: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+
+        if status.is_success() {
+            let access_token = value
+                .get("access_token")
+                .and_then(Value::as_str)
+                .with_context(|| format!("Token response has no access_token: {}", body))?;
+            return Ok(MofidToken {
+                access_token: access_token.to_string(),
+                expires_in_secs: value.get("expires_in").and_then(Value::as_u64),
+            });
+        }
+
+        if !otp_sent && requires_otp(&value, &body) {
+            println!("[Mofid] One-time code required");
+            form.push((config.otp_field.clone(), otp().await?));
+            otp_sent = true;
+            continue;
+        }
+
+        let message = value
+            .get("error_description")
+            .or_else(|| value.get("error"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| crate::decode_unicode_escapes(&body));
+        anyhow::bail!("Mofid login failed with status {}: {}", status, message);
+    }
+}
+
+fn requires_otp(value: &Value, body: &str) -> bool {
+    let error = value
+        .get("error")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_lowercase();
+    error.contains("otp")
+        || error.contains("two_factor")
+        || error.contains("2fa")
+        || body.to_lowercase().contains("otp_required")
+}
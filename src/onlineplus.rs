@@ -0,0 +1,600 @@
+//! OnlinePlus, the trading front-end of Sahm Ashna and several other
+//! brokers. Its ASP.NET API refuses posts without the anti-forgery token of
+//! the session, sent as `__RequestVerificationToken` next to the cookie;
+//! with `csrf_token` left empty it is read from the trading page before the
+//! first order.
+
+use crate::accounts;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::login;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::OnlinePlusOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use reqwest::header::{
+    ACCEPT, ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HeaderMap, HeaderValue, ORIGIN,
+    REFERER, USER_AGENT,
+};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+/// Header and form field carrying the anti-forgery token.
+pub const CSRF_HEADER: &str = "__RequestVerificationToken";
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OnlinePlusBrokersConfig {
+    pub brokers: Vec<OnlinePlusBrokerConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct OnlinePlusBrokerConfig {
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Unused when `accounts` are listed; each account sets its own.
+    #[serde(default)]
+    pub cookie: String,
+    /// The `__RequestVerificationToken` of the session; read from
+    /// `csrf_page_url` when empty.
+    #[serde(default)]
+    pub csrf_token: String,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    pub order_url: String,
+    /// The trading front-end, sent as `Origin` and `Referer`.
+    pub origin: String,
+    /// The page holding the token in a hidden form field; needed to read
+    /// the token when `csrf_token` is empty.
+    #[serde(default)]
+    pub csrf_page_url: Option<String>,
+    pub orders: Vec<OrderEntry<OnlinePlusOrderData>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
+    #[serde(skip)]
+    pub session: OnlinePlusSession,
+    /// One copy of this broker per entry of `accounts`, each with the
+    /// account's own credentials.
+    #[serde(default)]
+    pub accounts: Vec<OnlinePlusBrokerConfig>,
+}
+
+fn default_name() -> String {
+    "SahmAshna".to_string()
+}
+
+fn default_user_agent() -> String {
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36".to_string()
+}
+
+fn default_validity() -> String {
+    "Day".to_string()
+}
+
+/// The cookie and anti-forgery token an order goes out with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OnlinePlusCredentials {
+    pub cookie: String,
+    pub csrf_token: String,
+}
+
+/// The token read from the trading page, with the cookie it was issued
+/// for, shared by every clone of the config.
+#[derive(Debug, Clone, Default)]
+pub struct OnlinePlusSession(Arc<RwLock<Option<OnlinePlusCredentials>>>);
+
+impl OnlinePlusSession {
+    pub fn get(&self) -> Option<OnlinePlusCredentials> {
+        self.0
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    fn set(&self, credentials: OnlinePlusCredentials) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(credentials);
+    }
+}
+
+impl OnlinePlusBrokerConfig {
+    /// The credentials read from the trading page, or the configured ones.
+    pub fn credentials(&self) -> OnlinePlusCredentials {
+        self.session.get().unwrap_or_else(|| OnlinePlusCredentials {
+            cookie: self.cookie.clone(),
+            csrf_token: self.csrf_token.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct OnlinePlusOrderData {
+    #[serde(rename = "Isin", alias = "isin", default)]
+    pub isin: String,
+    /// `Buy` or `Sell`.
+    #[serde(rename = "Side")]
+    pub side: String,
+    #[serde(rename = "Price")]
+    pub price: i64,
+    #[serde(rename = "Quantity")]
+    pub quantity: i64,
+    /// `Day`, `GoodTillDate`, `GoodTillCancelled` or `FillAndKill`.
+    #[serde(rename = "Validity", default = "default_validity")]
+    pub validity: String,
+    #[serde(rename = "ValidityDate", default)]
+    pub validity_date: Option<String>,
+    #[serde(rename = "MaxShow", default)]
+    pub max_show: i64,
+    #[serde(rename = "MinQuantity", default)]
+    pub min_quantity: i64,
+    /// Buy on the broker's credit instead of the account's cash.
+    #[serde(rename = "UseCredit", default)]
+    pub use_credit: bool,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
+}
+
+/// Load `config_onlineplus.json`, accepting either a `brokers` array or a
+/// single broker object.
+pub fn load_config(path: &str) -> Result<OnlinePlusBrokersConfig> {
+    let config_str = encryption::read_config(path)?;
+    let mut value: serde_json::Value =
+        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+    accounts::expand(&mut value)?;
+    let mut config: OnlinePlusBrokersConfig = if value.get("brokers").is_some() {
+        serde_json::from_value(value)
+    } else {
+        serde_json::from_value(value).map(|broker| OnlinePlusBrokersConfig {
+            brokers: vec![broker],
+        })
+    }
+    .with_context(|| format!("Failed to parse {}", path))?;
+    for broker in &mut config.brokers {
+        secrets::resolve(&mut broker.cookie)?;
+        secrets::resolve(&mut broker.csrf_token)?;
+        for account in &mut broker.accounts {
+            secrets::resolve(&mut account.cookie)?;
+            secrets::resolve(&mut account.csrf_token)?;
+        }
+    }
+    Ok(config)
+}
+
+pub fn find_broker<'a>(
+    config: &'a OnlinePlusBrokersConfig,
+    name: &str,
+) -> Option<&'a OnlinePlusBrokerConfig> {
+    accounts::find(&config.brokers, name)
+}
+
+impl OrderFields for OnlinePlusOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.isin)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.isin = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn price(&self) -> Option<f64> {
+        Some(self.price as f64)
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.price = price.round() as i64;
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        u64::try_from(self.quantity).ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.quantity = quantity as i64;
+    }
+
+    fn side(&self) -> OrderSide {
+        if self.side.eq_ignore_ascii_case("sell") {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }
+    }
+}
+
+impl Broker for OnlinePlusBrokerConfig {
+    type Order = OnlinePlusOrderData;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn config_file(&self) -> &str {
+        "config_onlineplus.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<OnlinePlusOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<OnlinePlusOrderData>> {
+        &mut self.orders
+    }
+
+    fn accounts(&self) -> &[Self] {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self] {
+        &mut self.accounts
+    }
+
+    async fn ensure_session(&self) -> Result<()> {
+        if self.credentials().csrf_token.is_empty() && !self.cookie.is_empty() {
+            let credentials = fetch_csrf_token(self).await?;
+            println!(
+                "[{}] Read {} from {}",
+                self.name,
+                CSRF_HEADER,
+                self.csrf_page_url.as_deref().unwrap_or_default()
+            );
+            self.session.set(credentials);
+        }
+        Ok(())
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        let credentials = self.credentials();
+        if credentials.cookie.is_empty() {
+            anyhow::bail!(
+                "Cookie is required for {}. Please set 'cookie' in config_onlineplus.json",
+                self.name
+            );
+        }
+
+        println!("[{}] Using Cookie authentication", self.name);
+        println!(
+            "[{}] Cookie preview: {}...",
+            self.name,
+            &credentials.cookie[..credentials.cookie.len().min(50)]
+        );
+        if credentials.csrf_token.is_empty() {
+            println!(
+                "[{}] No csrf_token; orders will be refused with 400 or 403",
+                self.name
+            );
+        }
+        Ok(())
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, &self.credentials(), order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(self, client)
+    }
+}
+
+/// GET the trading page with the session cookie and read the anti-forgery
+/// token from its hidden form field. The page may set the anti-forgery
+/// cookie the token belongs to; it is added to the session cookie.
+pub async fn fetch_csrf_token(broker: &OnlinePlusBrokerConfig) -> Result<OnlinePlusCredentials> {
+    let url = broker.csrf_page_url.as_deref().with_context(|| {
+        format!(
+            "No csrf_page_url for {} to read {} from; set it or paste csrf_token",
+            broker.name, CSRF_HEADER
+        )
+    })?;
+    let response = broker
+        .settings
+        .client()
+        .get(url)
+        .header(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?)
+        .header(ACCEPT, HeaderValue::from_static("text/html"))
+        .header(COOKIE, HeaderValue::from_str(&broker.cookie)?)
+        .send()
+        .await
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    let status = response.status();
+    let set_cookie = login::cookies_from_response(&response);
+    let page = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "Fetching {} for {} failed with status {}; is the cookie still valid?",
+            url,
+            CSRF_HEADER,
+            status
+        );
+    }
+    let csrf_token = csrf_token_in(&page)
+        .with_context(|| format!("No {} on {}; is the cookie still valid?", CSRF_HEADER, url))?;
+    Ok(OnlinePlusCredentials {
+        cookie: login::merge_cookies(&broker.cookie, &set_cookie),
+        csrf_token,
+    })
+}
+
+/// The value of the `__RequestVerificationToken` input in an HTML page.
+pub fn csrf_token_in(page: &str) -> Option<String> {
+    let start = page.find(&format!("name=\"{}\"", CSRF_HEADER))?;
+    let tag_start = page[..start].rfind('<')?;
+    let tag_end = start + page[start..].find('>')?;
+    let tag = &page[tag_start..tag_end];
+    let value = &tag[tag.find("value=\"")? + "value=\"".len()..];
+    let token = &value[..value.find('"')?];
+    (!token.is_empty()).then(|| token.to_string())
+}
+
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(
+    broker: &OnlinePlusBrokerConfig,
+    credentials: &OnlinePlusCredentials,
+    order_json: &str,
+) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/javascript, */*; q=0.01"),
+    );
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("fa-IR,fa;q=0.9,en-US;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
+    headers.insert(
+        REFERER,
+        HeaderValue::from_str(&format!("{}/", broker.origin.trim_end_matches('/')))?,
+    );
+    headers.insert(
+        "X-Requested-With",
+        HeaderValue::from_static("XMLHttpRequest"),
+    );
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    session_headers(credentials, &mut headers)?;
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-origin"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/json; charset=utf-8"),
+    );
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// The cookie and, when known, the anti-forgery token.
+fn session_headers(credentials: &OnlinePlusCredentials, headers: &mut HeaderMap) -> Result<()> {
+    headers.insert(COOKIE, HeaderValue::from_str(&credentials.cookie)?);
+    headers.remove(CSRF_HEADER);
+    if !credentials.csrf_token.is_empty() {
+        headers.insert(CSRF_HEADER, HeaderValue::from_str(&credentials.csrf_token)?);
+    }
+    Ok(())
+}
+
+/// Build the request for `order_json` and send it.
+pub async fn send_order(
+    broker: &OnlinePlusBrokerConfig,
+    order_json: &str,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let request = PreparedRequest::new(
+        &broker.order_url,
+        broker.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(broker, &request, test_mode, curl_only, rate_limiter).await
+}
+
+/// Send a request built by [`Broker::prepare`].
+pub async fn send_request(
+    broker: &OnlinePlusBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = broker.settings.client();
+    let credentials = broker.credentials();
+
+    // Print curl command in test or curl-only mode
+    if test_mode || curl_only {
+        let csrf_line = if credentials.csrf_token.is_empty() {
+            String::new()
+        } else {
+            format!("  -H '{}: {}' \\\n", CSRF_HEADER, credentials.csrf_token)
+        };
+        println!("[{}] Equivalent curl command:", broker.name);
+        println!(
+            r#"curl '{}' \
+  --compressed \
+  -X POST \
+  -H 'User-Agent: {}' \
+  -H 'Accept: application/json, text/javascript, */*; q=0.01' \
+  -H 'Accept-Language: fa-IR,fa;q=0.9,en-US;q=0.5' \
+  -H 'Accept-Encoding: gzip, deflate, br, zstd' \
+  -H 'Content-Type: application/json; charset=utf-8' \
+  -H 'Origin: {}' \
+  -H 'Referer: {}/' \
+  -H 'X-Requested-With: XMLHttpRequest' \
+  -H 'Connection: keep-alive' \
+  -H 'Cookie: {}' \
+{}  -H 'Sec-Fetch-Dest: empty' \
+  -H 'Sec-Fetch-Mode: cors' \
+  -H 'Sec-Fetch-Site: same-origin' \
+  -H 'Pragma: no-cache' \
+  -H 'Cache-Control: no-cache' \
+  --data-raw '{}'"#,
+            broker.order_url,
+            broker.user_agent,
+            broker.origin,
+            broker.origin.trim_end_matches('/'),
+            credentials.cookie,
+            csrf_line,
+            order_json
+        );
+        println!();
+
+        // If curl_only, don't send the request
+        if curl_only {
+            return Ok(());
+        }
+    }
+
+    // Requests built before the token was read go out with it all the same.
+    let (url, mut headers) = request.parts();
+    session_headers(&credentials, &mut headers)?;
+
+    let mut timing = console::Timing::start();
+    if let Some(limiter) = rate_limiter {
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
+    }
+    timing.mark("rate limit");
+
+    console::debug_headers(&broker.name, "Request", &headers);
+    let mut capture = Capture::start(&broker.name, &url, &headers, &request.body);
+
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[{}] Sent order JSON: {}", broker.name, order_json);
+    let response = capture.sent(response)?;
+
+    let status = response.status();
+    console::debug_headers(&broker.name, "Response", response.headers());
+    capture.response_head(status, response.headers());
+    let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
+
+    let decoded_text = if response_text.contains("\\u") {
+        crate::decode_unicode_escapes(&response_text)
+    } else {
+        response_text.clone()
+    };
+
+    println!("[{}] Order response status: {}", broker.name, status);
+    println!("[{}] Order response body: {}", broker.name, decoded_text);
+    timing.report(&broker.name);
+
+    if let Some(file) = &broker.settings.response_schema_file {
+        schema_drift::check(&broker.name, file, status, &decoded_text);
+    }
+    success::check_response_as::<OnlinePlusOrderResponse>(
+        &broker.settings.success_rules,
+        status,
+        decoded_text,
+    )
+}
+
+pub async fn run_calibration(
+    broker: &OnlinePlusBrokerConfig,
+    client: &reqwest::Client,
+    rate_limiter: &RateLimiter,
+) -> Result<calibration::CalibrationSummary> {
+    let calibration = broker
+        .settings
+        .calibration
+        .as_ref()
+        .context("Calibration config missing")?;
+
+    let prefix = format!("[{}]", broker.name);
+    calibration::run_calibration(&prefix, calibration, rate_limiter, || {
+        send_probe(broker, client)
+    })
+    .await
+}
+
+async fn send_probe(
+    broker: &OnlinePlusBrokerConfig,
+    client: &reqwest::Client,
+) -> Result<(u64, u128, StatusCode)> {
+    let t0 = Instant::now();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+    headers.insert(COOKIE, HeaderValue::from_str(&broker.credentials().cookie)?);
+
+    let base_url = calibration::probe_url(&broker.order_url)?;
+    let response = client.head(base_url).headers(headers).send().await?;
+    let status = response.status();
+
+    let rtt = t0.elapsed();
+    let rtt_micros = rtt.as_micros();
+    let rtt_ms = rtt.as_millis() as u64;
+
+    Ok((rtt_ms, rtt_micros, status))
+}
@@ -0,0 +1,337 @@
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::signalr::{Invocation, SignalRConnection};
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+fn default_reconnect_delay_ms() -> u64 {
+    2000
+}
+
+fn default_isin_pointer() -> String {
+    "/isin".to_string()
+}
+
+fn default_order_id_pointer() -> Option<String> {
+    Some("/orderId".to_string())
+}
+
+/// `push_notifications` setting: the broker's SignalR hub, which reports
+/// orders as they are registered and filled.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PushNotificationsConfig {
+    /// `https://` address of the hub, without `/negotiate`.
+    pub hub_url: String,
+    /// Sent on top of the broker's own session headers.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Hub methods called once connected, such as joining the account's
+    /// group.
+    #[serde(default)]
+    pub subscribe: Vec<HubCall>,
+    /// Hub methods the server calls when an order is registered.
+    #[serde(default)]
+    pub accepted: Vec<String>,
+    /// Hub methods the server calls when an order trades.
+    #[serde(default)]
+    pub filled: Vec<String>,
+    #[serde(default)]
+    pub fields: EventFields,
+    /// Wait before connecting again after the hub drops the connection.
+    #[serde(default = "default_reconnect_delay_ms")]
+    pub reconnect_delay_ms: u64,
+}
+
+/// A hub method and its arguments.
+#[derive(Debug, Deserialize, Clone)]
+pub struct HubCall {
+    pub target: String,
+    #[serde(default)]
+    pub arguments: Vec<Value>,
+}
+
+/// JSON pointers into the first argument of an event.
+#[derive(Debug, Deserialize, Clone)]
+pub struct EventFields {
+    #[serde(default = "default_isin_pointer")]
+    pub isin: String,
+    #[serde(default = "default_order_id_pointer")]
+    pub order_id: Option<String>,
+    /// Matches events to orders of one side only; unset, an event matches
+    /// both sides.
+    #[serde(default)]
+    pub side: Option<String>,
+}
+
+impl Default for EventFields {
+    fn default() -> Self {
+        Self {
+            isin: default_isin_pointer(),
+            order_id: default_order_id_pointer(),
+            side: None,
+        }
+    }
+}
+
+/// How far an order got, as the broker pushed it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PushedStatus {
+    Accepted,
+    Filled,
+}
+
+impl std::fmt::Display for PushedStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Accepted => write!(f, "accepted"),
+            Self::Filled => write!(f, "filled"),
+        }
+    }
+}
+
+/// One pushed notification about an order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderEvent {
+    pub status: PushedStatus,
+    pub isin: Option<String>,
+    pub order_id: Option<String>,
+    pub side: Option<OrderSide>,
+}
+
+impl PushNotificationsConfig {
+    /// The order event `invocation` carries; `None` for hub methods that are
+    /// neither `accepted` nor `filled`.
+    pub fn event(&self, invocation: &Invocation) -> Option<OrderEvent> {
+        let status = if self.filled.contains(&invocation.target) {
+            PushedStatus::Filled
+        } else if self.accepted.contains(&invocation.target) {
+            PushedStatus::Accepted
+        } else {
+            return None;
+        };
+        let argument = invocation.arguments.first().unwrap_or(&Value::Null);
+        let text = |pointer: &str| match argument.pointer(pointer)? {
+            Value::String(text) => Some(text.trim().to_string()),
+            Value::Number(number) => Some(number.to_string()),
+            _ => None,
+        };
+        Some(OrderEvent {
+            status,
+            isin: text(&self.fields.isin).filter(|isin| !isin.is_empty()),
+            order_id: self.fields.order_id.as_deref().and_then(text),
+            side: self
+                .fields
+                .side
+                .as_deref()
+                .and_then(|pointer| argument.pointer(pointer))
+                .and_then(OrderSide::from_value),
+        })
+    }
+
+    /// `session` with the configured headers on top.
+    pub fn headers(&self, session: &HeaderMap) -> Result<HeaderMap> {
+        let mut headers = session.clone();
+        for (name, value) in &self.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())
+                    .with_context(|| format!("Invalid push_notifications header '{}'", name))?,
+                HeaderValue::from_str(value)?,
+            );
+        }
+        Ok(headers)
+    }
+}
+
+/// What a pushed event says about each order, by order index.
+type Statuses = Vec<Option<PushedStatus>>;
+
+/// Follows the broker's push notifications for the length of a run and
+/// keeps the furthest status each order reached. The connection closes
+/// when the tracker is dropped.
+pub struct OrderTracker {
+    statuses: watch::Sender<Statuses>,
+    task: JoinHandle<()>,
+}
+
+impl OrderTracker {
+    /// Connect to the hub with the broker's `session` headers and start
+    /// matching its events to `orders` by ISIN, and by side when the event
+    /// carries one. The first connection is made before returning so a
+    /// wrong address or session fails the run.
+    pub async fn start<T: OrderFields>(
+        name: &str,
+        config: &PushNotificationsConfig,
+        session: &HeaderMap,
+        orders: &[OrderEntry<T>],
+    ) -> Result<Self> {
+        let headers = config.headers(session)?;
+        let connection = connect(config, &headers)
+            .await
+            .with_context(|| format!("Failed to connect to push notifications of {}", name))?;
+        println!(
+            "[{}] Push notifications connected: {}",
+            name, config.hub_url
+        );
+
+        let keys: Vec<(Option<String>, OrderSide)> = orders
+            .iter()
+            .map(|order| {
+                (
+                    order.data.isin().map(|isin| isin.trim().to_uppercase()),
+                    order.data.side(),
+                )
+            })
+            .collect();
+        let (statuses, _) = watch::channel(vec![None; orders.len()]);
+        let task = tokio::spawn(follow(
+            name.to_string(),
+            config.clone(),
+            headers,
+            connection,
+            keys,
+            statuses.clone(),
+        ));
+        Ok(Self { statuses, task })
+    }
+
+    /// The furthest status pushed for order `index`.
+    pub fn status(&self, index: usize) -> Option<PushedStatus> {
+        self.statuses.borrow().get(index).copied().flatten()
+    }
+
+    /// Return once the broker pushed that order `index` was registered;
+    /// never when it does not.
+    pub async fn accepted(&self, index: usize) {
+        let mut statuses = self.statuses.subscribe();
+        if statuses
+            .wait_for(|statuses| statuses.get(index).copied().flatten().is_some())
+            .await
+            .is_err()
+        {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Drop for OrderTracker {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn connect(
+    config: &PushNotificationsConfig,
+    headers: &HeaderMap,
+) -> Result<SignalRConnection> {
+    let mut connection = SignalRConnection::connect(&config.hub_url, headers).await?;
+    for call in &config.subscribe {
+        connection.send(&call.target, &call.arguments).await?;
+    }
+    Ok(connection)
+}
+
+/// Record every event until aborted, reconnecting when the hub drops.
+async fn follow(
+    name: String,
+    config: PushNotificationsConfig,
+    headers: HeaderMap,
+    connection: SignalRConnection,
+    keys: Vec<(Option<String>, OrderSide)>,
+    statuses: watch::Sender<Statuses>,
+) {
+    let mut connection = Some(connection);
+    loop {
+        let mut current = match connection.take() {
+            Some(current) => current,
+            None => {
+                tokio::time::sleep(Duration::from_millis(config.reconnect_delay_ms)).await;
+                match connect(&config, &headers).await {
+                    Ok(current) => {
+                        println!("[{}] Push notifications reconnected", name);
+                        current
+                    }
+                    Err(e) => {
+                        eprintln!(
+                            "[{}] Warning: push notifications reconnect failed: {:#}",
+                            name, e
+                        );
+                        continue;
+                    }
+                }
+            }
+        };
+        loop {
+            match current.next_invocation().await {
+                Ok(Some(invocation)) => {
+                    if let Some(event) = config.event(&invocation) {
+                        record(&name, &event, &keys, &statuses);
+                    }
+                }
+                Ok(None) => {
+                    eprintln!("[{}] Warning: push notifications closed", name);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("[{}] Warning: push notifications dropped: {:#}", name, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn record(
+    name: &str,
+    event: &OrderEvent,
+    keys: &[(Option<String>, OrderSide)],
+    statuses: &watch::Sender<Statuses>,
+) {
+    let order_id = event
+        .order_id
+        .as_deref()
+        .map_or(String::new(), |id| format!(", order ID {}", id));
+    let Some(isin) = event.isin.as_deref().map(str::to_uppercase) else {
+        println!(
+            "[{}] Push: an order was {}{} (no ISIN to match it to)",
+            name, event.status, order_id
+        );
+        return;
+    };
+    let mut matched = false;
+    statuses.send_if_modified(|statuses| {
+        let mut changed = false;
+        for (index, (order_isin, side)) in keys.iter().enumerate() {
+            if order_isin.as_deref() != Some(isin.as_str())
+                || event.side.is_some_and(|event_side| event_side != *side)
+            {
+                continue;
+            }
+            matched = true;
+            if statuses[index].is_some_and(|status| status >= event.status) {
+                continue;
+            }
+            statuses[index] = Some(event.status);
+            changed = true;
+            summary!(
+                "[{}] Push: order #{} {} on {}{}",
+                name,
+                index + 1,
+                event.status,
+                isin,
+                order_id
+            );
+        }
+        changed
+    });
+    if !matched {
+        println!(
+            "[{}] Push: an order on {} was {}{}, not one of this run's",
+            name, isin, event.status, order_id
+        );
+    }
+}
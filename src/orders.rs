@@ -0,0 +1,965 @@
+use crate::conditions::SendWhen;
+use crate::jalali;
+use crate::market_data::PriceBand;
+use crate::persian;
+use anyhow::{Context, Result};
+use chrono_tz::Asia::Tehran;
+use rand::Rng;
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Deserializer, IntoDeserializer, MapAccess, Visitor,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderSide {
+    Buy,
+    Sell,
+}
+
+impl OrderSide {
+    /// Read a side however a broker encodes it: `Buy`/`Sell`,
+    /// `SIDE_BUY`/`SIDE_SELL`, TSE's `65`/`86` or `1`/`2`.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::String(side) => match side.trim().to_ascii_lowercase().as_str() {
+                "buy" | "side_buy" => Some(Self::Buy),
+                "sell" | "side_sell" => Some(Self::Sell),
+                _ => None,
+            },
+            Value::Number(side) => match side.as_i64()? {
+                65 | 1 => Some(Self::Buy),
+                86 | 2 => Some(Self::Sell),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for OrderSide {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Buy => write!(f, "buy"),
+            Self::Sell => write!(f, "sell"),
+        }
+    }
+}
+
+/// Whether `code` looks like an ISIN: `IR` followed by ten letters or digits.
+pub fn is_isin(code: &str) -> bool {
+    code.len() == 12 && code.starts_with("IR") && code.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// The fields market data checks need, read from (and written to) each
+/// broker's own payload.
+pub trait OrderFields {
+    fn isin(&self) -> Option<&str>;
+
+    fn set_isin(&mut self, isin: &str);
+
+    /// Symbol such as `فولاد` to look the ISIN up by, if the order sets one.
+    fn symbol(&self) -> Option<&str>;
+
+    fn price(&self) -> Option<f64>;
+
+    /// Replace the order price, in the payload's own representation.
+    fn set_price(&mut self, price: f64);
+
+    fn quantity(&self) -> Option<u64>;
+
+    /// Replace the order quantity, in the payload's own representation.
+    fn set_quantity(&mut self, quantity: u64);
+
+    fn side(&self) -> OrderSide;
+}
+
+/// Price fields that may say `upper_limit` or `lower_limit` instead of a
+/// number.
+const PRICE_FIELDS: [&str; 2] = ["price", "orderPrice"];
+
+/// Fields that hold a number, which may be typed as text: `"۲٬۴۷۴"` or
+/// `"2,474"`. Sent as text to brokers that take text, as a number otherwise.
+const NUMBER_FIELDS: [&str; 5] = [
+    "price",
+    "orderPrice",
+    "quantity",
+    "orderCount",
+    "disclosedQuantity",
+];
+
+/// Validity date fields that may say `today` or `today+N` instead of a date.
+const DATE_FIELDS: [&str; 2] = ["validityDate", "orderValiditydate"];
+
+/// Days after today that `today` or `today+N` stands for.
+fn parse_relative_date(date: &str) -> Option<i64> {
+    let offset = date.trim().strip_prefix("today")?.trim();
+    if offset.is_empty() {
+        return Some(0);
+    }
+    offset.strip_prefix('+')?.trim().parse().ok()
+}
+
+/// An order priced at the edge of the instrument's daily band.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceLimit {
+    /// The ceiling (سقف), as IPO buyers bid.
+    Upper,
+    /// The floor (کف).
+    Lower,
+}
+
+impl PriceLimit {
+    fn parse(price: &str) -> Option<Self> {
+        match price.trim() {
+            "upper_limit" => Some(Self::Upper),
+            "lower_limit" => Some(Self::Lower),
+            _ => None,
+        }
+    }
+
+    pub fn price(&self, band: &PriceBand) -> f64 {
+        match self {
+            Self::Upper => band.upper,
+            Self::Lower => band.lower,
+        }
+    }
+}
+
+impl std::fmt::Display for PriceLimit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Upper => write!(f, "upper_limit"),
+            Self::Lower => write!(f, "lower_limit"),
+        }
+    }
+}
+
+/// A configured order: the broker-specific payload plus options that control
+/// how it is sent. The options are never part of the request body.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "Value", bound(deserialize = "T: DeserializeOwned"))]
+pub struct OrderEntry<T> {
+    pub data: T,
+    pub vary: Option<OrderVariation>,
+    /// Labels such as `ipo` or `hedge` for enabling groups of orders with
+    /// `--tag` and reporting results per group.
+    pub tags: Vec<String>,
+    /// Price condition, such as `best_ask <= 2480`, that holds the order
+    /// back until a polled quote meets it.
+    pub send_when: Option<SendWhen>,
+    /// Higher priorities are sent first within a batch; 0 by default.
+    pub priority: i64,
+    /// Times the order is sent back-to-back in each batch; 1 by default.
+    pub repeat: usize,
+    /// Set when the price field says `upper_limit` or `lower_limit`; the
+    /// payload then holds a placeholder price of 0 until it is resolved.
+    pub price_limit: Option<PriceLimit>,
+    /// Spreads the order over several prices; see [`expand_ladders`].
+    pub ladder: Option<Ladder>,
+    /// Splits the order's quantity over time; see [`expand_tranches`].
+    pub tranches: Vec<Tranche>,
+    /// How long after a continuous run's first send the order waits; set
+    /// for tranches, 0 otherwise.
+    pub send_after_ms: u64,
+    /// Date fields that said `today` or `today+N`, with the offset in days;
+    /// they are set to the Jalali date in Tehran when the order is sent.
+    pub relative_dates: Vec<(&'static str, i64)>,
+    attempts: Arc<AtomicU64>,
+}
+
+/// The options of an [`OrderEntry`] as they are configured; every other
+/// field of the order is its payload.
+#[derive(Deserialize)]
+struct OrderOptions {
+    #[serde(default)]
+    vary: Option<OrderVariation>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    send_when: Option<SendWhen>,
+    #[serde(default)]
+    priority: i64,
+    #[serde(default = "default_repeat")]
+    repeat: usize,
+    #[serde(default)]
+    ladder: Option<Ladder>,
+    #[serde(default)]
+    tranches: Vec<Tranche>,
+}
+
+fn default_repeat() -> usize {
+    1
+}
+
+/// The fields of [`OrderOptions`], taken out of the payload.
+const OPTION_FIELDS: [&str; 7] = [
+    "vary",
+    "tags",
+    "send_when",
+    "priority",
+    "repeat",
+    "ladder",
+    "tranches",
+];
+
+impl<T: DeserializeOwned> TryFrom<Value> for OrderEntry<T> {
+    type Error = serde_json::Error;
+
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        persian::normalize_json(&mut value);
+        let mut price_limit = None;
+        let mut relative_dates = Vec::new();
+        if let Some(fields) = value.as_object_mut() {
+            for field in NUMBER_FIELDS {
+                if let Some(Value::String(text)) = fields.get_mut(field)
+                    && let Some(plain) = persian::plain_number(text)
+                {
+                    *text = plain;
+                }
+            }
+            for field in DATE_FIELDS {
+                if let Some(days) = fields
+                    .get(field)
+                    .and_then(Value::as_str)
+                    .and_then(parse_relative_date)
+                {
+                    relative_dates.push((field, days));
+                }
+            }
+            for field in PRICE_FIELDS {
+                let limit = fields
+                    .get(field)
+                    .and_then(Value::as_str)
+                    .and_then(PriceLimit::parse);
+                if limit.is_some() {
+                    price_limit = limit;
+                    fields.insert(field.to_string(), Value::from(0));
+                }
+            }
+        }
+        let Value::Object(mut payload) = value else {
+            return Err(serde::de::Error::custom("an order must be a JSON object"));
+        };
+        let mut options = Map::new();
+        for field in OPTION_FIELDS {
+            if let Some(option) = payload.remove(field) {
+                options.insert(field.to_string(), option);
+            }
+        }
+        let raw: OrderOptions = serde_json::from_value(Value::Object(options))?;
+        let data = T::deserialize(Payload(payload))?;
+        if raw.repeat == 0 {
+            return Err(serde::de::Error::custom("repeat must be >= 1"));
+        }
+        if raw.ladder.as_ref().is_some_and(|ladder| ladder.levels == 0) {
+            return Err(serde::de::Error::custom("ladder.levels must be >= 1"));
+        }
+        if let Some(vary) = &raw.vary
+            && let Some((field, _)) = relative_dates
+                .iter()
+                .find(|(field, _)| vary.dates.contains_key(*field))
+        {
+            return Err(serde::de::Error::custom(format!(
+                "'{}' is a relative date and also in vary.dates; use one of them",
+                field
+            )));
+        }
+        Ok(Self {
+            data,
+            vary: raw.vary,
+            tags: raw.tags,
+            send_when: raw.send_when,
+            priority: raw.priority,
+            repeat: raw.repeat,
+            price_limit,
+            ladder: raw.ladder,
+            tranches: raw.tranches,
+            send_after_ms: 0,
+            relative_dates,
+            attempts: Arc::default(),
+        })
+    }
+}
+
+/// An order's payload, deserialized in one pass. [`NUMBER_FIELDS`] are
+/// read as a number or as text, whichever the broker's type asks for: a
+/// price typed as `"2,474"` becomes 2474 for most brokers and stays
+/// `"2474"` for Bidar, and a limit price's placeholder 0 becomes `"0"`
+/// there. Every other field is read as it is, so a misspelled or
+/// mistyped field is reported as such.
+struct Payload(Map<String, Value>);
+
+impl<'de> Deserializer<'de> for Payload {
+    type Error = serde_json::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(PayloadFields {
+            fields: self.0.into_iter(),
+            value: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct PayloadFields {
+    fields: serde_json::map::IntoIter,
+    value: Option<(String, Value)>,
+}
+
+impl<'de> MapAccess<'de> for PayloadFields {
+    type Error = serde_json::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        let Some((key, value)) = self.fields.next() else {
+            return Ok(None);
+        };
+        let field = seed.deserialize(key.as_str().into_deserializer())?;
+        self.value = Some((key, value));
+        Ok(Some(field))
+    }
+
+    fn next_value_seed<S: DeserializeSeed<'de>>(
+        &mut self,
+        seed: S,
+    ) -> Result<S::Value, Self::Error> {
+        let (key, value) = self
+            .value
+            .take()
+            .ok_or_else(|| serde::de::Error::custom("order field value without its key"))?;
+        if NUMBER_FIELDS.contains(&key.as_str()) {
+            seed.deserialize(NumberField(value))
+        } else {
+            seed.deserialize(value)
+        }
+    }
+}
+
+/// A [`NUMBER_FIELDS`] value: numbers are handed out as text and numeric
+/// text as numbers when the target type asks for the other.
+struct NumberField(Value);
+
+macro_rules! number_field_as_number {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                match &self.0 {
+                    Value::String(text) => match persian::number_value(text) {
+                        Some(number) => number.$method(visitor),
+                        None => self.0.$method(visitor),
+                    },
+                    _ => self.0.$method(visitor),
+                }
+            }
+        )*
+    };
+}
+
+macro_rules! number_field_as_is {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+                self.0.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for NumberField {
+    type Error = serde_json::Error;
+
+    number_field_as_number! {
+        deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64 deserialize_i128
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_u128
+        deserialize_f32 deserialize_f64
+    }
+
+    number_field_as_is! {
+        deserialize_any deserialize_bool deserialize_char deserialize_bytes
+        deserialize_byte_buf deserialize_unit deserialize_seq deserialize_map
+        deserialize_identifier deserialize_ignored_any
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_string(visitor)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Number(number) => visitor.visit_string(number.to_string()),
+            other => other.deserialize_string(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_unit_struct(name, visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_tuple_struct(name, len, visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.0.deserialize_enum(name, variants, visitor)
+    }
+}
+
+/// Indices of `orders` from the highest priority to the lowest, keeping the
+/// configured order among equal priorities.
+pub fn priority_order<T>(orders: &[OrderEntry<T>]) -> Vec<usize> {
+    let mut indices: Vec<usize> = (0..orders.len()).collect();
+    indices.sort_by_key(|&index| std::cmp::Reverse(orders[index].priority));
+    indices
+}
+
+/// The order indices one batch sends: `indices` `batch_repeat` times over,
+/// each order `repeat` times back-to-back.
+pub fn batch_sequence<T>(
+    orders: &[OrderEntry<T>],
+    indices: &[usize],
+    batch_repeat: usize,
+) -> Vec<usize> {
+    let mut sequence = Vec::new();
+    for _ in 0..batch_repeat {
+        for &index in indices {
+            sequence.extend(std::iter::repeat_n(index, orders[index].repeat));
+        }
+    }
+    sequence
+}
+
+/// `ladder` on an order: send it as `levels` orders, `step` apart in price,
+/// for when it is unclear where the band or the matching price will land.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Ladder {
+    /// Price of the first rung; the order's own price when unset.
+    #[serde(default)]
+    pub base_price: Option<f64>,
+    /// Added to the price from one rung to the next; negative to step down.
+    pub step: f64,
+    pub levels: usize,
+}
+
+/// Replace each order that has a `ladder` with one order per rung, in its
+/// place, each counting its own attempts.
+pub fn expand_ladders<T: OrderFields + Clone>(
+    name: &str,
+    orders: &mut Vec<OrderEntry<T>>,
+) -> Result<()> {
+    if orders.iter().all(|order| order.ladder.is_none()) {
+        return Ok(());
+    }
+    let mut expanded = Vec::with_capacity(orders.len());
+    for (index, order) in orders.drain(..).enumerate() {
+        let Some(ladder) = order.ladder.clone() else {
+            expanded.push(order);
+            continue;
+        };
+        if let Some(limit) = order.price_limit {
+            anyhow::bail!(
+                "Order #{} of {} is priced at {} and cannot also have a ladder",
+                index + 1,
+                name,
+                limit
+            );
+        }
+        let base = ladder
+            .base_price
+            .or_else(|| order.data.price())
+            .with_context(|| {
+                format!(
+                    "Order #{} of {} needs a price or ladder.base_price",
+                    index + 1,
+                    name
+                )
+            })?;
+        let prices: Vec<f64> = (0..ladder.levels)
+            .map(|level| base + ladder.step * level as f64)
+            .collect();
+        if prices.iter().any(|price| *price <= 0.0) {
+            anyhow::bail!(
+                "The ladder of order #{} of {} goes down to {}; every price must be positive",
+                index + 1,
+                name,
+                prices[prices.len() - 1]
+            );
+        }
+        println!(
+            "[{}] Ladder: order #{} sent at {} price(s) from {} to {}",
+            name,
+            index + 1,
+            prices.len(),
+            prices[0],
+            prices[prices.len() - 1]
+        );
+        for price in prices {
+            let mut rung = order.clone();
+            rung.ladder = None;
+            rung.attempts = Arc::default();
+            rung.data.set_price(price);
+            expanded.push(rung);
+        }
+    }
+    *orders = expanded;
+    Ok(())
+}
+
+/// One part of an order's quantity, sent `after_ms` after the first part.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct Tranche {
+    /// Percentage of the order's quantity.
+    pub share: f64,
+    #[serde(default)]
+    pub after_ms: u64,
+}
+
+/// Replace each order that has `tranches` with one order per tranche, in
+/// its place, splitting the order's quantity by their shares; see
+/// [`split_by_shares`]. The first tranche must go at once and the others in
+/// order of `after_ms`.
+pub fn expand_tranches<T: OrderFields + Clone>(
+    name: &str,
+    orders: &mut Vec<OrderEntry<T>>,
+) -> Result<()> {
+    if orders.iter().all(|order| order.tranches.is_empty()) {
+        return Ok(());
+    }
+    let mut expanded = Vec::with_capacity(orders.len());
+    for (index, order) in orders.drain(..).enumerate() {
+        if order.tranches.is_empty() {
+            expanded.push(order);
+            continue;
+        }
+        let tranches = &order.tranches;
+        if tranches[0].after_ms != 0
+            || tranches
+                .windows(2)
+                .any(|pair| pair[1].after_ms < pair[0].after_ms)
+        {
+            anyhow::bail!(
+                "The tranches of order #{} of {} must start at after_ms 0 and keep increasing",
+                index + 1,
+                name
+            );
+        }
+        let shares: f64 = tranches.iter().map(|tranche| tranche.share).sum();
+        if tranches.iter().any(|tranche| tranche.share <= 0.0) || (shares - 100.0).abs() > 1e-6 {
+            anyhow::bail!(
+                "The tranche shares of order #{} of {} must be positive and add up to 100, not {}",
+                index + 1,
+                name,
+                shares
+            );
+        }
+        let total = order.data.quantity().with_context(|| {
+            format!("Order #{} of {} needs a quantity to split", index + 1, name)
+        })?;
+        let quantities = split_by_shares(total, tranches);
+        if quantities.contains(&0) {
+            anyhow::bail!(
+                "Order #{} of {} has too few shares ({}) for {} tranches",
+                index + 1,
+                name,
+                total,
+                tranches.len()
+            );
+        }
+        let parts: Vec<String> = tranches
+            .iter()
+            .zip(&quantities)
+            .map(|(tranche, quantity)| format!("{} at +{}ms", quantity, tranche.after_ms))
+            .collect();
+        println!(
+            "[{}] Tranches: order #{} sends {} as {}",
+            name,
+            index + 1,
+            total,
+            parts.join(", ")
+        );
+        for (tranche, quantity) in tranches.iter().zip(quantities) {
+            let mut part = order.clone();
+            part.tranches = Vec::new();
+            part.send_after_ms = tranche.after_ms;
+            part.attempts = Arc::default();
+            part.data.set_quantity(quantity);
+            expanded.push(part);
+        }
+    }
+    *orders = expanded;
+    Ok(())
+}
+
+/// `total` split in proportion to the tranche shares, in whole basis points,
+/// with integer arithmetic so equal shares get equal parts; the remainder
+/// goes one by one to the first tranches.
+pub fn split_by_shares(total: u64, tranches: &[Tranche]) -> Vec<u64> {
+    let weights: Vec<u128> = tranches
+        .iter()
+        .map(|tranche| (tranche.share * 100.0).round() as u128)
+        .collect();
+    let weight_sum: u128 = weights.iter().sum();
+    if weight_sum == 0 {
+        return vec![0; tranches.len()];
+    }
+    let mut quantities: Vec<u64> = weights
+        .iter()
+        .map(|weight| (total as u128 * weight / weight_sum) as u64)
+        .collect();
+    let remainder = total - quantities.iter().sum::<u64>();
+    for quantity in quantities.iter_mut().take(remainder as usize) {
+        *quantity += 1;
+    }
+    quantities
+}
+
+impl<T> OrderEntry<T> {
+    /// ` [tag, ...]` to append to log lines, empty for untagged orders.
+    pub fn tag_suffix(&self) -> String {
+        if self.tags.is_empty() {
+            String::new()
+        } else {
+            format!(" [{}]", self.tags.join(", "))
+        }
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|own| own.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// Command-line selection of configured orders: `--orders 1,3-5` keeps
+/// entries by their 1-based position in `orders`, `--symbol` keeps entries
+/// that have a field equal to the symbol and `--tag` keeps entries carrying
+/// any of the tags. Every given criterion must match.
+#[derive(Debug, Clone, Default)]
+pub struct OrderFilter {
+    positions: Vec<(usize, usize)>,
+    symbol: Option<String>,
+    tags: Vec<String>,
+}
+
+impl OrderFilter {
+    pub fn new(positions: Option<&str>, symbol: Option<&str>, tags: &[String]) -> Result<Self> {
+        let positions = match positions {
+            Some(positions) => parse_positions(positions).with_context(|| {
+                format!("Invalid --orders '{}'; expected e.g. 1,3-5", positions)
+            })?,
+            None => Vec::new(),
+        };
+        let symbol = symbol
+            .map(str::trim)
+            .filter(|symbol| !symbol.is_empty())
+            .map(persian::normalize);
+        let tags = tags
+            .iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        Ok(Self {
+            positions,
+            symbol,
+            tags,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty() && self.symbol.is_none() && self.tags.is_empty()
+    }
+
+    pub fn has_tags(&self) -> bool {
+        !self.tags.is_empty()
+    }
+
+    /// This filter, selecting `tags` as well.
+    pub fn with_tags(mut self, tags: &[String]) -> Self {
+        self.tags.extend(tags.iter().cloned());
+        self
+    }
+
+    /// Keep only the selected orders.
+    pub fn retain<T: Serialize>(&self, orders: &mut Vec<OrderEntry<T>>) -> Result<()> {
+        let mut keep = Vec::with_capacity(orders.len());
+        for (index, order) in orders.iter().enumerate() {
+            keep.push(self.matches(index + 1, order)?);
+        }
+        let mut keep = keep.into_iter();
+        orders.retain(|_| keep.next().unwrap_or(false));
+        Ok(())
+    }
+
+    fn matches<T: Serialize>(&self, position: usize, order: &OrderEntry<T>) -> Result<bool> {
+        if !self.tags.is_empty() && !self.tags.iter().any(|tag| order.has_tag(tag)) {
+            return Ok(false);
+        }
+        if !self.positions.is_empty()
+            && !self
+                .positions
+                .iter()
+                .any(|(first, last)| (*first..=*last).contains(&position))
+        {
+            return Ok(false);
+        }
+        let Some(symbol) = &self.symbol else {
+            return Ok(true);
+        };
+        let value = serde_json::to_value(&order.data)?;
+        Ok(value.as_object().is_some_and(|fields| {
+            fields.values().any(|field| {
+                field.as_str().is_some_and(|field| {
+                    persian::normalize(field.trim()).eq_ignore_ascii_case(symbol)
+                })
+            })
+        }))
+    }
+}
+
+impl std::fmt::Display for OrderFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut parts = Vec::new();
+        if !self.positions.is_empty() {
+            let positions: Vec<String> = self
+                .positions
+                .iter()
+                .map(|(first, last)| {
+                    if first == last {
+                        first.to_string()
+                    } else {
+                        format!("{}-{}", first, last)
+                    }
+                })
+                .collect();
+            parts.push(format!("--orders {}", positions.join(",")));
+        }
+        if let Some(symbol) = &self.symbol {
+            parts.push(format!("--symbol {}", symbol));
+        }
+        if !self.tags.is_empty() {
+            parts.push(format!("--tag {}", self.tags.join(",")));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Parse `1,3-5` into inclusive 1-based ranges.
+fn parse_positions(positions: &str) -> Result<Vec<(usize, usize)>> {
+    let mut ranges = Vec::new();
+    for part in positions.split(',').map(str::trim) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim().parse()?, last.trim().parse()?),
+            None => {
+                let position = part.parse()?;
+                (position, position)
+            }
+        };
+        if first == 0 || last < first {
+            anyhow::bail!("'{}' is not a range of positions starting at 1", part);
+        }
+        ranges.push((first, last));
+    }
+    Ok(ranges)
+}
+
+/// Per-attempt rewrites applied to the serialized order, keyed by JSON field
+/// name, for OMSes that reject byte-identical repeated payloads.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct OrderVariation {
+    /// Integer fields that grow by one on every attempt.
+    #[serde(default)]
+    pub increment: Vec<String>,
+    /// Fields set to today's Tehran date, using the given strftime format.
+    #[serde(default)]
+    pub dates: BTreeMap<String, String>,
+    /// Numeric fields shifted by a random offset in `-n..=n`, never below 1.
+    #[serde(default)]
+    pub jitter: BTreeMap<String, i64>,
+    /// Fields that take the listed values in turn, one per attempt.
+    #[serde(default)]
+    pub alternate: BTreeMap<String, Vec<Value>>,
+    /// String fields that get this many random digits appended.
+    #[serde(default)]
+    pub random_suffix: BTreeMap<String, usize>,
+}
+
+impl<T: Serialize> OrderEntry<T> {
+    /// Serialize the payload for the next attempt, applying any variation.
+    pub fn to_json(&self) -> Result<String> {
+        if self.is_static() {
+            return Ok(serde_json::to_string(&self.data)?);
+        }
+        Ok(serde_json::to_string(&self.render()?)?)
+    }
+
+    /// Whether every attempt sends the same payload: no `vary` and no
+    /// `relative_dates`.
+    pub fn is_static(&self) -> bool {
+        self.vary.is_none() && self.relative_dates.is_empty()
+    }
+
+    /// Build the payload for the next attempt as a JSON value.
+    pub fn render(&self) -> Result<Value> {
+        let mut value = serde_json::to_value(&self.data)?;
+        if !self.relative_dates.is_empty()
+            && let Some(fields) = value.as_object_mut()
+        {
+            let today = chrono::Utc::now().with_timezone(&Tehran).date_naive();
+            for (field, days) in &self.relative_dates {
+                let date = today + chrono::Duration::days(*days);
+                fields.insert(field.to_string(), Value::String(jalali::format(date)));
+            }
+        }
+        if let Some(variation) = &self.vary {
+            let attempt = self.attempts.fetch_add(1, Ordering::Relaxed);
+            variation.apply(&mut value, attempt)?;
+            // Logged once the attempt is over, to keep formatting and
+            // console writes off the send path.
+            let varied = variation
+                .fields()
+                .filter_map(|field| Some((field.to_string(), value.get(field)?.clone())))
+                .collect();
+            crate::attempt::note_variation(attempt + 1, varied);
+        }
+        Ok(value)
+    }
+}
+
+impl OrderVariation {
+    /// Every field this variation rewrites.
+    fn fields(&self) -> impl Iterator<Item = &str> {
+        self.increment
+            .iter()
+            .chain(self.dates.keys())
+            .chain(self.jitter.keys())
+            .chain(self.random_suffix.keys())
+            .chain(self.alternate.keys())
+            .map(String::as_str)
+    }
+
+    pub fn apply(&self, order: &mut Value, attempt: u64) -> Result<()> {
+        let fields = order
+            .as_object_mut()
+            .context("Order variation requires a JSON object payload")?;
+        let mut rng = rand::rng();
+
+        for field in &self.increment {
+            let offset = i64::try_from(attempt).context("Attempt counter overflowed")?;
+            update_integer(fields.get_mut(field), field, |base| base + offset)?;
+        }
+
+        if !self.dates.is_empty() {
+            let today = chrono::Utc::now().with_timezone(&Tehran);
+            for (field, format) in &self.dates {
+                fields.insert(
+                    field.clone(),
+                    Value::String(today.format(format).to_string()),
+                );
+            }
+        }
+
+        for (field, range) in &self.jitter {
+            let range = range.abs();
+            let offset = rng.random_range(-range..=range);
+            update_integer(fields.get_mut(field), field, |base| (base + offset).max(1))?;
+        }
+
+        for (field, values) in &self.alternate {
+            if values.is_empty() {
+                anyhow::bail!("alternate field '{}' needs at least one value", field);
+            }
+            let value = values[(attempt % values.len() as u64) as usize].clone();
+            fields.insert(field.clone(), value);
+        }
+
+        for (field, digits) in &self.random_suffix {
+            let value = fields
+                .get_mut(field)
+                .with_context(|| format!("Order has no field '{}' to suffix", field))?;
+            let Value::String(text) = value else {
+                anyhow::bail!("random_suffix field '{}' must be a string", field);
+            };
+            for _ in 0..*digits {
+                text.push(char::from(b'0' + rng.random_range(0..10u8)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Rewrite an integer field in place. Numeric strings (as used by Bidar) are
+/// updated and kept as strings.
+fn update_integer(value: Option<&mut Value>, field: &str, f: impl Fn(i64) -> i64) -> Result<()> {
+    let value = value.with_context(|| format!("Order has no field '{}' to vary", field))?;
+    match value {
+        Value::Number(number) => {
+            let base = number
+                .as_i64()
+                .with_context(|| format!("Field '{}' is not an integer", field))?;
+            *value = Value::from(f(base));
+        }
+        Value::String(text) => {
+            let base: i64 = text
+                .parse()
+                .with_context(|| format!("Field '{}' is not a numeric string", field))?;
+            *text = f(base).to_string();
+        }
+        _ => anyhow::bail!("Field '{}' must be a number or numeric string", field),
+    }
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Persian text as it is typed and as brokers send it: Persian (۰-۹) and
+//! Arabic (٠-٩) digits, the Arabic ي, ى and ك many keyboards and TSETMC use
+//! for ی and ک, and thousands separators, so `۲٬۴۷۴` is the price 2474 and
+//! `فولاد` is the same symbol however its ye was typed.
+
+use serde_json::Value;
+
+/// Separators written between groups of thousands: the comma, the Arabic
+/// thousands separator and the Persian comma.
+const THOUSANDS_SEPARATORS: [char; 3] = [',', '٬', '،'];
+
+/// `text` with ASCII digits and Persian ی and ک.
+pub fn normalize(text: &str) -> String {
+    text.chars().map(normalize_char).collect()
+}
+
+fn normalize_char(ch: char) -> char {
+    match ch {
+        '۰'..='۹' => char::from(b'0' + (ch as u32 - '۰' as u32) as u8),
+        '٠'..='٩' => char::from(b'0' + (ch as u32 - '٠' as u32) as u8),
+        // The Arabic decimal separator
+        '٫' => '.',
+        'ي' | 'ى' => 'ی',
+        'ك' => 'ک',
+        other => other,
+    }
+}
+
+/// `text` normalized, without the thousands separators between its digits.
+pub fn strip_thousands_separators(text: &str) -> String {
+    let chars: Vec<char> = text.chars().map(normalize_char).collect();
+    chars
+        .iter()
+        .enumerate()
+        .filter(|(i, ch)| {
+            !(THOUSANDS_SEPARATORS.contains(ch)
+                && *i > 0
+                && chars[i - 1].is_ascii_digit()
+                && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        })
+        .map(|(_, ch)| *ch)
+        .collect()
+}
+
+/// The number `text` writes, in ASCII digits without separators, if it is
+/// one: `۲٬۴۷۴` is `2474`.
+pub fn plain_number(text: &str) -> Option<String> {
+    let plain = strip_thousands_separators(text.trim());
+    let digits = plain.strip_prefix('-').unwrap_or(&plain);
+    let valid = !digits.is_empty()
+        && digits.chars().all(|ch| ch.is_ascii_digit() || ch == '.')
+        && digits.chars().filter(|ch| *ch == '.').count() <= 1
+        && !digits.starts_with('.')
+        && !digits.ends_with('.');
+    valid.then_some(plain)
+}
+
+/// The JSON number of a [`plain_number`].
+pub fn number_value(plain: &str) -> Option<Value> {
+    match plain.parse::<i64>() {
+        Ok(number) => Some(Value::from(number)),
+        Err(_) => plain.parse::<f64>().ok().map(Value::from),
+    }
+}
+
+/// Normalize every string in `value`.
+pub fn normalize_json(value: &mut Value) {
+    match value {
+        Value::String(text) if text.chars().any(|ch| normalize_char(ch) != ch) => {
+            *text = normalize(text);
+        }
+        Value::Array(items) => items.iter_mut().for_each(normalize_json),
+        Value::Object(fields) => fields.values_mut().for_each(normalize_json),
+        _ => {}
+    }
+}
@@ -0,0 +1,324 @@
+//! `sarkhati preflight`: everything worth knowing about a broker some ten
+//! minutes before the open, in one go/no-go checklist per broker and
+//! account. The config is loaded and its symbols looked up, the session is
+//! checked like `auth-check`, prices are checked against the daily band, and
+//! a fresh connection is warmed up and probed like `compare`. Nothing is
+//! sent to the order endpoint but GET and HEAD requests.
+
+use crate::accounts;
+use crate::auth_check::{self, SessionState};
+use crate::compare;
+use crate::doctor::{Check, CheckState};
+use crate::latency;
+use crate::market_data;
+use crate::price_check::{self, PriceCheck};
+use crate::registry::{self, AnyBroker};
+use crate::runner::Broker;
+use crate::with_broker;
+use anyhow::Result;
+use chrono_tz::Asia::Tehran;
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The checklist of one broker or account.
+#[derive(Debug, Clone)]
+pub struct Preflight {
+    pub name: String,
+    pub checks: Vec<Check>,
+}
+
+impl Preflight {
+    /// Go unless a check failed; warnings are for a human to weigh.
+    pub fn go(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.state != CheckState::Fail)
+    }
+}
+
+/// Run every check on `broker` and each of its accounts, probing each
+/// `probes` times.
+pub async fn check_broker<B: Broker + Clone>(
+    broker: &B,
+    probes: usize,
+    client: &reqwest::Client,
+) -> Vec<Preflight> {
+    let mut broker = broker.clone();
+    let config = check_config(&mut broker).await;
+    let mut preflights = Vec::new();
+    for sender in accounts::senders(&broker) {
+        let mut checks = vec![config.clone()];
+        checks.push(check_session(sender, client).await);
+        if config.state != CheckState::Fail {
+            checks.push(check_prices(sender).await);
+        }
+        checks.extend(check_connection(sender, probes).await);
+        preflights.push(Preflight {
+            name: sender.name().to_string(),
+            checks,
+        });
+    }
+    preflights
+}
+
+/// Orders configured and their symbols found, and `target_time` readable
+/// and still ahead.
+async fn check_config<B: Broker>(broker: &mut B) -> Check {
+    let name = broker.name().to_string();
+    let base_url = broker.settings().market_data_url.clone();
+    let mut resolved = market_data::resolve_symbols(&name, broker.orders_mut(), &base_url).await;
+    for account in broker.accounts_mut() {
+        if resolved.is_ok() {
+            let account_name = account.name().to_string();
+            resolved =
+                market_data::resolve_symbols(&account_name, account.orders_mut(), &base_url).await;
+        }
+    }
+    if let Err(e) = resolved {
+        return Check::problem(
+            "Config",
+            CheckState::Fail,
+            format!("{:#}", e),
+            "fix the symbol or give the ISIN",
+        );
+    }
+
+    let orders: usize = accounts::senders(broker)
+        .iter()
+        .map(|sender| sender.orders().len())
+        .sum();
+    if orders == 0 {
+        return Check::problem(
+            "Config",
+            CheckState::Fail,
+            "no orders".to_string(),
+            "add orders to the config",
+        );
+    }
+    let settings = broker.settings();
+    let Some(target_time) = &settings.target_time else {
+        return Check::pass("Config", format!("{} order(s), continuous", orders));
+    };
+    if settings.wait_for_trading.is_some() {
+        return Check::problem(
+            "Config",
+            CheckState::Fail,
+            "sets both target_time and wait_for_trading".to_string(),
+            "keep one of them",
+        );
+    }
+    let Ok(time) = chrono::NaiveTime::parse_from_str(target_time, "%H:%M:%S%.3f") else {
+        return Check::problem(
+            "Config",
+            CheckState::Fail,
+            format!("target_time '{}' is not HH:MM:SS.mmm", target_time),
+            "write it like 08:44:59.900",
+        );
+    };
+    let now = chrono::Utc::now().with_timezone(&Tehran).time();
+    let left = time.signed_duration_since(now);
+    if left <= chrono::TimeDelta::zero() {
+        return Check::problem(
+            "Config",
+            CheckState::Warn,
+            format!(
+                "{} order(s), target_time {} already passed today",
+                orders, target_time
+            ),
+            "a run started now waits for tomorrow",
+        );
+    }
+    Check::pass(
+        "Config",
+        format!(
+            "{} order(s), target_time {} in {}m {}s",
+            orders,
+            target_time,
+            left.num_minutes(),
+            left.num_seconds() % 60
+        ),
+    )
+}
+
+async fn check_session<B: Broker>(sender: &B, client: &reqwest::Client) -> Check {
+    let report = auth_check::check_broker(sender, client).await;
+    let mut details = Vec::new();
+    if let Some(status) = report.status {
+        details.push(status.to_string());
+    }
+    if let Some(expires_at) = report.expires_at {
+        details.push(auth_check::format_expiry(expires_at));
+    }
+    if !report.detail.is_empty() {
+        details.push(report.detail.clone());
+    }
+    let detail = details.join(", ");
+    match report.state {
+        SessionState::Valid => Check::pass("Session", detail),
+        SessionState::Expired => Check::problem(
+            "Session",
+            CheckState::Fail,
+            detail,
+            &format!("log in again: sarkhati login {}", sender.name()),
+        ),
+        SessionState::Unknown => Check::problem(
+            "Session",
+            CheckState::Warn,
+            detail,
+            "the session could not be checked; try auth-check again",
+        ),
+    }
+}
+
+/// The broker's `price_check`, or the default one, with its refusals as
+/// failures.
+async fn check_prices<B: Broker>(sender: &B) -> Check {
+    let settings = sender.settings();
+    let rule = settings.price_check.clone().unwrap_or_default();
+    let checked = price_check::check_prices(
+        sender.name(),
+        &PriceCheck {
+            on_invalid: price_check::OnInvalid::Refuse,
+            ..rule
+        },
+        sender.orders(),
+        &settings.market_data_url,
+    )
+    .await;
+    match checked {
+        Ok(()) => Check::pass(
+            "Prices",
+            format!("{} order(s) checked", sender.orders().len()),
+        ),
+        Err(e) => Check::problem(
+            "Prices",
+            CheckState::Fail,
+            format!("{:#}", e),
+            "fix the prices shown above",
+        ),
+    }
+}
+
+/// Warm a fresh connection up and probe it: the first probe opens the
+/// connection, the rest measure the round trip calibration will see.
+async fn check_connection<B: Broker>(sender: &B, probes: usize) -> Vec<Check> {
+    let comparison = compare::probe_broker(sender, probes.max(2)).await;
+    let Some(connect) = comparison.connect else {
+        return vec![Check::problem(
+            "Warm-up",
+            CheckState::Fail,
+            comparison
+                .error
+                .unwrap_or_else(|| "no connection".to_string()),
+            "the broker is unreachable; run doctor for details",
+        )];
+    };
+    let warm_up = Check::pass(
+        "Warm-up",
+        format!(
+            "connected in {:.1} ms over {:?}{}",
+            latency::ms(connect),
+            comparison.version.unwrap_or_default(),
+            comparison
+                .remote_addr
+                .map(|addr| format!(" to {}", addr))
+                .unwrap_or_default()
+        ),
+    );
+    let (Some(p50), Some(p90)) = (comparison.p50_us(), comparison.p90_us()) else {
+        return vec![
+            warm_up,
+            Check::problem(
+                "Calibration",
+                CheckState::Fail,
+                comparison
+                    .error
+                    .unwrap_or_else(|| "no probe came back".to_string()),
+                "the connection drops after the first request; check the network",
+            ),
+        ];
+    };
+    let detail = format!(
+        "p50 {:.1} ms, p90 {:.1} ms, jitter {:.1} ms over {} probe(s)",
+        p50 as f64 / 1000.0,
+        p90 as f64 / 1000.0,
+        comparison.jitter_us() / 1000.0,
+        comparison.rtts_us.len()
+    );
+    let calibration = match comparison.error {
+        Some(error) => Check::problem(
+            "Calibration",
+            CheckState::Warn,
+            format!("{}; {}", detail, error),
+            "some probes failed; the estimate may be off",
+        ),
+        None => Check::pass("Calibration", detail),
+    };
+    vec![warm_up, calibration]
+}
+
+fn print_preflight(preflight: &Preflight) {
+    println!("[Preflight] {}", preflight.name);
+    for check in &preflight.checks {
+        let state = match check.state {
+            CheckState::Pass => "PASS",
+            CheckState::Warn => "WARN",
+            CheckState::Fail => "FAIL",
+        };
+        println!(
+            "[Preflight]   {} {:<12} {}",
+            state, check.name, check.detail
+        );
+        if let Some(hint) = &check.hint {
+            println!("[Preflight]        -> {}", hint);
+        }
+    }
+    println!(
+        "[Preflight] {}: {}",
+        preflight.name,
+        if preflight.go() { "GO" } else { "NO-GO" }
+    );
+}
+
+/// Run `preflight` on `names`, or every configured broker when empty, and
+/// fail if any of them is a no-go.
+pub async fn run_preflight(names: &[String], probes: usize) -> Result<()> {
+    let brokers: Vec<AnyBroker> = if names.is_empty() {
+        registry::all_brokers()?
+    } else {
+        names
+            .iter()
+            .map(|name| registry::find_broker(name))
+            .collect::<Result<_>>()?
+    };
+    if brokers.is_empty() {
+        anyhow::bail!("No broker configs found in the current directory");
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()?;
+    let mut preflights = Vec::new();
+    for broker in &brokers {
+        let checked = with_broker!(broker, b => check_broker(b, probes, &client).await);
+        for preflight in &checked {
+            print_preflight(preflight);
+        }
+        preflights.extend(checked);
+    }
+
+    let no_go: Vec<&str> = preflights
+        .iter()
+        .filter(|preflight| !preflight.go())
+        .map(|preflight| preflight.name.as_str())
+        .collect();
+    if !no_go.is_empty() {
+        anyhow::bail!("NO-GO: {}", no_go.join(", "));
+    }
+    println!(
+        "[Preflight] GO for all {} broker(s)/account(s).",
+        preflights.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use crate::market_data::{MarketData, PriceBand};
+use crate::orders::{OrderEntry, OrderFields};
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+fn default_tick_size() -> f64 {
+    1.0
+}
+
+/// `price_check` setting: validate every order price before the run starts.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PriceCheck {
+    #[serde(default)]
+    pub on_invalid: OnInvalid,
+    /// Prices must be a multiple of this. TSETMC does not publish tick
+    /// sizes, so it is configured here.
+    #[serde(default = "default_tick_size")]
+    pub tick_size: f64,
+}
+
+impl Default for PriceCheck {
+    fn default() -> Self {
+        Self {
+            on_invalid: OnInvalid::default(),
+            tick_size: default_tick_size(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnInvalid {
+    /// Stop before anything is sent.
+    #[default]
+    Refuse,
+    /// Log the problem and send anyway.
+    Warn,
+}
+
+/// Why a price would be rejected, if it would be.
+pub fn price_problem(price: f64, tick_size: f64, band: Option<&PriceBand>) -> Option<String> {
+    if tick_size > 0.0 && (price / tick_size).fract().abs() > 1e-9 {
+        return Some(format!(
+            "price {} is not a multiple of the tick size {}",
+            price, tick_size
+        ));
+    }
+    let band = band?;
+    if price > band.upper {
+        Some(format!(
+            "price {} is above the daily band {} - {}",
+            price, band.lower, band.upper
+        ))
+    } else if price < band.lower {
+        Some(format!(
+            "price {} is below the daily band {} - {}",
+            price, band.lower, band.upper
+        ))
+    } else {
+        None
+    }
+}
+
+/// Check the price of every order in `orders` against the tick size and the
+/// instrument's daily band. Fails on an invalid price unless the rule only
+/// warns; a band that cannot be fetched is only warned about.
+pub async fn check_prices<T: OrderFields>(
+    name: &str,
+    rule: &PriceCheck,
+    orders: &[OrderEntry<T>],
+    base_url: &str,
+) -> Result<()> {
+    let market_data = MarketData::new(base_url)?;
+    let mut bands: HashMap<String, Option<PriceBand>> = HashMap::new();
+    let mut invalid = 0;
+    for (index, order) in orders.iter().enumerate() {
+        // Limit-priced orders get a price inside the band at send time.
+        if order.price_limit.is_some() {
+            continue;
+        }
+        let Some(price) = order.data.price() else {
+            continue;
+        };
+        let band = match order.data.isin() {
+            Some(isin) => {
+                if !bands.contains_key(isin) {
+                    let band = fetch_band(&market_data, isin).await;
+                    if let Err(e) = &band {
+                        eprintln!(
+                            "[{}] Warning: cannot check prices of {} against the daily band: {:#}",
+                            name, isin, e
+                        );
+                    }
+                    bands.insert(isin.to_string(), band.ok());
+                }
+                bands[isin]
+            }
+            None => None,
+        };
+        if let Some(problem) = price_problem(price, rule.tick_size, band.as_ref()) {
+            invalid += 1;
+            eprintln!("[{}] Order #{}: {}", name, index + 1, problem);
+        }
+    }
+
+    match (invalid, rule.on_invalid) {
+        (0, _) => {
+            println!("[{}] Prices of {} order(s) checked.", name, orders.len());
+            Ok(())
+        }
+        (_, OnInvalid::Warn) => {
+            eprintln!(
+                "[{}] Warning: sending {} order(s) with invalid prices.",
+                name, invalid
+            );
+            Ok(())
+        }
+        (_, OnInvalid::Refuse) => anyhow::bail!(
+            "{} order(s) of {} have prices the broker would reject; fix them or set price_check.on_invalid to \"warn\"",
+            invalid,
+            name
+        ),
+    }
+}
+
+async fn fetch_band(market_data: &MarketData, isin: &str) -> Result<PriceBand> {
+    let instrument = market_data.find_instrument(isin).await?;
+    market_data.price_band(&instrument.ins_code).await
+}
@@ -1,11 +1,33 @@
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tokio::time::sleep;
 
+fn default_spin_us() -> u64 {
+    1500
+}
+
+/// `precise_rate_limit`: spin through the end of each gap instead of
+/// sleeping, as tokio's timer only wakes on whole milliseconds.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PreciseRateLimit {
+    /// Gap between requests in microseconds; `batch_delay_ms` when unset.
+    #[serde(default)]
+    pub interval_us: Option<u64>,
+    /// How much of the end of each gap is spun rather than slept.
+    #[serde(default = "default_spin_us")]
+    pub spin_us: u64,
+}
+
 pub struct RateLimiter {
     rate_limit: Duration,
     last_request: Mutex<Option<Instant>>,
-    rate_limit_ms: u64,
+    /// Final part of each gap spent spinning, in precise mode.
+    spin: Option<Duration>,
+    global: Option<Arc<GlobalLimiter>>,
 }
 
 impl RateLimiter {
@@ -13,22 +35,168 @@ impl RateLimiter {
         Self {
             rate_limit: Duration::from_millis(rate_limit_ms),
             last_request: Mutex::new(None),
-            rate_limit_ms,
+            spin: None,
+            global: None,
+        }
+    }
+
+    /// Wait out gaps precisely, holding the thread for their last
+    /// `spin_us`, with the gap `precise` gives when it sets one.
+    pub fn with_precise(mut self, precise: Option<&PreciseRateLimit>) -> Self {
+        if let Some(precise) = precise {
+            if let Some(interval_us) = precise.interval_us {
+                self.rate_limit = Duration::from_micros(interval_us);
+            }
+            self.spin = Some(Duration::from_micros(precise.spin_us));
         }
+        self
+    }
+
+    /// Also wait on a limiter shared with every other broker.
+    pub fn with_global(mut self, global: Option<Arc<GlobalLimiter>>) -> Self {
+        self.global = global;
+        self
     }
 
+    /// The gap between requests, rounded up to whole milliseconds.
     pub fn rate_limit_ms(&self) -> u64 {
-        self.rate_limit_ms
+        self.rate_limit.as_micros().div_ceil(1000) as u64
     }
 
     pub async fn wait(&self) {
-        let mut last_request = self.last_request.lock().await;
-        if let Some(last) = *last_request {
-            let elapsed = last.elapsed();
-            if elapsed < self.rate_limit {
-                sleep(self.rate_limit - elapsed).await;
+        self.wait_for(0).await;
+    }
+
+    /// Wait for a request of roughly `request_bytes` bytes, counting it
+    /// against the global bandwidth budget when one is configured.
+    pub async fn wait_for(&self, request_bytes: usize) {
+        {
+            let mut last_request = self.last_request.lock().await;
+            if let Some(last) = *last_request {
+                pause_until(last + self.rate_limit, self.spin).await;
             }
+            *last_request = Some(Instant::now());
+        }
+
+        if let Some(global) = &self.global {
+            global.wait(request_bytes).await;
+        }
+    }
+}
+
+/// Sleep until `deadline`, or with `spin`, sleep until that long before it
+/// and spin the rest, which the timer cannot honor to the microsecond.
+async fn pause_until(deadline: Instant, spin: Option<Duration>) {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let Some(spin) = spin else {
+        if !remaining.is_zero() {
+            sleep(remaining).await;
+        }
+        return;
+    };
+    if remaining > spin {
+        sleep(remaining - spin).await;
+    }
+    while Instant::now() < deadline {
+        std::hint::spin_loop();
+    }
+}
+
+/// Rate limits are per session, so limiters are kept per (broker, account):
+/// every send and probe with the same credentials waits on the same limiter,
+/// while different accounts of one broker each get their full allowance.
+#[derive(Default)]
+pub struct RateLimiterRegistry {
+    limiters: std::sync::Mutex<HashMap<(String, String), Arc<RateLimiter>>>,
+}
+
+impl RateLimiterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The limiter for `account` of `broker` (`None` for a broker used
+    /// without accounts), created with `rate_limit_ms` and `global` on first
+    /// use.
+    pub fn get(
+        &self,
+        broker: &str,
+        account: Option<&str>,
+        rate_limit_ms: u64,
+        global: Option<Arc<GlobalLimiter>>,
+    ) -> Arc<RateLimiter> {
+        self.get_or_create(broker, account, || {
+            RateLimiter::new(rate_limit_ms).with_global(global)
+        })
+    }
+
+    /// The limiter for `account` of `broker`, made by `create` on first use.
+    pub fn get_or_create(
+        &self,
+        broker: &str,
+        account: Option<&str>,
+        create: impl FnOnce() -> RateLimiter,
+    ) -> Arc<RateLimiter> {
+        let key = (
+            broker.to_lowercase(),
+            account.unwrap_or_default().to_lowercase(),
+        );
+        let mut limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        limiters
+            .entry(key)
+            .or_insert_with(|| Arc::new(create()))
+            .clone()
+    }
+}
+
+/// Process-wide budget on total requests per second and upload bandwidth,
+/// shared by every broker so `all` cannot saturate the uplink at the open.
+pub struct GlobalLimiter {
+    request_interval: Duration,
+    bytes_per_sec: Option<u64>,
+    next_free: Mutex<Option<Instant>>,
+}
+
+impl GlobalLimiter {
+    pub fn new(requests_per_sec: Option<f64>, bandwidth_kbps: Option<u64>) -> Option<Self> {
+        let requests_per_sec = requests_per_sec.filter(|rps| *rps > 0.0);
+        let bytes_per_sec = bandwidth_kbps
+            .filter(|kbps| *kbps > 0)
+            .map(|kbps| kbps * 1000 / 8);
+        if requests_per_sec.is_none() && bytes_per_sec.is_none() {
+            return None;
         }
-        *last_request = Some(Instant::now());
+        Some(Self {
+            request_interval: requests_per_sec
+                .map(|rps| Duration::from_secs_f64(1.0 / rps))
+                .unwrap_or_default(),
+            bytes_per_sec,
+            next_free: Mutex::new(None),
+        })
     }
+
+    /// Reserve the next free slot and sleep until it starts.
+    pub async fn wait(&self, request_bytes: usize) {
+        let transmit_time = self
+            .bytes_per_sec
+            .map(|bps| Duration::from_secs_f64(request_bytes as f64 / bps as f64))
+            .unwrap_or_default();
+        let slot = {
+            let mut next_free = self.next_free.lock().await;
+            let now = Instant::now();
+            let slot = next_free.map_or(now, |free| free.max(now));
+            *next_free = Some(slot + self.request_interval.max(transmit_time));
+            slot
+        };
+        tokio::time::sleep_until(slot.into()).await;
+    }
+}
+
+/// Approximate on-the-wire size of a request, used for the bandwidth budget.
+pub fn request_size(headers: &HeaderMap, body_len: usize) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum::<usize>()
+        + body_len
 }
@@ -0,0 +1,261 @@
+use crate::captcha::{CaptchaConfig, CaptchaSolver, ConfiguredSolver};
+use crate::login;
+use crate::rayan_broker::{self, RayanBrokerConfig};
+use crate::runner::Broker;
+use crate::totp::TotpConfig;
+use anyhow::{Context, Result};
+use reqwest::header::{COOKIE, HeaderValue, ORIGIN, USER_AGENT};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+fn default_token_pointer() -> String {
+    "/token".to_string()
+}
+
+fn default_captcha_field() -> String {
+    "captcha".to_string()
+}
+
+fn default_otp_field() -> String {
+    "otp".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `login` section of an entry in `config_rayan.json`. Paths are relative to
+/// the broker's `host`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct RayanLoginConfig {
+    pub username: String,
+    /// Prompted for on the console when empty.
+    #[serde(default)]
+    pub password: String,
+    pub login_path: String,
+    /// JSON pointer to the access token in the login response.
+    #[serde(default = "default_token_pointer")]
+    pub token_pointer: String,
+    #[serde(default)]
+    pub captcha_path: Option<String>,
+    #[serde(default = "default_captcha_field")]
+    pub captcha_field: String,
+    #[serde(default)]
+    pub captcha: CaptchaConfig,
+    /// Sends a TOTP code in `otp_field` with every login, for accounts with
+    /// two-factor login.
+    #[serde(default)]
+    pub totp: Option<TotpConfig>,
+    #[serde(default = "default_otp_field")]
+    pub otp_field: String,
+    /// Log in again and retry once when an order fails with an expired
+    /// session.
+    #[serde(default = "default_true")]
+    pub relogin: bool,
+    /// Write each new token back to `config_rayan.json`.
+    #[serde(default = "default_true")]
+    pub save_token: bool,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    token: RwLock<Option<String>>,
+    generation: AtomicU64,
+    login_lock: tokio::sync::Mutex<()>,
+}
+
+/// Token obtained at runtime, shared by every clone of a broker config so
+/// all in-flight orders switch to a new session together.
+#[derive(Debug, Clone, Default)]
+pub struct RayanSession(Arc<SessionState>);
+
+impl RayanSession {
+    pub fn get(&self) -> Option<String> {
+        self.0
+            .token
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Incremented on every login; lets concurrent failures detect that
+    /// another task already refreshed the session.
+    pub fn generation(&self) -> u64 {
+        self.0.generation.load(Ordering::Acquire)
+    }
+
+    fn set(&self, token: String) {
+        *self
+            .0
+            .token
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(token);
+        self.0.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Run `login <name>` for a Rayan broker: authenticate and store the new
+/// token in the config file.
+pub async fn run_login(config_path: &str, name: &str) -> Result<()> {
+    let config = rayan_broker::load_config(config_path)?;
+    let broker = rayan_broker::find_broker(&config, name)
+        .with_context(|| format!("Broker '{}' not found in {}", name, config_path))?;
+    let login_config = broker.login.as_ref().with_context(|| {
+        format!(
+            "No 'login' section for {} in {}; add username (and optionally password)",
+            broker.name, config_path
+        )
+    })?;
+
+    let token = login_with_prompt(broker, login_config).await?;
+    save_token(config_path, &broker.name, &token)?;
+    println!("[{}] Saved new token to {}", broker.name, config_path);
+    Ok(())
+}
+
+/// Log in again unless another task already did since `seen_generation`,
+/// and make the new token the one every later order uses.
+pub async fn relogin(broker: &RayanBrokerConfig, seen_generation: u64) -> Result<()> {
+    let login_config = broker
+        .login
+        .as_ref()
+        .with_context(|| format!("No 'login' section for {}", broker.name))?;
+
+    let _guard = broker.session.0.login_lock.lock().await;
+    if broker.session.generation() != seen_generation {
+        return Ok(());
+    }
+
+    let token = login_with_prompt(broker, login_config).await?;
+    broker.session.set(token.clone());
+    if login_config.save_token {
+        match save_token(broker.config_file(), &broker.name, &token) {
+            Ok(()) => println!(
+                "[{}] Saved new token to {}",
+                broker.name,
+                broker.config_file()
+            ),
+            Err(e) => summary!(
+                "[{}] Warning: could not save new token: {:#}",
+                broker.name,
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+async fn login_with_prompt(
+    broker: &RayanBrokerConfig,
+    login_config: &RayanLoginConfig,
+) -> Result<String> {
+    let mut login_config = login_config.clone();
+    if login_config.password.is_empty() {
+        login_config.password =
+            login::prompt_line(&format!("[{}] Password: ", broker.name)).await?;
+    }
+
+    println!(
+        "[{}] Logging in as {} via {}{}",
+        broker.name,
+        login_config.username,
+        broker.host.trim_end_matches('/'),
+        login_config.login_path
+    );
+    let solver = ConfiguredSolver::from_config(&login_config.captcha)?;
+    let token = login(
+        &login_config,
+        &broker.host,
+        &broker.origin,
+        &broker.user_agent,
+        &solver,
+    )
+    .await?;
+    println!(
+        "[{}] Logged in; token={}...",
+        broker.name,
+        &token[..token.len().min(8)]
+    );
+    Ok(token)
+}
+
+/// Log in to a Rayan API host and read the access token from the answer.
+pub async fn login(
+    config: &RayanLoginConfig,
+    host: &str,
+    origin: &str,
+    user_agent: &str,
+    solver: &impl CaptchaSolver,
+) -> Result<String> {
+    let client = reqwest::Client::new();
+    let host = host.trim_end_matches('/');
+
+    let mut body = Map::new();
+    body.insert(
+        "username".to_string(),
+        Value::String(config.username.clone()),
+    );
+    body.insert(
+        "password".to_string(),
+        Value::String(config.password.clone()),
+    );
+
+    let mut cookie = String::new();
+    if let Some(captcha_path) = &config.captcha_path {
+        let captcha_url = format!("{}{}", host, captcha_path);
+        let (captcha, captcha_cookie) =
+            login::fetch_captcha(&client, &captcha_url, user_agent).await?;
+        cookie = captcha_cookie;
+        let answer = solver.solve(&captcha).await?;
+        body.insert(config.captcha_field.clone(), Value::String(answer));
+    }
+    if let Some(totp) = &config.totp {
+        body.insert(
+            config.otp_field.clone(),
+            Value::String(totp.fresh_code().await?),
+        );
+    }
+
+    let mut request = client
+        .post(format!("{}{}", host, config.login_path))
+        .header(USER_AGENT, HeaderValue::from_str(user_agent)?)
+        .header(ORIGIN, HeaderValue::from_str(origin)?)
+        .header(
+            "X-Requested-With",
+            HeaderValue::from_static("XMLHttpRequest"),
+        )
+        .json(&body);
+    if !cookie.is_empty() {
+        request = request.header(COOKIE, HeaderValue::from_str(&cookie)?);
+    }
+    let response = request.send().await.context("Rayan login request failed")?;
+    let status = response.status();
+    let response_text = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "Rayan login failed with status {}: {}",
+            status,
+            crate::decode_unicode_escapes(&response_text)
+        );
+    }
+    let answer: Value = serde_json::from_str(&response_text)
+        .with_context(|| format!("Login returned invalid JSON: {}", response_text))?;
+    match answer.pointer(&config.token_pointer) {
+        Some(Value::String(token)) if !token.is_empty() => Ok(token.clone()),
+        _ => anyhow::bail!(
+            "Login response has no token at {}: {}",
+            config.token_pointer,
+            crate::decode_unicode_escapes(&response_text)
+        ),
+    }
+}
+
+fn save_token(config_path: &str, name: &str, token: &str) -> Result<()> {
+    login::update_config_fields(
+        config_path,
+        Some(name),
+        &[("token", Value::String(token.to_string()))],
+    )
+}
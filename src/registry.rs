@@ -0,0 +1,261 @@
+use crate::bidar::{self, BidarConfig};
+use crate::custom_broker::{self, CustomBrokerConfig};
+use crate::danayan::{self, DanayanBrokerConfig};
+use crate::encryption::{ENCRYPTED_EXTENSION, config_exists};
+use crate::exir_broker::{self, ExirBrokerConfig};
+use crate::mobin::{self, MobinBrokerConfig};
+use crate::mofid::{self, MofidConfig};
+use crate::onlineplus::{self, OnlinePlusBrokerConfig};
+use crate::pasargad::{self, PasargadBrokerConfig};
+use crate::rayan_broker::{self, RayanBrokerConfig};
+use crate::runner::Broker;
+use crate::standard_broker::{self, StandardBrokerConfig};
+use crate::tadbir_broker::{self, TadbirBrokerConfig};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+/// Every config file a broker can be loaded from.
+pub const CONFIG_FILES: [&str; 11] = [
+    "config_mofid.json",
+    "config_bidar.json",
+    "config_standard.json",
+    "config_exir.json",
+    "config_danayan.json",
+    "config_pasargad.json",
+    "config_mobin.json",
+    "config_tadbir.json",
+    "config_rayan.json",
+    "config_onlineplus.json",
+    "config_custom.json",
+];
+
+/// The folder of `exe` when `dir` has no config file but that folder has,
+/// for a binary started by double-click or from another folder.
+pub fn exe_config_dir(dir: &Path, exe: &Path) -> Option<PathBuf> {
+    let has_configs = |dir: &Path| {
+        CONFIG_FILES.iter().any(|file| {
+            dir.join(file).exists()
+                || dir
+                    .join(format!("{}.{}", file, ENCRYPTED_EXTENSION))
+                    .exists()
+        })
+    };
+    let exe_dir = exe.parent()?;
+    (!has_configs(dir) && exe_dir != dir && has_configs(exe_dir)).then(|| exe_dir.to_path_buf())
+}
+
+/// Any configured broker, for commands that act on one broker chosen by name.
+/// Use [`with_broker!`](crate::with_broker) to call generic code on it.
+pub enum AnyBroker {
+    Mofid(MofidConfig),
+    Bidar(BidarConfig),
+    Danayan(DanayanBrokerConfig),
+    Standard(StandardBrokerConfig),
+    Exir(ExirBrokerConfig),
+    Pasargad(PasargadBrokerConfig),
+    Mobin(MobinBrokerConfig),
+    Tadbir(TadbirBrokerConfig),
+    Rayan(RayanBrokerConfig),
+    OnlinePlus(OnlinePlusBrokerConfig),
+    Custom(CustomBrokerConfig),
+}
+
+/// Bind the broker inside an [`AnyBroker`] and evaluate `$body` with it.
+#[macro_export]
+macro_rules! with_broker {
+    ($any:expr, $broker:ident => $body:expr) => {
+        match $any {
+            $crate::registry::AnyBroker::Mofid($broker) => $body,
+            $crate::registry::AnyBroker::Bidar($broker) => $body,
+            $crate::registry::AnyBroker::Danayan($broker) => $body,
+            $crate::registry::AnyBroker::Standard($broker) => $body,
+            $crate::registry::AnyBroker::Exir($broker) => $body,
+            $crate::registry::AnyBroker::Pasargad($broker) => $body,
+            $crate::registry::AnyBroker::Mobin($broker) => $body,
+            $crate::registry::AnyBroker::Tadbir($broker) => $body,
+            $crate::registry::AnyBroker::Rayan($broker) => $body,
+            $crate::registry::AnyBroker::OnlinePlus($broker) => $body,
+            $crate::registry::AnyBroker::Custom($broker) => $body,
+        }
+    };
+}
+
+impl AnyBroker {
+    pub fn name(&self) -> &str {
+        crate::with_broker!(self, broker => broker.name())
+    }
+
+    /// Config file this broker is stored in and, for multi-broker files, the
+    /// `name` of its entry in `brokers`.
+    pub fn config_location(&self) -> (&str, Option<&str>) {
+        match self {
+            AnyBroker::Mofid(broker) => (broker.config_file(), None),
+            AnyBroker::Bidar(broker) => (broker.config_file(), None),
+            other => {
+                crate::with_broker!(other, broker => (broker.config_file(), Some(broker.name())))
+            }
+        }
+    }
+
+    /// Whether the config authenticates with a `cookie` field.
+    pub fn has_cookie_field(&self) -> bool {
+        matches!(
+            self,
+            AnyBroker::Mofid(_)
+                | AnyBroker::Danayan(_)
+                | AnyBroker::Standard(_)
+                | AnyBroker::Exir(_)
+                | AnyBroker::Pasargad(_)
+                | AnyBroker::Mobin(_)
+        )
+    }
+}
+
+/// Resolve a command-line broker name: `mofid` and `bidar` load their own
+/// config files, anything else is looked up in the multi-broker configs.
+pub fn find_broker(name: &str) -> Result<AnyBroker> {
+    match name {
+        "mofid" => return Ok(AnyBroker::Mofid(mofid::load_config("config_mofid.json")?)),
+        "bidar" => return Ok(AnyBroker::Bidar(bidar::load_config("config_bidar.json")?)),
+        _ => {}
+    }
+
+    if config_exists("config_standard.json") {
+        let config = standard_broker::load_config("config_standard.json")?;
+        if let Some(broker) = standard_broker::find_broker(&config, name) {
+            return Ok(AnyBroker::Standard(broker.clone()));
+        }
+    }
+    if config_exists("config_exir.json") {
+        let config = exir_broker::load_config("config_exir.json")?;
+        if let Some(broker) = exir_broker::find_broker(&config, name) {
+            return Ok(AnyBroker::Exir(broker.clone()));
+        }
+    }
+    if config_exists("config_danayan.json") {
+        let config = danayan::load_config("config_danayan.json")?;
+        if let Some(broker) = danayan::find_broker(&config, name) {
+            return Ok(AnyBroker::Danayan(broker.clone()));
+        }
+    }
+    if config_exists("config_pasargad.json") {
+        let config = pasargad::load_config("config_pasargad.json")?;
+        if let Some(broker) = pasargad::find_broker(&config, name) {
+            return Ok(AnyBroker::Pasargad(broker.clone()));
+        }
+    }
+    if config_exists("config_mobin.json") {
+        let config = mobin::load_config("config_mobin.json")?;
+        if let Some(broker) = mobin::find_broker(&config, name) {
+            return Ok(AnyBroker::Mobin(broker.clone()));
+        }
+    }
+    if config_exists("config_tadbir.json") {
+        let config = tadbir_broker::load_config("config_tadbir.json")?;
+        if let Some(broker) = tadbir_broker::find_broker(&config, name) {
+            return Ok(AnyBroker::Tadbir(broker.clone()));
+        }
+    }
+    if config_exists("config_rayan.json") {
+        let config = rayan_broker::load_config("config_rayan.json")?;
+        if let Some(broker) = rayan_broker::find_broker(&config, name) {
+            return Ok(AnyBroker::Rayan(broker.clone()));
+        }
+    }
+    if config_exists("config_onlineplus.json") {
+        let config = onlineplus::load_config("config_onlineplus.json")?;
+        if let Some(broker) = onlineplus::find_broker(&config, name) {
+            return Ok(AnyBroker::OnlinePlus(broker.clone()));
+        }
+    }
+    if config_exists("config_custom.json") {
+        let config = custom_broker::load_config("config_custom.json")?;
+        if let Some(broker) = custom_broker::find_broker(&config, name) {
+            return Ok(AnyBroker::Custom(broker.clone()));
+        }
+    }
+    anyhow::bail!(
+        "Broker '{}' not found in config_standard.json, config_exir.json, config_danayan.json, config_pasargad.json, config_mobin.json, config_tadbir.json, config_rayan.json, config_onlineplus.json or config_custom.json",
+        name
+    )
+}
+
+/// Every broker in the config files present in the working directory.
+pub fn all_brokers() -> Result<Vec<AnyBroker>> {
+    let mut brokers = Vec::new();
+    if config_exists("config_mofid.json") {
+        brokers.push(AnyBroker::Mofid(mofid::load_config("config_mofid.json")?));
+    }
+    if config_exists("config_bidar.json") {
+        brokers.push(AnyBroker::Bidar(bidar::load_config("config_bidar.json")?));
+    }
+    if config_exists("config_standard.json") {
+        let config = standard_broker::load_config("config_standard.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Standard));
+    }
+    if config_exists("config_exir.json") {
+        let config = exir_broker::load_config("config_exir.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Exir));
+    }
+    if config_exists("config_danayan.json") {
+        let config = danayan::load_config("config_danayan.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Danayan));
+    }
+    if config_exists("config_pasargad.json") {
+        let config = pasargad::load_config("config_pasargad.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Pasargad));
+    }
+    if config_exists("config_mobin.json") {
+        let config = mobin::load_config("config_mobin.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Mobin));
+    }
+    if config_exists("config_tadbir.json") {
+        let config = tadbir_broker::load_config("config_tadbir.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Tadbir));
+    }
+    if config_exists("config_rayan.json") {
+        let config = rayan_broker::load_config("config_rayan.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Rayan));
+    }
+    if config_exists("config_onlineplus.json") {
+        let config = onlineplus::load_config("config_onlineplus.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::OnlinePlus));
+    }
+    if config_exists("config_custom.json") {
+        let config = custom_broker::load_config("config_custom.json")?;
+        brokers.extend(config.brokers.into_iter().map(AnyBroker::Custom));
+    }
+    Ok(brokers)
+}
+
+/// The single configured broker whose order URL is on `domain` or one of its
+/// subdomains.
+pub fn find_broker_by_domain(domain: &str) -> Result<AnyBroker> {
+    let domain = domain.trim_start_matches('.').to_lowercase();
+    let mut matches: Vec<AnyBroker> = all_brokers()?
+        .into_iter()
+        .filter(|broker| {
+            let order_url = with_broker!(broker, broker => broker.order_url());
+            reqwest::Url::parse(order_url)
+                .ok()
+                .and_then(|url| url.host_str().map(str::to_lowercase))
+                .is_some_and(|host| host == domain || host.ends_with(&format!(".{}", domain)))
+        })
+        .collect();
+    match matches.len() {
+        1 => Ok(matches.remove(0)),
+        0 => anyhow::bail!(
+            "No configured broker sends orders to {}; pass --broker <NAME>",
+            domain
+        ),
+        _ => anyhow::bail!(
+            "Several brokers send orders to {} ({}); pass --broker <NAME>",
+            domain,
+            matches
+                .iter()
+                .map(AnyBroker::name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
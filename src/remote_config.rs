@@ -0,0 +1,178 @@
+//! `--config URL`: config files pulled from a central server at startup, so
+//! a team edits orders in one place and every VPS runs the latest version.
+//!
+//! The document maps config file names to their contents:
+//! `{ "config_standard.json": { "brokers": [...] }, "config_mofid.json": {...} }`.
+//! Files it holds are read from it instead of the working directory.
+
+use crate::secrets;
+use anyhow::{Context, Result};
+use reqwest::header::{HeaderName, HeaderValue};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Last config document fetched, used when the server cannot be reached.
+pub const CACHE_FILE: &str = "remote_config.cache.json";
+
+/// Config file contents by file name, once `--config` was loaded.
+static FILES: Mutex<Option<BTreeMap<String, String>>> = Mutex::new(None);
+
+/// Fetch the config document at `source`, an http(s) URL or a path, sending
+/// `header` (`Name: value`, the value possibly a `keyring:` reference) with
+/// the request. A fetched document is cached in `cache_path`, readable by
+/// its owner only; when the fetch fails the cached one is used instead.
+/// Plain `http://` is refused except on the local machine, as the document
+/// carries sessions and passwords.
+pub async fn load(source: &str, header: Option<&str>, cache_path: &str) -> Result<()> {
+    let is_url = source.starts_with("http://") || source.starts_with("https://");
+    if source.starts_with("http://") && !is_loopback(source) {
+        anyhow::bail!(
+            "Refusing to fetch the config over plain http from {}; use https",
+            source
+        );
+    }
+    let document = if is_url {
+        match fetch(source, header)
+            .await
+            .and_then(|body| parse(&body).map(|files| (body, files)))
+        {
+            Ok((body, files)) => {
+                if let Err(e) = write_cache(cache_path, &body) {
+                    eprintln!(
+                        "[Config] Warning: could not cache the config in {}: {}",
+                        cache_path, e
+                    );
+                }
+                println!("[Config] Loaded {} from {}", names(&files), source);
+                files
+            }
+            Err(e) => {
+                let cached = std::fs::read_to_string(cache_path).map_err(|_| {
+                    e.context(format!(
+                        "No cached config in {} to fall back on",
+                        cache_path
+                    ))
+                })?;
+                let files = parse(&cached)
+                    .with_context(|| format!("Invalid cached config in {}", cache_path))?;
+                eprintln!(
+                    "[Config] Warning: could not fetch {}; using the cached copy in {}",
+                    source, cache_path
+                );
+                println!("[Config] Loaded {} from {}", names(&files), cache_path);
+                files
+            }
+        }
+    } else {
+        let body = std::fs::read_to_string(source)
+            .with_context(|| format!("Failed to read config from {}", source))?;
+        let files = parse(&body).with_context(|| format!("Invalid config in {}", source))?;
+        println!("[Config] Loaded {} from {}", names(&files), source);
+        files
+    };
+    *FILES.lock().unwrap_or_else(|e| e.into_inner()) = Some(document);
+    Ok(())
+}
+
+/// Whether the host of `url` is this machine.
+fn is_loopback(url: &str) -> bool {
+    let Some(url) = reqwest::Url::parse(url).ok() else {
+        return false;
+    };
+    let host = url.host_str().unwrap_or_default();
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    host.eq_ignore_ascii_case("localhost")
+        || host
+            .parse::<std::net::IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Save the fetched document with mode 600 where the OS has modes,
+/// tightening an older cache file too.
+fn write_cache(path: &str, body: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(body.as_bytes())
+}
+
+async fn fetch(url: &str, header: Option<&str>) -> Result<String> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()?;
+    let mut request = client.get(url);
+    if let Some(header) = header {
+        let (name, value) = header
+            .split_once(':')
+            .context("--config-header must look like 'Name: value'")?;
+        let mut value = value.trim().to_string();
+        secrets::resolve(&mut value)?;
+        request = request.header(
+            HeaderName::from_bytes(name.trim().as_bytes())
+                .with_context(|| format!("Invalid --config-header name '{}'", name.trim()))?,
+            HeaderValue::from_str(&value).context("Invalid --config-header value")?,
+        );
+    }
+    request
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("Failed to fetch config from {}", url))?
+        .text()
+        .await
+        .with_context(|| format!("Failed to fetch config from {}", url))
+}
+
+fn parse(body: &str) -> Result<BTreeMap<String, String>> {
+    let document: Map<String, Value> =
+        serde_json::from_str(body).context("Config document must be a JSON object")?;
+    document
+        .into_iter()
+        .map(|(name, contents)| {
+            if !contents.is_object() {
+                anyhow::bail!("{} in the config document must be a JSON object", name);
+            }
+            Ok((name, serde_json::to_string_pretty(&contents)?))
+        })
+        .collect()
+}
+
+fn names(files: &BTreeMap<String, String>) -> String {
+    files.keys().cloned().collect::<Vec<_>>().join(", ")
+}
+
+/// The contents of config file `path`, if the `--config` document has it.
+pub fn read(path: &str) -> Option<String> {
+    FILES
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()?
+        .get(path)
+        .cloned()
+}
+
+/// Replace config file `path` in the `--config` document, for the session
+/// and token updates of this run; `false` if the document does not have it.
+pub fn update(path: &str, contents: &str) -> bool {
+    let mut files = FILES.lock().unwrap_or_else(|e| e.into_inner());
+    match files.as_mut().and_then(|files| files.get_mut(path)) {
+        Some(file) => {
+            *file = contents.to_string();
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,303 @@
+//! What each broker answers to an order, as types: every broker's known
+//! response body deserializes into a struct of its own, and
+//! [`BrokerResponse::outcome`] turns it into an [`OrderResponse`], so an
+//! acceptance, a rejected order and an expired session are told apart by
+//! the fields the broker sets rather than by searching the body. A body
+//! that does not fit, or says neither way, is left to the HTTP status.
+
+use crate::errors::OrderErrorKind;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// What an order response means.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderResponse {
+    /// The broker took the order, with its order ID when it gave one.
+    Accepted { order_id: Option<String> },
+    /// The broker refused the order itself: its price, quantity, the
+    /// account's credit, the market being closed and the like.
+    ValidationError { message: String },
+    /// The session was refused; nothing will go through until it is renewed.
+    AuthError { message: String },
+}
+
+/// A broker's order response body.
+pub trait BrokerResponse: DeserializeOwned {
+    /// What the response means, `None` when the body does not say.
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse>;
+}
+
+/// Parse `body`, answered with `status`, as `R`.
+pub fn parse<R: BrokerResponse>(status: StatusCode, body: &str) -> Option<OrderResponse> {
+    serde_json::from_str::<R>(body).ok()?.outcome(status)
+}
+
+/// Mofid Online.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MofidOrderResponse {
+    #[serde(alias = "isSuccessful", alias = "IsSuccessfull")]
+    pub is_successfull: Option<bool>,
+    #[serde(alias = "Message")]
+    pub message: Option<String>,
+    /// Why the OMS refused the order; more precise than `message`.
+    pub oms_error_description: Option<String>,
+    #[serde(alias = "orderId", alias = "clientKey")]
+    pub oms_order_id: Option<Value>,
+}
+
+impl BrokerResponse for MofidOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let message = self.oms_error_description.or(self.message);
+        outcome(status, self.is_successfull, message, self.oms_order_id)
+    }
+}
+
+/// BMI Bourse, Ordibehesht and the other standard-OMS brokers.
+#[derive(Debug, Deserialize)]
+pub struct StandardOrderResponse {
+    #[serde(
+        rename = "IsSuccessfull",
+        alias = "isSuccessfull",
+        alias = "isSuccessful",
+        alias = "IsSuccessful"
+    )]
+    pub is_successful: Option<bool>,
+    #[serde(
+        rename = "MessageDesc",
+        alias = "messageDesc",
+        alias = "message",
+        alias = "Message"
+    )]
+    pub message: Option<String>,
+    #[serde(rename = "Data", alias = "data")]
+    pub data: Option<Value>,
+}
+
+impl BrokerResponse for StandardOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let order_id = self.data.filter(|data| !data.is_object());
+        outcome(status, self.is_successful, self.message, order_id)
+    }
+}
+
+/// Alvand and the other exirbroker.com brokers.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExirOrderResponse {
+    #[serde(alias = "isSuccess", alias = "isSuccessful", alias = "succeeded")]
+    pub success: Option<bool>,
+    pub message: Option<String>,
+    /// Field errors, each a message or an object with one.
+    #[serde(default)]
+    pub errors: Vec<Value>,
+    #[serde(alias = "orderId")]
+    pub id: Option<Value>,
+}
+
+impl BrokerResponse for ExirOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let mut success = self.success;
+        let mut message = self.message;
+        if let Some(error) = self.errors.first() {
+            success = Some(false);
+            message = message.or_else(|| value_message(error));
+        }
+        outcome(status, success, message, self.id)
+    }
+}
+
+/// Danayan.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DanayanOrderResponse {
+    #[serde(alias = "isSuccess", alias = "isSuccessfull")]
+    pub is_successful: Option<bool>,
+    #[serde(alias = "errorMessage")]
+    pub message: Option<String>,
+    pub order_id: Option<Value>,
+}
+
+impl BrokerResponse for DanayanOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        outcome(status, self.is_successful, self.message, self.order_id)
+    }
+}
+
+/// Bidar Trader.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BidarOrderResponse {
+    #[serde(alias = "isSuccessful", alias = "success")]
+    pub succeeded: Option<bool>,
+    pub message: Option<String>,
+    pub error: Option<Value>,
+    #[serde(alias = "id")]
+    pub order_id: Option<Value>,
+}
+
+impl BrokerResponse for BidarOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let mut succeeded = self.succeeded;
+        let mut message = self.message;
+        if let Some(error) = self.error.filter(|error| !error.is_null()) {
+            succeeded = Some(false);
+            message = message.or_else(|| value_message(&error));
+        }
+        outcome(status, succeeded, message, self.order_id)
+    }
+}
+
+/// Pasargad.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasargadOrderResponse {
+    #[serde(alias = "isSuccessful", alias = "succeeded")]
+    pub is_success: Option<bool>,
+    #[serde(alias = "errorMessage", alias = "Message")]
+    pub message: Option<String>,
+    /// The accepted order as `{"orderId": ...}`, or its ID alone.
+    pub data: Option<Value>,
+}
+
+impl BrokerResponse for PasargadOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let order_id = self.data.and_then(|data| match data {
+            Value::Object(mut fields) => fields.remove("orderId"),
+            other => Some(other),
+        });
+        outcome(status, self.is_success, self.message, order_id)
+    }
+}
+
+/// Mobin Sarmaye.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MobinOrderResponse {
+    #[serde(alias = "isSuccess", alias = "isSuccessful")]
+    pub succeeded: Option<bool>,
+    pub message: Option<String>,
+    /// Messages of a refused order; the first stands for all of them.
+    #[serde(default)]
+    pub messages: Vec<Value>,
+    #[serde(alias = "orderId", alias = "id")]
+    pub tracking_number: Option<Value>,
+}
+
+impl BrokerResponse for MobinOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let message = self
+            .message
+            .or_else(|| self.messages.first().and_then(value_message));
+        outcome(status, self.succeeded, message, self.tracking_number)
+    }
+}
+
+/// The Tadbir OMS brokers.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TadbirOrderResponse {
+    #[serde(alias = "isSuccess", alias = "succeeded")]
+    pub is_successful: Option<bool>,
+    #[serde(alias = "errorMessage")]
+    pub message: Option<String>,
+    /// The accepted order as `{"orderId": ...}`.
+    pub result: Option<Value>,
+}
+
+impl BrokerResponse for TadbirOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let order_id = self
+            .result
+            .and_then(|mut result| result.get_mut("orderId").map(Value::take));
+        outcome(status, self.is_successful, self.message, order_id)
+    }
+}
+
+/// The Rayan Ham Afza OMS brokers.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RayanOrderResponse {
+    #[serde(alias = "isSuccessful", alias = "succeeded")]
+    pub is_success: Option<bool>,
+    #[serde(alias = "errorMessage", alias = "Message")]
+    pub message: Option<String>,
+    /// The accepted order as `{"orderId": ...}`.
+    pub data: Option<Value>,
+}
+
+impl BrokerResponse for RayanOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        let order_id = self
+            .data
+            .and_then(|mut data| data.get_mut("orderId").map(Value::take));
+        outcome(status, self.is_success, self.message, order_id)
+    }
+}
+
+/// OnlinePlus (Sahm Ashna and others).
+#[derive(Debug, Deserialize)]
+pub struct OnlinePlusOrderResponse {
+    #[serde(
+        rename = "Succeeded",
+        alias = "IsSuccess",
+        alias = "succeeded",
+        alias = "isSuccess"
+    )]
+    pub succeeded: Option<bool>,
+    #[serde(rename = "Message", alias = "message")]
+    pub message: Option<String>,
+    #[serde(rename = "OrderId", alias = "orderId", alias = "TrackingCode")]
+    pub order_id: Option<Value>,
+}
+
+impl BrokerResponse for OnlinePlusOrderResponse {
+    fn outcome(self, status: StatusCode) -> Option<OrderResponse> {
+        outcome(status, self.succeeded, self.message, self.order_id)
+    }
+}
+
+/// The outcome shared by every broker: 401 and 403 refuse the session, as
+/// does a message saying so; a success flag or a status that is not 2xx
+/// decides between acceptance and a validation error; a 2xx response with
+/// no flag counts as accepted only with an order ID.
+fn outcome(
+    status: StatusCode,
+    success: Option<bool>,
+    message: Option<String>,
+    order_id: Option<Value>,
+) -> Option<OrderResponse> {
+    let message = message.unwrap_or_default();
+    let refused = !status.is_success() || success == Some(false);
+    if status == StatusCode::UNAUTHORIZED
+        || status == StatusCode::FORBIDDEN
+        || (refused && OrderErrorKind::classify(status, &message) == OrderErrorKind::AuthExpired)
+    {
+        return Some(OrderResponse::AuthError { message });
+    }
+    if refused {
+        return Some(OrderResponse::ValidationError { message });
+    }
+    let order_id = order_id.and_then(|id| match id {
+        Value::String(id) => Some(id),
+        Value::Number(id) => Some(id.to_string()),
+        _ => None,
+    });
+    if success == Some(true) || order_id.is_some() {
+        return Some(OrderResponse::Accepted { order_id });
+    }
+    None
+}
+
+/// The message of an error given as a string or as an object.
+fn value_message(error: &Value) -> Option<String> {
+    match error {
+        Value::String(message) => Some(message.clone()),
+        Value::Object(fields) => ["message", "description", "errorMessage"]
+            .iter()
+            .find_map(|key| fields.get(*key)?.as_str().map(str::to_string)),
+        _ => None,
+    }
+}
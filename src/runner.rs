@@ -0,0 +1,2386 @@
+use crate::accounts::{self, AccountStrategy};
+use crate::attempt::{self, Attempt};
+use crate::batch_results::{
+    self, BatchCollector, BatchResult, BatchSinkConfig, BatchSinks, OrderOutcome, OrderStatus,
+};
+use crate::book_snapshots::{BookRecorder, BookSnapshotSettings};
+use crate::calibration::{CalibrationConfig, CalibrationSummary, ConnectionWarmup, DelayModel};
+use crate::cancel_replace::{self, CancelReplaceSettings, Follower, Registered, Resender};
+use crate::circuit_breaker::{CircuitBreaker, CircuitBreakerSettings};
+use crate::conditions::{self, ConditionWatcher};
+use crate::control::{BrokerControl, Control};
+use crate::credit::{self, CreditCheck, RemainingCredit};
+use crate::depth::{DepthCheck, DepthChecker, DepthDecision};
+use crate::dispatch::{self, DispatchThread};
+use crate::errors::{OrderError, OrderErrorKind, order_error_kind};
+use crate::holidays::{HolidayCalendar, HolidaySettings, MarketClosedSettings, OnMarketClosed};
+use crate::ipo::{self, IpoProfile};
+use crate::latency::{self, SocketOptions};
+use crate::limit_prices::{self, LimitPrices};
+use crate::market_data::{self, TradingTrigger};
+use crate::market_feed::MarketFeedConfig;
+use crate::order_events::{OrderTracker, PushNotificationsConfig};
+use crate::orders::{self, OrderEntry, OrderFields, OrderFilter};
+use crate::price_check::{self, PriceCheck};
+use crate::rate_limiter::{
+    GlobalLimiter, PreciseRateLimit, RateLimiter, RateLimiterRegistry, request_size,
+};
+use crate::session_summary::SessionSummary;
+use crate::success::{self, SuccessRule};
+use crate::systemd::{self, Readiness};
+use crate::twap::{self, TwapSettings};
+use crate::watchlist::{Watchlist, WatchlistEntry};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use chrono::TimeZone;
+use chrono_tz::Asia::Tehran;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use reqwest::header::{ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderName};
+use reqwest::{StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::sync::{Semaphore, SemaphorePermit, oneshot};
+use tokio::task::JoinSet;
+
+fn default_batch_delay() -> u64 {
+    100
+}
+
+fn default_batch_repeat() -> usize {
+    1
+}
+
+/// Scheduling settings shared by every broker config, flattened into each
+/// broker's JSON object.
+#[derive(Debug, Deserialize, Clone)]
+pub struct BrokerSettings {
+    #[serde(default = "default_batch_delay")]
+    pub batch_delay_ms: u64,
+    #[serde(default = "default_batch_repeat")]
+    pub batch_repeat: usize,
+    #[serde(default)]
+    pub target_time: Option<String>,
+    #[serde(default)]
+    pub calibration: Option<CalibrationConfig>,
+    /// How far ahead of `target_time` the calibrated round trip puts each
+    /// send.
+    #[serde(default)]
+    pub delay_model: DelayModel,
+    /// Upper bound on requests in flight at once; unbounded when unset.
+    #[serde(default)]
+    pub max_concurrent_requests: Option<usize>,
+    /// Upper bound on order tasks of continuous batches still running, and
+    /// what a new batch does when it is reached.
+    #[serde(default)]
+    pub in_flight_limit: Option<InFlightLimit>,
+    /// Start each continuous batch only once every response of the one
+    /// before it is in, `batch_delay_ms` later.
+    #[serde(default)]
+    pub wait_for_batch_completion: bool,
+    /// Extra checks on 2xx responses before an order counts as accepted.
+    #[serde(default)]
+    pub success_rules: Vec<SuccessRule>,
+    /// Authenticated endpoint `auth-check` fetches; the order URL if unset.
+    #[serde(default)]
+    pub auth_check_url: Option<String>,
+    /// How each order is spread over the broker's `accounts`.
+    #[serde(default)]
+    pub account_strategy: AccountStrategy,
+    /// Only orders carrying one of these tags are sent, unless `--tag` is
+    /// given; every order when empty.
+    #[serde(default)]
+    pub enabled_tags: Vec<String>,
+    /// Start the batch loop when the instrument becomes tradable instead of
+    /// at a wall-clock `target_time`.
+    #[serde(default)]
+    pub wait_for_trading: Option<TradingTrigger>,
+    /// Delay between quote polls for orders with `send_when`.
+    #[serde(default = "conditions::default_quote_poll_interval_ms")]
+    pub quote_poll_interval_ms: u64,
+    /// Check each order against the order book right before sending it.
+    #[serde(default)]
+    pub depth_check: Option<DepthCheck>,
+    /// Validate order prices against the tick size and daily band before
+    /// the run starts.
+    #[serde(default)]
+    pub price_check: Option<PriceCheck>,
+    /// Base URL of the TSETMC market data API.
+    #[serde(default = "market_data::default_market_data_url")]
+    pub market_data_url: String,
+    /// WebSocket pushing quotes and trading states, followed by `send_when`
+    /// and `wait_for_trading` instead of polling.
+    #[serde(default)]
+    pub market_feed: Option<MarketFeedConfig>,
+    /// The broker's SignalR hub reporting orders as they are registered and
+    /// filled.
+    #[serde(default)]
+    pub push_notifications: Option<PushNotificationsConfig>,
+    /// The broker's purchasing-power endpoint, asked between batches so buys
+    /// the account can no longer pay for are held back.
+    #[serde(default)]
+    pub credit_check: Option<CreditCheck>,
+    /// Symbols whose orders each wait for their own open or time.
+    #[serde(default)]
+    pub watchlist: Vec<WatchlistEntry>,
+    /// Record the order book a moment before and after the send.
+    #[serde(default)]
+    pub book_snapshots: Option<BookSnapshotSettings>,
+    /// What scheduled runs do when `target_time` falls on a holiday.
+    #[serde(default)]
+    pub holidays: HolidaySettings,
+    /// What continuous runs do once the broker reports the market closed.
+    #[serde(default)]
+    pub market_closed: MarketClosedSettings,
+    /// Defaults for buying into an initial public offering.
+    #[serde(default)]
+    pub ipo: Option<IpoProfile>,
+    /// Spread every order evenly over a duration in child orders.
+    #[serde(default)]
+    pub twap: Option<TwapSettings>,
+    /// Cancel orders still unfilled after a window and send them again at
+    /// a stepped price.
+    #[serde(default)]
+    pub cancel_replace: Option<CancelReplaceSettings>,
+    /// Stop sending an order through an account, or the broker, once it was
+    /// accepted there.
+    #[serde(default)]
+    pub stop_on_accept: bool,
+    /// Start each order of a continuous batch only once the one before it,
+    /// by `priority`, has started, instead of all at once.
+    #[serde(default)]
+    pub priority_order: bool,
+    /// Gap between consecutive orders of one batch. Continuous batches
+    /// release every order at once when unset; scheduled sends use
+    /// `batch_delay_ms`.
+    #[serde(default)]
+    pub order_spacing_ms: Option<u64>,
+    /// Probe the endpoint in the last seconds before a scheduled send.
+    #[serde(default)]
+    pub connection_warmup: Option<ConnectionWarmup>,
+    /// TCP options of the connections orders and probes go out on.
+    #[serde(default)]
+    pub socket: SocketOptions,
+    /// Spin through the end of each rate-limit gap for sub-millisecond
+    /// accuracy.
+    #[serde(default)]
+    pub precise_rate_limit: Option<PreciseRateLimit>,
+    /// Where the result of each batch goes once its last response is in.
+    #[serde(default = "batch_results::default_batch_sinks")]
+    pub batch_sinks: Vec<BatchSinkConfig>,
+    /// Stop sending for a while when the endpoint keeps failing hard.
+    #[serde(default)]
+    pub circuit_breaker: Option<CircuitBreakerSettings>,
+    /// Send from a thread and runtime of the broker's own.
+    #[serde(default)]
+    pub dispatch_thread: Option<DispatchThread>,
+    /// Where the shapes of the broker's JSON responses are kept, to warn
+    /// when they change; no check when unset.
+    #[serde(default)]
+    pub response_schema_file: Option<String>,
+    /// Header each request carries its attempt's correlation ID in, for
+    /// brokers that tolerate an extra header; none when unset.
+    #[serde(default)]
+    pub correlation_header: Option<String>,
+    /// Stop the broker this long after it starts, wherever it is, so a run
+    /// left unattended ends by itself.
+    #[serde(default)]
+    pub max_runtime_secs: Option<u64>,
+    /// Built from `socket` on first use; see [`BrokerSettings::client`].
+    #[serde(skip)]
+    client: OnceLock<reqwest::Client>,
+}
+
+impl BrokerSettings {
+    /// Client orders and probes go out on, so calibration and warm-up keep
+    /// the order's connections open.
+    pub fn client(&self) -> &reqwest::Client {
+        self.client.get_or_init(|| latency::client(&self.socket))
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct InFlightLimit {
+    pub max_tasks: usize,
+    #[serde(default)]
+    pub when_full: WhenFull,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WhenFull {
+    /// Start the batch once enough earlier tasks have finished.
+    #[default]
+    Delay,
+    /// Drop the batch and try again after `batch_delay_ms`.
+    Shed,
+}
+
+/// Command-line options shared by every broker in one run.
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    pub test_mode: bool,
+    /// Print a ready-to-run curl command per order and exit without sending.
+    pub curl_only: bool,
+    /// Build and log every request without opening any connection.
+    pub dry_run: bool,
+    /// Cross-broker budget every send and probe waits on, if configured.
+    pub global_limiter: Option<Arc<GlobalLimiter>>,
+    /// Per-(broker, account) limiters shared by every run in the process.
+    pub rate_limiters: Arc<RateLimiterRegistry>,
+    /// Orders picked with `--orders`/`--symbol`; all orders when empty.
+    pub order_filter: OrderFilter,
+    /// State shared with the `--control-port` API, if it runs.
+    pub control: Option<Arc<Control>>,
+    /// Receives the result of every batch, besides the broker's sinks.
+    pub batch_results: Option<tokio::sync::mpsc::UnboundedSender<BatchResult>>,
+    /// Totals printed when the run ends, however it ends.
+    pub session_summary: Option<Arc<SessionSummary>>,
+}
+
+/// A broker integration driven by [`run_broker`].
+///
+/// Implementations only describe how to authenticate, send one order and
+/// probe the endpoint; scheduling, calibration and batching live here so they
+/// apply to every broker the same way.
+pub trait Broker: Send + Sync + 'static {
+    type Order: Serialize + OrderFields + Clone + Send + Sync + 'static;
+
+    /// Label used in log prefixes and error messages.
+    fn name(&self) -> &str;
+
+    /// Config file the broker was loaded from, for error messages.
+    fn config_file(&self) -> &str;
+
+    fn order_url(&self) -> &str;
+
+    /// Endpoint `order` is posted to, for brokers that pick one per order.
+    fn order_url_for(&self, _order: &Self::Order) -> Cow<'_, str> {
+        Cow::Borrowed(self.order_url())
+    }
+
+    fn settings(&self) -> &BrokerSettings;
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings;
+
+    fn orders(&self) -> &[OrderEntry<Self::Order>];
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<Self::Order>>;
+
+    /// Per-account copies of this broker from its `accounts` list. When there
+    /// are any, orders are sent through each account instead of the broker.
+    fn accounts(&self) -> &[Self]
+    where
+        Self: Sized,
+    {
+        &[]
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self]
+    where
+        Self: Sized,
+    {
+        &mut []
+    }
+
+    /// Get credentials ready before the run: log in, or start renewing a
+    /// token, when the broker supports it.
+    fn ensure_session(&self) -> impl Future<Output = Result<()>> + Send {
+        async { Ok(()) }
+    }
+
+    /// Log in again after the broker reported the session expired during
+    /// the run; `false` when the broker cannot log in by itself.
+    fn relogin(&self) -> impl Future<Output = Result<bool>> + Send {
+        async { Ok(false) }
+    }
+
+    /// Validate credentials and print which authentication method is used.
+    fn check_auth(&self) -> Result<()>;
+
+    /// Serialize the next attempt of `order` into the request body.
+    fn order_json(&self, order: &OrderEntry<Self::Order>) -> Result<String> {
+        order.to_json()
+    }
+
+    /// Map a calibrated round-trip estimate to the one-way delay used for
+    /// scheduling.
+    fn adjust_delay_estimate(&self, estimated_delay_ms: u64) -> u64 {
+        self.settings().delay_model.apply(estimated_delay_ms)
+    }
+
+    /// Headers sent with `order_json`, built exactly as `send_order` does.
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap>;
+
+    /// Build the URL, headers and body of `order_json` once, to send as
+    /// many times as needed.
+    fn prepare(&self, order: &Self::Order, order_json: String) -> Result<PreparedRequest> {
+        PreparedRequest::new(
+            &self.order_url_for(order),
+            self.order_headers(&order_json)?,
+            order_json,
+        )
+    }
+
+    /// Send `request`; headers that depend on the time or on a refreshed
+    /// session, such as Exir's X-App-N, are recomputed.
+    fn send_order(
+        &self,
+        order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send;
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<CalibrationSummary>> + Send;
+
+    /// Send one calibration probe, returning its RTT in milliseconds and
+    /// microseconds and the response status.
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send;
+}
+
+/// URL, headers and body of an order, built before the send loop.
+#[derive(Debug)]
+pub struct PreparedRequest {
+    pub url: Url,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Copies of `url` and `headers` cloned ahead of the sends that take
+    /// them, so taking one does not allocate.
+    spares: Mutex<Vec<(Url, HeaderMap)>>,
+}
+
+impl PreparedRequest {
+    pub fn new(url: &str, headers: HeaderMap, body: String) -> Result<Self> {
+        Ok(Self {
+            url: Url::parse(url).with_context(|| format!("Invalid order URL '{}'", url))?,
+            headers,
+            body: Bytes::from(body),
+            spares: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// The body as text, for logs and curl commands.
+    pub fn body_text(&self) -> &str {
+        std::str::from_utf8(&self.body).unwrap_or_default()
+    }
+
+    /// Clone `copies` more URLs and headers for later sends.
+    pub fn stock(&self, copies: usize) {
+        let mut spares = self.spares.lock().unwrap_or_else(|e| e.into_inner());
+        spares.reserve(copies);
+        for _ in 0..copies {
+            spares.push((self.url.clone(), self.headers.clone()));
+        }
+    }
+
+    /// URL and headers for one send: a stocked copy while any are left,
+    /// a fresh clone after, with the attempt's `correlation_header`.
+    pub fn parts(&self) -> (Url, HeaderMap) {
+        let (url, mut headers) = self
+            .spares
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .pop()
+            .unwrap_or_else(|| (self.url.clone(), self.headers.clone()));
+        attempt::add_header(&mut headers);
+        (url, headers)
+    }
+}
+
+/// Failures of the run the batch loop reacts to instead of sending on.
+#[derive(Default)]
+struct FailureSignals {
+    /// `market_closed.phrases` of the broker.
+    market_closed_phrases: Vec<String>,
+    market_closed: AtomicBool,
+    rate_limited: AtomicBool,
+    /// Senders whose session expired, `None` standing for the broker itself.
+    auth_expired: Mutex<HashSet<Option<usize>>>,
+}
+
+impl FailureSignals {
+    /// Record the failure of a send through `sender`, if `result` is one.
+    fn note(&self, sender: Option<usize>, result: &Result<()>) {
+        let Err(e) = result else {
+            return;
+        };
+        let mentions_closed = e
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<OrderError>())
+            .is_some_and(|order_error| order_error.mentions(&self.market_closed_phrases));
+        if mentions_closed {
+            self.market_closed.store(true, Ordering::Relaxed);
+            return;
+        }
+        match order_error_kind(e) {
+            Some(OrderErrorKind::MarketClosed) => self.market_closed.store(true, Ordering::Relaxed),
+            Some(OrderErrorKind::RateLimited) => self.rate_limited.store(true, Ordering::Relaxed),
+            Some(OrderErrorKind::AuthExpired) => {
+                self.auth_expired
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(sender);
+            }
+            _ => {}
+        }
+    }
+
+    fn market_closed(&self) -> bool {
+        self.market_closed.load(Ordering::Relaxed)
+    }
+
+    /// Forget a closed market once the next session opened.
+    fn clear_market_closed(&self) {
+        self.market_closed.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether a send was rate limited since the last call.
+    fn take_rate_limited(&self) -> bool {
+        self.rate_limited.swap(false, Ordering::Relaxed)
+    }
+
+    /// Senders whose session expired since the last call.
+    fn take_auth_expired(&self) -> Vec<Option<usize>> {
+        self.auth_expired
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .drain()
+            .collect()
+    }
+}
+
+/// Longest wait between batches while the broker keeps rate limiting.
+const MAX_RATE_LIMIT_BACKOFF_MS: u64 = 30_000;
+
+/// (account, order index) pairs that were accepted.
+type Accepted = HashSet<(Option<usize>, usize)>;
+
+/// Per-run sending state: the rate limiter of the broker's session and of
+/// each account's, and where the account strategy stands.
+struct SendState {
+    broker_limiter: Arc<RateLimiter>,
+    account_limiters: Vec<Arc<RateLimiter>>,
+    /// Requests allowed in flight at once, shared by the broker and its
+    /// accounts; `None` without `max_concurrent_requests`.
+    request_slots: Option<Semaphore>,
+    /// Account the next round-robin order goes to.
+    next_account: AtomicUsize,
+    /// Account that got each order index accepted, for
+    /// [`AccountStrategy::FirstSuccessStopsOthers`].
+    winners: Mutex<HashMap<usize, usize>>,
+    /// Accepted and failed sends per order tag.
+    tag_results: Mutex<BTreeMap<String, TagTally>>,
+    holds: Holds,
+    depth: Option<DepthChecker>,
+    /// Prices of the orders priced at `upper_limit` or `lower_limit`.
+    limit_prices: Option<LimitPrices>,
+    /// Orders accepted per account, `None` standing for the broker itself;
+    /// only tracked with `stop_on_accept`.
+    accepted: Option<Mutex<Accepted>>,
+    /// Orders the broker pushed as registered; those are not sent again.
+    order_events: Option<OrderTracker>,
+    credit: Option<RemainingCredit>,
+    books: Option<Arc<BookRecorder>>,
+    control: Option<Arc<BrokerControl>>,
+    /// Requests of the broker's orders that are the same on every attempt,
+    /// by order index; `None` for orders rebuilt each time.
+    broker_prepared: Vec<Option<PreparedRequest>>,
+    /// The same for each account's orders.
+    account_prepared: Vec<Vec<Option<PreparedRequest>>>,
+    failures: FailureSignals,
+    circuit: Option<CircuitBreaker>,
+    sinks: BatchSinks,
+    /// Parsed `correlation_header`.
+    correlation_header: Option<HeaderName>,
+    /// Where accepted orders go to be followed under `cancel_replace`.
+    registered: OnceLock<UnboundedSender<Registered>>,
+}
+
+/// What follows the market and the broker for the length of a run, set up
+/// before the first send.
+struct Watchers {
+    holds: Holds,
+    depth: Option<DepthChecker>,
+    limit_prices: Option<LimitPrices>,
+    order_events: Option<OrderTracker>,
+    credit: Option<RemainingCredit>,
+    books: Option<Arc<BookRecorder>>,
+}
+
+/// What keeps orders from being sent until the market is right for them.
+struct Holds {
+    /// Arms orders with `send_when` once their condition holds; `None` when
+    /// no order has one or conditions are not checked.
+    conditions: Option<ConditionWatcher>,
+    /// Releases each watched symbol's orders when it fires; `None` without
+    /// a `watchlist` or in a dry run.
+    watchlist: Option<Watchlist>,
+}
+
+impl Holds {
+    fn is_held(&self, index: usize) -> bool {
+        self.conditions
+            .as_ref()
+            .is_some_and(|conditions| !conditions.is_armed(index))
+            || self
+                .watchlist
+                .as_ref()
+                .is_some_and(|watchlist| !watchlist.is_released(index))
+    }
+}
+
+/// What became of one dispatched order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dispatch {
+    Sent,
+    /// Left out by the depth check, or already accepted.
+    Skipped,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct TagTally {
+    accepted: u64,
+    failed: u64,
+}
+
+impl SendState {
+    fn new<B: Broker>(broker: &B, options: &RunOptions, watchers: Watchers) -> Result<Self> {
+        let Watchers {
+            holds,
+            depth,
+            limit_prices,
+            order_events,
+            credit,
+            books,
+        } = watchers;
+        let limiter = |sender: &B| {
+            let (broker_name, account) = accounts::split_name(sender.name());
+            let settings = sender.settings();
+            options
+                .rate_limiters
+                .get_or_create(broker_name, account, || {
+                    RateLimiter::new(settings.batch_delay_ms)
+                        .with_precise(settings.precise_rate_limit.as_ref())
+                        .with_global(options.global_limiter.clone())
+                })
+        };
+        // Everything the first batch sends is built here, so sending it
+        // allocates nothing of ours: the client, and a copy of each order's
+        // URL and headers per send. Orders priced at a daily limit get their
+        // price at send time.
+        let batch_repeat = broker.settings().batch_repeat;
+        let prepare = |sender: &B| -> Result<Vec<Option<PreparedRequest>>> {
+            sender.settings().client();
+            sender
+                .orders()
+                .iter()
+                .map(|order| {
+                    if !order.is_static() || order.price_limit.is_some() {
+                        return Ok(None);
+                    }
+                    let request = sender.prepare(&order.data, sender.order_json(order)?)?;
+                    request.stock(batch_repeat * order.repeat);
+                    Ok(Some(request))
+                })
+                .collect()
+        };
+        Ok(Self {
+            broker_limiter: limiter(broker),
+            account_limiters: broker.accounts().iter().map(limiter).collect(),
+            request_slots: broker
+                .settings()
+                .max_concurrent_requests
+                .map(Semaphore::new),
+            next_account: AtomicUsize::new(0),
+            winners: Mutex::new(HashMap::new()),
+            tag_results: Mutex::new(BTreeMap::new()),
+            holds,
+            depth,
+            limit_prices,
+            accepted: broker
+                .settings()
+                .stop_on_accept
+                .then(|| Mutex::new(HashSet::new())),
+            order_events,
+            credit,
+            books,
+            control: options
+                .control
+                .as_ref()
+                .map(|control| control.register(broker.name(), broker.orders().len())),
+            broker_prepared: prepare(broker)?,
+            account_prepared: broker
+                .accounts()
+                .iter()
+                .map(prepare)
+                .collect::<Result<_>>()?,
+            failures: FailureSignals {
+                market_closed_phrases: broker.settings().market_closed.phrases.clone(),
+                ..FailureSignals::default()
+            },
+            circuit: broker
+                .settings()
+                .circuit_breaker
+                .as_ref()
+                .map(|settings| CircuitBreaker::new(broker.name(), settings)),
+            sinks: BatchSinks::new(
+                &broker.settings().batch_sinks,
+                options.batch_results.clone(),
+                options.session_summary.clone(),
+            ),
+            correlation_header: broker
+                .settings()
+                .correlation_header
+                .as_deref()
+                .map(|header| {
+                    HeaderName::from_bytes(header.as_bytes()).with_context(|| {
+                        format!(
+                            "Invalid correlation_header '{}' for {} in {}",
+                            header,
+                            broker.name(),
+                            broker.config_file()
+                        )
+                    })
+                })
+                .transpose()?,
+            registered: OnceLock::new(),
+        })
+    }
+
+    /// Hand order `index`, accepted at `price` with `body`, to the
+    /// `cancel_replace` follower if there is one.
+    fn follow(&self, index: usize, price: f64, body: String) {
+        if let Some(registered) = self.registered.get() {
+            let _ = registered.send(Registered { index, price, body });
+        }
+    }
+
+    /// Wait for a slot under `max_concurrent_requests`, held until the
+    /// response arrives; `None` without the cap.
+    async fn request_slot(&self) -> Option<SemaphorePermit<'_>> {
+        self.request_slots.as_ref()?.acquire().await.ok()
+    }
+
+    /// A new attempt at sending an order of `broker`, with an ID of its own.
+    fn new_attempt(&self, broker: &str) -> Attempt {
+        Attempt::new(broker, self.correlation_header.as_ref())
+    }
+
+    /// Whether order `index` was disabled from the control API.
+    fn is_disabled(&self, index: usize) -> bool {
+        self.control
+            .as_ref()
+            .is_some_and(|control| control.is_disabled(index))
+    }
+
+    /// Wait while the control API has the broker paused.
+    async fn wait_while_paused(&self, name: &str) {
+        if let Some(control) = &self.control
+            && control.is_paused()
+        {
+            println!("[{}] Paused; waiting to be resumed", name);
+            control.wait_while_paused().await;
+        }
+    }
+
+    /// Feed the outcome of a send through `sender` to the failure reactions
+    /// and the circuit breaker.
+    fn note_result(&self, sender: Option<usize>, result: &Result<()>) {
+        self.failures.note(sender, result);
+        if let Some(circuit) = &self.circuit {
+            circuit.record(result);
+        }
+    }
+
+    /// Return when the control API asks for an immediate send; never
+    /// without it.
+    async fn send_now_requested(&self) {
+        match &self.control {
+            Some(control) => control.send_now_requested().await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Whether order `index` waits for its `send_when` condition or its
+    /// watchlist entry.
+    fn is_held(&self, index: usize) -> bool {
+        self.holds.is_held(index)
+    }
+
+    /// Whether order `index` was already accepted through `account`.
+    fn is_accepted(&self, account: Option<usize>, index: usize) -> bool {
+        self.accepted.as_ref().is_some_and(|accepted| {
+            accepted
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .contains(&(account, index))
+        })
+    }
+
+    fn record_accepted(&self, account: Option<usize>, index: usize) {
+        if let Some(accepted) = &self.accepted {
+            accepted
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert((account, index));
+        }
+    }
+
+    /// Whether the broker pushed that order `index` was registered.
+    fn is_confirmed(&self, index: usize) -> bool {
+        self.order_events
+            .as_ref()
+            .is_some_and(|order_events| order_events.status(index).is_some())
+    }
+
+    /// Return once the broker pushes that order `index` was registered;
+    /// never without push notifications.
+    async fn confirmed(&self, index: usize) {
+        match &self.order_events {
+            Some(order_events) => order_events.accepted(index).await,
+            None => std::future::pending().await,
+        }
+    }
+
+    /// Whether order `index` was pushed as registered, or `stop_on_accept`
+    /// leaves nothing to send for it.
+    fn is_done(&self, index: usize) -> bool {
+        if self.is_confirmed(index) {
+            return true;
+        }
+        if self.accepted.is_none() {
+            return false;
+        }
+        // Only the winning account sends a won order again.
+        if self
+            .winners
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .contains_key(&index)
+        {
+            return true;
+        }
+        if self.account_limiters.is_empty() {
+            self.is_accepted(None, index)
+        } else {
+            (0..self.account_limiters.len()).all(|account| self.is_accepted(Some(account), index))
+        }
+    }
+
+    /// Whether `sender`, the broker itself or one of its accounts by
+    /// `account`, can still pay for its order `index` at `price`.
+    fn can_afford<B: Broker>(
+        &self,
+        sender: &B,
+        account: Option<usize>,
+        index: usize,
+        price: Option<f64>,
+    ) -> bool {
+        let Some(credit) = &self.credit else {
+            return true;
+        };
+        let orders = sender.orders();
+        let index = index % orders.len();
+        credit::cost(&orders[index].data, price)
+            .is_none_or(|cost| credit.covers(account.unwrap_or(0), index, cost))
+    }
+
+    fn record_tags(&self, tags: &[String], accepted: bool) {
+        let mut tag_results = self.tag_results.lock().unwrap_or_else(|e| e.into_inner());
+        for tag in tags {
+            let tally = tag_results.entry(tag.to_lowercase()).or_default();
+            if accepted {
+                tally.accepted += 1;
+            } else {
+                tally.failed += 1;
+            }
+        }
+    }
+
+    /// Print accepted and failed sends per tag, if any order is tagged.
+    fn print_tag_report(&self, name: &str) {
+        let tag_results = self.tag_results.lock().unwrap_or_else(|e| e.into_inner());
+        for (tag, tally) in tag_results.iter() {
+            summary!(
+                "[{}] Tag {}: {} accepted, {} failed",
+                name,
+                tag,
+                tally.accepted,
+                tally.failed
+            );
+        }
+    }
+}
+
+pub async fn run_broker<B: Broker>(broker: B, options: RunOptions) -> Result<()> {
+    let Some(max_runtime_secs) = broker.settings().max_runtime_secs else {
+        return send_orders(broker, options).await;
+    };
+    let name = broker.name().to_string();
+    let max_runtime = std::time::Duration::from_secs(max_runtime_secs);
+    match tokio::time::timeout(max_runtime, send_orders(broker, options)).await {
+        Ok(result) => result,
+        Err(_) => {
+            summary!(
+                "[{}] Stopping: max_runtime_secs ({}s) reached",
+                name,
+                max_runtime_secs
+            );
+            Ok(())
+        }
+    }
+}
+
+async fn send_orders<B: Broker>(mut broker: B, options: RunOptions) -> Result<()> {
+    let readiness = Readiness::register();
+    resolve_symbols(&mut broker, &options).await?;
+    let mut order_filter = options.order_filter.clone();
+    if !order_filter.has_tags() {
+        order_filter = order_filter.with_tags(&broker.settings().enabled_tags);
+    }
+    if !order_filter.is_empty() {
+        select_orders(&mut broker, &order_filter)?;
+    }
+    ipo::apply(&mut broker)?;
+    twap::apply(&mut broker)?;
+    cancel_replace::apply(&mut broker)?;
+    expand_orders(&mut broker)?;
+    let broker = Arc::new(broker);
+    let settings = broker.settings();
+    let name = broker.name();
+
+    println!("[{}] Starting order sender", name);
+
+    let senders = accounts::senders(broker.as_ref());
+    if !broker.accounts().is_empty() {
+        println!("[{}] Sending through {} account(s).", name, senders.len());
+    }
+    for sender in &senders {
+        if options.dry_run {
+            println!("[{}] Dry run: skipping login.", sender.name());
+        } else {
+            sender.ensure_session().await?;
+        }
+        sender.check_auth()?;
+
+        if sender.orders().is_empty() {
+            anyhow::bail!(
+                "No orders configured for {} in {}.",
+                sender.name(),
+                broker.config_file()
+            );
+        }
+    }
+    if settings.max_concurrent_requests == Some(0) {
+        anyhow::bail!(
+            "max_concurrent_requests must be >= 1 for {} in {}.",
+            name,
+            broker.config_file()
+        );
+    }
+    if settings.batch_repeat == 0 {
+        anyhow::bail!(
+            "batch_repeat must be >= 1 for {} in {}.",
+            name,
+            broker.config_file()
+        );
+    }
+    if let Some(limit) = &settings.in_flight_limit {
+        let all: Vec<usize> = (0..broker.orders().len()).collect();
+        let batch = orders::batch_sequence(broker.orders(), &all, settings.batch_repeat).len();
+        if limit.max_tasks < batch {
+            anyhow::bail!(
+                "in_flight_limit.max_tasks is {} but a batch of {} sends {} order tasks; raise it to at least {} in {}.",
+                limit.max_tasks,
+                name,
+                batch,
+                batch,
+                broker.config_file()
+            );
+        }
+    }
+
+    if options.curl_only {
+        println!(
+            "[{}] Curl-only mode: printing {} order(s) without sending.",
+            name,
+            broker.orders().len()
+        );
+        for sender in &senders {
+            for order in sender.orders() {
+                Attempt::new(name, None)
+                    .scope(async {
+                        let request = sender.prepare(&order.data, sender.order_json(order)?)?;
+                        sender
+                            .send_order(&order.data, &request, options.test_mode, true, None)
+                            .await
+                    })
+                    .await?;
+            }
+        }
+        return Ok(());
+    }
+
+    if let Some(rule) = &settings.price_check {
+        if options.dry_run {
+            println!("[{}] Dry run: skipping price checks.", name);
+        } else {
+            for sender in &senders {
+                price_check::check_prices(
+                    sender.name(),
+                    rule,
+                    sender.orders(),
+                    &settings.market_data_url,
+                )
+                .await?;
+            }
+        }
+    }
+
+    let has_conditions = broker
+        .orders()
+        .iter()
+        .any(|order| order.send_when.is_some());
+    let conditions = if has_conditions && options.dry_run {
+        println!(
+            "[{}] Dry run: not polling quotes; send_when conditions count as met.",
+            name
+        );
+        None
+    } else {
+        ConditionWatcher::start(
+            name,
+            broker.orders(),
+            &settings.market_data_url,
+            settings.quote_poll_interval_ms,
+            settings.market_feed.as_ref(),
+        )
+        .await?
+    };
+    let depth = match &settings.depth_check {
+        Some(_) if options.dry_run => {
+            println!("[{}] Dry run: skipping order book depth checks.", name);
+            None
+        }
+        Some(rule) => {
+            Some(DepthChecker::new(rule, broker.orders(), &settings.market_data_url).await?)
+        }
+        None => None,
+    };
+    let has_limit_prices = broker
+        .orders()
+        .iter()
+        .any(|order| order.price_limit.is_some());
+    let limit_prices = if has_limit_prices && options.dry_run {
+        println!(
+            "[{}] Dry run: not fetching daily limits; upper_limit/lower_limit orders show price 0.",
+            name
+        );
+        None
+    } else {
+        let limit_prices =
+            LimitPrices::new(name, broker.orders(), &settings.market_data_url).await?;
+        if let Some(limit_prices) = &limit_prices {
+            limit_prices.refresh(name).await?;
+        }
+        limit_prices
+    };
+    let order_events = match &settings.push_notifications {
+        Some(_) if options.dry_run => {
+            println!("[{}] Dry run: not connecting to push notifications.", name);
+            None
+        }
+        Some(push) => Some(
+            OrderTracker::start(
+                name,
+                push,
+                &session_headers(broker.as_ref())?,
+                broker.orders(),
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let credit = match &settings.credit_check {
+        Some(_) if options.dry_run => {
+            println!("[{}] Dry run: not checking the remaining credit.", name);
+            None
+        }
+        Some(check) => {
+            let senders = accounts::senders(broker.as_ref())
+                .into_iter()
+                .map(|sender| Ok((sender.name().to_string(), session_headers(sender)?)))
+                .collect::<Result<_>>()?;
+            let credit = RemainingCredit::new(check, senders)?;
+            credit.refresh().await?;
+            Some(credit)
+        }
+        None => None,
+    };
+    let watchlist = if !settings.watchlist.is_empty() && options.dry_run {
+        println!(
+            "[{}] Dry run: not watching the watchlist; every symbol counts as fired.",
+            name
+        );
+        None
+    } else {
+        Watchlist::start(
+            name,
+            &settings.watchlist,
+            broker.orders(),
+            &settings.market_data_url,
+            settings.market_feed.as_ref(),
+        )?
+    };
+    let books = match &settings.book_snapshots {
+        Some(_) if options.dry_run => {
+            println!("[{}] Dry run: not taking order book snapshots.", name);
+            None
+        }
+        Some(snapshots) => Some(Arc::new(
+            BookRecorder::new(
+                name,
+                snapshots,
+                broker.orders(),
+                &settings.market_data_url,
+                options.session_summary.clone(),
+            )
+            .await?,
+        )),
+        None => None,
+    };
+    let send_state = Arc::new(SendState::new(
+        broker.as_ref(),
+        &options,
+        Watchers {
+            holds: Holds {
+                conditions,
+                watchlist,
+            },
+            depth,
+            limit_prices,
+            order_events,
+            credit,
+            books,
+        },
+    )?);
+    if let Some(session) = &options.session_summary {
+        session.register(name, senders.len(), settings);
+    }
+
+    let follower = match &settings.cancel_replace {
+        Some(_) if options.dry_run => {
+            println!(
+                "[{}] Dry run: not following orders to cancel and replace them.",
+                name
+            );
+            None
+        }
+        Some(cancel_replace) => {
+            let resender = BrokerResender {
+                broker: broker.clone(),
+                send_state: send_state.clone(),
+                options: options.clone(),
+            };
+            let (follower, registered) = Follower::start(name, cancel_replace, resender);
+            let _ = send_state.registered.set(registered);
+            Some(follower)
+        }
+        None => None,
+    };
+
+    let result = run_sends(broker, send_state, options, readiness).await;
+    if let Some(follower) = follower
+        && result.is_ok()
+    {
+        follower.finish().await;
+    }
+    result
+}
+
+/// Send the test order, or run the scheduled or continuous sends, as the
+/// options and settings pick.
+async fn run_sends<B: Broker>(
+    broker: Arc<B>,
+    send_state: Arc<SendState>,
+    options: RunOptions,
+    mut readiness: Readiness,
+) -> Result<()> {
+    let settings = broker.settings();
+    let name = broker.name();
+
+    if options.test_mode {
+        println!(
+            "[{}] Test mode: sending one order immediately without scheduling.",
+            name
+        );
+        if send_state.is_held(0) {
+            println!(
+                "[{}] Test mode: order #1 is held by send_when or the watchlist; nothing sent.",
+                name
+            );
+            return Ok(());
+        }
+        let mut batch_result = BatchResult::new(name, 1);
+        let sent_at_epoch_ms = batch_result.started_epoch_ms;
+        let dispatch_start = std::time::Instant::now();
+        let (result, outcome) = send_state
+            .new_attempt(broker.name())
+            .scope(async {
+                let result = dispatch_order(broker.as_ref(), 0, &options, &send_state).await;
+                let outcome = OrderOutcome::new(
+                    0,
+                    &broker.orders()[0].tags,
+                    outcome_status(&result),
+                    sent_at_epoch_ms,
+                    dispatch_start.elapsed(),
+                );
+                (result, outcome)
+            })
+            .await;
+        batch_result.elapsed_ms = dispatch_start.elapsed().as_millis() as u64;
+        batch_result.orders.push(outcome);
+        send_state.sinks.write(&batch_result).await;
+        send_state.print_tag_report(name);
+        let dispatch = result.with_context(|| format!("Failed to send test order for {}", name))?;
+        if matches!(dispatch, Dispatch::Sent) {
+            summary!("[{}] ✓ Test order: Sent successfully", name);
+        }
+        return Ok(());
+    }
+
+    if let Some(trigger) = &settings.wait_for_trading {
+        if settings.target_time.is_some() {
+            anyhow::bail!(
+                "{} in {} sets both target_time and wait_for_trading; keep one.",
+                name,
+                broker.config_file()
+            );
+        }
+        if options.dry_run {
+            println!(
+                "[{}] Dry run: not polling the trading state of {}.",
+                name, trigger.isin
+            );
+        } else {
+            market_data::wait_for_trading(
+                name,
+                trigger,
+                &settings.market_data_url,
+                settings.market_feed.as_ref(),
+            )
+            .await?;
+        }
+    }
+
+    if let Some(thread) = &settings.dispatch_thread {
+        println!(
+            "[{}] Sending from a dedicated dispatch thread ({:?} priority)",
+            name, thread.priority
+        );
+        let target_time = settings.target_time.clone();
+        let broker = broker.clone();
+        return dispatch::run_dedicated(name, thread, async move {
+            match target_time {
+                Some(target_time) => {
+                    run_scheduled(
+                        broker.as_ref(),
+                        &target_time,
+                        send_state.as_ref(),
+                        &options,
+                        &mut readiness,
+                    )
+                    .await
+                }
+                None => {
+                    readiness.ready();
+                    run_continuous(broker, send_state, options).await
+                }
+            }
+        })
+        .await?;
+    }
+
+    if let Some(target_time_str) = &settings.target_time {
+        return run_scheduled(
+            broker.as_ref(),
+            target_time_str,
+            send_state.as_ref(),
+            &options,
+            &mut readiness,
+        )
+        .await;
+    }
+
+    readiness.ready();
+    run_continuous(broker, send_state, options).await
+}
+
+/// Sends an order `cancel_replace` replaces through the broker, the way
+/// [`dispatch_to_senders`] sends it.
+struct BrokerResender<B> {
+    broker: Arc<B>,
+    send_state: Arc<SendState>,
+    options: RunOptions,
+}
+
+impl<B: Broker> Resender for BrokerResender<B> {
+    fn session_headers(&self) -> Result<HeaderMap> {
+        session_headers(self.broker.as_ref())
+    }
+
+    async fn resend(&self, index: usize, price: f64) -> Result<String> {
+        let broker = self.broker.as_ref();
+        let send_state = self.send_state.as_ref();
+        send_state
+            .new_attempt(broker.name())
+            .scope(async {
+                let _slot = send_state.request_slot().await;
+                let (result, accepted) = success::capture_accepted(send_through(
+                    broker,
+                    index,
+                    Some(price),
+                    &self.options,
+                    &send_state.broker_limiter,
+                    &send_state.broker_prepared,
+                ))
+                .await;
+                send_state.note_result(None, &result);
+                result?;
+                accepted.context("The broker accepted the replacement without a response body")
+            })
+            .await
+    }
+}
+
+/// Fill in the ISIN of orders configured by `symbol`, for the broker and each
+/// of its accounts. Dry runs leave them empty rather than connect.
+async fn resolve_symbols<B: Broker>(broker: &mut B, options: &RunOptions) -> Result<()> {
+    if options.dry_run {
+        return Ok(());
+    }
+    let name = broker.name().to_string();
+    let base_url = broker.settings().market_data_url.clone();
+    market_data::resolve_symbols(&name, broker.orders_mut(), &base_url).await?;
+    for account in broker.accounts_mut() {
+        let account_name = account.name().to_string();
+        market_data::resolve_symbols(&account_name, account.orders_mut(), &base_url).await?;
+    }
+    Ok(())
+}
+
+/// Spread laddered orders over their prices and split tranched ones over
+/// time, for the broker and each of its accounts. Runs after `--orders` is
+/// applied, which counts configured orders, and after the IPO profile has
+/// set quantities.
+fn expand_orders<B: Broker>(broker: &mut B) -> Result<()> {
+    let name = broker.name().to_string();
+    orders::expand_ladders(&name, broker.orders_mut())?;
+    orders::expand_tranches(&name, broker.orders_mut())?;
+    for account in broker.accounts_mut() {
+        let account_name = account.name().to_string();
+        orders::expand_ladders(&account_name, account.orders_mut())?;
+        orders::expand_tranches(&account_name, account.orders_mut())?;
+    }
+    Ok(())
+}
+
+/// The headers that carry `sender`'s session, without the ones describing
+/// an order body, for the other endpoints of the broker.
+fn session_headers<B: Broker>(sender: &B) -> Result<HeaderMap> {
+    let mut headers = sender.order_headers("{}")?;
+    for header in [CONTENT_TYPE, CONTENT_LENGTH, ACCEPT_ENCODING] {
+        headers.remove(header);
+    }
+    Ok(headers)
+}
+
+/// Drop the orders `filter` does not select, from the broker and each of its
+/// accounts.
+fn select_orders<B: Broker>(broker: &mut B, filter: &OrderFilter) -> Result<()> {
+    let configured = broker.orders().len();
+    filter.retain(broker.orders_mut())?;
+    for account in broker.accounts_mut() {
+        filter.retain(account.orders_mut())?;
+    }
+    let selected = broker.orders().len();
+    if accounts::senders(broker)
+        .iter()
+        .any(|sender| sender.orders().is_empty())
+    {
+        anyhow::bail!(
+            "No orders of {} in {} match {}.",
+            broker.name(),
+            broker.config_file(),
+            filter
+        );
+    }
+    println!(
+        "[{}] Selected {} of {} order(s) with {}",
+        broker.name(),
+        selected,
+        configured,
+        filter
+    );
+    Ok(())
+}
+
+/// Send order `index` at its daily limit price if it has one, unless the
+/// depth check skips it, and count the result for each of the order's tags.
+async fn dispatch_order<B: Broker>(
+    broker: &B,
+    index: usize,
+    options: &RunOptions,
+    send_state: &SendState,
+) -> Result<Dispatch> {
+    let order = &broker.orders()[index];
+    if send_state.is_done(index)
+        || send_state.is_disabled(index)
+        || send_state.failures.market_closed()
+    {
+        return Ok(Dispatch::Skipped);
+    }
+    let limit_price = match &send_state.limit_prices {
+        Some(limit_prices) if limit_prices.is_limit_order(index) => Some(
+            limit_prices
+                .price(index)
+                .with_context(|| format!("No daily limit price for order #{} yet", index + 1))?,
+        ),
+        _ => None,
+    };
+    let price = match &send_state.depth {
+        Some(depth) => match depth
+            .check(broker.name(), index + 1, &order.data, limit_price)
+            .await
+        {
+            DepthDecision::Send => limit_price,
+            DepthDecision::Skip => return Ok(Dispatch::Skipped),
+            DepthDecision::Reprice(price) => Some(price),
+        },
+        None => limit_price,
+    };
+    if let Some(circuit) = &send_state.circuit
+        && !circuit.admit()
+    {
+        return Ok(Dispatch::Skipped);
+    }
+    // A push confirming the order cancels the attempt, wherever it got to.
+    let result = tokio::select! {
+        result = dispatch_to_senders(broker, index, price, options, send_state) => result,
+        _ = send_state.confirmed(index) => {
+            summary!(
+                "[{}] Order #{} was registered; cancelling its remaining attempts",
+                broker.name(),
+                index + 1
+            );
+            Ok(Dispatch::Skipped)
+        }
+    };
+    if let (Some(circuit), Ok(Dispatch::Skipped)) = (&send_state.circuit, &result) {
+        circuit.release();
+    }
+    if !options.dry_run && !matches!(result, Ok(Dispatch::Skipped)) {
+        send_state.record_tags(&order.tags, result.is_ok());
+        if let Some(control) = &send_state.control {
+            control.record_send(index, &result);
+        }
+    }
+    result
+}
+
+/// Send order `index` through the broker, or through its accounts as the
+/// account strategy picks them, with a per-account report. Succeeds when at
+/// least one account got it through; skipped when every picked account
+/// already got it accepted under `stop_on_accept`.
+async fn dispatch_to_senders<B: Broker>(
+    broker: &B,
+    index: usize,
+    price: Option<f64>,
+    options: &RunOptions,
+    send_state: &SendState,
+) -> Result<Dispatch> {
+    let accounts = broker.accounts();
+    if accounts.is_empty() {
+        if !send_state.can_afford(broker, None, index, price) {
+            return Ok(Dispatch::Skipped);
+        }
+        let _slot = send_state.request_slot().await;
+        let (result, accepted) = success::capture_accepted(send_through(
+            broker,
+            index,
+            price,
+            options,
+            &send_state.broker_limiter,
+            &send_state.broker_prepared,
+        ))
+        .await;
+        send_state.note_result(None, &result);
+        result?;
+        send_state.record_accepted(None, index);
+        if let (Some(body), Some(price)) = (
+            accepted,
+            price.or_else(|| broker.orders()[index].data.price()),
+        ) {
+            send_state.follow(index, price, body);
+        }
+        return Ok(Dispatch::Sent);
+    }
+
+    let strategy = broker.settings().account_strategy;
+    let winner = send_state
+        .winners
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&index)
+        .copied();
+    let selected: Vec<usize> = match (strategy, winner) {
+        (AccountStrategy::BroadcastAll, _) => (0..accounts.len()).collect(),
+        (AccountStrategy::RoundRobin, _) => {
+            vec![send_state.next_account.fetch_add(1, Ordering::Relaxed) % accounts.len()]
+        }
+        (AccountStrategy::FirstSuccessStopsOthers, Some(winner)) => vec![winner],
+        (AccountStrategy::FirstSuccessStopsOthers, None) => (0..accounts.len()).collect(),
+    };
+    let selected: Vec<usize> = selected
+        .into_iter()
+        .filter(|&account_index| {
+            !send_state.is_accepted(Some(account_index), index)
+                && send_state.can_afford(
+                    &accounts[account_index],
+                    Some(account_index),
+                    index,
+                    price,
+                )
+        })
+        .collect();
+    if selected.is_empty() {
+        return Ok(Dispatch::Skipped);
+    }
+
+    let mut sends: FuturesUnordered<_> = selected
+        .iter()
+        .map(|&account_index| {
+            let account = &accounts[account_index];
+            let limiter = &send_state.account_limiters[account_index];
+            let prepared = &send_state.account_prepared[account_index];
+            async move {
+                let _slot = send_state.request_slot().await;
+                (
+                    account_index,
+                    send_through(account, index, price, options, limiter, prepared).await,
+                )
+            }
+        })
+        .collect();
+    let mut results = Vec::with_capacity(selected.len());
+    while let Some((account_index, result)) = sends.next().await {
+        send_state.note_result(Some(account_index), &result);
+        let accepted = result.is_ok();
+        if accepted {
+            send_state.record_accepted(Some(account_index), index);
+        }
+        results.push((account_index, result));
+        if accepted && strategy == AccountStrategy::FirstSuccessStopsOthers {
+            // Dropping the remaining sends cancels them.
+            send_state
+                .winners
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(index, account_index);
+            break;
+        }
+    }
+    drop(sends);
+
+    let report: Vec<String> = selected
+        .iter()
+        .map(|&account_index| {
+            let label = accounts::account_label(broker, &accounts[account_index]);
+            match results.iter().find(|(i, _)| *i == account_index) {
+                Some((_, Ok(()))) => format!("{} ✓", label),
+                Some((_, Err(e))) => format!("{} ✗ {}", label, e),
+                None => format!("{} cancelled", label),
+            }
+        })
+        .collect();
+    summary!("[{}] Accounts: {}", broker.name(), report.join(", "));
+
+    if results.iter().any(|(_, result)| result.is_ok()) {
+        return Ok(Dispatch::Sent);
+    }
+    if results.len() == 1 {
+        return results.remove(0).1.map(|()| Dispatch::Sent);
+    }
+    anyhow::bail!("All {} accounts failed", results.len())
+}
+
+/// Send order `index` of `sender`'s own orders, at `price` when given, or in
+/// dry-run mode log exactly what would be sent. `prepared` holds the requests
+/// built ahead of the run.
+async fn send_through<B: Broker>(
+    sender: &B,
+    index: usize,
+    price: Option<f64>,
+    options: &RunOptions,
+    rate_limiter: &RateLimiter,
+    prepared: &[Option<PreparedRequest>],
+) -> Result<()> {
+    let orders = sender.orders();
+    let index = index % orders.len();
+    let order = &orders[index];
+    let built;
+    let request = match (price, &prepared[index]) {
+        (None, Some(request)) => request,
+        (Some(price), _) => {
+            let mut order = order.clone();
+            order.data.set_price(price);
+            built = sender.prepare(&order.data, sender.order_json(&order)?)?;
+            &built
+        }
+        (None, None) => {
+            built = sender.prepare(&order.data, sender.order_json(order)?)?;
+            &built
+        }
+    };
+    if options.dry_run {
+        return log_dry_run(sender, request, rate_limiter).await;
+    }
+    sender
+        .send_order(
+            &order.data,
+            request,
+            options.test_mode,
+            options.curl_only,
+            Some(rate_limiter),
+        )
+        .await
+}
+
+async fn log_dry_run<B: Broker>(
+    broker: &B,
+    request: &PreparedRequest,
+    rate_limiter: &RateLimiter,
+) -> Result<()> {
+    let headers = &request.headers;
+    rate_limiter
+        .wait_for(request_size(headers, request.body.len()))
+        .await;
+
+    let name = broker.name();
+    let now = chrono::Utc::now().with_timezone(&Tehran);
+    println!(
+        "[{}] DRY RUN {} would POST {}",
+        name,
+        now.format("%H:%M:%S%.6f"),
+        request.url
+    );
+    for (header, value) in headers {
+        println!(
+            "[{}]   {}: {}",
+            name,
+            header,
+            String::from_utf8_lossy(value.as_bytes())
+        );
+    }
+    println!("[{}]   Body: {}", name, request.body_text());
+    Ok(())
+}
+
+async fn run_scheduled<B: Broker>(
+    broker: &B,
+    target_time_str: &str,
+    send_state: &SendState,
+    options: &RunOptions,
+    readiness: &mut Readiness,
+) -> Result<()> {
+    let settings = broker.settings();
+    let name = broker.name();
+
+    println!(
+        "[{}] Scheduled mode enabled for target time {}",
+        name, target_time_str
+    );
+    let target_time = chrono::NaiveTime::parse_from_str(target_time_str, "%H:%M:%S%.3f")
+        .context("target_time must be in HH:MM:SS.mmm format")?;
+    let calibration_enabled = settings
+        .calibration
+        .as_ref()
+        .is_some_and(|calibration| calibration.enabled);
+    if calibration_enabled && options.dry_run {
+        println!(
+            "[{}] Dry run: skipping calibration probes; using zero delay estimate.",
+            name
+        );
+    }
+    let calibration_enabled = calibration_enabled && !options.dry_run;
+    if let Some(warmup) = &settings.connection_warmup
+        && warmup.interval_ms < settings.batch_delay_ms
+    {
+        anyhow::bail!(
+            "connection_warmup.interval_ms ({}) must be >= batch_delay_ms ({})",
+            warmup.interval_ms,
+            settings.batch_delay_ms
+        );
+    }
+    let warmup = settings
+        .connection_warmup
+        .as_ref()
+        .filter(|_| !options.dry_run);
+    if settings.connection_warmup.is_some() && options.dry_run {
+        println!("[{}] Dry run: skipping connection warm-up.", name);
+    }
+    // Probe with working credentials, the first account's when the broker's
+    // own are not used, pacing probes on that session.
+    let (prober, probe_limiter) = match broker.accounts().first() {
+        Some(account) => (account, &send_state.account_limiters[0]),
+        None => (broker, &send_state.broker_limiter),
+    };
+    let calendar = match settings.holidays.calendar.as_deref() {
+        Some(source) if options.dry_run && source.contains("://") => {
+            println!(
+                "[{}] Dry run: not downloading {}; using the bundled holiday table.",
+                name, source
+            );
+            HolidayCalendar::bundled()
+        }
+        source => HolidayCalendar::load(source).await?,
+    };
+
+    let mut round = 0u64;
+    loop {
+        let target_datetime = calendar.schedule(
+            name,
+            next_target_datetime(target_time)?,
+            settings.holidays.on_holiday,
+        )?;
+        let target_epoch_ms = target_datetime.timestamp_millis();
+        let now_epoch_ms = current_epoch_millis()?;
+        if now_epoch_ms < target_epoch_ms {
+            println!(
+                "[{}] Next target_time={} (epoch_ms={})",
+                name,
+                target_datetime.format("%Y-%m-%d %H:%M:%S%.3f"),
+                target_epoch_ms
+            );
+        }
+
+        let mut last_wall_epoch_ms = now_epoch_ms;
+
+        if calibration_enabled {
+            let calibration = settings
+                .calibration
+                .as_ref()
+                .context("Calibration config missing")?;
+            let expected_duration_ms =
+                calibration.max_probes() as i64 * calibration.probe_interval_ms as i64;
+            let max_delay_ms =
+                broker.adjust_delay_estimate(calibration.max_acceptable_rtt_ms) as i64;
+            let estimated_effective_delay_ms = max_delay_ms + calibration.safety_margin_ms as i64;
+            // Calibration finishes before the warm-up probes take over.
+            let warmup_window_ms = warmup.map_or(0, |warmup| warmup.window_ms as i64);
+            let latest_probe_finish_epoch_ms = target_epoch_ms
+                - estimated_effective_delay_ms
+                - settings.batch_delay_ms as i64
+                - warmup_window_ms;
+            let calibration_start_epoch_ms = latest_probe_finish_epoch_ms - expected_duration_ms;
+            if now_epoch_ms < calibration_start_epoch_ms {
+                let sleep_ms = calibration_start_epoch_ms - now_epoch_ms;
+                println!(
+                    "[{}] Waiting {}ms before calibration window (epoch_ms={})",
+                    name, sleep_ms, calibration_start_epoch_ms
+                );
+                tokio::time::sleep(std::time::Duration::from_millis(sleep_ms as u64)).await;
+            }
+            let now_epoch_ms = current_epoch_millis()?;
+            if now_epoch_ms > latest_probe_finish_epoch_ms {
+                anyhow::bail!(
+                    "Too late to calibrate before target_time; start earlier or reduce probes"
+                );
+            }
+            last_wall_epoch_ms = now_epoch_ms;
+        }
+
+        let (estimated_delay_ms, safety_margin_ms, last_probe_wall_time) = if calibration_enabled {
+            let summary = prober
+                .run_calibration(prober.settings().client(), probe_limiter)
+                .await?;
+            let estimated_delay_ms = broker.adjust_delay_estimate(summary.estimated_delay_ms);
+            if estimated_delay_ms != summary.estimated_delay_ms {
+                println!(
+                    "[{}] Delay model applied, estimate now {}ms",
+                    name, estimated_delay_ms
+                );
+            }
+            (
+                estimated_delay_ms,
+                settings
+                    .calibration
+                    .as_ref()
+                    .map(|calibration| calibration.safety_margin_ms)
+                    .unwrap_or_default(),
+                summary.last_probe_wall_time,
+            )
+        } else {
+            if !options.dry_run {
+                println!(
+                    "[{}] Calibration disabled; using zero delay estimate.",
+                    name
+                );
+            }
+            (0, 0, std::time::SystemTime::now())
+        };
+
+        let effective_delay_ms = estimated_delay_ms + safety_margin_ms;
+        let final_send_epoch_ms = target_epoch_ms - effective_delay_ms as i64;
+        let final_send_time = chrono::DateTime::<chrono::Utc>::from(
+            std::time::UNIX_EPOCH + std::time::Duration::from_millis(final_send_epoch_ms as u64),
+        )
+        .with_timezone(&Tehran);
+
+        let now_epoch_ms = current_epoch_millis()?;
+        if final_send_epoch_ms <= now_epoch_ms {
+            anyhow::bail!(
+                "final_send_time has already passed; increase target_time or reduce delay"
+            );
+        }
+
+        if calibration_enabled {
+            let last_probe_epoch_ms = last_probe_wall_time
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_millis() as i64;
+            let gap_ms = final_send_epoch_ms - last_probe_epoch_ms;
+            if gap_ms < settings.batch_delay_ms as i64 {
+                anyhow::bail!(
+                    "Last probe is too close to final_send_time; ensure at least {}ms gap",
+                    settings.batch_delay_ms
+                );
+            }
+        }
+
+        println!(
+            "[{}] target_time={} final_send_time={} estimator_delay={}ms safety_margin={}ms effective_delay={}ms",
+            name,
+            target_datetime.format("%H:%M:%S%.3f"),
+            final_send_time.format("%H:%M:%S%.3f"),
+            estimated_delay_ms,
+            safety_margin_ms,
+            effective_delay_ms
+        );
+        println!(
+            "[{}] target_epoch_ms={} final_send_epoch_ms={}",
+            name, target_epoch_ms, final_send_epoch_ms
+        );
+        readiness.ready();
+        if let Some(books) = &send_state.books {
+            books.record_around(final_send_epoch_ms);
+        }
+
+        // A new day's limits are only published in the morning, so fetch
+        // them just before the send rather than right after the last one.
+        if let Some(limit_prices) = &send_state.limit_prices
+            && limit_prices.is_stale()
+        {
+            wait_until_epoch_ms(
+                final_send_epoch_ms - limit_prices::REFRESH_LEAD_MS,
+                &mut last_wall_epoch_ms,
+            )
+            .await?;
+            limit_prices.refresh_if_stale(name).await;
+        }
+        if let Some(warmup) = warmup {
+            warm_up_connection(
+                prober,
+                probe_limiter,
+                warmup,
+                final_send_epoch_ms,
+                &mut last_wall_epoch_ms,
+            )
+            .await?;
+        }
+
+        let orders = broker.orders();
+        let sequence = orders::batch_sequence(
+            orders,
+            &orders::priority_order(orders),
+            settings.batch_repeat,
+        );
+        let total_orders = sequence.len();
+        let order_spacing_ms = settings.order_spacing_ms.unwrap_or(settings.batch_delay_ms) as i64;
+        // Later tranches keep their place in the spacing, shifted by their
+        // offset, and go out in time order.
+        let mut schedule: Vec<(usize, i64)> = sequence
+            .into_iter()
+            .enumerate()
+            .map(|(position, index)| {
+                let offset_ms =
+                    position as i64 * order_spacing_ms + orders[index].send_after_ms as i64;
+                (index, final_send_epoch_ms + offset_ms)
+            })
+            .collect();
+        schedule.sort_by_key(|&(_, scheduled_epoch_ms)| scheduled_epoch_ms);
+        round += 1;
+        let mut round_result = BatchResult::new(name, round);
+        let round_start = std::time::Instant::now();
+        let mut order_index = 0usize;
+        while order_index < total_orders {
+            let (index, scheduled_epoch_ms) = schedule[order_index];
+            let now_epoch_ms = current_epoch_millis()?;
+            if now_epoch_ms > scheduled_epoch_ms {
+                summary!(
+                    "[{}] Warning: scheduled send time passed by {}ms for order #{}",
+                    name,
+                    now_epoch_ms - scheduled_epoch_ms,
+                    order_index + 1
+                );
+            }
+            tokio::select! {
+                result = wait_until_epoch_ms(scheduled_epoch_ms, &mut last_wall_epoch_ms) => result?,
+                _ = send_state.send_now_requested() => println!(
+                    "[{}] Sending scheduled order #{} now, ahead of schedule",
+                    name,
+                    order_index + 1
+                ),
+            }
+            let skip_reason = if send_state
+                .control
+                .as_ref()
+                .is_some_and(|control| control.is_paused())
+            {
+                Some("paused")
+            } else if send_state.is_done(index) {
+                Some("already accepted")
+            } else if send_state.is_held(index) {
+                Some("held by send_when or the watchlist")
+            } else {
+                None
+            };
+            if let Some(reason) = skip_reason {
+                println!(
+                    "[{}] Skipping scheduled order #{}: {}",
+                    name,
+                    order_index + 1,
+                    reason
+                );
+                round_result.orders.push(OrderOutcome::new(
+                    index,
+                    &orders[index].tags,
+                    Ok(OrderStatus::Skipped),
+                    current_epoch_millis()?,
+                    std::time::Duration::ZERO,
+                ));
+                order_index += 1;
+                continue;
+            }
+
+            // Logged once the order is out, to keep formatting and console
+            // writes off the send path.
+            let actual_send_time = chrono::Utc::now().with_timezone(&Tehran);
+            let actual_epoch_us = current_epoch_micros()?;
+            let drift_micros = actual_epoch_us - scheduled_epoch_ms as i128 * 1_000;
+            let (dispatch, outcome) = send_state
+                .new_attempt(broker.name())
+                .scope(async {
+                    let dispatch_start = std::time::Instant::now();
+                    let dispatch = dispatch_order(broker, index, options, send_state).await;
+                    let elapsed = dispatch_start.elapsed();
+                    println!(
+                        "[{}] Released scheduled order #{}{} at {} (drift {}µs, epoch_us={})",
+                        name,
+                        order_index + 1,
+                        orders[index].tag_suffix(),
+                        actual_send_time.format("%H:%M:%S%.3f"),
+                        drift_micros,
+                        actual_epoch_us
+                    );
+                    let outcome = OrderOutcome::new(
+                        index,
+                        &orders[index].tags,
+                        outcome_status(&dispatch),
+                        (actual_epoch_us / 1_000) as i64,
+                        elapsed,
+                    );
+                    (dispatch, outcome)
+                })
+                .await;
+            round_result.orders.push(outcome);
+            if dispatch.is_err() {
+                round_result.elapsed_ms = round_start.elapsed().as_millis() as u64;
+                send_state.sinks.write(&round_result).await;
+            }
+            let dispatch = dispatch
+                .with_context(|| format!("Failed to send scheduled order #{}", order_index + 1))?;
+            if matches!(dispatch, Dispatch::Sent) {
+                summary!(
+                    "[{}] ✓ Scheduled order #{}: Sent successfully",
+                    name,
+                    order_index + 1
+                );
+            }
+            order_index += 1;
+        }
+        round_result.elapsed_ms = round_start.elapsed().as_millis() as u64;
+        send_state.sinks.write(&round_result).await;
+        send_state.print_tag_report(name);
+        if let Some(books) = &send_state.books {
+            books.finish().await;
+        }
+
+        if options.test_mode {
+            println!("[{}] Test mode: exiting after scheduled send", name);
+            return Ok(());
+        }
+    }
+}
+
+async fn run_continuous<B: Broker>(
+    broker: Arc<B>,
+    send_state: Arc<SendState>,
+    options: RunOptions,
+) -> Result<()> {
+    let settings = broker.settings();
+    let name = broker.name();
+
+    println!(
+        "[{}] Loaded {} order(s) from config",
+        name,
+        broker.orders().len()
+    );
+    println!(
+        "[{}] Batch delay: {}ms between batches",
+        name, settings.batch_delay_ms
+    );
+    if let Some(spacing) = settings.order_spacing_ms {
+        println!("[{}] Order spacing: {}ms within a batch", name, spacing);
+    }
+    if let Some(max_concurrent) = settings.max_concurrent_requests {
+        println!("[{}] Max concurrent requests: {}", name, max_concurrent);
+    }
+    if let Some(limit) = &settings.in_flight_limit {
+        println!(
+            "[{}] Max order tasks in flight: {} ({:?} when full)",
+            name, limit.max_tasks, limit.when_full
+        );
+    }
+    if settings.wait_for_batch_completion {
+        println!(
+            "[{}] Each batch waits for the responses of the one before it",
+            name
+        );
+    }
+    println!("[{}] Starting continuous order sending...\n", name);
+
+    let mut batch_number = 0u64;
+    let batch_delay = settings.batch_delay_ms;
+    let mut backoff_ms = 0;
+    // Order tasks of earlier batches still waiting for their response.
+    let mut tasks = JoinSet::new();
+    // When the first batch that sends anything went out; books are recorded
+    // around it and later tranches count from it.
+    let mut first_send: Option<tokio::time::Instant> = None;
+
+    loop {
+        while tasks.try_join_next().is_some() {}
+        batch_number += 1;
+        if batch_number > 1 {
+            send_state.print_tag_report(broker.name());
+        }
+        if send_state.failures.market_closed() {
+            if settings.market_closed.action == OnMarketClosed::Stop {
+                summary!("[{}] The broker reports the market closed; stopping.", name);
+                break;
+            }
+            wait_for_next_session(name, settings, &send_state).await?;
+            send_state.failures.clear_market_closed();
+        }
+        if send_state.failures.take_rate_limited() {
+            backoff_ms = (backoff_ms * 2).clamp(batch_delay.max(100), MAX_RATE_LIMIT_BACKOFF_MS);
+            println!(
+                "[{}] Rate limited; backing off {}ms before the next batch",
+                name, backoff_ms
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(tokio::time::Duration::from_millis(backoff_ms)) => {}
+                _ = send_state.send_now_requested() => {}
+            }
+        } else {
+            backoff_ms = 0;
+        }
+        for expired in send_state.failures.take_auth_expired() {
+            let sender = match expired {
+                Some(account_index) => &broker.accounts()[account_index],
+                None => broker.as_ref(),
+            };
+            summary!(
+                "[{}] Session expired; pausing to log in again",
+                sender.name()
+            );
+            systemd::status(&format!(
+                "{}: session expired, logging in again",
+                sender.name()
+            ));
+            let logged_in = sender
+                .relogin()
+                .await
+                .with_context(|| format!("Failed to log in again to {}", sender.name()))?;
+            if !logged_in {
+                anyhow::bail!(
+                    "The session of {} expired and it cannot log in by itself; refresh its credentials and restart",
+                    sender.name()
+                );
+            }
+            summary!("[{}] Logged in again; resuming", sender.name());
+            systemd::status("Armed");
+        }
+        if (0..broker.orders().len()).all(|index| send_state.is_done(index)) {
+            summary!("[{}] Every order was accepted; stopping.", broker.name());
+            break;
+        }
+        send_state.wait_while_paused(broker.name()).await;
+        if let Some(control) = &send_state.control {
+            control.record_batch();
+        }
+        if let Some(limit_prices) = &send_state.limit_prices {
+            limit_prices.refresh_if_stale(broker.name()).await;
+        }
+        if let Some(credit) = &send_state.credit {
+            credit.refresh_between_batches(broker.name()).await;
+        }
+        let ready: Vec<usize> = orders::priority_order(broker.orders())
+            .into_iter()
+            .filter(|&index| {
+                !send_state.is_held(index)
+                    && !send_state.is_done(index)
+                    && !send_state.is_disabled(index)
+                    && tranche_due(&broker.orders()[index], first_send)
+            })
+            .collect();
+        let held = broker.orders().len() - ready.len();
+        let sequence = orders::batch_sequence(broker.orders(), &ready, settings.batch_repeat);
+        if let Some(limit) = &settings.in_flight_limit
+            && !tasks.is_empty()
+            && tasks.len() + sequence.len() > limit.max_tasks
+        {
+            match limit.when_full {
+                WhenFull::Shed => {
+                    println!(
+                        "[{}] === Batch #{}: Shed, {} order tasks still in flight ===",
+                        name,
+                        batch_number,
+                        tasks.len()
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(tokio::time::Duration::from_millis(batch_delay)) => {}
+                        _ = send_state.send_now_requested() => {}
+                    }
+                    continue;
+                }
+                WhenFull::Delay => {
+                    println!(
+                        "[{}] Batch #{} waits for some of the {} order tasks in flight",
+                        name,
+                        batch_number,
+                        tasks.len()
+                    );
+                    while !tasks.is_empty() && tasks.len() + sequence.len() > limit.max_tasks {
+                        tasks.join_next().await;
+                    }
+                }
+            }
+        }
+        if held > 0 {
+            println!(
+                "[{}] === Batch #{}: Sending {} orders ({} held) ===",
+                name,
+                batch_number,
+                sequence.len(),
+                held
+            );
+        } else {
+            println!(
+                "[{}] === Batch #{}: Sending {} orders ===",
+                name,
+                batch_number,
+                sequence.len()
+            );
+        }
+
+        if first_send.is_none() && !sequence.is_empty() {
+            first_send = Some(tokio::time::Instant::now());
+            if let Some(books) = &send_state.books {
+                books.record_around(chrono::Utc::now().timestamp_millis());
+            }
+        }
+        let mut previous_started = None;
+        let collector = Arc::new(BatchCollector::new(name, batch_number, sequence.len()));
+        let batch_start = tokio::time::Instant::now();
+        let order_spacing =
+            std::time::Duration::from_millis(settings.order_spacing_ms.unwrap_or(0));
+        // The next batch waits for this one's last order to be released.
+        let last_release = order_spacing * sequence.len().saturating_sub(1) as u32;
+        for (position, index) in sequence.into_iter().enumerate() {
+            let broker = broker.clone();
+            let batch = batch_number;
+            let send_state = send_state.clone();
+            let options = options.clone();
+            let collector = collector.clone();
+            let (started, wait_for) = if settings.priority_order {
+                let (started, next_waits) = oneshot::channel();
+                (Some(started), previous_started.replace(next_waits))
+            } else {
+                (None, None)
+            };
+
+            let release_at = batch_start + order_spacing * position as u32;
+
+            tasks.spawn(async move {
+                tokio::time::sleep_until(release_at).await;
+                if let Some(previous) = wait_for {
+                    let _ = previous.await;
+                }
+                if let Some(started) = started {
+                    let _ = started.send(());
+                }
+                let attempt = send_state.new_attempt(broker.name());
+                let outcome = attempt
+                    .scope(async {
+                        let sent_at_epoch_ms = chrono::Utc::now().timestamp_millis();
+                        let dispatch_start = std::time::Instant::now();
+                        let result =
+                            dispatch_order(broker.as_ref(), index, &options, &send_state).await;
+                        let elapsed = dispatch_start.elapsed();
+                        let order = &broker.orders()[index];
+                        let tags = order.tag_suffix();
+                        match &result {
+                            Ok(Dispatch::Skipped) => {}
+                            Ok(Dispatch::Sent) => summary!(
+                                "[{}] ✓ Batch #{}, Order #{}{}: Sent successfully",
+                                broker.name(),
+                                batch,
+                                index + 1,
+                                tags
+                            ),
+                            Err(e) => eprintln!(
+                                "[{}] ✗ Batch #{}, Order #{}{}: Failed - {}",
+                                broker.name(),
+                                batch,
+                                index + 1,
+                                tags,
+                                e
+                            ),
+                        }
+                        OrderOutcome::new(
+                            index,
+                            &order.tags,
+                            outcome_status(&result),
+                            sent_at_epoch_ms,
+                            elapsed,
+                        )
+                    })
+                    .await;
+                if let Some(batch_result) = collector.add(outcome) {
+                    send_state.sinks.write(&batch_result).await;
+                }
+            });
+        }
+
+        if options.test_mode {
+            while tasks.join_next().await.is_some() {}
+            send_state.print_tag_report(broker.name());
+            println!("[{}] Test mode: exiting after one batch", broker.name());
+            break;
+        }
+
+        let mut pause = last_release + tokio::time::Duration::from_millis(batch_delay);
+        if settings.wait_for_batch_completion {
+            while tasks.join_next().await.is_some() {}
+            pause = tokio::time::Duration::from_millis(batch_delay);
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(pause) => {}
+            _ = send_state.send_now_requested() => {}
+        }
+    }
+
+    // Orders still waiting for a response are left to finish on their own.
+    tasks.detach_all();
+    if let Some(books) = &send_state.books {
+        books.finish().await;
+    }
+    Ok(())
+}
+
+/// Whether `order` is due in a continuous run whose first batch went out at
+/// `first_send`: at once, or `send_after_ms` after that batch.
+fn tranche_due<T>(order: &OrderEntry<T>, first_send: Option<tokio::time::Instant>) -> bool {
+    order.send_after_ms == 0
+        || first_send.is_some_and(|first_send| {
+            first_send.elapsed() >= std::time::Duration::from_millis(order.send_after_ms)
+        })
+}
+
+/// Sleep until the next session opens, per `market_closed.session_open` and
+/// the holiday table, or until `/send-now`.
+async fn wait_for_next_session(
+    name: &str,
+    settings: &BrokerSettings,
+    send_state: &SendState,
+) -> Result<()> {
+    let open = chrono::NaiveTime::parse_from_str(&settings.market_closed.session_open, "%H:%M:%S")
+        .context("market_closed.session_open must be in HH:MM:SS format")?;
+    let calendar = HolidayCalendar::load(settings.holidays.calendar.as_deref()).await?;
+    let now = chrono::Utc::now().with_timezone(&Tehran);
+    let next = calendar.next_session(now, open)?;
+    summary!(
+        "[{}] The broker reports the market closed; waiting for the next session at {}",
+        name,
+        next.format("%Y-%m-%d %H:%M:%S")
+    );
+    let wait = (next - now).to_std().unwrap_or_default();
+    tokio::select! {
+        _ = tokio::time::sleep(wait) => {}
+        _ = send_state.send_now_requested() => {}
+    }
+    println!("[{}] Resuming for the new session", name);
+    Ok(())
+}
+
+/// How a dispatch ended, for its [`OrderOutcome`].
+fn outcome_status(result: &Result<Dispatch>) -> Result<OrderStatus, &anyhow::Error> {
+    match result {
+        Ok(Dispatch::Sent) => Ok(OrderStatus::Accepted),
+        Ok(Dispatch::Skipped) => Ok(OrderStatus::Skipped),
+        Err(e) => Err(e),
+    }
+}
+
+fn next_target_datetime(target_time: chrono::NaiveTime) -> Result<chrono::DateTime<chrono_tz::Tz>> {
+    let now = chrono::Utc::now().with_timezone(&Tehran);
+    let today = now.date_naive();
+    let candidate = Tehran
+        .from_local_datetime(&today.and_time(target_time))
+        .single()
+        .context("Failed to resolve target_time in Asia/Tehran timezone")?;
+    if candidate > now {
+        Ok(candidate)
+    } else {
+        Ok(candidate + chrono::Duration::days(1))
+    }
+}
+
+/// Send the warm-up probes of `warmup` still ahead of a send at
+/// `send_epoch_ms`. A probe is abandoned after `interval_ms`, so a slow one
+/// never holds up the send, and a failed one is only logged.
+async fn warm_up_connection<B: Broker>(
+    prober: &B,
+    rate_limiter: &RateLimiter,
+    warmup: &ConnectionWarmup,
+    send_epoch_ms: i64,
+    last_wall_epoch_ms: &mut i64,
+) -> Result<()> {
+    let name = prober.name();
+    let now_epoch_ms = current_epoch_millis()?;
+    let schedule: Vec<i64> = warmup
+        .schedule(send_epoch_ms)
+        .into_iter()
+        .filter(|epoch_ms| *epoch_ms >= now_epoch_ms)
+        .collect();
+    println!(
+        "[{}] Connection warm-up: {} probe(s) in the last {}ms",
+        name,
+        schedule.len(),
+        warmup.window_ms
+    );
+    let timeout = std::time::Duration::from_millis(warmup.interval_ms);
+    for (probe, epoch_ms) in schedule.iter().enumerate() {
+        wait_until_epoch_ms(*epoch_ms, last_wall_epoch_ms).await?;
+        rate_limiter.wait().await;
+        let probe_sent = latency::timed(prober.send_probe(prober.settings().client()));
+        match tokio::time::timeout(timeout, probe_sent).await {
+            Ok((Ok((rtt_ms, _, status)), phases)) => println!(
+                "[{}] Warm-up #{}/{} status={} rtt={}ms{}",
+                name,
+                probe + 1,
+                schedule.len(),
+                status,
+                rtt_ms,
+                phases.describe()
+            ),
+            Ok((Err(e), _)) => eprintln!(
+                "[{}] Warning: warm-up probe #{} failed: {:#}",
+                name,
+                probe + 1,
+                e
+            ),
+            Err(_) => eprintln!(
+                "[{}] Warning: warm-up probe #{} took over {}ms; abandoned",
+                name,
+                probe + 1,
+                warmup.interval_ms
+            ),
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn current_epoch_millis() -> Result<i64> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("System time is before UNIX_EPOCH")?;
+    Ok(now.as_millis() as i64)
+}
+
+fn current_epoch_micros() -> Result<i128> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("System time is before UNIX_EPOCH")?;
+    Ok(now.as_micros() as i128)
+}
+
+pub(crate) async fn wait_until_epoch_ms(
+    target_epoch_ms: i64,
+    last_wall_epoch_ms: &mut i64,
+) -> Result<()> {
+    let now_epoch_ms = current_epoch_millis()?;
+    if now_epoch_ms < *last_wall_epoch_ms {
+        anyhow::bail!("System clock moved backwards; aborting");
+    }
+    *last_wall_epoch_ms = now_epoch_ms;
+
+    let until_target_ms = target_epoch_ms - now_epoch_ms;
+    let spin_threshold_ms = 5i64;
+    if until_target_ms > spin_threshold_ms {
+        tokio::time::sleep(std::time::Duration::from_millis(
+            (until_target_ms - spin_threshold_ms) as u64,
+        ))
+        .await;
+    }
+
+    loop {
+        let current_epoch_ms = current_epoch_millis()?;
+        if current_epoch_ms < *last_wall_epoch_ms {
+            anyhow::bail!("System clock moved backwards; aborting");
+        }
+        if current_epoch_ms >= target_epoch_ms {
+            *last_wall_epoch_ms = current_epoch_ms;
+            break;
+        }
+        *last_wall_epoch_ms = current_epoch_ms;
+        std::hint::spin_loop();
+    }
+
+    Ok(())
+}
@@ -0,0 +1,180 @@
+//! `response_schema_file`: notice when a broker changes its API. The shape
+//! of the first JSON response of each status class (2xx, 4xx, 5xx), every
+//! field's path and type, is stored in the file; each response after is
+//! compared against it, and a warning goes out once per change when fields
+//! disappear, change type, or new error-looking fields show up. Delete the
+//! file to learn the shapes again.
+
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{LazyLock, Mutex};
+
+/// Type of the value at each path, such as `/data/orderId` or
+/// `/errors[]/code`; `[]` stands for the elements of an array.
+pub type Shape = BTreeMap<String, String>;
+
+/// Field names that suggest a new field carries an error.
+const ERROR_WORDS: [&str; 6] = ["error", "message", "exception", "fault", "reason", "code"];
+
+/// Shapes by status class, as stored in the file.
+type SchemaFile = BTreeMap<String, Shape>;
+
+struct Watch {
+    schemas: SchemaFile,
+    /// Warnings already given, so each change is reported once.
+    warned: HashSet<String>,
+}
+
+static WATCHES: LazyLock<Mutex<HashMap<String, Watch>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The shape of `value`.
+pub fn shape(value: &Value) -> Shape {
+    let mut shape = Shape::new();
+    add_shape(&mut shape, String::new(), value);
+    shape
+}
+
+fn add_shape(shape: &mut Shape, path: String, value: &Value) {
+    let kind = match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(items) => {
+            for item in items {
+                add_shape(shape, format!("{}[]", path), item);
+            }
+            "array"
+        }
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                add_shape(shape, format!("{}/{}", path, key), field);
+            }
+            "object"
+        }
+    };
+    // Elements of one array may differ; the first non-null type stands.
+    match shape.get(&path).map(String::as_str) {
+        None | Some("null") => {
+            shape.insert(path, kind.to_string());
+        }
+        Some(_) => {}
+    }
+}
+
+/// How `current` departs from `expected`: fields gone from an object that
+/// is still there, types that changed and new fields named like errors.
+pub fn drift(expected: &Shape, current: &Shape) -> Vec<String> {
+    let mut changes = Vec::new();
+    let gone: Vec<&str> = expected
+        .keys()
+        .filter(|path| !current.contains_key(*path))
+        // Elements of an array that is empty now.
+        .filter(|path| !path.ends_with("[]"))
+        .filter(|path| {
+            let parent = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+            parent.is_empty() || current.get(parent).is_some_and(|kind| kind == "object")
+        })
+        .map(String::as_str)
+        .collect();
+    if !gone.is_empty() {
+        changes.push(format!("fields gone: {}", gone.join(", ")));
+    }
+    let retyped: Vec<String> = expected
+        .iter()
+        .filter_map(|(path, was)| {
+            let now = current.get(path)?;
+            (now != was && was != "null" && now != "null")
+                .then(|| format!("{} {} -> {}", display_path(path), was, now))
+        })
+        .collect();
+    if !retyped.is_empty() {
+        changes.push(format!("types changed: {}", retyped.join(", ")));
+    }
+    let errors: Vec<&str> = current
+        .keys()
+        .filter(|path| !expected.contains_key(*path))
+        .filter(|path| {
+            let name = path.rsplit('/').next().unwrap_or_default().to_lowercase();
+            ERROR_WORDS.iter().any(|word| name.contains(word))
+        })
+        .map(String::as_str)
+        .collect();
+    if !errors.is_empty() {
+        changes.push(format!("new error-like fields: {}", errors.join(", ")));
+    }
+    changes
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() { "(root)" } else { path }
+}
+
+fn status_class(status: StatusCode) -> String {
+    format!("{}xx", status.as_u16() / 100)
+}
+
+/// Compare `body`, a response of `name` with `status`, against the shapes in
+/// `file`, learning the shape of a status class seen for the first time.
+/// Returns the warnings given for this response.
+pub fn check(name: &str, file: &str, status: StatusCode, body: &str) -> Vec<String> {
+    let class = status_class(status);
+    let parsed = serde_json::from_str::<Value>(body).ok();
+    let mut watches = WATCHES.lock().unwrap_or_else(|e| e.into_inner());
+    let watch = watches.entry(file.to_string()).or_insert_with(|| Watch {
+        schemas: load(file).unwrap_or_else(|e| {
+            eprintln!(
+                "[{}] Warning: {:#}; learning response shapes again",
+                name, e
+            );
+            SchemaFile::new()
+        }),
+        warned: HashSet::new(),
+    });
+
+    let Some(expected) = watch.schemas.get(&class) else {
+        if let Some(value) = &parsed {
+            watch.schemas.insert(class.clone(), shape(value));
+            match save(file, &watch.schemas) {
+                Ok(()) => println!(
+                    "[{}] Stored the shape of {} responses in {}",
+                    name, class, file
+                ),
+                Err(e) => eprintln!("[{}] Warning: {:#}", name, e),
+            }
+        }
+        return Vec::new();
+    };
+    let changes = match &parsed {
+        Some(value) => drift(expected, &shape(value)),
+        None => vec!["the body is no longer JSON".to_string()],
+    };
+    let mut warnings = Vec::new();
+    for change in changes {
+        let warning = format!("{} responses changed shape: {}", class, change);
+        if watch.warned.insert(warning.clone()) {
+            eprintln!(
+                "[{}] Warning: SCHEMA DRIFT - {}. The broker may have changed its API; check orders by hand (delete {} once they work).",
+                name, warning, file
+            );
+            warnings.push(warning);
+        }
+    }
+    warnings
+}
+
+fn load(file: &str) -> Result<SchemaFile> {
+    match std::fs::read_to_string(file) {
+        Ok(text) => serde_json::from_str(&text).with_context(|| format!("Invalid {}", file)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SchemaFile::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", file)),
+    }
+}
+
+fn save(file: &str, schemas: &SchemaFile) -> Result<()> {
+    let json = serde_json::to_string_pretty(schemas)?;
+    std::fs::write(file, json).with_context(|| format!("Failed to write {}", file))
+}
@@ -0,0 +1,62 @@
+use anyhow::{Context, Result};
+
+/// Keyring service name every Sarkhati secret is stored under.
+const SERVICE: &str = "sarkhati";
+const PREFIX: &str = "keyring:";
+
+/// The secret name in a `"keyring:<name>"` reference, if `value` is one.
+pub fn reference_name(value: &str) -> Option<&str> {
+    value
+        .strip_prefix(PREFIX)
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+}
+
+fn entry(name: &str) -> Result<keyring::Entry> {
+    keyring::Entry::new(SERVICE, name)
+        .with_context(|| format!("Failed to open keyring entry '{}'", name))
+}
+
+/// Replace a `"keyring:<name>"` reference with the secret stored in the OS
+/// keyring; any other value is left as it is.
+pub fn resolve(value: &mut String) -> Result<()> {
+    let Some(name) = reference_name(value) else {
+        return Ok(());
+    };
+    let secret = entry(name)?.get_password().map_err(|e| match e {
+        keyring::Error::NoEntry => anyhow::anyhow!(
+            "Secret '{}' is not in the OS keyring; store it with `secrets set {}`",
+            name,
+            name
+        ),
+        other => anyhow::Error::new(other).context(format!(
+            "Failed to read secret '{}' from the OS keyring",
+            name
+        )),
+    })?;
+    *value = secret;
+    Ok(())
+}
+
+pub fn store(name: &str, secret: &str) -> Result<()> {
+    entry(name)?
+        .set_password(secret)
+        .with_context(|| format!("Failed to store secret '{}' in the OS keyring", name))
+}
+
+pub fn delete(name: &str) -> Result<()> {
+    entry(name)?
+        .delete_credential()
+        .with_context(|| format!("Failed to delete secret '{}' from the OS keyring", name))
+}
+
+/// Run `secrets set <name>`: read the value from the console and store it.
+pub async fn run_set(name: &str) -> Result<()> {
+    let secret = crate::login::prompt_line(&format!("[Secrets] Value for '{}': ", name)).await?;
+    store(name, &secret)?;
+    println!(
+        "[Secrets] Stored '{}'; use \"keyring:{}\" in a config file to refer to it",
+        name, name
+    );
+    Ok(())
+}
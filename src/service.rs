@@ -0,0 +1,173 @@
+//! `service install|uninstall`: run Sarkhati as a Windows service that
+//! starts on boot, so the sender is up before the open with nobody logged
+//! in. The service manager starts `sarkhati service run <args>`.
+
+use anyhow::Result;
+
+/// Name of the service in the service manager.
+pub const SERVICE_NAME: &str = "Sarkhati";
+
+/// Register a service that starts on boot and runs Sarkhati with `args`,
+/// which should include `--dir` since services start in the system folder.
+#[cfg(windows)]
+pub fn install(args: &[String]) -> Result<()> {
+    use anyhow::Context;
+    use std::ffi::OsString;
+    use windows_service::service::{
+        ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceType,
+    };
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .context("Failed to open the service manager; run as administrator")?;
+    let launch_arguments = ["service", "run"]
+        .into_iter()
+        .map(OsString::from)
+        .chain(args.iter().map(OsString::from))
+        .collect();
+    let info = ServiceInfo {
+        name: SERVICE_NAME.into(),
+        display_name: "Sarkhati order sender".into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: Vec::new(),
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG)
+        .context("Failed to install the service")?;
+    service.set_description("Sends the configured broker orders at the market open")?;
+    println!(
+        "[Service] Installed '{}' with arguments: {}",
+        SERVICE_NAME,
+        args.join(" ")
+    );
+    println!(
+        "[Service] It starts on boot; start it now with: sc start {}",
+        SERVICE_NAME
+    );
+    Ok(())
+}
+
+/// Stop the service if it is running and remove it.
+#[cfg(windows)]
+pub fn uninstall() -> Result<()> {
+    use anyhow::Context;
+    use windows_service::service::{ServiceAccess, ServiceState};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .context("Failed to open the service manager; run as administrator")?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .with_context(|| format!("Service '{}' is not installed", SERVICE_NAME))?;
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop().context("Failed to stop the service")?;
+    }
+    service.delete().context("Failed to remove the service")?;
+    println!("[Service] Removed '{}'", SERVICE_NAME);
+    Ok(())
+}
+
+/// The session to run once the service manager starts the service.
+#[cfg(windows)]
+static SESSION: std::sync::Mutex<Option<Box<dyn FnOnce() -> Result<()> + Send>>> =
+    std::sync::Mutex::new(None);
+
+#[cfg(windows)]
+windows_service::define_windows_service!(ffi_service_main, service_main);
+
+/// Hand the process to the service manager, which runs `session` as the
+/// service. Stopping the service ends the process, as SIGTERM ends a daemon.
+#[cfg(windows)]
+pub fn run(session: impl FnOnce() -> Result<()> + Send + 'static) -> Result<()> {
+    use anyhow::Context;
+
+    *SESSION.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(session));
+    windows_service::service_dispatcher::start(SERVICE_NAME, ffi_service_main).context(
+        "Failed to start as a service; use 'service install' instead of running this directly",
+    )
+}
+
+#[cfg(windows)]
+fn service_main(_arguments: Vec<std::ffi::OsString>) {
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+
+    fn status(state: ServiceState, exit_code: ServiceExitCode) -> ServiceStatus {
+        ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: if state == ServiceState::Running {
+                ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+            } else {
+                ServiceControlAccept::empty()
+            },
+            exit_code,
+            checkpoint: 0,
+            wait_hint: std::time::Duration::default(),
+            process_id: None,
+        }
+    }
+
+    let handle = std::sync::Arc::new(std::sync::OnceLock::new());
+    let stop_handle = handle.clone();
+    let registered =
+        service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                println!("[Service] Stopping");
+                if let Some(handle) = stop_handle.get() {
+                    let _ = handle.set_service_status(status(
+                        ServiceState::Stopped,
+                        ServiceExitCode::Win32(0),
+                    ));
+                }
+                std::process::exit(0);
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        });
+    let Ok(registered) = registered else {
+        return;
+    };
+    let _ = handle.set(registered);
+    let _ = registered.set_service_status(status(ServiceState::Running, ServiceExitCode::Win32(0)));
+
+    let session = SESSION.lock().unwrap_or_else(|e| e.into_inner()).take();
+    let exit_code = match session.map(|session| session()) {
+        Some(Err(e)) => {
+            eprintln!("[Service] Error: {:#}", e);
+            ServiceExitCode::ServiceSpecific(1)
+        }
+        _ => ServiceExitCode::Win32(0),
+    };
+    let _ = registered.set_service_status(status(ServiceState::Stopped, exit_code));
+}
+
+#[cfg(not(windows))]
+pub fn install(_args: &[String]) -> Result<()> {
+    anyhow::bail!("Services are only available on Windows; use --daemon or systemd instead")
+}
+
+#[cfg(not(windows))]
+pub fn uninstall() -> Result<()> {
+    anyhow::bail!("Services are only available on Windows; use --daemon or systemd instead")
+}
+
+#[cfg(not(windows))]
+pub fn run(_session: impl FnOnce() -> Result<()> + Send + 'static) -> Result<()> {
+    anyhow::bail!("Services are only available on Windows; use --daemon or systemd instead")
+}
@@ -0,0 +1,269 @@
+//! The summary printed when a run ends, whether the orders were sent, the
+//! run failed or it was interrupted: attempts per broker, when each order was
+//! first accepted, a histogram of the errors, and the request rate achieved
+//! against the configured one. `--summary-file` also stores it as JSON.
+//! `--heartbeat-secs` prints a line per broker from the same totals while
+//! the run goes on.
+
+use crate::batch_results::{BatchResult, OrderStatus};
+use crate::book_snapshots::BookSnapshot;
+use crate::runner::BrokerSettings;
+use anyhow::{Context, Result};
+use chrono_tz::Asia::Tehran;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FirstAccepted {
+    pub epoch_ms: i64,
+    /// 1-based; the sends of this order up to and including the accepted one.
+    pub attempt: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BrokerSummary {
+    pub broker: String,
+    pub batches: u64,
+    /// Orders sent, accepted or not; skipped ones are not attempts.
+    pub attempts: u64,
+    pub accepted: u64,
+    pub failed: u64,
+    pub skipped: u64,
+    /// By 1-based order number, for the orders accepted at least once.
+    pub first_accepted: BTreeMap<usize, FirstAccepted>,
+    /// Failures by error kind, `other` for those with none.
+    pub errors: BTreeMap<String, u64>,
+    /// Outcome of the last order answered, with its error kind if it failed.
+    pub last_status: Option<String>,
+    pub elapsed_ms: u64,
+    pub effective_rps: f64,
+    /// `None` when `batch_delay_ms` is 0 and nothing holds the rate back.
+    pub configured_rps: Option<f64>,
+    /// Order books taken around the send with `book_snapshots`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub book_snapshots: Vec<BookSnapshot>,
+    #[serde(skip)]
+    started: Instant,
+    #[serde(skip)]
+    order_attempts: BTreeMap<usize, u64>,
+}
+
+impl BrokerSummary {
+    fn new(broker: &str, configured_rps: Option<f64>) -> Self {
+        Self {
+            broker: broker.to_string(),
+            batches: 0,
+            attempts: 0,
+            accepted: 0,
+            failed: 0,
+            skipped: 0,
+            first_accepted: BTreeMap::new(),
+            errors: BTreeMap::new(),
+            last_status: None,
+            elapsed_ms: 0,
+            effective_rps: 0.0,
+            configured_rps,
+            book_snapshots: Vec::new(),
+            started: Instant::now(),
+            order_attempts: BTreeMap::new(),
+        }
+    }
+
+    fn add(&mut self, result: &BatchResult) {
+        self.batches += 1;
+        for outcome in &result.orders {
+            match outcome.status {
+                OrderStatus::Skipped => {
+                    self.skipped += 1;
+                    continue;
+                }
+                OrderStatus::Accepted => {
+                    self.accepted += 1;
+                    self.last_status = Some("accepted".to_string());
+                }
+                OrderStatus::Failed => {
+                    self.failed += 1;
+                    let kind = outcome
+                        .error_kind
+                        .map_or("other".to_string(), |kind| kind.to_string());
+                    self.last_status = Some(format!("failed ({})", kind));
+                    *self.errors.entry(kind).or_default() += 1;
+                }
+            }
+            self.attempts += 1;
+            let attempt = self.order_attempts.entry(outcome.order).or_default();
+            *attempt += 1;
+            if outcome.status == OrderStatus::Accepted {
+                self.first_accepted
+                    .entry(outcome.order)
+                    .or_insert(FirstAccepted {
+                        epoch_ms: outcome.sent_at_epoch_ms,
+                        attempt: *attempt,
+                    });
+            }
+        }
+    }
+
+    /// A copy with the elapsed time and achieved rate as of now.
+    fn snapshot(&self) -> Self {
+        let elapsed = self.started.elapsed();
+        let mut summary = self.clone();
+        summary.elapsed_ms = elapsed.as_millis() as u64;
+        if !elapsed.is_zero() {
+            summary.effective_rps = self.attempts as f64 / elapsed.as_secs_f64();
+        }
+        summary
+    }
+}
+
+/// Everything sent in this process, fed by each broker's batch results.
+#[derive(Default)]
+pub struct SessionSummary {
+    brokers: Mutex<BTreeMap<String, BrokerSummary>>,
+}
+
+impl SessionSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn brokers(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, BrokerSummary>> {
+        self.brokers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start counting `broker`'s sends, sent by `senders` sessions that each
+    /// wait out their own rate limit.
+    pub fn register(&self, broker: &str, senders: usize, settings: &BrokerSettings) {
+        let gap_us = settings
+            .precise_rate_limit
+            .as_ref()
+            .and_then(|precise| precise.interval_us)
+            .unwrap_or(settings.batch_delay_ms * 1000);
+        let configured_rps = (gap_us > 0).then(|| senders as f64 * 1e6 / gap_us as f64);
+        self.brokers().insert(
+            broker.to_string(),
+            BrokerSummary::new(broker, configured_rps),
+        );
+    }
+
+    pub fn add(&self, result: &BatchResult) {
+        if let Some(summary) = self.brokers().get_mut(&result.broker) {
+            summary.add(result);
+        }
+    }
+
+    pub fn add_book_snapshot(&self, broker: &str, snapshot: BookSnapshot) {
+        if let Some(summary) = self.brokers().get_mut(broker) {
+            summary.book_snapshots.push(snapshot);
+        }
+    }
+
+    /// Each registered broker's summary as of now.
+    pub fn brokers_summary(&self) -> Vec<BrokerSummary> {
+        self.brokers()
+            .values()
+            .map(BrokerSummary::snapshot)
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.brokers().is_empty()
+    }
+
+    pub fn print(&self) {
+        let brokers = self.brokers_summary();
+        if brokers.is_empty() {
+            return;
+        }
+        summary!("\nSession summary:");
+        for broker in &brokers {
+            let name = &broker.broker;
+            summary!(
+                "[{}] {} attempt(s) in {:.1}s: {} accepted, {} failed, {} skipped",
+                name,
+                broker.attempts,
+                broker.elapsed_ms as f64 / 1000.0,
+                broker.accepted,
+                broker.failed,
+                broker.skipped
+            );
+            summary!(
+                "[{}] Rate: {:.2} req/s achieved, {} configured",
+                name,
+                broker.effective_rps,
+                broker
+                    .configured_rps
+                    .map_or("unlimited".to_string(), |rps| format!("{:.2} req/s", rps))
+            );
+            for (order, first) in &broker.first_accepted {
+                summary!(
+                    "[{}] Order #{} first accepted at {} on attempt {}",
+                    name,
+                    order,
+                    format_time(first.epoch_ms),
+                    first.attempt
+                );
+            }
+            if !broker.book_snapshots.is_empty() {
+                summary!(
+                    "[{}] {} order book snapshot(s) taken around the send",
+                    name,
+                    broker.book_snapshots.len()
+                );
+            }
+            if !broker.errors.is_empty() {
+                let histogram: Vec<String> = broker
+                    .errors
+                    .iter()
+                    .map(|(kind, count)| format!("{} {}", kind, count))
+                    .collect();
+                summary!("[{}] Errors: {}", name, histogram.join(", "));
+            }
+        }
+    }
+
+    /// Print a line per broker every `interval`: how long it has run, its
+    /// batches, the request rate since the last line and the last outcome.
+    /// Runs until dropped.
+    pub async fn heartbeat(&self, interval: Duration) {
+        let mut attempts_before: BTreeMap<String, u64> = BTreeMap::new();
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick is immediate.
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            for broker in self.brokers_summary() {
+                let before = attempts_before
+                    .insert(broker.broker.clone(), broker.attempts)
+                    .unwrap_or(0);
+                summary!(
+                    "[{}] Heartbeat: {:.0}s elapsed, {} batch(es), {:.2} req/s, last: {}",
+                    broker.broker,
+                    broker.elapsed_ms as f64 / 1000.0,
+                    broker.batches,
+                    broker.attempts.saturating_sub(before) as f64 / interval.as_secs_f64(),
+                    broker.last_status.as_deref().unwrap_or("no response yet")
+                );
+            }
+        }
+    }
+
+    /// Write the summary of every broker to `path` as JSON.
+    pub fn save(&self, path: &str) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.brokers_summary())?;
+        std::fs::write(path, json).with_context(|| format!("Failed to write {}", path))
+    }
+}
+
+/// Tehran time of day with milliseconds.
+fn format_time(epoch_ms: i64) -> String {
+    chrono::DateTime::from_timestamp_millis(epoch_ms)
+        .map(|time| {
+            time.with_timezone(&Tehran)
+                .format("%H:%M:%S%.3f")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
@@ -0,0 +1,201 @@
+use anyhow::{Context, Result};
+use futures::{SinkExt, StreamExt};
+use reqwest::header::HeaderMap;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+/// Ends every message of SignalR's JSON protocol.
+const RECORD_SEPARATOR: char = '\u{1e}';
+
+/// How often the client tells the hub it is still there; the server drops
+/// clients silent for 30 seconds by default.
+const PING_INTERVAL: Duration = Duration::from_secs(15);
+
+const INVOCATION: u64 = 1;
+const PING: u64 = 6;
+const CLOSE: u64 = 7;
+
+/// A hub method the server called on the client.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Invocation {
+    pub target: String,
+    pub arguments: Vec<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Negotiation {
+    #[serde(default)]
+    connection_token: Option<String>,
+    #[serde(default)]
+    connection_id: Option<String>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// An open WebSocket connection to an ASP.NET Core SignalR hub, speaking
+/// the JSON hub protocol.
+pub struct SignalRConnection {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    /// Messages of the last frame not handed out yet.
+    pending: Vec<String>,
+    ping: tokio::time::Interval,
+}
+
+impl SignalRConnection {
+    /// Negotiate with the hub at `hub_url` and open its WebSocket. `headers`
+    /// go with the negotiate request and the WebSocket handshake, so the
+    /// broker's cookie or token authenticate both.
+    pub async fn connect(hub_url: &str, headers: &HeaderMap) -> Result<Self> {
+        let hub_url = hub_url.trim_end_matches('/');
+        let negotiate_url = match hub_url.contains('?') {
+            true => hub_url.replacen('?', "/negotiate?negotiateVersion=1&", 1),
+            false => format!("{}/negotiate?negotiateVersion=1", hub_url),
+        };
+        let response = reqwest::Client::new()
+            .post(&negotiate_url)
+            .headers(headers.clone())
+            .send()
+            .await
+            .with_context(|| format!("SignalR negotiate request to {} failed", negotiate_url))?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            anyhow::bail!(
+                "SignalR negotiate failed with status {}: {}",
+                status,
+                crate::decode_unicode_escapes(&body)
+            );
+        }
+        let negotiation: Negotiation = serde_json::from_str(&body)
+            .with_context(|| format!("Invalid SignalR negotiate response: {}", body))?;
+        if let Some(error) = negotiation.error {
+            anyhow::bail!("SignalR negotiate refused: {}", error);
+        }
+        let id = negotiation
+            .connection_token
+            .or(negotiation.connection_id)
+            .context("SignalR negotiate response has no connection token")?;
+
+        let mut request = socket_url(hub_url, &id)?.into_client_request()?;
+        for (name, value) in headers {
+            request.headers_mut().insert(name, value.clone());
+        }
+        let (mut socket, _) = tokio_tungstenite::connect_async(request)
+            .await
+            .context("SignalR WebSocket connection failed")?;
+
+        socket
+            .send(Message::text(frame(
+                &json!({ "protocol": "json", "version": 1 }),
+            )))
+            .await?;
+        let mut connection = Self {
+            socket,
+            pending: Vec::new(),
+            ping: tokio::time::interval_at(
+                tokio::time::Instant::now() + PING_INTERVAL,
+                PING_INTERVAL,
+            ),
+        };
+        let handshake = connection
+            .next_message()
+            .await?
+            .context("SignalR hub closed the connection during the handshake")?;
+        if let Some(error) = handshake.get("error").and_then(Value::as_str) {
+            anyhow::bail!("SignalR handshake refused: {}", error);
+        }
+        Ok(connection)
+    }
+
+    /// Call the hub method `target` without waiting for a result, as
+    /// `connection.send` does in the JavaScript client; hubs use such calls
+    /// to join a client to the groups it is notified in.
+    pub async fn send(&mut self, target: &str, arguments: &[Value]) -> Result<()> {
+        let message = json!({ "type": INVOCATION, "target": target, "arguments": arguments });
+        self.socket.send(Message::text(frame(&message))).await?;
+        Ok(())
+    }
+
+    /// The next hub method the server calls; `None` once the hub closes the
+    /// connection. Pings are answered along the way.
+    pub async fn next_invocation(&mut self) -> Result<Option<Invocation>> {
+        while let Some(message) = self.next_message().await? {
+            match message.get("type").and_then(Value::as_u64) {
+                Some(INVOCATION) => {
+                    let Some(target) = message.get("target").and_then(Value::as_str) else {
+                        continue;
+                    };
+                    let arguments = match message.get("arguments") {
+                        Some(Value::Array(arguments)) => arguments.clone(),
+                        _ => Vec::new(),
+                    };
+                    return Ok(Some(Invocation {
+                        target: target.to_string(),
+                        arguments,
+                    }));
+                }
+                Some(CLOSE) => {
+                    if let Some(error) = message.get("error").and_then(Value::as_str) {
+                        anyhow::bail!("SignalR hub closed the connection: {}", error);
+                    }
+                    return Ok(None);
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    /// The next protocol message, pinging the hub while waiting.
+    async fn next_message(&mut self) -> Result<Option<Value>> {
+        loop {
+            if !self.pending.is_empty() {
+                let text = self.pending.remove(0);
+                let message = serde_json::from_str(&text)
+                    .with_context(|| format!("Invalid SignalR message: {}", text))?;
+                return Ok(Some(message));
+            }
+            let received = tokio::select! {
+                received = self.socket.next() => received,
+                _ = self.ping.tick() => {
+                    self.socket.send(Message::text(frame(&json!({ "type": PING })))).await?;
+                    continue;
+                }
+            };
+            let text = match received {
+                None | Some(Ok(Message::Close(_))) => return Ok(None),
+                Some(Ok(Message::Text(text))) => text.to_string(),
+                Some(Ok(Message::Binary(bytes))) => String::from_utf8_lossy(&bytes).into_owned(),
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return Err(e.into()),
+            };
+            self.pending = text
+                .split(RECORD_SEPARATOR)
+                .filter(|message| !message.trim().is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+    }
+}
+
+fn frame(message: &Value) -> String {
+    format!("{}{}", message, RECORD_SEPARATOR)
+}
+
+/// The hub URL with a WebSocket scheme and the connection's `id`.
+fn socket_url(hub_url: &str, id: &str) -> Result<String> {
+    let url = match hub_url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}", rest),
+        Some(("http", rest)) => format!("ws://{}", rest),
+        _ => anyhow::bail!("SignalR hub URL must be http or https: {}", hub_url),
+    };
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let id: String = form_urlencoded::byte_serialize(id.as_bytes()).collect();
+    Ok(format!("{}{}id={}", url, separator, id))
+}
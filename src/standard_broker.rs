@@ -1,5 +1,15 @@
-use crate::calibration::{self, CalibrationConfig};
-use crate::rate_limiter::RateLimiter;
+use crate::accounts;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::StandardOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
 use anyhow::{Context, Result};
 use reqwest::StatusCode;
 use reqwest::header::{
@@ -7,20 +17,14 @@ use reqwest::header::{
     REFERER, USER_AGENT,
 };
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::future::Future;
 use std::time::Instant;
 
 pub fn default_user_agent() -> String {
     "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36".to_string()
 }
 
-pub fn default_batch_delay() -> u64 {
-    100
-}
-
-pub fn default_batch_repeat() -> usize {
-    1
-}
-
 #[derive(Debug, Deserialize, Clone)]
 pub struct StandardBrokersConfig {
     pub brokers: Vec<StandardBrokerConfig>,
@@ -29,21 +33,23 @@ pub struct StandardBrokersConfig {
 #[derive(Debug, Deserialize, Clone)]
 pub struct StandardBrokerConfig {
     pub name: String,
+    /// Unused when `accounts` are listed; each account sets its own.
+    #[serde(default)]
     pub cookie: String,
     #[serde(default = "default_user_agent")]
     pub user_agent: String,
     pub order_url: String,
     pub origin: String,
     pub referer: String,
-    pub orders: Vec<StandardOrderData>,
-    #[serde(default = "default_batch_delay")]
-    pub batch_delay_ms: u64,
-    #[serde(default = "default_batch_repeat")]
-    pub batch_repeat: usize,
+    pub orders: Vec<OrderEntry<StandardOrderData>>,
     #[serde(default)]
-    pub target_time: Option<String>,
+    pub body_encoding: BodyEncoding,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
+    /// One copy of this broker per entry of `accounts`, each with the
+    /// account's own credentials.
     #[serde(default)]
-    pub calibration: Option<CalibrationConfig>,
+    pub accounts: Vec<StandardBrokerConfig>,
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
@@ -68,6 +74,7 @@ pub struct StandardOrderData {
     pub max_show: i64,
     #[serde(rename = "orderId")]
     pub order_id: i64,
+    #[serde(default)]
     pub isin: String,
     #[serde(rename = "orderSide")]
     pub order_side: i32,
@@ -79,13 +86,86 @@ pub struct StandardOrderData {
     pub short_sell_is_enabled: bool,
     #[serde(rename = "shortSellIncentivePercent")]
     pub short_sell_incentive_percent: i32,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
+}
+
+/// How the order is written into the request body. Older OMSes only take
+/// form posts.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyEncoding {
+    #[default]
+    Json,
+    /// `application/x-www-form-urlencoded`, values percent-encoded as UTF-8.
+    Form,
+    /// `multipart/form-data`, one part per field.
+    Multipart,
+}
+
+/// Separates the parts of a multipart body; never appears in an order.
+const MULTIPART_BOUNDARY: &str = "----SarkhatiFormBoundary7MA4YWxkTrZu0gW";
+
+impl BodyEncoding {
+    pub fn content_type(&self) -> String {
+        match self {
+            Self::Json => "application/json".to_string(),
+            Self::Form => "application/x-www-form-urlencoded; charset=UTF-8".to_string(),
+            Self::Multipart => format!("multipart/form-data; boundary={}", MULTIPART_BOUNDARY),
+        }
+    }
+
+    /// Write the fields of `order` as a request body.
+    pub fn encode(&self, order: &Value) -> Result<String> {
+        Ok(match self {
+            Self::Json => serde_json::to_string(order)?,
+            Self::Form => form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(form_fields(order)?)
+                .finish(),
+            Self::Multipart => {
+                let mut body = String::new();
+                for (name, value) in form_fields(order)? {
+                    body.push_str(&format!(
+                        "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+                        MULTIPART_BOUNDARY, name, value
+                    ));
+                }
+                body.push_str(&format!("--{}--\r\n", MULTIPART_BOUNDARY));
+                body
+            }
+        })
+    }
+}
+
+/// The top-level fields of `order` in order: strings as they are, `null`
+/// as empty and anything else as its JSON text.
+fn form_fields(order: &Value) -> Result<Vec<(&str, String)>> {
+    let fields = order
+        .as_object()
+        .context("Form bodies need the order to be a JSON object")?;
+    Ok(fields
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(value) => value.clone(),
+                Value::Null => String::new(),
+                value => value.to_string(),
+            };
+            (name.as_str(), value)
+        })
+        .collect())
 }
 
 pub fn load_config(path: &str) -> Result<StandardBrokersConfig> {
-    let config_str =
-        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
-    let config: StandardBrokersConfig =
-        serde_json::from_str(&config_str).with_context(|| format!("Failed to parse {}", path))?;
+    let config_str = encryption::read_config(path)?;
+    let mut config: StandardBrokersConfig = accounts::parse_config(path, &config_str)?;
+    for broker in &mut config.brokers {
+        secrets::resolve(&mut broker.cookie)?;
+        for account in &mut broker.accounts {
+            secrets::resolve(&mut account.cookie)?;
+        }
+    }
     Ok(config)
 }
 
@@ -93,12 +173,182 @@ pub fn find_broker<'a>(
     config: &'a StandardBrokersConfig,
     name: &str,
 ) -> Option<&'a StandardBrokerConfig> {
-    config
-        .brokers
-        .iter()
-        .find(|broker| broker.name.eq_ignore_ascii_case(name))
+    accounts::find(&config.brokers, name)
+}
+
+impl OrderFields for StandardOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.isin)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.isin = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn price(&self) -> Option<f64> {
+        Some(self.order_price as f64)
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.order_price = price.round() as i64;
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        u64::try_from(self.order_count).ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.order_count = quantity as i64;
+    }
+
+    fn side(&self) -> OrderSide {
+        if self.order_side == 86 {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }
+    }
+}
+
+impl Broker for StandardBrokerConfig {
+    type Order = StandardOrderData;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn config_file(&self) -> &str {
+        "config_standard.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<StandardOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<StandardOrderData>> {
+        &mut self.orders
+    }
+
+    fn accounts(&self) -> &[Self] {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self] {
+        &mut self.accounts
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        if self.cookie.is_empty() {
+            anyhow::bail!(
+                "Cookie is required for {}. Please set 'cookie' in config_standard.json",
+                self.name
+            );
+        }
+
+        println!("[{}] Using Cookie authentication", self.name);
+        println!(
+            "[{}] Cookie preview: {}...",
+            self.name,
+            &self.cookie[..self.cookie.len().min(50)]
+        );
+        Ok(())
+    }
+
+    fn order_json(&self, order: &OrderEntry<StandardOrderData>) -> Result<String> {
+        match self.body_encoding {
+            BodyEncoding::Json => order.to_json(),
+            encoding => encoding.encode(&order.render()?),
+        }
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(self, client)
+    }
 }
 
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(broker: &StandardBrokerConfig, order_json: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("en-US,en;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(
+        "X-Requested-With",
+        HeaderValue::from_static("XMLHttpRequest"),
+    );
+    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    headers.insert(REFERER, HeaderValue::from_str(&broker.referer)?);
+    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
+    headers.insert("Priority", HeaderValue::from_static("u=0"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&broker.body_encoding.content_type())?,
+    );
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// Build the request for `order_json` and send it.
 pub async fn send_order(
     broker: &StandardBrokerConfig,
     order_json: &str,
@@ -106,9 +356,26 @@ pub async fn send_order(
     curl_only: bool,
     rate_limiter: Option<&RateLimiter>,
 ) -> Result<()> {
-    let client = reqwest::Client::new();
+    let request = PreparedRequest::new(
+        &broker.order_url,
+        broker.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(broker, &request, test_mode, curl_only, rate_limiter).await
+}
+
+/// Send a request built by [`Broker::prepare`].
+pub async fn send_request(
+    broker: &StandardBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = broker.settings.client();
 
-    if test_mode {
+    if test_mode || curl_only {
         println!("[{}] Equivalent curl command:", broker.name);
         println!(
             r#"curl '{}' \
@@ -118,7 +385,7 @@ pub async fn send_order(
   -H 'Accept: */*' \
   -H 'Accept-Language: en-US,en;q=0.5' \
   -H 'Accept-Encoding: gzip, deflate, br, zstd' \
-  -H 'Content-Type: application/json' \
+  -H 'Content-Type: {}' \
   -H 'X-Requested-With: XMLHttpRequest' \
   -H 'Origin: {}' \
   -H 'Connection: keep-alive' \
@@ -133,6 +400,7 @@ pub async fn send_order(
   --data-raw '{}'"#,
             broker.order_url,
             broker.user_agent,
+            broker.body_encoding.content_type(),
             broker.origin,
             broker.referer,
             broker.cookie,
@@ -145,55 +413,37 @@ pub async fn send_order(
         }
     }
 
-    let mut headers = HeaderMap::new();
-    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
-    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
-    headers.insert(
-        "Accept-Language",
-        HeaderValue::from_static("en-US,en;q=0.5"),
-    );
-    headers.insert(
-        ACCEPT_ENCODING,
-        HeaderValue::from_static("gzip, deflate, br, zstd"),
-    );
-    headers.insert(
-        "X-Requested-With",
-        HeaderValue::from_static("XMLHttpRequest"),
-    );
-    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
-    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
-    headers.insert(REFERER, HeaderValue::from_str(&broker.referer)?);
-    headers.insert(COOKIE, HeaderValue::from_str(&broker.cookie)?);
-    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
-    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
-    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
-    headers.insert("Priority", HeaderValue::from_static("u=0"));
-    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
-    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+    let (url, headers) = request.parts();
 
+    let mut timing = console::Timing::start();
     if let Some(limiter) = rate_limiter {
-        limiter.wait().await;
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
     }
+    timing.mark("rate limit");
 
-    let body_bytes = order_json.as_bytes();
+    console::debug_headers(&broker.name, "Request", &headers);
+    let mut capture = Capture::start(&broker.name, &url, &headers, &request.body);
 
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-    headers.insert(
-        CONTENT_LENGTH,
-        HeaderValue::from_str(&body_bytes.len().to_string())?,
-    );
-
-    println!("[{}] Sending order JSON: {}", broker.name, order_json);
-
-    let response = client
-        .post(&broker.order_url)
-        .headers(headers)
-        .body(order_json.to_string())
-        .send()
-        .await?;
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[{}] Sent order body: {}", broker.name, order_json);
+    let response = capture.sent(response)?;
 
     let status = response.status();
+    console::debug_headers(&broker.name, "Response", response.headers());
+    capture.response_head(status, response.headers());
     let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
 
     let decoded_text = if response_text.contains("\\u") {
         crate::decode_unicode_escapes(&response_text)
@@ -203,12 +453,16 @@ pub async fn send_order(
 
     println!("[{}] Order response status: {}", broker.name, status);
     println!("[{}] Order response body: {}", broker.name, decoded_text);
+    timing.report(&broker.name);
 
-    if !status.is_success() {
-        anyhow::bail!("Order failed with status {}: {}", status, decoded_text);
+    if let Some(file) = &broker.settings.response_schema_file {
+        schema_drift::check(&broker.name, file, status, &decoded_text);
     }
-
-    Ok(())
+    success::check_response_as::<StandardOrderResponse>(
+        &broker.settings.success_rules,
+        status,
+        decoded_text,
+    )
 }
 
 pub async fn run_calibration(
@@ -217,6 +471,7 @@ pub async fn run_calibration(
     rate_limiter: &RateLimiter,
 ) -> Result<calibration::CalibrationSummary> {
     let calibration = broker
+        .settings
         .calibration
         .as_ref()
         .context("Calibration config missing")?;
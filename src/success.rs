@@ -0,0 +1,104 @@
+use crate::errors::OrderError;
+use crate::responses::{self, BrokerResponse, OrderResponse};
+use anyhow::Result;
+use regex::Regex;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::future::Future;
+
+tokio::task_local! {
+    static ACCEPTED_BODY: RefCell<Option<String>>;
+}
+
+/// Run `future`, an order send, and return its output along with the body
+/// of the response that accepted the order, if one did.
+pub async fn capture_accepted<F: Future>(future: F) -> (F::Output, Option<String>) {
+    ACCEPTED_BODY
+        .scope(RefCell::new(None), async {
+            let output = future.await;
+            (output, ACCEPTED_BODY.with(|body| body.take()))
+        })
+        .await
+}
+
+/// Keep the body of an accepting response for [`capture_accepted`].
+fn accepted(body: String) -> Result<()> {
+    let _ = ACCEPTED_BODY.try_with(|kept| *kept.borrow_mut() = Some(body));
+    Ok(())
+}
+
+/// A check an order response must pass to count as accepted, for brokers
+/// that answer HTTP 200 with an error payload. Configured per broker as
+/// `success_rules`; every rule must pass.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum SuccessRule {
+    /// The value at `json_pointer` (RFC 6901, e.g. `/isSuccessful`) equals `equals`.
+    JsonPointer { json_pointer: String, equals: Value },
+    /// The raw response body matches `body_regex`.
+    BodyRegex { body_regex: BodyRegex },
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(try_from = "String")]
+pub struct BodyRegex(Regex);
+
+impl TryFrom<String> for BodyRegex {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        Regex::new(&pattern).map(BodyRegex)
+    }
+}
+
+impl SuccessRule {
+    fn passes(&self, body: &str, parsed: Option<&Value>) -> bool {
+        match self {
+            SuccessRule::JsonPointer {
+                json_pointer,
+                equals,
+            } => parsed
+                .and_then(|value| value.pointer(json_pointer))
+                .is_some_and(|value| value == equals),
+            SuccessRule::BodyRegex { body_regex } => body_regex.0.is_match(body),
+        }
+    }
+}
+
+/// Decide whether an order response means success: the status must be 2xx
+/// and every configured rule must pass. Failures become an [`OrderError`].
+pub fn check_response(rules: &[SuccessRule], status: StatusCode, body: String) -> Result<()> {
+    if !status.is_success() {
+        return Err(OrderError::new(status, body).into());
+    }
+    if rules.is_empty() {
+        return accepted(body);
+    }
+
+    let parsed = serde_json::from_str::<Value>(&body).ok();
+    if rules.iter().all(|rule| rule.passes(&body, parsed.as_ref())) {
+        accepted(body)
+    } else {
+        Err(OrderError::new(status, body).into())
+    }
+}
+
+/// [`check_response`] for a broker whose responses parse as `R`: without
+/// `rules`, a body `R` understands decides, and one it does not is left to
+/// the status. Configured rules always win.
+pub fn check_response_as<R: BrokerResponse>(
+    rules: &[SuccessRule],
+    status: StatusCode,
+    body: String,
+) -> Result<()> {
+    if !rules.is_empty() {
+        return check_response(rules, status, body);
+    }
+    match responses::parse::<R>(status, &body) {
+        Some(OrderResponse::Accepted { .. }) => accepted(body),
+        Some(response) => Err(OrderError::from_response(status, body, response).into()),
+        None => check_response(rules, status, body),
+    }
+}
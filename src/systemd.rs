@@ -0,0 +1,94 @@
+//! Notifications for systemd services with `Type=notify` and `WatchdogSec=`:
+//! `READY=1` once every broker is armed, and watchdog pings for as long as
+//! the runtime keeps scheduling its tasks, so a wedged instance is restarted
+//! before the open instead of hanging. Outside systemd nothing is sent.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Brokers registered that are not armed yet.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+static READY_SENT: AtomicBool = AtomicBool::new(false);
+
+/// One broker getting ready. The service is ready once every broker is
+/// armed, or gave up: dropping this counts as done.
+pub struct Readiness {
+    done: bool,
+}
+
+impl Readiness {
+    pub fn register() -> Self {
+        PENDING.fetch_add(1, Ordering::SeqCst);
+        Self { done: false }
+    }
+
+    /// The broker has loaded its config and finished calibrating; later
+    /// calls do nothing.
+    pub fn ready(&mut self) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        if PENDING.fetch_sub(1, Ordering::SeqCst) == 1 && !READY_SENT.swap(true, Ordering::SeqCst) {
+            notify(&["READY=1", "STATUS=Armed"]);
+        }
+    }
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        self.ready();
+    }
+}
+
+/// When systemd set a watchdog interval, ping it at half that interval
+/// from a task on the runtime. A blocked or starved runtime stops the
+/// pings and systemd restarts the service.
+pub fn start_watchdog() {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    println!("[systemd] Watchdog every {}ms", interval.as_millis());
+    tokio::spawn(async move {
+        let mut ticks = tokio::time::interval(interval / 2);
+        loop {
+            ticks.tick().await;
+            notify(&["WATCHDOG=1"]);
+        }
+    });
+}
+
+/// Show `text` as the service's status in `systemctl status`.
+pub fn status(text: &str) {
+    notify(&[&format!("STATUS={}", text)]);
+}
+
+/// Tell systemd the service is shutting down.
+pub fn stopping() {
+    notify(&["STOPPING=1"]);
+}
+
+#[cfg(unix)]
+fn watchdog_interval() -> Option<std::time::Duration> {
+    let mut usec = 0;
+    sd_notify::watchdog_enabled(false, &mut usec).then(|| std::time::Duration::from_micros(usec))
+}
+
+#[cfg(not(unix))]
+fn watchdog_interval() -> Option<std::time::Duration> {
+    None
+}
+
+#[cfg(unix)]
+fn notify(states: &[&str]) {
+    let states: Vec<_> = states
+        .iter()
+        .map(|state| sd_notify::NotifyState::Custom(state))
+        .collect();
+    if let Err(e) = sd_notify::notify(false, &states) {
+        eprintln!("[systemd] Warning: notification failed: {}", e);
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_states: &[&str]) {}
@@ -0,0 +1,564 @@
+//! Brokers running the Tadbir OMS. Every broker of the family takes the
+//! same order payload and the same bearer token and API key headers, so an
+//! entry of `config_tadbir.json` only needs its `host`; the order URL and
+//! origin follow from it. With a `login` section or a `refresh_token`, the
+//! token and API key are negotiated by [`crate::tadbir_login`].
+
+use crate::accounts;
+use crate::calibration;
+use crate::capture::Capture;
+use crate::console;
+use crate::encryption;
+use crate::errors::{OrderErrorKind, order_error_kind};
+use crate::orders::{OrderEntry, OrderFields, OrderSide};
+use crate::rate_limiter::{RateLimiter, request_size};
+use crate::responses::TadbirOrderResponse;
+use crate::runner::{Broker, BrokerSettings, PreparedRequest};
+use crate::schema_drift;
+use crate::secrets;
+use crate::success;
+use crate::tadbir_login::{self, TadbirCredentials, TadbirLoginConfig, TadbirSession};
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+use reqwest::header::{
+    ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, HeaderMap, HeaderValue,
+    ORIGIN, REFERER, USER_AGENT,
+};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Instant;
+
+fn default_user_agent() -> String {
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/143.0.0.0 Safari/537.36".to_string()
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TadbirBrokersConfig {
+    pub brokers: Vec<TadbirBrokerConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TadbirBrokerConfig {
+    pub name: String,
+    /// The broker's API host, such as `https://api.example-broker.ir`.
+    pub host: String,
+    /// Bearer token of the session, with or without its `Bearer ` prefix.
+    /// Unused when `accounts` are listed; each account sets its own. May be
+    /// left empty when `login` is set.
+    #[serde(default)]
+    pub token: String,
+    /// Sent as `X-Api-Key`; left out when empty.
+    #[serde(default)]
+    pub api_key: String,
+    /// Renews `token` and `api_key` without a password, through the token
+    /// endpoint of `login`.
+    #[serde(default)]
+    pub refresh_token: String,
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    pub order_url: String,
+    /// The trading front-end; `host` when left out.
+    #[serde(default)]
+    pub origin: String,
+    pub orders: Vec<OrderEntry<TadbirOrderData>>,
+    #[serde(flatten)]
+    pub settings: BrokerSettings,
+    #[serde(default)]
+    pub login: Option<TadbirLoginConfig>,
+    #[serde(skip)]
+    pub session: TadbirSession,
+    /// One copy of this broker per entry of `accounts`, each with the
+    /// account's own credentials.
+    #[serde(default)]
+    pub accounts: Vec<TadbirBrokerConfig>,
+}
+
+impl TadbirBrokerConfig {
+    /// Fill in `origin` from `host` where it is left out.
+    pub fn fill_from_host(&mut self) {
+        if self.origin.is_empty() {
+            self.origin = self.host.trim_end_matches('/').to_string();
+        }
+        for account in &mut self.accounts {
+            account.fill_from_host();
+        }
+    }
+
+    /// The session from the latest negotiation, or the configured one.
+    pub fn credentials(&self) -> TadbirCredentials {
+        self.session.get().unwrap_or_else(|| TadbirCredentials {
+            token: self.token.clone(),
+            api_key: self.api_key.clone(),
+            refresh_token: self.refresh_token.clone(),
+        })
+    }
+
+    /// Whether a session can be negotiated instead of configured.
+    pub fn can_negotiate(&self) -> bool {
+        self.login.is_some()
+    }
+
+    fn relogin_enabled(&self) -> bool {
+        self.login.as_ref().is_some_and(|login| login.relogin)
+    }
+
+    fn needs_login(&self) -> bool {
+        let token = self.credentials().token;
+        token.is_empty() || token == "PASTE_YOUR_TOKEN_HERE"
+    }
+}
+
+fn default_validity_type() -> i32 {
+    1
+}
+
+fn default_finance_type() -> i32 {
+    1
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TadbirOrderData {
+    #[serde(default)]
+    pub isin: String,
+    /// `1` to buy, `2` to sell.
+    #[serde(rename = "orderSide")]
+    pub order_side: i32,
+    pub price: i64,
+    pub quantity: i64,
+    /// `1` for a day order, `2` good till date, `3` good till cancelled.
+    #[serde(rename = "validityType", default = "default_validity_type")]
+    pub validity_type: i32,
+    #[serde(rename = "validityDate", default)]
+    pub validity_date: Option<String>,
+    #[serde(rename = "maxShow", default)]
+    pub max_show: i64,
+    #[serde(rename = "minimumQuantity", default)]
+    pub minimum_quantity: i64,
+    /// `1` to pay from the account's own credit.
+    #[serde(rename = "financeType", default = "default_finance_type")]
+    pub finance_type: i32,
+    /// Looked up to fill in the ISIN when that is left out; never sent.
+    #[serde(default, skip_serializing)]
+    pub symbol: Option<String>,
+}
+
+pub fn load_config(path: &str) -> Result<TadbirBrokersConfig> {
+    let config_str = encryption::read_config(path)?;
+    let mut config: TadbirBrokersConfig = accounts::parse_config(path, &config_str)?;
+    for broker in &mut config.brokers {
+        broker.fill_from_host();
+        resolve_secrets(broker)?;
+        for account in &mut broker.accounts {
+            resolve_secrets(account)?;
+        }
+    }
+    Ok(config)
+}
+
+fn resolve_secrets(broker: &mut TadbirBrokerConfig) -> Result<()> {
+    secrets::resolve(&mut broker.token)?;
+    secrets::resolve(&mut broker.api_key)?;
+    secrets::resolve(&mut broker.refresh_token)?;
+    if let Some(login) = &mut broker.login {
+        secrets::resolve(&mut login.password)?;
+    }
+    Ok(())
+}
+
+pub fn find_broker<'a>(
+    config: &'a TadbirBrokersConfig,
+    name: &str,
+) -> Option<&'a TadbirBrokerConfig> {
+    accounts::find(&config.brokers, name)
+}
+
+impl OrderFields for TadbirOrderData {
+    fn isin(&self) -> Option<&str> {
+        Some(&self.isin)
+    }
+
+    fn set_isin(&mut self, isin: &str) {
+        self.isin = isin.to_string();
+    }
+
+    fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    fn price(&self) -> Option<f64> {
+        Some(self.price as f64)
+    }
+
+    fn set_price(&mut self, price: f64) {
+        self.price = price.round() as i64;
+    }
+
+    fn quantity(&self) -> Option<u64> {
+        u64::try_from(self.quantity).ok()
+    }
+
+    fn set_quantity(&mut self, quantity: u64) {
+        self.quantity = quantity as i64;
+    }
+
+    fn side(&self) -> OrderSide {
+        if self.order_side == 2 {
+            OrderSide::Sell
+        } else {
+            OrderSide::Buy
+        }
+    }
+}
+
+impl Broker for TadbirBrokerConfig {
+    type Order = TadbirOrderData;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn config_file(&self) -> &str {
+        "config_tadbir.json"
+    }
+
+    fn order_url(&self) -> &str {
+        &self.order_url
+    }
+
+    fn settings(&self) -> &BrokerSettings {
+        &self.settings
+    }
+
+    fn settings_mut(&mut self) -> &mut BrokerSettings {
+        &mut self.settings
+    }
+
+    fn orders(&self) -> &[OrderEntry<TadbirOrderData>] {
+        &self.orders
+    }
+
+    fn orders_mut(&mut self) -> &mut Vec<OrderEntry<TadbirOrderData>> {
+        &mut self.orders
+    }
+
+    fn accounts(&self) -> &[Self] {
+        &self.accounts
+    }
+
+    fn accounts_mut(&mut self) -> &mut [Self] {
+        &mut self.accounts
+    }
+
+    async fn ensure_session(&self) -> Result<()> {
+        if self.can_negotiate() && self.needs_login() {
+            tadbir_login::relogin(self, self.session.generation()).await?;
+        }
+        Ok(())
+    }
+
+    async fn relogin(&self) -> Result<bool> {
+        if !self.relogin_enabled() {
+            return Ok(false);
+        }
+        tadbir_login::relogin(self, self.session.generation()).await?;
+        Ok(true)
+    }
+
+    fn check_auth(&self) -> Result<()> {
+        if self.credentials().token.is_empty() {
+            anyhow::bail!(
+                "Token is required for {}. Please set 'token', 'refresh_token' or 'login' in config_tadbir.json",
+                self.name
+            );
+        }
+
+        println!("[{}] Using Bearer token authentication", self.name);
+        let token = authorization_value(&self.credentials());
+        println!(
+            "[{}] Token preview: {}...",
+            self.name,
+            &token[..token.len().min(50)]
+        );
+        Ok(())
+    }
+
+    fn order_headers(&self, order_json: &str) -> Result<HeaderMap> {
+        build_order_headers(self, order_json)
+    }
+
+    fn send_order(
+        &self,
+        _order: &Self::Order,
+        request: &PreparedRequest,
+        test_mode: bool,
+        curl_only: bool,
+        rate_limiter: Option<&RateLimiter>,
+    ) -> impl Future<Output = Result<()>> + Send {
+        send_request(self, request, test_mode, curl_only, rate_limiter)
+    }
+
+    fn run_calibration(
+        &self,
+        client: &reqwest::Client,
+        rate_limiter: &RateLimiter,
+    ) -> impl Future<Output = Result<calibration::CalibrationSummary>> + Send {
+        run_calibration(self, client, rate_limiter)
+    }
+
+    fn send_probe(
+        &self,
+        client: &reqwest::Client,
+    ) -> impl Future<Output = Result<(u64, u128, StatusCode)>> + Send {
+        send_probe(self, client)
+    }
+}
+
+fn authorization_value(credentials: &TadbirCredentials) -> String {
+    if credentials.token.starts_with("Bearer ") {
+        credentials.token.clone()
+    } else {
+        format!("Bearer {}", credentials.token)
+    }
+}
+
+/// The bearer token and, when set, the API key.
+fn auth_headers(credentials: &TadbirCredentials, headers: &mut HeaderMap) -> Result<()> {
+    headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&authorization_value(credentials))?,
+    );
+    if !credentials.api_key.is_empty() {
+        headers.insert("X-Api-Key", HeaderValue::from_str(&credentials.api_key)?);
+    }
+    Ok(())
+}
+
+/// Headers for one order request, including content type and length.
+pub fn build_order_headers(broker: &TadbirBrokerConfig, order_json: &str) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(
+        ACCEPT,
+        HeaderValue::from_static("application/json, text/plain, */*"),
+    );
+    headers.insert(
+        "Accept-Language",
+        HeaderValue::from_static("en-US,en;q=0.5"),
+    );
+    headers.insert(
+        ACCEPT_ENCODING,
+        HeaderValue::from_static("gzip, deflate, br, zstd"),
+    );
+    headers.insert(ORIGIN, HeaderValue::from_str(&broker.origin)?);
+    headers.insert(
+        REFERER,
+        HeaderValue::from_str(&format!("{}/", broker.origin.trim_end_matches('/')))?,
+    );
+    headers.insert("Connection", HeaderValue::from_static("keep-alive"));
+    auth_headers(&broker.credentials(), &mut headers)?;
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("empty"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("cors"));
+    headers.insert("Sec-Fetch-Site", HeaderValue::from_static("same-site"));
+    headers.insert("Pragma", HeaderValue::from_static("no-cache"));
+    headers.insert("Cache-Control", HeaderValue::from_static("no-cache"));
+
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(
+        CONTENT_LENGTH,
+        HeaderValue::from_str(&order_json.len().to_string())?,
+    );
+
+    Ok(headers)
+}
+
+/// Build the request for `order_json` and send it.
+pub async fn send_order(
+    broker: &TadbirBrokerConfig,
+    order_json: &str,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let request = PreparedRequest::new(
+        &broker.order_url,
+        broker.order_headers(order_json)?,
+        order_json.to_string(),
+    )?;
+    send_request(broker, &request, test_mode, curl_only, rate_limiter).await
+}
+
+/// Send a request built by [`Broker::prepare`]. With a session to
+/// negotiate, an expired one is renewed and the order retried once.
+pub async fn send_request(
+    broker: &TadbirBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let generation = broker.session.generation();
+    let result = send_request_once(broker, request, test_mode, curl_only, rate_limiter).await;
+    let relogin = broker.relogin_enabled();
+    match result {
+        Err(e) if relogin && order_error_kind(&e) == Some(OrderErrorKind::AuthExpired) => {
+            println!(
+                "[{}] Session expired; negotiating a new one and retrying",
+                broker.name
+            );
+            tadbir_login::relogin(broker, generation)
+                .await
+                .with_context(|| format!("Re-login failed after: {}", e))?;
+            send_request_once(broker, request, test_mode, curl_only, rate_limiter).await
+        }
+        result => result,
+    }
+}
+
+async fn send_request_once(
+    broker: &TadbirBrokerConfig,
+    request: &PreparedRequest,
+    test_mode: bool,
+    curl_only: bool,
+    rate_limiter: Option<&RateLimiter>,
+) -> Result<()> {
+    let order_json = request.body_text();
+    let client = broker.settings.client();
+    let credentials = broker.credentials();
+
+    // Print curl command in test or curl-only mode
+    if test_mode || curl_only {
+        let api_key_line = if credentials.api_key.is_empty() {
+            String::new()
+        } else {
+            format!("  -H 'X-Api-Key: {}' \\\n", credentials.api_key)
+        };
+        println!("[{}] Equivalent curl command:", broker.name);
+        println!(
+            r#"curl '{}' \
+  --compressed \
+  -X POST \
+  -H 'User-Agent: {}' \
+  -H 'Accept: application/json, text/plain, */*' \
+  -H 'Accept-Language: en-US,en;q=0.5' \
+  -H 'Accept-Encoding: gzip, deflate, br, zstd' \
+  -H 'Content-Type: application/json' \
+  -H 'Origin: {}' \
+  -H 'Referer: {}/' \
+  -H 'Connection: keep-alive' \
+  -H 'Authorization: {}' \
+{}  -H 'Sec-Fetch-Dest: empty' \
+  -H 'Sec-Fetch-Mode: cors' \
+  -H 'Sec-Fetch-Site: same-site' \
+  -H 'Pragma: no-cache' \
+  -H 'Cache-Control: no-cache' \
+  --data-raw '{}'"#,
+            broker.order_url,
+            broker.user_agent,
+            broker.origin,
+            broker.origin.trim_end_matches('/'),
+            authorization_value(&credentials),
+            api_key_line,
+            order_json
+        );
+        println!();
+
+        // If curl_only, don't send the request
+        if curl_only {
+            return Ok(());
+        }
+    }
+
+    // The token and API key change on a re-login.
+    let (url, mut headers) = request.parts();
+    headers.remove("X-Api-Key");
+    auth_headers(&credentials, &mut headers)?;
+
+    let mut timing = console::Timing::start();
+    if let Some(limiter) = rate_limiter {
+        limiter
+            .wait_for(request_size(&headers, order_json.len()))
+            .await;
+    }
+    timing.mark("rate limit");
+
+    console::debug_headers(&broker.name, "Request", &headers);
+    let mut capture = Capture::start(&broker.name, &url, &headers, &request.body);
+
+    let response = timing
+        .response(
+            client
+                .post(url)
+                .headers(headers)
+                .body(request.body.clone())
+                .send(),
+        )
+        .await;
+    println!("[{}] Sent order JSON: {}", broker.name, order_json);
+    let response = capture.sent(response)?;
+
+    let status = response.status();
+    console::debug_headers(&broker.name, "Response", response.headers());
+    capture.response_head(status, response.headers());
+    let response_text = response.text().await?;
+    timing.mark("body");
+    capture.finish(&response_text);
+
+    let decoded_text = if response_text.contains("\\u") {
+        crate::decode_unicode_escapes(&response_text)
+    } else {
+        response_text.clone()
+    };
+
+    println!("[{}] Order response status: {}", broker.name, status);
+    println!("[{}] Order response body: {}", broker.name, decoded_text);
+    timing.report(&broker.name);
+
+    if let Some(file) = &broker.settings.response_schema_file {
+        schema_drift::check(&broker.name, file, status, &decoded_text);
+    }
+    success::check_response_as::<TadbirOrderResponse>(
+        &broker.settings.success_rules,
+        status,
+        decoded_text,
+    )
+}
+
+pub async fn run_calibration(
+    broker: &TadbirBrokerConfig,
+    client: &reqwest::Client,
+    rate_limiter: &RateLimiter,
+) -> Result<calibration::CalibrationSummary> {
+    let calibration = broker
+        .settings
+        .calibration
+        .as_ref()
+        .context("Calibration config missing")?;
+
+    let prefix = format!("[{}]", broker.name);
+    calibration::run_calibration(&prefix, calibration, rate_limiter, || {
+        send_probe(broker, client)
+    })
+    .await
+}
+
+async fn send_probe(
+    broker: &TadbirBrokerConfig,
+    client: &reqwest::Client,
+) -> Result<(u64, u128, StatusCode)> {
+    let t0 = Instant::now();
+
+    let mut headers = HeaderMap::new();
+    headers.insert(USER_AGENT, HeaderValue::from_str(&broker.user_agent)?);
+    headers.insert(ACCEPT, HeaderValue::from_static("*/*"));
+    auth_headers(&broker.credentials(), &mut headers)?;
+
+    let base_url = calibration::probe_url(&broker.order_url)?;
+    let response = client.head(base_url).headers(headers).send().await?;
+    let status = response.status();
+
+    let rtt = t0.elapsed();
+    let rtt_micros = rtt.as_micros();
+    let rtt_ms = rtt.as_millis() as u64;
+
+    Ok((rtt_ms, rtt_micros, status))
+}
@@ -0,0 +1,360 @@
+use crate::login;
+use crate::runner::Broker;
+use crate::tadbir_broker::{self, TadbirBrokerConfig};
+use anyhow::{Context, Result};
+use reqwest::header::{ACCEPT, AUTHORIZATION, HeaderValue, ORIGIN, USER_AGENT};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+fn default_api_key_pointer() -> String {
+    "/apiKey".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// `login` section of an entry in `config_tadbir.json`. Paths are relative
+/// to the broker's `host`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TadbirLoginConfig {
+    /// Needed unless the entry's `refresh_token` is enough.
+    #[serde(default)]
+    pub username: String,
+    /// Prompted for on the console when empty.
+    #[serde(default)]
+    pub password: String,
+    /// OAuth token endpoint taking the password and refresh-token grants.
+    pub token_path: String,
+    pub client_id: String,
+    /// Left out of the token request when unset.
+    #[serde(default)]
+    pub scope: Option<String>,
+    /// Endpoint handing out the `X-Api-Key` for a token; unset on hosts
+    /// that do not use one.
+    #[serde(default)]
+    pub api_key_path: Option<String>,
+    /// JSON pointer to the key in that endpoint's response.
+    #[serde(default = "default_api_key_pointer")]
+    pub api_key_pointer: String,
+    /// Log in again and retry once when an order fails with an expired
+    /// session.
+    #[serde(default = "default_true")]
+    pub relogin: bool,
+    /// Write each new token, API key and refresh token back to
+    /// `config_tadbir.json`.
+    #[serde(default = "default_true")]
+    pub save_session: bool,
+}
+
+/// What a Tadbir order needs: the bearer token and, on hosts using one, the
+/// API key; plus the refresh token that renews them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TadbirCredentials {
+    pub token: String,
+    pub api_key: String,
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    current: RwLock<Option<TadbirCredentials>>,
+    generation: AtomicU64,
+    login_lock: tokio::sync::Mutex<()>,
+}
+
+/// Credentials negotiated at runtime, shared by every clone of a broker
+/// config so all in-flight orders switch to a new session together.
+#[derive(Debug, Clone, Default)]
+pub struct TadbirSession(Arc<SessionState>);
+
+impl TadbirSession {
+    pub fn get(&self) -> Option<TadbirCredentials> {
+        self.0
+            .current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    /// Incremented on every login; lets concurrent failures detect that
+    /// another task already refreshed the session.
+    pub fn generation(&self) -> u64 {
+        self.0.generation.load(Ordering::Acquire)
+    }
+
+    fn set(&self, credentials: TadbirCredentials) {
+        *self
+            .0
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(credentials);
+        self.0.generation.fetch_add(1, Ordering::AcqRel);
+    }
+}
+
+/// Run `login <name>` for a Tadbir broker: negotiate a session and store
+/// its token, API key and refresh token in the config file.
+pub async fn run_login(config_path: &str, name: &str) -> Result<()> {
+    let config = tadbir_broker::load_config(config_path)?;
+    let broker = tadbir_broker::find_broker(&config, name)
+        .with_context(|| format!("Broker '{}' not found in {}", name, config_path))?;
+    if !broker.can_negotiate() {
+        anyhow::bail!(
+            "No 'login' section for {} in {}; add its token_path, client_id and username (and optionally password)",
+            broker.name,
+            config_path
+        );
+    }
+
+    let credentials = negotiate_with_prompt(broker).await?;
+    save_credentials(config_path, &broker.name, &credentials)?;
+    println!("[{}] Saved new session to {}", broker.name, config_path);
+    Ok(())
+}
+
+/// Negotiate a new session unless another task already did since
+/// `seen_generation`, and make it the one every later order uses.
+pub async fn relogin(broker: &TadbirBrokerConfig, seen_generation: u64) -> Result<()> {
+    if !broker.can_negotiate() {
+        anyhow::bail!("No 'login' section for {}", broker.name);
+    }
+
+    let _guard = broker.session.0.login_lock.lock().await;
+    if broker.session.generation() != seen_generation {
+        return Ok(());
+    }
+
+    let credentials = negotiate_with_prompt(broker).await?;
+    broker.session.set(credentials.clone());
+    if broker
+        .login
+        .as_ref()
+        .is_some_and(|login| login.save_session)
+    {
+        match save_credentials(broker.config_file(), &broker.name, &credentials) {
+            Ok(()) => println!(
+                "[{}] Saved new session to {}",
+                broker.name,
+                broker.config_file()
+            ),
+            Err(e) => summary!(
+                "[{}] Warning: could not save new session: {:#}",
+                broker.name,
+                e
+            ),
+        }
+    }
+    Ok(())
+}
+
+/// Try the refresh token first; fall back to the password, asking for it on
+/// the console when it is not configured.
+async fn negotiate_with_prompt(broker: &TadbirBrokerConfig) -> Result<TadbirCredentials> {
+    let mut login_config = broker
+        .login
+        .clone()
+        .with_context(|| format!("No 'login' section for {}", broker.name))?;
+    let refresh_token = broker.credentials().refresh_token;
+
+    if !refresh_token.is_empty() {
+        println!(
+            "[{}] Renewing the session with the refresh token",
+            broker.name
+        );
+        match negotiate(
+            &login_config,
+            Grant::RefreshToken(&refresh_token),
+            &broker.host,
+            &broker.origin,
+            &broker.user_agent,
+        )
+        .await
+        {
+            Ok(credentials) => return Ok(credentials),
+            Err(e) if !login_config.username.is_empty() => {
+                println!(
+                    "[{}] Refresh token refused ({:#}); logging in with the password",
+                    broker.name, e
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    if login_config.username.is_empty() {
+        anyhow::bail!(
+            "No username for {}; set login.username or refresh_token in {}",
+            broker.name,
+            broker.config_file()
+        );
+    }
+    if login_config.password.is_empty() {
+        login_config.password =
+            login::prompt_line(&format!("[{}] Password: ", broker.name)).await?;
+    }
+    println!(
+        "[{}] Logging in as {} via {}{}",
+        broker.name,
+        login_config.username,
+        broker.host.trim_end_matches('/'),
+        login_config.token_path
+    );
+    let grant = Grant::Password {
+        username: &login_config.username,
+        password: &login_config.password,
+    };
+    negotiate(
+        &login_config,
+        grant,
+        &broker.host,
+        &broker.origin,
+        &broker.user_agent,
+    )
+    .await
+}
+
+/// How the token endpoint is asked for a session.
+#[derive(Debug, Clone, Copy)]
+pub enum Grant<'a> {
+    Password {
+        username: &'a str,
+        password: &'a str,
+    },
+    RefreshToken(&'a str),
+}
+
+/// Get a token from the token endpoint, then the API key for it when the
+/// host hands one out.
+pub async fn negotiate(
+    config: &TadbirLoginConfig,
+    grant: Grant<'_>,
+    host: &str,
+    origin: &str,
+    user_agent: &str,
+) -> Result<TadbirCredentials> {
+    let client = reqwest::Client::new();
+    let host = host.trim_end_matches('/');
+
+    let mut form = vec![("client_id", config.client_id.as_str())];
+    if let Some(scope) = &config.scope {
+        form.push(("scope", scope));
+    }
+    match grant {
+        Grant::Password { username, password } => form.extend([
+            ("grant_type", "password"),
+            ("username", username),
+            ("password", password),
+        ]),
+        Grant::RefreshToken(refresh_token) => form.extend([
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token),
+        ]),
+    }
+
+    let response = client
+        .post(format!("{}{}", host, config.token_path))
+        .header(USER_AGENT, HeaderValue::from_str(user_agent)?)
+        .header(ORIGIN, HeaderValue::from_str(origin)?)
+        .form(&form)
+        .send()
+        .await
+        .context("Tadbir token request failed")?;
+    let status = response.status();
+    let body = response.text().await?;
+    let value: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+    if !status.is_success() {
+        let message = value
+            .get("error_description")
+            .or_else(|| value.get("error"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .unwrap_or_else(|| crate::decode_unicode_escapes(&body));
+        anyhow::bail!("Tadbir login failed with status {}: {}", status, message);
+    }
+    let token = value
+        .get("access_token")
+        .and_then(Value::as_str)
+        .with_context(|| format!("Token response has no access_token: {}", body))?
+        .to_string();
+    let refresh_token = match (value.get("refresh_token").and_then(Value::as_str), grant) {
+        (Some(rotated), _) => rotated.to_string(),
+        (None, Grant::RefreshToken(refresh_token)) => refresh_token.to_string(),
+        (None, Grant::Password { .. }) => String::new(),
+    };
+
+    let api_key = match &config.api_key_path {
+        Some(api_key_path) => {
+            fetch_api_key(
+                &client,
+                config,
+                &format!("{}{}", host, api_key_path),
+                &token,
+                user_agent,
+            )
+            .await?
+        }
+        None => String::new(),
+    };
+
+    Ok(TadbirCredentials {
+        token,
+        api_key,
+        refresh_token,
+    })
+}
+
+async fn fetch_api_key(
+    client: &reqwest::Client,
+    config: &TadbirLoginConfig,
+    url: &str,
+    token: &str,
+    user_agent: &str,
+) -> Result<String> {
+    let response = client
+        .get(url)
+        .header(USER_AGENT, HeaderValue::from_str(user_agent)?)
+        .header(ACCEPT, HeaderValue::from_static("application/json"))
+        .header(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", token))?,
+        )
+        .send()
+        .await
+        .context("Tadbir API key request failed")?;
+    let status = response.status();
+    let body = response.text().await?;
+    if !status.is_success() {
+        anyhow::bail!(
+            "Tadbir API key request failed with status {}: {}",
+            status,
+            crate::decode_unicode_escapes(&body)
+        );
+    }
+    let value: Value = serde_json::from_str(&body)
+        .with_context(|| format!("API key response is not JSON: {}", body))?;
+    match value.pointer(&config.api_key_pointer) {
+        Some(Value::String(api_key)) if !api_key.is_empty() => Ok(api_key.clone()),
+        _ => anyhow::bail!(
+            "API key response has no key at {}: {}",
+            config.api_key_pointer,
+            body
+        ),
+    }
+}
+
+fn save_credentials(config_path: &str, name: &str, credentials: &TadbirCredentials) -> Result<()> {
+    let mut fields = vec![
+        ("token", Value::String(credentials.token.clone())),
+        ("api_key", Value::String(credentials.api_key.clone())),
+    ];
+    if !credentials.refresh_token.is_empty() {
+        fields.push((
+            "refresh_token",
+            Value::String(credentials.refresh_token.clone()),
+        ));
+    }
+    login::update_config_fields(config_path, Some(name), &fields)
+}
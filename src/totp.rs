@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn default_period_secs() -> u64 {
+    30
+}
+
+/// Wait for the next code when the current one expires sooner than this, so
+/// it is still valid when the server checks it.
+const MIN_REMAINING: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TotpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+/// `totp` section of a login config: the shared secret from the
+/// authenticator setup QR code, so one-time codes need no prompt.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TotpConfig {
+    /// Base32 secret, or a `keyring:<name>` reference.
+    pub secret: String,
+    #[serde(default = "default_digits")]
+    pub digits: u32,
+    #[serde(default = "default_period_secs")]
+    pub period_secs: u64,
+    #[serde(default)]
+    pub algorithm: TotpAlgorithm,
+}
+
+impl TotpConfig {
+    /// The RFC 6238 code for `unix_secs`.
+    pub fn code_at(&self, unix_secs: u64) -> Result<String> {
+        if self.period_secs == 0 {
+            anyhow::bail!("totp.period_secs must be >= 1");
+        }
+        if !(6..=9).contains(&self.digits) {
+            anyhow::bail!("totp.digits must be between 6 and 9");
+        }
+        let key = decode_base32(&self.secret).context("totp.secret is not valid base32")?;
+        let counter = (unix_secs / self.period_secs).to_be_bytes();
+        let digest = match self.algorithm {
+            TotpAlgorithm::Sha1 => hmac_digest::<Hmac<sha1::Sha1>>(&key, &counter),
+            TotpAlgorithm::Sha256 => hmac_digest::<Hmac<sha2::Sha256>>(&key, &counter),
+        };
+
+        let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+        let truncated = u32::from_be_bytes([
+            digest[offset] & 0x7f,
+            digest[offset + 1],
+            digest[offset + 2],
+            digest[offset + 3],
+        ]);
+        let code = truncated as u64 % 10u64.pow(self.digits);
+        Ok(format!("{:0width$}", code, width = self.digits as usize))
+    }
+
+    /// A code with at least a few seconds of validity left, waiting for the
+    /// next period if needed.
+    pub async fn fresh_code(&self) -> Result<String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let period = Duration::from_secs(self.period_secs.max(1));
+        let into_period = Duration::from_nanos((now.as_nanos() % period.as_nanos()) as u64);
+        let remaining = period - into_period;
+        let now = if remaining < MIN_REMAINING {
+            tokio::time::sleep(remaining).await;
+            now + remaining
+        } else {
+            now
+        };
+        self.code_at(now.as_secs())
+    }
+}
+
+fn hmac_digest<M: Mac + hmac::digest::KeyInit>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac =
+        <M as hmac::digest::KeyInit>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Decode RFC 4648 base32, ignoring case, spaces and padding as
+/// authenticator apps display secrets.
+fn decode_base32(secret: &str) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0u32;
+    for ch in secret.chars().filter(|ch| !matches!(ch, ' ' | '-' | '=')) {
+        let value = match ch.to_ascii_uppercase() {
+            letter @ 'A'..='Z' => letter as u32 - 'A' as u32,
+            digit @ '2'..='7' => digit as u32 - '2' as u32 + 26,
+            other => anyhow::bail!("invalid character '{}'", other),
+        };
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    if bytes.is_empty() {
+        anyhow::bail!("secret is empty");
+    }
+    Ok(bytes)
+}
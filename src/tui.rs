@@ -0,0 +1,436 @@
+//! `--tui`: a live dashboard instead of the interleaved log.
+//!
+//! The run keeps printing as usual; stdout and stderr are redirected into a
+//! pipe and every `[name] ...` line is read back to update that broker's
+//! panel and the scrolling log.
+
+use ratatui::Frame;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use std::collections::VecDeque;
+
+/// Log lines kept for the log pane and printed when the dashboard closes.
+const LOG_LIMIT: usize = 5000;
+
+/// Panels side by side on one row.
+const PANELS_PER_ROW: usize = 3;
+
+/// What the dashboard knows about one broker, with its accounts folded in.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Panel {
+    pub name: String,
+    /// Epoch milliseconds of the scheduled `target_time`.
+    pub target_epoch_ms: Option<i64>,
+    /// Calibrated delay estimate and safety margin, as logged.
+    pub delay: Option<String>,
+    pub batches: u64,
+    pub scheduled_sends: u64,
+    pub accepted: u64,
+    pub failed: u64,
+    pub last_status: Option<String>,
+    pub last_message: Option<String>,
+    pub last_error: Option<String>,
+}
+
+/// Dashboard state built from the captured log.
+#[derive(Debug, Default)]
+pub struct DashboardState {
+    panels: Vec<Panel>,
+    log: VecDeque<String>,
+}
+
+impl DashboardState {
+    pub fn panels(&self) -> &[Panel] {
+        &self.panels
+    }
+
+    /// Add one captured line to the log and to its broker's panel.
+    pub fn update(&mut self, line: &str) {
+        if self.log.len() == LOG_LIMIT {
+            self.log.pop_front();
+        }
+        self.log.push_back(line.to_string());
+
+        let Some((name, rest)) = line
+            .strip_prefix('[')
+            .and_then(|line| line.split_once("] "))
+        else {
+            return;
+        };
+        let broker = name
+            .split(crate::accounts::SEPARATOR)
+            .next()
+            .unwrap_or(name);
+        let panel = match self
+            .panels
+            .iter()
+            .position(|panel| panel.name.eq_ignore_ascii_case(broker))
+        {
+            Some(position) => &mut self.panels[position],
+            None => {
+                self.panels.push(Panel {
+                    name: broker.to_string(),
+                    ..Panel::default()
+                });
+                self.panels.last_mut().expect("just pushed")
+            }
+        };
+
+        if let Some(target) = field(rest, "target_epoch_ms=") {
+            panel.target_epoch_ms = target.parse().ok();
+        } else if rest.starts_with("Next target_time=") {
+            let target = field(rest, "(epoch_ms=").unwrap_or_default();
+            panel.target_epoch_ms = target.trim_end_matches(')').parse().ok();
+        } else if rest.contains("estimator_delay=") {
+            panel.delay = Some(format!(
+                "estimate {}, margin {}",
+                field(rest, "estimator_delay=").unwrap_or("?"),
+                field(rest, "safety_margin=").unwrap_or("?")
+            ));
+        } else if let Some(batch) = rest.strip_prefix("=== Batch #") {
+            let number = batch.split(':').next().unwrap_or_default();
+            panel.batches = number.parse().unwrap_or(panel.batches);
+        } else if rest.starts_with("Sending scheduled order #") {
+            panel.scheduled_sends += 1;
+        } else if let Some(status) = rest.strip_prefix("Order response status: ") {
+            panel.last_status = Some(status.to_string());
+        } else if let Some(body) = rest.strip_prefix("Order response body: ") {
+            panel.last_message = Some(body.chars().take(120).collect());
+        } else if rest.starts_with('✓') {
+            panel.accepted += 1;
+        } else if rest.starts_with('✗') {
+            panel.failed += 1;
+        } else if rest.starts_with("Error") || rest.contains("failed") {
+            panel.last_error = Some(rest.to_string());
+        }
+    }
+
+    fn draw(&self, frame: &mut Frame, now_epoch_ms: i64) {
+        let [title, panels, log] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Percentage(50),
+            Constraint::Fill(1),
+        ])
+        .areas(frame.area());
+        frame.render_widget(
+            Line::styled(
+                "Sarkhati - q or Ctrl+C quits; the full log is printed on exit",
+                Style::new().add_modifier(Modifier::BOLD),
+            ),
+            title,
+        );
+
+        let rows: Vec<&[Panel]> = self.panels.chunks(PANELS_PER_ROW).collect();
+        let row_areas =
+            Layout::vertical(vec![Constraint::Fill(1); rows.len().max(1)]).split(panels);
+        for (row, area) in rows.iter().zip(row_areas.iter()) {
+            let areas = Layout::horizontal(vec![Constraint::Fill(1); PANELS_PER_ROW]).split(*area);
+            for (panel, area) in row.iter().zip(areas.iter()) {
+                draw_panel(frame, panel, *area, now_epoch_ms);
+            }
+        }
+
+        let height = log.height.saturating_sub(2) as usize;
+        let lines: Vec<Line> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(height))
+            .map(|line| Line::styled(line.as_str(), line_style(line)))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(Block::bordered().title("Log")),
+            log,
+        );
+    }
+}
+
+fn draw_panel(frame: &mut Frame, panel: &Panel, area: Rect, now_epoch_ms: i64) {
+    let countdown = match panel.target_epoch_ms {
+        Some(target) if target > now_epoch_ms => {
+            let ms = target - now_epoch_ms;
+            format!(
+                "in {:02}:{:02}:{:02}.{}",
+                ms / 3_600_000,
+                ms / 60_000 % 60,
+                ms / 1000 % 60,
+                ms / 100 % 10
+            )
+        }
+        Some(_) => "passed".to_string(),
+        None => "-".to_string(),
+    };
+    let sends = if panel.scheduled_sends > 0 {
+        format!("{} scheduled sends", panel.scheduled_sends)
+    } else {
+        format!("{} batches", panel.batches)
+    };
+    let mut lines = vec![
+        Line::from(format!("Target: {}", countdown)),
+        Line::from(format!(
+            "Calibration: {}",
+            panel.delay.as_deref().unwrap_or("-")
+        )),
+        Line::from(sends),
+        Line::styled(
+            format!("✓ {}  ✗ {}", panel.accepted, panel.failed),
+            if panel.accepted > 0 {
+                Style::new().fg(Color::Green)
+            } else {
+                Style::new()
+            },
+        ),
+        Line::from(format!(
+            "Last: {} {}",
+            panel.last_status.as_deref().unwrap_or("-"),
+            panel.last_message.as_deref().unwrap_or("")
+        )),
+    ];
+    if let Some(error) = &panel.last_error {
+        lines.push(Line::styled(error.as_str(), Style::new().fg(Color::Red)));
+    }
+    let border = if panel.accepted > 0 {
+        Style::new().fg(Color::Green)
+    } else if panel.last_error.is_some() || panel.failed > 0 {
+        Style::new().fg(Color::Red)
+    } else {
+        Style::new()
+    };
+    frame.render_widget(
+        Paragraph::new(lines).block(
+            Block::bordered()
+                .title(panel.name.as_str())
+                .border_style(border),
+        ),
+        area,
+    );
+}
+
+fn line_style(line: &str) -> Style {
+    if line.contains('✓') {
+        Style::new().fg(Color::Green)
+    } else if line.contains('✗') || line.contains("Error") {
+        Style::new().fg(Color::Red)
+    } else if line.contains("Warning") {
+        Style::new().fg(Color::Yellow)
+    } else {
+        Style::new()
+    }
+}
+
+/// The value after `key` in a `key=value key=value` line.
+fn field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    let start = line.find(key)? + key.len();
+    line[start..].split_whitespace().next()
+}
+
+#[cfg(unix)]
+pub use capture::Dashboard;
+
+#[cfg(unix)]
+mod capture {
+    use super::DashboardState;
+    use anyhow::{Context, Result};
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyModifiers};
+    use ratatui::crossterm::execute;
+    use ratatui::crossterm::terminal::{
+        EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode,
+    };
+    use ratatui::{Terminal, TerminalOptions, Viewport};
+    use std::fs::File;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::fd::{FromRawFd, RawFd};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, Receiver};
+    use std::sync::{Arc, Mutex};
+    use std::thread::JoinHandle;
+    use std::time::Duration;
+
+    const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+    /// The running dashboard. Stdout and stderr go to it until
+    /// [`Dashboard::finish`].
+    pub struct Dashboard {
+        saved_stdout: RawFd,
+        saved_stderr: RawFd,
+        stop: Arc<AtomicBool>,
+        reader: Option<JoinHandle<()>>,
+        render: Option<JoinHandle<()>>,
+        state: Arc<Mutex<DashboardState>>,
+    }
+
+    impl Dashboard {
+        pub fn start() -> Result<Self> {
+            std::io::stdout().flush()?;
+            std::io::stderr().flush()?;
+            let mut pipe = [0; 2];
+            // SAFETY: plain descriptor calls on descriptors this process
+            // owns; each result is checked before use.
+            let (saved_stdout, saved_stderr) = unsafe {
+                if libc::pipe(pipe.as_mut_ptr()) != 0 {
+                    return Err(std::io::Error::last_os_error()).context("Failed to create a pipe");
+                }
+                let saved_stdout = libc::dup(libc::STDOUT_FILENO);
+                let saved_stderr = libc::dup(libc::STDERR_FILENO);
+                if saved_stdout < 0
+                    || saved_stderr < 0
+                    || libc::dup2(pipe[1], libc::STDOUT_FILENO) < 0
+                    || libc::dup2(pipe[1], libc::STDERR_FILENO) < 0
+                {
+                    return Err(std::io::Error::last_os_error())
+                        .context("Failed to capture stdout");
+                }
+                libc::close(pipe[1]);
+                (saved_stdout, saved_stderr)
+            };
+
+            let (lines_tx, lines) = mpsc::channel();
+            // SAFETY: the read end is owned by this reader from here on.
+            let pipe_reader = unsafe { File::from_raw_fd(pipe[0]) };
+            let reader = std::thread::spawn(move || {
+                for line in BufReader::new(pipe_reader).lines() {
+                    let Ok(line) = line else { break };
+                    if lines_tx.send(line).is_err() {
+                        break;
+                    }
+                }
+            });
+
+            let stop = Arc::new(AtomicBool::new(false));
+            let state = Arc::new(Mutex::new(DashboardState::default()));
+            // SAFETY: a fresh duplicate owned by the render thread.
+            let tty = unsafe { File::from_raw_fd(libc::dup(saved_stdout)) };
+            let render = {
+                let stop = stop.clone();
+                let state = state.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = render_loop(tty, &lines, &stop, &state) {
+                        state
+                            .lock()
+                            .unwrap_or_else(|e| e.into_inner())
+                            .update(&format!("[TUI] Error: {:#}", e));
+                    }
+                })
+            };
+
+            Ok(Self {
+                saved_stdout,
+                saved_stderr,
+                stop,
+                reader: Some(reader),
+                render: Some(render),
+                state,
+            })
+        }
+
+        /// Close the dashboard, give stdout and stderr back and print the
+        /// captured log.
+        pub fn finish(mut self) {
+            self.close();
+        }
+
+        fn close(&mut self) {
+            let _ = std::io::stdout().flush();
+            let _ = std::io::stderr().flush();
+            // SAFETY: restores the descriptors saved in `start`; this also
+            // closes the pipe's write end, so the reader sees EOF.
+            unsafe {
+                libc::dup2(self.saved_stdout, libc::STDOUT_FILENO);
+                libc::dup2(self.saved_stderr, libc::STDERR_FILENO);
+            }
+            if let Some(reader) = self.reader.take() {
+                let _ = reader.join();
+            }
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(render) = self.render.take() {
+                let _ = render.join();
+            }
+            let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            for line in &state.log {
+                println!("{}", line);
+            }
+        }
+    }
+
+    impl Drop for Dashboard {
+        fn drop(&mut self) {
+            if self.render.is_some() {
+                self.close();
+            }
+        }
+    }
+
+    fn render_loop(
+        tty: File,
+        lines: &Receiver<String>,
+        stop: &AtomicBool,
+        state: &Mutex<DashboardState>,
+    ) -> Result<()> {
+        enable_raw_mode()?;
+        let mut tty = tty;
+        execute!(tty, EnterAlternateScreen)?;
+        let mut terminal = Terminal::with_options(
+            CrosstermBackend::new(tty),
+            TerminalOptions {
+                viewport: Viewport::Fullscreen,
+            },
+        )?;
+        let result = (|| -> Result<()> {
+            loop {
+                // Drain after the stop flag is seen, so the last lines make
+                // it into the log.
+                let stopping = stop.load(Ordering::Relaxed);
+                {
+                    let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+                    while let Ok(line) = lines.try_recv() {
+                        state.update(&line);
+                    }
+                    let now_epoch_ms = chrono::Utc::now().timestamp_millis();
+                    terminal.draw(|frame| state.draw(frame, now_epoch_ms))?;
+                }
+                if stopping {
+                    return Ok(());
+                }
+                if event::poll(REDRAW_INTERVAL)?
+                    && let Event::Key(key) = event::read()?
+                    && (key.code == KeyCode::Char('q')
+                        || (key.code == KeyCode::Char('c')
+                            && key.modifiers.contains(KeyModifiers::CONTROL)))
+                {
+                    quit(&mut terminal);
+                }
+            }
+        })();
+        restore(&mut terminal);
+        result
+    }
+
+    fn restore(terminal: &mut Terminal<CrosstermBackend<File>>) {
+        let _ = disable_raw_mode();
+        let _ = execute!(terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = terminal.show_cursor();
+    }
+
+    /// Leave the dashboard on `q` or Ctrl+C, which raw mode keeps from
+    /// interrupting the run.
+    fn quit(terminal: &mut Terminal<CrosstermBackend<File>>) -> ! {
+        restore(terminal);
+        let _ = writeln!(terminal.backend_mut(), "Stopped from the dashboard.");
+        std::process::exit(130);
+    }
+}
+
+/// Without a Unix terminal to capture, `--tui` is refused.
+#[cfg(not(unix))]
+pub struct Dashboard;
+
+#[cfg(not(unix))]
+impl Dashboard {
+    pub fn start() -> anyhow::Result<Self> {
+        anyhow::bail!("--tui is only supported on Unix terminals")
+    }
+
+    pub fn finish(self) {}
+}
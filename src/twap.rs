@@ -0,0 +1,99 @@
+use crate::orders::{OrderEntry, Tranche};
+use crate::runner::Broker;
+use anyhow::Result;
+use serde::Deserialize;
+
+/// `twap` setting: send each order's quantity in `slices` equal child
+/// orders spread evenly over `duration_secs`, for building a position
+/// rather than racing for the open.
+#[derive(Debug, Deserialize, Clone)]
+pub struct TwapSettings {
+    pub slices: usize,
+    pub duration_secs: u64,
+}
+
+impl TwapSettings {
+    /// One tranche per slice, `duration_secs / slices` apart, the first at
+    /// once.
+    pub fn tranches(&self) -> Vec<Tranche> {
+        let interval_ms = self.duration_secs * 1000 / self.slices as u64;
+        (0..self.slices as u64)
+            .map(|slice| Tranche {
+                share: 100.0 / self.slices as f64,
+                after_ms: slice * interval_ms,
+            })
+            .collect()
+    }
+}
+
+/// Apply the broker's `twap` setting, if it has one: give every order of
+/// the broker and its accounts the slices as tranches, and stop sending
+/// each child once it was accepted so it goes out once. Continuous batches
+/// wait for the responses of the one before, so a child still awaiting its
+/// answer is not sent again. `batch_repeat` and an order's `repeat` would
+/// send each child several times, so they are refused.
+pub fn apply<B: Broker>(broker: &mut B) -> Result<()> {
+    let Some(twap) = broker.settings().twap.clone() else {
+        return Ok(());
+    };
+    let name = broker.name().to_string();
+    if twap.slices == 0 || twap.duration_secs == 0 {
+        anyhow::bail!(
+            "twap of {} needs at least 1 slice and a duration of at least 1 second",
+            name
+        );
+    }
+    let tranches = twap.tranches();
+    check_repeat(&name, broker.settings().batch_repeat)?;
+    slice(&name, broker.orders_mut(), &tranches)?;
+    let settings = broker.settings_mut();
+    settings.stop_on_accept = true;
+    settings.wait_for_batch_completion = true;
+    for account in broker.accounts_mut() {
+        let account_name = account.name().to_string();
+        check_repeat(&account_name, account.settings().batch_repeat)?;
+        slice(&account_name, account.orders_mut(), &tranches)?;
+        account.settings_mut().stop_on_accept = true;
+    }
+    println!(
+        "[{}] TWAP: each order in {} slice(s) over {}s, one every {:.1}s",
+        name,
+        twap.slices,
+        twap.duration_secs,
+        twap.duration_secs as f64 / twap.slices as f64
+    );
+    Ok(())
+}
+
+fn check_repeat(name: &str, batch_repeat: usize) -> Result<()> {
+    if batch_repeat > 1 {
+        anyhow::bail!(
+            "{} has batch_repeat {} and cannot be sliced by twap, which sends each slice once",
+            name,
+            batch_repeat
+        );
+    }
+    Ok(())
+}
+
+fn slice<T>(name: &str, orders: &mut [OrderEntry<T>], tranches: &[Tranche]) -> Result<()> {
+    for (index, order) in orders.iter_mut().enumerate() {
+        if !order.tranches.is_empty() {
+            anyhow::bail!(
+                "Order #{} of {} has its own tranches and cannot also be sliced by twap",
+                index + 1,
+                name
+            );
+        }
+        if order.repeat > 1 {
+            anyhow::bail!(
+                "Order #{} of {} has repeat {} and cannot be sliced by twap, which sends each slice once",
+                index + 1,
+                name,
+                order.repeat
+            );
+        }
+        order.tranches = tranches.to_vec();
+    }
+    Ok(())
+}
@@ -0,0 +1,212 @@
+use crate::market_data::{self, TradingTrigger};
+use crate::market_feed::MarketFeedConfig;
+use crate::orders::{OrderEntry, OrderFields};
+use anyhow::{Context, Result};
+use chrono::TimeZone;
+use chrono_tz::Asia::Tehran;
+use serde::Deserialize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::task::JoinHandle;
+
+/// One `watchlist` entry: the broker's orders on `isin` wait for `when`.
+#[derive(Debug, Deserialize, Clone)]
+pub struct WatchlistEntry {
+    pub isin: String,
+    pub when: WatchTrigger,
+    /// How often the trading state is polled for `open`.
+    #[serde(default = "market_data::default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+impl WatchlistEntry {
+    /// Whether `order` is on this entry's symbol.
+    fn matches<T: OrderFields>(&self, order: &OrderEntry<T>) -> bool {
+        order
+            .data
+            .isin()
+            .is_some_and(|isin| isin.trim().eq_ignore_ascii_case(self.isin.trim()))
+    }
+}
+
+/// What releases a watched symbol's orders: `open` once TSETMC reports it
+/// allowed for trading, or a Tehran time such as `08:59:58.500`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum WatchTrigger {
+    Open,
+    At(chrono::NaiveTime),
+}
+
+impl TryFrom<String> for WatchTrigger {
+    type Error = anyhow::Error;
+
+    fn try_from(when: String) -> Result<Self> {
+        let when = when.trim();
+        if when.eq_ignore_ascii_case("open") {
+            return Ok(Self::Open);
+        }
+        chrono::NaiveTime::parse_from_str(when, "%H:%M:%S%.f")
+            .map(Self::At)
+            .with_context(|| {
+                format!(
+                    "Invalid watchlist when '{}'; expected 'open' or a time such as 08:59:58.500",
+                    when
+                )
+            })
+    }
+}
+
+impl std::fmt::Display for WatchTrigger {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Open => write!(f, "open"),
+            Self::At(time) => write!(f, "{}", time.format("%H:%M:%S%.3f")),
+        }
+    }
+}
+
+/// Follows every `watchlist` entry at once and releases each symbol's
+/// orders as soon as its own trigger fires; orders on symbols not in the
+/// list are never held. Watching stops when the watchlist is dropped.
+pub struct Watchlist {
+    released: Arc<Vec<AtomicBool>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Watchlist {
+    /// Start watching `entries` for `orders`; `None` when there are no
+    /// entries. Every entry must match at least one order.
+    pub fn start<T: OrderFields>(
+        name: &str,
+        entries: &[WatchlistEntry],
+        orders: &[OrderEntry<T>],
+        base_url: &str,
+        feed: Option<&MarketFeedConfig>,
+    ) -> Result<Option<Self>> {
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let released: Arc<Vec<AtomicBool>> = Arc::new(
+            orders
+                .iter()
+                .map(|order| {
+                    let watched = entries.iter().any(|entry| entry.matches(order));
+                    AtomicBool::new(!watched)
+                })
+                .collect(),
+        );
+        let mut watches = Vec::with_capacity(entries.len());
+        for (position, entry) in entries.iter().enumerate() {
+            if entries[..position]
+                .iter()
+                .any(|earlier| earlier.isin.trim().eq_ignore_ascii_case(entry.isin.trim()))
+            {
+                anyhow::bail!("Watchlist of {} lists {} twice", name, entry.isin);
+            }
+            let indexes: Vec<usize> = orders
+                .iter()
+                .enumerate()
+                .filter(|(_, order)| entry.matches(order))
+                .map(|(index, _)| index)
+                .collect();
+            if indexes.is_empty() {
+                anyhow::bail!(
+                    "Watchlist entry {} of {} matches none of its orders",
+                    entry.isin,
+                    name
+                );
+            }
+            watches.push((entry, indexes));
+        }
+        let mut tasks = Vec::with_capacity(watches.len());
+        for (entry, indexes) in watches {
+            println!(
+                "[{}] Watchlist: {} held until {}",
+                name,
+                order_list(&indexes),
+                watched(entry)
+            );
+            let name = name.to_string();
+            let entry = entry.clone();
+            let base_url = base_url.to_string();
+            let feed = feed.cloned();
+            let released = released.clone();
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = fire(&name, &entry, &base_url, feed.as_ref()).await {
+                    eprintln!("[{}] Watchlist: {} stays held: {:#}", name, entry.isin, e);
+                    return;
+                }
+                for &index in &indexes {
+                    released[index].store(true, Ordering::Relaxed);
+                }
+                summary!(
+                    "[{}] Watchlist: {} fired; releasing {}",
+                    name,
+                    entry.isin,
+                    order_list(&indexes)
+                );
+            }));
+        }
+        Ok(Some(Self { released, tasks }))
+    }
+
+    /// Whether order `index` is free to be sent.
+    pub fn is_released(&self, index: usize) -> bool {
+        self.released
+            .get(index)
+            .is_none_or(|released| released.load(Ordering::Relaxed))
+    }
+}
+
+impl Drop for Watchlist {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Return once `entry`'s trigger fires.
+async fn fire(
+    name: &str,
+    entry: &WatchlistEntry,
+    base_url: &str,
+    feed: Option<&MarketFeedConfig>,
+) -> Result<()> {
+    match entry.when {
+        WatchTrigger::Open => {
+            let trigger = TradingTrigger {
+                poll_interval_ms: entry.poll_interval_ms,
+                ..TradingTrigger::new(&entry.isin)
+            };
+            market_data::wait_for_trading(name, &trigger, base_url, feed).await
+        }
+        WatchTrigger::At(time) => {
+            let now = chrono::Utc::now().with_timezone(&Tehran);
+            let at = Tehran
+                .from_local_datetime(&now.date_naive().and_time(time))
+                .single()
+                .context("Failed to resolve the watchlist time in Asia/Tehran timezone")?;
+            if let Ok(wait) = (at - now).to_std() {
+                tokio::time::sleep(wait).await;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn watched(entry: &WatchlistEntry) -> String {
+    match entry.when {
+        WatchTrigger::Open => format!("{} opens", entry.isin),
+        WatchTrigger::At(_) => format!("{} for {}", entry.when, entry.isin),
+    }
+}
+
+fn order_list(indexes: &[usize]) -> String {
+    let orders: Vec<String> = indexes
+        .iter()
+        .map(|index| format!("#{}", index + 1))
+        .collect();
+    format!("order(s) {}", orders.join(", "))
+}
@@ -0,0 +1,161 @@
+use sarkhati::accounts;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::rate_limiter::RateLimiterRegistry;
+use sarkhati::runner::{Broker, RunOptions, run_broker};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn family_config(server: &MockServer, strategy: &str) -> CustomBrokersConfig {
+    let mut value = json!({
+        "brokers": [{
+            "name": "acme",
+            "account_strategy": strategy,
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}", "qty": "{{quantity}}" },
+            "batch_delay_ms": 0,
+            "orders": [{ "isin": "IRO1RVND0001", "quantity": 100 }],
+            "accounts": [
+                { "name": "me", "headers": { "X-Api-Key": "key-me" } },
+                {
+                    "name": "wife",
+                    "headers": { "X-Api-Key": "key-wife" },
+                    "orders": [{ "isin": "IRO1RVND0001", "quantity": 40 }]
+                }
+            ]
+        }]
+    });
+    assert!(accounts::expand(&mut value).unwrap());
+    serde_json::from_value(value).unwrap()
+}
+
+fn test_options() -> RunOptions {
+    RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    }
+}
+
+#[tokio::test]
+async fn orders_fan_out_to_every_account_with_its_own_credentials() {
+    let server = MockServer::start().await;
+    for key in ["key-me", "key-wife"] {
+        Mock::given(method("POST"))
+            .and(path("/orders"))
+            .and(header("x-api-key", key))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .named(key)
+            .mount(&server)
+            .await;
+    }
+    let mut config = family_config(&server, "broadcast-all");
+    let broker = config.brokers.remove(0);
+    let names: Vec<&str> = broker.accounts().iter().map(|a| a.name()).collect();
+    assert_eq!(names, ["acme/me", "acme/wife"]);
+
+    run_broker(broker, test_options()).await.unwrap();
+
+    let bodies: Vec<Value> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect();
+    assert_eq!(bodies.len(), 2);
+    assert!(bodies.contains(&json!({ "symbol": "IRO1RVND0001", "qty": 100 })));
+    assert!(bodies.contains(&json!({ "symbol": "IRO1RVND0001", "qty": 40 })));
+}
+
+#[tokio::test]
+async fn order_fails_only_when_every_account_fails() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(header("x-api-key", "key-me"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(header("x-api-key", "key-wife"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    let mut config = family_config(&server, "broadcast-all");
+    run_broker(config.brokers.remove(0), test_options())
+        .await
+        .unwrap();
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    let mut config = family_config(&server, "broadcast-all");
+    let error = run_broker(config.brokers.remove(0), test_options())
+        .await
+        .unwrap_err();
+    assert!(format!("{:#}", error).contains("All 2 accounts failed"));
+}
+
+#[tokio::test]
+async fn round_robin_sends_each_order_through_one_account() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(header("x-api-key", "key-me"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let mut config = family_config(&server, "round-robin");
+    run_broker(config.brokers.remove(0), test_options())
+        .await
+        .unwrap();
+
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn first_success_cancels_the_slower_accounts() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(header("x-api-key", "key-me"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(header("x-api-key", "key-wife"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(10)))
+        .mount(&server)
+        .await;
+    let mut config = family_config(&server, "first-success-stops-others");
+
+    let started = Instant::now();
+    run_broker(config.brokers.remove(0), test_options())
+        .await
+        .unwrap();
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[tokio::test]
+async fn rate_limiters_are_shared_per_broker_and_account() {
+    let registry = RateLimiterRegistry::new();
+    let me = registry.get("bmi", Some("me"), 500, None);
+    let wife = registry.get("bmi", Some("wife"), 500, None);
+    assert!(Arc::ptr_eq(
+        &me,
+        &registry.get("BMI", Some("me"), 500, None)
+    ));
+    assert!(!Arc::ptr_eq(&me, &wife));
+    assert!(!Arc::ptr_eq(&me, &registry.get("bmi", None, 500, None)));
+
+    // Each account gets its own allowance: one send per account is immediate.
+    me.wait().await;
+    let started = Instant::now();
+    wife.wait().await;
+    assert!(started.elapsed() < Duration::from_millis(250));
+    me.wait().await;
+    assert!(started.elapsed() >= Duration::from_millis(400));
+}
@@ -0,0 +1,59 @@
+use sarkhati::auth_check::{self, SessionState};
+use sarkhati::bidar::BidarConfig;
+use serde_json::json;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+// Payload is {"exp":4102444800}, 2100-01-01.
+const LONG_LIVED_JWT: &str = "e30.eyJleHAiOjQxMDI0NDQ4MDB9.sig";
+
+fn bidar(server: &MockServer, authorization: &str) -> BidarConfig {
+    serde_json::from_value(json!({
+        "authorization": authorization,
+        "order_url": format!("{}/trader/v1/order/buy", server.uri()),
+        "orders": []
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn accepted_credentials_report_valid_with_expiry() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/trader/v1/order/buy"))
+        .and(header(
+            "authorization",
+            format!("Bearer {}", LONG_LIVED_JWT),
+        ))
+        .respond_with(ResponseTemplate::new(405))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let report =
+        auth_check::check_broker(&bidar(&server, LONG_LIVED_JWT), &reqwest::Client::new()).await;
+    assert_eq!(report.state, SessionState::Valid);
+    assert_eq!(report.expires_at, Some(4_102_444_800));
+}
+
+#[tokio::test]
+async fn rejected_or_expired_credentials_report_expired() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(header("authorization", "Bearer stale"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let client = reqwest::Client::new();
+
+    let report = auth_check::check_broker(&bidar(&server, "stale"), &client).await;
+    assert_eq!(report.state, SessionState::Expired);
+
+    // Payload is {"exp":1}: the server may still answer, but the token is dead.
+    let report = auth_check::check_broker(&bidar(&server, "e30.eyJleHAiOjF9.sig"), &client).await;
+    assert_eq!(report.state, SessionState::Expired);
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use common::{broker_server, temp_path};
+use sarkhati::batch_results::{BatchResult, OrderStatus};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::errors::OrderErrorKind;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn two_order_broker(server: &MockServer, sinks: Value) -> CustomBrokersConfig {
+    serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_sinks": sinks,
+            "orders": [
+                { "isin": "IRO1FOLD0001", "tags": ["steel"] },
+                { "isin": "IRO1KHOD0001" }
+            ]
+        }]
+    }))
+    .unwrap()
+}
+
+/// The result of the first batch of a continuous run.
+async fn run_one_batch(config: &mut CustomBrokersConfig) -> BatchResult {
+    let (results, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let options = RunOptions {
+        batch_results: Some(results),
+        ..RunOptions::default()
+    };
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), options));
+    let result = received.recv().await.expect("one batch result");
+    run.abort();
+    result
+}
+
+#[tokio::test]
+async fn each_batch_reports_the_outcome_of_every_order() {
+    let server = broker_server().await;
+    let mut config = two_order_broker(&server, json!([]));
+
+    let result = run_one_batch(&mut config).await;
+
+    assert_eq!(result.broker, "acme");
+    assert_eq!(result.batch, 1);
+    assert_eq!(result.count(OrderStatus::Accepted), 1);
+    assert_eq!(result.count(OrderStatus::Failed), 1);
+    let accepted = result.orders.iter().find(|o| o.order == 1).unwrap();
+    assert_eq!(accepted.tags, ["steel"]);
+    assert!(accepted.error.is_none());
+    let failed = result.orders.iter().find(|o| o.order == 2).unwrap();
+    assert_eq!(failed.error_kind, Some(OrderErrorKind::PriceOutOfRange));
+    assert!(
+        failed
+            .error
+            .as_ref()
+            .unwrap()
+            .contains("price out of range")
+    );
+}
+
+#[tokio::test]
+async fn file_and_sqlite_sinks_store_each_batch() {
+    let server = broker_server().await;
+    let file = temp_path("batches.jsonl");
+    let database = temp_path("batches.sqlite");
+    let mut config = two_order_broker(
+        &server,
+        json!([
+            { "type": "file", "path": file },
+            { "type": "sqlite", "path": database }
+        ]),
+    );
+
+    run_one_batch(&mut config).await;
+
+    let lines = std::fs::read_to_string(&file).unwrap();
+    let batch: Value = serde_json::from_str(lines.trim()).unwrap();
+    assert_eq!(batch["broker"], "acme");
+    assert_eq!(batch["orders"].as_array().unwrap().len(), 2);
+
+    let connection = rusqlite::Connection::open(&database).unwrap();
+    let statuses: Vec<String> = connection
+        .prepare("SELECT status FROM batch_orders ORDER BY order_number")
+        .unwrap()
+        .query_map([], |row| row.get(0))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(statuses, ["accepted", "failed"]);
+
+    let _ = std::fs::remove_file(file);
+    let _ = std::fs::remove_file(database);
+}
+
+#[tokio::test]
+async fn webhook_sinks_receive_each_batch_as_json() {
+    let server = broker_server().await;
+    let hook = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/batches"))
+        .and(body_string_contains("\"broker\":\"acme\""))
+        .respond_with(ResponseTemplate::new(204))
+        .expect(1)
+        .mount(&hook)
+        .await;
+    let mut config = two_order_broker(
+        &server,
+        json!([{ "type": "webhook", "url": format!("{}/batches", hook.uri()) }]),
+    );
+
+    run_one_batch(&mut config).await;
+}
+
+#[tokio::test]
+async fn each_attempt_sends_and_stores_its_correlation_id() {
+    let server = broker_server().await;
+    let mut config = two_order_broker(&server, json!([]));
+    config.brokers[0].settings.correlation_header = Some("X-Request-ID".to_string());
+
+    let result = run_one_batch(&mut config).await;
+
+    let sent: Vec<String> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            request.headers["x-request-id"]
+                .to_str()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    let mut stored: Vec<String> = result
+        .orders
+        .iter()
+        .map(|outcome| outcome.attempt_id.clone().expect("an attempt ID"))
+        .collect();
+    stored.sort();
+    let mut sent = sent[..2].to_vec();
+    sent.sort();
+    assert_eq!(stored, sent);
+    assert_ne!(stored[0], stored[1]);
+}
@@ -0,0 +1,1212 @@
+//! Drives every broker's send and calibration paths against a local mock
+//! server and checks the exact requests they produce.
+
+use reqwest::header::HeaderValue;
+use sarkhati::errors::{OrderErrorKind, order_error_kind};
+use sarkhati::orders::{OrderFields, OrderSide};
+use sarkhati::rate_limiter::RateLimiter;
+use sarkhati::runner::Broker;
+use sarkhati::{
+    bidar, custom_broker, danayan, exir_broker, mobin, mofid, onlineplus, pasargad, rayan_broker,
+    standard_broker, tadbir_broker,
+};
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+const USER_AGENT: &str = "sarkhati-test";
+
+fn config<T: DeserializeOwned>(value: Value) -> T {
+    serde_json::from_value(value).expect("test config should deserialize")
+}
+
+async fn mock_order_endpoint(server: &MockServer, order_path: &str) {
+    Mock::given(method("POST"))
+        .and(path(order_path))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"isSuccessful":true}"#))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+async fn single_request(server: &MockServer) -> Request {
+    let mut requests = server
+        .received_requests()
+        .await
+        .expect("request recording is enabled");
+    assert_eq!(requests.len(), 1, "expected exactly one request");
+    requests.remove(0)
+}
+
+fn header<'a>(request: &'a Request, name: &str) -> &'a str {
+    request
+        .headers
+        .get(name)
+        .unwrap_or_else(|| panic!("missing header {}", name))
+        .to_str()
+        .expect("header should be ASCII")
+}
+
+fn body_json(request: &Request) -> Value {
+    serde_json::from_slice(&request.body).expect("body should be JSON")
+}
+
+fn calibration_settings() -> Value {
+    json!({
+        "enabled": true,
+        "probe_count": 3,
+        "probe_interval_ms": 0,
+        "warmup_probes": 1
+    })
+}
+
+async fn mock_probe_endpoint(server: &MockServer, probes: u64) {
+    Mock::given(method("HEAD"))
+        .and(path("/"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(probes)
+        .mount(server)
+        .await;
+}
+
+fn mofid_config(server: &MockServer, cookie: &str, authorization: &str) -> mofid::MofidConfig {
+    config(json!({
+        "cookie": cookie,
+        "authorization": authorization,
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/apigateway/api/v1/Order/send", server.uri()),
+        "calibration": calibration_settings(),
+        "orders": [{
+            "orderSide": "Buy",
+            "price": 50340,
+            "quantity": 100,
+            "symbolIsin": "IRO1RVND0001",
+            "validityType": 74,
+            "validityDate": null,
+            "orderFrom": "34"
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn mofid_sends_bearer_token_and_order_body() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/apigateway/api/v1/Order/send").await;
+    let broker = mofid_config(&server, "", "abc.def");
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    mofid::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "authorization"), "Bearer abc.def");
+    assert!(request.headers.get("cookie").is_none());
+    assert_eq!(header(&request, "user-agent"), USER_AGENT);
+    assert_eq!(header(&request, "x-appname"), "titan");
+    assert_eq!(header(&request, "origin"), "https://tg.mofidonline.com");
+    assert_eq!(header(&request, "referer"), "https://tg.mofidonline.com/");
+    assert_eq!(header(&request, "content-type"), "application/json");
+    assert_eq!(
+        body_json(&request),
+        json!({
+            "orderSide": "Buy",
+            "price": 50340,
+            "quantity": 100,
+            "symbolIsin": "IRO1RVND0001",
+            "validityType": 74,
+            "validityDate": null,
+            "orderFrom": "34"
+        })
+    );
+}
+
+#[tokio::test]
+async fn mofid_prefers_cookie_over_authorization() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/apigateway/api/v1/Order/send").await;
+    let broker = mofid_config(&server, "session=1", "Bearer abc.def");
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    mofid::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "cookie"), "session=1");
+    assert!(request.headers.get("authorization").is_none());
+}
+
+#[tokio::test]
+async fn mofid_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = mofid_config(&server, "session=1", "");
+
+    let client = reqwest::Client::new();
+    mofid::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn bidar_config(server: &MockServer) -> bidar::BidarConfig {
+    config(json!({
+        "authorization": "token-1",
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/trader/v1/order/buy", server.uri()),
+        "x_user_trace": "trace-1",
+        "calibration": calibration_settings(),
+        "orders": [{
+            "type": "LIMIT",
+            "quantity": "100",
+            "isin": "IRO1RVND0001",
+            "validity": "DAY",
+            "price": "50340"
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn bidar_sends_bearer_token_trace_and_string_fields() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/trader/v1/order/buy").await;
+    let broker = bidar_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    bidar::send_order(&broker, &broker.order_url, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "authorization"), "Bearer token-1");
+    assert_eq!(header(&request, "x-user-trace"), "trace-1");
+    assert_eq!(header(&request, "origin"), "https://bidartrader.ir");
+    assert_eq!(header(&request, "referer"), "https://bidartrader.ir/");
+    assert_eq!(header(&request, "accept"), "application/json");
+    assert_eq!(
+        body_json(&request),
+        json!({
+            "type": "LIMIT",
+            "quantity": "100",
+            "isin": "IRO1RVND0001",
+            "validity": "DAY",
+            "price": "50340"
+        })
+    );
+}
+
+#[tokio::test]
+async fn bidar_sends_sell_orders_to_the_sell_endpoint() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/trader/v1/order/sell").await;
+    let mut broker = bidar_config(&server);
+    broker.orders[0].data.side = Some("sell".to_string());
+
+    let order = &broker.orders[0];
+    let request = broker
+        .prepare(&order.data, broker.order_json(order).unwrap())
+        .unwrap();
+    broker
+        .send_order(&order.data, &request, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert!(body_json(&request).get("side").is_none());
+
+    // An order's own order_url wins over the derived one.
+    broker.orders[0].data.order_url = Some(format!("{}/custom/sell", server.uri()));
+    assert_eq!(
+        broker.order_url_for(&broker.orders[0].data),
+        format!("{}/custom/sell", server.uri())
+    );
+}
+
+#[tokio::test]
+async fn bidar_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = bidar_config(&server);
+
+    let client = reqwest::Client::new();
+    bidar::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn danayan_config(server: &MockServer) -> danayan::DanayanBrokerConfig {
+    config(json!({
+        "name": "danayan",
+        "cookie": "session=2",
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/api/v1/TseOms/RegisterOrder", server.uri()),
+        "origin": "https://trader.danayan.broker",
+        "calibration": calibration_settings(),
+        "orders": [{
+            "orderValidityType": 1,
+            "orderPaymentGateway": 1,
+            "price": 50340,
+            "quantity": 100,
+            "disclosedQuantity": null,
+            "isin": "IRO1RVND0001",
+            "orderSide": 1
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn danayan_sends_cookie_origin_and_order_body() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/api/v1/TseOms/RegisterOrder").await;
+    let broker = danayan_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    danayan::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "cookie"), "session=2");
+    assert_eq!(header(&request, "origin"), "https://trader.danayan.broker");
+    assert_eq!(header(&request, "sec-fetch-site"), "same-site");
+    assert_eq!(
+        body_json(&request),
+        json!({
+            "orderValidityType": 1,
+            "orderPaymentGateway": 1,
+            "price": 50340,
+            "quantity": 100,
+            "disclosedQuantity": null,
+            "isin": "IRO1RVND0001",
+            "orderSide": 1
+        })
+    );
+}
+
+#[tokio::test]
+async fn danayan_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = danayan_config(&server);
+
+    let client = reqwest::Client::new();
+    danayan::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn pasargad_config(server: &MockServer) -> pasargad::PasargadBrokerConfig {
+    config(json!({
+        "name": "pasargad",
+        "cookie": "session=4",
+        "authorization": "abc.def",
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/oms/api/v1/Order/Send", server.uri()),
+        "origin": "https://online.pasargad.example.ir",
+        "calibration": calibration_settings(),
+        "orders": [{
+            "isin": "IRO1RVND0001",
+            "side": 1,
+            "price": "۵۰٬۳۴۰",
+            "quantity": 100,
+            "validityType": 1
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn pasargad_sends_cookie_bearer_token_and_order_body() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/oms/api/v1/Order/Send").await;
+    let broker = pasargad_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    pasargad::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "cookie"), "session=4");
+    assert_eq!(header(&request, "authorization"), "Bearer abc.def");
+    assert_eq!(
+        header(&request, "origin"),
+        "https://online.pasargad.example.ir"
+    );
+    assert_eq!(
+        header(&request, "referer"),
+        "https://online.pasargad.example.ir/"
+    );
+    assert_eq!(
+        body_json(&request),
+        json!({
+            "isin": "IRO1RVND0001",
+            "side": 1,
+            "price": 50340,
+            "quantity": 100,
+            "validityType": 1,
+            "validityDate": null,
+            "disclosedQuantity": null,
+            "financeId": 1
+        })
+    );
+}
+
+#[tokio::test]
+async fn pasargad_sends_only_the_credentials_it_has() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/oms/api/v1/Order/Send").await;
+    let mut broker = pasargad_config(&server);
+    broker.cookie.clear();
+    broker.authorization = "Bearer xyz".to_string();
+    assert!(broker.check_auth().is_ok());
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    pasargad::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "authorization"), "Bearer xyz");
+    assert!(!request.headers.contains_key("cookie"));
+
+    broker.authorization.clear();
+    assert!(broker.check_auth().is_err());
+}
+
+#[test]
+fn broker_entries_need_their_order_url() {
+    let entry = json!({
+        "name": "broker",
+        "host": "https://api.broker.ir",
+        "origin": "https://online.broker.ir",
+        "orders": []
+    });
+    let errors = [
+        serde_json::from_value::<pasargad::PasargadBrokerConfig>(entry.clone()).err(),
+        serde_json::from_value::<mobin::MobinBrokerConfig>(entry.clone()).err(),
+        serde_json::from_value::<onlineplus::OnlinePlusBrokerConfig>(entry.clone()).err(),
+        serde_json::from_value::<tadbir_broker::TadbirBrokerConfig>(entry.clone()).err(),
+        serde_json::from_value::<rayan_broker::RayanBrokerConfig>(entry).err(),
+    ];
+    for error in errors {
+        let error = error.expect("an entry without order_url should be refused");
+        assert!(error.to_string().contains("order_url"), "{}", error);
+    }
+}
+
+#[tokio::test]
+async fn pasargad_example_loads() {
+    let config = pasargad::load_config("config_pasargad.example.json").unwrap();
+    let broker = pasargad::find_broker(&config, "pasargad").unwrap();
+    assert!(broker.settings.target_time.is_some());
+    assert!(broker.settings.calibration.is_some());
+    assert_eq!(broker.orders[0].data.side, 1);
+}
+
+#[tokio::test]
+async fn pasargad_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = pasargad_config(&server);
+
+    let client = reqwest::Client::new();
+    pasargad::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn mobin_config(server: &MockServer) -> mobin::MobinBrokerConfig {
+    config(json!({
+        "name": "mobin",
+        "cookie": "session=5",
+        "xsrf_token": "xsrf-5",
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/api/Order/AddOrder", server.uri()),
+        "origin": "https://online.mobin.example.ir",
+        "calibration": calibration_settings(),
+        "orders": [{
+            "isin": "IRO1RVND0001",
+            "orderSide": "Sell",
+            "price": 50340,
+            "quantity": 100,
+            "validityDate": "1405/01/20"
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn mobin_sends_cookie_xsrf_token_and_order_body() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/api/Order/AddOrder").await;
+    let broker = mobin_config(&server);
+    assert_eq!(broker.orders[0].data.side(), OrderSide::Sell);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    mobin::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "cookie"), "session=5");
+    assert_eq!(header(&request, "x-xsrf-token"), "xsrf-5");
+    assert_eq!(header(&request, "x-requested-with"), "XMLHttpRequest");
+    assert_eq!(
+        header(&request, "referer"),
+        "https://online.mobin.example.ir/"
+    );
+    assert_eq!(
+        body_json(&request),
+        json!({
+            "instrumentIsin": "IRO1RVND0001",
+            "orderSide": "Sell",
+            "price": 50340,
+            "quantity": 100,
+            "validityType": "Day",
+            "validityDate": "1405/01/20",
+            "disclosedQuantity": null,
+            "isShortSell": false
+        })
+    );
+}
+
+#[tokio::test]
+async fn mobin_example_loads() {
+    let config = mobin::load_config("config_mobin.example.json").unwrap();
+    let broker = mobin::find_broker(&config, "mobin").unwrap();
+    assert!(broker.settings.target_time.is_some());
+    assert!(broker.settings.calibration.is_some());
+    assert_eq!(broker.orders[0].data.isin, "IRO1RVND0001");
+}
+
+#[tokio::test]
+async fn mobin_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = mobin_config(&server);
+
+    let client = reqwest::Client::new();
+    mobin::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn onlineplus_config(server: &MockServer, csrf_token: &str) -> onlineplus::OnlinePlusBrokerConfig {
+    config(json!({
+        "name": "sahmashna",
+        "cookie": "ASP.NET_SessionId=7",
+        "csrf_token": csrf_token,
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/Customer/SendOrder", server.uri()),
+        "origin": server.uri(),
+        "csrf_page_url": format!("{}/Home/Default/page-1", server.uri()),
+        "calibration": calibration_settings(),
+        "orders": [{
+            "isin": "IRO1RVND0001",
+            "Side": "Buy",
+            "Price": 50340,
+            "Quantity": 100
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn onlineplus_reads_the_csrf_token_from_the_page_and_sends_it() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/Home/Default/page-1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Set-Cookie", "__RequestVerificationToken_L2=af; Path=/")
+                .set_body_string(
+                    r#"<form><input name="__RequestVerificationToken" type="hidden" value="csrf-7" /></form>"#,
+                ),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    mock_order_endpoint(&server, "/Customer/SendOrder").await;
+    let broker = onlineplus_config(&server, "");
+
+    broker.ensure_session().await.unwrap();
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    onlineplus::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    let request = &requests[1];
+    assert_eq!(
+        header(request, "cookie"),
+        "ASP.NET_SessionId=7; __RequestVerificationToken_L2=af"
+    );
+    assert_eq!(header(request, "__requestverificationtoken"), "csrf-7");
+    assert_eq!(header(request, "x-requested-with"), "XMLHttpRequest");
+    assert_eq!(
+        body_json(request),
+        json!({
+            "Isin": "IRO1RVND0001",
+            "Side": "Buy",
+            "Price": 50340,
+            "Quantity": 100,
+            "Validity": "Day",
+            "ValidityDate": null,
+            "MaxShow": 0,
+            "MinQuantity": 0,
+            "UseCredit": false
+        })
+    );
+}
+
+#[test]
+fn onlineplus_finds_the_token_whatever_the_attribute_order() {
+    assert_eq!(
+        onlineplus::csrf_token_in(
+            r#"<input type="hidden" value="abc" name="__RequestVerificationToken">"#
+        )
+        .as_deref(),
+        Some("abc")
+    );
+    assert_eq!(onlineplus::csrf_token_in("<html></html>"), None);
+}
+
+#[tokio::test]
+async fn onlineplus_example_loads() {
+    let config = onlineplus::load_config("config_onlineplus.example.json").unwrap();
+    let broker = onlineplus::find_broker(&config, "sahmashna").unwrap();
+    assert!(broker.settings.calibration.is_some());
+    assert_eq!(broker.orders[0].data.isin, "IRO1RVND0001");
+}
+
+#[tokio::test]
+async fn onlineplus_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = onlineplus_config(&server, "csrf-7");
+
+    let client = reqwest::Client::new();
+    onlineplus::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn tadbir_config(server: &MockServer) -> tadbir_broker::TadbirBrokerConfig {
+    let mut broker: tadbir_broker::TadbirBrokerConfig = config(json!({
+        "name": "tadbir",
+        "host": format!("{}/", server.uri()),
+        "order_url": format!("{}/api/order", server.uri()),
+        "token": "abc.def",
+        "api_key": "key-6",
+        "user_agent": USER_AGENT,
+        "calibration": calibration_settings(),
+        "orders": [{
+            "isin": "IRO1RVND0001",
+            "orderSide": 1,
+            "price": 50340,
+            "quantity": 100
+        }]
+    }));
+    broker.fill_from_host();
+    broker
+}
+
+#[tokio::test]
+async fn tadbir_sends_token_api_key_and_order_body_to_the_host() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/api/order").await;
+    let broker = tadbir_config(&server);
+    assert_eq!(broker.origin, server.uri());
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    tadbir_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "authorization"), "Bearer abc.def");
+    assert_eq!(header(&request, "x-api-key"), "key-6");
+    assert_eq!(header(&request, "origin"), server.uri());
+    assert_eq!(
+        body_json(&request),
+        json!({
+            "isin": "IRO1RVND0001",
+            "orderSide": 1,
+            "price": 50340,
+            "quantity": 100,
+            "validityType": 1,
+            "validityDate": null,
+            "maxShow": 0,
+            "minimumQuantity": 0,
+            "financeType": 1
+        })
+    );
+}
+
+#[tokio::test]
+async fn tadbir_example_lists_brokers_by_host() {
+    let config = tadbir_broker::load_config("config_tadbir.example.json").unwrap();
+    assert_eq!(config.brokers.len(), 2);
+    let broker = tadbir_broker::find_broker(&config, "tadbir").unwrap();
+    assert_eq!(broker.origin, "https://online.example-broker.ir");
+    assert!(broker.settings.calibration.is_some());
+    let other = tadbir_broker::find_broker(&config, "another-tadbir-broker").unwrap();
+    assert_eq!(other.origin, "https://api.another-broker.ir");
+    assert!(other.can_negotiate());
+}
+
+#[tokio::test]
+async fn tadbir_calibration_probes_the_host() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = tadbir_config(&server);
+
+    let client = reqwest::Client::new();
+    tadbir_broker::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn rayan_config(server: &MockServer) -> rayan_broker::RayanBrokerConfig {
+    let mut broker: rayan_broker::RayanBrokerConfig = config(json!({
+        "name": "rayan",
+        "host": server.uri(),
+        "order_url": format!("{}/api/order", server.uri()),
+        "token": "Bearer tok-7",
+        "headers": { "X-App-Version": "4.2.0" },
+        "user_agent": USER_AGENT,
+        "calibration": calibration_settings(),
+        "orders": [{
+            "isin": "IRO1RVND0001",
+            "side": 2,
+            "price": 50340,
+            "volume": 100
+        }]
+    }));
+    broker.fill_from_host();
+    broker
+}
+
+#[tokio::test]
+async fn rayan_sends_token_family_and_host_headers() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/api/order").await;
+    let broker = rayan_config(&server);
+    assert_eq!(broker.orders[0].data.side(), OrderSide::Sell);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    rayan_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "authorization"), "Bearer tok-7");
+    assert_eq!(header(&request, "x-requested-with"), "XMLHttpRequest");
+    assert_eq!(header(&request, "x-app-version"), "4.2.0");
+    assert_eq!(
+        body_json(&request),
+        json!({
+            "symbolIsin": "IRO1RVND0001",
+            "side": 2,
+            "price": 50340,
+            "volume": 100,
+            "validityType": 1,
+            "validityDate": null,
+            "disclosedVolume": 0,
+            "creditSource": 0
+        })
+    );
+}
+
+#[tokio::test]
+async fn rayan_example_lists_brokers_by_host() {
+    let config = rayan_broker::load_config("config_rayan.example.json").unwrap();
+    assert_eq!(config.brokers.len(), 2);
+    let broker = rayan_broker::find_broker(&config, "rayan").unwrap();
+    assert_eq!(broker.origin, "https://online.example-rayan-broker.ir");
+    let other = rayan_broker::find_broker(&config, "another-rayan-broker").unwrap();
+    assert_eq!(other.origin, "https://api.another-rayan-broker.ir");
+    assert!(other.login.is_some());
+}
+
+#[tokio::test]
+async fn rayan_calibration_probes_the_host() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = rayan_config(&server);
+
+    let client = reqwest::Client::new();
+    rayan_broker::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn standard_config(server: &MockServer) -> standard_broker::StandardBrokerConfig {
+    config(json!({
+        "name": "bmi",
+        "cookie": "session=3",
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/Web/V1/Order/Post", server.uri()),
+        "origin": "https://online.bmibourse.ir",
+        "referer": "https://online.bmibourse.ir/",
+        "calibration": calibration_settings(),
+        "orders": [{
+            "IsSymbolCautionAgreement": false,
+            "CautionAgreementSelected": false,
+            "IsSymbolSepahAgreement": false,
+            "SepahAgreementSelected": false,
+            "orderCount": 100,
+            "orderPrice": 50340,
+            "FinancialProviderId": 1,
+            "minimumQuantity": 0,
+            "maxShow": 0,
+            "orderId": 0,
+            "isin": "IRO1RVND0001",
+            "orderSide": 65,
+            "orderValidity": 74,
+            "orderValiditydate": null,
+            "shortSellIsEnabled": false,
+            "shortSellIncentivePercent": 0
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn standard_sends_cookie_origin_referer_and_order_body() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/Web/V1/Order/Post").await;
+    let broker = standard_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    standard_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "cookie"), "session=3");
+    assert_eq!(header(&request, "origin"), "https://online.bmibourse.ir");
+    assert_eq!(header(&request, "referer"), "https://online.bmibourse.ir/");
+    assert_eq!(header(&request, "x-requested-with"), "XMLHttpRequest");
+    assert_eq!(header(&request, "accept"), "*/*");
+    let body = body_json(&request);
+    assert_eq!(body["orderCount"], 100);
+    assert_eq!(body["orderPrice"], 50340);
+    assert_eq!(body["isin"], "IRO1RVND0001");
+    assert_eq!(body["orderSide"], 65);
+}
+
+#[tokio::test]
+async fn ordibehesht_example_runs_on_the_standard_sender() {
+    let config = standard_broker::load_config("config_standard.example.json").unwrap();
+    let mut broker = standard_broker::find_broker(&config, "ordibehesht")
+        .unwrap()
+        .clone();
+    assert!(broker.settings.target_time.is_some());
+    assert!(broker.settings.calibration.is_some());
+    assert_eq!(broker.settings.batch_delay_ms, 100);
+
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/Web/V1/Order/Post").await;
+    broker.order_url = format!("{}/Web/V1/Order/Post", server.uri());
+    let order = &broker.orders[0];
+    let request = broker
+        .prepare(&order.data, broker.order_json(order).unwrap())
+        .unwrap();
+    broker
+        .send_order(
+            &order.data,
+            &request,
+            false,
+            false,
+            Some(&RateLimiter::new(broker.settings.batch_delay_ms)),
+        )
+        .await
+        .unwrap();
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "origin"), "https://online.oibourse.ir");
+}
+
+#[tokio::test]
+async fn standard_sends_form_encoded_orders() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/Web/V1/Order/Post").await;
+    let mut broker = standard_config(&server);
+    broker.body_encoding = standard_broker::BodyEncoding::Form;
+    broker.orders[0].data.isin = "وبملت".to_string();
+
+    let body = broker.order_json(&broker.orders[0]).unwrap();
+    standard_broker::send_order(&broker, &body, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(
+        header(&request, "content-type"),
+        "application/x-www-form-urlencoded; charset=UTF-8"
+    );
+    let body = String::from_utf8(request.body).unwrap();
+    assert!(body.starts_with("IsSymbolCautionAgreement=false&"));
+    assert!(body.contains("&orderCount=100&orderPrice=50340&"));
+    assert!(body.contains("&isin=%D9%88%D8%A8%D9%85%D9%84%D8%AA&"));
+    assert!(body.contains("&orderValiditydate=&"));
+
+    let encoding = standard_broker::BodyEncoding::Multipart;
+    let body = encoding.encode(&json!({ "isin": "وبملت" })).unwrap();
+    assert!(
+        encoding
+            .content_type()
+            .ends_with("boundary=----SarkhatiFormBoundary7MA4YWxkTrZu0gW")
+    );
+    assert_eq!(
+        body,
+        "------SarkhatiFormBoundary7MA4YWxkTrZu0gW\r\nContent-Disposition: form-data; name=\"isin\"\r\n\r\nوبملت\r\n------SarkhatiFormBoundary7MA4YWxkTrZu0gW--\r\n"
+    );
+}
+
+#[tokio::test]
+async fn standard_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = standard_config(&server);
+
+    let client = reqwest::Client::new();
+    standard_broker::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn exir_config(server: &MockServer) -> exir_broker::ExirBrokerConfig {
+    config(json!({
+        "name": "alvand",
+        "cookie": "session=4",
+        "nt": "031234567890123456789",
+        "user_agent": USER_AGENT,
+        "order_url": format!("{}/api/v1/order", server.uri()),
+        "origin": "https://arzeshafarin.exirbroker.com",
+        "referer": "https://arzeshafarin.exirbroker.com/exir/mainNew",
+        "calibration": calibration_settings(),
+        "orders": [{
+            "insMaxLcode": "IRO1RVND0001",
+            "bankAccountId": -1,
+            "side": "SIDE_BUY",
+            "orderType": "ORDER_TYPE_LIMIT",
+            "quantity": 100,
+            "price": 50340,
+            "validityType": "VALIDITY_TYPE_DAY",
+            "validityDate": "",
+            "coreType": "c",
+            "hasUnderCautionAgreement": false,
+            "dividedOrder": false
+        }]
+    }))
+}
+
+#[tokio::test]
+async fn exir_sends_x_app_n_cookie_and_order_body() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/api/v1/order").await;
+    let broker = exir_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    exir_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "cookie"), "session=4");
+    assert_eq!(
+        header(&request, "origin"),
+        "https://arzeshafarin.exirbroker.com"
+    );
+
+    // X-App-N is "<first>.<second>" where second = utc_seconds * sum(path chars).
+    let x_app_n = header(&request, "x-app-n");
+    let (first, second) = x_app_n
+        .split_once('.')
+        .unwrap_or_else(|| panic!("X-App-N '{}' has no '.'", x_app_n));
+    assert!(first.parse::<i64>().is_ok(), "bad X-App-N '{}'", x_app_n);
+    let second: i64 = second.parse().unwrap();
+    let path_sum: i64 = "/api/v1/order".chars().map(|c| c as i64).sum();
+    assert_eq!(second % path_sum, 0, "bad X-App-N '{}'", x_app_n);
+    assert!(second / path_sum < 86_400);
+
+    let body = body_json(&request);
+    assert_eq!(body["insMaxLcode"], "IRO1RVND0001");
+    assert_eq!(body["side"], "SIDE_BUY");
+    assert_eq!(body["quantity"], 100);
+}
+
+#[tokio::test]
+async fn exir_prepared_request_gets_fresh_x_app_n_and_cookie() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/api/v1/order").await;
+    let broker = exir_config(&server);
+
+    let order = &broker.orders[0];
+    let mut request = broker
+        .prepare(&order.data, broker.order_json(order).unwrap())
+        .unwrap();
+    request
+        .headers
+        .insert("x-app-n", HeaderValue::from_static("stale"));
+    request
+        .headers
+        .insert("cookie", HeaderValue::from_static("session=old"));
+    broker
+        .send_order(&order.data, &request, false, false, None)
+        .await
+        .unwrap();
+
+    let sent = single_request(&server).await;
+    assert_eq!(header(&sent, "cookie"), "session=4");
+    assert_ne!(header(&sent, "x-app-n"), "stale");
+    assert_eq!(sent.body, request.body);
+}
+
+#[test]
+fn exir_preset_fills_in_the_urls() {
+    let mut broker: exir_broker::ExirBrokerConfig = config(json!({
+        "name": "alvand",
+        "preset": "arzeshafarin",
+        "referer": "https://arzeshafarin.exirbroker.com/exir/main",
+        "orders": []
+    }));
+    broker.apply_preset().unwrap();
+    assert_eq!(
+        broker.order_url,
+        "https://arzeshafarin.exirbroker.com/api/v1/order"
+    );
+    assert_eq!(broker.origin, "https://arzeshafarin.exirbroker.com");
+    assert_eq!(
+        broker.referer,
+        "https://arzeshafarin.exirbroker.com/exir/main"
+    );
+
+    broker.preset = Some("nowhere".to_string());
+    let error = format!("{:#}", broker.apply_preset().unwrap_err());
+    assert!(error.contains("known presets: arzeshafarin"), "{}", error);
+
+    let mut broker: exir_broker::ExirBrokerConfig = config(json!({ "name": "bare", "orders": [] }));
+    assert!(broker.apply_preset().is_err());
+}
+
+#[test]
+fn exir_example_loads_with_a_preset() {
+    let config = exir_broker::load_config("config_exir.example.json").unwrap();
+    let broker = exir_broker::find_broker(&config, "alvand").unwrap();
+    assert_eq!(broker.origin, "https://arzeshafarin.exirbroker.com");
+}
+
+#[test]
+fn exir_x_app_n() {
+    assert_eq!(
+        exir_broker::x_app_n_at("031234567890123456789", "/a", 100),
+        "337766400.14400"
+    );
+
+    // The query string is summed with the path.
+    let x_app_n = exir_broker::calculate_x_app_n(
+        "031234567890123456789",
+        "https://x.ir/api/v1/order?check=1",
+    );
+    let second: i64 = x_app_n.split_once('.').unwrap().1.parse().unwrap();
+    let path_sum: i64 = "/api/v1/order?check=1".chars().map(|c| c as i64).sum();
+    assert_eq!(second % path_sum, 0, "bad X-App-N '{}'", x_app_n);
+    assert!(second / path_sum < 86_400);
+}
+
+#[tokio::test]
+async fn exir_verify_x_app_n_needs_a_2xx_from_auth_check_url() {
+    let server = MockServer::start().await;
+    let mut broker = exir_config(&server);
+    let client = reqwest::Client::new();
+    let error = exir_broker::verify_x_app_n(&broker, &client)
+        .await
+        .err()
+        .unwrap();
+    assert!(error.to_string().contains("auth_check_url"));
+
+    broker.settings.auth_check_url = Some(format!("{}/api/v1/portfolio", server.uri()));
+    Mock::given(method("GET"))
+        .and(path("/api/v1/portfolio"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let check = exir_broker::verify_x_app_n(&broker, &client).await.unwrap();
+    assert!(check.accepted());
+
+    let requests = server.received_requests().await.unwrap();
+    assert_eq!(header(&requests[0], "cookie"), "session=4");
+    assert!(requests[0].headers.get("x-app-n").is_some());
+
+    for status in [403, 404, 405] {
+        server.reset().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(status))
+            .mount(&server)
+            .await;
+        let check = exir_broker::verify_x_app_n(&broker, &client).await.unwrap();
+        assert!(!check.accepted(), "{}", status);
+    }
+}
+
+#[tokio::test]
+async fn exir_calibration_probes_with_nt_header() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .and(path("/"))
+        .and(wiremock::matchers::header("nt", "031234567890123456789"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(3)
+        .mount(&server)
+        .await;
+    let broker = exir_config(&server);
+
+    let client = reqwest::Client::new();
+    exir_broker::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+fn custom_config(server: &MockServer) -> custom_broker::CustomBrokerConfig {
+    config(json!({
+        "name": "acme",
+        "order_url": format!("{}/orders", server.uri()),
+        "user_agent": USER_AGENT,
+        "headers": { "X-Api-Key": "key-1" },
+        "body_template": {
+            "symbol": "{{isin}}",
+            "qty": "{{quantity}}",
+            "note": "buy {{quantity}} @ {{price}}"
+        },
+        "calibration": calibration_settings(),
+        "orders": [{ "isin": "IRO1RVND0001", "quantity": 100, "price": 50340 }]
+    }))
+}
+
+#[tokio::test]
+async fn custom_broker_sends_configured_headers_and_rendered_template() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/orders").await;
+    let broker = custom_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    custom_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    let request = single_request(&server).await;
+    assert_eq!(header(&request, "x-api-key"), "key-1");
+    assert_eq!(header(&request, "user-agent"), USER_AGENT);
+    assert_eq!(header(&request, "content-type"), "application/json");
+    assert_eq!(
+        body_json(&request),
+        json!({ "symbol": "IRO1RVND0001", "qty": 100, "note": "buy 100 @ 50340" })
+    );
+}
+
+#[tokio::test]
+async fn custom_broker_calibration_probes_the_origin() {
+    let server = MockServer::start().await;
+    mock_probe_endpoint(&server, 3).await;
+    let broker = custom_config(&server);
+
+    let client = reqwest::Client::new();
+    custom_broker::run_calibration(&broker, &client, &RateLimiter::new(0))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn failed_status_is_reported_as_error() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(400).set_body_string(r#"{"message":"م"}"#))
+        .mount(&server)
+        .await;
+    let broker = danayan_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    let error = danayan::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("400"));
+    assert!(error.to_string().contains("م"));
+    assert_eq!(order_error_kind(&error), Some(OrderErrorKind::Unknown));
+}
+
+#[tokio::test]
+async fn rejection_message_is_classified() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(400)
+                .set_body_string(r#"{"message":"\u0642\u06cc\u0645\u062a \u062e\u0627\u0631\u062c \u0627\u0632 \u0645\u062d\u062f\u0648\u062f\u0647 \u0645\u062c\u0627\u0632"}"#),
+        )
+        .mount(&server)
+        .await;
+    let broker = standard_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    let error = standard_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        order_error_kind(&error),
+        Some(OrderErrorKind::PriceOutOfRange)
+    );
+}
+
+#[tokio::test]
+async fn curl_only_test_mode_sends_nothing() {
+    let server = MockServer::start().await;
+    let broker = danayan_config(&server);
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    danayan::send_order(&broker, &order_json, true, true, None)
+        .await
+        .unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    assert!(requests.is_empty());
+}
+
+#[tokio::test]
+async fn success_rules_reject_error_payload_with_200_status() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_string(r#"{"isSuccessful":false,"message":"duplicate order"}"#),
+        )
+        .mount(&server)
+        .await;
+    let mut broker = danayan_config(&server);
+    broker.settings.success_rules =
+        config(json!([{ "json_pointer": "/isSuccessful", "equals": true }]));
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    let error = danayan::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        order_error_kind(&error),
+        Some(OrderErrorKind::DuplicateOrder)
+    );
+}
+
+#[tokio::test]
+async fn success_rules_accept_matching_body() {
+    let server = MockServer::start().await;
+    mock_order_endpoint(&server, "/api/v1/TseOms/RegisterOrder").await;
+    let mut broker = danayan_config(&server);
+    broker.settings.success_rules = config(json!([
+        { "json_pointer": "/isSuccessful", "equals": true },
+        { "body_regex": "\"isSuccessful\"\\s*:\\s*true" }
+    ]));
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    danayan::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,138 @@
+use reqwest::StatusCode;
+use sarkhati::calibration::{self, CalibrationConfig, DelayModel, OnUnstable};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::rate_limiter::RateLimiter;
+use sarkhati::runner::{Broker, RunOptions, run_broker};
+use sarkhati::standard_broker::StandardBrokerConfig;
+use serde_json::json;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn config(on_unstable: OnUnstable) -> CalibrationConfig {
+    CalibrationConfig {
+        probe_count: 5,
+        probe_interval_ms: 0,
+        warmup_probes: 1,
+        max_rtt_stddev_ms: Some(10.0),
+        on_unstable,
+        max_extra_probes: 3,
+        ..CalibrationConfig::default()
+    }
+}
+
+/// Calibrate against probes answering after `rtts_ms` in turn, returning the
+/// estimate and the probes sent.
+async fn calibrate(config: &CalibrationConfig, rtts_ms: &[u64]) -> (anyhow::Result<u64>, usize) {
+    let sent = AtomicUsize::new(0);
+    let result = calibration::run_calibration("[test]", config, &RateLimiter::new(0), || {
+        let rtt_ms = rtts_ms[sent.fetch_add(1, Ordering::Relaxed)];
+        async move { Ok((rtt_ms, u128::from(rtt_ms) * 1000, StatusCode::OK)) }
+    })
+    .await;
+    (
+        result.map(|summary| summary.estimated_delay_ms),
+        sent.load(Ordering::Relaxed),
+    )
+}
+
+#[tokio::test]
+async fn abort_stops_as_soon_as_the_rtts_spread() {
+    let (result, sent) = calibrate(&config(OnUnstable::Abort), &[90, 40, 42, 95, 41]).await;
+
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("max_rtt_stddev_ms"), "{}", error);
+    assert_eq!(sent, 4);
+}
+
+#[tokio::test]
+async fn extend_probes_until_the_latest_rtts_settle() {
+    let rtts_ms = [90, 40, 95, 42, 41, 40, 41, 42, 40];
+    let (result, sent) = calibrate(&config(OnUnstable::Extend), &rtts_ms).await;
+
+    assert_eq!(sent, 7);
+    assert_eq!(result.unwrap(), 41);
+}
+
+#[tokio::test]
+async fn extend_gives_up_after_max_extra_probes() {
+    let rtts_ms = [90, 40, 95, 41, 96, 40, 97, 41];
+    let (result, sent) = calibrate(&config(OnUnstable::Extend), &rtts_ms).await;
+
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("after 3 extra probes"), "{}", error);
+    assert_eq!(sent, 8);
+}
+
+#[test]
+fn std_dev_of_samples() {
+    assert_eq!(calibration::std_dev(&[40]), 0.0);
+    assert_eq!(calibration::std_dev(&[40, 40, 40]), 0.0);
+    assert_eq!(calibration::std_dev(&[2, 4, 4, 4, 5, 5, 7, 9]), 2.0);
+}
+
+#[test]
+fn every_broker_applies_its_delay_model() {
+    let broker = |delay_model: Option<&str>| {
+        let mut config = json!({
+            "name": "bmi",
+            "order_url": "https://example.com/order",
+            "origin": "https://example.com",
+            "referer": "https://example.com/",
+            "orders": []
+        });
+        if let Some(delay_model) = delay_model {
+            config["delay_model"] = json!(delay_model);
+        }
+        serde_json::from_value::<StandardBrokerConfig>(config).unwrap()
+    };
+    assert_eq!(broker(None).adjust_delay_estimate(41), 41);
+    assert_eq!(broker(Some("rtt")).adjust_delay_estimate(41), 41);
+    assert_eq!(broker(Some("half_rtt")).adjust_delay_estimate(41), 21);
+    assert_eq!(DelayModel::HalfRtt.apply(40), 20);
+}
+
+#[tokio::test]
+async fn connection_warmup_probes_right_before_the_scheduled_send() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let target_time = chrono::Utc::now().with_timezone(&chrono_tz::Asia::Tehran)
+        + chrono::Duration::milliseconds(2_000);
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "qty": "{{quantity}}" },
+            "batch_delay_ms": 100,
+            "target_time": target_time.format("%H:%M:%S%.3f").to_string(),
+            "holidays": { "on_holiday": "ignore" },
+            "connection_warmup": { "window_ms": 1200, "interval_ms": 400 },
+            "orders": [{ "quantity": 1 }]
+        }]
+    }))
+    .unwrap();
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+
+    let methods = loop {
+        let requests = server.received_requests().await.unwrap();
+        if requests
+            .iter()
+            .any(|request| request.method.as_str() == "POST")
+        {
+            break requests
+                .iter()
+                .map(|request| request.method.to_string())
+                .collect::<Vec<_>>();
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    };
+    run.abort();
+    assert_eq!(methods, ["HEAD", "HEAD", "HEAD", "POST"]);
+}
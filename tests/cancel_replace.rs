@@ -0,0 +1,195 @@
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use wiremock::matchers::{method, path, path_regex};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+/// Accepts every order with the next order ID.
+#[derive(Default)]
+struct NumberedOrders {
+    last_id: AtomicU64,
+}
+
+impl Respond for NumberedOrders {
+    fn respond(&self, _: &Request) -> ResponseTemplate {
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        ResponseTemplate::new(200).set_body_json(json!({ "data": { "orderId": id } }))
+    }
+}
+
+/// A broker whose orders are still open, except the ones in `filled`.
+async fn broker_server(filled: &[u64]) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(NumberedOrders::default())
+        .mount(&server)
+        .await;
+    for id in filled {
+        Mock::given(method("GET"))
+            .and(path(format!("/orders/{}", id)))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({ "data": { "state": "Executed" } })),
+            )
+            .with_priority(1)
+            .mount(&server)
+            .await;
+    }
+    Mock::given(method("GET"))
+        .and(path_regex("^/orders/[0-9]+$"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "data": { "state": "Open" } })),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("DELETE"))
+        .and(path_regex("^/orders/[0-9]+$"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    server
+}
+
+fn replacing_broker(server: &MockServer, extra: Value) -> CustomBrokersConfig {
+    let mut broker = json!({
+        "name": "acme",
+        "order_url": format!("{}/orders", server.uri()),
+        "body_template": { "symbol": "{{isin}}", "price": "{{price}}" },
+        "cancel_replace": {
+            "order_id": "/data/orderId",
+            "status": {
+                "url": format!("{}/orders/{{order_id}}", server.uri()),
+                "state": "/data/state",
+                "filled": ["Executed"],
+                "poll_interval_ms": 20
+            },
+            "cancel": { "url": format!("{}/orders/{{order_id}}", server.uri()) },
+            "window_ms": 100,
+            "price_step": 10,
+            "max_replacements": 2
+        },
+        "orders": [{ "isin": "IRO1FOLD0001", "price": 1000 }]
+    });
+    broker
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    serde_json::from_value(json!({ "brokers": [broker] })).unwrap()
+}
+
+/// Send the test order and wait for the follow-ups it leads to.
+async fn send_test_order(config: &mut CustomBrokersConfig) {
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        run_broker(config.brokers.remove(0), options),
+    )
+    .await
+    .expect("the run ends once the order is filled or left open")
+    .unwrap();
+}
+
+/// Prices of the orders `server` received, in order.
+async fn sent_prices(server: &MockServer) -> Vec<u64> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == "POST")
+        .map(|request| {
+            request.body_json::<Value>().unwrap()["price"]
+                .as_u64()
+                .unwrap()
+        })
+        .collect()
+}
+
+/// Paths of the cancel requests `server` received, in order.
+async fn cancelled(server: &MockServer) -> Vec<String> {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == "DELETE")
+        .map(|request| request.url.path().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn an_unfilled_order_is_cancelled_and_sent_a_step_further() {
+    let server = broker_server(&[2]).await;
+    let mut config = replacing_broker(&server, json!({}));
+
+    send_test_order(&mut config).await;
+
+    assert_eq!(sent_prices(&server).await, [1000, 1010]);
+    assert_eq!(cancelled(&server).await, ["/orders/1"]);
+}
+
+#[tokio::test]
+async fn continuous_runs_send_an_order_once_and_leave_the_rest_to_replacements() {
+    let server = broker_server(&[2]).await;
+    let mut config = replacing_broker(&server, json!({ "batch_delay_ms": 10 }));
+
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the run ends once the order is filled")
+    .unwrap();
+
+    assert_eq!(sent_prices(&server).await, [1000, 1010]);
+    assert_eq!(cancelled(&server).await, ["/orders/1"]);
+}
+
+#[tokio::test]
+async fn an_order_is_left_open_after_max_replacements() {
+    let server = broker_server(&[]).await;
+    let mut config = replacing_broker(&server, json!({}));
+
+    send_test_order(&mut config).await;
+
+    assert_eq!(sent_prices(&server).await, [1000, 1010, 1020]);
+    assert_eq!(cancelled(&server).await, ["/orders/1", "/orders/2"]);
+}
+
+#[tokio::test]
+async fn a_filled_order_is_not_replaced() {
+    let server = broker_server(&[1]).await;
+    let mut config = replacing_broker(&server, json!({}));
+
+    send_test_order(&mut config).await;
+
+    assert_eq!(sent_prices(&server).await, [1000]);
+    assert!(cancelled(&server).await.is_empty());
+}
+
+#[tokio::test]
+async fn cancel_replace_refuses_repeated_orders() {
+    let server = broker_server(&[]).await;
+    for extra in [
+        json!({ "batch_repeat": 2 }),
+        json!({ "orders": [{ "isin": "IRO1FOLD0001", "price": 1000, "repeat": 2 }] }),
+    ] {
+        let mut config = replacing_broker(&server, extra);
+
+        let error = run_broker(config.brokers.remove(0), RunOptions::default())
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("cannot use cancel_replace"),
+            "{:#}",
+            error
+        );
+    }
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
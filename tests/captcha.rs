@@ -0,0 +1,56 @@
+use sarkhati::captcha::{Captcha, CaptchaConfig, CaptchaSolver, ConfiguredSolver};
+use serde_json::json;
+use wiremock::matchers::{body_json, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn http_solver_posts_base64_image_and_reads_answer() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/solve"))
+        .and(body_json(json!({ "image": "AQID", "api_key": "k" })))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "result": { "text": " 4821 " } })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let config: CaptchaConfig = serde_json::from_value(json!({
+        "type": "http",
+        "url": format!("{}/solve", server.uri()),
+        "api_key": "k",
+        "answer_pointer": "/result/text"
+    }))
+    .unwrap();
+    let solver = ConfiguredSolver::from_config(&config).unwrap();
+    let captcha = Captcha {
+        image: vec![1, 2, 3],
+        extension: "png".to_string(),
+    };
+
+    assert_eq!(solver.solve(&captcha).await.unwrap(), "4821");
+}
+
+#[tokio::test]
+async fn http_solver_reports_missing_answer() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "error": "busy" })))
+        .mount(&server)
+        .await;
+
+    let config: CaptchaConfig = serde_json::from_value(json!({
+        "type": "http",
+        "url": server.uri()
+    }))
+    .unwrap();
+    let solver = ConfiguredSolver::from_config(&config).unwrap();
+    let captcha = Captcha {
+        image: vec![0],
+        extension: "png".to_string(),
+    };
+
+    let error = solver.solve(&captcha).await.unwrap_err();
+    assert!(error.to_string().contains("/text"));
+}
@@ -0,0 +1,57 @@
+use sarkhati::capture;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn each_order_and_its_response_are_captured_to_a_file() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(
+            ResponseTemplate::new(400)
+                .insert_header("X-Trace", "t-42")
+                .set_body_string("price out of range"),
+        )
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "headers": { "Cookie": "session=secret" },
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_sinks": [],
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap();
+    let dir = std::env::temp_dir().join(format!("sarkhati-{}-capture", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    capture::set_dir(dir.to_str().unwrap()).unwrap();
+
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+    assert!(run_broker(config.brokers.remove(0), options).await.is_err());
+
+    let files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(files.len(), 1);
+    let name = files[0].file_name().unwrap().to_str().unwrap();
+    assert!(name.contains("_acme_"), "{}", name);
+    let captured = std::fs::read_to_string(&files[0]).unwrap();
+    assert!(captured.contains(&format!("POST {}/orders", server.uri())));
+    assert!(captured.contains(r#"{"symbol":"IRO1FOLD0001"}"#));
+    assert!(captured.contains("cookie: <redacted>"));
+    assert!(!captured.contains("secret"));
+    assert!(captured.contains("HTTP 400 Bad Request"));
+    assert!(captured.contains("x-trace: t-42"));
+    assert!(captured.ends_with("price out of range\n"));
+    let _ = std::fs::remove_dir_all(dir);
+}
@@ -0,0 +1,104 @@
+use reqwest::StatusCode;
+use sarkhati::circuit_breaker::{CircuitBreaker, CircuitBreakerSettings, is_hard_failure};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::errors::OrderError;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::json;
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn rejected(status: StatusCode) -> anyhow::Result<()> {
+    Err(OrderError::new(status, String::new()).into())
+}
+
+fn breaker(cool_down_ms: u64) -> CircuitBreaker {
+    CircuitBreaker::new(
+        "test",
+        &CircuitBreakerSettings {
+            failure_threshold: 2,
+            cool_down_ms,
+        },
+    )
+}
+
+#[test]
+fn only_server_errors_and_unreachable_endpoints_are_hard_failures() {
+    assert!(is_hard_failure(
+        &rejected(StatusCode::BAD_GATEWAY).unwrap_err()
+    ));
+    assert!(!is_hard_failure(
+        &rejected(StatusCode::BAD_REQUEST).unwrap_err()
+    ));
+    assert!(!is_hard_failure(&anyhow::anyhow!("no price yet")));
+}
+
+#[test]
+fn consecutive_hard_failures_open_the_circuit_and_a_probe_closes_it() {
+    let circuit = breaker(0);
+    circuit.record(&rejected(StatusCode::SERVICE_UNAVAILABLE));
+    circuit.record(&rejected(StatusCode::BAD_REQUEST));
+    circuit.record(&rejected(StatusCode::SERVICE_UNAVAILABLE));
+    assert!(!circuit.is_open(), "a rejection resets the count");
+
+    circuit.record(&rejected(StatusCode::SERVICE_UNAVAILABLE));
+    assert!(circuit.is_open());
+
+    assert!(circuit.admit(), "the probe goes out after the cool-down");
+    assert!(!circuit.admit(), "only one probe at a time");
+    circuit.record(&Ok(()));
+    assert!(!circuit.is_open());
+    assert!(circuit.admit());
+}
+
+#[test]
+fn a_failed_probe_reopens_the_circuit() {
+    let circuit = breaker(60_000);
+    circuit.record(&rejected(StatusCode::INTERNAL_SERVER_ERROR));
+    circuit.record(&rejected(StatusCode::INTERNAL_SERVER_ERROR));
+    assert!(!circuit.admit(), "still cooling down");
+
+    let circuit = breaker(0);
+    circuit.record(&rejected(StatusCode::INTERNAL_SERVER_ERROR));
+    circuit.record(&rejected(StatusCode::INTERNAL_SERVER_ERROR));
+    assert!(circuit.admit());
+    circuit.record(&rejected(StatusCode::INTERNAL_SERVER_ERROR));
+    assert!(circuit.is_open());
+
+    circuit.release();
+    assert!(circuit.admit(), "a probe that sent nothing is handed back");
+    circuit.release();
+    assert!(circuit.admit());
+}
+
+#[tokio::test]
+async fn an_open_circuit_stops_sending_to_a_failing_endpoint() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 20,
+            "circuit_breaker": { "failure_threshold": 3, "cool_down_ms": 60000 },
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap();
+
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    run.abort();
+
+    let posted = server.received_requests().await.unwrap().len();
+    assert!(
+        (3..=5).contains(&posted),
+        "sent {} orders to a failing endpoint",
+        posted
+    );
+}
@@ -0,0 +1,55 @@
+//! Fixtures shared by the integration tests. Each test crate uses only some
+//! of them.
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use wiremock::matchers::{body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+/// A broker that accepts orders for FOLD and rejects those for KHOD.
+pub async fn broker_server() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .and(body_string_contains("FOLD"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .and(body_string_contains("KHOD"))
+        .respond_with(ResponseTemplate::new(400).set_body_string("price out of range"))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// A broker that answers every order with `response`.
+pub async fn rejecting_server(response: ResponseTemplate) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(response)
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Orders `server` has received.
+pub async fn posted_orders(server: &MockServer) -> usize {
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == "POST")
+        .count()
+}
+
+/// A path under the temp directory, unique to this test run, with nothing
+/// at it yet.
+pub fn temp_path(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("sarkhati-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path
+}
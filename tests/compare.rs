@@ -0,0 +1,56 @@
+use sarkhati::compare::{self, Comparison};
+use sarkhati::custom_broker::CustomBrokerConfig;
+use serde_json::json;
+use std::time::Duration;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn broker(name: &str, order_url: String) -> CustomBrokerConfig {
+    serde_json::from_value(json!({
+        "name": name,
+        "order_url": order_url,
+        "body_template": { "qty": "{{quantity}}" },
+        "batch_delay_ms": 0,
+        "calibration": { "probe_interval_ms": 0 },
+        "orders": []
+    }))
+    .unwrap()
+}
+
+async fn probe_server(delay: Duration) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200).set_delay(delay))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn ranks_brokers_by_probe_latency() {
+    let slow = probe_server(Duration::from_millis(60)).await;
+    let fast = probe_server(Duration::ZERO).await;
+
+    let mut comparisons: Vec<Comparison> = vec![
+        compare::probe_broker(&broker("slow", format!("{}/order", slow.uri())), 3).await,
+        compare::probe_broker(&broker("down", "http://127.0.0.1:9/order".to_string()), 3).await,
+        compare::probe_broker(&broker("fast", format!("{}/order", fast.uri())), 3).await,
+    ];
+    compare::rank(&mut comparisons);
+
+    let names: Vec<&str> = comparisons.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["fast", "slow", "down"]);
+    let fast = &comparisons[0];
+    assert_eq!(fast.rtts_us.len(), 2);
+    assert!(fast.connect.is_some());
+    assert_eq!(fast.version, Some(reqwest::Version::HTTP_11));
+    assert_eq!(
+        fast.remote_addr
+            .map(|addr| addr.ip().to_string())
+            .as_deref(),
+        Some("127.0.0.1")
+    );
+    assert!(comparisons[1].p50_us().unwrap() >= 60_000);
+    assert!(comparisons[2].rtts_us.is_empty());
+    assert!(comparisons[2].error.as_ref().unwrap().contains("probe #1"));
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::posted_orders;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::market_data::Quote;
+use sarkhati::orders::OrderEntry;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Map, Value, json};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+async fn market(best_ask: u64) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [
+                { "insCode": INS_CODE, "instrumentID": "IRO1FOLD0001", "lVal18AFC": "فولاد" }
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/ClosingPrice/GetClosingPriceInfo/{}",
+            INS_CODE
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "closingPriceInfo": { "pDrCotVal": 2490 }
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/BestLimits/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "bestLimits": [
+                { "number": 1, "pMeDem": 2480, "pMeOf": best_ask },
+                { "number": 2, "pMeDem": 2470, "pMeOf": 2520 }
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    server
+}
+
+fn conditional_broker(server: &MockServer) -> CustomBrokersConfig {
+    serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "market_data_url": server.uri(),
+            "body_template": { "symbol": "{{isin}}" },
+            "orders": [{ "isin": "IRO1FOLD0001", "send_when": "best_ask <= 2500" }]
+        }]
+    }))
+    .unwrap()
+}
+
+#[test]
+fn send_when_parses_conditions_and_an_optional_isin() {
+    let order: OrderEntry<Map<String, Value>> = serde_json::from_value(json!({
+        "isin": "IRO1FOLD0001",
+        "send_when": { "condition": "last_price>=2600", "isin": "IRO1FOLD0001" }
+    }))
+    .unwrap();
+    let send_when = order.send_when.unwrap();
+    assert_eq!(send_when.condition.to_string(), "last_price >= 2600");
+    assert_eq!(send_when.isin.as_deref(), Some("IRO1FOLD0001"));
+    assert_eq!(order.data.len(), 1, "send_when is never sent");
+
+    let quote = Quote {
+        last_price: Some(2600.0),
+        ..Quote::default()
+    };
+    assert!(send_when.condition.is_met(&quote));
+    assert!(!send_when.condition.is_met(&Quote::default()));
+
+    for condition in ["best_ask", "close <= 10", "best_ask = 10", "best_ask <= x"] {
+        let parsed = serde_json::from_value::<OrderEntry<Map<String, Value>>>(
+            json!({ "send_when": condition }),
+        );
+        assert!(parsed.is_err(), "{:?} should be rejected", condition);
+    }
+}
+
+#[tokio::test]
+async fn order_is_held_until_its_condition_holds() {
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+
+    let server = market(2510).await;
+    let mut config = conditional_broker(&server);
+    run_broker(config.brokers.remove(0), options.clone())
+        .await
+        .unwrap();
+    assert_eq!(posted_orders(&server).await, 0);
+
+    let server = market(2500).await;
+    let mut config = conditional_broker(&server);
+    run_broker(config.brokers.remove(0), options).await.unwrap();
+    assert_eq!(posted_orders(&server).await, 1);
+}
@@ -0,0 +1,55 @@
+use sarkhati::console::{self, Level, paint, prefix_color};
+
+#[test]
+fn paints_prefix_in_broker_color_and_outcome_in_green_or_red() {
+    let color = prefix_color("bmi");
+    assert_eq!(prefix_color("BMI"), color);
+    assert_eq!(prefix_color("bmi/second"), color);
+
+    let prefix = format!("\x1b[1m{}[bmi]\x1b[0m ", color);
+    assert_eq!(
+        paint("[bmi] ✓ Batch #1, Order #1: Sent successfully"),
+        format!(
+            "{}\x1b[32m✓ Batch #1, Order #1: Sent successfully\x1b[0m",
+            prefix
+        )
+    );
+    assert_eq!(
+        paint("[bmi] ✗ Batch #1, Order #1: Failed - HTTP 401 Unauthorized"),
+        format!(
+            "{}\x1b[1;31m✗ Batch #1, Order #1: Failed - HTTP 401 Unauthorized\x1b[0m",
+            prefix
+        )
+    );
+    assert_eq!(
+        paint("[bmi] ✗ Batch #1, Order #1: Failed - HTTP 500"),
+        format!(
+            "{}\x1b[31m✗ Batch #1, Order #1: Failed - HTTP 500\x1b[0m",
+            prefix
+        )
+    );
+    assert_eq!(
+        paint("[bmi] Loaded 1 order(s) from config"),
+        format!("{}Loaded 1 order(s) from config", prefix)
+    );
+    assert_eq!(paint("plain line"), "plain line");
+}
+
+#[test]
+fn log_file_keeps_the_lines_quiet_mode_hides() {
+    let path = std::env::temp_dir().join(format!("sarkhati-console-{}.log", std::process::id()));
+    console::open_log_file(path.to_str().unwrap()).unwrap();
+    console::set_level(Level::Quiet);
+
+    sarkhati::println!("[bmi] Order response body: {{\"isSuccessful\":true}}");
+    sarkhati::summary!("[bmi] ✓ Test order: Sent successfully");
+    sarkhati::debug!("[bmi] Request header cookie: secret");
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].ends_with(" [bmi] Order response body: {\"isSuccessful\":true}"));
+    assert!(lines[1].ends_with(" [bmi] ✓ Test order: Sent successfully"));
+    assert!(lines[2].ends_with(" [bmi] Request header cookie: secret"));
+}
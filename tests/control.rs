@@ -0,0 +1,81 @@
+use sarkhati::control::{self, Control};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn api_pauses_brokers_and_disables_orders() {
+    let control = Control::new();
+    let broker = control.register("bmi", 2);
+    let address = control::start(control.clone(), 0).await.unwrap();
+    let client = reqwest::Client::new();
+    let post = |path: &str| client.post(format!("http://{}{}", address, path)).send();
+
+    let response = post("/brokers/BMI/pause").await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert!(broker.is_paused());
+
+    let status: Value = post("/brokers/bmi/orders/2/disable")
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status["paused"], json!(true));
+    assert_eq!(status["disabled_orders"], json!([2]));
+    assert!(broker.is_disabled(1));
+
+    post("/brokers/bmi/resume").await.unwrap();
+    assert!(!broker.is_paused());
+    assert_eq!(
+        post("/brokers/bmi/orders/3/disable")
+            .await
+            .unwrap()
+            .status(),
+        400
+    );
+    assert_eq!(post("/brokers/alvand/pause").await.unwrap().status(), 404);
+
+    let status: Value = client
+        .get(format!("http://{}/status", address))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(status[0]["name"], json!("bmi"));
+    assert_eq!(status[0]["paused"], json!(false));
+    assert_eq!(status[0]["orders"], json!(2));
+}
+
+#[tokio::test]
+async fn disabled_orders_are_not_sent() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "orders": [{ "isin": "IRO1FOLD0001", "price": 2500 }]
+        }]
+    }))
+    .unwrap();
+    let control = Control::new();
+    control.register("acme", 1).set_disabled(1, true).unwrap();
+    let options = RunOptions {
+        test_mode: true,
+        control: Some(control.clone()),
+        ..RunOptions::default()
+    };
+
+    run_broker(config.brokers.remove(0), options).await.unwrap();
+    assert_eq!(control.status()[0].accepted, 0);
+}
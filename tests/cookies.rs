@@ -0,0 +1,65 @@
+use rusqlite::Connection;
+use sarkhati::cookies;
+use std::path::{Path, PathBuf};
+
+fn cookie_database(name: &str, rows: &[(&str, &str, &str, &str, i64)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sarkhati-test-{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("cookies.sqlite");
+    let _ = std::fs::remove_file(&path);
+    let connection = Connection::open(&path).unwrap();
+    connection
+        .execute(
+            "CREATE TABLE moz_cookies (id INTEGER PRIMARY KEY, name TEXT, value TEXT, host TEXT, path TEXT, expiry INTEGER)",
+            [],
+        )
+        .unwrap();
+    for (host, path, name, value, expiry) in rows {
+        connection
+            .execute(
+                "INSERT INTO moz_cookies (host, path, name, value, expiry) VALUES (?1, ?2, ?3, ?4, ?5)",
+                (host, path, name, value, expiry),
+            )
+            .unwrap();
+    }
+    path
+}
+
+#[test]
+fn firefox_cookies_for_domain_and_subdomains_become_one_header() {
+    let future = chrono::Utc::now().timestamp() + 3600;
+    let database = cookie_database(
+        "header",
+        &[
+            (".bmibourse.ir", "/", "ASP.NET_SessionId", "s1", future),
+            ("online.bmibourse.ir", "/api", "Token", "t1", future * 1000),
+            ("bmibourse.ir", "/", "old", "x", 1),
+            (".notbmibourse.ir", "/", "other", "y", future),
+            ("example.com", "/", "unrelated", "z", future),
+        ],
+    );
+
+    let found = cookies::read_firefox_cookies(&database, "bmibourse.ir").unwrap();
+    assert_eq!(
+        cookies::cookie_header(&found),
+        "Token=t1; ASP.NET_SessionId=s1"
+    );
+}
+
+#[test]
+fn firefox_default_profile_prefers_install_section() {
+    let root = Path::new("/home/user/.mozilla/firefox");
+    let ini = "[Profile1]\nName=old\nIsRelative=1\nPath=abc.default\nDefault=1\n\n\
+               [Profile0]\nName=release\nIsRelative=1\nPath=xyz.default-release\n\n\
+               [Install4F96D1932A9F858E]\nDefault=xyz.default-release\nLocked=1\n";
+    assert_eq!(
+        cookies::default_profile(ini, root),
+        Some(root.join("xyz.default-release"))
+    );
+
+    let ini = "[Profile0]\nIsRelative=0\nPath=/data/ff\nDefault=1\n";
+    assert_eq!(
+        cookies::default_profile(ini, root),
+        Some(PathBuf::from("/data/ff"))
+    );
+}
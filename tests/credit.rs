@@ -0,0 +1,106 @@
+use sarkhati::credit;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Map, Value, json};
+use std::time::Duration;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[test]
+fn buys_cost_price_times_quantity() {
+    let order = |value: Value| -> Map<String, Value> { serde_json::from_value(value).unwrap() };
+    assert_eq!(
+        credit::cost(&order(json!({ "price": 2500, "quantity": "400" })), None),
+        Some(1_000_000.0)
+    );
+    assert_eq!(
+        credit::cost(
+            &order(json!({ "price": 2500, "quantity": 400 })),
+            Some(2600.0)
+        ),
+        Some(1_040_000.0)
+    );
+    assert_eq!(
+        credit::cost(
+            &order(json!({ "side": "SIDE_SELL", "price": 2500, "quantity": 400 })),
+            None
+        ),
+        None,
+        "sells need no credit"
+    );
+    assert_eq!(credit::cost(&order(json!({ "price": 2500 })), None), None);
+
+    let body = json!({ "data": { "remain": "۱۲٬۵۰۰٬۰۰۰" }, "total": 9000 });
+    assert_eq!(
+        credit::parse_remaining(&body, "/data/remain"),
+        Some(12_500_000.0)
+    );
+    assert_eq!(credit::parse_remaining(&body, "/total"), Some(9000.0));
+    assert_eq!(credit::parse_remaining(&body, "/missing"), None);
+}
+
+#[tokio::test]
+async fn buys_wait_for_the_credit_to_cover_them() {
+    let server = MockServer::start().await;
+    // Enough for the small order at first, for both once re-checked.
+    Mock::given(method("GET"))
+        .and(path("/credit"))
+        .and(header("cookie", "session=5"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "remain": 1_000_000 })))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/credit"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "remain": "10,000,000" })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "headers": { "Cookie": "session=5" },
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 100,
+            "stop_on_accept": true,
+            "credit_check": { "url": format!("{}/credit", server.uri()), "remaining": "/remain" },
+            "orders": [
+                { "isin": "IRO1KHOD0001", "price": 1000, "quantity": 5000 },
+                { "isin": "IRO1FOLD0001", "price": 1000, "quantity": 500 }
+            ]
+        }]
+    }))
+    .unwrap();
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the loop should stop once both orders are accepted")
+    .unwrap();
+
+    let requests: Vec<String> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| match request.url.path() {
+            "/orders" => {
+                let body: Value = serde_json::from_slice(&request.body).unwrap();
+                body["symbol"].as_str().unwrap().to_string()
+            }
+            path => path.to_string(),
+        })
+        .collect();
+    assert_eq!(
+        requests,
+        ["/credit", "IRO1FOLD0001", "/credit", "IRO1KHOD0001"]
+    );
+}
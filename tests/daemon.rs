@@ -0,0 +1,26 @@
+#![cfg(unix)]
+
+use sarkhati::daemon;
+use std::process::Command;
+
+#[test]
+fn stop_signals_the_daemon_and_removes_stale_pid_files() {
+    let pid_file = std::env::temp_dir().join(format!("sarkhati-daemon-{}.pid", std::process::id()));
+    let pid_file = pid_file.to_str().unwrap();
+
+    let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+    std::fs::write(pid_file, child.id().to_string()).unwrap();
+    // Reap the child as soon as it exits, as the daemon's parent would.
+    let waiter = std::thread::spawn(move || child.wait().unwrap());
+    daemon::stop(pid_file).unwrap();
+    assert!(!waiter.join().unwrap().success());
+    assert!(!std::path::Path::new(pid_file).exists());
+
+    let mut child = Command::new("true").spawn().unwrap();
+    child.wait().unwrap();
+    std::fs::write(pid_file, child.id().to_string()).unwrap();
+    let error = daemon::stop(pid_file).unwrap_err();
+    assert!(error.to_string().contains("stale"));
+    assert!(!std::path::Path::new(pid_file).exists());
+    assert!(daemon::stop(pid_file).is_err());
+}
@@ -0,0 +1,84 @@
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+/// A market where the best bid is 2480 and the broker accepts every order.
+async fn market() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [{ "insCode": INS_CODE, "instrumentID": "IRO1FOLD0001" }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/BestLimits/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "bestLimits": [
+                { "number": 2, "pMeDem": 2470, "qTitMeDem": 500, "zOrdMeDem": 2 },
+                {
+                    "number": 1,
+                    "pMeDem": 2480, "qTitMeDem": 1200, "zOrdMeDem": 3,
+                    "pMeOf": 2500, "qTitMeOf": 300, "zOrdMeOf": 1
+                }
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// Send one buy order at 2450, about 1.2% behind the best bid.
+async fn send_with(depth_check: Value) -> Vec<Value> {
+    let server = market().await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "market_data_url": server.uri(),
+            "depth_check": depth_check,
+            "body_template": { "symbol": "{{isin}}", "price": "{{price}}" },
+            "orders": [{ "isin": "IRO1FOLD0001", "price": 2450 }]
+        }]
+    }))
+    .unwrap();
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+    run_broker(config.brokers.remove(0), options).await.unwrap();
+
+    server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == "POST")
+        .map(|request| serde_json::from_slice(&request.body).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn orders_close_enough_to_the_queue_are_sent_unchanged() {
+    let sent = send_with(json!({ "max_behind_percent": 2.0 })).await;
+    assert_eq!(sent, [json!({ "symbol": "IRO1FOLD0001", "price": 2450 })]);
+}
+
+#[tokio::test]
+async fn orders_far_behind_the_queue_are_skipped_or_repriced() {
+    let sent = send_with(json!({ "max_behind_percent": 1.0 })).await;
+    assert!(sent.is_empty());
+
+    let sent = send_with(json!({ "max_behind_percent": 1.0, "action": "adjust" })).await;
+    assert_eq!(sent, [json!({ "symbol": "IRO1FOLD0001", "price": 2480 })]);
+}
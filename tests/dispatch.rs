@@ -0,0 +1,104 @@
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::dispatch::{self, DispatchThread, RealtimePolicy, ThreadPriority};
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::json;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn dedicated_runtime_runs_on_its_own_thread() {
+    let thread: DispatchThread = serde_json::from_value(json!({ "priority": "normal" })).unwrap();
+    assert_eq!(thread.priority, ThreadPriority::Normal);
+
+    let caller = std::thread::current().id();
+    let (name, id, spawned) = dispatch::run_dedicated("acme", &thread, async {
+        // Tasks spawned from the dispatcher stay on its runtime.
+        let spawned = tokio::spawn(async { std::thread::current().id() })
+            .await
+            .unwrap();
+        let current = std::thread::current();
+        (current.name().map(str::to_string), current.id(), spawned)
+    })
+    .await
+    .unwrap();
+    assert_eq!(name.as_deref(), Some("dispatch-acme"));
+    assert_ne!(id, caller);
+    assert_eq!(spawned, id);
+}
+
+#[tokio::test]
+async fn scheduled_send_goes_out_from_the_dispatch_thread() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let target_time = chrono::Utc::now().with_timezone(&chrono_tz::Asia::Tehran)
+        + chrono::Duration::milliseconds(500);
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "qty": "{{quantity}}" },
+            "target_time": target_time.format("%H:%M:%S%.3f").to_string(),
+            "holidays": { "on_holiday": "ignore" },
+            "dispatch_thread": { "priority": "high" },
+            "orders": [{ "quantity": 1 }]
+        }]
+    }))
+    .unwrap();
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+
+    let received = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            let requests = server.received_requests().await.unwrap();
+            if !requests.is_empty() {
+                break requests;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the scheduled order should be sent");
+    run.abort();
+    assert_eq!(received.len(), 1);
+    assert_eq!(
+        serde_json::from_slice::<serde_json::Value>(&received[0].body).unwrap(),
+        json!({ "qty": 1 })
+    );
+}
+
+#[tokio::test]
+async fn realtime_and_pinning_fall_back_when_not_permitted() {
+    let thread: DispatchThread = serde_json::from_value(json!({
+        "realtime": "fifo",
+        "realtime_priority": 80,
+        "cpu": 0
+    }))
+    .unwrap();
+    assert_eq!(thread.realtime, Some(RealtimePolicy::Fifo));
+
+    // Runs whether or not the sandbox allows SCHED_FIFO.
+    let allowed = dispatch::run_dedicated("acme", &thread, async {
+        std::fs::read_to_string("/proc/thread-self/status").ok()
+    })
+    .await
+    .unwrap();
+    // Only checked where this process may run on CPU 0 at all.
+    let process_on_cpu_0 = std::fs::read_to_string("/proc/self/status")
+        .is_ok_and(|status| status.contains("Cpus_allowed_list:\t0"));
+    if process_on_cpu_0 && let Some(status) = allowed {
+        assert!(
+            status.contains("Cpus_allowed_list:\t0\n"),
+            "not pinned to CPU 0: {}",
+            status
+        );
+    }
+
+    let thread: DispatchThread =
+        serde_json::from_value(json!({ "realtime": "rr", "realtime_priority": 0 })).unwrap();
+    let error = dispatch::run_dedicated("acme", &thread, async {})
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("between 1 and 99"), "{}", error);
+}
@@ -0,0 +1,94 @@
+use sarkhati::doctor::{self, CheckState};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+use wiremock::MockServer;
+
+/// An SNTP server on localhost whose clock runs `ahead_secs` ahead.
+async fn fake_ntp_server(ahead_secs: f64) -> String {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let address = socket.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut request = [0u8; 48];
+        while let Ok((_, client)) = socket.recv_from(&mut request).await {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs_f64()
+                + ahead_secs;
+            let mut response = [0u8; 48];
+            // Version 4, server mode, stratum 1
+            response[0] = 0x24;
+            response[1] = 1;
+            response[24..32].copy_from_slice(&request[40..48]);
+            response[32..40].copy_from_slice(&doctor::to_ntp(now));
+            response[40..48].copy_from_slice(&doctor::to_ntp(now));
+            let _ = socket.send_to(&response, client).await;
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn a_reachable_host_passes_dns_and_connect() {
+    let server = MockServer::start().await;
+    let checks = doctor::check_host(&server.uri(), &reqwest::Client::new()).await;
+    assert_eq!(checks.len(), 2);
+    assert!(
+        checks.iter().all(|check| check.state == CheckState::Pass),
+        "{:?}",
+        checks
+    );
+    assert!(checks[0].detail.starts_with("127.0.0.1"));
+    assert!(checks[1].detail.starts_with("HTTP 404"));
+}
+
+#[tokio::test]
+async fn an_unreachable_host_fails_with_a_hint() {
+    let checks = doctor::check_host("http://127.0.0.1:1/orders", &reqwest::Client::new()).await;
+    assert_eq!(checks[0].state, CheckState::Pass);
+    assert_eq!(checks[1].state, CheckState::Fail);
+    assert!(checks[1].hint.as_deref().unwrap().contains("firewall"));
+
+    let checks = doctor::check_host("not a url", &reqwest::Client::new()).await;
+    assert_eq!(checks[0].state, CheckState::Fail);
+}
+
+#[tokio::test]
+async fn the_clock_offset_is_measured_against_ntp() {
+    let server = fake_ntp_server(2.0).await;
+    let offset = doctor::clock_offset_ms(&server).await.unwrap();
+    assert!((1900.0..2100.0).contains(&offset), "{}", offset);
+    let check = doctor::check_clock(&server).await;
+    assert_eq!(check.state, CheckState::Fail);
+    assert!(check.detail.ends_with("behind"), "{}", check.detail);
+
+    let server = fake_ntp_server(0.0).await;
+    assert_eq!(doctor::check_clock(&server).await.state, CheckState::Pass);
+}
+
+#[cfg(unix)]
+#[test]
+fn configs_readable_by_others_warn() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("sarkhati-{}-doctor.json", std::process::id()));
+    std::fs::write(&path, "{}").unwrap();
+    let name = path.to_str().unwrap();
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let check = doctor::check_config_permissions(name);
+    assert_eq!(check.state, CheckState::Warn);
+    assert_eq!(check.hint, Some(format!("chmod 600 {}", name)));
+
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+    assert_eq!(
+        doctor::check_config_permissions(name).state,
+        CheckState::Pass
+    );
+    let _ = std::fs::remove_file(path);
+
+    assert_eq!(
+        doctor::check_config_permissions("missing.json").state,
+        CheckState::Fail
+    );
+}
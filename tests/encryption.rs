@@ -0,0 +1,21 @@
+use sarkhati::encryption;
+
+#[test]
+fn encrypted_config_round_trips_with_the_passphrase() {
+    let config = r#"{"brokers":[{"name":"bmi","cookie":"ASP.NET_SessionId=abc"}]}"#;
+    let data = encryption::encrypt(config, "correct horse").unwrap();
+    assert!(!String::from_utf8_lossy(&data).contains("SessionId"));
+
+    assert_eq!(encryption::decrypt(&data, "correct horse").unwrap(), config);
+    let error = encryption::decrypt(&data, "wrong").unwrap_err();
+    assert!(error.to_string().contains("Wrong passphrase"));
+}
+
+#[test]
+fn tampered_or_plain_files_are_rejected() {
+    let mut data = encryption::encrypt("{}", "pass").unwrap();
+    let last = data.len() - 1;
+    data[last] ^= 1;
+    assert!(encryption::decrypt(&data, "pass").is_err());
+    assert!(encryption::decrypt(b"{}", "pass").is_err());
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::{posted_orders, rejecting_server};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::mofid::MofidConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::json;
+use std::time::Duration;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn continuous_broker(server: &MockServer) -> CustomBrokersConfig {
+    serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 0,
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn a_closed_market_stops_the_batch_loop() {
+    let server =
+        rejecting_server(ResponseTemplate::new(400).set_body_string("Market is closed")).await;
+    let mut config = continuous_broker(&server);
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the loop should stop on its own")
+    .unwrap();
+    assert!(posted_orders(&server).await < 10);
+}
+
+#[tokio::test]
+async fn configured_phrases_also_mean_the_market_is_closed() {
+    let server = rejecting_server(
+        ResponseTemplate::new(200)
+            .set_body_json(json!({ "ok": false, "message": "نماد در وضعیت توقف است" })),
+    )
+    .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 0,
+            "success_rules": [{ "json_pointer": "/ok", "equals": true }],
+            "market_closed": { "phrases": ["وضعیت توقف"] },
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap();
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the loop should stop on its own")
+    .unwrap();
+}
+
+#[tokio::test]
+async fn rate_limiting_backs_off_between_batches() {
+    let server = rejecting_server(ResponseTemplate::new(429)).await;
+    let mut config = continuous_broker(&server);
+
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    run.abort();
+
+    // Without the backoff a zero batch delay sends hundreds of orders a second.
+    let posted = posted_orders(&server).await;
+    assert!(posted < 30, "sent {} orders while rate limited", posted);
+}
+
+#[tokio::test]
+async fn an_expired_session_stops_a_broker_that_cannot_log_in_again() {
+    let server = rejecting_server(ResponseTemplate::new(401)).await;
+    let mut config = continuous_broker(&server);
+
+    let error = tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the loop should stop on its own")
+    .unwrap_err();
+    assert!(
+        format!("{:#}", error).contains("cannot log in by itself"),
+        "{:#}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn an_expired_session_logs_in_again_and_resumes() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/order"))
+        .and(header("authorization", "Bearer stale"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/order"))
+        .and(header("authorization", "Bearer fresh"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/connect/token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "access_token": "fresh" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    let broker: MofidConfig = serde_json::from_value(json!({
+        "authorization": "stale",
+        "order_url": format!("{}/order", server.uri()),
+        "batch_delay_ms": 200,
+        "stop_on_accept": true,
+        "login": {
+            "username": "user1",
+            "password": "secret",
+            "token_url": format!("{}/connect/token", server.uri()),
+            "save_token": false
+        },
+        "orders": [{
+            "orderSide": "Buy",
+            "price": 50340,
+            "quantity": 100,
+            "symbolIsin": "IRO1RVND0001",
+            "validityType": 74,
+            "validityDate": null,
+            "orderFrom": "34"
+        }]
+    }))
+    .unwrap();
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(broker, RunOptions::default()),
+    )
+    .await
+    .expect("the loop should stop once the order is accepted")
+    .unwrap();
+    assert_eq!(last_order_token(&server).await, "fresh");
+}
+
+async fn last_order_token(server: &MockServer) -> String {
+    let requests = server.received_requests().await.unwrap();
+    let last = requests
+        .iter()
+        .rev()
+        .find(|request| request.url.path() == "/order")
+        .unwrap();
+    last.headers["authorization"]
+        .to_str()
+        .unwrap()
+        .trim_start_matches("Bearer ")
+        .to_string()
+}
@@ -0,0 +1,89 @@
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::fixtures::{self, Fixture};
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn acme(order_url: &str) -> CustomBrokersConfig {
+    serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": order_url,
+            "headers": { "Cookie": "session=secret" },
+            "body_template": { "symbol": "{{isin}}", "password": "hunter2" },
+            "batch_sinks": [],
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn a_recorded_exchange_is_sanitized_and_replayed() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(
+            ResponseTemplate::new(400)
+                .insert_header("X-Trace", "t-42")
+                .set_body_json(json!({ "error": "price out of range", "token": "abc" })),
+        )
+        .mount(&server)
+        .await;
+    let dir = std::env::temp_dir().join(format!("sarkhati-{}-fixtures", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    fixtures::set_record_dir(dir.to_str().unwrap()).unwrap();
+
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+    let recorded = run_broker(
+        acme(&format!("{}/orders", server.uri())).brokers.remove(0),
+        options.clone(),
+    )
+    .await;
+    assert!(recorded.is_err());
+
+    let loaded = fixtures::load(dir.to_str().unwrap()).unwrap();
+    assert_eq!(loaded.len(), 1);
+    let fixture: &Fixture = &loaded[0];
+    assert_eq!(fixture.broker, "acme");
+    assert_eq!(fixture.path, "/orders");
+    assert_eq!(fixture.status, 400);
+    assert_eq!(fixture.request_headers["cookie"], "<redacted>");
+    assert_eq!(fixture.response_headers["x-trace"], "t-42");
+    let text = serde_json::to_string(fixture).unwrap();
+    assert!(!text.contains("secret"), "{}", text);
+    assert!(!text.contains("hunter2"), "{}", text);
+    assert!(!text.contains("abc"), "{}", text);
+    assert!(text.contains("IRO1FOLD0001"));
+
+    // The same broker against the replayed fixture fails the same way, with
+    // the sanitized body.
+    let address = fixtures::serve(loaded, 0).await.unwrap();
+    let replayed = run_broker(
+        acme(&format!("http://{}/orders", address))
+            .brokers
+            .remove(0),
+        options,
+    )
+    .await;
+    let (recorded, replayed) = (recorded.unwrap_err(), replayed.unwrap_err());
+    assert_eq!(
+        format!("{:#}", recorded)
+            .replace(&server.uri(), "")
+            .replace("abc", "<redacted>"),
+        format!("{:#}", replayed).replace(&format!("http://{}", address), "")
+    );
+
+    let client = reqwest::Client::new();
+    let unknown = client
+        .post(format!("http://{}/elsewhere", address))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(unknown.status(), 404);
+    let _ = std::fs::remove_dir_all(dir);
+}
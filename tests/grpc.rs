@@ -0,0 +1,74 @@
+use sarkhati::control::Control;
+use sarkhati::grpc::{self, proto};
+use tokio_stream::StreamExt;
+
+#[tokio::test]
+async fn grpc_steers_brokers_and_streams_order_events() {
+    let control = Control::new();
+    let broker = control.register("acme", 2);
+    let address = grpc::start(control.clone(), 0).await.unwrap();
+    let mut client = proto::control_client::ControlClient::connect(format!("http://{}", address))
+        .await
+        .unwrap();
+
+    let status = client
+        .pause(proto::BrokerRequest {
+            broker: "ACME".into(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(status.paused);
+    assert!(broker.is_paused());
+
+    let status = client
+        .disable_order(proto::OrderRequest {
+            broker: "acme".into(),
+            order: 2,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.disabled_orders, vec![2]);
+    let error = client
+        .disable_order(proto::OrderRequest {
+            broker: "acme".into(),
+            order: 3,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), tonic::Code::InvalidArgument);
+    let error = client
+        .pause(proto::BrokerRequest {
+            broker: "alvand".into(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(error.code(), tonic::Code::NotFound);
+
+    let mut events = client
+        .watch_orders(proto::WatchRequest {
+            broker: String::new(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    broker.record_send(0, &anyhow::Ok(()));
+    broker.record_send(1, &Err::<(), _>(anyhow::anyhow!("HTTP 500")));
+
+    let accepted = events.next().await.unwrap().unwrap();
+    assert_eq!((accepted.broker.as_str(), accepted.order), ("acme", 1));
+    assert!(accepted.accepted);
+    let failed = events.next().await.unwrap().unwrap();
+    assert_eq!(failed.order, 2);
+    assert!(!failed.accepted);
+    assert_eq!(failed.error.as_deref(), Some("HTTP 500"));
+
+    let reply = client
+        .status(proto::StatusRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(reply.brokers.len(), 1);
+    assert_eq!((reply.brokers[0].accepted, reply.brokers[0].failed), (1, 1));
+}
@@ -0,0 +1,72 @@
+use chrono::{NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::Asia::Tehran;
+use sarkhati::holidays::{HolidayCalendar, OnHoliday};
+
+fn date(date: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+}
+
+#[test]
+fn weekends_and_listed_holidays_are_closed() {
+    let calendar = HolidayCalendar::bundled();
+    assert_eq!(calendar.closed_because(date("2026-03-21")), Some("Nowruz"));
+    assert_eq!(
+        calendar.closed_because(date("2026-06-05")),
+        Some("15 Khordad Uprising")
+    );
+    assert_eq!(
+        calendar.closed_because(date("2026-10-15")),
+        Some("the weekend")
+    );
+    assert_eq!(calendar.closed_because(date("2026-10-17")), None);
+    assert_eq!(
+        calendar.next_trading_day(date("2026-03-19")),
+        date("2026-03-25")
+    );
+}
+
+#[test]
+fn closed_targets_roll_to_the_next_session_or_refuse() {
+    let calendar =
+        HolidayCalendar::parse(r#"{ "holidays": [{ "date": "2026-10-17", "name": "Test Day" }] }"#)
+            .unwrap();
+    let friday = Tehran.with_ymd_and_hms(2026, 10, 16, 8, 45, 0).unwrap();
+
+    let rolled = calendar.schedule("test", friday, OnHoliday::Roll).unwrap();
+    assert_eq!(
+        rolled,
+        Tehran.with_ymd_and_hms(2026, 10, 18, 8, 45, 0).unwrap()
+    );
+
+    let error = calendar
+        .schedule("test", friday, OnHoliday::Refuse)
+        .unwrap_err();
+    assert!(error.to_string().contains("2026-10-16 (the weekend)"));
+
+    let kept = calendar
+        .schedule("test", friday, OnHoliday::Ignore)
+        .unwrap();
+    assert_eq!(kept, friday);
+}
+
+#[test]
+fn the_next_session_opens_later_today_or_on_the_next_trading_day() {
+    let calendar = HolidayCalendar::parse(r#"{ "holidays": [] }"#).unwrap();
+    let open = NaiveTime::from_hms_opt(8, 45, 0).unwrap();
+    let saturday_dawn = Tehran.with_ymd_and_hms(2026, 10, 17, 6, 0, 0).unwrap();
+    let saturday_afternoon = Tehran.with_ymd_and_hms(2026, 10, 17, 14, 0, 0).unwrap();
+    let wednesday_afternoon = Tehran.with_ymd_and_hms(2026, 10, 21, 14, 0, 0).unwrap();
+
+    assert_eq!(
+        calendar.next_session(saturday_dawn, open).unwrap(),
+        Tehran.with_ymd_and_hms(2026, 10, 17, 8, 45, 0).unwrap()
+    );
+    assert_eq!(
+        calendar.next_session(saturday_afternoon, open).unwrap(),
+        Tehran.with_ymd_and_hms(2026, 10, 18, 8, 45, 0).unwrap()
+    );
+    assert_eq!(
+        calendar.next_session(wednesday_afternoon, open).unwrap(),
+        Tehran.with_ymd_and_hms(2026, 10, 24, 8, 45, 0).unwrap()
+    );
+}
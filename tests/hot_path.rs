@@ -0,0 +1,107 @@
+//! The send path of a prepared order allocates nothing of Sarkhati's own
+//! before the request is handed to reqwest.
+
+use sarkhati::console::Timing;
+use sarkhati::latency;
+use sarkhati::runner::Broker;
+use sarkhati::standard_broker::StandardBrokerConfig;
+use serde_json::json;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts allocations per thread, so tests running in parallel do not see
+/// each other's.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations_of<T>(run: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let output = run();
+    (output, ALLOCATIONS.with(Cell::get) - before)
+}
+
+fn broker() -> StandardBrokerConfig {
+    serde_json::from_value(json!({
+        "name": "bmi",
+        "cookie": "session=3",
+        "order_url": "https://api.bmibourse.ir/Web/V1/Order/Post",
+        "origin": "https://online.bmibourse.ir",
+        "referer": "https://online.bmibourse.ir/",
+        "orders": [{
+            "IsSymbolCautionAgreement": false,
+            "CautionAgreementSelected": false,
+            "IsSymbolSepahAgreement": false,
+            "SepahAgreementSelected": false,
+            "orderCount": 100,
+            "orderPrice": 50340,
+            "FinancialProviderId": 1,
+            "minimumQuantity": 0,
+            "maxShow": 0,
+            "orderId": 0,
+            "isin": "IRO1RVND0001",
+            "orderSide": 65,
+            "orderValidity": 74,
+            "orderValiditydate": null,
+            "shortSellIsEnabled": false,
+            "shortSellIncentivePercent": 0
+        }]
+    }))
+    .expect("test config should deserialize")
+}
+
+#[test]
+fn stocked_requests_are_taken_without_allocating() {
+    let broker = broker();
+    let order = &broker.orders()[0];
+    let request = broker
+        .prepare(&order.data, broker.order_json(order).unwrap())
+        .unwrap();
+    request.stock(2);
+
+    for _ in 0..2 {
+        let ((url, headers), allocations) = allocations_of(|| request.parts());
+        assert_eq!(allocations, 0);
+        assert_eq!(url, request.url);
+        assert_eq!(headers, request.headers);
+        drop((url, headers));
+    }
+    // Once the stock runs out, parts are cloned.
+    let (_, allocations) = allocations_of(|| request.parts());
+    assert!(allocations > 0);
+}
+
+#[test]
+fn timing_a_send_does_not_allocate() {
+    // The executor sets up its thread's waker on first use.
+    futures::executor::block_on(async {});
+    let (_, allocations) = allocations_of(|| {
+        let mut timing = Timing::start();
+        timing.mark("rate limit");
+        let (output, phases) = futures::executor::block_on(latency::timed(async { 1 }));
+        timing.mark("body");
+        (timing, output, phases)
+    });
+    assert_eq!(allocations, 0);
+}
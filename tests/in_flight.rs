@@ -0,0 +1,217 @@
+use sarkhati::accounts;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+const RESPONSE_DELAY: Duration = Duration::from_millis(200);
+
+/// Answers every order after [`RESPONSE_DELAY`], noting when each arrived.
+#[derive(Clone, Default)]
+struct SlowResponder {
+    arrivals: Arc<Mutex<Vec<Instant>>>,
+}
+
+impl Respond for SlowResponder {
+    fn respond(&self, _: &Request) -> ResponseTemplate {
+        self.arrivals.lock().unwrap().push(Instant::now());
+        ResponseTemplate::new(200).set_delay(RESPONSE_DELAY)
+    }
+}
+
+async fn slow_server() -> (MockServer, SlowResponder) {
+    let server = MockServer::start().await;
+    let responder = SlowResponder::default();
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(responder.clone())
+        .mount(&server)
+        .await;
+    (server, responder)
+}
+
+fn capped_broker(server: &MockServer, when_full: &str) -> CustomBrokersConfig {
+    serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 10,
+            "in_flight_limit": { "max_tasks": 1, "when_full": when_full },
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap()
+}
+
+/// Run the broker until the server has seen `count` orders and return the
+/// gaps between their arrivals.
+async fn gaps_between_orders(
+    responder: &SlowResponder,
+    config: &mut CustomBrokersConfig,
+    count: usize,
+) -> Vec<Duration> {
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while responder.arrivals.lock().unwrap().len() < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the orders arrive");
+    run.abort();
+    let arrivals = responder.arrivals.lock().unwrap();
+    arrivals[..count]
+        .windows(2)
+        .map(|pair| pair[1] - pair[0])
+        .collect()
+}
+
+/// Each order only goes out once the one before it was answered; without
+/// that a 10ms batch delay sends several orders per response.
+fn assert_one_at_a_time(gaps: &[Duration]) {
+    assert!(
+        gaps.iter().all(|gap| *gap >= RESPONSE_DELAY),
+        "orders overlapped: {:?}",
+        gaps
+    );
+}
+
+#[tokio::test]
+async fn a_full_task_cap_delays_new_batches() {
+    let (server, responder) = slow_server().await;
+    let mut config = capped_broker(&server, "delay");
+
+    assert_one_at_a_time(&gaps_between_orders(&responder, &mut config, 3).await);
+}
+
+#[tokio::test]
+async fn a_full_task_cap_sheds_new_batches() {
+    let (server, responder) = slow_server().await;
+    let mut config = capped_broker(&server, "shed");
+
+    assert_one_at_a_time(&gaps_between_orders(&responder, &mut config, 3).await);
+}
+
+#[tokio::test]
+async fn a_task_cap_smaller_than_one_batch_is_refused() {
+    let (server, responder) = slow_server().await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_repeat": 2,
+            "in_flight_limit": { "max_tasks": 3 },
+            "orders": [{ "isin": "IRO1FOLD0001" }, { "isin": "IRO1FOLD0002" }]
+        }]
+    }))
+    .unwrap();
+
+    let error = run_broker(config.brokers.remove(0), RunOptions::default())
+        .await
+        .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("max_tasks is 3 but a batch of acme sends 4 order tasks"),
+        "{:#}",
+        error
+    );
+    assert!(responder.arrivals.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn batches_can_wait_for_the_responses_of_the_one_before() {
+    let (server, responder) = slow_server().await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 0,
+            "wait_for_batch_completion": true,
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap();
+
+    assert_one_at_a_time(&gaps_between_orders(&responder, &mut config, 3).await);
+}
+
+/// Whether no more than `cap` orders arrived within any [`RESPONSE_DELAY`],
+/// which the server holds each of them for.
+fn assert_at_most_in_flight(arrivals: &[Instant], cap: usize) {
+    for window in arrivals.windows(cap + 1) {
+        let gap = window[cap] - window[0];
+        assert!(
+            gap >= RESPONSE_DELAY,
+            "{} orders arrived within {:?}",
+            cap + 1,
+            gap
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_concurrency_cap_holds_across_accounts_in_a_test_send() {
+    let (server, responder) = slow_server().await;
+    let mut value = json!({
+        "brokers": [{
+            "name": "acme",
+            "account_strategy": "broadcast-all",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "max_concurrent_requests": 1,
+            "orders": [{ "isin": "IRO1FOLD0001" }],
+            "accounts": [{ "name": "a" }, { "name": "b" }, { "name": "c" }]
+        }]
+    });
+    assert!(accounts::expand(&mut value).unwrap());
+    let mut config: CustomBrokersConfig = serde_json::from_value(value).unwrap();
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+
+    run_broker(config.brokers.remove(0), options).await.unwrap();
+    let arrivals = responder.arrivals.lock().unwrap().clone();
+    assert_eq!(arrivals.len(), 3);
+    assert_at_most_in_flight(&arrivals, 1);
+}
+
+#[tokio::test]
+async fn the_concurrency_cap_holds_in_continuous_batches() {
+    let (server, responder) = slow_server().await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 10,
+            "max_concurrent_requests": 2,
+            "orders": [
+                { "isin": "IRO1FOLD0001" },
+                { "isin": "IRO1FOLD0001" },
+                { "isin": "IRO1FOLD0001" },
+                { "isin": "IRO1FOLD0001" }
+            ]
+        }]
+    }))
+    .unwrap();
+
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while responder.arrivals.lock().unwrap().len() < 8 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the orders arrive");
+    run.abort();
+    let arrivals = responder.arrivals.lock().unwrap().clone();
+    assert_at_most_in_flight(&arrivals, 2);
+}
@@ -0,0 +1,99 @@
+use sarkhati::accounts;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::ipo::split;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use std::time::Duration;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+#[test]
+fn quantity_splits_evenly_with_the_remainder_up_front() {
+    assert_eq!(split(10, 3), Some(vec![4, 3, 3]));
+    assert_eq!(split(6, 2), Some(vec![3, 3]));
+    assert_eq!(split(1, 2), None);
+}
+
+async fn mock_market(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [{ "insCode": INS_CODE, "instrumentID": "IRO1FOLD0001" }]
+        })))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/Instrument/GetStaticThreshold/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "staticThreshold": [{ "psGelStaMin": 2360, "psGelStaMax": 2610 }]
+        })))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/ClosingPrice/GetClosingPriceInfo/{}",
+            INS_CODE
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "closingPriceInfo": { "instrumentState": { "cEtaval": "A " } }
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn ipo_buys_at_the_ceiling_once_per_account_and_stops() {
+    let server = MockServer::start().await;
+    mock_market(&server).await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut value = json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "market_data_url": server.uri(),
+            "body_template": { "symbol": "{{isin}}", "price": "{{price}}", "qty": "{{quantity}}" },
+            "batch_delay_ms": 10,
+            "ipo": { "symbol": "IRO1FOLD0001", "total_quantity": 5 },
+            "orders": [{ "isin": "IRO1FOLD0001", "price": 0, "quantity": 1 }],
+            "accounts": [{ "name": "me" }, { "name": "wife" }]
+        }]
+    });
+    assert!(accounts::expand(&mut value).unwrap());
+    let mut config: CustomBrokersConfig = serde_json::from_value(value).unwrap();
+
+    // Without test mode the run only ends because every account got its
+    // order accepted.
+    tokio::time::timeout(
+        Duration::from_secs(10),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the run did not stop after the orders were accepted")
+    .unwrap();
+
+    let mut orders: Vec<Value> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == "POST")
+        .map(|request| request.body_json().unwrap())
+        .collect();
+    // Batches do not wait for responses, so a few more may have gone out
+    // before the first acceptance came back.
+    orders.sort_by_key(|order| order["qty"].as_u64());
+    orders.dedup();
+    assert_eq!(
+        orders,
+        [
+            json!({ "symbol": "IRO1FOLD0001", "price": 2610, "qty": 2 }),
+            json!({ "symbol": "IRO1FOLD0001", "price": 2610, "qty": 3 }),
+        ]
+    );
+}
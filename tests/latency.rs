@@ -0,0 +1,65 @@
+use sarkhati::custom_broker::CustomBrokerConfig;
+use sarkhati::latency::{self, SocketOptions};
+use sarkhati::runner::Broker;
+use serde_json::json;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn records_connection_setup_only_for_new_connections() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let url = server.uri().replace("127.0.0.1", "localhost");
+    let client = latency::client(&SocketOptions::default());
+
+    let (response, phases) = latency::timed(client.get(&url).send()).await;
+    response.unwrap().bytes().await.unwrap();
+    assert!(phases.dns.is_some());
+    assert!(phases.connect >= phases.dns);
+    assert!(phases.handshake().is_some());
+    assert!(phases.describe().contains(" connect="));
+
+    let (response, phases) = latency::timed(client.get(&url).send()).await;
+    response.unwrap();
+    assert!(phases.connect.is_none());
+    assert_eq!(phases.describe(), " reused");
+}
+
+#[tokio::test]
+async fn brokers_connect_with_their_socket_options() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let broker: CustomBrokerConfig = serde_json::from_value(json!({
+        "name": "acme",
+        "order_url": format!("{}/orders", server.uri()),
+        "body_template": {},
+        "socket": {
+            "tcp_nodelay": false,
+            "tcp_keepalive_ms": 15000,
+            "tcp_keepalive_interval_ms": 5000,
+            "tcp_keepalive_retries": 3,
+            "tcp_user_timeout_ms": 10000,
+            "pool_idle_timeout_ms": 60000
+        },
+        "orders": []
+    }))
+    .unwrap();
+    let socket = &broker.settings().socket;
+    assert!(!socket.tcp_nodelay);
+    assert_eq!(socket.tcp_keepalive_ms, Some(15000));
+    assert_eq!(socket.tcp_keepalive_retries, Some(3));
+    assert!(SocketOptions::default().tcp_nodelay);
+
+    let (_, status) = broker
+        .send_probe(broker.settings().client())
+        .await
+        .map(|(rtt_ms, _, status)| (rtt_ms, status))
+        .unwrap();
+    assert_eq!(status, 200);
+}
@@ -0,0 +1,58 @@
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+async fn posted_price(order_price: Value) -> Value {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [{ "insCode": INS_CODE, "instrumentID": "IRO1FOLD0001" }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/Instrument/GetStaticThreshold/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "staticThreshold": [{ "psGelStaMin": 2360, "psGelStaMax": 2610 }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "market_data_url": server.uri(),
+            "price_check": { "tick_size": 7 },
+            "body_template": { "symbol": "{{isin}}", "price": "{{price}}" },
+            "orders": [{ "isin": "IRO1FOLD0001", "price": order_price }]
+        }]
+    }))
+    .unwrap();
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+    run_broker(config.brokers.remove(0), options).await.unwrap();
+    let requests = server.received_requests().await.unwrap();
+    let post = requests
+        .iter()
+        .find(|request| request.method.as_str() == "POST")
+        .expect("order was not sent");
+    post.body_json::<Value>().unwrap()["price"].clone()
+}
+
+#[tokio::test]
+async fn limit_orders_are_priced_from_the_daily_band() {
+    assert_eq!(posted_price(json!("upper_limit")).await, json!(2610));
+    assert_eq!(posted_price(json!("lower_limit")).await, json!(2360));
+}
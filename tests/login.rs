@@ -0,0 +1,511 @@
+use sarkhati::bidar::{self, BidarConfig};
+use sarkhati::captcha::{Captcha, CaptchaSolver};
+use sarkhati::exir_broker::{self, ExirBrokerConfig};
+use sarkhati::exir_login::{self, ExirLoginConfig};
+use sarkhati::login;
+use sarkhati::mofid_login::{self, MofidLoginConfig};
+use sarkhati::rayan_broker::{self, RayanBrokerConfig};
+use sarkhati::runner::Broker;
+use sarkhati::tadbir_broker::{self, TadbirBrokerConfig};
+use serde_json::json;
+use wiremock::matchers::{body_string_contains, header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+struct FixedSolver(&'static str);
+
+impl CaptchaSolver for FixedSolver {
+    async fn solve(&self, captcha: &Captcha) -> anyhow::Result<String> {
+        assert_eq!(captcha.extension, "jpeg");
+        Ok(self.0.to_string())
+    }
+}
+
+fn mofid_login_config(server: &MockServer, captcha: bool) -> MofidLoginConfig {
+    let mut config = json!({
+        "username": "user1",
+        "password": "secret",
+        "token_url": format!("{}/connect/token", server.uri()),
+    });
+    if captcha {
+        config["captcha_url"] = json!(format!("{}/captcha", server.uri()));
+    }
+    serde_json::from_value(config).unwrap()
+}
+
+#[tokio::test]
+async fn mofid_login_retries_with_one_time_code() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/connect/token"))
+        .and(body_string_contains("otp=123456"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "access_token": "tok", "expires_in": 3600 })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/connect/token"))
+        .and(body_string_contains("grant_type=password"))
+        .and(body_string_contains("username=user1"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({ "error": "otp_required" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let config = mofid_login_config(&server, false);
+    let mut prompts = 0;
+    let token = mofid_login::login(&config, "ua", &FixedSolver("-"), async || {
+        prompts += 1;
+        Ok("123456".to_string())
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(token.access_token, "tok");
+    assert_eq!(token.expires_in_secs, Some(3600));
+    assert_eq!(prompts, 1);
+}
+
+#[tokio::test]
+async fn mofid_login_sends_captcha_answer_with_its_cookie() {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/captcha"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Content-Type", "image/jpeg")
+                .insert_header("Set-Cookie", "captcha_id=abc; Path=/; HttpOnly")
+                .set_body_bytes(vec![0xff, 0xd8]),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/connect/token"))
+        .and(header("cookie", "captcha_id=abc"))
+        .and(body_string_contains("captcha=7Kx2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "access_token": "tok" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let config = mofid_login_config(&server, true);
+    let token = mofid_login::login(&config, "ua", &FixedSolver("7Kx2"), async || {
+        anyhow::bail!("no one-time code expected")
+    })
+    .await
+    .unwrap();
+
+    assert_eq!(token.access_token, "tok");
+}
+
+#[tokio::test]
+async fn mofid_login_reports_error_description() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(
+            json!({ "error": "invalid_grant", "error_description": "invalid_username_or_password" }),
+        ))
+        .mount(&server)
+        .await;
+
+    let config = mofid_login_config(&server, false);
+    let error = mofid_login::login(&config, "ua", &FixedSolver("-"), async || {
+        anyhow::bail!("no one-time code expected")
+    })
+    .await
+    .unwrap_err();
+
+    assert!(error.to_string().contains("invalid_username_or_password"));
+}
+
+async fn mock_exir_login(server: &MockServer, jwt: &str, nt: &str, logins: u64) {
+    Mock::given(method("POST"))
+        .and(path("/api/v1/login"))
+        .and(body_string_contains(r#""username":"user1""#))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Set-Cookie", format!("JWT-TOKEN={}; Path=/; HttpOnly", jwt))
+                .set_body_json(json!({ "result": "ok" })),
+        )
+        .expect(logins)
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/user/info"))
+        .and(header("cookie", format!("JWT-TOKEN={}", jwt)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "nt": nt })))
+        .expect(logins)
+        .mount(server)
+        .await;
+}
+
+fn exir_login_json() -> serde_json::Value {
+    json!({ "username": "user1", "password": "secret", "save_session": false })
+}
+
+#[tokio::test]
+async fn exir_login_reads_cookie_and_nt_from_user_info() {
+    let server = MockServer::start().await;
+    mock_exir_login(&server, "abc", "031234567890", 1).await;
+
+    let config: ExirLoginConfig = serde_json::from_value(exir_login_json()).unwrap();
+    let credentials = exir_login::login(
+        &config,
+        &server.uri(),
+        &format!("{}/exir/mainNew", server.uri()),
+        "ua",
+        &FixedSolver("-"),
+    )
+    .await
+    .unwrap();
+
+    assert_eq!(credentials.cookie, "JWT-TOKEN=abc");
+    assert_eq!(credentials.nt, "031234567890");
+}
+
+#[tokio::test]
+async fn exir_login_sends_totp_code() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/login"))
+        .and(body_string_contains(r#""otp":""#))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .insert_header("Set-Cookie", "JWT-TOKEN=abc; Path=/")
+                .set_body_json(json!({})),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/api/v1/user/info"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "nt": "031234567890" })))
+        .mount(&server)
+        .await;
+
+    let mut login = exir_login_json();
+    login["totp"] = json!({ "secret": "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ" });
+    let config: ExirLoginConfig = serde_json::from_value(login).unwrap();
+    exir_login::login(
+        &config,
+        &server.uri(),
+        &server.uri(),
+        "ua",
+        &FixedSolver("-"),
+    )
+    .await
+    .unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    let code = body["otp"].as_str().unwrap();
+    assert_eq!(code.len(), 6);
+    assert!(code.chars().all(|ch| ch.is_ascii_digit()));
+}
+
+#[tokio::test]
+async fn exir_order_logs_in_again_when_session_expires() {
+    let server = MockServer::start().await;
+    mock_exir_login(&server, "fresh", "049876543210", 1).await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/order"))
+        .and(header("cookie", "JWT-TOKEN=fresh"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/order"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let broker: ExirBrokerConfig = serde_json::from_value(json!({
+        "name": "alvand",
+        "cookie": "JWT-TOKEN=stale",
+        "nt": "031234567890",
+        "order_url": format!("{}/api/v1/order", server.uri()),
+        "origin": server.uri(),
+        "referer": format!("{}/exir/mainNew", server.uri()),
+        "login": exir_login_json(),
+        "orders": [{
+            "insMaxLcode": "IRO1RVND0001",
+            "bankAccountId": -1,
+            "side": "SIDE_BUY",
+            "orderType": "ORDER_TYPE_LIMIT",
+            "quantity": 100,
+            "price": 50340,
+            "validityType": "VALIDITY_TYPE_DAY",
+            "validityDate": "",
+            "coreType": "c",
+            "hasUnderCautionAgreement": false,
+            "dividedOrder": false
+        }]
+    }))
+    .unwrap();
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    exir_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    assert_eq!(broker.credentials().nt, "049876543210");
+    assert_eq!(broker.session.generation(), 1);
+}
+
+#[tokio::test]
+async fn rayan_order_logs_in_first_and_again_when_session_expires() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/Account/Login"))
+        .and(body_string_contains(r#""username":"user1""#))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "data": { "token": "t1" } })),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/v1/Account/Login"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({ "data": { "token": "t2" } })),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/order"))
+        .and(header("authorization", "Bearer t2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "isSuccess": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/order"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut broker: RayanBrokerConfig = serde_json::from_value(json!({
+        "name": "rayan",
+        "host": server.uri(),
+        "order_url": format!("{}/api/order", server.uri()),
+        "login": {
+            "username": "user1",
+            "password": "secret",
+            "login_path": "/api/v1/Account/Login",
+            "token_pointer": "/data/token",
+            "save_token": false
+        },
+        "orders": [{ "symbolIsin": "IRO1RVND0001", "side": 1, "price": 50340, "volume": 100 }]
+    }))
+    .unwrap();
+    broker.fill_from_host();
+
+    broker.ensure_session().await.unwrap();
+    assert_eq!(broker.token(), "t1");
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    rayan_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    assert_eq!(broker.token(), "t2");
+    assert_eq!(broker.session.generation(), 2);
+}
+
+async fn mock_tadbir_api_key(server: &MockServer, token: &str, api_key: &str) {
+    Mock::given(method("GET"))
+        .and(path("/api/v2/User/ApiKey"))
+        .and(header("authorization", format!("Bearer {}", token)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "apiKey": api_key })))
+        .expect(1)
+        .mount(server)
+        .await;
+}
+
+/// A Tadbir `login` section with the endpoints of the mock server, plus
+/// `extra`.
+fn tadbir_login_json(extra: serde_json::Value) -> serde_json::Value {
+    let mut login = json!({
+        "token_path": "/connect/token",
+        "client_id": "web",
+        "api_key_path": "/api/v2/User/ApiKey"
+    });
+    login
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    login
+}
+
+fn tadbir_config(server: &MockServer, extra: serde_json::Value) -> TadbirBrokerConfig {
+    let mut broker = json!({
+        "name": "tadbir",
+        "host": server.uri(),
+        "order_url": format!("{}/api/order", server.uri()),
+        "orders": [{ "isin": "IRO1RVND0001", "orderSide": 1, "price": 50340, "quantity": 100 }]
+    });
+    broker
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    let mut broker: TadbirBrokerConfig = serde_json::from_value(broker).unwrap();
+    broker.fill_from_host();
+    broker
+}
+
+#[tokio::test]
+async fn tadbir_session_is_negotiated_from_the_refresh_token() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/connect/token"))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .and(body_string_contains("refresh_token=r1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(json!({ "access_token": "t1", "refresh_token": "r2" })),
+        )
+        .expect(1)
+        .mount(&server)
+        .await;
+    mock_tadbir_api_key(&server, "t1", "k1").await;
+
+    let broker = tadbir_config(
+        &server,
+        json!({
+            "refresh_token": "r1",
+            "login": tadbir_login_json(json!({ "save_session": false }))
+        }),
+    );
+    broker.ensure_session().await.unwrap();
+
+    let credentials = broker.credentials();
+    assert_eq!(credentials.token, "t1");
+    assert_eq!(credentials.api_key, "k1");
+    assert_eq!(credentials.refresh_token, "r2");
+}
+
+#[tokio::test]
+async fn tadbir_order_falls_back_to_the_password_when_the_session_expires() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/connect/token"))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({ "error": "invalid_grant" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/connect/token"))
+        .and(body_string_contains("grant_type=password"))
+        .and(body_string_contains("username=user1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "access_token": "fresh" })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    mock_tadbir_api_key(&server, "fresh", "k2").await;
+    Mock::given(method("POST"))
+        .and(path("/api/order"))
+        .and(header("authorization", "Bearer fresh"))
+        .and(header("x-api-key", "k2"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({ "isSuccessful": true })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/api/order"))
+        .respond_with(ResponseTemplate::new(401))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let broker = tadbir_config(
+        &server,
+        json!({
+            "token": "stale",
+            "api_key": "k0",
+            "refresh_token": "expired",
+            "login": tadbir_login_json(json!({
+                "username": "user1",
+                "password": "secret",
+                "save_session": false
+            }))
+        }),
+    );
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    tadbir_broker::send_order(&broker, &order_json, false, false, None)
+        .await
+        .unwrap();
+
+    assert_eq!(broker.credentials().token, "fresh");
+    assert_eq!(broker.session.generation(), 1);
+}
+
+fn bidar_config(server: &MockServer, authorization: &str) -> BidarConfig {
+    serde_json::from_value(json!({
+        "authorization": authorization,
+        "refresh_token": "refresh-1",
+        "token_refresh": {
+            "url": format!("{}/identity/refresh", server.uri()),
+            "save_tokens": false
+        },
+        "order_url": format!("{}/trader/v1/order/buy", server.uri()),
+        "orders": [{
+            "type": "LIMIT",
+            "quantity": "1",
+            "isin": "IRO1RVND0001",
+            "validity": "DAY",
+            "price": "50340"
+        }]
+    }))
+    .unwrap()
+}
+
+#[test]
+fn bidar_reads_expiry_from_jwt() {
+    // Payload is {"exp":1700000000}.
+    let token = "e30.eyJleHAiOjE3MDAwMDAwMDB9.sig";
+    assert_eq!(login::jwt_expiry(token), Some(1_700_000_000));
+    assert_eq!(
+        login::jwt_expiry(&format!("Bearer {}", token)),
+        Some(1_700_000_000)
+    );
+    assert_eq!(login::jwt_expiry("opaque-token"), None);
+}
+
+#[tokio::test]
+async fn bidar_refreshes_expired_token_before_orders_use_it() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/identity/refresh"))
+        .and(body_string_contains(r#""refreshToken":"refresh-1""#))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "accessToken": "fresh-token",
+            "refreshToken": "refresh-2",
+            "expires_in": 3600
+        })))
+        .expect(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/trader/v1/order/buy"))
+        .and(header("authorization", "Bearer fresh-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    // Payload is {"exp":1}, long expired.
+    let broker = bidar_config(&server, "e30.eyJleHAiOjF9.sig");
+    broker.ensure_session().await.unwrap();
+    assert_eq!(broker.current_token(), "fresh-token");
+
+    let order_json = broker.order_json(&broker.orders[0]).unwrap();
+    bidar::send_order(&broker, &broker.order_url, &order_json, false, false, None)
+        .await
+        .unwrap();
+}
@@ -0,0 +1,210 @@
+use sarkhati::market_data::{self, InstrumentState, MarketData, PriceBand, TradingTrigger};
+use sarkhati::mofid::MofidOrderData;
+use sarkhati::orders::OrderEntry;
+use serde_json::json;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+fn closing_price(state: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "closingPriceInfo": { "instrumentState": { "cEtaval": state } }
+    }))
+}
+
+async fn mock_search(server: &MockServer) {
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [
+                { "insCode": "1", "instrumentID": "IRO1FOLD0002", "lVal18AFC": "فولادح" },
+                {
+                    "insCode": INS_CODE,
+                    "instrumentID": "IRO1FOLD0001",
+                    "lVal18AFC": "فولاد",
+                    "lVal30": "فولاد مباركه اصفهان"
+                }
+            ]
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn instrument_is_found_by_isin() {
+    let server = MockServer::start().await;
+    mock_search(&server).await;
+
+    let instrument = MarketData::new(&server.uri())
+        .unwrap()
+        .find_instrument("IRO1FOLD0001")
+        .await
+        .unwrap();
+    assert_eq!(instrument.ins_code, INS_CODE);
+    assert_eq!(instrument.symbol, "فولاد");
+}
+
+#[tokio::test]
+async fn quote_reports_prices_band_and_state() {
+    let server = MockServer::start().await;
+    mock_search(&server).await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/ClosingPrice/GetClosingPriceInfo/{}",
+            INS_CODE
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "closingPriceInfo": {
+                "pDrCotVal": 2490,
+                "pClosing": 2485.0,
+                "instrumentState": { "cEtaval": "A " }
+            }
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/BestLimits/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "bestLimits": [{ "number": 1, "pMeDem": 2480, "pMeOf": 0 }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/Instrument/GetStaticThreshold/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "staticThreshold": [
+                { "psGelStaMin": 2300, "psGelStaMax": 2500 },
+                { "psGelStaMin": 2360, "psGelStaMax": 2610 }
+            ]
+        })))
+        .mount(&server)
+        .await;
+
+    let quote = MarketData::new(&server.uri())
+        .unwrap()
+        .quote_isin("IRO1FOLD0001")
+        .await
+        .unwrap();
+    assert_eq!(quote.instrument.symbol, "فولاد");
+    assert_eq!(quote.quote.last_price, Some(2490.0));
+    assert_eq!(quote.quote.closing_price, Some(2485.0));
+    assert_eq!(quote.quote.best_bid, Some(2480.0));
+    assert_eq!(quote.quote.best_ask, None, "an empty sell side has no ask");
+    assert_eq!(quote.quote.state, Some(InstrumentState("A".to_string())));
+    assert_eq!(
+        quote.band,
+        PriceBand {
+            lower: 2360.0,
+            upper: 2610.0
+        }
+    );
+    assert!(quote.band.contains(2610.0) && !quote.band.contains(2620.0));
+}
+
+#[tokio::test]
+async fn trigger_releases_once_the_state_is_allowed() {
+    let server = MockServer::start().await;
+    mock_search(&server).await;
+    let state_path = format!("/ClosingPrice/GetClosingPriceInfo/{}", INS_CODE);
+    Mock::given(method("GET"))
+        .and(path(state_path.as_str()))
+        .respond_with(closing_price("I "))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(state_path.as_str()))
+        .respond_with(closing_price("A "))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let trigger: TradingTrigger = serde_json::from_value(json!({
+        "isin": "IRO1FOLD0001",
+        "poll_interval_ms": 10
+    }))
+    .unwrap();
+    market_data::wait_for_trading("test", &trigger, &server.uri(), None)
+        .await
+        .unwrap();
+}
+
+/// The search path as it arrives, with the Persian query percent-encoded.
+fn search_path(query: &str) -> String {
+    let encoded: String = query.bytes().map(|b| format!("%{:02X}", b)).collect();
+    format!("/Instrument/GetInstrumentSearch/{}", encoded)
+}
+
+async fn mock_symbol_search(server: &MockServer) {
+    // TSETMC spells symbols with the Arabic ي and ك.
+    Mock::given(method("GET"))
+        .and(path(search_path("شپنا")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [
+                { "insCode": "7745894403636165", "instrumentID": "IRO1NAFT0001", "lVal18AFC": "شپنا", "flow": 1, "cgrValCot": "N1" },
+                { "insCode": "1", "instrumentID": "IRR1NAFT0101", "lVal18AFC": "شپناح", "flow": 1 }
+            ]
+        })))
+        .mount(server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(search_path("کی")))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [
+                { "insCode": "2", "instrumentID": "IRO1KAYY0001", "lVal18AFC": "كي", "flow": 1 },
+                { "insCode": "3", "instrumentID": "IRO3KAYY0001", "lVal18AFC": "كي", "flow": 2 }
+            ]
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn symbols_resolve_to_exactly_one_isin() {
+    let server = MockServer::start().await;
+    mock_symbol_search(&server).await;
+    let market_data = MarketData::new(&server.uri()).unwrap();
+
+    let instrument = market_data.find_symbol("شپنا").await.unwrap();
+    assert_eq!(instrument.isin, "IRO1NAFT0001");
+    assert_eq!(instrument.market(), "TSE");
+    assert_eq!(instrument.board, "N1");
+
+    let error = market_data.find_symbol("کی").await.unwrap_err();
+    let error = format!("{:#}", error);
+    assert!(error.contains("IRO1KAYY0001 (TSE)") && error.contains("IRO3KAYY0001 (IFB)"));
+}
+
+#[tokio::test]
+async fn orders_configured_by_symbol_get_its_isin() {
+    let server = MockServer::start().await;
+    mock_symbol_search(&server).await;
+    let mut orders: Vec<OrderEntry<MofidOrderData>> = serde_json::from_value(json!([
+        {
+            "orderSide": "Buy", "price": 2474, "quantity": 1, "symbol": "شپنا",
+            "validityType": 0, "validityDate": null, "orderFrom": "Titan"
+        },
+        {
+            "orderSide": "Buy", "price": 2474, "quantity": 1, "symbolIsin": "IRO1FOLD0001",
+            "symbol": "شپنا", "validityType": 0, "validityDate": null, "orderFrom": "Titan"
+        }
+    ]))
+    .unwrap();
+    market_data::resolve_symbols("mofid", &mut orders, &server.uri())
+        .await
+        .unwrap();
+
+    let sent: serde_json::Value = serde_json::from_str(&orders[0].to_json().unwrap()).unwrap();
+    assert_eq!(sent["symbolIsin"], "IRO1NAFT0001");
+    assert!(sent.get("symbol").is_none(), "symbol is never sent");
+    assert_eq!(orders[1].data.symbol_isin, "IRO1FOLD0001");
+
+    orders[0].data.symbol = None;
+    orders[0].data.symbol_isin.clear();
+    let error = market_data::resolve_symbols("mofid", &mut orders, &server.uri())
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("needs an ISIN or a symbol"));
+}
@@ -0,0 +1,166 @@
+use futures::{SinkExt, StreamExt};
+use sarkhati::conditions::ConditionWatcher;
+use sarkhati::market_data::{self, InstrumentState, Quote, TradingTrigger};
+use sarkhati::market_feed::{self, FeedFields, FeedUpdate, MarketFeedConfig};
+use sarkhati::orders::OrderEntry;
+use serde_json::{Map, Value, json};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_tungstenite::tungstenite::Message;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+/// TSETMC reporting the instrument as it is before the open.
+async fn market(state: &str, best_ask: u64) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [
+                { "insCode": INS_CODE, "instrumentID": "IRO1FOLD0001", "lVal18AFC": "فولاد" }
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/ClosingPrice/GetClosingPriceInfo/{}",
+            INS_CODE
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "closingPriceInfo": { "pDrCotVal": 2490, "instrumentState": { "cEtaval": state } }
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/BestLimits/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "bestLimits": [{ "number": 1, "pMeDem": 2480, "pMeOf": best_ask }]
+        })))
+        .mount(&server)
+        .await;
+    server
+}
+
+/// A feed that reports the first message it gets, pushes `messages` and
+/// stays open.
+async fn feed(messages: Vec<Value>) -> (MarketFeedConfig, oneshot::Receiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (subscribed, subscription) = oneshot::channel();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let Some(Ok(Message::Text(first))) = socket.next().await else {
+            return;
+        };
+        let _ = subscribed.send(first.to_string());
+        for message in messages {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            socket
+                .send(Message::text(message.to_string()))
+                .await
+                .unwrap();
+        }
+        while socket.next().await.is_some() {}
+    });
+    let config = serde_json::from_value(json!({
+        "url": format!("ws://{}/quotes", address),
+        "subscribe": { "action": "subscribe", "isin": "{{isin}}" }
+    }))
+    .unwrap();
+    (config, subscription)
+}
+
+#[test]
+fn messages_are_read_through_the_configured_pointers() {
+    let fields: FeedFields = serde_json::from_value(json!({
+        "isin": "/s",
+        "best_ask": "/q/ask",
+        "last_price": null
+    }))
+    .unwrap();
+    let updates = market_feed::parse_message(
+        &fields,
+        r#"[{"s":"IRO1FOLD0001","q":{"ask":"2500"},"lastPrice":2490,"state":"A "},{"ping":1}]"#,
+    );
+    assert_eq!(
+        updates,
+        vec![FeedUpdate {
+            isin: "IRO1FOLD0001".to_string(),
+            best_ask: Some(2500.0),
+            state: Some(InstrumentState("A".to_string())),
+            ..FeedUpdate::default()
+        }]
+    );
+    assert!(market_feed::parse_message(&fields, "ping").is_empty());
+
+    let mut quote = Quote {
+        best_bid: Some(2480.0),
+        ..Quote::default()
+    };
+    updates[0].apply(&mut quote);
+    assert_eq!(
+        quote.best_bid,
+        Some(2480.0),
+        "missing fields keep their value"
+    );
+    assert_eq!(quote.best_ask, Some(2500.0));
+}
+
+#[tokio::test]
+async fn trigger_releases_on_a_pushed_state() {
+    let server = market("I ", 2510).await;
+    let (config, subscription) = feed(vec![
+        json!({ "isin": "IRO1FOLD0001", "state": "AR" }),
+        json!({ "isin": "IRO1FOLD0001", "state": "A" }),
+    ])
+    .await;
+    // Polling alone would not see the change within the test.
+    let trigger: TradingTrigger = serde_json::from_value(json!({
+        "isin": "IRO1FOLD0001",
+        "poll_interval_ms": 60000
+    }))
+    .unwrap();
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        market_data::wait_for_trading("test", &trigger, &server.uri(), Some(&config)),
+    )
+    .await
+    .expect("the pushed state should release the trigger")
+    .unwrap();
+    assert_eq!(
+        serde_json::from_str::<Value>(&subscription.await.unwrap()).unwrap(),
+        json!({ "action": "subscribe", "isin": "IRO1FOLD0001" })
+    );
+}
+
+#[tokio::test]
+async fn conditions_are_armed_by_pushed_quotes() {
+    let server = market("A", 2510).await;
+    let (config, _subscription) = feed(vec![
+        json!({ "isin": "IRO1FOLD0001", "bestAsk": 2505 }),
+        json!({ "isin": "IRO1FOLD0001", "bestAsk": 2500 }),
+    ])
+    .await;
+    let orders: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "isin": "IRO1FOLD0001", "send_when": "best_ask <= 2500" }
+    ]))
+    .unwrap();
+
+    let watcher = ConditionWatcher::start("test", &orders, &server.uri(), 60000, Some(&config))
+        .await
+        .unwrap()
+        .unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !watcher.is_armed(0) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the pushed best ask should arm the order");
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{posted_orders, rejecting_server};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use std::time::{Duration, Instant};
+use wiremock::{MockServer, ResponseTemplate};
+
+fn broker(server: &MockServer, extra: Value) -> CustomBrokersConfig {
+    let mut broker = json!({
+        "name": "acme",
+        "order_url": format!("{}/orders", server.uri()),
+        "body_template": { "symbol": "{{isin}}" },
+        "batch_delay_ms": 50,
+        "batch_sinks": [],
+        "max_runtime_secs": 1,
+        "orders": [{ "isin": "IRO1FOLD0001" }]
+    });
+    broker
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    serde_json::from_value(json!({ "brokers": [broker] })).unwrap()
+}
+
+#[tokio::test]
+async fn continuous_run_stops_after_max_runtime() {
+    let server =
+        rejecting_server(ResponseTemplate::new(400).set_body_string("price out of range")).await;
+    let mut config = broker(&server, json!({}));
+    let started = Instant::now();
+
+    let result = tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the run stops by itself");
+
+    assert!(result.is_ok());
+    assert!(started.elapsed() >= Duration::from_secs(1));
+    assert!(posted_orders(&server).await > 1);
+}
+
+#[tokio::test]
+async fn dedicated_dispatch_thread_stops_with_the_run() {
+    let server =
+        rejecting_server(ResponseTemplate::new(400).set_body_string("price out of range")).await;
+    let mut config = broker(&server, json!({ "dispatch_thread": {} }));
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the run stops by itself")
+    .unwrap();
+
+    let sent = posted_orders(&server).await;
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(posted_orders(&server).await, sent);
+}
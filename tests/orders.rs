@@ -0,0 +1,469 @@
+use sarkhati::bidar::BidarOrderData;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::jalali;
+use sarkhati::mofid::MofidOrderData;
+use sarkhati::orders::{self, OrderEntry, OrderFilter};
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Map, Value, json};
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn orders() -> Vec<OrderEntry<Map<String, Value>>> {
+    serde_json::from_value(json!([
+        { "isin": "IRO1FOLD0001", "quantity": 1, "tags": ["ipo"] },
+        { "isin": "IRO1RVND0001", "quantity": 2, "tags": ["hedge"] },
+        { "isin": "IRO1FOLD0001", "quantity": 3, "tags": ["IPO", "hedge"] },
+        { "isin": "IRO1FOLD0001", "quantity": 4 },
+        { "isin": "IRO1KHOD0001", "quantity": 5 }
+    ]))
+    .unwrap()
+}
+
+fn quantities(orders: &[OrderEntry<Map<String, Value>>]) -> Vec<i64> {
+    orders
+        .iter()
+        .map(|order| order.data["quantity"].as_i64().unwrap())
+        .collect()
+}
+
+#[test]
+fn positions_and_symbol_select_matching_orders() {
+    let mut selected = orders();
+    OrderFilter::new(Some("1,3-5"), None, &[])
+        .unwrap()
+        .retain(&mut selected)
+        .unwrap();
+    assert_eq!(quantities(&selected), [1, 3, 4, 5]);
+
+    let mut selected = orders();
+    OrderFilter::new(Some("1,3-5"), Some("iro1fold0001"), &[])
+        .unwrap()
+        .retain(&mut selected)
+        .unwrap();
+    assert_eq!(quantities(&selected), [1, 3, 4]);
+}
+
+#[test]
+fn tags_select_orders_carrying_any_of_them() {
+    let mut selected = orders();
+    OrderFilter::new(None, None, &["ipo".to_string()])
+        .unwrap()
+        .retain(&mut selected)
+        .unwrap();
+    assert_eq!(quantities(&selected), [1, 3]);
+
+    let mut selected = orders();
+    OrderFilter::default()
+        .with_tags(&["ipo".to_string(), "hedge".to_string()])
+        .retain(&mut selected)
+        .unwrap();
+    assert_eq!(quantities(&selected), [1, 2, 3]);
+
+    // Tags are options, never part of the request body.
+    assert_eq!(
+        orders()[0].to_json().unwrap(),
+        r#"{"isin":"IRO1FOLD0001","quantity":1}"#
+    );
+}
+
+#[test]
+fn malformed_positions_are_rejected() {
+    for positions in ["", "0", "3-1", "a", "1,,2"] {
+        assert!(
+            OrderFilter::new(Some(positions), None, &[]).is_err(),
+            "{:?} should be rejected",
+            positions
+        );
+    }
+}
+
+#[test]
+fn jalali_dates() {
+    let date = |y, m, d| chrono::NaiveDate::from_ymd_opt(y, m, d).unwrap();
+    assert_eq!(jalali::format(date(2025, 3, 21)), "1404/01/01");
+    assert_eq!(jalali::format(date(2025, 10, 16)), "1404/07/24");
+    assert_eq!(jalali::format(date(2025, 3, 20)), "1403/12/30");
+    assert_eq!(jalali::format(date(2024, 12, 31)), "1403/10/11");
+}
+
+#[test]
+fn today_validity_dates_are_filled_when_sent() {
+    let order: OrderEntry<Map<String, Value>> = serde_json::from_value(json!({
+        "orderValiditydate": "today",
+        "validityDate": "today+2",
+        "note": "today"
+    }))
+    .unwrap();
+    let today = chrono::Utc::now()
+        .with_timezone(&chrono_tz::Asia::Tehran)
+        .date_naive();
+
+    let body: Value = serde_json::from_str(&order.to_json().unwrap()).unwrap();
+    assert_eq!(body["orderValiditydate"], jalali::format(today));
+    assert_eq!(
+        body["validityDate"],
+        jalali::format(today + chrono::Duration::days(2))
+    );
+    assert_eq!(body["note"], "today");
+}
+
+#[test]
+fn a_relative_date_cannot_also_be_varied() {
+    let error = serde_json::from_value::<OrderEntry<Map<String, Value>>>(json!({
+        "validityDate": "today+1",
+        "vary": { "dates": { "validityDate": "%Y-%m-%d" } }
+    }))
+    .err()
+    .unwrap();
+    assert!(
+        error
+            .to_string()
+            .contains("'validityDate' is a relative date and also in vary.dates"),
+        "{}",
+        error
+    );
+
+    // A date field that is not relative can still be varied.
+    let order: OrderEntry<Map<String, Value>> = serde_json::from_value(json!({
+        "validityDate": null,
+        "vary": { "dates": { "validityDate": "%Y-%m-%d" } }
+    }))
+    .unwrap();
+    assert!(order.render().unwrap()["validityDate"].is_string());
+}
+
+#[test]
+fn variations_jitter_and_alternate_per_attempt() {
+    let order: OrderEntry<Map<String, Value>> = serde_json::from_value(json!({
+        "orderPrice": 2474,
+        "orderCount": 2,
+        "vary": {
+            "jitter": { "orderCount": 5 },
+            "alternate": { "orderPrice": [2474, 2475] }
+        }
+    }))
+    .unwrap();
+
+    for attempt in 0..20 {
+        let body = order.render().unwrap();
+        let price = if attempt % 2 == 0 { 2474 } else { 2475 };
+        assert_eq!(body["orderPrice"], price);
+        let count = body["orderCount"].as_i64().unwrap();
+        assert!((1..=7).contains(&count), "orderCount {}", count);
+    }
+}
+
+#[test]
+fn priority_order_puts_higher_priorities_first() {
+    let orders: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "quantity": 1 },
+        { "quantity": 2, "priority": 5 },
+        { "quantity": 3, "priority": -1 },
+        { "quantity": 4, "priority": 5 }
+    ]))
+    .unwrap();
+    assert_eq!(orders::priority_order(&orders), [1, 3, 0, 2]);
+}
+
+#[tokio::test]
+async fn priority_order_sends_a_batch_strictly_by_priority() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "qty": "{{quantity}}" },
+            "batch_delay_ms": 100,
+            "priority_order": true,
+            "orders": [
+                { "quantity": 1 },
+                { "quantity": 2, "priority": 10 },
+                { "quantity": 3, "priority": 5 }
+            ]
+        }]
+    }))
+    .unwrap();
+    // Batches are spaced by the rate limiter the whole batch queues on.
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+    let mut requests = Vec::new();
+    for _ in 0..100 {
+        requests = server.received_requests().await.unwrap();
+        if requests.len() >= 3 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    run.abort();
+
+    let quantities: Vec<i64> = requests[..3]
+        .iter()
+        .map(|request| {
+            serde_json::from_slice::<Value>(&request.body).unwrap()["qty"]
+                .as_i64()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(quantities, [2, 3, 1]);
+}
+
+#[test]
+fn batch_sequence_repeats_orders_back_to_back() {
+    let orders: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "quantity": 1, "repeat": 3 },
+        { "quantity": 2 },
+        { "quantity": 3, "repeat": 2 }
+    ]))
+    .unwrap();
+    assert_eq!(
+        orders::batch_sequence(&orders, &[2, 0, 1], 1),
+        [2, 2, 0, 0, 0, 1]
+    );
+    assert_eq!(
+        orders::batch_sequence(&orders, &[1, 2], 2),
+        [1, 2, 2, 1, 2, 2]
+    );
+
+    let error = serde_json::from_value::<OrderEntry<Map<String, Value>>>(json!({
+        "quantity": 1,
+        "repeat": 0
+    }))
+    .unwrap_err();
+    assert!(error.to_string().contains("repeat must be >= 1"));
+}
+
+#[test]
+fn ladders_expand_into_one_order_per_price() {
+    let mut orders: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "quantity": 1, "price": 1000 },
+        { "quantity": 2, "price": 2480, "ladder": { "step": -10, "levels": 3 } },
+        { "quantity": 3, "price": "0", "ladder": { "base_price": 500, "step": 5, "levels": 2 } }
+    ]))
+    .unwrap();
+    orders::expand_ladders("Test", &mut orders).unwrap();
+    let prices: Vec<Value> = orders
+        .iter()
+        .map(|order| order.data["price"].clone())
+        .collect();
+    assert_eq!(
+        prices,
+        [
+            json!(1000),
+            json!(2480),
+            json!(2470),
+            json!(2460),
+            json!("500"),
+            json!("505")
+        ]
+    );
+    assert_eq!(quantities(&orders), [1, 2, 2, 2, 3, 3]);
+    assert!(orders.iter().all(|order| order.ladder.is_none()));
+
+    let mut limit: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "quantity": 1, "price": "upper_limit", "ladder": { "step": -10, "levels": 3 } }
+    ]))
+    .unwrap();
+    let error = orders::expand_ladders("Test", &mut limit).unwrap_err();
+    assert!(error.to_string().contains("cannot also have a ladder"));
+
+    let mut negative: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "quantity": 1, "price": 20, "ladder": { "step": -10, "levels": 3 } }
+    ]))
+    .unwrap();
+    assert!(orders::expand_ladders("Test", &mut negative).is_err());
+}
+
+#[test]
+fn tranches_split_the_quantity_by_share() {
+    let mut orders: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "quantity": 7, "tranches": [
+            { "share": 50 },
+            { "share": 25, "after_ms": 2000 },
+            { "share": 25, "after_ms": 5000 }
+        ] },
+        { "quantity": 9 }
+    ]))
+    .unwrap();
+    orders::expand_tranches("Test", &mut orders).unwrap();
+    assert_eq!(quantities(&orders), [4, 2, 1, 9]);
+    let offsets: Vec<u64> = orders.iter().map(|order| order.send_after_ms).collect();
+    assert_eq!(offsets, [0, 2000, 5000, 0]);
+
+    for tranches in [
+        json!([{ "share": 50 }, { "share": 40, "after_ms": 1000 }]),
+        json!([{ "share": 50, "after_ms": 1000 }, { "share": 50, "after_ms": 2000 }]),
+        json!([{ "share": 50 }, { "share": 25, "after_ms": 2000 }, { "share": 25, "after_ms": 1000 }]),
+    ] {
+        let mut orders: Vec<OrderEntry<Map<String, Value>>> =
+            serde_json::from_value(json!([{ "quantity": 10, "tranches": tranches }])).unwrap();
+        assert!(orders::expand_tranches("Test", &mut orders).is_err());
+    }
+}
+
+#[tokio::test]
+async fn later_tranches_wait_for_their_offset() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "qty": "{{quantity}}" },
+            "batch_delay_ms": 100,
+            "orders": [{ "quantity": 10, "tranches": [
+                { "share": 60 },
+                { "share": 40, "after_ms": 600 }
+            ] }]
+        }]
+    }))
+    .unwrap();
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+
+    let sent = || async {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| serde_json::from_slice::<Value>(&request.body).unwrap()["qty"].clone())
+            .collect::<Vec<Value>>()
+    };
+    while sent().await.is_empty() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(300)).await;
+    assert!(sent().await.iter().all(|qty| qty == 6));
+    tokio::time::sleep(std::time::Duration::from_millis(700)).await;
+    assert!(sent().await.iter().any(|qty| qty == 4));
+    run.abort();
+}
+
+#[tokio::test]
+async fn order_spacing_releases_a_batch_one_order_at_a_time() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "qty": "{{quantity}}" },
+            "batch_delay_ms": 100,
+            "order_spacing_ms": 400,
+            "orders": [{ "quantity": 1 }, { "quantity": 2 }]
+        }]
+    }))
+    .unwrap();
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+
+    while server.received_requests().await.unwrap().is_empty() {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+    tokio::time::sleep(std::time::Duration::from_millis(400)).await;
+    let requests = server.received_requests().await.unwrap();
+    assert!(requests.len() >= 2);
+    let second: Value = serde_json::from_slice(&requests[1].body).unwrap();
+    assert_eq!(second["qty"], 2);
+    run.abort();
+}
+
+#[test]
+fn a_misspelled_field_is_reported_by_name() {
+    // The limit price's placeholder and the text quantity used to be tried
+    // in several forms, and the error of the first try was reported.
+    let error = serde_json::from_value::<OrderEntry<BidarOrderData>>(json!({
+        "type": "BUY",
+        "quantty": "10",
+        "isin": "IRO1FOLD0001",
+        "validity": "DAY",
+        "price": "upper_limit"
+    }))
+    .err()
+    .unwrap();
+    assert_eq!(error.to_string(), "missing field `quantity`");
+
+    let error = serde_json::from_value::<OrderEntry<MofidOrderData>>(json!({
+        "orderSide": "Buy",
+        "price": "۲٬۴۷۴",
+        "quantity": "1,000",
+        "validityType": 0,
+        "validityDate": null,
+        "ordrFrom": "Titan"
+    }))
+    .err()
+    .unwrap();
+    assert_eq!(error.to_string(), "missing field `orderFrom`");
+
+    let error = serde_json::from_value::<OrderEntry<MofidOrderData>>(json!({
+        "orderSide": "Buy",
+        "price": "about 2474",
+        "quantity": 1000,
+        "validityType": 0,
+        "validityDate": null,
+        "orderFrom": "Titan"
+    }))
+    .err()
+    .unwrap();
+    assert!(
+        error
+            .to_string()
+            .contains("invalid type: string \"about 2474\""),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn persian_digits_and_letters_in_orders_are_normalized() {
+    let order: OrderEntry<MofidOrderData> = serde_json::from_value(json!({
+        "orderSide": "Buy",
+        "price": "۲٬۴۷۴",
+        "quantity": "1,000",
+        "symbol": "فولاد كاوه",
+        "validityType": 0,
+        "validityDate": null,
+        "orderFrom": "Titan"
+    }))
+    .unwrap();
+    assert_eq!(order.data.price, 2474);
+    assert_eq!(order.data.quantity, 1000);
+    assert_eq!(order.data.symbol.as_deref(), Some("فولاد کاوه"));
+
+    // Bidar takes numbers as text and keeps them so, in ASCII digits.
+    let order: OrderEntry<BidarOrderData> = serde_json::from_value(json!({
+        "type": "BUY",
+        "quantity": "۱۰",
+        "isin": "IRO1FOLD0001",
+        "validity": "DAY",
+        "price": "upper_limit"
+    }))
+    .unwrap();
+    assert_eq!(order.data.quantity, "10");
+    assert_eq!(order.data.price, "0");
+    assert!(order.price_limit.is_some());
+
+    // A custom broker gets text as it was typed, digits aside.
+    let custom: Vec<OrderEntry<Map<String, Value>>> =
+        serde_json::from_value(json!([{ "quantity": "٣", "price": 2474 }])).unwrap();
+    assert_eq!(custom[0].data["quantity"], "3");
+    assert_eq!(custom[0].data["price"], 2474);
+
+    // --symbol matches whichever ye either side was typed with.
+    let mut selected = orders();
+    selected[1].data.insert("symbol".into(), json!("وبملي"));
+    OrderFilter::new(None, Some("وبملی"), &[])
+        .unwrap()
+        .retain(&mut selected)
+        .unwrap();
+    assert_eq!(quantities(&selected), [2]);
+}
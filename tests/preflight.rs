@@ -0,0 +1,93 @@
+use sarkhati::bidar::BidarConfig;
+use sarkhati::doctor::CheckState;
+use sarkhati::preflight;
+use serde_json::json;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn bidar(server: &MockServer, target_time: Option<&str>) -> BidarConfig {
+    serde_json::from_value(json!({
+        "authorization": "token",
+        "order_url": format!("{}/trader/v1/order/buy", server.uri()),
+        "market_data_url": server.uri(),
+        "target_time": target_time,
+        "orders": [{
+            "type": "BUY",
+            "quantity": "10",
+            "isin": "IRO1FOLD0001",
+            "validity": "DAY",
+            "price": "2474"
+        }]
+    }))
+    .unwrap()
+}
+
+fn states(preflight: &preflight::Preflight) -> Vec<(&str, CheckState)> {
+    preflight
+        .checks
+        .iter()
+        .map(|check| (check.name.as_str(), check.state))
+        .collect()
+}
+
+#[tokio::test]
+async fn a_ready_broker_is_a_go() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(405))
+        .mount(&server)
+        .await;
+
+    let preflights =
+        preflight::check_broker(&bidar(&server, None), 3, &reqwest::Client::new()).await;
+    assert_eq!(preflights.len(), 1);
+    assert_eq!(
+        states(&preflights[0]),
+        [
+            ("Config", CheckState::Pass),
+            ("Session", CheckState::Pass),
+            ("Prices", CheckState::Pass),
+            ("Warm-up", CheckState::Pass),
+            ("Calibration", CheckState::Pass),
+        ]
+    );
+    assert!(preflights[0].go());
+    assert!(
+        preflights[0].checks[4].detail.contains("over 2 probe(s)"),
+        "{}",
+        preflights[0].checks[4].detail
+    );
+}
+
+#[tokio::test]
+async fn an_expired_session_or_a_bad_target_time_is_a_no_go() {
+    let server = MockServer::start().await;
+    Mock::given(method("HEAD"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(401))
+        .mount(&server)
+        .await;
+
+    let preflights =
+        preflight::check_broker(&bidar(&server, Some("8:45")), 2, &reqwest::Client::new()).await;
+    let preflight = &preflights[0];
+    assert!(!preflight.go());
+    assert_eq!(preflight.checks[0].state, CheckState::Fail);
+    assert!(preflight.checks[0].detail.contains("HH:MM:SS.mmm"));
+    assert_eq!(preflight.checks[1].name, "Session");
+    assert_eq!(preflight.checks[1].state, CheckState::Fail);
+    assert!(
+        preflight.checks[1]
+            .hint
+            .as_deref()
+            .unwrap()
+            .contains("login")
+    );
+}
@@ -0,0 +1,92 @@
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::market_data::PriceBand;
+use sarkhati::price_check::price_problem;
+use sarkhati::runner::{RunOptions, run_broker};
+use serde_json::{Value, json};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+#[test]
+fn prices_must_sit_on_a_tick_inside_the_band() {
+    let band = PriceBand {
+        lower: 2360.0,
+        upper: 2610.0,
+    };
+    assert_eq!(price_problem(2610.0, 10.0, Some(&band)), None);
+    assert_eq!(price_problem(2360.0, 1.0, None), None);
+    assert!(
+        price_problem(2615.0, 10.0, None)
+            .unwrap()
+            .contains("tick size 10")
+    );
+    assert!(
+        price_problem(2620.0, 10.0, Some(&band))
+            .unwrap()
+            .contains("above")
+    );
+    assert!(
+        price_problem(2350.0, 10.0, Some(&band))
+            .unwrap()
+            .contains("below")
+    );
+}
+
+async fn run_with(price_check: Value) -> (anyhow::Result<()>, usize) {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [{ "insCode": INS_CODE, "instrumentID": "IRO1FOLD0001" }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!("/Instrument/GetStaticThreshold/{}", INS_CODE)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "staticThreshold": [{ "psGelStaMin": 2360, "psGelStaMax": 2610 }]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "market_data_url": server.uri(),
+            "price_check": price_check,
+            "body_template": { "symbol": "{{isin}}", "price": "{{price}}" },
+            "orders": [{ "isin": "IRO1FOLD0001", "price": 2700 }]
+        }]
+    }))
+    .unwrap();
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+    let result = run_broker(config.brokers.remove(0), options).await;
+    let posted = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.method.as_str() == "POST")
+        .count();
+    (result, posted)
+}
+
+#[tokio::test]
+async fn out_of_band_prices_refuse_the_run_unless_only_warned_about() {
+    let (result, posted) = run_with(json!({})).await;
+    assert!(format!("{:#}", result.unwrap_err()).contains("1 order(s) of acme"));
+    assert_eq!(posted, 0);
+
+    let (result, posted) = run_with(json!({ "on_invalid": "warn" })).await;
+    result.unwrap();
+    assert_eq!(posted, 1);
+}
@@ -0,0 +1,45 @@
+use sarkhati::rate_limiter::{PreciseRateLimit, RateLimiter};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn precise_mode_honors_sub_millisecond_gaps() {
+    let precise: PreciseRateLimit = serde_json::from_value(json!({ "interval_us": 300 })).unwrap();
+    assert_eq!(precise.spin_us, 1500);
+    let limiter = RateLimiter::new(100).with_precise(Some(&precise));
+    assert_eq!(limiter.rate_limit_ms(), 1);
+
+    let mut sent = Vec::new();
+    for _ in 0..20 {
+        limiter.wait().await;
+        sent.push(Instant::now());
+    }
+    let gaps: Vec<Duration> = sent.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    // Timestamps taken after each wait returns, so allow a little slack.
+    assert!(
+        gaps.iter().all(|gap| *gap >= Duration::from_micros(280)),
+        "{:?}",
+        gaps
+    );
+    // Sleeping would round every gap up to the timer's millisecond.
+    let mean = gaps.iter().sum::<Duration>() / gaps.len() as u32;
+    assert!(mean < Duration::from_micros(900), "mean gap {:?}", mean);
+}
+
+#[tokio::test]
+async fn precise_mode_keeps_batch_delay_without_an_interval() {
+    let precise: PreciseRateLimit = serde_json::from_value(json!({ "spin_us": 500 })).unwrap();
+    let limiter = RateLimiter::new(5).with_precise(Some(&precise));
+    assert_eq!(limiter.rate_limit_ms(), 5);
+
+    limiter.wait().await;
+    let start = Instant::now();
+    limiter.wait().await;
+    let gap = start.elapsed();
+    assert!(gap >= Duration::from_micros(4_900), "gap {:?}", gap);
+    assert!(
+        gap < Duration::from_millis(5) + Duration::from_millis(3),
+        "gap {:?}",
+        gap
+    );
+}
@@ -0,0 +1,19 @@
+use sarkhati::registry::exe_config_dir;
+
+#[test]
+fn falls_back_to_the_executable_folder_for_configs() {
+    let root = std::env::temp_dir().join(format!("sarkhati-registry-{}", std::process::id()));
+    let (cwd, install) = (root.join("cwd"), root.join("install"));
+    std::fs::create_dir_all(&cwd).unwrap();
+    std::fs::create_dir_all(&install).unwrap();
+    let exe = install.join("sarkhati.exe");
+
+    assert_eq!(exe_config_dir(&cwd, &exe), None);
+    std::fs::write(install.join("config_standard.json.enc"), "").unwrap();
+    assert_eq!(exe_config_dir(&cwd, &exe), Some(install.clone()));
+    // Configs in the current folder win.
+    std::fs::write(cwd.join("config_mofid.json"), "{}").unwrap();
+    assert_eq!(exe_config_dir(&cwd, &exe), None);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
@@ -0,0 +1,72 @@
+use sarkhati::{encryption, remote_config};
+use serde_json::{Value, json};
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn fetches_configs_and_falls_back_to_the_cache() {
+    let server = MockServer::start().await;
+    let cache = std::env::temp_dir().join(format!(
+        "sarkhati-remote-config-{}.json",
+        std::process::id()
+    ));
+    let cache = cache.to_str().unwrap();
+    let url = format!("{}/sarkhati.json", server.uri());
+
+    Mock::given(method("GET"))
+        .and(path("/sarkhati.json"))
+        .and(header("Authorization", "Bearer team-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "config_remote_test.json": { "brokers": [{ "name": "central" }] }
+        })))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    remote_config::load(&url, Some("Authorization: Bearer team-token"), cache)
+        .await
+        .unwrap();
+    // The cache holds sessions and passwords.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(cache).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+    assert!(encryption::config_exists("config_remote_test.json"));
+    let config: Value =
+        serde_json::from_str(&encryption::read_config("config_remote_test.json").unwrap()).unwrap();
+    assert_eq!(config["brokers"][0]["name"], "central");
+
+    // Writes stay in memory rather than creating the file.
+    encryption::write_config("config_remote_test.json", r#"{"brokers":[]}"#).unwrap();
+    assert_eq!(
+        encryption::read_config("config_remote_test.json").unwrap(),
+        r#"{"brokers":[]}"#
+    );
+    assert!(!std::path::Path::new("config_remote_test.json").exists());
+
+    Mock::given(method("GET"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    remote_config::load(&url, None, cache).await.unwrap();
+    let config: Value =
+        serde_json::from_str(&encryption::read_config("config_remote_test.json").unwrap()).unwrap();
+    assert_eq!(config["brokers"][0]["name"], "central");
+
+    std::fs::remove_file(cache).unwrap();
+    assert!(remote_config::load(&url, None, cache).await.is_err());
+}
+
+#[tokio::test]
+async fn plain_http_is_refused_off_this_machine() {
+    let error = remote_config::load(
+        "http://configs.example.com/sarkhati.json",
+        None,
+        "remote_config_http_test.cache.json",
+    )
+    .await
+    .unwrap_err();
+    assert!(error.to_string().contains("plain http"));
+    assert!(!std::path::Path::new("remote_config_http_test.cache.json").exists());
+}
@@ -0,0 +1,163 @@
+use reqwest::StatusCode;
+use sarkhati::errors::{OrderError, OrderErrorKind};
+use sarkhati::responses::{
+    BidarOrderResponse, DanayanOrderResponse, ExirOrderResponse, MobinOrderResponse,
+    MofidOrderResponse, OnlinePlusOrderResponse, OrderResponse, PasargadOrderResponse,
+    RayanOrderResponse, StandardOrderResponse, TadbirOrderResponse, parse,
+};
+use sarkhati::success::check_response_as;
+
+#[test]
+fn each_broker_format_is_understood() {
+    assert_eq!(
+        parse::<MofidOrderResponse>(
+            StatusCode::OK,
+            r#"{"isSuccessfull":true,"omsOrderId":1234,"message":null}"#
+        ),
+        Some(OrderResponse::Accepted {
+            order_id: Some("1234".to_string())
+        })
+    );
+    assert_eq!(
+        parse::<MofidOrderResponse>(
+            StatusCode::OK,
+            r#"{"isSuccessfull":false,"message":"خطا","omsErrorDescription":"قیمت خارج از محدوده مجاز"}"#
+        ),
+        Some(OrderResponse::ValidationError {
+            message: "قیمت خارج از محدوده مجاز".to_string()
+        })
+    );
+    assert_eq!(
+        parse::<StandardOrderResponse>(
+            StatusCode::OK,
+            r#"{"IsSuccessfull":true,"MessageDesc":"","Data":"98765"}"#
+        ),
+        Some(OrderResponse::Accepted {
+            order_id: Some("98765".to_string())
+        })
+    );
+    assert_eq!(
+        parse::<ExirOrderResponse>(
+            StatusCode::BAD_REQUEST,
+            r#"{"errors":[{"field":"price","message":"price out of range"}]}"#
+        ),
+        Some(OrderResponse::ValidationError {
+            message: "price out of range".to_string()
+        })
+    );
+    assert_eq!(
+        parse::<DanayanOrderResponse>(StatusCode::OK, r#"{"orderId":"D-1"}"#),
+        Some(OrderResponse::Accepted {
+            order_id: Some("D-1".to_string())
+        })
+    );
+    assert_eq!(
+        parse::<PasargadOrderResponse>(
+            StatusCode::OK,
+            r#"{"isSuccess":true,"message":null,"data":{"orderId":5512}}"#
+        ),
+        Some(OrderResponse::Accepted {
+            order_id: Some("5512".to_string())
+        })
+    );
+    assert_eq!(
+        parse::<MobinOrderResponse>(
+            StatusCode::OK,
+            r#"{"succeeded":false,"messages":["اعتبار کافی نیست"]}"#
+        ),
+        Some(OrderResponse::ValidationError {
+            message: "اعتبار کافی نیست".to_string()
+        })
+    );
+    assert_eq!(
+        parse::<RayanOrderResponse>(
+            StatusCode::OK,
+            r#"{"isSuccess":false,"message":"قیمت خارج از محدوده مجاز است"}"#
+        ),
+        Some(OrderResponse::ValidationError {
+            message: "قیمت خارج از محدوده مجاز است".to_string()
+        })
+    );
+    assert_eq!(
+        parse::<OnlinePlusOrderResponse>(StatusCode::OK, r#"{"Succeeded":true,"OrderId":3312}"#),
+        Some(OrderResponse::Accepted {
+            order_id: Some("3312".to_string())
+        })
+    );
+    assert_eq!(
+        parse::<TadbirOrderResponse>(
+            StatusCode::OK,
+            r#"{"isSuccessful":true,"result":{"orderId":8841}}"#
+        ),
+        Some(OrderResponse::Accepted {
+            order_id: Some("8841".to_string())
+        })
+    );
+    assert_eq!(
+        parse::<BidarOrderResponse>(
+            StatusCode::OK,
+            r#"{"succeeded":true,"error":"token expired"}"#
+        ),
+        Some(OrderResponse::AuthError {
+            message: "token expired".to_string()
+        })
+    );
+}
+
+#[test]
+fn bodies_that_do_not_say_are_left_to_the_status() {
+    assert_eq!(parse::<DanayanOrderResponse>(StatusCode::OK, "OK"), None);
+    assert_eq!(parse::<DanayanOrderResponse>(StatusCode::OK, "{}"), None);
+    assert_eq!(
+        parse::<MofidOrderResponse>(StatusCode::UNAUTHORIZED, "{}"),
+        Some(OrderResponse::AuthError {
+            message: String::new()
+        })
+    );
+    assert!(check_response_as::<DanayanOrderResponse>(&[], StatusCode::OK, "OK".into()).is_ok());
+    assert!(
+        check_response_as::<DanayanOrderResponse>(&[], StatusCode::BAD_GATEWAY, "<html>".into())
+            .is_err()
+    );
+}
+
+#[test]
+fn an_error_payload_with_200_is_a_rejection() {
+    let error = check_response_as::<StandardOrderResponse>(
+        &[],
+        StatusCode::OK,
+        r#"{"IsSuccessfull":false,"MessageDesc":"سفارش تکراری است"}"#.into(),
+    )
+    .unwrap_err();
+    let error = error.downcast_ref::<OrderError>().unwrap();
+    assert_eq!(error.kind, OrderErrorKind::DuplicateOrder);
+    assert_eq!(
+        error.response,
+        Some(OrderResponse::ValidationError {
+            message: "سفارش تکراری است".to_string()
+        })
+    );
+}
+
+#[test]
+fn a_rejected_session_is_an_auth_error_whatever_the_message() {
+    let error = check_response_as::<MofidOrderResponse>(
+        &[],
+        StatusCode::FORBIDDEN,
+        r#"{"isSuccessfull":false,"message":"price out of range"}"#.into(),
+    )
+    .unwrap_err();
+    let error = error.downcast_ref::<OrderError>().unwrap();
+    assert_eq!(error.kind, OrderErrorKind::AuthExpired);
+}
+
+#[test]
+fn messages_match_however_digits_and_letters_were_written() {
+    let error = OrderError::new(
+        StatusCode::BAD_REQUEST,
+        "حداقل مبلغ سفارش ۵٬۰۰۰٬۰۰۰ ريال است؛ موجودي كافي نيست".to_string(),
+    );
+    assert_eq!(error.kind, OrderErrorKind::InsufficientFunds);
+    assert!(error.mentions(&["5000000 ریال".to_string()]));
+    assert!(error.mentions(&["5,000,000".to_string()]));
+}
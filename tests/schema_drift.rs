@@ -0,0 +1,112 @@
+mod common;
+
+use common::temp_path;
+use reqwest::StatusCode;
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use sarkhati::schema_drift::{self, shape};
+use serde_json::{Value, json};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[test]
+fn shapes_record_nested_fields_and_array_elements() {
+    let shape = shape(&json!({
+        "isSuccessful": true,
+        "data": { "orderId": 12, "note": null },
+        "errors": [{ "code": "E1" }]
+    }));
+
+    assert_eq!(shape["/isSuccessful"], "bool");
+    assert_eq!(shape["/data/orderId"], "number");
+    assert_eq!(shape["/data/note"], "null");
+    assert_eq!(shape["/errors"], "array");
+    assert_eq!(shape["/errors[]/code"], "string");
+}
+
+#[test]
+fn drift_reports_gone_fields_type_changes_and_new_error_fields() {
+    let expected =
+        shape(&json!({ "isSuccessful": true, "data": { "orderId": 12 }, "list": [{ "a": 1 }] }));
+    let current = shape(&json!({ "data": { "orderId": "12" }, "list": [], "errorMessage": "x" }));
+
+    let changes = schema_drift::drift(&expected, &current);
+
+    assert_eq!(
+        changes,
+        [
+            "fields gone: /isSuccessful",
+            "types changed: /data/orderId number -> string",
+            "new error-like fields: /errorMessage"
+        ]
+    );
+}
+
+#[test]
+fn null_fields_and_empty_arrays_are_not_drift() {
+    let expected = shape(&json!({ "data": { "orderId": 12 }, "list": [{ "a": 1 }], "note": "x" }));
+    let current = shape(&json!({ "data": null, "list": [], "note": null }));
+
+    assert!(schema_drift::drift(&expected, &current).is_empty());
+}
+
+#[test]
+fn first_response_of_each_class_is_learned_then_compared() {
+    let file = temp_path("learned-schemas.json");
+    let file_name = file.to_str().unwrap();
+
+    let learned = schema_drift::check("acme", file_name, StatusCode::OK, r#"{"ok":true,"id":1}"#);
+    assert!(learned.is_empty());
+    let stored: Value = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(stored["2xx"]["/ok"], "bool");
+
+    assert!(
+        schema_drift::check("acme", file_name, StatusCode::OK, r#"{"ok":false,"id":2}"#).is_empty()
+    );
+    let drifted = schema_drift::check("acme", file_name, StatusCode::OK, r#"{"id":3}"#);
+    assert_eq!(drifted, ["2xx responses changed shape: fields gone: /ok"]);
+    // Each change is reported once.
+    assert!(schema_drift::check("acme", file_name, StatusCode::OK, r#"{"id":4}"#).is_empty());
+    assert_eq!(
+        schema_drift::check(
+            "acme",
+            file_name,
+            StatusCode::OK,
+            "<html>maintenance</html>"
+        ),
+        ["2xx responses changed shape: the body is no longer JSON"]
+    );
+    let _ = std::fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn order_responses_are_checked_against_the_schema_file() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200).set_body_string(r#"{"isSuccessful":true}"#))
+        .mount(&server)
+        .await;
+    let file = temp_path("order-schemas.json");
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_sinks": [],
+            "response_schema_file": file,
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap();
+    let options = RunOptions {
+        test_mode: true,
+        ..RunOptions::default()
+    };
+
+    run_broker(config.brokers.remove(0), options).await.unwrap();
+
+    let stored: Value = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(stored["2xx"]["/isSuccessful"], "bool");
+    let _ = std::fs::remove_file(file);
+}
@@ -0,0 +1,55 @@
+use sarkhati::{login, secrets};
+use serde_json::{Value, json};
+
+fn use_mock_keyring() {
+    keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+}
+
+#[test]
+fn plain_values_are_not_looked_up() {
+    use_mock_keyring();
+    let mut cookie = "JWT-TOKEN=abc".to_string();
+    secrets::resolve(&mut cookie).unwrap();
+    assert_eq!(cookie, "JWT-TOKEN=abc");
+    assert_eq!(secrets::reference_name("keyring:bmi"), Some("bmi"));
+    assert_eq!(secrets::reference_name("keyring:"), None);
+}
+
+#[test]
+fn missing_secret_names_the_command_that_stores_it() {
+    use_mock_keyring();
+    let mut cookie = "keyring:bmi".to_string();
+    let error = secrets::resolve(&mut cookie).unwrap_err();
+    assert!(
+        error.to_string().contains("secrets set bmi"),
+        "unexpected error: {}",
+        error
+    );
+}
+
+#[test]
+fn config_updates_keep_keyring_references() {
+    use_mock_keyring();
+    let path = std::env::temp_dir().join(format!("sarkhati-secrets-{}.json", std::process::id()));
+    std::fs::write(
+        &path,
+        json!({ "brokers": [{ "name": "alvand", "cookie": "keyring:alvand", "nt": "old" }] })
+            .to_string(),
+    )
+    .unwrap();
+
+    login::update_config_fields(
+        path.to_str().unwrap(),
+        Some("alvand"),
+        &[
+            ("cookie", Value::String("JWT-TOKEN=new".into())),
+            ("nt", Value::String("new".into())),
+        ],
+    )
+    .unwrap();
+
+    let config: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(config["brokers"][0]["cookie"], "keyring:alvand");
+    assert_eq!(config["brokers"][0]["nt"], "new");
+}
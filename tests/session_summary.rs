@@ -0,0 +1,142 @@
+mod common;
+
+use common::{broker_server, temp_path};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::runner::{RunOptions, run_broker};
+use sarkhati::session_summary::SessionSummary;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn two_order_broker(server: &MockServer) -> CustomBrokersConfig {
+    serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 100,
+            "batch_sinks": [],
+            "orders": [{ "isin": "IRO1FOLD0001" }, { "isin": "IRO1KHOD0001" }]
+        }]
+    }))
+    .unwrap()
+}
+
+/// The session summary after `batches` batches of a continuous run.
+async fn run_batches(batches: usize) -> Arc<SessionSummary> {
+    let server = broker_server().await;
+    let mut config = two_order_broker(&server);
+    let session = Arc::new(SessionSummary::new());
+    let (results, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let options = RunOptions {
+        batch_results: Some(results),
+        session_summary: Some(session.clone()),
+        ..RunOptions::default()
+    };
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), options));
+    for _ in 0..batches {
+        received.recv().await.expect("a batch result");
+    }
+    run.abort();
+    session
+}
+
+#[tokio::test]
+async fn summary_counts_attempts_first_acceptance_and_errors() {
+    let session = run_batches(2).await;
+
+    let brokers = session.brokers_summary();
+    assert_eq!(brokers.len(), 1);
+    let acme = &brokers[0];
+    assert_eq!(acme.broker, "acme");
+    assert_eq!(acme.batches, 2);
+    assert_eq!(acme.attempts, 4);
+    assert_eq!(acme.accepted, 2);
+    assert_eq!(acme.failed, 2);
+    assert_eq!(acme.first_accepted.len(), 1);
+    assert_eq!(acme.first_accepted[&1].attempt, 1);
+    assert_eq!(acme.errors.get("PriceOutOfRange"), Some(&2));
+    assert_eq!(acme.configured_rps, Some(10.0));
+    assert!(acme.effective_rps > 0.0);
+    assert!(matches!(
+        acme.last_status.as_deref(),
+        Some("accepted" | "failed (PriceOutOfRange)")
+    ));
+}
+
+#[tokio::test]
+async fn summary_is_saved_as_json() {
+    let session = run_batches(1).await;
+    let file = temp_path("summary.json");
+
+    session.save(file.to_str().unwrap()).unwrap();
+
+    let saved: Value = serde_json::from_str(&std::fs::read_to_string(&file).unwrap()).unwrap();
+    assert_eq!(saved[0]["broker"], "acme");
+    assert_eq!(saved[0]["attempts"], 2);
+    assert_eq!(saved[0]["errors"]["PriceOutOfRange"], 1);
+    assert!(saved[0]["first_accepted"]["1"]["epoch_ms"].is_i64());
+    let _ = std::fs::remove_file(file);
+}
+
+#[tokio::test]
+async fn order_books_are_recorded_around_the_send() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [
+                { "insCode": "46348559193224090", "instrumentID": "IRO1FOLD0001", "lVal18AFC": "فولاد" }
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/BestLimits/46348559193224090"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "bestLimits": [
+                { "number": 1, "pMeDem": 2480, "qTitMeDem": 900000, "zOrdMeDem": 41, "pMeOf": 2500, "qTitMeOf": 300, "zOrdMeOf": 1 }
+            ]
+        })))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "market_data_url": server.uri(),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 50,
+            "batch_sinks": [],
+            "stop_on_accept": true,
+            "book_snapshots": { "after_ms": 100 },
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap();
+    let session = Arc::new(SessionSummary::new());
+    let options = RunOptions {
+        session_summary: Some(session.clone()),
+        ..RunOptions::default()
+    };
+
+    run_broker(config.brokers.remove(0), options).await.unwrap();
+
+    let snapshots = &session.brokers_summary()[0].book_snapshots;
+    let moments: Vec<Value> = snapshots
+        .iter()
+        .map(|snapshot| serde_json::to_value(snapshot.moment).unwrap())
+        .collect();
+    assert_eq!(moments, [json!("before"), json!("after")]);
+    let after = &snapshots[1];
+    assert_eq!(after.isin, "IRO1FOLD0001");
+    assert!(after.epoch_ms >= after.send_epoch_ms + 100);
+    assert_eq!(after.book.best_bid(), Some(2480.0));
+    assert_eq!(after.book.levels[0].bid_volume, 900000);
+}
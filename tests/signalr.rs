@@ -0,0 +1,243 @@
+use futures::{SinkExt, StreamExt};
+use reqwest::header::{COOKIE, HeaderMap, HeaderValue};
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::order_events::{OrderTracker, PushNotificationsConfig, PushedStatus};
+use sarkhati::orders::{OrderEntry, OrderSide};
+use sarkhati::runner::{RunOptions, run_broker};
+use sarkhati::signalr::Invocation;
+use serde_json::{Map, Value, json};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const RS: char = '\u{1e}';
+
+/// Answer a negotiate request with a connection token.
+async fn negotiate(mut stream: TcpStream, seen: &mpsc::UnboundedSender<String>) {
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.unwrap();
+        if read == 0 {
+            return;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let request = String::from_utf8_lossy(&request).to_string();
+    seen.send(request.lines().next().unwrap_or_default().to_string())
+        .unwrap();
+    let body = r#"{"negotiateVersion":1,"connectionId":"c-1","connectionToken":"tok-1"}"#;
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await.unwrap();
+}
+
+/// A hub that serves negotiate and the WebSocket on one port, reports what
+/// it was sent on `seen` and pushes `frames` once the client subscribed.
+#[allow(clippy::result_large_err)]
+async fn hub(frames: Vec<String>) -> (String, mpsc::UnboundedReceiver<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let (seen, seen_rx) = mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut start = [0u8; 4];
+            stream.peek(&mut start).await.unwrap();
+            if &start == b"POST" {
+                negotiate(stream, &seen).await;
+                continue;
+            }
+            let handshake_seen = seen.clone();
+            let mut socket = tokio_tungstenite::accept_hdr_async(
+                stream,
+                |request: &Request, response: Response| {
+                    let cookie = request
+                        .headers()
+                        .get("cookie")
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default();
+                    handshake_seen
+                        .send(format!("{} cookie={}", request.uri(), cookie))
+                        .unwrap();
+                    Ok(response)
+                },
+            )
+            .await
+            .unwrap();
+            // The protocol handshake, then the subscribe call.
+            for _ in 0..2 {
+                let Some(Ok(Message::Text(text))) = socket.next().await else {
+                    return;
+                };
+                seen.send(text.to_string()).unwrap();
+                if text.contains("protocol") {
+                    socket
+                        .send(Message::text(format!("{{}}{}", RS)))
+                        .await
+                        .unwrap();
+                }
+            }
+            for frame in &frames {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                socket.send(Message::text(frame.clone())).await.unwrap();
+            }
+            while socket.next().await.is_some() {}
+            return;
+        }
+    });
+    (format!("http://{}/hubs/order", address), seen_rx)
+}
+
+fn invocation(target: &str, argument: Value) -> String {
+    format!(
+        "{}{}",
+        json!({ "type": 1, "target": target, "arguments": [argument] }),
+        RS
+    )
+}
+
+fn push_settings(hub_url: &str) -> Value {
+    json!({
+        "hub_url": hub_url,
+        "subscribe": [{ "target": "JoinCustomer", "arguments": ["4411"] }],
+        "accepted": ["OrderAccepted"],
+        "filled": ["OrderExecuted"],
+        "fields": { "isin": "/isin", "order_id": "/id", "side": "/side" }
+    })
+}
+
+fn push_config(hub_url: &str) -> PushNotificationsConfig {
+    serde_json::from_value(push_settings(hub_url)).unwrap()
+}
+
+#[test]
+fn hub_calls_are_read_as_order_events() {
+    let config = push_config("https://oms.example/hubs/order");
+    let event = config
+        .event(&Invocation {
+            target: "OrderExecuted".to_string(),
+            arguments: vec![json!({ "isin": " IRO1FOLD0001 ", "id": 9921, "side": 2 })],
+        })
+        .unwrap();
+    assert_eq!(event.status, PushedStatus::Filled);
+    assert_eq!(event.isin.as_deref(), Some("IRO1FOLD0001"));
+    assert_eq!(event.order_id.as_deref(), Some("9921"));
+    assert_eq!(event.side, Some(OrderSide::Sell));
+
+    let other = Invocation {
+        target: "MarketMessage".to_string(),
+        arguments: vec![],
+    };
+    assert!(config.event(&other).is_none());
+}
+
+#[tokio::test]
+async fn pushed_events_reach_the_matching_orders() {
+    let (hub_url, mut seen) = hub(vec![
+        // A ping and an unrelated call share the frame with the event.
+        format!(
+            "{{\"type\":6}}{}{}{}",
+            RS,
+            invocation("MarketMessage", json!("open")),
+            invocation(
+                "OrderAccepted",
+                json!({ "isin": "IRO1FOLD0001", "id": "77", "side": "Buy" })
+            )
+        ),
+        invocation(
+            "OrderExecuted",
+            json!({ "isin": "IRO1FOLD0001", "id": "77", "side": "Buy" }),
+        ),
+    ])
+    .await;
+    let orders: Vec<OrderEntry<Map<String, Value>>> = serde_json::from_value(json!([
+        { "isin": "IRO1FOLD0001", "side": "SIDE_BUY" },
+        { "isin": "IRO1FOLD0001", "side": "SIDE_SELL" },
+        { "isin": "IRO1KHOD0001", "side": "SIDE_BUY" }
+    ]))
+    .unwrap();
+    let mut session = HeaderMap::new();
+    session.insert(COOKIE, HeaderValue::from_static("session=5"));
+
+    let tracker = OrderTracker::start("acme", &push_config(&hub_url), &session, &orders)
+        .await
+        .unwrap();
+
+    assert!(
+        seen.recv()
+            .await
+            .unwrap()
+            .starts_with("POST /hubs/order/negotiate?negotiateVersion=1")
+    );
+    assert_eq!(
+        seen.recv().await.unwrap(),
+        "/hubs/order?id=tok-1 cookie=session=5"
+    );
+    assert_eq!(
+        seen.recv().await.unwrap(),
+        format!("{}{}", json!({ "protocol": "json", "version": 1 }), RS)
+    );
+    assert_eq!(
+        seen.recv().await.unwrap(),
+        format!(
+            "{}{}",
+            json!({ "type": 1, "target": "JoinCustomer", "arguments": ["4411"] }),
+            RS
+        )
+    );
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while tracker.status(0) != Some(PushedStatus::Filled) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("the pushed fill should reach order #1");
+    assert_eq!(tracker.status(1), None, "the event is for the buy order");
+    assert_eq!(tracker.status(2), None);
+}
+
+#[tokio::test]
+async fn a_pushed_registration_stops_the_order_mid_batch() {
+    let (hub_url, _seen) = hub(vec![invocation(
+        "OrderAccepted",
+        json!({ "isin": "IRO1FOLD0001", "id": "77" }),
+    )])
+    .await;
+    // The broker never answers, so only the push can say the order is in.
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(60)))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 100,
+            "push_notifications": push_settings(&hub_url),
+            "orders": [{ "isin": "IRO1FOLD0001" }]
+        }]
+    }))
+    .unwrap();
+
+    tokio::time::timeout(
+        Duration::from_secs(5),
+        run_broker(config.brokers.remove(0), RunOptions::default()),
+    )
+    .await
+    .expect("the loop should stop once the order is pushed as registered")
+    .unwrap();
+    assert!(server.received_requests().await.unwrap().len() < 10);
+}
@@ -0,0 +1,32 @@
+#![cfg(unix)]
+
+use sarkhati::systemd::Readiness;
+use std::os::unix::net::UnixDatagram;
+
+#[test]
+fn ready_is_sent_once_every_broker_is_armed() {
+    let path = std::env::temp_dir().join(format!("sarkhati-notify-{}.sock", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket.set_nonblocking(true).unwrap();
+    // SAFETY: this test binary has a single test, so no other thread reads
+    // the environment.
+    unsafe { std::env::set_var("NOTIFY_SOCKET", &path) };
+
+    let mut armed = Readiness::register();
+    let failed = Readiness::register();
+    armed.ready();
+    armed.ready();
+    let mut buffer = [0; 256];
+    assert!(socket.recv(&mut buffer).is_err());
+
+    // A broker that gave up before arming no longer holds readiness back.
+    drop(failed);
+    let len = socket.recv(&mut buffer).unwrap();
+    assert_eq!(&buffer[..len], b"READY=1\nSTATUS=Armed\n");
+
+    drop(armed);
+    drop(Readiness::register());
+    assert!(socket.recv(&mut buffer).is_err());
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,35 @@
+use sarkhati::totp::TotpConfig;
+use serde_json::json;
+
+fn totp(secret: &str, digits: u32, algorithm: &str) -> TotpConfig {
+    serde_json::from_value(json!({
+        "secret": secret,
+        "digits": digits,
+        "algorithm": algorithm
+    }))
+    .unwrap()
+}
+
+#[test]
+fn totp_matches_rfc_6238_vectors() {
+    // Base32 of the ASCII seeds from RFC 6238 appendix B.
+    let sha1 = totp("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ", 8, "sha1");
+    assert_eq!(sha1.code_at(59).unwrap(), "94287082");
+    assert_eq!(sha1.code_at(1_111_111_109).unwrap(), "07081804");
+    assert_eq!(sha1.code_at(20_000_000_000).unwrap(), "65353130");
+
+    let sha256 = totp(
+        "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA",
+        8,
+        "sha256",
+    );
+    assert_eq!(sha256.code_at(59).unwrap(), "46119246");
+    assert_eq!(sha256.code_at(1_111_111_109).unwrap(), "68084774");
+}
+
+#[test]
+fn totp_accepts_secrets_as_authenticator_apps_show_them() {
+    let spaced = totp("gezd gnbv gy3t qojq gezd gnbv gy3t qojq", 6, "sha1");
+    assert_eq!(spaced.code_at(59).unwrap(), "287082");
+    assert!(totp("not base32!", 6, "sha1").code_at(59).is_err());
+}
@@ -0,0 +1,39 @@
+use sarkhati::tui::{DashboardState, Panel};
+
+#[test]
+fn log_lines_fill_each_brokers_panel() {
+    let mut state = DashboardState::default();
+    for line in [
+        "[bmi] Next target_time=2026-10-17 08:45:00.000 (epoch_ms=1792215300000)",
+        "[bmi] target_time=08:45:00.000 final_send_time=08:44:59.960 estimator_delay=35ms safety_margin=5ms effective_delay=40ms",
+        "[alvand/wife] Order response status: 400 Bad Request",
+        "[alvand/wife] Order response body: {\"error\":\"credit\"}",
+        "[alvand] === Batch #3: Sending 2 orders ===",
+        "[alvand] ✓ Batch #3, Order #1: Sent successfully",
+        "[alvand] ✗ Batch #3, Order #2: Failed - 400 Bad Request",
+        "Starting Sarkhati - All Brokers in Parallel",
+    ] {
+        state.update(line);
+    }
+
+    assert_eq!(
+        state.panels(),
+        [
+            Panel {
+                name: "bmi".to_string(),
+                target_epoch_ms: Some(1792215300000),
+                delay: Some("estimate 35ms, margin 5ms".to_string()),
+                ..Panel::default()
+            },
+            Panel {
+                name: "alvand".to_string(),
+                batches: 3,
+                accepted: 1,
+                failed: 1,
+                last_status: Some("400 Bad Request".to_string()),
+                last_message: Some("{\"error\":\"credit\"}".to_string()),
+                ..Panel::default()
+            },
+        ]
+    );
+}
@@ -0,0 +1,170 @@
+use sarkhati::accounts;
+use sarkhati::custom_broker::{CustomBrokerConfig, CustomBrokersConfig};
+use sarkhati::orders;
+use sarkhati::runner::{RunOptions, run_broker};
+use sarkhati::twap::{self, TwapSettings};
+use serde_json::{Value, json};
+use std::time::Duration;
+use wiremock::matchers::method;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[test]
+fn slices_are_equal_and_evenly_spaced() {
+    let twap = TwapSettings {
+        slices: 10,
+        duration_secs: 300,
+    };
+    let tranches = twap.tranches();
+    assert_eq!(tranches.len(), 10);
+    assert_eq!(tranches[0].after_ms, 0);
+    assert_eq!(tranches[1].after_ms, 30_000);
+    assert_eq!(tranches[9].after_ms, 270_000);
+    assert!(tranches.iter().all(|tranche| tranche.share == 10.0));
+}
+
+#[test]
+fn slices_split_the_quantity_exactly() {
+    for (slices, total) in [(29, 145), (31, 155), (97, 97), (7, 700)] {
+        let twap = TwapSettings {
+            slices,
+            duration_secs: 60,
+        };
+        let quantities = orders::split_by_shares(total, &twap.tranches());
+        assert_eq!(quantities, vec![total / slices as u64; slices]);
+    }
+
+    let twap = TwapSettings {
+        slices: 3,
+        duration_secs: 60,
+    };
+    assert_eq!(
+        orders::split_by_shares(1000, &twap.tranches()),
+        [334, 333, 333]
+    );
+}
+
+fn twap_broker(extra: Value) -> CustomBrokerConfig {
+    let mut broker = json!({
+        "name": "acme",
+        "order_url": "http://127.0.0.1:1/orders",
+        "body_template": { "qty": "{{quantity}}" },
+        "twap": { "slices": 2, "duration_secs": 10 },
+        "orders": [{ "quantity": 10 }]
+    });
+    broker
+        .as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    let mut value = json!({ "brokers": [broker] });
+    accounts::expand(&mut value).unwrap();
+    let mut config: CustomBrokersConfig = serde_json::from_value(value).unwrap();
+    config.brokers.remove(0)
+}
+
+#[test]
+fn twap_refuses_batch_repeat() {
+    let mut broker = twap_broker(json!({ "batch_repeat": 2 }));
+    let error = twap::apply(&mut broker).unwrap_err();
+    assert!(
+        error.to_string().contains("acme has batch_repeat 2"),
+        "{}",
+        error
+    );
+
+    let mut broker = twap_broker(json!({
+        "accounts": [{ "name": "second", "batch_repeat": 3 }]
+    }));
+    let error = twap::apply(&mut broker).unwrap_err();
+    assert!(error.to_string().contains("batch_repeat 3"), "{}", error);
+}
+
+#[test]
+fn twap_refuses_repeated_orders() {
+    let mut broker = twap_broker(json!({
+        "orders": [{ "quantity": 10 }, { "quantity": 20, "repeat": 2 }]
+    }));
+    let error = twap::apply(&mut broker).unwrap_err();
+    assert!(
+        error.to_string().contains("Order #2 of acme has repeat 2"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn each_slice_is_sent_once_at_its_time() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "qty": "{{quantity}}" },
+            "batch_delay_ms": 50,
+            "twap": { "slices": 3, "duration_secs": 1 },
+            "orders": [{ "quantity": 1000 }]
+        }]
+    }))
+    .unwrap();
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+
+    let sent = || async {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .map(|request| serde_json::from_slice::<Value>(&request.body).unwrap()["qty"].clone())
+            .collect::<Vec<Value>>()
+    };
+    while sent().await.is_empty() {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    assert_eq!(sent().await, [json!(334)]);
+    tokio::time::sleep(Duration::from_millis(1000)).await;
+    assert_eq!(sent().await, [json!(334), json!(333), json!(333)]);
+    tokio::time::timeout(Duration::from_secs(2), run)
+        .await
+        .expect("the run ends once every slice was accepted")
+        .unwrap()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn a_slice_awaiting_its_response_is_not_sent_again() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_millis(400)))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "body_template": { "qty": "{{quantity}}" },
+            "batch_delay_ms": 20,
+            "twap": { "slices": 2, "duration_secs": 1 },
+            "orders": [{ "quantity": 10 }]
+        }]
+    }))
+    .unwrap();
+    let run = tokio::spawn(run_broker(config.brokers.remove(0), RunOptions::default()));
+
+    tokio::time::timeout(Duration::from_secs(5), run)
+        .await
+        .expect("the run ends once every slice was accepted")
+        .unwrap()
+        .unwrap();
+    let sent: Vec<Value> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| serde_json::from_slice::<Value>(&request.body).unwrap()["qty"].clone())
+        .collect();
+    assert_eq!(sent, [json!(5), json!(5)]);
+}
@@ -0,0 +1,89 @@
+use rand::Rng;
+use sarkhati::decode_unicode_escapes;
+
+#[test]
+fn escapes_decode_to_the_characters_they_encode() {
+    let cases = [
+        (r"\u0633\u0641\u0627\u0631\u0634", "سفارش"),
+        (r"\ud83d\ude80 sent", "🚀 sent"),
+        (r"\uD83D\uDE80", "🚀"),
+        (r"x\u0041\ud840\udc00y", "xA𠀀y"),
+        (
+            r#"{"message":"\u062e\u0637\u0627"}"#,
+            r#"{"message":"خطا"}"#,
+        ),
+    ];
+    for (escaped, decoded) in cases {
+        assert_eq!(decode_unicode_escapes(escaped), decoded, "{}", escaped);
+    }
+}
+
+#[test]
+fn what_is_not_a_valid_escape_is_kept() {
+    let cases = [
+        (r"\ud83d alone", r"\ud83d alone"),
+        (r"\ude80 alone", r"\ude80 alone"),
+        (r"\ud83d\u0041", r"\ud83dA"),
+        (r"\ud83d\ud83d", r"\ud83d\ud83d"),
+        (r"\\u0633", r"\\u0633"),
+        (r"\u06", r"\u06"),
+        (r"\uZZZZ", r"\uZZZZ"),
+        (r"\u06سفارش", r"\u06سفارش"),
+        (r"\n\t\", r"\n\t\"),
+        ("no escapes", "no escapes"),
+    ];
+    for (text, expected) in cases {
+        assert_eq!(decode_unicode_escapes(text), expected, "{}", text);
+    }
+}
+
+/// Escape each character of `text` as JSON would, or leave it, at random.
+fn escape_some(text: &str, rng: &mut impl Rng) -> String {
+    let mut escaped = String::new();
+    for ch in text.chars() {
+        if ch == '\\' {
+            escaped.push_str(r"\\");
+        } else if rng.random_bool(0.5) {
+            let mut units = [0u16; 2];
+            for unit in ch.encode_utf16(&mut units) {
+                if rng.random_bool(0.5) {
+                    escaped.push_str(&format!("\\u{:04x}", unit));
+                } else {
+                    escaped.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+        } else {
+            escaped.push(ch);
+        }
+    }
+    escaped
+}
+
+/// Any text, some of it escaped, decodes back to itself with only its
+/// backslashes still doubled.
+#[test]
+fn escaped_text_decodes_back_to_itself() {
+    let mut rng = rand::rng();
+    let alphabet: Vec<char> = "ab\\u0 سفارش ۱۲۳ يك🚀😀𠀀\u{10FFFF}\u{FFFF}\u{D7FF}\u{E000}"
+        .chars()
+        .collect();
+    for _ in 0..2000 {
+        let length = rng.random_range(0..24);
+        let text: String = (0..length)
+            .map(|_| {
+                if rng.random_bool(0.2) {
+                    rng.random::<char>()
+                } else {
+                    alphabet[rng.random_range(0..alphabet.len())]
+                }
+            })
+            .collect();
+        let escaped = escape_some(&text, &mut rng);
+        assert_eq!(
+            decode_unicode_escapes(&escaped),
+            text.replace('\\', r"\\"),
+            "{:?}",
+            escaped
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use sarkhati::custom_broker::CustomBrokersConfig;
+use sarkhati::orders::OrderEntry;
+use sarkhati::runner::{RunOptions, run_broker};
+use sarkhati::watchlist::{WatchTrigger, Watchlist, WatchlistEntry};
+use serde_json::{Map, Value, json};
+use std::collections::BTreeSet;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const INS_CODE: &str = "46348559193224090";
+
+fn entries(value: Value) -> Vec<WatchlistEntry> {
+    serde_json::from_value(value).unwrap()
+}
+
+#[test]
+fn entries_fire_on_open_or_at_a_time() {
+    let parsed = entries(json!([
+        { "isin": "IRO1FOLD0001", "when": "open" },
+        { "isin": "IRO1KHOD0001", "when": "08:59:58.500" }
+    ]));
+    assert_eq!(parsed[0].when, WatchTrigger::Open);
+    assert_eq!(
+        parsed[1].when,
+        WatchTrigger::At(chrono::NaiveTime::from_hms_milli_opt(8, 59, 58, 500).unwrap())
+    );
+    assert!(
+        serde_json::from_value::<WatchlistEntry>(json!({ "isin": "IRO1FOLD0001", "when": "soon" }))
+            .is_err()
+    );
+
+    let orders: Vec<OrderEntry<Map<String, Value>>> =
+        serde_json::from_value(json!([{ "isin": "IRO1FOLD0001" }])).unwrap();
+    let error = Watchlist::start(
+        "acme",
+        &entries(json!([{ "isin": "IRO1KHOD0001", "when": "open" }])),
+        &orders,
+        "http://127.0.0.1:9",
+        None,
+    )
+    .err()
+    .unwrap();
+    assert!(error.to_string().contains("matches none of its orders"));
+}
+
+#[tokio::test]
+async fn each_symbol_is_released_by_its_own_trigger() {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/orders"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/Instrument/GetInstrumentSearch/IRO1FOLD0001"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "instrumentSearch": [
+                { "insCode": INS_CODE, "instrumentID": "IRO1FOLD0001", "lVal18AFC": "فولاد" }
+            ]
+        })))
+        .mount(&server)
+        .await;
+    Mock::given(method("GET"))
+        .and(path(format!(
+            "/ClosingPrice/GetClosingPriceInfo/{}",
+            INS_CODE
+        )))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "closingPriceInfo": { "pDrCotVal": 2490, "instrumentState": { "cEtaval": "A" } }
+        })))
+        .mount(&server)
+        .await;
+    let mut config: CustomBrokersConfig = serde_json::from_value(json!({
+        "brokers": [{
+            "name": "acme",
+            "order_url": format!("{}/orders", server.uri()),
+            "market_data_url": server.uri(),
+            "body_template": { "symbol": "{{isin}}" },
+            "batch_delay_ms": 50,
+            "batch_sinks": [],
+            "max_runtime_secs": 1,
+            "watchlist": [
+                { "isin": "IRO1FOLD0001", "when": "open" },
+                { "isin": "IRO1KHOD0001", "when": "00:00:00" },
+                { "isin": "IRO1CHML0001", "when": "23:59:59.999" }
+            ],
+            "orders": [
+                { "isin": "IRO1CHML0001" },
+                { "isin": "IRO1FOLD0001" },
+                { "isin": "IRO1KHOD0001" },
+                { "isin": "IRO1PNES0001" }
+            ]
+        }]
+    }))
+    .unwrap();
+
+    run_broker(config.brokers.remove(0), RunOptions::default())
+        .await
+        .unwrap();
+
+    let sent: BTreeSet<String> = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|request| request.url.path() == "/orders")
+        .map(|request| {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            body["symbol"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert_eq!(
+        sent,
+        BTreeSet::from([
+            "IRO1FOLD0001".to_string(),
+            "IRO1KHOD0001".to_string(),
+            "IRO1PNES0001".to_string()
+        ]),
+        "the open and past symbols fire, the unwatched one is never held"
+    );
+}